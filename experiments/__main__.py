@@ -17,6 +17,9 @@ Steps:
 
 If running more than one experiment, they are grouped by AWS environment.
 
+To track results across revisions, import them into a database and compare
+runs: see `python -m experiments db --help`.
+
 Requirements:
 
 - Terraform (runnable as `terraform`)
@@ -29,7 +32,6 @@ from __future__ import annotations
 import argparse
 import asyncio
 import io
-import json
 import signal
 import sys
 
@@ -42,6 +44,8 @@ from experiments.express.args import Args as ExpressArgs
 from experiments.riposte.args import Args as RiposteArgs
 from experiments.dissent.args import Args as DissentArgs
 
+from experiments import db
+from experiments.schema import ExperimentsFileError, load_experiments
 from experiments.system import Args as SystemArgs, System
 from experiments.util import stream_json
 from experiments.run import run_experiments, Args as RunArgs
@@ -116,8 +120,12 @@ async def main(args: Args):
 
     system: System = args.system_args.system
     if args.system_args.experiments_file:
-        experiments_json = json.load(args.system_args.experiments_file)
-        experiments = list(map(system.experiment.from_dict, experiments_json))
+        try:
+            experiments = load_experiments(
+                args.system_args.experiments_file, system.experiment
+            )
+        except ExperimentsFileError as err:
+            sys.exit(f"Invalid experiments file: {err}")
     elif args.cleanup:
         experiments = []
     else:
@@ -146,4 +154,6 @@ async def main(args: Args):
 
 
 if __name__ == "__main__":
+    if sys.argv[1:2] == ["db"]:
+        sys.exit(db.main(sys.argv[2:]))
     asyncio.run(main(parse_args(sys.argv)))
@@ -2,6 +2,7 @@
 import os
 import json
 import operator
+import re
 import subprocess
 
 from contextlib import contextmanager
@@ -20,10 +21,40 @@ Region = NewType("Region", str)
 SHA = NewType("SHA", str)
 AMI = NewType("AMI", str)
 InstanceType = NewType("InstanceType", str)
+# Debian-style architecture name (as in Ubuntu AMI names).
+Arch = NewType("Arch", str)
 
 AWS_REGION = Region("us-east-2")
+# Regions Spectrum experiments can place machines in. Each needs a provider in
+# spectrum/main.tf and a copy of the image (see spectrum/main.pkr.hcl).
+REGIONS = (
+    Region("us-east-1"),
+    Region("us-east-2"),
+    Region("us-west-1"),
+    Region("us-west-2"),
+    Region("eu-west-1"),
+)
 DEFAULT_INSTANCE_TYPE = InstanceType("c5.4xlarge")
 
+AMD64 = Arch("amd64")
+ARM64 = Arch("arm64")
+
+
+def instance_arch(instance_type: InstanceType) -> Arch:
+    """The CPU architecture of an EC2 instance type.
+
+    Graviton families have a "g" after the generation number (c6g, c7gn, m6gd,
+    t4g); a1 is the first-generation Graviton. Everything else is x86.
+    """
+    family = instance_type.split(".")[0]
+    match = re.fullmatch(r"([a-z]+)(\d+)([a-z-]*)", family)
+    if match is None:
+        return AMD64
+    prefix, _, suffix = match.groups()
+    if prefix == "a" or "g" in suffix:
+        return ARM64
+    return AMD64
+
 
 class NoImageError(Exception):
     pass
@@ -0,0 +1,288 @@
+"""Historical experiment results, in SQLite.
+
+Tracks performance across revisions without spreadsheets:
+
+    python -m experiments db import results.json
+    python -m experiments db compare RUN_A RUN_B
+
+`import` stores a results file (as written by `python -m experiments`) as a
+named run: by default, the git commit its results were built from (see
+`metadata` in each result). `compare` matches up the configurations (experiment
+parameters) two runs share and reports how throughput and latency changed for
+each, flagging regressions beyond a threshold (and exiting nonzero if there are
+any).
+"""
+from __future__ import annotations
+
+import argparse
+import json
+import sqlite3
+
+from dataclasses import dataclass
+from datetime import datetime, timezone
+from typing import Any, Dict, List, Optional, Tuple
+
+DEFAULT_DB = "results.db"
+# Relative change (in either throughput or latency) that counts as a regression.
+DEFAULT_THRESHOLD = 0.05
+
+_SCHEMA = """
+CREATE TABLE IF NOT EXISTS runs (
+    name TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    imported_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS results (
+    run TEXT NOT NULL REFERENCES runs (name) ON DELETE CASCADE,
+    config TEXT NOT NULL,
+    time_ms INTEGER NOT NULL,
+    queries INTEGER NOT NULL,
+    qps REAL NOT NULL,
+    mean_latency_ms REAL,
+    metadata TEXT
+);
+CREATE INDEX IF NOT EXISTS results_by_run ON results (run, config);
+"""
+
+
+def connect(path: str) -> sqlite3.Connection:
+    """Open (creating if needed) the results database at `path`."""
+    conn = sqlite3.connect(path)
+    conn.execute("PRAGMA foreign_keys = ON")
+    conn.executescript(_SCHEMA)
+    return conn
+
+
+def config_key(experiment: Dict[str, Any]) -> str:
+    """A canonical string for an experiment's parameters.
+
+    >>> config_key({"clients": 10, "channels": 2})
+    '{"channels":2,"clients":10}'
+    """
+    return json.dumps(experiment, sort_keys=True, separators=(",", ":"))
+
+
+def describe(config: str) -> str:
+    """A configuration, for humans.
+
+    >>> describe('{"channels":2,"protocol":{"security":16}}')
+    'channels=2, protocol={"security":16}'
+    """
+    params = json.loads(config)
+    return ", ".join(
+        f"{key}={json.dumps(value, separators=(',', ':'))}"
+        for key, value in params.items()
+    )
+
+
+def default_run_name(results: List[Dict[str, Any]]) -> str:
+    """The git commit that every result was built from.
+
+    >>> default_run_name([{"metadata": {"git_commit": "abc123"}}] * 2)
+    'abc123'
+    >>> default_run_name([{"metadata": None}])
+    Traceback (most recent call last):
+    ...
+    ValueError: Results don't all come from one known git commit; name the run with --run.
+    """
+    commits = {(result.get("metadata") or {}).get("git_commit") for result in results}
+    if len(commits) != 1 or None in commits:
+        raise ValueError(
+            "Results don't all come from one known git commit; name the run with --run."
+        )
+    return commits.pop()
+
+
+def import_results(
+    conn: sqlite3.Connection,
+    results: List[Dict[str, Any]],
+    run: str,
+    source: str,
+    replace: bool = False,
+) -> int:
+    """Store `results` as run `run`; returns how many results were stored."""
+    rows = [
+        (
+            run,
+            config_key(result["experiment"]),
+            result["time"],
+            result["queries"],
+            result["queries"] / result["time"] * 1000 if result["time"] else 0.0,
+            result.get("mean_latency"),
+            json.dumps(result["metadata"]) if result.get("metadata") else None,
+        )
+        for result in results
+    ]
+    with conn:
+        if replace:
+            conn.execute("DELETE FROM runs WHERE name = ?", (run,))
+        try:
+            conn.execute(
+                "INSERT INTO runs (name, source, imported_at) VALUES (?, ?, ?)",
+                (run, source, datetime.now(timezone.utc).isoformat()),
+            )
+        except sqlite3.IntegrityError:
+            raise ValueError(
+                f"Run [{run}] already imported (use --replace to overwrite it)."
+            ) from None
+        conn.executemany("INSERT INTO results VALUES (?, ?, ?, ?, ?, ?, ?)", rows)
+    return len(rows)
+
+
+@dataclass(frozen=True)
+class Delta:
+    """How one configuration's (mean) performance changed between two runs."""
+
+    config: str
+    qps_a: float
+    latency_a: Optional[float]
+    qps_b: float
+    latency_b: Optional[float]
+
+    @property
+    def qps_change(self) -> float:
+        """Relative change in throughput.
+
+        >>> Delta("{}", 100.0, None, 90.0, None).qps_change
+        -0.1
+        """
+        if self.qps_a == 0:
+            return 0.0 if self.qps_b == 0 else float("inf")
+        return (self.qps_b - self.qps_a) / self.qps_a
+
+    @property
+    def latency_change(self) -> Optional[float]:
+        """Relative change in mean latency (if both runs measured it)."""
+        if not self.latency_a or self.latency_b is None:
+            return None
+        return (self.latency_b - self.latency_a) / self.latency_a
+
+    def is_regression(self, threshold: float) -> bool:
+        """Whether throughput fell, or latency rose, by more than `threshold`.
+
+        >>> Delta("{}", 100.0, 10.0, 94.0, 10.0).is_regression(0.05)
+        True
+        >>> Delta("{}", 100.0, 10.0, 96.0, 10.0).is_regression(0.05)
+        False
+        >>> Delta("{}", 100.0, 10.0, 100.0, 11.0).is_regression(0.05)
+        True
+        """
+        if self.qps_change < -threshold:
+            return True
+        latency_change = self.latency_change
+        return latency_change is not None and latency_change > threshold
+
+
+def _means(
+    conn: sqlite3.Connection, run: str
+) -> Dict[str, Tuple[float, Optional[float]]]:
+    if conn.execute("SELECT 1 FROM runs WHERE name = ?", (run,)).fetchone() is None:
+        raise ValueError(f"No run named [{run}].")
+    rows = conn.execute(
+        "SELECT config, AVG(qps), AVG(mean_latency_ms) FROM results"
+        " WHERE run = ? GROUP BY config",
+        (run,),
+    )
+    return {config: (qps, latency) for config, qps, latency in rows}
+
+
+def compare(
+    conn: sqlite3.Connection, run_a: str, run_b: str
+) -> Tuple[List[Delta], int, int]:
+    """Compare the configurations runs `run_a` and `run_b` share.
+
+    Repeated trials of a configuration are averaged. Returns the deltas, and how
+    many configurations only `run_a` and only `run_b` have.
+    """
+    means_a = _means(conn, run_a)
+    means_b = _means(conn, run_b)
+    shared = sorted(means_a.keys() & means_b.keys())
+    deltas = [Delta(config, *means_a[config], *means_b[config]) for config in shared]
+    return deltas, len(means_a) - len(shared), len(means_b) - len(shared)
+
+
+def _format_delta(delta: Delta, threshold: float) -> str:
+    flag = "REGRESSION" if delta.is_regression(threshold) else "ok"
+    line = (
+        f"[{flag}] {describe(delta.config)}\n"
+        f"    qps: {delta.qps_a:.0f} -> {delta.qps_b:.0f} ({delta.qps_change:+.1%})"
+    )
+    latency_change = delta.latency_change
+    if latency_change is not None:
+        line += (
+            f"; mean latency: {delta.latency_a:.0f}ms -> {delta.latency_b:.0f}ms"
+            f" ({latency_change:+.1%})"
+        )
+    return line
+
+
+def _import(conn: sqlite3.Connection, args: argparse.Namespace) -> int:
+    results = json.load(args.results)
+    run = args.run or default_run_name(results)
+    count = import_results(conn, results, run, args.results.name, args.replace)
+    print(f"Imported {count} results as run [{run}].")
+    return 0
+
+
+def _compare(conn: sqlite3.Connection, args: argparse.Namespace) -> int:
+    deltas, only_a, only_b = compare(conn, args.run_a, args.run_b)
+    for delta in deltas:
+        print(_format_delta(delta, args.threshold))
+    regressions = sum(delta.is_regression(args.threshold) for delta in deltas)
+    print(
+        f"{len(deltas)} configurations compared; {regressions} regressions"
+        f" (threshold {args.threshold:.0%})."
+    )
+    if only_a or only_b:
+        print(
+            f"Skipped {only_a} configurations only in [{args.run_a}]"
+            f" and {only_b} only in [{args.run_b}]."
+        )
+    return 1 if regressions else 0
+
+
+def main(argv: List[str]) -> int:
+    parser = argparse.ArgumentParser(
+        prog="experiments db",
+        description=__doc__.partition("\n\n")[0],
+    )
+    parser.add_argument(
+        "--db", default=DEFAULT_DB, help=f"path to the database (default: {DEFAULT_DB})"
+    )
+    subparsers = parser.add_subparsers(required=True, dest="command")
+
+    import_parser = subparsers.add_parser(
+        "import", help="store a results file as a run"
+    )
+    import_parser.add_argument(
+        "results", type=argparse.FileType("r"), help="results file (JSON)"
+    )
+    import_parser.add_argument(
+        "--run", help="name for the run (default: the results' git commit)"
+    )
+    import_parser.add_argument(
+        "--replace", action="store_true", help="overwrite a run with the same name"
+    )
+    import_parser.set_defaults(handler=_import)
+
+    compare_parser = subparsers.add_parser(
+        "compare", help="compare two runs, configuration by configuration"
+    )
+    compare_parser.add_argument("run_a", help="baseline run")
+    compare_parser.add_argument("run_b", help="run to compare against the baseline")
+    compare_parser.add_argument(
+        "--threshold",
+        type=float,
+        default=DEFAULT_THRESHOLD,
+        help="relative change that counts as a regression (default: %(default)s)",
+    )
+    compare_parser.set_defaults(handler=_compare)
+
+    args = parser.parse_args(argv)
+    conn = connect(args.db)
+    try:
+        return args.handler(conn, args)
+    except ValueError as err:
+        parser.exit(2, f"experiments db: error: {err}\n")
+    finally:
+        conn.close()
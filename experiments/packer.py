@@ -76,7 +76,13 @@ def ensure_ami_build(
     config: system.PackerConfig,
     force_rebuilt: Optional[Set[system.PackerConfig]],
     packer_dir: Path,
+    background: bool = False,
 ) -> Build:
+    """Find a build matching config, building a new AMI if needed (or forced).
+
+    In the background, report progress without a spinner so we don't clobber
+    whatever is running in the foreground.
+    """
     manifest_path = packer_dir / "manifest.json"
     builds = Manifest.from_disk(manifest_path)
     build = builds.most_recent_matching(config)
@@ -94,13 +100,15 @@ def ensure_ami_build(
         packer_vars = cloud.format_args(args)
         with open("packer.log", "w") as log_file:
             msg = f"[infrastructure] building AMI (output in [{log_file.name}])"
-            with Halo(msg) as spinner:
-                check_call(
-                    ["packer", "build"] + packer_vars + ["main.pkr.hcl"],
-                    stdout=log_file,
-                    cwd=packer_dir,
-                )
-                spinner.succeed()
+            cmd = ["packer", "build"] + packer_vars + ["main.pkr.hcl"]
+            if background:
+                Halo(f"{msg} in the background").info()
+                check_call(cmd, stdout=log_file, cwd=packer_dir)
+                Halo("[infrastructure] built AMI").succeed()
+            else:
+                with Halo(msg) as spinner:
+                    check_call(cmd, stdout=log_file, cwd=packer_dir)
+                    spinner.succeed()
 
     builds = Manifest.from_disk(manifest_path)
     build = builds.most_recent_matching(config)
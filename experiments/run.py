@@ -63,19 +63,56 @@ async def _connect_ssh(hostname: Hostname, *args, **kwargs) -> AsyncIterator[Mac
         raise reraise_err from None
 
 
+def schedule_builds(
+    environments: List[Environment],
+    system: System,
+    force_rebuilt: Set[Any],
+    build_args: BuildArgs,
+) -> Dict[Environment, asyncio.Task]:
+    """Start (re)building the AMIs for all environments in the background.
+
+    Building an AMI includes compiling, which is slow. Rather than building
+    everything up front, builds run one at a time in the order the environments
+    are needed: the first environment can deploy and run experiments while the
+    AMIs for later environments build. Environments that share a Packer config
+    share a build.
+
+    Returns a task (resolving to the packer.Build) per environment.
+    """
+    by_config: Dict[Any, asyncio.Task] = {}
+    previous: Optional[asyncio.Task] = None
+
+    async def build_after(config, previous: Optional[asyncio.Task]) -> packer.Build:
+        if previous is not None:
+            # Any failure gets raised to whoever awaits that build.
+            await asyncio.wait([previous])
+        return await asyncio.to_thread(
+            packer.ensure_ami_build,
+            config,
+            force_rebuilt,
+            system.root_dir,
+            background=True,
+        )
+
+    tasks = {}
+    for environment in environments:
+        config = system.packer_config.from_args(build_args, environment)
+        if config not in by_config:
+            by_config[config] = asyncio.create_task(build_after(config, previous))
+            previous = by_config[config]
+        tasks[environment] = by_config[config]
+    return tasks
+
+
 @contextmanager
 def packer_and_tf(
     environment: Environment,
     system: System,
-    force_rebuilt: Optional[Set[Any]],
+    build: Optional[packer.Build],
     build_args: BuildArgs,
 ) -> Iterator[Dict[Any, Any]]:
     packer_config = system.packer_config.from_args(build_args, environment)
 
-    if force_rebuilt is not None:
-        build = packer.ensure_ami_build(packer_config, force_rebuilt, system.root_dir)
-    else:
-        build = None
     tf_vars = environment.make_tf_vars(build, build_args)
     try:
         with cloud.terraform(tf_vars, system.root_dir) as data:
@@ -92,25 +129,30 @@ def packer_and_tf(
 async def deployed(
     environment: Environment,
     system: System,
-    force_rebuilt: Optional[Set[Any]],
+    build_task: Optional[asyncio.Task],
     build_args: BuildArgs,
 ) -> AsyncIterator[Setting]:
     """Yields a Setting (handle to populated environment).
 
     This might require a few steps:
 
-    1. create a Packer image (if it's missing or forced)
+    1. wait for a Packer image (if it's missing or forced)
     2. `terraform apply`
     3. connecting (SSH) to the deployed machines
     4. performing additional setup (depending on the system)
 
-    The "if forced" bit is a little tricky. We keep a (mutable) set of the
-    configurations that have been rebuilt, since we want to rebuild at most once
-    per execution. If that argument is None, we don't force rebuilding.
+    Forced builds are started ahead of time by schedule_builds(); build_task is
+    the one for this environment (None if we're not forcing a rebuild).
     """
     Halo(f"[infrastructure] {environment}").stop_and_persist(symbol="•")
 
-    with packer_and_tf(environment, system, force_rebuilt, build_args) as data:
+    build = None
+    if build_task is not None:
+        if not build_task.done():
+            Halo("[infrastructure] waiting for AMI build").info()
+        build = await build_task
+
+    with packer_and_tf(environment, system, build, build_args) as data:
         ssh_key = asyncssh.import_private_key(data["private_key"])
 
         # The "stack" bit is so that we can have an async context manager that,
@@ -223,17 +265,28 @@ async def run_experiments(
     We clean up the environment at the end if requested (by args.cleanup).
     """
     system = system_args.system
-    # a mutable set indicates that we should rebuild everything (but at most one
-    # time per execution!)
-    force_rebuilt = set() if args.packer.force_rebuild else None
+    build_args = system_args.build
+    by_environment = group_by_environment(all_experiments)
+
+    # When forcing, we rebuild every configuration (but at most once per
+    # execution!), overlapping the builds with running experiments.
+    build_tasks: Dict[Environment, asyncio.Task] = {}
+    if args.packer.force_rebuild:
+        environments = [env for env, _ in by_environment]
+        build_tasks = schedule_builds(environments, system, set(), build_args)
 
     cleanup = cloud.cleanup(system) if args.cleanup else nullcontext()
-    with cleanup:
-        for env, env_experiments in group_by_environment(all_experiments):
-            build_args = system_args.build
-            async with deployed(env, system, force_rebuilt, build_args) as setting:
-                for experiment in env_experiments:
-                    print()
-                    Halo(f"{experiment}").stop_and_persist(symbol="•")
-                    result = await retry_experiment(experiment, setting, ctrl_c)
-                    yield result
+    try:
+        with cleanup:
+            for env, env_experiments in by_environment:
+                build_task = build_tasks.get(env)
+                async with deployed(env, system, build_task, build_args) as setting:
+                    for experiment in env_experiments:
+                        print()
+                        Halo(f"{experiment}").stop_and_persist(symbol="•")
+                        result = await retry_experiment(experiment, setting, ctrl_c)
+                        yield result
+    finally:
+        # Don't start builds we won't use (one already running will finish).
+        for task in build_tasks.values():
+            task.cancel()
@@ -0,0 +1,127 @@
+"""Reading experiments files, with errors that say where the problem is.
+
+A typo in an experiments file should stop us before we build any images or
+launch any machines, and the error should point at the offending experiment
+(`experiments.json:12:3: ...`) rather than being a bare `TypeError` from deep
+inside a constructor.
+"""
+from __future__ import annotations
+
+import dataclasses
+import difflib
+import json
+
+from typing import Any, Dict, Iterator, List, TextIO, Tuple, Type
+
+from experiments.system import Experiment
+
+
+class ExperimentsFileError(ValueError):
+    """A problem with an experiments file, at a specific line and column."""
+
+    def __init__(self, path: str, line: int, column: int, msg: str):
+        super().__init__(f"{path}:{line}:{column}: {msg}")
+        self.path = path
+        self.line = line
+        self.column = column
+
+
+def check_fields(cls: Type[Any], data: Dict[str, Any], what: str):
+    """Check that `data` has every required field of dataclass `cls`, and no others.
+
+    >>> @dataclasses.dataclass
+    ... class Foo:
+    ...     channels: int
+    ...     clients: int = 1
+    >>> check_fields(Foo, {"channels": 1}, "foo")
+    >>> check_fields(Foo, {"chanels": 1}, "foo")
+    Traceback (most recent call last):
+    ...
+    ValueError: Unknown field [chanels] in foo (did you mean [channels]?).
+    >>> check_fields(Foo, {"clients": 1}, "foo")
+    Traceback (most recent call last):
+    ...
+    ValueError: Missing required field [channels] in foo.
+    """
+    if not isinstance(data, dict):
+        raise ValueError(f"Expected an object for {what}, got {data!r}.")
+    fields = dataclasses.fields(cls)
+    known = [f.name for f in fields]
+    for key in data:
+        if key not in known:
+            close = difflib.get_close_matches(key, known, n=1)
+            hint = f" (did you mean [{close[0]}]?)" if close else ""
+            raise ValueError(f"Unknown field [{key}] in {what}{hint}.")
+    for f in fields:
+        required = (
+            f.default is dataclasses.MISSING
+            and f.default_factory is dataclasses.MISSING  # type: ignore
+        )
+        if required and f.name not in data:
+            raise ValueError(f"Missing required field [{f.name}] in {what}.")
+
+
+def _position(text: str, offset: int) -> Tuple[int, int]:
+    """The (1-indexed) line and column of `offset` in `text`.
+
+    >>> _position('[\\n  {"a": 1}]', 4)
+    (2, 3)
+    """
+    line = text.count("\n", 0, offset) + 1
+    column = offset - text.rfind("\n", 0, offset)
+    return line, column
+
+
+def _skip_whitespace(text: str, idx: int) -> int:
+    while idx < len(text) and text[idx] in " \t\r\n":
+        idx += 1
+    return idx
+
+
+def _iter_array(text: str) -> Iterator[Tuple[int, Any]]:
+    """Yield (offset, value) for each element of the top-level JSON array in `text`.
+
+    Raises `json.JSONDecodeError` for malformed JSON (or anything but an array).
+    """
+    decoder = json.JSONDecoder()
+    idx = _skip_whitespace(text, 0)
+    if text[idx : idx + 1] != "[":
+        raise json.JSONDecodeError("Expecting a list of experiments", text, idx)
+    idx = _skip_whitespace(text, idx + 1)
+    if text[idx : idx + 1] != "]":
+        while True:
+            value, end = decoder.raw_decode(text, idx)
+            yield idx, value
+            idx = _skip_whitespace(text, end)
+            if text[idx : idx + 1] == "]":
+                break
+            if text[idx : idx + 1] != ",":
+                raise json.JSONDecodeError("Expecting ',' delimiter", text, idx)
+            idx = _skip_whitespace(text, idx + 1)
+    idx = _skip_whitespace(text, idx + 1)
+    if idx != len(text):
+        raise json.JSONDecodeError("Extra data", text, idx)
+
+
+def load_experiments(file: TextIO, experiment: Type[Experiment]) -> List[Experiment]:
+    """Read a JSON list of experiments from `file`.
+
+    Checks every experiment's fields (and whatever else `experiment.from_dict`
+    checks) up-front, raising `ExperimentsFileError` for the first problem.
+    """
+    path = getattr(file, "name", "<experiments>")
+    text = file.read()
+    experiments = []
+    try:
+        for idx, (offset, data) in enumerate(_iter_array(text)):
+            try:
+                if dataclasses.is_dataclass(experiment):
+                    check_fields(experiment, data, "experiment")
+                experiments.append(experiment.from_dict(data))
+            except (ValueError, TypeError) as err:
+                line, column = _position(text, offset)
+                msg = f"experiment {idx}: {err}"
+                raise ExperimentsFileError(path, line, column, msg) from err
+    except json.JSONDecodeError as err:
+        raise ExperimentsFileError(path, err.lineno, err.colno, err.msg) from None
+    return experiments
@@ -1,12 +1,14 @@
 from __future__ import annotations
 
 import asyncio
+import json
 import math
 import re
 
 from abc import ABC, abstractmethod
 from contextlib import contextmanager
 from dataclasses import dataclass, field
+from collections import Counter
 from itertools import chain, starmap, product, cycle
 from operator import attrgetter, itemgetter
 from pathlib import Path
@@ -34,7 +36,16 @@ from tenacity import stop_after_attempt, wait_fixed, AsyncRetrying
 from experiments import system, packer
 
 from experiments.system import Result, Machine, Milliseconds
-from experiments.cloud import DEFAULT_INSTANCE_TYPE, InstanceType, SHA, AWS_REGION
+from experiments.cloud import (
+    DEFAULT_INSTANCE_TYPE,
+    InstanceType,
+    SHA,
+    AWS_REGION,
+    REGIONS,
+    Region,
+    instance_arch,
+)
+from experiments.schema import check_fields
 from experiments.util import Bytes
 
 BuildProfile = NewType("BuildProfile", str)
@@ -43,15 +54,63 @@ BuildProfile = NewType("BuildProfile", str)
 # Need to update install.sh to change this
 MAX_WORKERS_PER_MACHINE = 10
 
+# Viewer processes in non-hammer mode each run this many threads (one client
+# per thread).
+VIEWER_THREADS = 20
+
 EXPERIMENT_TIMEOUT = 60.0
 EXPERIMENT_LONG_TIMEOUT = 1000
 
 
+def _check_positive(name: str, value: Any):
+    if isinstance(value, bool) or not isinstance(value, int) or value <= 0:
+        raise ValueError(f"[{name}] must be a positive integer, got {value!r}.")
+
+
+def _check_region(region: str) -> Region:
+    if region not in REGIONS:
+        raise ValueError(f"Invalid region [{region}]. Expected one of {REGIONS}.")
+    return Region(region)
+
+
+@dataclass(frozen=True, order=True)
+class Placement:
+    """Which AWS region each role runs in.
+
+    Services find each other through the config store by public hostname, so
+    cross-region traffic goes over the (real) WAN.
+    """
+
+    publisher: Region = AWS_REGION
+    clients: Region = AWS_REGION
+    # Group i runs in groups[i % len(groups)].
+    groups: Tuple[Region, ...] = (Region("us-east-1"), Region("us-west-1"))
+
+    def group_region(self, group: int) -> Region:
+        return self.groups[group % len(self.groups)]
+
+    @classmethod
+    def from_dict(cls, data: Dict[str, Any]) -> Placement:
+        kwargs: Dict[str, Any] = {}
+        for role in ("publisher", "clients"):
+            if role in data:
+                kwargs[role] = _check_region(data.pop(role))
+        if "groups" in data:
+            groups = data.pop("groups")
+            if isinstance(groups, str):
+                groups = [groups]
+            if not groups:
+                raise ValueError("Need at least one region for groups.")
+            kwargs["groups"] = tuple(map(_check_region, groups))
+        if data:
+            raise ValueError(f"Unknown roles in regions: {list(data.keys())}")
+        return cls(**kwargs)
+
+
 @dataclass
 class Setting(system.Setting):
     publisher: Machine
-    workers_east: List[Machine]
-    workers_west: List[Machine]
+    workers: Dict[Region, List[Machine]]
     clients: List[Machine]
 
     @staticmethod
@@ -60,43 +119,35 @@ class Setting(system.Setting):
     ) -> Dict[Union[str, Tuple[str, int]], str]:
         result = {}
         result["publisher"] = tf_data["publisher"]
-        for idx, worker in enumerate(tf_data["workers_east"]):
-            result[("worker_east", idx)] = worker
-        for idx, worker in enumerate(tf_data["workers_west"]):
-            result[("worker_west", idx)] = worker
-        for idx, client in enumerate(tf_data["clients"]):
+        for region, workers in tf_data["workers"].items():
+            for idx, worker in enumerate(workers):
+                result[("worker", region, idx)] = worker
+        clients = chain.from_iterable(tf_data["clients"].values())
+        for idx, client in enumerate(clients):
             result[("client", idx)] = client
         return result
 
     @classmethod
     def from_dict(cls, machines: Dict[Any, Machine]) -> Setting:
         publisher = None
-        workers_east = []
-        workers_west = []
+        workers: Dict[Region, List[Machine]] = {}
         clients = []
         for ident, machine in machines.items():
             if ident == "publisher":
                 publisher = machine
-            elif ident[0] == "worker_east":
-                workers_east.append(machine)
-            elif ident[0] == "worker_west":
-                workers_west.append(machine)
+            elif ident[0] == "worker":
+                workers.setdefault(Region(ident[1]), []).append(machine)
             elif ident[0] == "client":
                 clients.append(machine)
             else:
                 raise ValueError(f"Invalid identifier [{ident}]")
         if publisher is None:
             raise ValueError("Missing publisher.")
-        return cls(
-            publisher=publisher,
-            workers_east=workers_east,
-            workers_west=workers_west,
-            clients=clients,
-        )
+        return cls(publisher=publisher, workers=workers, clients=clients)
 
     @property
     def all_workers(self):
-        return self.workers_east + self.workers_west
+        return list(chain.from_iterable(self.workers.values()))
 
     async def additional_setup(self):
         with Halo("[infrastructure] starting etcd") as spinner:
@@ -128,28 +179,38 @@ class Setting(system.Setting):
 @dataclass(order=True, frozen=True)
 class Environment(system.Environment):
     instance_type: InstanceType
+    publisher_region: Region
+    client_region: Region
     client_machines: int
-    worker_machines_east: int
-    worker_machines_west: int
+    # (region, count), sorted by region
+    worker_machines: Tuple[Tuple[Region, int], ...]
 
     @property
     def total_machines(self) -> int:
-        return (
-            self.client_machines
-            + self.worker_machines_east
-            + self.worker_machines_west
-            + 1
-        )
+        workers = sum(count for _, count in self.worker_machines)
+        return self.client_machines + workers + 1
+
+    def _machines_by_region(self) -> Dict[Region, Dict[str, int]]:
+        machines: Dict[Region, Dict[str, int]] = {}
+
+        def add(region: Region, role: str, count: int):
+            counts = machines.setdefault(
+                region, {"publisher": 0, "workers": 0, "clients": 0}
+            )
+            counts[role] += count
+
+        add(self.publisher_region, "publisher", 1)
+        add(self.client_region, "clients", self.client_machines)
+        for region, count in self.worker_machines:
+            add(region, "workers", count)
+        return machines
 
     def make_tf_vars(
         self, _build: Optional[packer.Build], build_args: BuildArgs
     ) -> Dict[str, Any]:
         tf_vars = {
             "instance_type": self.instance_type,
-            "client_machine_count": self.client_machines,
-            "worker_machine_east_count": self.worker_machines_east,
-            "worker_machine_west_count": self.worker_machines_west,
-            "region": AWS_REGION,
+            "machines": json.dumps(self._machines_by_region()),
             "sha": build_args.sha,
         }
         return tf_vars
@@ -157,11 +218,8 @@ class Environment(system.Environment):
     @staticmethod
     def make_tf_cleanup_vars():
         return {
-            "region": AWS_REGION,  # must be the same
             "instance_type": DEFAULT_INSTANCE_TYPE,
-            "client_machine_count": 0,
-            "worker_machine_east_count": 0,
-            "worker_machine_west_count": 0,
+            "machines": "{}",
             "sha": "null",
         }
 
@@ -179,14 +237,19 @@ class Protocol(ABC):
 
     @classmethod
     def from_dict(cls, data: Dict[str, Any]) -> Protocol:
-        assert len(data) == 1
-        key = next(iter(data.keys()))
         subclasses = {cls.__name__: cls for cls in Protocol.__subclasses__()}
+        if not isinstance(data, dict) or len(data) != 1:
+            raise ValueError(
+                f"Invalid protocol {data}. "
+                f"Expected an object with one of {list(subclasses.keys())}."
+            )
+        key = next(iter(data.keys()))
         subcls: Optional[Type[Protocol]] = subclasses.get(key, None)
         if subcls is None:
             raise ValueError(
                 f"Invalid protocol {data}. Expected one of {list(subclasses.keys())}."
             )
+        check_fields(subcls, data[key], f"protocol {key}")
         return subcls._from_dict(data[key])  # pylint: disable=protected-access
 
 
@@ -198,6 +261,9 @@ class Symmetric(Protocol):
     def flag(self) -> str:
         return f"--security {self.security}"
 
+    def __post_init__(self):
+        _check_positive("security", self.security)
+
     @classmethod
     def _from_dict(cls, data: Dict[str, Any]) -> Symmetric:
         return cls(**data)
@@ -211,6 +277,9 @@ class SymmetricPub(Protocol):
     def flag(self) -> str:
         return f"--security {self.security} --public"
 
+    def __post_init__(self):
+        _check_positive("security", self.security)
+
     @classmethod
     def _from_dict(cls, data: Dict[str, Any]) -> SymmetricPub:
         return cls(**data)
@@ -224,11 +293,34 @@ class SeedHomomorphic(Protocol):
     def flag(self) -> str:
         return "--security-multi-key 16"
 
+    def __post_init__(self):
+        _check_positive("parties", self.parties)
+        if self.parties < 2:
+            raise ValueError(f"Need at least 2 parties, got {self.parties}.")
+
     @classmethod
     def _from_dict(cls, data: Dict[str, Any]) -> SeedHomomorphic:
         return cls(**data)
 
 
+@dataclass(frozen=True)
+class Insecure(Protocol):
+    """No cryptography: for measuring network and serialization overhead."""
+
+    parties: int = 2
+
+    @property
+    def flag(self) -> str:
+        return "--no-security"
+
+    def __post_init__(self):
+        _check_positive("parties", self.parties)
+
+    @classmethod
+    def _from_dict(cls, data: Dict[str, Any]) -> Insecure:
+        return cls(**data)
+
+
 async def _install_spectrum_config(machine: Machine, spectrum_config: Dict[str, Any]):
     spectrum_config_str = "\n".join([f"{k}={v}" for k, v in spectrum_config.items()])
     with NamedTemporaryFile() as tmp:
@@ -240,10 +332,37 @@ async def _install_spectrum_config(machine: Machine, spectrum_config: Dict[str,
     )
 
 
+KEYS_DIR = "/home/ubuntu/spectrum/keys"
+
+
+async def _distribute_keys(publisher: Machine, clients: List[Machine], etcd_url: str):
+    """Copy the experiment's channel keys to every client machine.
+
+    Each client ends up with `KEYS_DIR/key-<idx>.json` for its broadcasters.
+    """
+    await publisher.ssh.run(
+        f"rm -rf {KEYS_DIR} && "
+        f"SPECTRUM_CONFIG_SERVER={etcd_url} "
+        f"/home/ubuntu/spectrum/setup export-keys --out {KEYS_DIR}",
+        check=True,
+        timeout=30,
+    )
+    with TemporaryDirectory() as tmpdir:
+        await asyncssh.scp((publisher.ssh, KEYS_DIR), tmpdir, recurse=True)
+        keys = Path(tmpdir) / Path(KEYS_DIR).name
+
+        async def upload(client: Machine):
+            await client.ssh.run(f"rm -rf {KEYS_DIR}", check=True)
+            await asyncssh.scp(
+                str(keys), (client.ssh, str(Path(KEYS_DIR).parent)), recurse=True
+            )
+
+        await asyncio.gather(*map(upload, clients))
+
+
 async def _prepare_worker(
     machine: Machine,
     group: int,
-    worker_start_idx: int,
     num_workers: int,
     etcd_env: Mapping[str, str],
     leader: bool,
@@ -251,7 +370,6 @@ async def _prepare_worker(
     spectrum_config: Dict[str, Any] = {
         "SPECTRUM_WORKER_GROUP": group,
         "SPECTRUM_LEADER_GROUP": group,
-        "SPECTRUM_WORKER_START_INDEX": worker_start_idx,
         "SPECTRUM_LOG_LEVEL": "debug",
         "SPECTRUM_TLS_CA": "/home/ubuntu/spectrum/data/ca.crt",
         "SPECTRUM_TLS_KEY": "/home/ubuntu/spectrum/data/server.key",
@@ -291,6 +409,43 @@ class Experiment(system.Experiment):
     protocol: Protocol = Symmetric()
     hammer: bool = True
     expected_runtime: int = None
+    regions: Placement = Placement()
+
+    def __post_init__(self):
+        for name in (
+            "clients",
+            "channels",
+            "message_size",
+            "workers_per_machine",
+            "worker_machines_per_group",
+        ):
+            _check_positive(name, getattr(self, name))
+        for name in ("clients_per_machine", "expected_runtime"):
+            if getattr(self, name) is not None:
+                _check_positive(name, getattr(self, name))
+        if self.workers_per_machine > MAX_WORKERS_PER_MACHINE:
+            raise ValueError(
+                f"[workers_per_machine] can be at most {MAX_WORKERS_PER_MACHINE}, "
+                f"got {self.workers_per_machine}."
+            )
+        if self.channels > self.clients:
+            raise ValueError(
+                f"Need at least as many clients ({self.clients}) "
+                f"as channels ({self.channels})."
+            )
+        if not isinstance(self.hammer, bool):
+            raise ValueError(f"[hammer] must be true or false, got {self.hammer!r}.")
+        if not isinstance(self.protocol, Protocol):
+            raise ValueError(f"Invalid protocol {self.protocol!r}.")
+        if not self.hammer:
+            # Each machine runs its clients in whole viewer processes.
+            for count in distribute(self.clients, self.cpm):
+                if count % VIEWER_THREADS != 0:
+                    raise ValueError(
+                        f"Without hammer, clients per machine must be multiples of "
+                        f"{VIEWER_THREADS}; [clients] {self.clients} and "
+                        f"[clients_per_machine] {self.cpm} give a machine {count}."
+                    )
 
     @property
     def groups(self) -> int:
@@ -298,11 +453,11 @@ class Experiment(system.Experiment):
             return 2
         if isinstance(self.protocol, SymmetricPub):
             return 2
-        if isinstance(self.protocol, SeedHomomorphic):
+        if isinstance(self.protocol, (SeedHomomorphic, Insecure)):
             return self.protocol.parties
         raise TypeError(
             f"Invalid protocol {self.protocol}. "
-            "Expected one of Symmetric, SymmetricPub, SeedHomomorphic"
+            "Expected one of Symmetric, SymmetricPub, SeedHomomorphic, Insecure"
         )
 
     @property
@@ -320,12 +475,18 @@ class Experiment(system.Experiment):
 
     def to_environment(self) -> Environment:
         client_machines = math.ceil(self.clients / self.cpm)
-        worker_machines_east = self.worker_machines_per_group * ((self.groups + 1) // 2)
-        worker_machines_west = self.worker_machines_per_group * (self.groups // 2)
+        groups_by_region = Counter(map(self.regions.group_region, range(self.groups)))
+        worker_machines = tuple(
+            sorted(
+                (region, groups * self.worker_machines_per_group)
+                for region, groups in groups_by_region.items()
+            )
+        )
         return Environment(
             instance_type=self.instance_type,
-            worker_machines_east=worker_machines_east,
-            worker_machines_west=worker_machines_west,
+            publisher_region=self.regions.publisher,
+            client_region=self.regions.clients,
+            worker_machines=worker_machines,
             client_machines=client_machines,
         )
 
@@ -334,6 +495,9 @@ class Experiment(system.Experiment):
         protocol = data.pop("protocol", None)
         if protocol is not None:
             data["protocol"] = Protocol.from_dict(protocol)
+        regions = data.pop("regions", None)
+        if regions is not None:
+            data["regions"] = Placement.from_dict(regions)
         return cls(**data)
 
     async def _fetch_timing(
@@ -377,6 +541,20 @@ class Experiment(system.Experiment):
         queries = int(total_qps * (int(max_time) / 1000))
         return (queries, Milliseconds(max_time))
 
+    async def _fetch_metadata(self, publisher: Machine) -> Optional[Dict[str, Any]]:
+        """Run metadata the publisher logged at startup.
+
+        That's the git commit, build profile, experiment ID, and protocol parameters.
+        """
+        cmd_result = await publisher.ssh.run(
+            "journalctl --unit spectrum-publisher | grep -o 'Run metadata: .*'",
+        )
+        for line in cmd_result.stdout.split("\n"):
+            match = re.match(r"Run metadata: (.*)", line)
+            if match:
+                return json.loads(match.group(1))
+        return None
+
     async def _fetch_latencies(
         self, client: Machine
     ) -> Optional[Tuple[Milliseconds, int]]:
@@ -418,7 +596,7 @@ class Experiment(system.Experiment):
             if isinstance(self.protocol, SymmetricPub):
                 threads //= 4
         else:
-            threads = 20
+            threads = VIEWER_THREADS
             nprocs = count // threads
             assert count % threads == 0
         spectrum_config: Dict[str, Any] = {
@@ -470,6 +648,7 @@ class Experiment(system.Experiment):
         total_time = sum(map(itemgetter(0), latencies))
         total_requests = sum(map(itemgetter(1), latencies))
         mean_latency = int(total_time / total_requests)
+        metadata = await self._fetch_metadata(setting.publisher)
 
         if self.hammer:
             results = await asyncio.gather(
@@ -489,6 +668,7 @@ class Experiment(system.Experiment):
                 time=Milliseconds(int(mean_time)),
                 queries=(total_qps * mean_time / 1000),
                 mean_latency=mean_latency,
+                metadata=metadata,
             )
         else:
             result = await setting.publisher.ssh.run(
@@ -503,12 +683,11 @@ class Experiment(system.Experiment):
                 time=Milliseconds(time),
                 queries=self.clients,
                 mean_latency=mean_latency,
+                metadata=metadata,
             )
 
     async def _inner_run(self, setting: Setting, spinner: Halo) -> Result:
         publisher = setting.publisher
-        workers_east = setting.workers_east
-        workers_west = setting.workers_west
         clients = setting.clients
 
         etcd_url = f"etcd://{publisher.hostname}:2379"
@@ -540,25 +719,27 @@ class Experiment(system.Experiment):
             check=True,
             timeout=30,
         )
+        spinner.text = "[experiment] distributing channel keys"
+        await _distribute_keys(publisher, clients, etcd_url)
 
         spinner.text = "[experiment] starting workers and clients"
         assert self.workers_per_machine <= MAX_WORKERS_PER_MACHINE
         tasks = []
-        workers_by_region = cycle((iter(workers_east), iter(workers_west)))
-        for (group, workers) in zip(range(self.groups), workers_by_region):
-            worker_start_idx = 0
+        workers_by_region = {
+            region: iter(workers) for region, workers in setting.workers.items()
+        }
+        for group in range(self.groups):
+            workers = workers_by_region[self.regions.group_region(group)]
             for idx in range(self.worker_machines_per_group):
                 worker = next(workers)
                 leader = idx == 0 and not self.hammer
                 task = _prepare_worker(
                     worker,
                     group + 1,
-                    worker_start_idx,
                     self.workers_per_machine,
                     etcd_env,
                     leader,
                 )
-                worker_start_idx += self.workers_per_machine
                 tasks.append(task)
         await asyncio.gather(*tasks)
 
@@ -589,8 +770,7 @@ class Experiment(system.Experiment):
         finally:
             spinner.text = "[experiment] shutting everything down"
             shutdowns = []
-            all_workers = setting.workers_west + setting.workers_east
-            for worker in all_workers:
+            for worker in setting.all_workers:
                 shutdowns.append(
                     worker.ssh.run(
                         "sudo systemctl stop 'spectrum-worker@*'", check=False
@@ -636,6 +816,7 @@ class PackerConfig(system.PackerConfig):
                 "profile": self.profile,
                 "region": AWS_REGION,
                 "instance_type": self.instance_type,
+                "arch": instance_arch(self.instance_type),
             }
 
     def matches(self, build: Dict[str, str]) -> bool:
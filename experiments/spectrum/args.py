@@ -128,7 +128,8 @@ For example:
 
 Also configurable:
 
-- `instance_type`: AWS instance type (same for all machines).
+- `instance_type`: AWS instance type (same for all machines). Graviton types
+  (e.g. `c6g.4xlarge`, `c7g.4xlarge`) get arm64 images and binaries.
 - `clients_per_machine`
 - `workers_per_machine`: *processes* to run on each machine
 - `worker_machines_per_group`: worker *machines* in each group
@@ -136,6 +137,11 @@ Also configurable:
   - `{"Symmetric": {"security": 16}}` (16-byte prime, 2 groups)
   - `{"SymmetricPub": {"security": 16}}` (16-byte prime, public, 2 groups)
   - `{"SeedHomomorphic": {"parties": 3}}` (3 groups, default security)
+  - `{"Insecure": {"parties": 2}}` (no cryptography, for measuring overhead)
+- `regions`: AWS regions for each role, e.g.
+  `{"publisher": "us-east-2", "clients": "us-west-2", "groups": ["eu-west-1", "us-east-1"]}`
+  (group i runs in `groups[i % len(groups)]`). Defaults to the publisher and
+  clients in us-east-2, and groups alternating between us-east-1 and us-west-1.
 """,
         )
         parser.set_defaults(arg_cls=cls)
@@ -7,11 +7,12 @@
 # Binaries go to $HOME/ubuntu/spectrum/.
 #
 # Need Spectrum source in $HOME/spectrum-src.tar.gz and AWS credentials set via environment variable.
+# $ARCH ("amd64" or "arm64") keeps binaries for different architectures apart.
 set -x
 set -eufo pipefail
 
 S3_BUCKET=hornet-spectrum
-ARCHIVE_NAME="spectrum-${SRC_SHA}-${INSTANCE_TYPE}-${PROFILE}"
+ARCHIVE_NAME="spectrum-${SRC_SHA}-${ARCH}-${INSTANCE_TYPE}-${PROFILE}"
 S3_OBJECT="s3://${S3_BUCKET}/${ARCHIVE_NAME}"
 
 object_exists=$(aws s3api head-object --bucket $S3_BUCKET --key $ARCHIVE_NAME || true)
@@ -16,7 +16,8 @@ curl https://sh.rustup.rs -sSf | sh -s -- \
     -y \
     --default-toolchain nightly-2021-11-07  # TODO
 
-curl https://awscli.amazonaws.com/awscli-exe-linux-x86_64.zip \
+# x86_64 or aarch64
+curl "https://awscli.amazonaws.com/awscli-exe-linux-$(uname -m).zip" \
     -o "awscliv2.zip"
 unzip -q awscliv2.zip
 sudo ./aws/install
@@ -12,6 +12,12 @@ variable "instance_type" {
   type = string
 }
 
+# Must match instance_type: "amd64" or "arm64" (Graviton).
+variable "arch" {
+  type    = string
+  default = "amd64"
+}
+
 variable "profile" {
   type    = string
   default = "debug"
@@ -35,7 +41,7 @@ variable "src_archive" {
 data "amazon-ami" "ubuntu" {
   access_key = var.aws_access_key
   filters = {
-    name                = "ubuntu/images/*ubuntu-focal-20.04-amd64-server-*"
+    name                = "ubuntu/images/*ubuntu-focal-20.04-${var.arch}-server-*"
     root-device-type    = "ebs"
     virtualization-type = "hvm"
   }
@@ -52,7 +58,8 @@ source "amazon-ebs" "spectrum" {
   ami_name      = "spectrum-${local.timestamp}"
   instance_type = var.instance_type
   region        = var.region
-  ami_regions   = ["us-east-1", "us-west-1"]
+  # Every region in REGIONS (cloud.py) besides this one.
+  ami_regions   = ["us-east-1", "us-west-1", "us-west-2", "eu-west-1"]
   secret_key    = var.aws_secret_key
   source_ami    = data.amazon-ami.ubuntu.id
   ssh_username  = "ubuntu"
@@ -64,6 +71,7 @@ source "amazon-ebs" "spectrum" {
     Project = "spectrum"
     Sha = var.sha
     InstanceType = var.instance_type
+    Arch = var.arch
   }
 }
 
@@ -89,13 +97,14 @@ build {
   }
 
   provisioner "shell" {
-    environment_vars = ["AWS_ACCESS_KEY_ID=${var.aws_access_key}", "AWS_SECRET_ACCESS_KEY=${var.aws_secret_key}", "SRC_SHA=${var.sha}", "INSTANCE_TYPE=${var.instance_type}", "PROFILE=${var.profile}"]
+    environment_vars = ["AWS_ACCESS_KEY_ID=${var.aws_access_key}", "AWS_SECRET_ACCESS_KEY=${var.aws_secret_key}", "SRC_SHA=${var.sha}", "INSTANCE_TYPE=${var.instance_type}", "ARCH=${var.arch}", "PROFILE=${var.profile}"]
     script           = "./compile.sh"
   }
 
   post-processor "manifest" {
     custom_data = {
       instance_type = var.instance_type
+      arch          = var.arch
       profile       = var.profile
       sha           = var.sha
     }
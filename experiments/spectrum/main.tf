@@ -12,29 +12,39 @@ terraform {
   }
 }
 
+# Machines go in any of these regions (see REGIONS in cloud.py). Providers
+# can't be created dynamically, so each region gets spelled out below.
+
 locals {
   tags = { Project = "spectrum" }
 }
 provider "aws" {
-  region = var.region
+  alias  = "us_east_1"
+  region = "us-east-1"
   default_tags { tags = local.tags }
 }
 provider "aws" {
-  alias  = "east"
-  region = "us-east-1"
+  alias  = "us_east_2"
+  region = "us-east-2"
   default_tags { tags = local.tags }
 }
 provider "aws" {
-  alias  = "west"
+  alias  = "us_west_1"
   region = "us-west-1"
   default_tags { tags = local.tags }
 }
-
-variable "sha" {
-  type = string
+provider "aws" {
+  alias  = "us_west_2"
+  region = "us-west-2"
+  default_tags { tags = local.tags }
+}
+provider "aws" {
+  alias  = "eu_west_1"
+  region = "eu-west-1"
+  default_tags { tags = local.tags }
 }
 
-variable "region" {
+variable "sha" {
   type = string
 }
 
@@ -42,15 +52,23 @@ variable "instance_type" {
   type = string
 }
 
-variable "client_machine_count" {
-  type = number
+# Machine counts by region, e.g.
+# {"us-east-2": {"publisher": 1, "workers": 2, "clients": 3}}
+# (missing regions get no machines).
+variable "machines" {
+  type = map(object({ publisher = number, workers = number, clients = number }))
 }
 
-variable "worker_machine_east_count" {
-  type = number
-}
-variable "worker_machine_west_count" {
-  type = number
+locals {
+  none = { publisher = 0, workers = 0, clients = 0 }
+  machines = {
+    for region in ["us-east-1", "us-east-2", "us-west-1", "us-west-2", "eu-west-1"] :
+    region => lookup(var.machines, region, local.none)
+  }
+  used = {
+    for region, machines in local.machines :
+    region => machines.publisher + machines.workers + machines.clients > 0
+  }
 }
 
 resource "tls_private_key" "main" {
@@ -58,118 +76,107 @@ resource "tls_private_key" "main" {
   rsa_bits  = 4096
 }
 
-module "image_main" {
-  source        = "../modules/image"
-  image_name    = "spectrum_image"
+module "region_us_east_1" {
+  source        = "./modules/region"
+  machines      = local.machines["us-east-1"]
+  sha           = var.sha
   instance_type = var.instance_type
-  providers     = { aws = aws }
-  extra_filters = var.sha != "null" ? { "tag:Sha" = [var.sha] } : {}
+  public_key    = tls_private_key.main.public_key_openssh
+  providers     = { aws = aws.us_east_1 }
 }
-module "image_east" {
-  source        = "../modules/image"
-  image_name    = "spectrum_image"
+module "region_us_east_2" {
+  source        = "./modules/region"
+  machines      = local.machines["us-east-2"]
+  sha           = var.sha
   instance_type = var.instance_type
-  providers     = { aws = aws.east }
-  extra_filters = var.sha != "null" ? { "tag:Sha" = [var.sha] } : {}
+  public_key    = tls_private_key.main.public_key_openssh
+  providers     = { aws = aws.us_east_2 }
 }
-module "image_west" {
-  source        = "../modules/image"
-  image_name    = "spectrum_image"
+module "region_us_west_1" {
+  source        = "./modules/region"
+  machines      = local.machines["us-west-1"]
+  sha           = var.sha
   instance_type = var.instance_type
-  providers     = { aws = aws.west }
-  extra_filters = var.sha != "null" ? { "tag:Sha" = [var.sha] } : {}
+  public_key    = tls_private_key.main.public_key_openssh
+  providers     = { aws = aws.us_west_1 }
 }
-
-module "network_main" {
-  source     = "../modules/net"
-  public_key = tls_private_key.main.public_key_openssh
-  providers  = { aws = aws }
-}
-module "network_east" {
-  source     = "../modules/net"
-  public_key = tls_private_key.main.public_key_openssh
-  providers  = { aws = aws.east }
+module "region_us_west_2" {
+  source        = "./modules/region"
+  machines      = local.machines["us-west-2"]
+  sha           = var.sha
+  instance_type = var.instance_type
+  public_key    = tls_private_key.main.public_key_openssh
+  providers     = { aws = aws.us_west_2 }
 }
-module "network_west" {
-  source     = "../modules/net"
-  public_key = tls_private_key.main.public_key_openssh
-  providers  = { aws = aws.west }
+module "region_eu_west_1" {
+  source        = "./modules/region"
+  machines      = local.machines["eu-west-1"]
+  sha           = var.sha
+  instance_type = var.instance_type
+  public_key    = tls_private_key.main.public_key_openssh
+  providers     = { aws = aws.eu_west_1 }
 }
 
-resource "aws_instance" "publisher" {
-  ami             = module.image_main.ami.id
-  instance_type   = var.instance_type
-  key_name        = module.network_main.key_pair.key_name
-  security_groups = [module.network_main.security_group.name]
-  tags = {
-    Name = "spectrum_publisher"
+locals {
+  regions = {
+    "us-east-1" = module.region_us_east_1
+    "us-east-2" = module.region_us_east_2
+    "us-west-1" = module.region_us_west_1
+    "us-west-2" = module.region_us_west_2
+    "eu-west-1" = module.region_eu_west_1
   }
+  # Every region's security group lets in every instance (in any region).
+  instances = flatten([for region in values(local.regions) : region.instances])
 }
 
-resource "aws_instance" "worker_east" {
-  provider        = aws.east # TODO: east AND west
-  ami             = module.image_east.ami.id
-  count           = var.worker_machine_east_count
-  instance_type   = var.instance_type
-  key_name        = module.network_east.key_pair.key_name
-  security_groups = [module.network_east.security_group.name]
-  tags            = { Name = "spectrum_worker" }
-}
-resource "aws_instance" "worker_west" {
-  provider        = aws.west
-  ami             = module.image_west.ami.id
-  count           = var.worker_machine_west_count
-  instance_type   = var.instance_type
-  key_name        = module.network_west.key_pair.key_name
-  security_groups = [module.network_west.security_group.name]
-  tags            = { Name = "spectrum_worker" }
-}
-
-resource "aws_instance" "client" {
-  ami             = module.image_main.ami.id
-  count           = var.client_machine_count
-  instance_type   = var.instance_type
-  key_name        = module.network_main.key_pair.key_name
-  security_groups = [module.network_main.security_group.name]
-  tags            = { Name = "spectrum_client" }
+module "secgroup_us_east_1" {
+  source         = "./modules/secgroup"
+  count          = local.used["us-east-1"] ? 1 : 0
+  instances      = local.instances
+  security_group = module.region_us_east_1.security_group
+  providers      = { aws = aws.us_east_1 }
 }
-
-locals {
-  instances = concat(aws_instance.client, aws_instance.worker_east, aws_instance.worker_west, [aws_instance.publisher])
+module "secgroup_us_east_2" {
+  source         = "./modules/secgroup"
+  count          = local.used["us-east-2"] ? 1 : 0
+  instances      = local.instances
+  security_group = module.region_us_east_2.security_group
+  providers      = { aws = aws.us_east_2 }
 }
-module "secgroup_main" {
+module "secgroup_us_west_1" {
   source         = "./modules/secgroup"
+  count          = local.used["us-west-1"] ? 1 : 0
   instances      = local.instances
-  security_group = module.network_main.security_group
-  providers      = { aws = aws }
+  security_group = module.region_us_west_1.security_group
+  providers      = { aws = aws.us_west_1 }
 }
-module "secgroup_east" {
+module "secgroup_us_west_2" {
   source         = "./modules/secgroup"
+  count          = local.used["us-west-2"] ? 1 : 0
   instances      = local.instances
-  security_group = module.network_east.security_group
-  providers      = { aws = aws.east }
+  security_group = module.region_us_west_2.security_group
+  providers      = { aws = aws.us_west_2 }
 }
-module "secgroup_west" {
+module "secgroup_eu_west_1" {
   source         = "./modules/secgroup"
+  count          = local.used["eu-west-1"] ? 1 : 0
   instances      = local.instances
-  security_group = module.network_west.security_group
-  providers      = { aws = aws.west }
+  security_group = module.region_eu_west_1.security_group
+  providers      = { aws = aws.eu_west_1 }
 }
 
 output "publisher" {
-  value = aws_instance.publisher.public_dns
-}
-
-output "workers_west" {
-  value = aws_instance.worker_west.*.public_dns
+  value = one(flatten([for region in values(local.regions) : region.publishers]))
 }
 
-output "workers_east" {
-  value = aws_instance.worker_east.*.public_dns
+# By region.
+output "workers" {
+  value = { for name, region in local.regions : name => region.workers }
 }
 
+# By region.
 output "clients" {
-  value = aws_instance.client.*.public_dns
+  value = { for name, region in local.regions : name => region.clients }
 }
 
 output "private_key" {
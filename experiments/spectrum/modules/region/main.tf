@@ -0,0 +1,95 @@
+terraform {
+  required_providers {
+    aws = {
+      source  = "hashicorp/aws"
+      version = "~> 3.0"
+    }
+  }
+}
+
+# The Spectrum machines in one AWS region.
+#
+# With no machines, this creates nothing (not even a network), so unused
+# regions don't need a copy of the image.
+
+variable "sha" {
+  type = string
+}
+
+variable "instance_type" {
+  type = string
+}
+
+variable "public_key" {
+  type = string
+}
+
+variable "machines" {
+  type = object({ publisher = number, workers = number, clients = number })
+}
+
+locals {
+  used = var.machines.publisher + var.machines.workers + var.machines.clients > 0
+}
+
+module "image" {
+  source        = "../../../modules/image"
+  count         = local.used ? 1 : 0
+  image_name    = "spectrum_image"
+  instance_type = var.instance_type
+  extra_filters = var.sha != "null" ? { "tag:Sha" = [var.sha] } : {}
+}
+
+module "network" {
+  source     = "../../../modules/net"
+  count      = local.used ? 1 : 0
+  public_key = var.public_key
+}
+
+resource "aws_instance" "publisher" {
+  count           = var.machines.publisher
+  ami             = module.image[0].ami.id
+  instance_type   = var.instance_type
+  key_name        = module.network[0].key_pair.key_name
+  security_groups = [module.network[0].security_group.name]
+  tags            = { Name = "spectrum_publisher" }
+}
+
+resource "aws_instance" "worker" {
+  count           = var.machines.workers
+  ami             = module.image[0].ami.id
+  instance_type   = var.instance_type
+  key_name        = module.network[0].key_pair.key_name
+  security_groups = [module.network[0].security_group.name]
+  tags            = { Name = "spectrum_worker" }
+}
+
+resource "aws_instance" "client" {
+  count           = var.machines.clients
+  ami             = module.image[0].ami.id
+  instance_type   = var.instance_type
+  key_name        = module.network[0].key_pair.key_name
+  security_groups = [module.network[0].security_group.name]
+  tags            = { Name = "spectrum_client" }
+}
+
+output "publishers" {
+  value = aws_instance.publisher.*.public_dns
+}
+
+output "workers" {
+  value = aws_instance.worker.*.public_dns
+}
+
+output "clients" {
+  value = aws_instance.client.*.public_dns
+}
+
+output "instances" {
+  value = concat(aws_instance.publisher, aws_instance.worker, aws_instance.client)
+}
+
+# null if the region is unused
+output "security_group" {
+  value = local.used ? module.network[0].security_group : null
+}
@@ -43,10 +43,11 @@ def main(argv):
     data = {k: v["value"] for k, v in data.items()}
 
     if args.client is not None:
-        hostname = data["clients"][args.client]
+        clients = [c for region in data["clients"].values() for c in region]
+        hostname = clients[args.client]
         machine = f"clients{args.client}"
     elif args.worker is not None:
-        workers = data["workers_east"] + data["workers_west"]
+        workers = [w for region in data["workers"].values() for w in region]
         hostname = workers[args.worker]
         machine = f"workers{args.worker}"
     else:
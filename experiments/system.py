@@ -145,6 +145,9 @@ class Result:
     time: Milliseconds
     queries: int
     mean_latency: Optional[Milliseconds] = None
+    # What produced the result (code version, build, experiment ID), as the
+    # system reports it.
+    metadata: Optional[Dict[str, Any]] = None
 
     @property
     def qps(self) -> float:
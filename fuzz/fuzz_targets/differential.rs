@@ -0,0 +1,198 @@
+//! Differential fuzzing between the two-key and multi-key VDPFs.
+//!
+//! Both constructions get the same write (a message on a channel, or cover
+//! traffic) under the same access keys, optionally corrupted the same way. They
+//! should recover the same channel contents, and their audits should accept or
+//! reject together.
+//!
+//! Multi-key messages are group elements, so messages are compared in that
+//! encoding: each 31-byte chunk `m` of a message becomes `g^m` (and the
+//! identity pads out the rest).
+#![no_main]
+use libfuzzer_sys::{arbitrary::Arbitrary, fuzz_target};
+use spectrum_primitives::{
+    AuthKey, Bytes, Dpf, ElementVector, MultiKeyKey, MultiKeyVdpf, TwoKeyKey, TwoKeyVdpf, Vdpf,
+};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::Add;
+
+// Keep the (slow) multi-key evaluations small.
+const MAX_CHANNELS: usize = 4;
+const MAX_MSG_SIZE: usize = 96;
+// Bytes per group element, so that every chunk fits in a scalar.
+const CHUNK_SIZE: usize = 31;
+
+#[derive(Debug, Arbitrary)]
+enum Write {
+    Cover,
+    Message { channel: u8, data: Vec<u8> },
+}
+
+#[derive(Debug, Arbitrary)]
+enum Corruption {
+    None,
+    /// Prove the write with a key that isn't any channel's access key.
+    BadAccessKey,
+    /// Flip one key's share of whether `point` is the one written to.
+    FlipBit {
+        key: u8,
+        point: u8,
+    },
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    channels: u8,
+    msg_size: u8,
+    write: Write,
+    corruption: Corruption,
+}
+
+thread_local! {
+    // Setting up a multi-key VDPF precomputes its PRG's generators: too slow
+    // to do on every run.
+    static MULTI_KEY: RefCell<HashMap<(usize, usize), MultiKeyVdpf>> = Default::default();
+}
+
+fn multi_key_vdpf(channels: usize, msg_size: usize) -> MultiKeyVdpf {
+    let elements = (msg_size + CHUNK_SIZE - 1) / CHUNK_SIZE;
+    MULTI_KEY.with(|cache| {
+        cache
+            .borrow_mut()
+            .entry((channels, elements))
+            .or_insert_with(|| {
+                MultiKeyVdpf::with_channels_parties_msg_size(channels, 2, elements * 32)
+            })
+            .clone()
+    })
+}
+
+fn scalar(bytes: &[u8]) -> AuthKey {
+    AuthKey::try_from(Bytes::from(bytes.to_vec())).expect("chunks fit in a scalar")
+}
+
+// `msg` as group elements, padded out to the length of `null`.
+fn encode<G: Clone + From<AuthKey>>(msg: &Bytes, null: &ElementVector<G>) -> ElementVector<G> {
+    let mut elements = null.0.clone();
+    for (element, chunk) in elements.iter_mut().zip(msg.as_ref().chunks(CHUNK_SIZE)) {
+        *element = G::from(scalar(chunk));
+    }
+    ElementVector(elements)
+}
+
+fn flip_two_key<M, S>(mut key: TwoKeyKey<M, S>, point: usize) -> TwoKeyKey<M, S> {
+    key.bits[point] = !key.bits[point];
+    key
+}
+
+fn flip_multi_key<M, S>(key: MultiKeyKey<M, S>, point: usize) -> MultiKeyKey<M, S>
+where
+    M: Clone,
+    S: Clone + Add<Output = S> + From<AuthKey>,
+{
+    let mut bits = key.bits();
+    bits[point] = bits[point].clone() + S::from(scalar(&[1]));
+    MultiKeyKey::new(key.msg(), bits, key.seeds())
+}
+
+/// Run `write` through `vdpf`: the recovered channels, and whether the audit
+/// passed.
+fn run<V, F>(
+    vdpf: &V,
+    access_keys: &[V::AuthKey],
+    bad_key: &V::AuthKey,
+    write: &Option<(V::Message, usize)>,
+    corruption: &Corruption,
+    flip: F,
+) -> (Vec<V::Message>, bool)
+where
+    V: Vdpf,
+    V::Key: Clone,
+    V::Message: Clone,
+    F: Fn(V::Key, usize) -> V::Key,
+{
+    let (mut dpf_keys, proof_shares) = match write {
+        Some((msg, channel)) => {
+            let dpf_keys = vdpf.gen(msg.clone(), *channel);
+            let access_key = match corruption {
+                Corruption::BadAccessKey => bad_key,
+                _ => &access_keys[*channel],
+            };
+            let proof_shares = vdpf.gen_proofs(access_key, *channel, &dpf_keys);
+            (dpf_keys, proof_shares)
+        }
+        None => (vdpf.gen_empty(), vdpf.gen_proofs_noop()),
+    };
+    if let Corruption::FlipBit { key, point } = corruption {
+        let (key, point) = (*key as usize % vdpf.keys(), *point as usize % vdpf.points());
+        dpf_keys[key] = flip(dpf_keys[key].clone(), point);
+    }
+
+    let tokens = dpf_keys
+        .iter()
+        .zip(proof_shares)
+        .map(|(key, proof_share)| vdpf.gen_audit(access_keys, key, proof_share))
+        .collect();
+    let audit = vdpf.check_audit(tokens);
+    let parts = dpf_keys.into_iter().map(|key| vdpf.eval(key)).collect();
+    (vdpf.combine(parts), audit)
+}
+
+fuzz_target!(|input: Input| {
+    let channels = 1 + input.channels as usize % MAX_CHANNELS;
+    let msg_size = 1 + input.msg_size as usize % MAX_MSG_SIZE;
+    let two_key = TwoKeyVdpf::with_channels_msg_size(channels, msg_size);
+    let multi_key = multi_key_vdpf(channels, msg_size);
+
+    // Both constructions take the same access keys.
+    let access_keys = two_key.new_access_keys();
+    let bad_key = two_key.new_access_key();
+
+    let write = match &input.write {
+        Write::Cover => None,
+        Write::Message { channel, data } => {
+            let mut data = data.clone();
+            data.resize(msg_size, 0);
+            Some((Bytes::from(data), *channel as usize % channels))
+        }
+    };
+    let null = multi_key.null_message();
+    let multi_key_write = write
+        .as_ref()
+        .map(|(msg, channel)| (encode(msg, &null), *channel));
+
+    let (two_key_channels, two_key_audit) = run(
+        &two_key,
+        &access_keys,
+        &bad_key,
+        &write,
+        &input.corruption,
+        flip_two_key,
+    );
+    let (multi_key_channels, multi_key_audit) = run(
+        &multi_key,
+        &access_keys,
+        &bad_key,
+        &multi_key_write,
+        &input.corruption,
+        flip_multi_key,
+    );
+
+    assert_eq!(
+        two_key_audit, multi_key_audit,
+        "audits disagree (two-key: {}, multi-key: {})",
+        two_key_audit, multi_key_audit
+    );
+    if let Corruption::FlipBit { .. } = input.corruption {
+        // Corrupted keys write garbage, differently for each construction.
+        return;
+    }
+    let two_key_channels: Vec<_> = two_key_channels
+        .iter()
+        .map(|msg| encode(msg, &null))
+        .collect();
+    assert_eq!(two_key_channels, multi_key_channels, "channels disagree");
+});
@@ -1,5 +1,48 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+// The commit we're building, or "unknown" outside of a git checkout.
+fn git_commit() -> String {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output();
+    let commit = match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        _ => return "unknown".to_string(),
+    };
+    let dirty = Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .output()
+        .map(|output| !output.stdout.is_empty())
+        .unwrap_or(false);
+    if dirty {
+        format!("{}-dirty", commit)
+    } else {
+        commit
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tonic_build::compile_protos("proto/admin.proto")?;
     tonic_build::compile_protos("proto/health.proto")?;
     tonic_build::compile_protos("proto/spectrum.proto")?;
+
+    // Stamped into every binary; see `spectrum::metadata`.
+    println!("cargo:rustc-env=SPECTRUM_GIT_COMMIT={}", git_commit());
+    println!(
+        "cargo:rustc-env=SPECTRUM_BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap_or_else(|_| "unknown".to_string())
+    );
+    // Printing any of these turns off the default (rerun on any change in the
+    // package), so list everything the stamps depend on. Missing paths would
+    // mean rerunning every build.
+    for path in &["build.rs", "proto", "src", "../.git/HEAD", "../.git/index"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
     Ok(())
 }
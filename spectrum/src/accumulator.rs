@@ -1,9 +1,21 @@
-use spectrum_protocol::Accumulatable;
+use crate::rt::{
+    spawn_blocking,
+    sync::{Mutex, OwnedMutexGuard, RwLock},
+    JoinError,
+};
+use futures::future::{self, Future};
+use spectrum_protocol::{Accumulatable, ParamsMismatch};
 use std::ops::{Deref, DerefMut};
-use tokio::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 pub struct Accumulator<D> {
-    lock: RwLock<(D, usize)>,
+    lock: Arc<RwLock<(D, usize)>>,
+    // Partial states for `accumulate_on()` to update outside of `lock` (so
+    // that updates run concurrently); allocated on first use, and folded into
+    // the state when it's read.
+    partials: Vec<Arc<Mutex<Option<D>>>>,
+    next_partial: AtomicUsize,
 }
 
 impl<D> Accumulator<D>
@@ -11,33 +23,221 @@ where
     D: Accumulatable + Clone,
 {
     pub fn new(accum: D) -> Accumulator<D> {
+        Self::with_partials(accum, 1)
+    }
+
+    /// Like `new()`, but with up to `partials` (at least one) updates from
+    /// `accumulate_on()` running at once.
+    ///
+    /// Each one needs its own partial state, the size of `accum`.
+    pub fn with_partials(accum: D, partials: usize) -> Accumulator<D> {
         let data = (accum, 0_usize);
         Accumulator {
-            lock: RwLock::new(data),
+            lock: Arc::new(RwLock::new(data)),
+            partials: (0..partials.max(1))
+                .map(|_| Arc::new(Mutex::new(None)))
+                .collect(),
+            next_partial: AtomicUsize::new(0),
         }
     }
 
-    pub async fn accumulate(&self, data: D) -> usize {
+    /// Combine `data` into the state, returning the new count.
+    ///
+    /// If `data` doesn't match the state, returns an error and leaves both
+    /// state and count unchanged.
+    pub async fn try_accumulate(&self, data: D) -> Result<usize, ParamsMismatch> {
         let mut lock = self.lock.write().await;
-        let tuple: &mut (D, usize) = lock.deref_mut();
-        let state = &mut tuple.0;
-        let count = &mut tuple.1;
-
-        state.combine(data);
+        let (state, count) = lock.deref_mut();
+        state.try_combine(data)?;
         *count += 1;
-        *count
+        Ok(*count)
     }
 
-    pub async fn get(&self) -> D {
+    /// Update the state in place with `f`, which `run` runs (say, on the
+    /// blocking thread pool, or a scheduled pool).
+    ///
+    /// `f` gets a partial state (see `with_partials()`) rather than the state
+    /// itself, so only updates waiting on the same partial are serialized.
+    /// The partial state comes first, so updates waiting on each other don't
+    /// wait in `run`'s queue too. If `f` panics, the count is not incremented
+    /// (but anything it already wrote to the partial state stays).
+    pub async fn accumulate_on<F, R, Fut>(&self, run: R, f: F) -> Result<usize, JoinError>
+    where
+        F: FnOnce(&mut D) + Send + 'static,
+        D: Send + 'static,
+        R: FnOnce(Box<dyn FnOnce() + Send>) -> Fut,
+        Fut: Future<Output = Result<(), JoinError>>,
+    {
+        let mut partial = self.lock_partial().await;
+        if partial.is_none() {
+            // Not while holding the partial state: `get()` waits on that with
+            // the state locked.
+            drop(partial);
+            let empty = D::empty(self.lock.read().await.0.params());
+            partial = self.lock_partial().await;
+            partial.get_or_insert(empty);
+        }
+        run(Box::new(move || {
+            f(partial.as_mut().expect("Partial state filled in above."));
+        }))
+        .await?;
+        // The partial state is unlocked by now: whoever sees the new count
+        // sees the update when they read the state.
+        let mut lock = self.lock.write().await;
+        lock.1 += 1;
+        Ok(lock.1)
+    }
+
+    // A free partial state if there is one; else, wait for the next in turn.
+    async fn lock_partial(&self) -> OwnedMutexGuard<Option<D>> {
+        let first = self.next_partial.fetch_add(1, Ordering::Relaxed);
+        let partials = self.partials.len();
+        for idx in (first..first + partials).map(|idx| idx % partials) {
+            if let Ok(partial) = self.partials[idx].clone().try_lock_owned() {
+                return partial;
+            }
+        }
+        self.partials[first % partials].clone().lock_owned().await
+    }
+
+    // Combine (and clear) the partial states into `state`.
+    async fn fold_partials(&self, state: &mut D) {
+        for partial in &self.partials {
+            if let Some(partial) = partial.lock().await.take() {
+                state.combine(partial);
+            }
+        }
+    }
+
+    /// The number of updates since the last `reset()`.
+    pub async fn count(&self) -> usize {
         let lock = self.lock.read().await;
-        let (state, _) = lock.deref();
+        lock.deref().1
+    }
+
+    pub async fn get(&self) -> D {
+        let mut lock = self.lock.write().await;
+        let (state, _) = lock.deref_mut();
+        self.fold_partials(state).await;
         state.clone()
     }
+
+    /// Reset to an empty state (with the same parameters) and a zero count.
+    ///
+    /// Returns the old state.
+    pub async fn reset(&self) -> D {
+        let mut lock = self.lock.write().await;
+        let (state, count) = lock.deref_mut();
+        self.fold_partials(state).await;
+        *count = 0;
+        let empty = D::empty(state.params());
+        std::mem::replace(state, empty)
+    }
+}
+
+/// Accumulates vectors (say, a share per channel), folding each one in as it
+/// arrives.
+///
+/// The state is split into contiguous stripes, each with its own lock, and
+/// stripes are combined in parallel on the blocking thread pool. Arrivals
+/// only contend on one stripe at a time, so once the last one arrives, only
+/// its own combining (split across the stripes) is left.
+pub struct StripedAccumulator<D> {
+    stripes: Vec<Arc<Mutex<Vec<D>>>>,
+    stripe_len: usize,
+    len: usize,
+    count: AtomicUsize,
+}
+
+impl<D> StripedAccumulator<D>
+where
+    D: Accumulatable + Clone + Send + 'static,
+{
+    /// Split `accum` into (at most) `stripes` stripes.
+    pub fn new(accum: Vec<D>, stripes: usize) -> Self {
+        let len = accum.len();
+        let stripe_len = len.div_ceil(stripes).max(1);
+        let stripes = accum
+            .chunks(stripe_len)
+            .map(|stripe| Arc::new(Mutex::new(stripe.to_vec())))
+            .collect();
+        StripedAccumulator {
+            stripes,
+            stripe_len,
+            len,
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Combine `data` into the state, returning the new count.
+    ///
+    /// If `data` doesn't match the state, returns an error and leaves both
+    /// state and count unchanged.
+    pub async fn try_accumulate(&self, data: Vec<D>) -> Result<usize, ParamsMismatch> {
+        if data.len() != self.len {
+            return Err(ParamsMismatch::new(self.len, data.len()));
+        }
+        // Combining never changes parameters, so check every stripe before
+        // folding into any of them (without holding the locks in between).
+        for (stripe, chunk) in self.stripes.iter().zip(data.chunks(self.stripe_len)) {
+            let stripe = stripe.lock().await;
+            for (state, elem) in stripe.iter().zip(chunk) {
+                state.check_combine(elem)?;
+            }
+        }
+
+        let mut data = data.into_iter();
+        let folds: Vec<_> = self
+            .stripes
+            .iter()
+            .map(|stripe| {
+                let chunk: Vec<D> = data.by_ref().take(self.stripe_len).collect();
+                let stripe = stripe.clone();
+                async move {
+                    let mut stripe = stripe.lock_owned().await;
+                    spawn_blocking(move || {
+                        for (state, elem) in stripe.iter_mut().zip(chunk) {
+                            state.combine(elem);
+                        }
+                    })
+                    .await
+                    .expect("Combining checked data should not panic.");
+                }
+            })
+            .collect();
+        future::join_all(folds).await;
+        // Only counted once every stripe has it, so whoever sees the last
+        // count sees all the data.
+        Ok(self.count.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    pub async fn get(&self) -> Vec<D> {
+        let mut state = Vec::with_capacity(self.len);
+        for stripe in &self.stripes {
+            state.extend(stripe.lock().await.iter().cloned());
+        }
+        state
+    }
+
+    /// Reset to an empty state (with the same parameters) and a zero count.
+    ///
+    /// Returns the old state.
+    pub async fn reset(&self) -> Vec<D> {
+        let mut state = Vec::with_capacity(self.len);
+        for stripe in &self.stripes {
+            let mut stripe = stripe.lock().await;
+            let empty = stripe.iter().map(|elem| D::empty(elem.params())).collect();
+            state.extend(std::mem::replace(&mut *stripe, empty));
+        }
+        self.count.store(0, Ordering::SeqCst);
+        state
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use spectrum_primitives::Bytes;
 
     #[derive(Debug, Clone, PartialEq, Eq)]
     struct MyData(u8);
@@ -46,7 +246,7 @@ mod tests {
         type Parameters = ();
 
         fn combine(&mut self, other: MyData) {
-            (*self).0 += other.0;
+            self.0 += other.0;
         }
 
         fn empty(_: ()) -> Self {
@@ -67,7 +267,7 @@ mod tests {
     async fn test_accumulator_accumulate_identity() {
         let accumulator = Accumulator::new(MyData::empty(()));
 
-        accumulator.accumulate(MyData::empty(())).await;
+        accumulator.try_accumulate(MyData::empty(())).await.unwrap();
 
         assert_eq!(accumulator.get().await, MyData(0));
     }
@@ -78,20 +278,190 @@ mod tests {
         let count = 10;
 
         for _ in 0..count {
-            accumulator.accumulate(MyData(1)).await;
+            accumulator.try_accumulate(MyData(1)).await.unwrap();
         }
 
         assert_eq!(accumulator.get().await, MyData(count as u8));
     }
 
+    #[tokio::test]
+    async fn test_accumulator_count() {
+        let accumulator = Accumulator::new(MyData::empty(()));
+        assert_eq!(accumulator.count().await, 0);
+
+        for expected in 1..=3 {
+            let count = accumulator.try_accumulate(MyData(1)).await;
+            assert_eq!(count, Ok(expected));
+            assert_eq!(accumulator.count().await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_accumulator_try_accumulate() {
+        let accumulator = Accumulator::new(vec![Bytes::empty(2); 2]);
+
+        let good = vec![Bytes::from(vec![1, 2]), Bytes::from(vec![3, 4])];
+        assert_eq!(accumulator.try_accumulate(good.clone()).await, Ok(1));
+
+        let bad = vec![Bytes::from(vec![1, 2]), Bytes::from(vec![3, 4, 5])];
+        assert!(accumulator.try_accumulate(bad).await.is_err());
+        let bad = vec![Bytes::from(vec![1, 2])];
+        assert!(accumulator.try_accumulate(bad).await.is_err());
+
+        assert_eq!(accumulator.count().await, 1);
+        assert_eq!(accumulator.get().await, good);
+    }
+
+    #[tokio::test]
+    async fn test_accumulator_accumulate_on_then_try_accumulate() {
+        let accumulator = Accumulator::new(MyData::empty(()));
+
+        let count = accumulator
+            .accumulate_on(spawn_blocking, |data| data.0 += 2)
+            .await;
+        assert_eq!(count.unwrap(), 1);
+        let count = accumulator.try_accumulate(MyData(1)).await;
+        assert_eq!(count, Ok(2));
+
+        assert_eq!(accumulator.get().await, MyData(3));
+    }
+
+    #[tokio::test]
+    async fn test_accumulator_accumulate_on() {
+        let accumulator = Accumulator::new(MyData::empty(()));
+        let runs = AtomicUsize::new(0);
+
+        let run = |job| {
+            runs.fetch_add(1, Ordering::SeqCst);
+            spawn_blocking(job)
+        };
+        let count = accumulator.accumulate_on(run, |data| data.0 += 2).await;
+        assert_eq!(count.unwrap(), 1);
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(accumulator.get().await, MyData(2));
+    }
+
+    #[tokio::test]
+    async fn test_accumulator_accumulate_on_concurrent() {
+        use crate::rt::timeout;
+        use std::sync::Barrier;
+        use std::time::Duration;
+
+        // Both updates have to be running at once to get past the barrier.
+        let accumulator = Accumulator::with_partials(MyData::empty(()), 2);
+        let barrier = Arc::new(Barrier::new(2));
+        let updates = (1..=2).map(|x| {
+            let barrier = barrier.clone();
+            accumulator.accumulate_on(spawn_blocking, move |data| {
+                barrier.wait();
+                data.0 += x;
+            })
+        });
+        let counts = timeout(Duration::from_secs(10), future::try_join_all(updates))
+            .await
+            .expect("updates should run concurrently")
+            .unwrap();
+        assert_eq!(counts.len(), 2);
+
+        assert_eq!(accumulator.count().await, 2);
+        assert_eq!(accumulator.get().await, MyData(3));
+        accumulator.try_accumulate(MyData(1)).await.unwrap();
+        assert_eq!(accumulator.reset().await, MyData(4));
+        assert_eq!(accumulator.get().await, MyData(0));
+    }
+
+    #[tokio::test]
+    async fn test_accumulator_accumulate_on_panic() {
+        let accumulator = Accumulator::new(MyData::empty(()));
+
+        let result = accumulator
+            .accumulate_on(spawn_blocking, |_| panic!("bad data"))
+            .await;
+        assert!(result.is_err());
+
+        assert_eq!(accumulator.count().await, 0);
+        assert_eq!(accumulator.get().await, MyData(0));
+    }
+
+    #[tokio::test]
+    async fn test_accumulator_reset() {
+        let accumulator = Accumulator::new(MyData::empty(()));
+        accumulator.try_accumulate(MyData(1)).await.unwrap();
+        accumulator.try_accumulate(MyData(2)).await.unwrap();
+
+        assert_eq!(accumulator.reset().await, MyData(3));
+
+        assert_eq!(accumulator.count().await, 0);
+        assert_eq!(accumulator.get().await, MyData(0));
+    }
+
+    #[tokio::test]
+    async fn test_striped_accumulator() {
+        let data: Vec<MyData> = (0..5).map(MyData).collect();
+        // Uneven stripes, and more stripes than elements.
+        for stripes in &[1, 2, 3, 5, 8] {
+            let accumulator = StripedAccumulator::new(vec![MyData(0); 5], *stripes);
+            assert_eq!(accumulator.try_accumulate(data.clone()).await, Ok(1));
+            assert_eq!(accumulator.try_accumulate(data.clone()).await, Ok(2));
+            let expected: Vec<MyData> = (0..5).map(|x| MyData(2 * x)).collect();
+            assert_eq!(accumulator.get().await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_striped_accumulator_concurrent() {
+        let accumulator = StripedAccumulator::new(vec![MyData(0); 7], 3);
+        let arrivals = (0..10).map(|_| accumulator.try_accumulate(vec![MyData(1); 7]));
+        let mut counts = future::try_join_all(arrivals).await.unwrap();
+        counts.sort_unstable();
+        assert_eq!(counts, (1..=10).collect::<Vec<_>>());
+        assert_eq!(accumulator.get().await, vec![MyData(10); 7]);
+    }
+
+    #[tokio::test]
+    async fn test_striped_accumulator_mismatch() {
+        let accumulator = StripedAccumulator::new(vec![Bytes::empty(2); 3], 2);
+        let good = vec![Bytes::from(vec![1, 2]); 3];
+        assert_eq!(accumulator.try_accumulate(good.clone()).await, Ok(1));
+
+        // Bad element in the last stripe: the first one must be untouched.
+        let bad = vec![
+            Bytes::from(vec![1, 2]),
+            Bytes::from(vec![1, 2]),
+            Bytes::from(vec![1, 2, 3]),
+        ];
+        assert!(accumulator.try_accumulate(bad).await.is_err());
+        let bad = vec![Bytes::from(vec![1, 2]); 2];
+        assert!(accumulator.try_accumulate(bad).await.is_err());
+
+        assert_eq!(accumulator.get().await, good);
+        assert_eq!(accumulator.try_accumulate(good.clone()).await, Ok(2));
+    }
+
+    #[tokio::test]
+    async fn test_striped_accumulator_reset() {
+        let accumulator = StripedAccumulator::new(vec![MyData(0); 3], 2);
+        accumulator
+            .try_accumulate(vec![MyData(1); 3])
+            .await
+            .unwrap();
+
+        assert_eq!(accumulator.reset().await, vec![MyData(1); 3]);
+
+        assert_eq!(accumulator.get().await, vec![MyData(0); 3]);
+        let count = accumulator.try_accumulate(vec![MyData(1); 3]).await;
+        assert_eq!(count, Ok(1));
+    }
+
     #[tokio::test]
     async fn test_accumulator_vec() {
         let data: Vec<MyData> = vec![MyData(0); 3];
         let accumulator = Accumulator::new(data);
 
         let data = vec![MyData(0), MyData(1), MyData(2)];
-        accumulator.accumulate(data.clone()).await;
-        accumulator.accumulate(data).await;
+        accumulator.try_accumulate(data.clone()).await.unwrap();
+        accumulator.try_accumulate(data).await.unwrap();
 
         assert_eq!(
             accumulator.get().await,
@@ -0,0 +1,52 @@
+use clap::{crate_authors, crate_version, Parser, Subcommand};
+use spectrum::cli;
+use spectrum::logs::LogFilter;
+use spectrum::services::admin::{self, GetLogFilterRequest, SetLogFilterRequest};
+use tonic::transport::Certificate;
+
+/// Spectrum -- control a running worker, leader, or publisher.
+///
+/// For instance, to turn up logging for one worker module without a restart:
+///
+///     admin --addr https://worker:6000 set-log-filter \
+///         debug,spectrum::worker::audit_registry=trace
+#[derive(Parser)]
+#[clap(version = crate_version!(), author = crate_authors!())]
+struct Args {
+    /// Address of the service (as it registered, e.g. `http://127.0.0.1:6000`).
+    #[clap(long, env = "SPECTRUM_ADMIN_ADDR")]
+    addr: String,
+    #[clap(flatten)]
+    tls: cli::TlsCaArgs,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the service's log filter.
+    GetLogFilter,
+    /// Replace the service's log filter.
+    SetLogFilter {
+        /// A level, then any per-module levels (e.g. `info,spectrum::worker=trace`).
+        filter: LogFilter,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let args = Args::parse();
+    let tls: Option<Certificate> = args.tls.into();
+    let mut client = admin::connect(&args.addr, tls).await?;
+    let response = match args.command {
+        Command::GetLogFilter => client.get_log_filter(GetLogFilterRequest {}).await?,
+        Command::SetLogFilter { filter } => {
+            let filter = filter.to_string();
+            client
+                .set_log_filter(SetLogFilterRequest { filter })
+                .await?
+        }
+    };
+    println!("{}", response.into_inner().filter);
+    Ok(())
+}
@@ -1,14 +1,17 @@
 use clap::{crate_authors, crate_version, ArgGroup, Parser};
 use futures::prelude::*;
 use rand::{thread_rng, Rng};
+use spectrum::rt::ctrl_c;
 use spectrum::{
-    cli, client, config, experiment, protocols::wrapper::ChannelKeyWrapper, services::ClientInfo,
+    cli,
+    client::{self, bench},
+    config, experiment, metadata,
+    protocols::wrapper::ChannelKeyWrapper,
+    services::{checksum, ClientInfo},
 };
 use spectrum_primitives::Bytes;
 use std::convert::TryFrom;
-use std::fs::File;
-use std::io::Read;
-use tokio::signal::ctrl_c;
+use std::fs::{self, File};
 
 /// Run a Spectrum broadcasting client.
 ///
@@ -25,14 +28,22 @@ struct Args {
     #[clap(flatten)]
     logs: cli::LogArgs,
     #[clap(flatten)]
+    discovery: cli::DiscoveryArgs,
+    #[clap(flatten)]
     client: BroadcasterArgs,
+    #[clap(flatten)]
+    channel_pool: cli::ChannelPoolArgs,
+    #[clap(flatten)]
+    bench: BenchArgs,
     /// Max jitter. Useful for big big messages (make big).
     #[clap(long, env = "SPECTRUM_MAX_JITTER_MILLIS", default_value = "100")]
     max_jitter: u64,
 }
 
+// The message is required unless benchmarking (checked when converting to a
+// `ClientInfo`).
 #[derive(Parser)]
-#[clap(group = ArgGroup::new("message").required(true))]
+#[clap(group = ArgGroup::new("message"))]
 struct BroadcasterArgs {
     /// The message to broadcast
     #[clap(long = "message", group = "message")]
@@ -43,8 +54,8 @@ struct BroadcasterArgs {
     msg_file: Option<String>,
 
     /// File containing the broadcast key, serialized to JSON.
-    #[clap(long, required = true)]
-    key_file: String,
+    #[clap(long, required_unless_present = "bench_keygen")]
+    key_file: Option<String>,
 
     /// Max jitter. Useful for big big messages (make big).
     #[clap(long, env = "SPECTRUM_MAX_JITTER_MILLIS", default_value = "100")]
@@ -58,44 +69,113 @@ impl TryFrom<BroadcasterArgs> for ClientInfo {
         let msg = if let Some(msg) = args.msg {
             Bytes::from(Vec::from(msg.as_bytes()))
         } else if let Some(msg_file) = args.msg_file {
-            let msg_file_reader = File::open(&msg_file).map_err(|e| e.to_string())?;
-            Bytes::from(
-                msg_file_reader
-                    .bytes()
-                    .collect::<Result<Vec<_>, _>>()
-                    .map_err(|e| e.to_string())?,
-            )
+            Bytes::from(fs::read(&msg_file).map_err(|e| e.to_string())?)
         } else {
             return Err(
                 "Invalid BroadcasterArgs: at least one of `msg`, `msg_file` must be `Some()`."
                     .to_string(),
             );
         };
-        let key_file = args.key_file;
+        let key_file = args.key_file.ok_or("Missing `key_file`.")?;
         let key_file_reader = File::open(&key_file).map_err(|e| e.to_string())?;
         let key: ChannelKeyWrapper = serde_json::from_reader(key_file_reader)
-            .map_err(|e| format!("Could not read key file [{}]: {}", key_file, e.to_string()))?;
+            .map_err(|e| format!("Could not read key file [{}]: {}", key_file, e))?;
         // -1 because the CLI needs non-zero or it thinks we didn't supply it
         // from environment variable
         Ok(Self::new_broadcaster(thread_rng().gen(), msg, key))
     }
 }
 
+#[derive(Parser)]
+struct BenchArgs {
+    /// Instead of broadcasting, time the client-side work of a broadcast
+    /// (DPF keys, proof shares, serialization) for every combination of
+    /// `--bench-message-sizes` and `--bench-channels`, and print the results
+    /// as JSON.
+    ///
+    /// Doesn't contact any server.
+    #[clap(long)]
+    bench_keygen: bool,
+
+    /// Protocol to benchmark: `two-key`, `two-key-pub`, or `multi-key`.
+    #[clap(long, default_value = "two-key")]
+    bench_protocol: bench::BenchProtocol,
+
+    /// Number of groups to benchmark (multi-key only; the others have 2).
+    #[clap(long, default_value = "2")]
+    bench_groups: usize,
+
+    /// Message sizes (in bytes) to benchmark, comma-separated.
+    #[clap(long, use_delimiter = true, default_value = "1024,10240,102400")]
+    bench_message_sizes: Vec<usize>,
+
+    /// Channel counts to benchmark, comma-separated.
+    #[clap(long, use_delimiter = true, default_value = "1,10,100")]
+    bench_channels: Vec<usize>,
+
+    /// Broadcasts to average over, for each combination.
+    #[clap(long, default_value = "10")]
+    bench_iterations: usize,
+}
+
+impl BenchArgs {
+    fn run(&self) -> Vec<bench::KeygenCost> {
+        let mut costs = vec![];
+        for &msg_size in &self.bench_message_sizes {
+            for &channels in &self.bench_channels {
+                costs.push(bench::bench_keygen(
+                    self.bench_protocol,
+                    self.bench_groups,
+                    channels,
+                    msg_size,
+                    self.bench_iterations,
+                ));
+            }
+        }
+        costs
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
     let args = Args::parse();
     args.logs.init();
 
-    let config = config::from_env().await?;
+    if args.bench.bench_keygen {
+        serde_json::to_writer_pretty(std::io::stdout(), &args.bench.run())?;
+        println!();
+        return Ok(());
+    }
+
+    let config = args.discovery.wrap(config::from_env().await?)?;
     let experiment = experiment::read_from_store(&config).await?;
-    let info = ClientInfo::try_from(args.client)?;
+    metadata::RunMetadata::new(&experiment).log();
+    let mut info = ClientInfo::try_from(args.client)?;
+    if experiment.channel_checksums() {
+        info.broadcast = info
+            .broadcast
+            .map(|(msg, key)| (checksum::seal(msg.as_ref()), key));
+    }
+    let capacity = experiment.get_protocol().payload_capacity();
+    if let Some((msg, _)) = &info.broadcast {
+        if msg.len() > capacity {
+            return Err(format!(
+                "Message is {} bytes, but channels only hold {}.",
+                msg.len(),
+                capacity
+            )
+            .into());
+        }
+    }
     client::viewer::run(
         config,
         experiment.get_protocol().clone(),
         info,
-        experiment.hammer,
+        experiment.hammer_client(),
         None,
         args.max_jitter,
+        None,
+        args.channel_pool.into(),
         ctrl_c().map(|_| ()),
     )
     .await
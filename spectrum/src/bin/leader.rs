@@ -1,10 +1,13 @@
 use clap::{crate_authors, crate_version, Parser};
 use futures::prelude::*;
+use spectrum::rt::ctrl_c;
 use spectrum::{
-    cli, config, experiment, leader,
+    cli, config, experiment,
+    leader::{self, SpillConfig},
+    metadata,
     services::{Group, LeaderInfo},
 };
-use tokio::signal::ctrl_c;
+use std::path::PathBuf;
 
 /// Run a Spectrum leader (one per trust group).
 ///
@@ -21,6 +24,8 @@ struct Args {
     #[clap(flatten)]
     logs: cli::LogArgs,
     #[clap(flatten)]
+    discovery: cli::DiscoveryArgs,
+    #[clap(flatten)]
     leader: LeaderArgs,
     #[clap(flatten)]
     net: cli::NetArgs,
@@ -31,6 +36,25 @@ struct LeaderArgs {
     /// The index of the group of this leader.
     #[clap(long, env = "SPECTRUM_LEADER_GROUP")]
     group: u16,
+
+    /// Write worker shares to disk once more than this many megabytes of
+    /// them are waiting to be combined (default: never).
+    #[clap(long, env = "SPECTRUM_LEADER_SPILL_ABOVE_MB")]
+    spill_above_mb: Option<usize>,
+
+    /// Directory for shares written to disk (default: the system temp
+    /// directory).
+    #[clap(long, env = "SPECTRUM_LEADER_SPILL_DIR")]
+    spill_dir: Option<PathBuf>,
+}
+
+impl LeaderArgs {
+    fn spill(&self) -> SpillConfig {
+        SpillConfig {
+            watermark: self.spill_above_mb.map(|mb| mb * 1024 * 1024),
+            dir: self.spill_dir.clone(),
+        }
+    }
 }
 
 impl From<LeaderArgs> for LeaderInfo {
@@ -46,9 +70,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
     let args = Args::parse();
     args.logs.init();
 
-    let config = config::from_env().await?;
+    let config = args.discovery.wrap(config::from_env().await?)?;
     let experiment = experiment::read_from_store(&config).await?;
+    metadata::RunMetadata::new(&experiment).log();
     let protocol = experiment.get_protocol().clone();
+    let spill = args.leader.spill();
     let info = LeaderInfo::from(args.leader);
     leader::run(
         config,
@@ -56,6 +82,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
         protocol,
         info,
         args.net.into(),
+        spill,
         ctrl_c().map(|_| ()),
     )
     .await
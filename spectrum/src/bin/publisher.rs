@@ -1,12 +1,29 @@
 use clap::{crate_authors, crate_version, Parser};
 use futures::prelude::*;
-use spectrum::{cli, config, experiment, publisher, services::PublisherInfo};
+use spectrum::rt::{
+    ctrl_c,
+    sync::{Mutex, Notify},
+};
+use spectrum::{
+    cli, config, experiment, publisher,
+    services::{
+        abort::AbortNotice,
+        decoding::DecodedChannel,
+        election::ElectionPolicy,
+        manifest::{ManifestSigner, SignedManifest},
+        quorum::QuorumPolicy,
+        registration::RegistrationSchedule,
+        PublisherInfo,
+    },
+};
+use spectrum_primitives::Bytes;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::signal::ctrl_c;
-use tokio::sync::{Mutex, Notify};
+use std::time::{Duration, Instant};
 
-/// Run a Spectrum publisher (one per deployment).
+/// Run a Spectrum publisher (one per deployment, or a few replicas).
 ///
 /// The publisher is responsible for aggregating shares *between* trust groups;
 /// it receives shares from the leader of each group.
@@ -20,25 +37,94 @@ struct Args {
     #[clap(flatten)]
     logs: cli::LogArgs,
     #[clap(flatten)]
+    discovery: cli::DiscoveryArgs,
+    #[clap(flatten)]
     net: cli::NetArgs,
+    #[clap(flatten)]
+    profile: cli::ProfileArgs,
     /// How long to delay between quorum and clients start.
     ///
     /// Might need to increase this if lots of clients on the same machine.
     #[clap(long, env = "SPECTRUM_DELAY_MS", default_value = "5000")]
     delay_ms: i64,
+    /// The index of this publisher (if running replicated publishers).
+    ///
+    /// Only the first publisher sets the experiment start time (unless
+    /// `--elect`).
+    #[clap(long = "index", env = "SPECTRUM_PUBLISHER_INDEX", default_value = "1")]
+    idx: u16,
+    /// Elect the publisher that sets the start time through the config store,
+    /// rather than always using the first.
+    ///
+    /// The winner holds a lease that it keeps renewing; if it goes down,
+    /// another publisher takes over once the lease runs out.
+    #[clap(long, env = "SPECTRUM_PUBLISHER_ELECT")]
+    elect: bool,
+    /// How long an elected publisher's lease lasts without renewal.
+    #[clap(long, env = "SPECTRUM_PUBLISHER_LEASE_MS", default_value = "10000")]
+    lease_ms: u64,
+    /// Start the round once each group has this many workers registered.
+    ///
+    /// By default, waits for every worker in the experiment.
+    #[clap(long, env = "SPECTRUM_MIN_WORKERS_PER_GROUP")]
+    min_workers_per_group: Option<u16>,
+    /// How long to wait for every worker before settling for
+    /// `--min-workers-per-group`.
+    #[clap(long, env = "SPECTRUM_QUORUM_GRACE_MS", default_value = "30000")]
+    quorum_grace_ms: u64,
+    /// Open client registration this long after quorum.
+    ///
+    /// By default, registration is open as soon as workers come up.
+    #[clap(long, env = "SPECTRUM_REGISTRATION_OPENS_MS")]
+    registration_opens_ms: Option<u64>,
+    /// Close client registration this long after quorum.
+    ///
+    /// By default, registration closes at the experiment start time.
+    #[clap(long, env = "SPECTRUM_REGISTRATION_CLOSES_MS")]
+    registration_closes_ms: Option<u64>,
+    /// File with the hex-encoded ed25519 key for signing round manifests.
+    ///
+    /// By default, uses a fresh key (logged on startup).
+    #[clap(long, env = "SPECTRUM_MANIFEST_KEY_FILE")]
+    manifest_key_file: Option<PathBuf>,
+    /// Write the signed manifest of each round (as JSON lines) here.
+    #[clap(long, env = "SPECTRUM_MANIFEST_OUT")]
+    manifest_out: Option<PathBuf>,
+    /// Write each round's decoded channels (as JSON lines) here.
+    ///
+    /// Channels are decoded as their metadata says (see `spectrum-setup
+    /// set-decoders`), or else written as raw hex.
+    #[clap(long, env = "SPECTRUM_DECODED_OUT")]
+    decoded_out: Option<PathBuf>,
+}
+
+// Append `line` to the file at `path` (creating it if needed).
+fn append_line(path: &Path, line: &str) {
+    let written = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(err) = written {
+        eprintln!("Failed to write to {}: {}", path.display(), err);
+    }
 }
 
 #[derive(Debug, Clone)]
 struct CliRemote {
     start: Arc<Mutex<Option<Instant>>>,
     done: Arc<Notify>,
+    manifest_out: Option<PathBuf>,
+    decoded_out: Option<PathBuf>,
 }
 
 impl CliRemote {
-    fn new(done: Arc<Notify>) -> Self {
+    fn new(done: Arc<Notify>, manifest_out: Option<PathBuf>, decoded_out: Option<PathBuf>) -> Self {
         CliRemote {
             start: Default::default(),
             done,
+            manifest_out,
+            decoded_out,
         }
     }
 }
@@ -50,12 +136,42 @@ impl publisher::Remote for CliRemote {
         start.replace(Instant::now());
     }
 
-    async fn done(&self) {
+    async fn done(&self, _recovered: Vec<Bytes>) {
         let start = self.start.lock().await;
         let elapsed = start.expect("Can't call done() before start()!").elapsed();
         eprintln!("Elapsed time: {}ms", elapsed.as_millis());
         self.done.notify_one();
     }
+
+    async fn decoded(&self, channels: &[DecodedChannel]) {
+        if let Some(path) = &self.decoded_out {
+            for channel in channels {
+                let json = serde_json::to_string(channel).expect("Channels should serialize.");
+                append_line(path, &json);
+            }
+        }
+    }
+
+    async fn manifest(&self, manifest: &SignedManifest) {
+        if let Some(path) = &self.manifest_out {
+            append_line(path, &manifest.to_json());
+        }
+    }
+
+    async fn stats(&self, table: String) {
+        eprintln!("Worker stats:\n{}", table);
+    }
+
+    async fn hammer_done(&self, result: &publisher::HammerResult) {
+        let json = serde_json::to_string(result).expect("Hammer result should serialize.");
+        eprintln!("Hammer result: {}", json);
+        self.done.notify_one();
+    }
+
+    async fn aborted(&self, notice: &AbortNotice) {
+        eprintln!("Round {} aborted: {}", notice.round, notice.reason);
+        self.done.notify_one();
+    }
 }
 
 #[tokio::main]
@@ -63,12 +179,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
     let args = Args::parse();
     args.logs.init();
 
-    let config = config::from_env().await?;
+    let config = args.discovery.wrap(config::from_env().await?)?;
     let experiment = experiment::read_from_store(&config).await?;
-    let info = PublisherInfo::new();
+    // -1 because the CLI needs non-zero or it thinks we didn't supply it
+    // from environment variable
+    let info = PublisherInfo::new(args.idx - 1);
+
+    let signer = match &args.manifest_key_file {
+        Some(path) => ManifestSigner::from_hex(&fs::read_to_string(path)?)?,
+        None => ManifestSigner::generate(),
+    };
+
+    let profile = args.profile.start()?;
 
     let done = Arc::new(Notify::new());
-    let remote = CliRemote::new(done.clone());
+    let remote = CliRemote::new(
+        done.clone(),
+        args.manifest_out.clone(),
+        args.decoded_out.clone(),
+    );
     let shutdown = async move {
         futures::select! {
             _ = ctrl_c().fuse() => {},
@@ -76,6 +205,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
         }
     };
 
+    let lease = Duration::from_millis(args.lease_ms);
+    let election = args.elect.then_some(ElectionPolicy { lease });
     publisher::run(
         config,
         experiment.get_protocol().clone(),
@@ -84,6 +215,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
         remote,
         shutdown,
         args.delay_ms,
+        QuorumPolicy {
+            min_workers_per_group: args.min_workers_per_group,
+            grace: Duration::from_millis(args.quorum_grace_ms),
+        },
+        RegistrationSchedule {
+            opens_after: args.registration_opens_ms.map(Duration::from_millis),
+            closes_after: args.registration_closes_ms.map(Duration::from_millis),
+        },
+        signer,
+        election,
     )
-    .await
+    .await?;
+    if let Some(profile) = profile {
+        profile.finish()?;
+    }
+    Ok(())
 }
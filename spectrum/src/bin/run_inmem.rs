@@ -0,0 +1,118 @@
+use spectrum::cli;
+use spectrum::config;
+use spectrum::experiment::{Experiment, TopologyBounds};
+use spectrum::net::{self, PartitionSchedule, PortLayout, SimulatedLatency};
+use spectrum::run_in_process;
+use spectrum::services::failures::ErrorBudget;
+use spectrum::DEFAULT_TIMEOUT;
+
+use clap::{crate_authors, crate_version, Parser};
+use log::{info, warn};
+use std::time::Duration;
+
+/// Spectrum -- run an experiment entirely in one process.
+///
+/// Uses an in-memory config store and checks that every broadcaster's message
+/// is recovered by the publisher.
+#[derive(Parser)]
+#[clap(version = crate_version!(), author = crate_authors!())]
+struct Args {
+    #[clap(flatten)]
+    experiment: cli::ExperimentArgs,
+    #[clap(flatten)]
+    logs: cli::LogArgs,
+
+    /// Instead of the experiment given above, run this many experiments with
+    /// random topologies (see `--seed`).
+    #[clap(long)]
+    fuzz_topology: Option<u64>,
+
+    /// Seed for the first random topology; topology `i` uses `seed + i`.
+    #[clap(long, default_value = "0")]
+    seed: u64,
+
+    /// Add synthetic latency to each request, by the kind of service that
+    /// receives it.
+    ///
+    /// For example, `leader=40ms,worker=10ms~2ms` delays requests to leaders
+    /// by 40ms and to workers by 10ms (give or take 2ms), round trip.
+    #[clap(long)]
+    simulate_latency: Option<SimulatedLatency>,
+
+    /// Cut off requests between sets of services for a while, measured from
+    /// the start of each experiment.
+    ///
+    /// For example, `group2/leader1@2s..4s` fails every request between group
+    /// 2 (its leader and workers) and the leader of group 1, either way, from
+    /// 2s to 4s in. Sides are `publishers`, `clients`, `leader<g>`,
+    /// `group<g>`, or `worker<g>.<i>`; separate several cuts with commas.
+    #[clap(long, default_value = "")]
+    simulate_partitions: PartitionSchedule,
+
+    /// Let up to this many clients fail without failing the experiment.
+    ///
+    /// Publishers, leaders, and workers can't fail.
+    #[clap(long, default_value = "0")]
+    tolerate_client_failures: usize,
+
+    /// How to pick ports for the services.
+    ///
+    /// `reserved` holds a free port for each service until it starts;
+    /// `free` picks free ports without holding them; a number `N` gives
+    /// service `i` port `N + i`, for predictable addresses when debugging.
+    #[clap(long, default_value = "reserved")]
+    ports: PortLayout,
+
+    /// Fail each experiment if it isn't done after this long (default: 10s).
+    #[clap(long)]
+    timeout_ms: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let args = Args::parse();
+    args.logs.init();
+    if let Some(latency) = args.simulate_latency {
+        info!("Simulating latency: {:?}", latency);
+        net::simulate_latency(latency);
+    }
+
+    let experiments: Vec<(Option<u64>, Experiment)> = match args.fuzz_topology {
+        Some(count) => {
+            let bounds = TopologyBounds::default();
+            (args.seed..args.seed + count)
+                .map(|seed| (Some(seed), Experiment::random(seed, &bounds)))
+                .collect()
+        }
+        None => vec![(None, args.experiment.into())],
+    };
+
+    for (seed, experiment) in experiments {
+        info!(
+            "Running experiment (seed {:?}): {} groups of {}, {} channels, {} clients, {}-byte messages",
+            seed,
+            experiment.groups(),
+            experiment.group_size(),
+            experiment.channels(),
+            experiment.clients(),
+            experiment.msg_size()
+        );
+        let config = config::from_string("mem://").await?;
+        let partitions = args.simulate_partitions.clone();
+        let budget = ErrorBudget::new(args.tolerate_client_failures);
+        let timeout = args
+            .timeout_ms
+            .map_or(DEFAULT_TIMEOUT, Duration::from_millis);
+        let summary = run_in_process(
+            experiment, config, None, partitions, budget, args.ports, timeout,
+        )
+        .await
+        .map_err(|err| format!("Experiment failed (seed {:?}): {}", seed, err))?;
+        for failure in &summary.failures {
+            warn!("Tolerated failure: {}", failure);
+        }
+        eprintln!("Elapsed time: {}ms", summary.elapsed.as_millis());
+    }
+
+    Ok(())
+}
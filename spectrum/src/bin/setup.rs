@@ -1,15 +1,19 @@
 use spectrum::cli;
 use spectrum::config;
 use spectrum::experiment::{write_to_store, Experiment};
+use spectrum::keys;
+use spectrum::services::{decoding, revocation};
 
-use clap::{crate_authors, crate_version, Parser};
-
-// use std::fs::File;
+use clap::{crate_authors, crate_version, Parser, Subcommand};
+use log::info;
+use std::path::PathBuf;
 
 /// Spectrum -- set up an experiment.
 ///
-/// Writes the experiment details to etcd and dumps key files to disk
-/// (`key-{0..n}.json`, `keys.json`).
+/// Writes the experiment details to etcd. Use the `export-keys` subcommand
+/// afterwards to dump the channel keys to disk for broadcasters,
+/// `revoke-channel` if one of them leaks, and `set-decoders` to say how the
+/// publisher should decode a channel.
 #[derive(Parser)]
 #[clap(version = crate_version!(), author = crate_authors!())]
 struct Args {
@@ -17,6 +21,51 @@ struct Args {
     experiment: cli::ExperimentArgs,
     #[clap(flatten)]
     logs: cli::LogArgs,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write the stored experiment's channel keys to a directory.
+    ///
+    /// Each channel gets `key-<idx>.json` (for a broadcaster's `--key-file`),
+    /// and `keys.json` has all of them.
+    ExportKeys {
+        /// Directory to write the key files to (created if needed).
+        #[clap(long)]
+        out: PathBuf,
+    },
+    /// Replace the stored experiment's channel keys with ones from a directory.
+    ///
+    /// Reads the `keys.json` written by `export-keys`.
+    ImportKeys {
+        /// Directory to read the key files from.
+        #[clap(long = "in")]
+        dir: PathBuf,
+    },
+    /// Revoke a channel's key (say, because it leaked).
+    ///
+    /// Running workers pick this up within a second or so, and fail the
+    /// audits of any writes under the old key from then on. Nobody gets a key
+    /// for the channel again until new keys are imported.
+    RevokeChannel {
+        /// Index of the channel.
+        channel: usize,
+        /// Why (logged by the workers).
+        #[clap(long, default_value = "revoked by operator")]
+        reason: String,
+    },
+    /// Set the decoders the publisher runs on a channel's recovered contents.
+    ///
+    /// Built in: `length-prefixed`, `utf8`, and `protobuf-any` (e.g.,
+    /// `set-decoders 3 length-prefixed utf8`). No decoders means raw bytes.
+    SetDecoders {
+        /// Index of the channel.
+        channel: usize,
+        /// Decoder names, in the order to run them.
+        decoders: Vec<String>,
+    },
 }
 
 #[tokio::main]
@@ -24,19 +73,34 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
     let args = Args::parse();
     args.logs.init();
 
-    let experiment = Experiment::from(args.experiment);
     let config = config::from_env().await?;
-    write_to_store(&config, &experiment).await?;
-
-    // let keys = experiment.get_keys();
-    // for (idx, key) in keys.iter().enumerate() {
-    //     let file = File::create(&format!("key-{}.json", idx))?;
-    //     serde_json::to_writer(file, key)?;
-    // }
-    // {
-    //     let file = File::create("keys.json")?;
-    //     serde_json::to_writer(file, &keys)?;
-    // }
+    match args.command {
+        None => {
+            let experiment = Experiment::from(args.experiment);
+            write_to_store(&config, &experiment).await?;
+            info!("Experiment ID: {}", experiment.id());
+        }
+        Some(Command::ExportKeys { out }) => {
+            let count = keys::export_keys(&config, &out).await?;
+            info!("Exported {} channel keys to {}.", count, out.display());
+        }
+        Some(Command::ImportKeys { dir }) => {
+            let count = keys::import_keys(&config, &dir).await?;
+            info!("Imported {} channel keys from {}.", count, dir.display());
+        }
+        Some(Command::RevokeChannel { channel, reason }) => {
+            revocation::revoke_channel(&config, channel, &reason).await?;
+            info!("Revoked the key for channel {}.", channel);
+        }
+        Some(Command::SetDecoders { channel, decoders }) => {
+            let metadata = decoding::ChannelMetadata { decoders };
+            decoding::set_channel_metadata(&config, channel, &metadata).await?;
+            info!(
+                "Set decoders for channel {}: {:?}",
+                channel, metadata.decoders
+            );
+        }
+    }
 
     Ok(())
 }
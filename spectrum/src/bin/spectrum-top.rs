@@ -0,0 +1,51 @@
+use clap::{crate_authors, crate_version, Parser};
+use spectrum::{cli, config, rt, top};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use tonic::transport::Certificate;
+
+/// Spectrum -- live dashboard for a running experiment.
+///
+/// Shows the round phase, each node's health (and which publisher is
+/// elected), and per-worker throughput from the publisher's stats (workers
+/// report these in hammer mode), refreshing in place. Plain ANSI, so it works
+/// fine over SSH.
+///
+/// Use `$SPECTRUM_CONFIG_SERVER=etcd://127.0.0.1:8000` to point to the
+/// experiment's etcd instance.
+#[derive(Parser)]
+#[clap(version = crate_version!(), author = crate_authors!())]
+struct Args {
+    #[clap(flatten)]
+    discovery: cli::DiscoveryArgs,
+    #[clap(flatten)]
+    tls: cli::TlsCaArgs,
+    /// How often to refresh (nodes get this long to answer, too).
+    #[clap(long, env = "SPECTRUM_TOP_INTERVAL_MILLIS", default_value = "1000")]
+    interval_ms: u64,
+    /// Print one snapshot and exit (e.g. for scripts), rather than refreshing.
+    #[clap(long)]
+    once: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let args = Args::parse();
+    let config = args.discovery.wrap(config::from_env().await?)?;
+    let tls: Option<Certificate> = args.tls.into();
+    let interval = Duration::from_millis(args.interval_ms);
+    let mut dashboard = top::Dashboard::default();
+    loop {
+        let started = Instant::now();
+        let snapshot = top::snapshot(&config, tls.clone(), interval).await;
+        let frame = dashboard.render(&snapshot);
+        if args.once {
+            print!("{}", frame);
+            return Ok(());
+        }
+        // Clear the screen and move the cursor home, then draw.
+        print!("\x1b[2J\x1b[H{}", frame);
+        io::stdout().flush()?;
+        rt::sleep_until(started + interval).await;
+    }
+}
@@ -1,10 +1,11 @@
 use rand::{thread_rng, Rng};
 use std::iter::repeat_with;
+use std::sync::Arc;
 use tonic::transport::Certificate;
 
 use clap::{crate_authors, crate_version, Parser};
 use futures::stream::{FuturesUnordered, StreamExt};
-use spectrum::{cli, client, config, experiment, services::ClientInfo};
+use spectrum::{cli, client, config, experiment, metadata, rt, services::ClientInfo};
 
 /// Run a Spectrum viewing client.
 ///
@@ -22,6 +23,8 @@ use spectrum::{cli, client, config, experiment, services::ClientInfo};
 struct Args {
     #[clap(flatten)]
     logs: cli::LogArgs,
+    #[clap(flatten)]
+    discovery: cli::DiscoveryArgs,
     /// Run this many threads in parallel.
     #[clap(long, env = "SPECTRUM_VIEWER_THREADS", default_value = "1")]
     threads: u16,
@@ -30,53 +33,72 @@ struct Args {
     /// Max jitter. Useful for big big messages (make big).
     #[clap(long, env = "SPECTRUM_MAX_JITTER_MILLIS", default_value = "100")]
     max_jitter: u64,
+    /// Pre-generate this many sets of cover traffic and cycle through them,
+    /// rather than generating fresh tokens per client (0 to disable).
+    ///
+    /// Cuts client CPU for load tests; don't use it in a real deployment.
+    #[clap(long, env = "SPECTRUM_VIEWER_COVER_POOL", default_value = "0")]
+    cover_pool: usize,
+    #[clap(flatten)]
+    channel_pool: cli::ChannelPoolArgs,
 }
 
 fn main() {
     let args = Args::parse();
     args.logs.init();
 
-    tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .unwrap()
-        .block_on(async {
-            let config = config::from_env().await?;
-            let experiment = experiment::read_from_store(&config).await?;
-            let hammer = experiment.hammer;
-            let tls: Option<Certificate> = args.tls.into();
-            let max_jitter = args.max_jitter;
+    rt::block_on(async {
+        let config = args.discovery.wrap(config::from_env().await?)?;
+        let experiment = experiment::read_from_store(&config).await?;
+        metadata::RunMetadata::new(&experiment).log();
+        let hammer = experiment.hammer_client();
+        let tls: Option<Certificate> = args.tls.into();
+        let max_jitter = args.max_jitter;
+        // Before the round starts, so it doesn't count against us.
+        let cover_pool = match args.cover_pool {
+            0 => None,
+            size => Some(Arc::new(client::CoverPool::new(
+                experiment.get_protocol(),
+                size,
+            ))),
+        };
+        // Shared by all of the threads.
+        let channel_pool: Option<Arc<client::ChannelPool>> = args.channel_pool.into();
 
-            repeat_with(|| {
-                let protocol = experiment.get_protocol().clone();
-                let info = ClientInfo::new(thread_rng().gen());
-                let config = config.clone();
-                let tls = tls.clone();
-                tokio::spawn(async move {
-                    client::viewer::run(
-                        config,
-                        protocol,
-                        info,
-                        hammer,
-                        tls,
-                        max_jitter,
-                        futures::future::ready(()),
-                    )
-                    .await
-                })
+        repeat_with(|| {
+            let protocol = experiment.get_protocol().clone();
+            let info = ClientInfo::new(thread_rng().gen());
+            let config = config.clone();
+            let tls = tls.clone();
+            let cover_pool = cover_pool.clone();
+            let channel_pool = channel_pool.clone();
+            rt::spawn(async move {
+                client::viewer::run(
+                    config,
+                    protocol,
+                    info,
+                    hammer,
+                    tls,
+                    max_jitter,
+                    cover_pool,
+                    channel_pool,
+                    futures::future::ready(()),
+                )
+                .await
             })
-            .take(args.threads.into())
-            .collect::<FuturesUnordered<_>>()
-            .map(|r: Result<Result<(), _>, _>| match r {
-                Ok(Ok(())) => Ok::<(), Box<dyn std::error::Error + Sync + Send>>(()),
-                Ok(Err(err)) => Err(err),
-                Err(err) => Err(err.into()),
-            })
-            .collect::<Vec<Result<(), _>>>()
-            .await
-            .into_iter()
-            .collect::<Result<Vec<()>, _>>()
-            .map(|_| ())
         })
-        .unwrap();
+        .take(args.threads.into())
+        .collect::<FuturesUnordered<_>>()
+        .map(|r: Result<Result<(), _>, _>| match r {
+            Ok(Ok(())) => Ok::<(), Box<dyn std::error::Error + Sync + Send>>(()),
+            Ok(Err(err)) => Err(err),
+            Err(err) => Err(err.into()),
+        })
+        .collect::<Vec<Result<(), _>>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<()>, _>>()
+        .map(|_| ())
+    })
+    .unwrap();
 }
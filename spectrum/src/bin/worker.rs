@@ -1,11 +1,15 @@
 use clap::{crate_authors, crate_version, Parser};
 use futures::prelude::*;
+use spectrum::rt::ctrl_c;
 use spectrum::{
     cli, config, experiment,
-    services::{Group, WorkerInfo},
-    worker,
+    experiment::Experiment,
+    metadata,
+    net::Config as NetConfig,
+    services::{assignment, Group, WorkerInfo},
+    worker::{self, AuditFailurePolicy, AuditSink, CryptoPoolConfig, EarlyUploadPolicy},
+    Error,
 };
-use tokio::signal::ctrl_c;
 
 /// Run a Spectrum worker (many per trust group).
 ///
@@ -23,27 +27,113 @@ struct Args {
     #[clap(flatten)]
     logs: cli::LogArgs,
     #[clap(flatten)]
+    discovery: cli::DiscoveryArgs,
+    #[clap(flatten)]
     worker: WorkerArgs,
     #[clap(flatten)]
     net: cli::NetArgs,
+    #[clap(flatten)]
+    profile: cli::ProfileArgs,
 }
 
 #[derive(Parser)]
 struct WorkerArgs {
     /// The index of the group of this worker.
+    ///
+    /// If omitted, claim a free slot in any group (see `--index`).
     #[clap(long, env = "SPECTRUM_WORKER_GROUP")]
-    group: u16,
+    group: Option<u16>,
 
     /// The index within the group of this worker.
+    ///
+    /// If omitted, claim the lowest free index from the config server (keyed
+    /// by this worker's public address, so restarts keep their index).
     #[clap(long = "index", env = "SPECTRUM_WORKER_INDEX")]
-    idx: u16,
+    idx: Option<u16>,
+
+    /// What to do with a write whose audit fails.
+    ///
+    /// One of `reject` (drop it), `quarantine[:<dir>]` (drop it, saving the
+    /// write token under `<dir>` [default: quarantine] for offline analysis),
+    /// or `accept-flagged` (apply it anyway). Failures are counted and reported
+    /// to the leader and publisher either way.
+    #[clap(
+        long,
+        default_value = "reject",
+        env = "SPECTRUM_WORKER_ON_AUDIT_FAILURE"
+    )]
+    on_audit_failure: AuditFailurePolicy,
+
+    /// Also record each audit failure (client, worker, time, and outcome) to
+    /// an external sink, as newline-delimited JSON.
+    ///
+    /// Either `file:<path>` (append to a file) or `http://<url>` (POST
+    /// batches to a webhook). Events are batched and sent in the background;
+    /// if the sink falls behind, some are dropped rather than slowing audits.
+    #[clap(long, env = "SPECTRUM_WORKER_AUDIT_LOG")]
+    audit_log: Option<AuditSink>,
+
+    /// What to do with an upload that arrives before the start time.
+    ///
+    /// One of `reject` (the client gets an error and can retry) or
+    /// `hold[:<max>]` (accept it, and process it once the round starts; at
+    /// most `<max>` [default: 1024] are held at once).
+    #[clap(long, default_value = "reject", env = "SPECTRUM_WORKER_EARLY_UPLOADS")]
+    early_uploads: EarlyUploadPolicy,
+
+    /// Send audit shares to workers on the same host through shared memory.
+    ///
+    /// Peers are on the same host if they have the same machine ID
+    /// (`/etc/machine-id`); both ends need this flag. Shares sent this way
+    /// skip TLS, simulated latency, and partitions.
+    #[clap(long)]
+    shm_transport: bool,
+
+    /// Keep write tokens encrypted while they wait for their audits.
+    ///
+    /// The key is random, kept only in memory, and replaced every round, so
+    /// a captured image of the worker's state doesn't reveal the tokens.
+    #[clap(long)]
+    seal_write_tokens: bool,
+
+    /// How to share CPU-heavy work between audits and accumulation.
+    ///
+    /// Comma-separated `<key>=<n>` pairs: `slots` (jobs at once; one per CPU
+    /// unless given), and the weights `audit` and `accumulate`. When jobs
+    /// queue up, each kind gets run time in proportion to its weight, so long
+    /// accumulations can't starve the audits our peers are waiting on.
+    #[clap(
+        long,
+        default_value = "audit=1,accumulate=1",
+        env = "SPECTRUM_WORKER_CRYPTO_POOL"
+    )]
+    crypto_pool: CryptoPoolConfig,
 }
 
-impl From<WorkerArgs> for WorkerInfo {
-    fn from(args: WorkerArgs) -> Self {
+impl WorkerArgs {
+    async fn worker_info<C: config::Store>(
+        &self,
+        config: &C,
+        experiment: &Experiment,
+        net: &NetConfig,
+    ) -> Result<WorkerInfo, Error> {
         // -1 because the CLI needs non-zero or it thinks we didn't supply it
         // from environment variable
-        Self::new(Group::new(args.group - 1), args.idx - 1)
+        let group = self.group.map(|group| Group::new(group - 1));
+        let owner = net.public_addr();
+        match (group, self.idx) {
+            (Some(group), Some(idx)) => Ok(WorkerInfo::new(group, idx - 1)),
+            (Some(group), None) => {
+                assignment::claim_in_group(config, group, experiment.group_size(), &owner).await
+            }
+            (None, None) => {
+                assignment::claim(config, experiment.groups(), experiment.group_size(), &owner)
+                    .await
+            }
+            (None, Some(_)) => Err(Error::new(
+                "Can't give a worker index (--index) without a group (--group).",
+            )),
+        }
     }
 }
 
@@ -52,17 +142,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
     let args = Args::parse();
     args.logs.init();
 
-    let config = config::from_env().await?;
+    let config = args.discovery.wrap(config::from_env().await?)?;
     let experiment = experiment::read_from_store(&config).await?;
+    metadata::RunMetadata::new(&experiment).log();
     let protocol = experiment.get_protocol().clone();
-    let info = WorkerInfo::from(args.worker);
+    let net: NetConfig = args.net.into();
+    let info = args.worker.worker_info(&config, &experiment, &net).await?;
+    let profile = args.profile.start()?;
     worker::run(
         config,
         experiment,
         protocol,
         info,
-        args.net.into(),
+        net,
+        args.worker.on_audit_failure,
+        args.worker.audit_log,
+        args.worker.early_uploads,
+        args.worker.shm_transport,
+        args.worker.seal_write_tokens,
+        args.worker.crypto_pool,
         ctrl_c().map(|_| ()),
     )
-    .await
+    .await?;
+    if let Some(profile) = profile {
+        profile.finish()?;
+    }
+    Ok(())
 }
@@ -1,9 +1,25 @@
 use crate::{
-    experiment::Experiment, net::Config as NetConfig, protocols::wrapper::ProtocolWrapper,
+    client::{ChannelPool, ChannelPoolConfig},
+    config::Store,
+    experiment::{Experiment, HammerConfig, ProtocolConfig, ProtocolSeed, RunMode, Topology},
+    logs::{self, LogFilter},
+    net::{systemd, Config as NetConfig, Limits, Scheme},
+    profile::Profile,
+    protocols::wrapper::ProtocolWrapper,
+    services::{
+        budget::StageBudgets,
+        deadline::Deadlines,
+        discovery::{Discovered, DnsSrvDiscovery, FileDiscovery},
+        privacy::PrivacyBudget,
+        reservation::ReservationRound,
+    },
+    Error,
 };
 
 use clap::Parser;
-use simplelog::{LevelFilter, SimpleLogger, TermLogger, TerminalMode};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tonic::transport::{Certificate, Identity};
 
 #[derive(Parser)]
@@ -26,35 +42,43 @@ impl From<Args> for Experiment {
     }
 }
 
-// TODO: parse valid values, put in help string
 #[derive(Parser)]
 pub struct LogArgs {
-    /// Log level.
+    /// Log level, then any per-module levels.
+    ///
+    /// For example, `debug,spectrum::worker::audit_registry=trace`. Change it
+    /// while running with the `admin` tool.
     #[clap(short = 'v', long, default_value = "debug", env = "SPECTRUM_LOG_LEVEL")]
-    log_level: LevelFilter,
+    log_level: LogFilter,
 }
 
 impl LogArgs {
     pub fn init(&self) {
-        use simplelog::{CombinedLogger, ConfigBuilder, SharedLogger};
-        const FILTER: &str = "spectrum";
-
-        let config = ConfigBuilder::new().add_filter_ignore_str(FILTER).build();
-        let other_logger: Box<dyn SharedLogger> =
-            match TermLogger::new(LevelFilter::Info, config.clone(), TerminalMode::Stderr) {
-                Some(logger) => logger,
-                None => SimpleLogger::new(LevelFilter::Info, config),
-            };
-
-        let config = ConfigBuilder::new().add_filter_allow_str(FILTER).build();
-        let spectrum_logger: Box<dyn SharedLogger> =
-            match TermLogger::new(self.log_level, config.clone(), TerminalMode::Stderr) {
-                Some(logger) => logger,
-                None => SimpleLogger::new(self.log_level, config),
-            };
-
-        CombinedLogger::init(vec![spectrum_logger, other_logger])
-            .expect("Failed initializing logger.");
+        logs::init(self.log_level.clone());
+    }
+}
+
+#[derive(Parser)]
+pub struct ProfileArgs {
+    /// Write a CPU profile of this process here at shutdown.
+    ///
+    /// A flamegraph if the path ends in `.svg`, and a gzipped pprof protobuf
+    /// (e.g. `worker.pb.gz`) otherwise.
+    #[clap(long, env = "SPECTRUM_PROFILE_OUT")]
+    profile_out: Option<PathBuf>,
+
+    /// Profiling samples per second.
+    #[clap(long, default_value = "99", env = "SPECTRUM_PROFILE_FREQUENCY")]
+    profile_frequency: i32,
+}
+
+impl ProfileArgs {
+    /// Start profiling, if requested.
+    pub fn start(&self) -> Result<Option<Profile>, Box<dyn std::error::Error + Sync + Send>> {
+        self.profile_out
+            .clone()
+            .map(|out| Profile::start(out, self.profile_frequency))
+            .transpose()
     }
 }
 
@@ -62,7 +86,8 @@ impl LogArgs {
 pub struct NetArgs {
     /// Port on which the service should bind (localhost interface).
     ///
-    /// If not given, a random unused port will be picked.
+    /// If not given, a random unused port will be picked. Ignored if systemd
+    /// passes a socket (socket activation; see `$LISTEN_FDS`).
     #[clap(long)]
     local_port: Option<u16>,
 
@@ -72,10 +97,46 @@ pub struct NetArgs {
     #[clap(long = "public-address")]
     public_addr: Option<String>,
 
+    /// Scheme to publish with the public address (`http` or `https`).
+    ///
+    /// Use `https` when a reverse proxy in front of this service terminates
+    /// TLS.
+    #[clap(long, default_value = "http")]
+    public_scheme: Scheme,
+
+    /// Deadline (in milliseconds) for sending audit shares to other workers.
+    #[clap(long)]
+    verify_deadline_ms: Option<u64>,
+
+    /// Deadline (in milliseconds) for sending aggregates to leaders/publishers.
+    #[clap(long)]
+    aggregate_deadline_ms: Option<u64>,
+
+    /// Maximum concurrent HTTP/2 streams per connection.
+    #[clap(long)]
+    max_concurrent_streams: Option<u32>,
+
+    /// Maximum requests in flight per connection.
+    #[clap(long)]
+    max_concurrent_requests_per_connection: Option<usize>,
+
+    /// Maximum open connections (more wait until one closes).
+    #[clap(long)]
+    max_connections: Option<usize>,
+
+    /// Maximum open connections from one IP address (more are refused).
+    #[clap(long)]
+    max_connections_per_ip: Option<usize>,
+
     #[clap(flatten)]
     tls: TlsServerArgs,
 }
 
+/// TLS configuration for a server.
+///
+/// If `--tls-ca` is given, peers are expected to trust that CA. Otherwise, the
+/// certificate is treated as self-signed and pinned: it gets published in the
+/// config store and peers verify this service against it directly.
 #[derive(Parser)]
 pub struct TlsServerArgs {
     /// Path to .crt
@@ -90,6 +151,19 @@ pub struct TlsServerArgs {
     tls_ca: TlsCaArgs,
 }
 
+impl TlsServerArgs {
+    /// The certificate to pin in the config store (if not using a CA).
+    fn pinned_cert(&self) -> Option<Certificate> {
+        if self.tls_ca.ca_file.is_some() {
+            return None;
+        }
+        self.cert_file.as_ref().map(|cert_file| {
+            let pem = std::fs::read_to_string(cert_file).unwrap();
+            Certificate::from_pem(pem)
+        })
+    }
+}
+
 impl From<TlsServerArgs> for Option<(Identity, Certificate)> {
     fn from(args: TlsServerArgs) -> Option<(Identity, Certificate)> {
         match (args.cert_file, args.key_file) {
@@ -97,9 +171,10 @@ impl From<TlsServerArgs> for Option<(Identity, Certificate)> {
             (Some(cert_file), Some(key_file)) => {
                 let cert = std::fs::read_to_string(cert_file).unwrap();
                 let key = std::fs::read_to_string(key_file).unwrap();
-                let identity = Identity::from_pem(cert, key);
-                let cert: Option<Certificate> = args.tls_ca.into();
-                Some(identity).zip(cert)
+                let identity = Identity::from_pem(&cert, key);
+                // Without a CA, our own (self-signed) certificate is the root of trust.
+                let ca: Option<Certificate> = args.tls_ca.into();
+                Some((identity, ca.unwrap_or_else(|| Certificate::from_pem(cert))))
             }
             _ => {
                 panic!("TLS cert and key must be provided together.");
@@ -111,6 +186,9 @@ impl From<TlsServerArgs> for Option<(Identity, Certificate)> {
 #[derive(Parser)]
 pub struct TlsCaArgs {
     /// Path to ca.pem
+    ///
+    /// If not given, servers are verified against the certificates they pin
+    /// in the config store.
     #[clap(long = "tls-ca", env = "SPECTRUM_TLS_CA")]
     ca_file: Option<String>,
 }
@@ -124,14 +202,98 @@ impl From<TlsCaArgs> for Option<Certificate> {
     }
 }
 
+/// Sharing worker connections between the clients in one process.
+#[derive(Parser)]
+pub struct ChannelPoolArgs {
+    /// Open this many connections to each worker, shared by every client in
+    /// the process (0 for a connection per client).
+    #[clap(long, env = "SPECTRUM_CHANNELS_PER_WORKER", default_value = "0")]
+    channels_per_worker: usize,
+
+    /// Maximum requests in flight on each shared connection.
+    ///
+    /// Keep this at or below the workers' `--max-concurrent-streams`.
+    #[clap(long, env = "SPECTRUM_STREAMS_PER_CHANNEL", default_value = "100")]
+    streams_per_channel: usize,
+}
+
+impl From<ChannelPoolArgs> for Option<Arc<ChannelPool>> {
+    fn from(args: ChannelPoolArgs) -> Option<Arc<ChannelPool>> {
+        match args.channels_per_worker {
+            0 => None,
+            channels_per_worker => Some(Arc::new(ChannelPool::new(ChannelPoolConfig {
+                channels_per_worker,
+                streams_per_channel: args.streams_per_channel,
+            }))),
+        }
+    }
+}
+
 impl From<NetArgs> for NetConfig {
     fn from(args: NetArgs) -> NetConfig {
+        let pinned_cert = args.tls.pinned_cert();
         let tls: Option<(Identity, Certificate)> = args.tls.into();
-        match (args.local_port, args.public_addr) {
-            (None, None) => NetConfig::with_free_port_localhost(tls),
-            (None, Some(public_addr)) => NetConfig::with_free_port(public_addr, tls),
-            (Some(local_port), None) => NetConfig::new_localhost(local_port, tls),
-            (Some(local_port), Some(public_addr)) => NetConfig::new(local_port, public_addr, tls),
+        let listener = systemd::take_listener().expect("Bad socket from systemd.");
+        let mut config = match (listener, args.local_port, args.public_addr) {
+            (Some(listener), _, public_addr) => {
+                NetConfig::with_listener(listener, public_addr, tls)
+                    .expect("Socket from systemd should be a TCP listener.")
+            }
+            (None, None, None) => NetConfig::with_free_port_localhost(tls),
+            (None, None, Some(public_addr)) => NetConfig::with_free_port(public_addr, tls),
+            (None, Some(local_port), None) => NetConfig::new_localhost(local_port, tls),
+            (None, Some(local_port), Some(public_addr)) => {
+                NetConfig::new(local_port, public_addr, tls)
+            }
+        };
+        config.set_pinned_cert(pinned_cert);
+        config.set_public_scheme(args.public_scheme);
+        let mut deadlines = Deadlines::default();
+        if let Some(ms) = args.verify_deadline_ms {
+            deadlines.verify = Duration::from_millis(ms);
+        }
+        if let Some(ms) = args.aggregate_deadline_ms {
+            deadlines.aggregate = Duration::from_millis(ms);
+        }
+        config.set_deadlines(deadlines);
+        config.set_limits(Limits {
+            max_concurrent_streams: args.max_concurrent_streams,
+            concurrency_per_connection: args.max_concurrent_requests_per_connection,
+            max_connections: args.max_connections,
+            max_connections_per_ip: args.max_connections_per_ip,
+        });
+        config
+    }
+}
+
+/// How nodes find each other.
+///
+/// By default, nodes register themselves in the config store.
+#[derive(Parser)]
+pub struct DiscoveryArgs {
+    /// Path to a static TOML file listing all nodes.
+    #[clap(long, env = "SPECTRUM_NODES_FILE")]
+    nodes_file: Option<String>,
+
+    /// DNS SRV name listing all nodes (e.g. `_spectrum._tcp.example.com`).
+    #[clap(long, env = "SPECTRUM_DNS_SRV")]
+    dns_srv: Option<String>,
+}
+
+impl DiscoveryArgs {
+    /// Pair `store` with the requested discovery backend.
+    pub fn wrap<C>(self, store: C) -> Result<Discovered<C>, Error>
+    where
+        C: 'static + Store + Clone + Send + Sync,
+    {
+        let discovered = Discovered::new(store);
+        match (self.nodes_file, self.dns_srv) {
+            (None, None) => Ok(discovered),
+            (Some(path), None) => Ok(discovered.with_discovery(FileDiscovery::from_file(path)?)),
+            (None, Some(name)) => Ok(discovered.with_discovery(DnsSrvDiscovery::new(name))),
+            (Some(_), Some(_)) => Err(Error::new(
+                "At most one of --nodes-file and --dns-srv may be given.",
+            )),
         }
     }
 }
@@ -173,6 +335,10 @@ pub struct ExperimentArgs {
     security_bytes: Option<u32>,
 
     /// Size (in bytes) to use for the secure protocol.
+    ///
+    /// The multi-key protocol precomputes a table (about 150KiB) for each 32
+    /// bytes of message, for at most 1024 of them (about 150MiB, per process);
+    /// messages past 32KiB evaluate the rest more slowly.
     #[clap(long = "security-multi-key", group = "security")]
     security_multi_key_bytes: Option<u32>,
 
@@ -189,6 +355,62 @@ pub struct ExperimentArgs {
     /// If true, don't set up a publisher or leaders; just measure raw QPS.
     #[clap(long)]
     hammer: bool,
+
+    /// In hammer mode, total uploads per second to aim for across all clients.
+    #[clap(long, requires = "hammer")]
+    hammer_target_qps: Option<u64>,
+
+    /// In hammer mode, stop after this long (in milliseconds).
+    #[clap(long, requires = "hammer")]
+    hammer_duration_ms: Option<u64>,
+
+    /// In hammer mode, how often workers report progress (in milliseconds).
+    #[clap(long, default_value = "1000")]
+    hammer_report_interval_ms: u64,
+
+    /// Send shares to the publisher as differences from the previous round's.
+    #[clap(long, conflicts_with = "hammer")]
+    delta_shares: bool,
+
+    /// Have broadcasters prefix messages with a checksum, so the publisher can
+    /// flag channels that more than one broadcaster wrote to.
+    ///
+    /// Takes 8 bytes of each message. Not supported with the multi-key protocol.
+    #[clap(long, conflicts_with_all = &["hammer", "security-multi-key-bytes"])]
+    channel_checksums: bool,
+
+    /// Make the per-round participation counts in the publisher's manifests
+    /// (ε, δ)-differentially private, given as `<epsilon>,<delta>`.
+    ///
+    /// Workers add random noise to their client counts (without making any
+    /// extra writes). Without this, manifests don't include participation
+    /// counts.
+    #[clap(long, conflicts_with = "hammer")]
+    participation_privacy: Option<PrivacyBudget>,
+
+    /// Latency budgets for stages of the round, e.g.
+    /// `upload=50ms,audit=200ms,aggregate=2s`.
+    ///
+    /// The publisher's manifests record how many uploads, audits, and
+    /// aggregations went over budget.
+    #[clap(long, conflicts_with = "hammer")]
+    stage_budgets: Option<StageBudgets>,
+
+    /// Make this the reservation round of the two-phase variant, with this
+    /// many slots per channel.
+    ///
+    /// Broadcasters each reserve a random slot, and the publisher assigns the
+    /// reserved slots to parts of a `--message-size` payload message (for a
+    /// later payload round). Messages in this round are 16 bytes per slot.
+    #[clap(
+        long,
+        conflicts_with_all = &["hammer", "security-multi-key-bytes", "channel-checksums"]
+    )]
+    reservation_slots: Option<usize>,
+
+    /// Number of replicated publishers; leaders send their shares to all of them.
+    #[clap(long, default_value = "1")]
+    publishers: u16,
 }
 
 impl ExperimentArgs {
@@ -218,10 +440,45 @@ impl From<ExperimentArgs> for ProtocolWrapper {
 
 impl From<ExperimentArgs> for Experiment {
     fn from(args: ExperimentArgs) -> Self {
-        let group_size = args.group_size;
-        let clients = args.clients;
-        let hammer = args.hammer;
-        Experiment::new_sample_keys(args.into(), group_size, clients, hammer)
+        let topology =
+            Topology::new(args.group_size, args.clients).with_publishers(args.publishers);
+        let reservation_round = args
+            .reservation_slots
+            .map(|slots| ReservationRound::new(slots, args.msg_size));
+        let mode = if args.hammer {
+            RunMode::Hammer(HammerConfig {
+                target_qps: args.hammer_target_qps,
+                duration: args.hammer_duration_ms.map(Duration::from_millis),
+                report_interval: Duration::from_millis(args.hammer_report_interval_ms),
+            })
+        } else {
+            RunMode::Broadcast {
+                delta_shares: args.delta_shares,
+                channel_checksums: args.channel_checksums,
+                participation_privacy: args.participation_privacy,
+                stage_budgets: args.stage_budgets.unwrap_or_default(),
+                reservation_round,
+            }
+        };
+        let multi_key = args.security_bytes().is_some() && args.security_multi_key_bytes.is_some();
+        let protocol = match reservation_round {
+            Some(round) => {
+                let protocol: ProtocolWrapper = args.into();
+                ProtocolConfig::sample_keys(
+                    protocol
+                        .reservation_round(round.reservation)
+                        .expect("Reservation rounds don't support the multi-key protocol."),
+                )
+            }
+            // Every process derives the same generators from the seed.
+            None if multi_key => ProtocolConfig::sample_keys_from_seed(ProtocolSeed::random(
+                args.groups,
+                args.channels,
+                args.msg_size,
+            )),
+            None => ProtocolConfig::sample_keys(args.into()),
+        };
+        Experiment::from_parts(protocol, topology, mode)
     }
 }
 
@@ -231,28 +488,130 @@ pub mod tests {
 
     #[test]
     fn test_security_default() {
-        let args = ExperimentArgs::try_parse_from(&["binary"]).unwrap();
+        let args = ExperimentArgs::try_parse_from(["binary"]).unwrap();
         assert_eq!(args.security_bytes(), Some(16));
     }
 
     #[test]
     fn test_security_no_security() {
-        let args = ExperimentArgs::try_parse_from(&["binary", "--no-security"]).unwrap();
+        let args = ExperimentArgs::try_parse_from(["binary", "--no-security"]).unwrap();
         assert_eq!(args.security_bytes(), None);
     }
 
     #[test]
     fn test_security_custom_security() {
-        let args = ExperimentArgs::try_parse_from(&["binary", "--security", "30"]).unwrap();
+        let args = ExperimentArgs::try_parse_from(["binary", "--security", "30"]).unwrap();
         assert_eq!(args.security_bytes(), Some(30));
     }
 
     #[test]
     fn test_security_conflicts() {
         assert!(
-            ExperimentArgs::try_parse_from(&["binary", "--security", "30", "--no-security"])
+            ExperimentArgs::try_parse_from(["binary", "--security", "30", "--no-security"])
                 .is_err(),
             "Passing both `--no-security` and `--security` should error."
         );
     }
+
+    #[test]
+    fn test_hammer_config() {
+        let args =
+            ExperimentArgs::try_parse_from(["binary", "--hammer", "--hammer-duration-ms", "5000"])
+                .unwrap();
+        let experiment = Experiment::from(args);
+        assert_eq!(
+            experiment.hammer(),
+            Some(HammerConfig {
+                duration: Some(Duration::from_secs(5)),
+                ..Default::default()
+            })
+        );
+        assert!(
+            ExperimentArgs::try_parse_from(["binary", "--hammer-duration-ms", "5000"]).is_err(),
+            "Hammer options require `--hammer`."
+        );
+    }
+
+    #[test]
+    fn test_channel_checksums() {
+        let args = ExperimentArgs::try_parse_from(["binary", "--channel-checksums"]).unwrap();
+        assert!(Experiment::from(args).channel_checksums());
+        assert!(
+            ExperimentArgs::try_parse_from([
+                "binary",
+                "--channel-checksums",
+                "--security-multi-key",
+                "16"
+            ])
+            .is_err(),
+            "Multi-key messages have no room for a checksum."
+        );
+    }
+
+    #[test]
+    fn test_participation_privacy() {
+        let args =
+            ExperimentArgs::try_parse_from(["binary", "--participation-privacy", "0.5,1e-6"])
+                .unwrap();
+        assert_eq!(
+            Experiment::from(args).participation_privacy(),
+            Some(PrivacyBudget::new(0.5, 1e-6).unwrap())
+        );
+        assert!(
+            ExperimentArgs::try_parse_from(["binary", "--participation-privacy", "0.5,2"]).is_err(),
+            "Delta must be less than 1."
+        );
+    }
+
+    #[test]
+    fn test_reservation_slots() {
+        let args = ExperimentArgs::try_parse_from([
+            "binary",
+            "--reservation-slots",
+            "4",
+            "--message-size",
+            "100",
+        ])
+        .unwrap();
+        let experiment = Experiment::from(args);
+        assert_eq!(
+            experiment.reservation_round(),
+            Some(ReservationRound::new(4, 100))
+        );
+        assert_eq!(experiment.msg_size(), 64);
+        assert!(
+            ExperimentArgs::try_parse_from([
+                "binary",
+                "--reservation-slots",
+                "4",
+                "--security-multi-key",
+                "16"
+            ])
+            .is_err(),
+            "Multi-key messages can't hold reservations."
+        );
+    }
+
+    #[test]
+    fn test_channel_pool() {
+        let args = ChannelPoolArgs::try_parse_from(["binary"]).unwrap();
+        assert!(Option::<Arc<ChannelPool>>::from(args).is_none());
+
+        let args = ChannelPoolArgs::try_parse_from([
+            "binary",
+            "--channels-per-worker",
+            "4",
+            "--streams-per-channel",
+            "50",
+        ])
+        .unwrap();
+        let pool = Option::<Arc<ChannelPool>>::from(args).unwrap();
+        assert_eq!(
+            pool.config(),
+            ChannelPoolConfig {
+                channels_per_worker: 4,
+                streams_per_channel: 50,
+            }
+        );
+    }
 }
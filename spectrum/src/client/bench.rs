@@ -0,0 +1,217 @@
+//! Client-side costs, measured without any servers.
+//!
+//! Before a broadcaster uploads anything it generates DPF keys for its
+//! channel, generates their proof shares, and serializes the resulting write
+//! tokens. [`bench_keygen`] times each of those for a given set of protocol
+//! parameters; `broadcaster --bench-keygen` runs it over a grid of message
+//! sizes and channel counts and prints the results as JSON.
+use crate::experiment::ProtocolConfig;
+use crate::proto;
+use crate::protocols::{secure, wrapper::ChannelKeyWrapper, wrapper::ProtocolWrapper, Protocol};
+
+use prost::Message;
+use rand::thread_rng;
+use serde::Serialize;
+use spectrum_primitives::{Bytes, Dpf, EncodeElements, Vdpf};
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Which protocol to benchmark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BenchProtocol {
+    TwoKey,
+    TwoKeyPub,
+    MultiKey,
+}
+
+impl BenchProtocol {
+    pub fn protocol(self, groups: usize, channels: usize, msg_size: usize) -> ProtocolWrapper {
+        match self {
+            BenchProtocol::TwoKey => {
+                ProtocolWrapper::new(true, false, 2, channels, msg_size, false)
+            }
+            BenchProtocol::TwoKeyPub => {
+                ProtocolWrapper::new(true, false, 2, channels, msg_size, true)
+            }
+            BenchProtocol::MultiKey => {
+                ProtocolWrapper::new(true, true, groups, channels, msg_size, false)
+            }
+        }
+    }
+}
+
+impl FromStr for BenchProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "two-key" => Ok(BenchProtocol::TwoKey),
+            "two-key-pub" => Ok(BenchProtocol::TwoKeyPub),
+            "multi-key" => Ok(BenchProtocol::MultiKey),
+            _ => Err(format!(
+                "Unknown protocol [{}] (expected two-key, two-key-pub, or multi-key).",
+                s
+            )),
+        }
+    }
+}
+
+/// Mean client-side cost of one broadcast.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeygenCost {
+    pub protocol: BenchProtocol,
+    pub groups: usize,
+    pub channels: usize,
+    pub msg_size: usize,
+    pub iterations: usize,
+    /// Generating the DPF keys, in microseconds.
+    pub dpf_gen_us: f64,
+    /// Generating the proof shares, in microseconds.
+    pub proof_gen_us: f64,
+    /// Serializing the write tokens for the wire, in microseconds.
+    pub serialize_us: f64,
+    /// Size of the serialized write tokens (for every group, together).
+    pub upload_bytes: usize,
+}
+
+#[derive(Default)]
+struct Timings {
+    dpf_gen: Duration,
+    proof_gen: Duration,
+    serialize: Duration,
+    upload_bytes: usize,
+}
+
+fn time_broadcast<V>(
+    protocol: &secure::Wrapper<V>,
+    message: <V as Dpf>::Message,
+    key: ChannelKeyWrapper,
+) -> Timings
+where
+    V: Vdpf,
+    <V as Vdpf>::AuthKey: TryFrom<ChannelKeyWrapper>,
+    <<V as Vdpf>::AuthKey as TryFrom<ChannelKeyWrapper>>::Error: fmt::Debug,
+    secure::WriteToken<<V as Dpf>::Key, <V as Vdpf>::ProofShare>: Into<proto::WriteToken>,
+{
+    let key = key.try_into().expect("key should match the protocol");
+
+    let start = Instant::now();
+    let dpf_keys = protocol.gen_dpf_keys(message, 0);
+    let dpf_gen = start.elapsed();
+
+    let start = Instant::now();
+    let write_tokens = protocol.gen_write_tokens(&key, 0, dpf_keys);
+    let proof_gen = start.elapsed();
+
+    let start = Instant::now();
+    let mut upload_bytes = 0;
+    for write_token in write_tokens {
+        let write_token: proto::WriteToken = write_token.into();
+        let mut data = Vec::with_capacity(write_token.encoded_len());
+        write_token
+            .encode(&mut data)
+            .expect("Vec should have enough capacity");
+        upload_bytes += data.len();
+    }
+    let serialize = start.elapsed();
+
+    Timings {
+        dpf_gen,
+        proof_gen,
+        serialize,
+        upload_bytes,
+    }
+}
+
+/// Time `iterations` broadcasts (to channel 0) with the given parameters.
+///
+/// `groups` only matters for the multi-key protocol; the others always have 2.
+pub fn bench_keygen(
+    kind: BenchProtocol,
+    groups: usize,
+    channels: usize,
+    msg_size: usize,
+    iterations: usize,
+) -> KeygenCost {
+    assert!(iterations >= 1, "Expected at least 1 iteration.");
+    let config = ProtocolConfig::sample_keys(kind.protocol(groups, channels, msg_size));
+    let key = config.keys()[0].clone();
+    let mut total = Timings::default();
+    for _ in 0..iterations {
+        let message = Bytes::random(msg_size, &mut thread_rng());
+        let timings = match config.protocol() {
+            ProtocolWrapper::Insecure(_) => unreachable!("BenchProtocol is always secure"),
+            ProtocolWrapper::Secure(protocol) => time_broadcast(protocol, message, key.clone()),
+            ProtocolWrapper::SecurePub(protocol) => time_broadcast(protocol, message, key.clone()),
+            ProtocolWrapper::SecureMultiKey(protocol) => {
+                // Random bytes are (almost never) group elements; encode them.
+                let message = message
+                    .encode(protocol.slot_size())
+                    .expect("message should fit the protocol");
+                time_broadcast(protocol, message, key.clone())
+            }
+        };
+        total.dpf_gen += timings.dpf_gen;
+        total.proof_gen += timings.proof_gen;
+        total.serialize += timings.serialize;
+        total.upload_bytes = timings.upload_bytes;
+    }
+
+    let mean_us = |total: Duration| total.as_secs_f64() * 1e6 / iterations as f64;
+    KeygenCost {
+        protocol: kind,
+        groups: config.protocol().num_parties(),
+        channels,
+        msg_size,
+        iterations,
+        dpf_gen_us: mean_us(total.dpf_gen),
+        proof_gen_us: mean_us(total.proof_gen),
+        serialize_us: mean_us(total.serialize),
+        upload_bytes: total.upload_bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_protocol() {
+        for kind in &[
+            BenchProtocol::TwoKey,
+            BenchProtocol::TwoKeyPub,
+            BenchProtocol::MultiKey,
+        ] {
+            let name = serde_json::to_value(kind).unwrap();
+            assert_eq!(name.as_str().unwrap().parse(), Ok(*kind));
+        }
+        "three-key"
+            .parse::<BenchProtocol>()
+            .expect_err("bad protocol");
+    }
+
+    #[test]
+    fn test_bench_keygen() {
+        for kind in &[
+            BenchProtocol::TwoKey,
+            BenchProtocol::TwoKeyPub,
+            BenchProtocol::MultiKey,
+        ] {
+            let cost = bench_keygen(*kind, 3, 4, 64, 2);
+            assert_eq!(cost.protocol, *kind);
+            assert_eq!(cost.channels, 4);
+            assert_eq!(cost.iterations, 2);
+            assert!(cost.upload_bytes > 0);
+            let expected_groups = if *kind == BenchProtocol::MultiKey {
+                3
+            } else {
+                2
+            };
+            assert_eq!(cost.groups, expected_groups);
+        }
+    }
+}
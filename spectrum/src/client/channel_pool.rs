@@ -0,0 +1,135 @@
+use crate::rt::sync::Mutex;
+
+use log::debug;
+use tonic::transport::channel::Channel;
+
+use std::collections::HashMap;
+
+use crate::net::client::Builder;
+
+type TokioError = Box<dyn std::error::Error + Sync + Send>;
+
+/// How a [`ChannelPool`] spreads clients over connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelPoolConfig {
+    /// Connections to open to each worker.
+    pub channels_per_worker: usize,
+    /// Requests in flight at a time on each connection; more wait their turn.
+    ///
+    /// Keep this at or below the workers' `--max-concurrent-streams`.
+    pub streams_per_channel: usize,
+}
+
+// The connections to one worker, handed out round-robin.
+#[derive(Debug)]
+struct Slots<T> {
+    channels: Vec<T>,
+    next: usize,
+}
+
+impl<T: Clone> Slots<T> {
+    fn new() -> Self {
+        Slots {
+            channels: vec![],
+            next: 0,
+        }
+    }
+
+    // The next connection to hand out, or `None` if we should open another.
+    fn next(&mut self, max: usize) -> Option<T> {
+        if self.channels.len() < max {
+            return None;
+        }
+        let channel = self.channels[self.next % self.channels.len()].clone();
+        self.next += 1;
+        Some(channel)
+    }
+
+    fn push(&mut self, channel: T) {
+        self.channels.push(channel);
+    }
+}
+
+/// Worker connections shared by the clients in one process.
+///
+/// A process simulating hundreds of clients runs out of sockets with a
+/// connection per client, but a single shared connection runs into HTTP/2's
+/// limit on concurrent streams. The pool keeps a fixed number of connections
+/// to each worker (keyed by address) and caps the requests in flight on each.
+#[derive(Debug)]
+pub struct ChannelPool {
+    config: ChannelPoolConfig,
+    workers: Mutex<HashMap<String, Slots<Channel>>>,
+}
+
+impl ChannelPool {
+    pub fn new(config: ChannelPoolConfig) -> Self {
+        assert!(
+            config.channels_per_worker >= 1,
+            "Expected at least 1 channel per worker."
+        );
+        assert!(
+            config.streams_per_channel >= 1,
+            "Expected at least 1 stream per channel."
+        );
+        ChannelPool {
+            config,
+            workers: Default::default(),
+        }
+    }
+
+    pub fn config(&self) -> ChannelPoolConfig {
+        self.config
+    }
+
+    /// A connection to the worker `builder` is for, opening one (with our
+    /// stream limit) if this worker doesn't have its share yet.
+    pub async fn get(&self, builder: &Builder) -> Result<Channel, TokioError> {
+        let uri = builder.uri();
+        // Held while connecting, so that concurrent clients don't open more
+        // than `channels_per_worker` between them.
+        let mut workers = self.workers.lock().await;
+        let slots = workers.entry(uri.to_string()).or_insert_with(Slots::new);
+        if let Some(channel) = slots.next(self.config.channels_per_worker) {
+            return Ok(channel);
+        }
+        debug!(
+            "Opening shared connection {}/{} to {}.",
+            slots.channels.len() + 1,
+            self.config.channels_per_worker,
+            uri
+        );
+        let channel = builder
+            .clone()
+            .concurrency_limit(Some(self.config.streams_per_channel))
+            .channel()
+            .await?;
+        slots.push(channel.clone());
+        Ok(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_fill_then_cycle() {
+        let mut slots = Slots::new();
+        for idx in 0..3 {
+            assert_eq!(slots.next(3), None);
+            slots.push(idx);
+        }
+        let handed_out: Vec<_> = (0..7).map(|_| slots.next(3).unwrap()).collect();
+        assert_eq!(handed_out, vec![0, 1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 1 channel")]
+    fn test_no_channels() {
+        ChannelPool::new(ChannelPoolConfig {
+            channels_per_worker: 0,
+            streams_per_channel: 100,
+        });
+    }
+}
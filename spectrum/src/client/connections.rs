@@ -1,18 +1,23 @@
-use crate::proto::{worker_client::WorkerClient, RegisterClientRequest};
+use crate::proto::{worker_client::WorkerClient, RegisterClientRequest, RegistrationWindowRequest};
 use crate::Error;
 use crate::{
     config,
+    net::{client::Builder, tls::Certificate, ClientChannel},
     services::{
-        discovery::{resolve_all, Node},
+        deadline::{self, Deadlines},
+        discovery::{resolve_all, Discovery, Node},
+        quorum::delay_until,
+        registration::Window,
         ClientInfo, Group, Service,
     },
 };
 use config::store::Store;
 
+use super::ChannelPool;
+
+use chrono::prelude::*;
 use log::{debug, trace};
 use rand::{seq::IteratorRandom, thread_rng};
-use tokio::time::sleep;
-use tonic::transport::{channel::Channel, Certificate, ClientTlsConfig, Uri};
 
 use std::collections::HashSet;
 use std::time::Duration;
@@ -48,50 +53,79 @@ fn pick_worker_shards(nodes: Vec<Node>) -> Vec<Node> {
     shards
 }
 
+// Workers may not be up yet when clients start, so retry a few times.
+const CONNECT_ATTEMPTS: usize = 10;
+const CONNECT_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Client builder for the worker at `uri`.
+fn worker_builder(uri: String, cert: Option<Certificate>) -> Builder {
+    Builder::new(uri)
+        .tls(cert)
+        .connect_attempts(CONNECT_ATTEMPTS, CONNECT_RETRY_DELAY)
+}
+
 async fn connect(
-    addr: String,
+    uri: String,
     cert: Option<Certificate>,
-) -> Result<WorkerClient<Channel>, TokioError> {
-    let mut attempts: u8 = 0;
-    let uri = format!("http://{}", addr)
-        .parse::<Uri>()
-        .expect("Invalid URI");
-    loop {
-        let mut builder = Channel::builder(uri.clone());
-        if let Some(ref cert) = cert {
-            debug!("TLS for client.");
-            builder = builder.tls_config(
-                ClientTlsConfig::new()
-                    .domain_name("spectrum.example.com")
-                    .ca_certificate(cert.clone()),
-            )?;
-        }
-        let res = builder.connect().await;
-        if let Ok(channel) = res {
-            return Ok(WorkerClient::new(channel));
-        }
-        debug!("Failed to connect to worker: {:?}", res);
-        attempts += 1;
-        if attempts >= 10 {
-            return Err(Box::new(Error::from(format!(
-                "Failed to connect to worker on every attempt! {:?}",
-                res
-            ))));
+    pool: Option<&ChannelPool>,
+    from: &Service,
+    to: &Service,
+) -> Result<WorkerClient<ClientChannel>, TokioError> {
+    let builder = worker_builder(uri, cert);
+    let channel = match pool {
+        Some(pool) => pool.get(&builder).await?,
+        None => builder.channel().await?,
+    };
+    Ok(WorkerClient::new(builder.wrap(
+        channel,
+        from.clone(),
+        to.clone(),
+    )))
+}
+
+/// Ask `client` whether registration is open, waiting for it to open if it
+/// hasn't yet.
+async fn wait_for_registration_open(
+    client: &mut WorkerClient<ClientChannel>,
+) -> Result<(), TokioError> {
+    let req = deadline::request(
+        RegistrationWindowRequest {},
+        Deadlines::default().register,
+        None,
+    );
+    let response = client.get_registration_window(req).await?.into_inner();
+    if response.open {
+        return Ok(());
+    }
+    let window = Window::from_proto(&response)?;
+    match window.opens {
+        Some(opens) if DateTime::<FixedOffset>::from(Utc::now()) < opens => {
+            debug!("Registration opens at {}; waiting.", opens);
+            delay_until(opens).await;
+            Ok(())
         }
-        sleep(Duration::from_millis(50)).await;
+        _ => Err(Box::new(Error::new("Registration window is closed."))),
     }
 }
 
+/// Connect to one worker per group and register with each.
+///
+/// Waits for each worker's registration window to open first. Connections
+/// come from `pool` if given (shared with the other clients using it).
+///
+/// Returns each worker client alongside the session token it issued us.
 pub async fn connect_and_register<C>(
     config: &C,
     info: ClientInfo,
     cert: Option<Certificate>,
-) -> Result<Vec<WorkerClient<Channel>>, TokioError>
+    pool: Option<&ChannelPool>,
+) -> Result<Vec<(WorkerClient<ClientChannel>, Vec<u8>)>, TokioError>
 where
-    C: Store,
+    C: Store + Discovery,
 {
     let nodes: Vec<Node> = resolve_all(config).await?;
     let shards: Vec<Node> = pick_worker_shards(nodes);
+    let me = Service::from(info.clone());
     let mut clients = vec![];
     let req = RegisterClientRequest {
         client_id: Some(info.to_proto()),
@@ -104,12 +138,24 @@ where
             .collect(),
     };
     for shard in shards {
-        let mut client = connect(shard.addr.clone(), cert.clone()).await?;
-        let req = tonic::Request::new(req.clone());
+        let mut client = connect(
+            shard.uri(),
+            shard.tls_cert(cert.clone()),
+            pool,
+            &me,
+            &shard.service,
+        )
+        .await?;
+        wait_for_registration_open(&mut client).await?;
+        let req = deadline::request(req.clone(), Deadlines::default().register, None);
         trace!("Registering with shard {}...", shard.addr);
-        client.register_client(req).await?;
+        let session_token = client
+            .register_client(req)
+            .await?
+            .into_inner()
+            .session_token;
         trace!("Registered with shard {}!", shard.addr);
-        clients.push(client);
+        clients.push((client, session_token));
     }
     Ok(clients)
 }
@@ -153,7 +199,6 @@ mod tests {
 
             let shard_groups: HashSet<Group> = shards
                 .iter()
-                .cloned()
                 .map(|node| match node.service {
                     Service::Worker(info) => info.group,
                     _ => { panic!("All shards should be workers."); }
@@ -173,7 +218,6 @@ mod tests {
             let shards = pick_worker_shards(nodes);
 
             let shard_groups: HashSet<Group> = shards.iter()
-                    .cloned()
                     .map(|node| match node.service {
                         Service::Worker(info) => info.group,
                         _ => { panic!("All shards should be workers."); }
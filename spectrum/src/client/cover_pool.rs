@@ -0,0 +1,76 @@
+use crate::proto;
+use crate::protocols::{wrapper::ProtocolWrapper, Protocol};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn cover<P>(protocol: &P) -> Vec<proto::WriteToken>
+where
+    P: Protocol,
+    P::WriteToken: Into<proto::WriteToken>,
+{
+    protocol.cover().into_iter().map(Into::into).collect()
+}
+
+/// A pool of pre-generated cover traffic, handed out round-robin.
+///
+/// Cover write tokens are all equivalent, so load tests can generate a few
+/// sets up-front and reuse them across many clients rather than paying for
+/// generation per client. Don't use this in a real deployment: workers can
+/// link clients that send the same token.
+#[derive(Debug)]
+pub struct CoverPool {
+    // One set of write tokens (one per group) per entry.
+    sets: Vec<Vec<proto::WriteToken>>,
+    next: AtomicUsize,
+}
+
+impl CoverPool {
+    /// Generate `size` sets of cover write tokens for `protocol`.
+    pub fn new(protocol: &ProtocolWrapper, size: usize) -> Self {
+        assert!(size >= 1, "Expected at least 1 set of cover tokens.");
+        let sets = (0..size)
+            .map(|_| match protocol {
+                ProtocolWrapper::Insecure(protocol) => cover(protocol),
+                ProtocolWrapper::Secure(protocol) => cover(protocol),
+                ProtocolWrapper::SecurePub(protocol) => cover(protocol),
+                ProtocolWrapper::SecureMultiKey(protocol) => cover(protocol),
+            })
+            .collect();
+        CoverPool {
+            sets,
+            next: Default::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    /// The next set of write tokens, cycling back to the start once exhausted.
+    pub fn next(&self) -> Vec<proto::WriteToken> {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.sets.len();
+        self.sets[idx].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycles() {
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 16, false);
+        let pool = CoverPool::new(&protocol, 3);
+        assert_eq!(pool.len(), 3);
+        let first: Vec<_> = (0..3).map(|_| pool.next()).collect();
+        for set in &first {
+            assert_eq!(set.len(), protocol.num_parties());
+        }
+        let second: Vec<_> = (0..3).map(|_| pool.next()).collect();
+        assert_eq!(first, second);
+    }
+}
@@ -1,2 +1,27 @@
+pub mod bench;
+mod channel_pool;
 mod connections;
+mod cover_pool;
 pub mod viewer;
+
+use crate::protocols::wrapper::{ChannelKeyWrapper, ProtocolWrapper};
+use crate::services::ClientInfo;
+use serde::Serialize;
+
+pub use crate::protocols::typed::{Error as MessageError, TypedProtocol};
+pub use channel_pool::{ChannelPool, ChannelPoolConfig};
+pub use cover_pool::CoverPool;
+
+/// A broadcaster that sends a typed message rather than raw bytes.
+///
+/// The message is serialized to fit the protocol's fixed-size channel slot;
+/// recover it with [`ProtocolWrapper::decode_message`].
+pub fn typed_broadcaster<M: Serialize>(
+    protocol: &ProtocolWrapper,
+    idx: u128,
+    message: &M,
+    key: ChannelKeyWrapper,
+) -> Result<ClientInfo, MessageError> {
+    let message = protocol.encode_message(message)?;
+    Ok(ClientInfo::new_broadcaster(idx, message, key))
+}
@@ -1,23 +1,29 @@
-use crate::proto::{self, UploadRequest};
+use crate::proto::{self, worker_client::WorkerClient, UploadRequest};
 use crate::{
-    client::connections,
+    client::{connections, ChannelPool, CoverPool},
     config,
+    experiment::HammerClient,
+    net::{tls::Certificate, ClientChannel},
     protocols::{wrapper::ChannelKeyWrapper, wrapper::ProtocolWrapper, Protocol},
     services::{
+        abort::{watch_for_abort, CancellationToken},
+        deadline::{self, Deadlines},
+        discovery::Discovery,
         quorum::{delay_until, wait_for_start_time_set},
         ClientInfo,
     },
 };
 use spectrum_primitives::Bytes;
 
+use crate::rt::{sleep, sleep_until, spawn};
 use config::store::Store;
 use futures::prelude::*;
 use futures::stream::FuturesUnordered;
 use log::{debug, error, info, trace, warn};
-use tokio::time::sleep;
-use tonic::transport::Certificate;
+use tonic::Code;
 
 use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
 use std::{
     convert::{TryFrom, TryInto},
@@ -26,17 +32,79 @@ use std::{
 
 type TokioError = Box<dyn std::error::Error + Sync + Send>;
 
+/// Cover traffic, from the pool if we have one.
+fn cover<P>(protocol: &P, pool: &Option<Arc<CoverPool>>) -> Vec<proto::WriteToken>
+where
+    P: Protocol,
+    P::WriteToken: Into<proto::WriteToken>,
+{
+    match pool {
+        Some(pool) => pool.next(),
+        None => protocol.cover().into_iter().map(Into::into).collect(),
+    }
+}
+
+/// Pad `msg` out to a whole channel slot (`payload_capacity()` bytes) with the
+/// rest of an empty slot: zeros, or identity elements for group-based
+/// protocols.
+fn pad<P>(protocol: &P, msg: Bytes) -> Bytes
+where
+    P: Protocol,
+    Bytes: TryFrom<P::Accumulator>,
+    <Bytes as TryFrom<P::Accumulator>>::Error: fmt::Debug,
+{
+    let capacity = protocol.payload_capacity();
+    assert!(msg.len() <= capacity, "Message doesn't fit in a channel.");
+    let empty = protocol.new_accumulator().remove(0);
+    let empty: Vec<u8> = Bytes::try_from(empty).unwrap().into();
+    let mut msg: Vec<u8> = msg.into();
+    msg.extend_from_slice(&empty[msg.len()..capacity]);
+    msg.into()
+}
+
+/// Upload to one worker (retrying until it goes through), returning the
+/// upload's sequence number (or `None` if the round was aborted).
+async fn upload(
+    mut client: WorkerClient<ClientChannel>,
+    request: UploadRequest,
+    cancel: CancellationToken,
+) -> Option<u64> {
+    let upload_timeout = Deadlines::default().upload;
+    let start_time = Instant::now();
+    loop {
+        let req = deadline::request(request.clone(), upload_timeout, None);
+        trace!("About to send upload request.");
+        match client.upload(req).await {
+            Ok(response) => {
+                info!("Request took {}ms.", start_time.elapsed().as_millis());
+                let response = response.into_inner();
+                debug!("RESPONSE={:?}", response);
+                return Some(response.upload_seq);
+            }
+            Err(err) if err.code() == Code::Aborted || cancel.is_cancelled() => {
+                warn!("Round aborted; giving up on upload.");
+                return None;
+            }
+            Err(err) => warn!("Error, trying again: {}", err),
+        };
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn inner_run<C, F, P>(
     config: C,
     protocol: P,
     info: ClientInfo,
-    hammer: bool,
+    hammer: Option<HammerClient>,
     cert: Option<Certificate>,
     max_jitter: u64,
+    cover_pool: Option<Arc<CoverPool>>,
+    channel_pool: Option<Arc<ChannelPool>>,
     shutdown: F,
 ) -> Result<(), TokioError>
 where
-    C: Store,
+    C: 'static + Store + Discovery + Clone,
     F: Future<Output = ()> + Send + 'static,
     P: Protocol,
     P::ChannelKey: TryFrom<ChannelKeyWrapper>,
@@ -52,10 +120,14 @@ where
     <Bytes as TryFrom<P::Accumulator>>::Error: fmt::Debug,
 {
     info!("Client starting");
+    let cancel = CancellationToken::default();
+    let abort_watcher = spawn(watch_for_abort(config.clone(), cancel.clone()));
     let start_time = wait_for_start_time_set(&config).await?;
     debug!("Received configuration from configuration server; initializing.");
 
-    let clients: Vec<_> = connections::connect_and_register(&config, info.clone(), cert).await?;
+    let clients: Vec<_> =
+        connections::connect_and_register(&config, info.clone(), cert, channel_pool.as_deref())
+            .await?;
     let client_id = info.to_proto(); // before we move info
 
     let jitter = Duration::from_millis(rand::random::<u64>() % max_jitter);
@@ -63,93 +135,167 @@ where
 
     {
         // free the write token memory after send!
-        let mut write_tokens = match info.broadcast {
+        let mut write_tokens: Vec<proto::WriteToken> = match info.broadcast {
             Some((msg, key)) => {
                 info!("Broadcaster about to send write token.");
                 debug!("Write token: msg.len()={}, key={:?}", msg.len(), key);
-                protocol.broadcast(
-                    msg.try_into().unwrap(),
-                    info.idx.try_into().expect("idx should be small"),
-                    key.try_into().unwrap(),
-                )
+                let msg = pad(&protocol, msg);
+                protocol
+                    .broadcast(
+                        msg.try_into().unwrap(),
+                        info.idx.try_into().expect("idx should be small"),
+                        key.try_into().unwrap(),
+                    )
+                    .into_iter()
+                    .map(Into::into)
+                    .collect()
             }
-            None => protocol.cover(),
+            None => cover(&protocol, &cover_pool),
         };
 
         delay_until(start_time).await;
         debug!("Client detected start time ready.");
+        let started = Instant::now();
 
         loop {
-            clients
-                .iter()
-                .cloned()
-                .zip(write_tokens.into_iter())
-                .map(|(mut client, write_token)| {
-                    let client_id = client_id.clone();
-                    let write_token = write_token.into();
-                    tokio::spawn(async move {
-                        let response;
-                        let start_time = Instant::now();
-                        loop {
-                            let req = tonic::Request::new(UploadRequest {
-                                client_id: Some(client_id.clone()),
-                                write_token: Some(write_token.clone()),
-                            });
-                            trace!("About to send upload request.");
-                            {
-                                match client.upload(req).await {
-                                    Ok(r) => {
-                                        response = r;
-                                        break;
-                                    }
-                                    Err(err) => warn!("Error, trying again: {}", err),
-                                };
-                            }
-                            sleep(Duration::from_millis(100)).await;
-                        }
-                        info!("Request took {}ms.", start_time.elapsed().as_millis());
-                        debug!("RESPONSE={:?}", response.into_inner());
-                    })
+            let next_upload = hammer.and_then(|h| h.interval).map(|i| Instant::now() + i);
+            let mut uploads = clients.iter().cloned().zip(write_tokens).map(
+                |((client, session_token), write_token)| {
+                    let request = UploadRequest {
+                        client_id: Some(client_id.clone()),
+                        write_token: Some(write_token),
+                        session_token,
+                        upload_seq: 0,
+                    };
+                    (client, request)
+                },
+            );
+            // The first worker assigns the upload a sequence number, which the
+            // others use to keep its audit shares apart from any retries.
+            let (first, request) = uploads.next().expect("should have a worker");
+            let upload_seq = match upload(first, request, cancel.clone()).await {
+                Some(upload_seq) => upload_seq,
+                None => break,
+            };
+            uploads
+                .map(|(client, request)| {
+                    spawn(upload(
+                        client,
+                        UploadRequest {
+                            upload_seq,
+                            ..request
+                        },
+                        cancel.clone(),
+                    ))
                 })
                 .collect::<FuturesUnordered<_>>()
                 .inspect_err(|err| error!("{:?}", err))
                 .try_collect::<Vec<_>>()
                 .await
                 .expect("tokio spawn should succeed");
-            if !hammer {
+            let hammer = match hammer {
+                Some(hammer) => hammer,
+                None => break,
+            };
+            if matches!(hammer.duration, Some(duration) if started.elapsed() >= duration) {
+                info!("Hammer duration elapsed; done uploading.");
+                break;
+            }
+            if cancel.is_cancelled() {
                 break;
             }
-            write_tokens = protocol.cover();
+            if let Some(next_upload) = next_upload {
+                sleep_until(next_upload).await;
+            }
+            write_tokens = cover(&protocol, &cover_pool);
         }
     }
 
-    shutdown.await;
+    // Don't wait around for the others if the round was aborted.
+    future::select(
+        Box::pin(shutdown),
+        Box::pin(async {
+            cancel.cancelled().await;
+        }),
+    )
+    .await;
+    abort_watcher.abort();
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run<C, F>(
     config: C,
     protocol: ProtocolWrapper,
     info: ClientInfo,
-    hammer: bool,
+    hammer: Option<HammerClient>,
     cert: Option<Certificate>,
     max_jitter: u64,
+    cover_pool: Option<Arc<CoverPool>>,
+    channel_pool: Option<Arc<ChannelPool>>,
     shutdown: F,
 ) -> Result<(), TokioError>
 where
-    C: Store,
+    C: 'static + Store + Discovery + Clone,
     F: Future<Output = ()> + Send + 'static,
 {
     match protocol {
+        ProtocolWrapper::Insecure(protocol) => {
+            inner_run(
+                config,
+                protocol,
+                info,
+                hammer,
+                cert,
+                max_jitter,
+                cover_pool,
+                channel_pool,
+                shutdown,
+            )
+            .await?;
+        }
         ProtocolWrapper::Secure(protocol) => {
-            inner_run(config, protocol, info, hammer, cert, max_jitter, shutdown).await?;
+            inner_run(
+                config,
+                protocol,
+                info,
+                hammer,
+                cert,
+                max_jitter,
+                cover_pool,
+                channel_pool,
+                shutdown,
+            )
+            .await?;
         }
         ProtocolWrapper::SecurePub(protocol) => {
-            inner_run(config, protocol, info, hammer, cert, max_jitter, shutdown).await?;
+            inner_run(
+                config,
+                protocol,
+                info,
+                hammer,
+                cert,
+                max_jitter,
+                cover_pool,
+                channel_pool,
+                shutdown,
+            )
+            .await?;
         }
         ProtocolWrapper::SecureMultiKey(protocol) => {
-            inner_run(config, protocol, info, hammer, cert, max_jitter, shutdown).await?;
+            inner_run(
+                config,
+                protocol,
+                info,
+                hammer,
+                cert,
+                max_jitter,
+                cover_pool,
+                channel_pool,
+                shutdown,
+            )
+            .await?;
         }
     }
     Ok(())
@@ -6,20 +6,22 @@ use crate::config::{
 };
 use crate::net::Config as NetConfig;
 
+use crate::rt::{sleep, Child, Command};
 use derivative::Derivative;
-use etcd_rs::{Client, ClientConfig, KeyRange, PutRequest, RangeRequest};
+use etcd_rs::{
+    Client, ClientConfig, DeleteRequest, KeyRange, PutRequest, RangeRequest, TxnCmp, TxnRequest,
+};
 use log::debug;
 use tempfile::TempDir;
-use tokio::{
-    process::{Child, Command},
-    time::sleep,
-};
 use tonic::async_trait;
 
 use std::ffi::OsStr;
 use std::process::Stdio;
 use std::time::Duration;
 
+// etcd's default limit (`--max-txn-ops`) on operations per transaction.
+const MAX_TXN_OPS: usize = 128;
+
 #[derive(Derivative, Clone)]
 #[derivative(Debug)]
 pub struct EtcdStore {
@@ -63,11 +65,11 @@ pub struct Runner {
     /// The address on which etcd listens for clients (as "host:port" string).
     addr: String,
     /// Temporary directory for etcd data.
-    data_dir: TempDir,
+    _data_dir: TempDir,
     /// The etcd process.
     ///
     /// When dropped, will kill this process.
-    process: Child,
+    _process: Child,
 }
 
 impl Runner {
@@ -103,15 +105,15 @@ impl Runner {
 
         Ok(Runner {
             addr: client_addr.public_addr(),
-            data_dir: temp_dir,
-            process,
+            _data_dir: temp_dir,
+            _process: process,
         })
     }
 
     pub async fn get_store(&self) -> Result<EtcdStore, Error> {
-        Ok(EtcdStore::connect(format!("http://{}", self.addr))
+        EtcdStore::connect(format!("http://{}", self.addr))
             .await
-            .map_err(|e| Error::from(e.to_string()))?)
+            .map_err(|e| Error::from(e.to_string()))
     }
 
     // Returns
@@ -151,6 +153,38 @@ impl Store for EtcdStore {
         Ok(())
     }
 
+    // Atomic within each chunk of `MAX_TXN_OPS`, but not across chunks.
+    async fn put_batch(&self, entries: Vec<(Key, Value)>) -> Result<(), Error> {
+        for chunk in entries.chunks(MAX_TXN_OPS) {
+            let txn = chunk.iter().fold(TxnRequest::new(), |txn, (key, value)| {
+                txn.and_then(PutRequest::new(key.join("/"), value.clone()))
+            });
+            self.client.kv().txn(txn).await.map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    async fn put_if_absent(&self, key: Key, value: Value) -> Result<bool, Error> {
+        let key = key.join("/");
+        // Version 0 means the key doesn't exist.
+        let txn = TxnRequest::new()
+            .when_version(KeyRange::key(key.clone()), TxnCmp::Equal, 0)
+            .and_then(PutRequest::new(key, value));
+        let response = self.client.kv().txn(txn).await.map_err(|e| e.to_string())?;
+        Ok(response.is_success())
+    }
+
+    async fn compare_and_swap(&self, key: Key, current: Value, new: Value) -> Result<bool, Error> {
+        let key = key.join("/");
+        // A missing key never compares equal.
+        let txn = TxnRequest::new()
+            .when_version(KeyRange::key(key.clone()), TxnCmp::Greater, 0)
+            .when_value(KeyRange::key(key.clone()), TxnCmp::Equal, current)
+            .and_then(PutRequest::new(key, new));
+        let response = self.client.kv().txn(txn).await.map_err(|e| e.to_string())?;
+        Ok(response.is_success())
+    }
+
     async fn list(&self, prefix: Key) -> Result<Vec<(Key, Value)>, Error> {
         let prefix = prefix.join("/") + "/";
         let range = KeyRange::prefix(prefix);
@@ -165,16 +199,28 @@ impl Store for EtcdStore {
             .into_iter()
             .map(|kv| {
                 (
-                    kv.key_str()
-                        .to_string()
-                        .split('/')
-                        .map(ToString::to_string)
-                        .collect(),
+                    kv.key_str().split('/').map(ToString::to_string).collect(),
                     kv.value_str().to_string(),
                 )
             })
             .collect())
     }
+
+    // Not atomic: the key itself goes first, then the ones under it.
+    async fn delete_prefix(&self, prefix: Key) -> Result<usize, Error> {
+        let key = prefix.join("/");
+        let mut deleted = 0;
+        for range in [KeyRange::key(key.clone()), KeyRange::prefix(key + "/")] {
+            let response = self
+                .client
+                .kv()
+                .delete(DeleteRequest::new(range))
+                .await
+                .map_err(|e| e.to_string())?;
+            deleted += response.count_deleted();
+        }
+        Ok(deleted)
+    }
 }
 
 #[cfg(all(test, feature = "etcd-tests"))]
@@ -182,8 +228,7 @@ mod tests {
     use super::*;
     use crate::config::store::tests::*;
 
-    use etcd_rs::DeleteRequest;
-    use proptest::collection::hash_set;
+    use proptest::collection::{hash_map, hash_set};
     use proptest::test_runner::TestRunner;
 
     /// Clear the etcd store between test runs.
@@ -248,6 +293,62 @@ mod tests {
             .unwrap()
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_put_batch() {
+        let wrapper = Runner::create().await.unwrap();
+        let store = wrapper.get_store().await.unwrap();
+
+        TestRunner::default()
+            .run(
+                &(
+                    hash_map(keys(), values(), 0..10usize),
+                    keys(),
+                    values(),
+                    values(),
+                ),
+                |(entries, repeated, value1, value2)| {
+                    futures::executor::block_on(async {
+                        clear(store.client.clone()).await?;
+                        run_test_put_batch(store.clone(), entries, repeated, value1, value2).await
+                    })
+                },
+            )
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_put_if_absent() {
+        let wrapper = Runner::create().await.unwrap();
+        let store = wrapper.get_store().await.unwrap();
+
+        TestRunner::default()
+            .run(&(keys(), values(), values()), |(key, value1, value2)| {
+                futures::executor::block_on(async {
+                    clear(store.client.clone()).await?;
+                    run_test_put_if_absent(store.clone(), key, value1, value2).await
+                })
+            })
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_compare_and_swap() {
+        let wrapper = Runner::create().await.unwrap();
+        let store = wrapper.get_store().await.unwrap();
+
+        TestRunner::default()
+            .run(
+                &(keys(), values(), values(), values()),
+                |(key, value1, value2, value3)| {
+                    futures::executor::block_on(async {
+                        clear(store.client.clone()).await?;
+                        run_test_compare_and_swap(store.clone(), key, value1, value2, value3).await
+                    })
+                },
+            )
+            .unwrap()
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_list() {
         let wrapper = Runner::create().await.unwrap();
@@ -270,4 +371,28 @@ mod tests {
             )
             .unwrap()
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_delete_prefix() {
+        let wrapper = Runner::create().await.unwrap();
+        let store = wrapper.get_store().await.unwrap();
+
+        TestRunner::default()
+            .run(
+                &(
+                    keys(),
+                    hash_set(keys(), 0..10usize),
+                    hash_set(keys(), 0..10usize),
+                    values(),
+                ),
+                |(prefix, suffixes, other_keys, value)| {
+                    futures::executor::block_on(async {
+                        clear(store.client.clone()).await?;
+                        run_test_delete_prefix(store.clone(), prefix, suffixes, other_keys, value)
+                            .await
+                    })
+                },
+            )
+            .unwrap()
+    }
 }
@@ -1,6 +1,7 @@
+#[cfg(feature = "etcd")]
+use crate::config::etcd::EtcdStore;
 use crate::{
     config::{
-        etcd::EtcdStore,
         inmem::InMemoryStore,
         store::{Key, Store, Value},
     },
@@ -13,6 +14,7 @@ pub static CONFIG_SERVER_ENV_VAR: &str = "SPECTRUM_CONFIG_SERVER";
 #[derive(Clone, Debug)]
 pub enum Wrapper {
     InMem(InMemoryStore),
+    #[cfg(feature = "etcd")]
     Etcd(EtcdStore),
 }
 
@@ -22,6 +24,7 @@ impl From<InMemoryStore> for Wrapper {
     }
 }
 
+#[cfg(feature = "etcd")]
 impl From<EtcdStore> for Wrapper {
     fn from(store: EtcdStore) -> Self {
         Self::Etcd(store)
@@ -33,6 +36,7 @@ impl Store for Wrapper {
     async fn get(&self, key: Key) -> Result<Option<Value>, Error> {
         match self {
             Wrapper::InMem(store) => store.get(key).await,
+            #[cfg(feature = "etcd")]
             Wrapper::Etcd(store) => store.get(key).await,
         }
     }
@@ -40,16 +44,50 @@ impl Store for Wrapper {
     async fn put(&self, key: Key, value: Value) -> Result<(), Error> {
         match self {
             Wrapper::InMem(store) => store.put(key, value).await,
+            #[cfg(feature = "etcd")]
             Wrapper::Etcd(store) => store.put(key, value).await,
         }
     }
 
+    async fn put_batch(&self, entries: Vec<(Key, Value)>) -> Result<(), Error> {
+        match self {
+            Wrapper::InMem(store) => store.put_batch(entries).await,
+            #[cfg(feature = "etcd")]
+            Wrapper::Etcd(store) => store.put_batch(entries).await,
+        }
+    }
+
+    async fn put_if_absent(&self, key: Key, value: Value) -> Result<bool, Error> {
+        match self {
+            Wrapper::InMem(store) => store.put_if_absent(key, value).await,
+            #[cfg(feature = "etcd")]
+            Wrapper::Etcd(store) => store.put_if_absent(key, value).await,
+        }
+    }
+
+    async fn compare_and_swap(&self, key: Key, current: Value, new: Value) -> Result<bool, Error> {
+        match self {
+            Wrapper::InMem(store) => store.compare_and_swap(key, current, new).await,
+            #[cfg(feature = "etcd")]
+            Wrapper::Etcd(store) => store.compare_and_swap(key, current, new).await,
+        }
+    }
+
     async fn list(&self, prefix: Key) -> Result<Vec<(Key, Value)>, Error> {
         match self {
             Wrapper::InMem(store) => store.list(prefix).await,
+            #[cfg(feature = "etcd")]
             Wrapper::Etcd(store) => store.list(prefix).await,
         }
     }
+
+    async fn delete_prefix(&self, prefix: Key) -> Result<usize, Error> {
+        match self {
+            Wrapper::InMem(store) => store.delete_prefix(prefix).await,
+            #[cfg(feature = "etcd")]
+            Wrapper::Etcd(store) => store.delete_prefix(prefix).await,
+        }
+    }
 }
 
 pub async fn from_string(s: &str) -> Result<Wrapper, String> {
@@ -79,6 +117,7 @@ pub async fn from_string(s: &str) -> Result<Wrapper, String> {
                 ))
             }
         }
+        #[cfg(feature = "etcd")]
         "etcd" => {
             let endpoint = format!("http://{}", remainder);
             EtcdStore::connect(endpoint)
@@ -86,6 +125,8 @@ pub async fn from_string(s: &str) -> Result<Wrapper, String> {
                 .map(Wrapper::from)
                 .map_err(|e| e.to_string())
         }
+        #[cfg(not(feature = "etcd"))]
+        "etcd" => Err("Built without etcd support (the `etcd` feature).".to_string()),
         _ => Err(format!(
             "Unrecognized config server specification [{}]. \
              Expected [mem://] or [etcd://].",
@@ -142,6 +183,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "etcd")]
     #[tokio::test]
     async fn test_from_string_etcd() {
         from_string("etcd://127.0.0.1:2379")
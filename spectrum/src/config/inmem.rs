@@ -27,6 +27,32 @@ impl Store for InMemoryStore {
         Ok(())
     }
 
+    async fn put_batch(&self, entries: Vec<(Key, Value)>) -> Result<(), Error> {
+        let mut map = self.map.lock().unwrap();
+        map.extend(entries);
+        Ok(())
+    }
+
+    async fn put_if_absent(&self, key: Key, value: Value) -> Result<bool, Error> {
+        let mut map = self.map.lock().unwrap();
+        if map.contains_key(&key) {
+            return Ok(false);
+        }
+        map.insert(key, value);
+        Ok(true)
+    }
+
+    async fn compare_and_swap(&self, key: Key, current: Value, new: Value) -> Result<bool, Error> {
+        let mut map = self.map.lock().unwrap();
+        match map.get_mut(&key) {
+            Some(value) if *value == current => {
+                *value = new;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     async fn list(&self, prefix: Key) -> Result<Vec<(Key, Value)>, Error> {
         let map = self.map.lock().unwrap();
         let mut res = Vec::new();
@@ -37,6 +63,13 @@ impl Store for InMemoryStore {
         }
         Ok(res)
     }
+
+    async fn delete_prefix(&self, prefix: Key) -> Result<usize, Error> {
+        let mut map = self.map.lock().unwrap();
+        let before = map.len();
+        map.retain(|key, _| !key.starts_with(&prefix));
+        Ok(before - map.len())
+    }
 }
 
 #[cfg(test)]
@@ -45,7 +78,7 @@ pub mod tests {
     use crate::config::store::tests::*;
 
     use futures::executor::block_on;
-    use proptest::collection::hash_set;
+    use proptest::collection::{hash_map, hash_set};
     use proptest::prelude::*;
     use proptest::strategy::LazyJust;
 
@@ -77,6 +110,42 @@ pub mod tests {
             block_on(test).unwrap()
         }
 
+        #[test]
+        fn test_put_batch(
+            store in stores(),
+            entries in hash_map(keys(), values(), 0..10usize),
+            repeated in keys(),
+            value1 in values(),
+            value2 in values()
+        ) {
+            let test = run_test_put_batch(store, entries, repeated, value1, value2);
+            block_on(test).unwrap()
+        }
+
+        #[test]
+        fn test_put_if_absent(
+            store in stores(),
+            key in keys(),
+            value1 in values(),
+            value2 in values()
+        ) {
+            let test = run_test_put_if_absent(store, key, value1, value2);
+            block_on(test).unwrap()
+        }
+
+        #[test]
+        fn test_compare_and_swap(
+            store in stores(),
+            key in keys(),
+            value1 in values(),
+            value2 in values(),
+            value3 in values()
+        ) {
+            let test = run_test_compare_and_swap(store, key, value1, value2, value3);
+            // `?` rather than `unwrap()`: the test may reject its inputs.
+            block_on(test)?;
+        }
+
         #[test]
         fn test_list(
             store in stores(),
@@ -88,5 +157,17 @@ pub mod tests {
             let test = run_test_list(store, prefix, suffixes, other_keys, value);
             block_on(test).unwrap()
         }
+
+        #[test]
+        fn test_delete_prefix(
+            store in stores(),
+            prefix in keys(),
+            suffixes in hash_set(keys(), 0..10usize),
+            other_keys in hash_set(keys(), 0..10usize),
+            value in values()
+        ) {
+            let test = run_test_delete_prefix(store, prefix, suffixes, other_keys, value);
+            block_on(test).unwrap()
+        }
     }
 }
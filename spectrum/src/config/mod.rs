@@ -1,8 +1,10 @@
+#[cfg(feature = "etcd")]
 mod etcd;
 pub mod factory;
 mod inmem;
 pub mod store;
 
+#[cfg(feature = "etcd")]
 pub use etcd::Runner as EtcdRunner;
 pub use factory::{from_env, from_string};
 pub use store::{Key, Store, Value};
@@ -13,7 +13,33 @@ pub trait Store: std::fmt::Debug {
 
     async fn put(&self, key: Key, value: Value) -> Result<(), Error>;
 
+    /// Put all of `entries`, in one round trip where the backend supports it.
+    ///
+    /// Later entries win if a key repeats.
+    async fn put_batch(&self, entries: Vec<(Key, Value)>) -> Result<(), Error> {
+        for (key, value) in entries {
+            self.put(key, value).await?;
+        }
+        Ok(())
+    }
+
+    /// Put `value` at `key` if (and only if) `key` has no value yet, atomically.
+    ///
+    /// Returns whether the put happened.
+    async fn put_if_absent(&self, key: Key, value: Value) -> Result<bool, Error>;
+
+    /// Replace the value at `key` with `new` if (and only if) it's still
+    /// `current`, atomically.
+    ///
+    /// Returns whether the swap happened.
+    async fn compare_and_swap(&self, key: Key, current: Value, new: Value) -> Result<bool, Error>;
+
     async fn list(&self, prefix: Key) -> Result<Vec<(Key, Value)>, Error>;
+
+    /// Delete `prefix` and every key under it.
+    ///
+    /// Returns how many keys were deleted.
+    async fn delete_prefix(&self, prefix: Key) -> Result<usize, Error>;
 }
 
 #[cfg(test)]
@@ -22,7 +48,7 @@ pub(in crate::config) mod tests {
     use proptest::collection::{vec, VecStrategy};
     use proptest::prelude::*;
     use proptest::string::{string_regex, RegexGeneratorStrategy};
-    use std::collections::HashSet;
+    use std::collections::{HashMap, HashSet};
 
     type TestResult = Result<(), proptest::test_runner::TestCaseError>;
 
@@ -59,6 +85,76 @@ pub(in crate::config) mod tests {
         Ok(())
     }
 
+    pub async fn run_test_put_batch<C: Store + Sync>(
+        store: C,
+        entries: HashMap<Key, Value>,
+        repeated: Key,
+        value1: Value,
+        value2: Value,
+    ) -> TestResult {
+        let mut batch: Vec<(Key, Value)> = entries.clone().into_iter().collect();
+        batch.push((repeated.clone(), value1));
+        batch.push((repeated.clone(), value2.clone()));
+        store.put_batch(batch).await?;
+
+        for (key, value) in entries {
+            if key != repeated {
+                prop_assert_eq!(store.get(key).await?, Some(value));
+            }
+        }
+        prop_assert_eq!(store.get(repeated).await?, Some(value2));
+        store.put_batch(vec![]).await?;
+        Ok(())
+    }
+
+    pub async fn run_test_put_if_absent<C: Store>(
+        store: C,
+        key: Key,
+        value1: Value,
+        value2: Value,
+    ) -> TestResult {
+        prop_assert!(store.put_if_absent(key.clone(), value1.clone()).await?);
+        prop_assert!(!store.put_if_absent(key.clone(), value2.clone()).await?);
+        prop_assert_eq!(store.get(key.clone()).await?, Some(value1));
+
+        // Plain puts still overwrite.
+        store.put(key.clone(), value2.clone()).await?;
+        prop_assert_eq!(store.get(key).await?, Some(value2));
+        Ok(())
+    }
+
+    pub async fn run_test_compare_and_swap<C: Store>(
+        store: C,
+        key: Key,
+        value1: Value,
+        value2: Value,
+        value3: Value,
+    ) -> TestResult {
+        prop_assume!(value1 != value2);
+        // Nothing to compare against yet.
+        prop_assert!(
+            !store
+                .compare_and_swap(key.clone(), value1.clone(), value2.clone())
+                .await?
+        );
+        prop_assert!(store.get(key.clone()).await?.is_none());
+
+        store.put(key.clone(), value1.clone()).await?;
+        prop_assert!(
+            !store
+                .compare_and_swap(key.clone(), value2.clone(), value3.clone())
+                .await?
+        );
+        prop_assert_eq!(store.get(key.clone()).await?, Some(value1.clone()));
+        prop_assert!(
+            store
+                .compare_and_swap(key.clone(), value1, value3.clone())
+                .await?
+        );
+        prop_assert_eq!(store.get(key).await?, Some(value3));
+        Ok(())
+    }
+
     pub async fn run_test_list<C: Store>(
         store: C,
         prefix: Key,
@@ -96,4 +192,39 @@ pub(in crate::config) mod tests {
 
         Ok(())
     }
+
+    pub async fn run_test_delete_prefix<C: Store>(
+        store: C,
+        prefix: Key,
+        suffixes: HashSet<Key>,
+        other_keys: HashSet<Key>,
+        value: Value,
+    ) -> TestResult {
+        store.put(prefix.clone(), value.clone()).await?;
+        for suffix in &suffixes {
+            let key: Key = prefix
+                .iter()
+                .cloned()
+                .chain(suffix.iter().cloned())
+                .collect();
+            store.put(key, value.clone()).await?;
+        }
+        let other_keys: HashSet<Key> = other_keys
+            .into_iter()
+            .filter(|key| !key.starts_with(&prefix))
+            .collect();
+        for key in &other_keys {
+            store.put(key.clone(), value.clone()).await?;
+        }
+
+        let deleted = store.delete_prefix(prefix.clone()).await?;
+        prop_assert_eq!(deleted, suffixes.len() + 1);
+        prop_assert!(store.get(prefix.clone()).await?.is_none());
+        prop_assert!(store.list(prefix.clone()).await?.is_empty());
+        for key in other_keys {
+            prop_assert_eq!(store.get(key).await?, Some(value.clone()));
+        }
+        prop_assert_eq!(store.delete_prefix(prefix).await?, 0);
+        Ok(())
+    }
 }
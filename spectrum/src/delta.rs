@@ -0,0 +1,264 @@
+//! Shipping shares as differences from the previous round's.
+//!
+//! When rounds repeat with mostly identical traffic, consecutive aggregates
+//! from a leader are mostly identical too. In delta mode, the leader sends
+//! only the nonzero runs of (this round's share XOR the last share the
+//! publisher acknowledged), and the publisher XORs them back onto its copy.
+//! This works on the encoded bytes, so it's lossless for any accumulator
+//! type; it only saves bandwidth if the shares actually repeat.
+//!
+//! If the publisher doesn't have the base (e.g. it restarted), it rejects the
+//! delta with `FAILED_PRECONDITION` and the leader resends the full share.
+use crate::proto::{share_delta::Chunk, Share, ShareDelta};
+
+use crate::rt::sync::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use tonic::Status;
+
+// Zero runs shorter than this don't get their own chunk (a chunk costs a few
+// bytes of framing).
+const MIN_GAP: usize = 8;
+
+/// The difference from `base` to `share`, if they're the same shape.
+pub fn diff(base_round: u64, base: &Share, share: &Share) -> Option<ShareDelta> {
+    if base.data.len() != share.data.len() {
+        return None;
+    }
+    let mut chunks = Vec::new();
+    for (element, (base, current)) in base.data.iter().zip(share.data.iter()).enumerate() {
+        if base.len() != current.len() {
+            return None;
+        }
+        let xor: Vec<u8> = base
+            .iter()
+            .zip(current.iter())
+            .map(|(b, c)| b ^ c)
+            .collect();
+        let mut idx = 0;
+        while idx < xor.len() {
+            if xor[idx] == 0 {
+                idx += 1;
+                continue;
+            }
+            let start = idx;
+            let mut end = idx;
+            while idx < xor.len() && idx - end < MIN_GAP {
+                if xor[idx] != 0 {
+                    end = idx + 1;
+                }
+                idx += 1;
+            }
+            chunks.push(Chunk {
+                element: element as u32,
+                offset: start as u32,
+                data: xor[start..end].to_vec(),
+            });
+        }
+    }
+    Some(ShareDelta { base_round, chunks })
+}
+
+/// Reconstruct a share from its `base` and `delta`.
+pub fn apply(base: &Share, delta: &ShareDelta) -> Result<Share, Status> {
+    let mut share = base.clone();
+    for chunk in &delta.chunks {
+        let element = share
+            .data
+            .get_mut(chunk.element as usize)
+            .ok_or_else(|| Status::invalid_argument("Delta chunk element out of range."))?;
+        let start = chunk.offset as usize;
+        let target = start
+            .checked_add(chunk.data.len())
+            .and_then(|end| element.get_mut(start..end))
+            .ok_or_else(|| Status::invalid_argument("Delta chunk out of range."))?;
+        target
+            .iter_mut()
+            .zip(chunk.data.iter())
+            .for_each(|(t, c)| *t ^= c);
+    }
+    Ok(share)
+}
+
+/// What to send for a round: the full share or a delta.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    Full(Share),
+    Delta(ShareDelta),
+}
+
+/// Sender side (one per receiver): remembers the last acknowledged share.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    acked: Mutex<Option<(u64, Share)>>,
+}
+
+impl Encoder {
+    /// The payload for `share`: a delta if the receiver has a usable base.
+    pub async fn encode(&self, share: &Share) -> Payload {
+        if let Some((round, base)) = self.acked.lock().await.as_ref() {
+            if let Some(delta) = diff(*round, base, share) {
+                return Payload::Delta(delta);
+            }
+        }
+        Payload::Full(share.clone())
+    }
+
+    /// Record that the receiver has `share` for `round`.
+    pub async fn acked(&self, round: u64, share: Share) {
+        self.acked.lock().await.replace((round, share));
+    }
+
+    /// Forget the base (e.g. after the receiver lost it).
+    pub async fn reset(&self) {
+        self.acked.lock().await.take();
+    }
+}
+
+/// Receiver side: the latest share from each sender.
+#[derive(Debug)]
+pub struct Decoder<K> {
+    latest: Mutex<HashMap<K, (u64, Share)>>,
+}
+
+impl<K> Default for Decoder<K> {
+    fn default() -> Self {
+        Decoder {
+            latest: Default::default(),
+        }
+    }
+}
+
+impl<K: Eq + Hash> Decoder<K> {
+    /// The full share `sender` sent for `round`.
+    pub async fn decode(&self, sender: K, round: u64, payload: Payload) -> Result<Share, Status> {
+        let mut latest = self.latest.lock().await;
+        let share = match payload {
+            Payload::Full(share) => share,
+            Payload::Delta(delta) => match latest.get(&sender) {
+                Some((base_round, base)) if *base_round == delta.base_round => apply(base, &delta)?,
+                _ => {
+                    return Err(Status::failed_precondition(format!(
+                        "Missing base share (round {}) for delta.",
+                        delta.base_round
+                    )))
+                }
+            },
+        };
+        latest.insert(sender, (round, share.clone()));
+        Ok(share)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn shares() -> impl Strategy<Value = (Share, Share)> {
+        (1..4usize, 0..64usize).prop_flat_map(|(elements, len)| {
+            let share = || vec(vec(any::<u8>(), len), elements).prop_map(|data| Share { data });
+            (share(), share())
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_diff_apply((base, share) in shares()) {
+            let delta = diff(0, &base, &share).unwrap();
+            prop_assert_eq!(apply(&base, &delta).unwrap(), share);
+        }
+    }
+
+    #[test]
+    fn test_diff_identical_is_empty() {
+        let share = Share {
+            data: vec![vec![1; 100], vec![2; 100]],
+        };
+        assert!(diff(0, &share, &share).unwrap().chunks.is_empty());
+    }
+
+    #[test]
+    fn test_diff_is_sparse() {
+        let base = Share {
+            data: vec![vec![0; 1000]],
+        };
+        let mut share = base.clone();
+        share.data[0][10] = 1;
+        share.data[0][12] = 1;
+        share.data[0][500] = 1;
+        let delta = diff(0, &base, &share).unwrap();
+        assert_eq!(delta.chunks.len(), 2);
+        assert_eq!(delta.chunks[0].data, vec![1, 0, 1]);
+        assert_eq!(delta.chunks[1].offset, 500);
+    }
+
+    #[test]
+    fn test_diff_shape_mismatch() {
+        let base = Share {
+            data: vec![vec![0; 10]],
+        };
+        let longer = Share {
+            data: vec![vec![0; 11]],
+        };
+        assert_eq!(diff(0, &base, &longer), None);
+        let more = Share {
+            data: vec![vec![0; 10], vec![0; 10]],
+        };
+        assert_eq!(diff(0, &base, &more), None);
+    }
+
+    #[test]
+    fn test_apply_out_of_range() {
+        let base = Share {
+            data: vec![vec![0; 10]],
+        };
+        for (element, offset, len) in &[(1, 0, 1), (0, 9, 2), (0, u32::MAX, 1)] {
+            let delta = ShareDelta {
+                base_round: 0,
+                chunks: vec![Chunk {
+                    element: *element,
+                    offset: *offset,
+                    data: vec![1; *len],
+                }],
+            };
+            let err = apply(&base, &delta).unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_encoder_decoder() {
+        let encoder = Encoder::default();
+        let decoder = Decoder::default();
+        let round0 = Share {
+            data: vec![vec![1, 2, 3]],
+        };
+        let round1 = Share {
+            data: vec![vec![1, 2, 4]],
+        };
+        block_on(async {
+            // Nothing acknowledged yet, so the full share.
+            let payload = encoder.encode(&round0).await;
+            assert_eq!(payload, Payload::Full(round0.clone()));
+            assert_eq!(decoder.decode("leader", 0, payload).await.unwrap(), round0);
+            encoder.acked(0, round0).await;
+
+            let payload = encoder.encode(&round1).await;
+            assert!(matches!(payload, Payload::Delta(_)));
+            assert_eq!(
+                decoder.decode("leader", 1, payload.clone()).await.unwrap(),
+                round1
+            );
+
+            // A receiver without the base rejects the delta.
+            let err = Decoder::default()
+                .decode("leader", 1, payload)
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+        });
+    }
+}
@@ -1,24 +1,401 @@
 use crate::config::store::{Error, Store};
 use crate::protocols::wrapper::{ChannelKeyWrapper, ProtocolWrapper};
+use crate::services::budget::StageBudgets;
+use crate::services::checksum::{self, CHECKSUM_LEN};
+use crate::services::manifest::ExpectedTraffic;
+use crate::services::privacy::PrivacyBudget;
+use crate::services::reservation::ReservationRound;
 use crate::services::{ClientInfo, Group, LeaderInfo, PublisherInfo, Service, WorkerInfo};
 
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
+use std::cmp::max;
 use std::convert::TryInto;
-use std::iter::{once, IntoIterator};
+use std::time::Duration;
 
-// TODO: properly serialize protocol details
+// The AES PRG can't expand to fewer bytes than its seed (16 bytes).
+const MIN_MSG_SIZE: usize = 16;
+
+/// A multi-key protocol's parameters, and the seed its generators come from.
+///
+/// The generators are big, so experiments store this instead of the protocol
+/// (see [`ProtocolConfig::sample_keys_from_seed`]), and every process derives
+/// the same protocol from it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolSeed {
+    pub groups: usize,
+    pub channels: usize,
+    pub msg_size: usize,
+    pub seed: [u8; 32],
+}
+
+impl ProtocolSeed {
+    pub fn random(groups: usize, channels: usize, msg_size: usize) -> Self {
+        ProtocolSeed {
+            groups,
+            channels,
+            msg_size,
+            seed: thread_rng().gen(),
+        }
+    }
+
+    pub fn protocol(&self) -> ProtocolWrapper {
+        ProtocolWrapper::multi_key_from_seed(self.groups, self.channels, self.msg_size, self.seed)
+    }
+}
+
+/// The protocol (and its parameters) and a key for each channel.
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub struct Experiment {
+#[serde(into = "StoredProtocolConfig", from = "StoredProtocolConfig")]
+pub struct ProtocolConfig {
     protocol: ProtocolWrapper,
+    keys: Vec<ChannelKeyWrapper>,
+    // Set if `protocol` came from a seed (which gets stored instead).
+    seed: Option<ProtocolSeed>,
+}
+
+// How a `ProtocolConfig` is stored.
+#[derive(Serialize, Deserialize)]
+struct StoredProtocolConfig {
+    protocol: StoredProtocol,
+    keys: Vec<ChannelKeyWrapper>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredProtocol {
+    Seeded { seeded_multi_key: ProtocolSeed },
+    Full(ProtocolWrapper),
+}
+
+impl From<ProtocolConfig> for StoredProtocolConfig {
+    fn from(config: ProtocolConfig) -> Self {
+        let protocol = match config.seed {
+            Some(seed) => StoredProtocol::Seeded {
+                seeded_multi_key: seed,
+            },
+            None => StoredProtocol::Full(config.protocol),
+        };
+        StoredProtocolConfig {
+            protocol,
+            keys: config.keys,
+        }
+    }
+}
+
+impl From<StoredProtocolConfig> for ProtocolConfig {
+    fn from(stored: StoredProtocolConfig) -> Self {
+        let (protocol, seed) = match stored.protocol {
+            StoredProtocol::Seeded { seeded_multi_key } => {
+                (seeded_multi_key.protocol(), Some(seeded_multi_key))
+            }
+            StoredProtocol::Full(protocol) => (protocol, None),
+        };
+        ProtocolConfig {
+            protocol,
+            keys: stored.keys,
+            seed,
+        }
+    }
+}
+
+impl ProtocolConfig {
+    pub fn new(protocol: ProtocolWrapper, keys: Vec<ChannelKeyWrapper>) -> Self {
+        assert_eq!(protocol.num_channels(), keys.len());
+        ProtocolConfig {
+            protocol,
+            keys,
+            seed: None,
+        }
+    }
+
+    /// A fresh random key of the right kind for `protocol`.
+    pub fn sample_key(protocol: &ProtocolWrapper) -> ChannelKeyWrapper {
+        use spectrum_primitives::{AuthKey, Sampleable, TwoKeyPubAuthKey};
+        match protocol {
+            // Audits ignore it, so it's only a label.
+            ProtocolWrapper::Insecure(_) => hex::encode(thread_rng().gen::<[u8; 16]>()).into(),
+            ProtocolWrapper::Secure(_) => AuthKey::sample().into(),
+            ProtocolWrapper::SecurePub(_) => TwoKeyPubAuthKey::sample().into(),
+            ProtocolWrapper::SecureMultiKey(_) => AuthKey::sample().into(),
+        }
+    }
+
+    /// Like `sample_key()`, but drawing from `rng` (so a seeded `rng` always
+    /// gives the same key).
+    pub fn sample_key_from_rng<R: Rng>(
+        protocol: &ProtocolWrapper,
+        rng: &mut R,
+    ) -> ChannelKeyWrapper {
+        use spectrum_primitives::{AuthKey, Bytes, TwoKeyPubAuthKey};
+        use std::convert::TryFrom;
+        // 64 bytes reduce to a uniformly random scalar.
+        let mut scalar = || {
+            let mut bytes = vec![0; 64];
+            rng.fill_bytes(&mut bytes);
+            AuthKey::try_from(Bytes::from(bytes)).expect("Any 64 bytes are a scalar.")
+        };
+        match protocol {
+            ProtocolWrapper::Insecure(_) => hex::encode(rng.gen::<[u8; 16]>()).into(),
+            ProtocolWrapper::Secure(_) => scalar().into(),
+            ProtocolWrapper::SecurePub(_) => TwoKeyPubAuthKey::from(scalar()).into(),
+            ProtocolWrapper::SecureMultiKey(_) => scalar().into(),
+        }
+    }
+
+    /// Use fresh random keys for every channel.
+    pub fn sample_keys(protocol: ProtocolWrapper) -> Self {
+        let keys = (0..protocol.num_channels())
+            .map(|_| ProtocolConfig::sample_key(&protocol))
+            .collect();
+        ProtocolConfig::new(protocol, keys)
+    }
+
+    /// The multi-key protocol from `seed`, with fresh random keys for every
+    /// channel.
+    pub fn sample_keys_from_seed(seed: ProtocolSeed) -> Self {
+        let mut config = ProtocolConfig::sample_keys(seed.protocol());
+        config.seed = Some(seed);
+        config
+    }
+
+    /// Like `new()`, but for keys from outside: checks that there's one per
+    /// channel, of the right kind for the protocol.
+    pub fn try_new(protocol: ProtocolWrapper, keys: Vec<ChannelKeyWrapper>) -> Result<Self, Error> {
+        if protocol.num_channels() != keys.len() {
+            return Err(Error::new(&format!(
+                "Expected {} channel keys, got {}.",
+                protocol.num_channels(),
+                keys.len()
+            )));
+        }
+        for (idx, key) in keys.iter().enumerate() {
+            let fits = match protocol {
+                ProtocolWrapper::Insecure(_) => matches!(key, ChannelKeyWrapper::Insecure(_)),
+                ProtocolWrapper::SecurePub(_) => matches!(key, ChannelKeyWrapper::SecurePub(_)),
+                ProtocolWrapper::Secure(_) | ProtocolWrapper::SecureMultiKey(_) => {
+                    matches!(key, ChannelKeyWrapper::Secure(_))
+                }
+            };
+            if !fits {
+                return Err(Error::new(&format!(
+                    "Key for channel {} is the wrong kind for this protocol.",
+                    idx
+                )));
+            }
+        }
+        Ok(ProtocolConfig {
+            protocol,
+            keys,
+            seed: None,
+        })
+    }
+
+    pub fn protocol(&self) -> &ProtocolWrapper {
+        &self.protocol
+    }
+
+    pub fn keys(&self) -> &[ChannelKeyWrapper] {
+        &self.keys
+    }
+}
+
+/// How many of each service to run.
+///
+/// The number of groups is fixed by the protocol.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Topology {
     // TODO(zjn): when nonzero types hit stable, replace u16 with NonZeroU16.
     // https://github.com/rust-lang/rfcs/blob/master/text/2307-concrete-nonzero-types.md
     group_size: u16,
+    #[serde(with = "clients_as_u64")]
     clients: u128,
-    pub hammer: bool,
-    keys: Vec<ChannelKeyWrapper>,
+    #[serde(default = "default_publishers")]
+    publishers: u16,
+}
+
+fn default_publishers() -> u16 {
+    1
+}
+
+// Stored as a `u64`: `Topology` gets flattened into `Experiment`, and serde
+// can't buffer a `u128` for a flattened field.
+mod clients_as_u64 {
+    use serde::{ser::Error as _, Deserialize, Deserializer, Serializer};
+    use std::convert::TryFrom;
+
+    pub fn serialize<S: Serializer>(clients: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        let clients = u64::try_from(*clients).map_err(S::Error::custom)?;
+        serializer.serialize_u64(clients)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        u64::deserialize(deserializer).map(u128::from)
+    }
+}
+
+impl Topology {
+    pub fn new(group_size: u16, clients: u128) -> Self {
+        assert!(group_size >= 1, "Expected at least 1 worker per group.");
+        assert!(clients >= 1, "Expected at least 1 client.");
+        Topology {
+            group_size,
+            clients,
+            publishers: default_publishers(),
+        }
+    }
+
+    /// Use `publishers` replicated publishers (each leader sends to all of them).
+    pub fn with_publishers(mut self, publishers: u16) -> Self {
+        assert!(publishers >= 1, "Expected at least 1 publisher.");
+        self.publishers = publishers;
+        self
+    }
+
+    pub fn group_size(&self) -> u16 {
+        self.group_size
+    }
+
+    pub fn clients(&self) -> u128 {
+        self.clients
+    }
+
+    pub fn publishers(&self) -> u16 {
+        self.publishers
+    }
+}
+
+/// Configuration for a hammer (load test) run.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct HammerConfig {
+    /// Total uploads per second to aim for across all clients (as fast as
+    /// possible if `None`).
+    pub target_qps: Option<u64>,
+    /// How long to run for (until interrupted if `None`).
+    pub duration: Option<Duration>,
+    /// How often workers report progress to the publisher.
+    pub report_interval: Duration,
+}
+
+impl Default for HammerConfig {
+    fn default() -> Self {
+        HammerConfig {
+            target_qps: None,
+            duration: None,
+            report_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// What a client does in hammer mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HammerClient {
+    /// Minimum time between uploads.
+    pub interval: Option<Duration>,
+    /// Stop uploading this long after the start time.
+    pub duration: Option<Duration>,
+}
+
+/// How the services behave while running.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "mode", rename_all = "kebab-case")]
+pub enum RunMode {
+    /// Clients broadcast, and the publisher recovers the channels.
+    Broadcast {
+        /// Leaders send the publisher each round's share as a difference from
+        /// the previous round's (saves bandwidth if rounds look alike).
+        #[serde(default)]
+        delta_shares: bool,
+        /// Broadcasters prefix messages with a checksum, so the publisher can
+        /// flag channels with colliding writes (byte-oriented protocols only).
+        #[serde(default)]
+        channel_checksums: bool,
+        /// Workers pad their client counts with noise, so the participation
+        /// counts the publisher publishes are differentially private.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        participation_privacy: Option<PrivacyBudget>,
+        /// Latency budgets for each stage of the round; the manifest records
+        /// which stages went over.
+        #[serde(default, skip_serializing_if = "StageBudgets::is_empty")]
+        stage_budgets: StageBudgets,
+        /// This is the reservation round of the two-phase variant:
+        /// broadcasters reserve slots for a later payload round, and the
+        /// publisher assigns them.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reservation_round: Option<ReservationRound>,
+    },
+    /// Don't set up leaders; clients upload in a loop, and workers just
+    /// measure raw QPS (and report it to the publisher).
+    Hammer(HammerConfig),
+}
+
+impl Default for RunMode {
+    fn default() -> Self {
+        RunMode::Broadcast {
+            delta_shares: false,
+            channel_checksums: false,
+            participation_privacy: None,
+            stage_budgets: StageBudgets::default(),
+            reservation_round: None,
+        }
+    }
+}
+
+impl RunMode {
+    fn from_hammer(hammer: bool) -> Self {
+        if hammer {
+            RunMode::Hammer(HammerConfig::default())
+        } else {
+            RunMode::default()
+        }
+    }
+}
+
+// A random (version 4) UUID.
+fn new_id() -> String {
+    let mut bytes: [u8; 16] = thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// Flattened so the stored JSON doesn't depend on how we group the fields.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Experiment {
+    /// A random UUID, so results can be traced back to the experiment.
+    #[serde(default)]
+    id: String,
+    #[serde(flatten)]
+    protocol: ProtocolConfig,
+    #[serde(flatten)]
+    topology: Topology,
+    #[serde(flatten)]
+    mode: RunMode,
 }
 
 impl Experiment {
+    pub fn from_parts(protocol: ProtocolConfig, topology: Topology, mode: RunMode) -> Self {
+        Experiment {
+            id: new_id(),
+            protocol,
+            topology,
+            mode,
+        }
+    }
+
+    /// Identifies this experiment (empty if it was stored without an ID).
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
     pub fn new(
         protocol: ProtocolWrapper,
         group_size: u16,
@@ -26,16 +403,17 @@ impl Experiment {
         hammer: bool,
         keys: Vec<ChannelKeyWrapper>,
     ) -> Experiment {
-        assert!(group_size >= 1, "Expected at least 1 worker per group.");
-        assert!(clients >= 1, "Expected at least 1 client.");
-        assert_eq!(protocol.num_channels(), keys.len());
-        Experiment {
-            protocol,
-            group_size,
-            clients,
-            hammer,
-            keys,
-        }
+        Experiment::from_parts(
+            ProtocolConfig::new(protocol, keys),
+            Topology::new(group_size, clients),
+            RunMode::from_hammer(hammer),
+        )
+    }
+
+    /// Use `publishers` replicated publishers (each leader sends to all of them).
+    pub fn with_publishers(mut self, publishers: u16) -> Self {
+        self.topology = self.topology.with_publishers(publishers);
+        self
     }
 
     pub fn new_sample_keys(
@@ -44,44 +422,175 @@ impl Experiment {
         clients: u128,
         hammer: bool,
     ) -> Self {
-        use spectrum_primitives::{AuthKey, Sampleable, TwoKeyPubAuthKey};
-        let channels = 0..protocol.num_channels();
-        let keys: Vec<ChannelKeyWrapper> = match &protocol {
-            ProtocolWrapper::Secure(_) => {
-                channels.map(|_: usize| AuthKey::sample().into()).collect()
-            }
-            ProtocolWrapper::SecurePub(_) => channels
-                .map(|_: usize| TwoKeyPubAuthKey::sample().into())
-                .collect(),
-            ProtocolWrapper::SecureMultiKey(_) => {
-                channels.map(|_: usize| AuthKey::sample().into()).collect()
-            }
+        Experiment::from_parts(
+            ProtocolConfig::sample_keys(protocol),
+            Topology::new(group_size, clients),
+            RunMode::from_hammer(hammer),
+        )
+    }
+
+    pub fn protocol_config(&self) -> &ProtocolConfig {
+        &self.protocol
+    }
+
+    /// Use `keys` for the channels instead (say, keys already given out to
+    /// broadcasters).
+    pub fn with_keys(mut self, keys: Vec<ChannelKeyWrapper>) -> Result<Self, Error> {
+        let seed = self.protocol.seed;
+        self.protocol = ProtocolConfig::try_new(self.protocol.protocol.clone(), keys)?;
+        self.protocol.seed = seed;
+        Ok(self)
+    }
+
+    pub fn topology(&self) -> &Topology {
+        &self.topology
+    }
+
+    pub fn mode(&self) -> RunMode {
+        self.mode
+    }
+
+    pub fn hammer(&self) -> Option<HammerConfig> {
+        match self.mode {
+            RunMode::Hammer(config) => Some(config),
+            RunMode::Broadcast { .. } => None,
+        }
+    }
+
+    /// How each client should pace itself in hammer mode (assuming all
+    /// `clients()` are running).
+    pub fn hammer_client(&self) -> Option<HammerClient> {
+        let config = self.hammer()?;
+        let interval = config
+            .target_qps
+            .map(|qps| Duration::from_secs_f64(self.clients() as f64 / qps.max(1) as f64));
+        Some(HammerClient {
+            interval,
+            duration: config.duration,
+        })
+    }
+
+    pub fn delta_shares(&self) -> bool {
+        match self.mode {
+            RunMode::Broadcast { delta_shares, .. } => delta_shares,
+            RunMode::Hammer(_) => false,
+        }
+    }
+
+    pub fn channel_checksums(&self) -> bool {
+        match self.mode {
+            RunMode::Broadcast {
+                channel_checksums, ..
+            } => channel_checksums,
+            RunMode::Hammer(_) => false,
+        }
+    }
+
+    pub fn participation_privacy(&self) -> Option<PrivacyBudget> {
+        match self.mode {
+            RunMode::Broadcast {
+                participation_privacy,
+                ..
+            } => participation_privacy,
+            RunMode::Hammer(_) => None,
+        }
+    }
+
+    pub fn stage_budgets(&self) -> StageBudgets {
+        match self.mode {
+            RunMode::Broadcast { stage_budgets, .. } => stage_budgets,
+            RunMode::Hammer(_) => StageBudgets::default(),
+        }
+    }
+
+    pub fn reservation_round(&self) -> Option<ReservationRound> {
+        match self.mode {
+            RunMode::Broadcast {
+                reservation_round, ..
+            } => reservation_round,
+            RunMode::Hammer(_) => None,
+        }
+    }
+
+    /// Generate an experiment with a random shape (within `bounds`).
+    ///
+    /// Everything but the ID (protocol, groups, group size, channels,
+    /// clients, message size, multi-key generators, and channel keys) is
+    /// determined by `seed`, so a failing topology can be reproduced.
+    pub fn random(seed: u64, bounds: &TopologyBounds) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        // Every protocol needs at least 2 groups (trust assumption).
+        let groups = rng.gen_range(2..=max(bounds.max_groups, 2));
+        let group_size = rng.gen_range(1..=max(bounds.max_group_size, 1));
+        let channels = rng.gen_range(1..=max(bounds.max_channels, 1));
+        let clients = rng.gen_range((channels as u128)..=max(bounds.max_clients, channels as u128));
+        let msg_size = rng.gen_range(MIN_MSG_SIZE..=max(bounds.max_msg_size, MIN_MSG_SIZE));
+        // The two-key protocols only support exactly 2 groups.
+        let (multi_key, public) = match (groups, rng.gen_range(0..3)) {
+            (2, 0) => (false, false),
+            (2, 1) => (false, true),
+            _ => (true, false),
         };
-        Experiment::new(protocol, group_size, clients, hammer, keys)
+        let seed = multi_key.then(|| ProtocolSeed {
+            groups,
+            channels,
+            msg_size,
+            seed: rng.gen(),
+        });
+        let protocol = match seed {
+            Some(seed) => seed.protocol(),
+            None => ProtocolWrapper::new(true, false, groups, channels, msg_size, public),
+        };
+        let keys = (0..channels)
+            .map(|_| ProtocolConfig::sample_key_from_rng(&protocol, &mut rng))
+            .collect();
+        let mut protocol = ProtocolConfig::new(protocol, keys);
+        protocol.seed = seed;
+        Experiment::from_parts(
+            protocol,
+            Topology::new(group_size, clients),
+            RunMode::from_hammer(false),
+        )
     }
 
     pub fn groups(&self) -> u16 {
-        self.protocol.num_parties().try_into().unwrap()
+        self.get_protocol().num_parties().try_into().unwrap()
     }
 
     pub fn group_size(&self) -> u16 {
-        self.group_size
+        self.topology.group_size()
     }
 
     pub fn clients(&self) -> u128 {
-        self.clients
+        self.topology.clients()
+    }
+
+    pub fn publishers(&self) -> u16 {
+        self.topology.publishers()
     }
 
     pub fn channels(&self) -> usize {
-        self.protocol.num_channels()
+        self.get_protocol().num_channels()
     }
 
+    /// The clients `iter_clients()` gives: a broadcaster per channel, and
+    /// viewers (cover traffic only) for the rest.
+    pub fn expected_traffic(&self) -> ExpectedTraffic {
+        let broadcasters = self.channels() as u64;
+        ExpectedTraffic {
+            broadcasters,
+            clients: max(self.clients() as u64, broadcasters),
+        }
+    }
+
+    /// How many bytes a broadcaster can send (see
+    /// [`ProtocolWrapper::payload_capacity`]).
     pub fn msg_size(&self) -> usize {
-        self.protocol.message_len()
+        self.get_protocol().payload_capacity()
     }
 
     pub fn iter_services(&self) -> impl Iterator<Item = Service> + '_ {
-        let publishers = once((PublisherInfo::new()).into());
+        let publishers = (0..self.publishers()).map(|idx| PublisherInfo::new(idx).into());
         let groups = (0..self.groups()).map(Group::new);
         let workers = groups.clone().flat_map(move |group| {
             (0..self.group_size()).map(move |idx| (WorkerInfo::new(group, idx)).into())
@@ -89,7 +598,7 @@ impl Experiment {
 
         let iter = publishers.chain(workers);
 
-        if self.hammer {
+        if self.hammer().is_some() {
             return Box::new(iter) as Box<dyn Iterator<Item = Service>>;
         }
 
@@ -101,25 +610,27 @@ impl Experiment {
     pub fn iter_clients(&self) -> impl Iterator<Item = Service> + '_ {
         let msg_size = self.msg_size();
         let viewers = (0..(self.channels() as u128))
-            .zip(self.get_keys().into_iter())
+            .zip(self.get_keys())
             .map(move |(idx, key)| {
-                let msg: Vec<u8> = match self.get_protocol() {
-                    ProtocolWrapper::SecureMultiKey(_) => {
-                        // need multiple of 32
-                        let rem = msg_size % 32;
-                        let size = if rem != 0 {
-                            msg_size + (32 - rem)
-                        } else {
-                            msg_size
-                        };
-                        let chunks = size / 32;
-                        use std::iter::repeat;
+                let msg: Vec<u8> = match (self.get_protocol(), self.reservation_round()) {
+                    (protocol @ ProtocolWrapper::SecureMultiKey(_), _) => {
+                        // Every chunk has to encode a group element; fill the
+                        // slot with copies of one that does.
+
                         let good_elem: Vec<u8> = vec![
                             203, 85, 12, 213, 56, 234, 12, 193, 19, 132, 128, 64, 142, 110, 170,
                             185, 179, 108, 97, 63, 13, 211, 247, 120, 79, 219, 110, 234, 131, 123,
                             19, 215,
                         ];
-                        repeat(good_elem).take(chunks).flatten().collect()
+                        std::iter::repeat_n(good_elem, protocol.slot_size())
+                            .flatten()
+                            .collect()
+                    }
+                    (_, Some(round)) => round.sample_message(&mut thread_rng()).0.into(),
+                    _ if self.channel_checksums() => {
+                        let payload_size = msg_size.saturating_sub(CHECKSUM_LEN);
+                        let payload = vec![(idx % 256).try_into().unwrap(); payload_size];
+                        checksum::seal(&payload).into()
                     }
                     _ => {
                         vec![(idx % 256).try_into().unwrap(); msg_size]
@@ -135,17 +646,41 @@ impl Experiment {
     }
 
     pub fn get_protocol(&self) -> &ProtocolWrapper {
-        &self.protocol
+        self.protocol.protocol()
     }
 
     pub fn get_keys(&self) -> Vec<ChannelKeyWrapper> {
-        self.keys.clone()
+        self.protocol.keys().to_vec()
+    }
+}
+
+/// Upper bounds on the shape of an experiment from [`Experiment::random`].
+#[derive(Debug, Clone)]
+pub struct TopologyBounds {
+    pub max_groups: usize,
+    pub max_group_size: u16,
+    pub max_channels: usize,
+    pub max_clients: u128,
+    pub max_msg_size: usize,
+}
+
+impl Default for TopologyBounds {
+    fn default() -> Self {
+        TopologyBounds {
+            max_groups: 4,
+            max_group_size: 3,
+            max_channels: 4,
+            max_clients: 8,
+            max_msg_size: 64,
+        }
     }
 }
 
 // Get the peer nodes for a worker.
 //
 // These should be all worker nodes in the same group except the worker itself.
+/// Multi-key protocols are stored as their [`ProtocolSeed`] (if they have
+/// one), not their generators.
 pub async fn write_to_store<C: Store>(config: &C, experiment: &Experiment) -> Result<(), Error> {
     let json_str = serde_json::to_string(experiment).map_err(|err| Error::new(&err.to_string()))?;
     config
@@ -162,107 +697,404 @@ pub async fn read_from_store<C: Store>(config: &C) -> Result<Experiment, Error>
         .get(vec!["experiment".to_string(), "config".to_string()])
         .await?
         .ok_or_else(|| Error::new("No experiment string in store."))?;
-    Ok(serde_json::from_str(&json_str).map_err(|err| Error::new(&err.to_string()))?)
+    serde_json::from_str(&json_str).map_err(|err| Error::new(&err.to_string()))
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
     use crate::config::tests::inmem_stores;
-    use core::ops::Range;
     use futures::executor::block_on;
     use proptest::prelude::*;
 
-    // TODO: restore
-    // impl Arbitrary for Experiment {
-    //     type Parameters = bool;
-    //     type Strategy = BoxedStrategy<Self>;
-
-    //     fn arbitrary_with(hammer: bool) -> Self::Strategy {
-    //         let group_size: Range<u16> = 1..10;
-    //         // TODO(zjn): add SecureProtocols too (maybe via impl arbitrary for ProtocolWrapper?)
-    //         let protocols = any::<insecure::InsecureProtocol>().prop_map(ProtocolWrapper::from);
-    //         (protocols, group_size)
-    //             .prop_flat_map(move |(protocol, group_size)| {
-    //                 let clients: Range<u128> = (protocol.num_channels() as u128)..20;
-    //                 let keys =
-    //                     prop::collection::vec(any::<ChannelKeyWrapper>(), protocol.num_channels());
-    //                 (clients, keys).prop_map(move |(clients, keys)| {
-    //                     Experiment::new(protocol.clone(), group_size, clients, hammer, keys)
-    //                 })
-    //             })
-    //             .boxed()
-    //     }
-    // }
-
-    // proptest! {
-    //     #[test]
-    //     fn test_experiment_roundtrip(config in inmem_stores(), experiment: Experiment) {
-    //         block_on(async {
-    //             write_to_store(&config, &experiment).await.unwrap();
-    //             assert_eq!(
-    //                 read_from_store(&config).await.unwrap(),
-    //                 experiment);
-    //         });
-    //     }
-
-    //     #[test]
-    //     fn test_experiment_iter_services(experiment: Experiment) {
-    //         let services: Vec<Service> = experiment.iter_services().collect();
-
-    //         let mut publishers = vec![];
-    //         let mut leaders = vec![];
-    //         let mut workers = vec![];
-    //         for service in services {
-    //             match service {
-    //                 Service::Publisher(_) => { publishers.push(service) },
-    //                 Service::Leader(_) => { leaders.push(service) },
-    //                 Service::Worker(_) => { workers.push(service) },
-    //                 Service::Client(_) => {
-    //                     panic!("Clients not (yet) in iter_services");
-    //                 }
-    //             }
-    //         }
-    //         let actual = (publishers.len(), leaders.len(), workers.len());
-    //         let expected = (1,
-    //                         experiment.groups() as usize,
-    //                         (experiment.groups() * experiment.group_size()) as usize);
-    //         prop_assert_eq!(actual, expected);
-    //     }
-
-    //     #[test]
-    //     fn test_experiment_iter_services_hammer(experiment in Experiment::arbitrary_with(true)) {
-    //         let services: Vec<Service> = experiment.iter_services().collect();
-
-    //         let mut publishers = vec![];
-    //         let mut workers = vec![];
-    //         for service in services {
-    //             match service {
-    //                 Service::Publisher(_) => { publishers.push(service) },
-    //                 Service::Leader(_) => { return Err(TestCaseError::fail("Didn't expect leaders in hammer mode")); },
-    //                 Service::Worker(_) => { workers.push(service) },
-    //                 Service::Client(_) => {
-    //                     panic!("Clients not (yet) in iter_services");
-    //                 }
-    //             }
-    //         }
-    //         let expected_workers = (experiment.groups() * experiment.group_size()) as usize;
-    //         prop_assert_eq!(workers.len(), expected_workers);
-    //         prop_assert_eq!(publishers.len(), 1);
-    //     }
-
-    //     #[test]
-    //     fn test_experiment_iter_clients(experiment: Experiment) {
-    //         let clients: Vec<Service> = experiment.iter_clients().collect();
-
-    //         for client in &clients {
-    //             match client {
-    //                 Service::Client(_) => {}
-    //                 _ => { panic!("Only clients expected in iter_clients()."); }
-    //             }
-    //         }
-
-    //         prop_assert_eq!(clients.len(), experiment.clients() as usize);
-    //     }
-    // }
+    impl Arbitrary for Experiment {
+        type Parameters = bool;
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(hammer: bool) -> Self::Strategy {
+            let mode = if hammer {
+                any::<HammerConfig>().prop_map(RunMode::Hammer).boxed()
+            } else {
+                any::<bool>()
+                    .prop_map(|delta_shares| RunMode::Broadcast {
+                        delta_shares,
+                        channel_checksums: false,
+                        participation_privacy: None,
+                        stage_budgets: StageBudgets::default(),
+                        reservation_round: None,
+                    })
+                    .boxed()
+            };
+            (any::<u64>(), 1..3u16, mode)
+                .prop_map(|(seed, publishers, mode)| {
+                    let experiment = Experiment::random(seed, &TopologyBounds::default());
+                    Experiment::from_parts(
+                        experiment.protocol,
+                        experiment.topology.with_publishers(publishers),
+                        mode,
+                    )
+                })
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for HammerConfig {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            let millis = || (1..100_000u64).prop_map(Duration::from_millis);
+            (
+                any::<Option<u64>>(),
+                proptest::option::of(millis()),
+                millis(),
+            )
+                .prop_map(|(target_qps, duration, report_interval)| HammerConfig {
+                    target_qps,
+                    duration,
+                    report_interval,
+                })
+                .boxed()
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_experiment_roundtrip(config in inmem_stores(), experiment: Experiment) {
+            block_on(async {
+                write_to_store(&config, &experiment).await.unwrap();
+                assert_eq!(
+                    read_from_store(&config).await.unwrap(),
+                    experiment);
+            });
+        }
+
+        #[test]
+        fn test_topology_roundtrip(experiment: Experiment) {
+            let json = serde_json::to_string(experiment.topology()).unwrap();
+            let topology: Topology = serde_json::from_str(&json).unwrap();
+            prop_assert_eq!(&topology, experiment.topology());
+        }
+
+        #[test]
+        fn test_protocol_config_roundtrip(experiment: Experiment) {
+            let json = serde_json::to_string(experiment.protocol_config()).unwrap();
+            let protocol: ProtocolConfig = serde_json::from_str(&json).unwrap();
+            prop_assert_eq!(&protocol, experiment.protocol_config());
+        }
+
+        #[test]
+        fn test_experiment_iter_services(experiment: Experiment) {
+            let services: Vec<Service> = experiment.iter_services().collect();
+
+            let mut publishers = vec![];
+            let mut leaders = vec![];
+            let mut workers = vec![];
+            for service in services {
+                match service {
+                    Service::Publisher(_) => { publishers.push(service) },
+                    Service::Leader(_) => { leaders.push(service) },
+                    Service::Worker(_) => { workers.push(service) },
+                    Service::Client(_) => {
+                        panic!("Clients not (yet) in iter_services");
+                    }
+                }
+            }
+            let actual = (publishers.len(), leaders.len(), workers.len());
+            let expected = (experiment.publishers() as usize,
+                            experiment.groups() as usize,
+                            (experiment.groups() * experiment.group_size()) as usize);
+            prop_assert_eq!(actual, expected);
+        }
+
+        #[test]
+        fn test_expected_traffic(experiment: Experiment) {
+            let clients: Vec<ClientInfo> = experiment
+                .iter_clients()
+                .map(|service| match service {
+                    Service::Client(info) => info,
+                    _ => panic!("Only clients in iter_clients"),
+                })
+                .collect();
+            let broadcasters = clients.iter().filter(|info| info.broadcast.is_some()).count();
+            let expected = experiment.expected_traffic();
+            prop_assert_eq!(expected.broadcasters, broadcasters as u64);
+            prop_assert_eq!(expected.clients, clients.len() as u64);
+        }
+
+        #[test]
+        fn test_experiment_iter_services_hammer(experiment in Experiment::arbitrary_with(true)) {
+            let services: Vec<Service> = experiment.iter_services().collect();
+
+            let mut publishers = vec![];
+            let mut workers = vec![];
+            for service in services {
+                match service {
+                    Service::Publisher(_) => { publishers.push(service) },
+                    Service::Leader(_) => { return Err(TestCaseError::fail("Didn't expect leaders in hammer mode")); },
+                    Service::Worker(_) => { workers.push(service) },
+                    Service::Client(_) => {
+                        panic!("Clients not (yet) in iter_services");
+                    }
+                }
+            }
+            let expected_workers = (experiment.groups() * experiment.group_size()) as usize;
+            prop_assert_eq!(workers.len(), expected_workers);
+            prop_assert_eq!(publishers.len(), experiment.publishers() as usize);
+        }
+
+        #[test]
+        fn test_experiment_iter_clients(experiment: Experiment) {
+            let clients: Vec<Service> = experiment.iter_clients().collect();
+
+            for client in &clients {
+                match client {
+                    Service::Client(_) => {}
+                    _ => { panic!("Only clients expected in iter_clients()."); }
+                }
+            }
+
+            prop_assert_eq!(clients.len(), experiment.clients() as usize);
+        }
+    }
+
+    #[test]
+    fn test_random_is_reproducible() {
+        let bounds = TopologyBounds::default();
+        for seed in 0..5 {
+            let experiment = Experiment::random(seed, &bounds);
+            let again = Experiment::random(seed, &bounds);
+            assert_eq!(experiment.protocol_config(), again.protocol_config());
+            assert_eq!(experiment.topology(), again.topology());
+            assert_ne!(
+                experiment.protocol_config(),
+                Experiment::random(seed + 5, &bounds).protocol_config()
+            );
+        }
+    }
+
+    /// The stored format predates the split into parts; keep it flat.
+    #[test]
+    fn test_experiment_json_is_flat() {
+        let experiment = Experiment::random(0, &TopologyBounds::default());
+        let json = serde_json::to_value(&experiment).unwrap();
+        let mut keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            vec![
+                "channel_checksums",
+                "clients",
+                "delta_shares",
+                "group_size",
+                "id",
+                "keys",
+                "mode",
+                "protocol",
+                "publishers"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multi_key_stored_as_seed() {
+        let seed = ProtocolSeed {
+            groups: 3,
+            channels: 2,
+            msg_size: 64,
+            seed: [7; 32],
+        };
+        let experiment = Experiment::from_parts(
+            ProtocolConfig::sample_keys_from_seed(seed),
+            Topology::new(1, 2),
+            RunMode::default(),
+        );
+        let json = serde_json::to_value(&experiment).unwrap();
+        assert_eq!(
+            json["protocol"],
+            serde_json::json!({ "seeded_multi_key": seed })
+        );
+        let parsed: Experiment = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, experiment);
+        assert_eq!(parsed.get_protocol(), &seed.protocol());
+    }
+
+    #[test]
+    fn test_participation_privacy_roundtrip() {
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 64, false);
+        let budget = PrivacyBudget::new(0.5, 1e-6).unwrap();
+        let mode = RunMode::Broadcast {
+            delta_shares: false,
+            channel_checksums: false,
+            participation_privacy: Some(budget),
+            stage_budgets: StageBudgets::default(),
+            reservation_round: None,
+        };
+        let experiment = Experiment::from_parts(
+            ProtocolConfig::sample_keys(protocol),
+            Topology::new(1, 5),
+            mode,
+        );
+        let json = serde_json::to_string(&experiment).unwrap();
+        let parsed: Experiment = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.participation_privacy(), Some(budget));
+    }
+
+    #[test]
+    fn test_stage_budgets_roundtrip() {
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 64, false);
+        let stage_budgets: StageBudgets = "upload=50ms,aggregate=2s".parse().unwrap();
+        let mode = RunMode::Broadcast {
+            delta_shares: false,
+            channel_checksums: false,
+            participation_privacy: None,
+            stage_budgets,
+            reservation_round: None,
+        };
+        let experiment = Experiment::from_parts(
+            ProtocolConfig::sample_keys(protocol),
+            Topology::new(1, 5),
+            mode,
+        );
+        let json = serde_json::to_string(&experiment).unwrap();
+        let parsed: Experiment = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.stage_budgets(), stage_budgets);
+    }
+
+    #[test]
+    fn test_id() {
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 64, false);
+        let experiment = Experiment::new_sample_keys(protocol.clone(), 1, 5, false);
+        let other = Experiment::new_sample_keys(protocol, 1, 5, false);
+        assert_eq!(experiment.id().len(), 36);
+        assert_eq!(&experiment.id()[14..15], "4");
+        assert_ne!(experiment.id(), other.id());
+
+        // Experiments stored before IDs still parse.
+        let mut json = serde_json::to_value(&experiment).unwrap();
+        json.as_object_mut().unwrap().remove("id");
+        let parsed: Experiment = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.id(), "");
+    }
+
+    #[test]
+    fn test_with_keys() {
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 64, false);
+        let experiment = Experiment::new_sample_keys(protocol, 1, 5, false);
+        let other = Experiment::new_sample_keys(experiment.get_protocol().clone(), 1, 5, false);
+        let swapped = experiment.clone().with_keys(other.get_keys()).unwrap();
+        assert_eq!(swapped.get_keys(), other.get_keys());
+
+        experiment
+            .clone()
+            .with_keys(other.get_keys()[..2].to_vec())
+            .expect_err("Need a key per channel.");
+        let pub_protocol = ProtocolWrapper::new(true, false, 2, 3, 64, true);
+        let pub_keys = ProtocolConfig::sample_keys(pub_protocol).keys().to_vec();
+        experiment
+            .with_keys(pub_keys)
+            .expect_err("Keys should match the protocol.");
+    }
+
+    #[test]
+    fn test_channel_checksums_messages() {
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 64, false);
+        let mode = RunMode::Broadcast {
+            delta_shares: false,
+            channel_checksums: true,
+            participation_privacy: None,
+            stage_budgets: StageBudgets::default(),
+            reservation_round: None,
+        };
+        let experiment = Experiment::from_parts(
+            ProtocolConfig::sample_keys(protocol),
+            Topology::new(1, 5),
+            mode,
+        );
+        let messages: Vec<_> = experiment
+            .iter_clients()
+            .filter_map(|service| match service {
+                Service::Client(info) => info.broadcast.map(|(msg, _)| msg),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(messages.len(), 3);
+        for msg in messages {
+            assert_eq!(msg.len(), 64);
+            assert_eq!(
+                checksum::check(msg.as_ref()),
+                checksum::ChannelStatus::Intact
+            );
+        }
+    }
+
+    #[test]
+    fn test_reservation_round_messages() {
+        let round = ReservationRound::new(4, 1024);
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 1024, false)
+            .reservation_round(round.reservation)
+            .unwrap();
+        let mode = RunMode::Broadcast {
+            delta_shares: false,
+            channel_checksums: false,
+            participation_privacy: None,
+            stage_budgets: StageBudgets::default(),
+            reservation_round: Some(round),
+        };
+        let experiment = Experiment::from_parts(
+            ProtocolConfig::sample_keys(protocol),
+            Topology::new(1, 5),
+            mode,
+        );
+        let json = serde_json::to_string(&experiment).unwrap();
+        let parsed: Experiment = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.reservation_round(), Some(round));
+
+        let messages: Vec<_> = experiment
+            .iter_clients()
+            .filter_map(|service| match service {
+                Service::Client(info) => info.broadcast.map(|(msg, _)| msg),
+                _ => None,
+            })
+            .collect();
+        // One reservation per channel, so no collisions.
+        let slots = round.tally(&messages).unwrap();
+        assert_eq!(slots.assignments.len(), 3);
+        assert_eq!(slots.collisions, 0);
+    }
+
+    #[test]
+    fn test_hammer_client() {
+        let experiment = Experiment::random(0, &TopologyBounds::default());
+        assert_eq!(experiment.hammer_client(), None);
+
+        let experiment = Experiment::from_parts(
+            experiment.protocol,
+            Topology::new(1, 100),
+            RunMode::Hammer(HammerConfig {
+                target_qps: Some(50),
+                duration: Some(Duration::from_secs(10)),
+                ..Default::default()
+            }),
+        );
+        assert_eq!(
+            experiment.hammer_client(),
+            Some(HammerClient {
+                interval: Some(Duration::from_secs(2)),
+                duration: Some(Duration::from_secs(10)),
+            })
+        );
+    }
+
+    #[test]
+    fn test_topology_default_publishers() {
+        let topology: Topology =
+            serde_json::from_str(r#"{"group_size": 2, "clients": 10}"#).unwrap();
+        assert_eq!(topology, Topology::new(2, 10));
+    }
 }
@@ -0,0 +1,161 @@
+//! Channel key files, for getting keys to broadcasters.
+//!
+//! Exporting writes one `key-<idx>.json` per channel (what a broadcaster's
+//! `--key-file` expects) plus a `keys.json` with all of them. Importing reads
+//! `keys.json` back, so an experiment can reuse keys that were already handed
+//! out. The files are plain JSON, so keep them private (on Unix, they're only
+//! readable by their owner).
+//!
+//! Revoked keys (see [`revocation`](crate::services::revocation)) don't get a
+//! `key-<idx>.json`, and can't be imported.
+use crate::config::store::{Error, Store};
+use crate::experiment::{read_from_store, write_to_store};
+use crate::protocols::wrapper::ChannelKeyWrapper;
+use crate::services::revocation::{applicable, get_revocations};
+
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+const ALL_KEYS_FILE: &str = "keys.json";
+
+/// The file holding channel `idx`'s key.
+pub fn key_path(dir: &Path, idx: usize) -> PathBuf {
+    dir.join(format!("key-{}.json", idx))
+}
+
+fn io_error(path: &Path, err: impl std::fmt::Display) -> Error {
+    Error::new(&format!("{}: {}", path.display(), err))
+}
+
+fn create_private(path: &Path) -> Result<File, Error> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path).map_err(|err| io_error(path, err))
+}
+
+fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), Error> {
+    let file = create_private(path)?;
+    serde_json::to_writer(file, value).map_err(|err| io_error(path, err))
+}
+
+/// Write `keys` to `dir` (creating it if needed), skipping the per-channel
+/// files for channels in `skip`.
+pub fn write_keys(dir: &Path, keys: &[ChannelKeyWrapper], skip: &[usize]) -> Result<(), Error> {
+    fs::create_dir_all(dir).map_err(|err| io_error(dir, err))?;
+    for (idx, key) in keys.iter().enumerate() {
+        if !skip.contains(&idx) {
+            write_json(&key_path(dir, idx), key)?;
+        }
+    }
+    write_json(&dir.join(ALL_KEYS_FILE), keys)
+}
+
+/// Read the keys for every channel from `dir`.
+pub fn read_keys(dir: &Path) -> Result<Vec<ChannelKeyWrapper>, Error> {
+    let path = dir.join(ALL_KEYS_FILE);
+    let file = File::open(&path).map_err(|err| io_error(&path, err))?;
+    serde_json::from_reader(file).map_err(|err| io_error(&path, err))
+}
+
+/// Write the keys of the experiment in `config` to `dir`, returning how many
+/// channels got a key file (all but the revoked ones).
+pub async fn export_keys<C: Store>(config: &C, dir: &Path) -> Result<usize, Error> {
+    let keys = read_from_store(config).await?.get_keys();
+    let revocations = get_revocations(config).await?;
+    let revoked: Vec<usize> = applicable(&revocations, &keys)
+        .into_iter()
+        .map(|r| r.channel)
+        .collect();
+    write_keys(dir, &keys, &revoked)?;
+    Ok(keys.len() - revoked.len())
+}
+
+/// Replace the keys of the experiment in `config` with those in `dir`,
+/// returning how many there were.
+pub async fn import_keys<C: Store>(config: &C, dir: &Path) -> Result<usize, Error> {
+    let keys = read_keys(dir)?;
+    let revocations = get_revocations(config).await?;
+    if let Some(revocation) = applicable(&revocations, &keys).first() {
+        return Err(Error::new(&format!(
+            "Key for channel {} was revoked ({}).",
+            revocation.channel, revocation.reason
+        )));
+    }
+    let count = keys.len();
+    let experiment = read_from_store(config).await?.with_keys(keys)?;
+    write_to_store(config, &experiment).await?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::factory::from_string;
+    use crate::experiment::Experiment;
+    use crate::protocols::wrapper::ProtocolWrapper;
+    use crate::services::revocation::revoke_channel;
+
+    fn experiment() -> Experiment {
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 16, false);
+        Experiment::new_sample_keys(protocol, 1, 10, false)
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = from_string("").await.unwrap();
+        let original = experiment();
+        write_to_store(&config, &original).await.unwrap();
+
+        assert_eq!(export_keys(&config, dir.path()).await.unwrap(), 3);
+        for (idx, key) in original.get_keys().iter().enumerate() {
+            let file = File::open(key_path(dir.path(), idx)).unwrap();
+            let read: ChannelKeyWrapper = serde_json::from_reader(file).unwrap();
+            assert_eq!(&read, key);
+        }
+
+        // A new experiment (with fresh keys) picks the old keys back up.
+        write_to_store(&config, &experiment()).await.unwrap();
+        assert_ne!(
+            read_from_store(&config).await.unwrap().get_keys(),
+            original.get_keys()
+        );
+        assert_eq!(import_keys(&config, dir.path()).await.unwrap(), 3);
+        assert_eq!(
+            read_from_store(&config).await.unwrap().get_keys(),
+            original.get_keys()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_wrong_count() {
+        let dir = tempfile::tempdir().unwrap();
+        write_keys(dir.path(), &experiment().get_keys()[..2], &[]).unwrap();
+        let config = from_string("").await.unwrap();
+        write_to_store(&config, &experiment()).await.unwrap();
+        import_keys(&config, dir.path())
+            .await
+            .expect_err("Experiment has 3 channels.");
+    }
+
+    #[tokio::test]
+    async fn test_revoked() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = from_string("").await.unwrap();
+        write_to_store(&config, &experiment()).await.unwrap();
+        revoke_channel(&config, 1, "leaked").await.unwrap();
+
+        assert_eq!(export_keys(&config, dir.path()).await.unwrap(), 2);
+        assert!(key_path(dir.path(), 0).exists());
+        assert!(!key_path(dir.path(), 1).exists());
+        import_keys(&config, dir.path())
+            .await
+            .expect_err("Channel 1's key was revoked.");
+    }
+}
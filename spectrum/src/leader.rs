@@ -2,58 +2,198 @@ use crate::proto::{
     expect_field,
     leader_server::{Leader, LeaderServer},
     publisher_client::PublisherClient,
-    AggregateGroupRequest, AggregateWorkerRequest, AggregateWorkerResponse, Share,
+    AggregateGroupRequest, AggregateWorkerRequest, AggregateWorkerResponse, AuditFailures, Share,
 };
 use crate::{
-    accumulator::Accumulator,
+    accumulator::StripedAccumulator,
     config::store::Store,
+    delta::{self, Payload},
     experiment::Experiment,
-    net::Config as NetConfig,
+    net::{client::Builder, systemd, tls, ClientChannel, Config as NetConfig},
     protocols::{wrapper::ProtocolWrapper, Protocol},
     services::{
-        discovery::{register, resolve_all, Node},
+        abort::{watch_for_abort, CancellationToken},
+        admin::{AdminServer, LogAdmin},
+        budget::StageTallies,
+        deadline::{self, Deadlines},
+        discovery::{register, resolve_all, Discovery, Node},
+        election,
         health::{wait_for_health, AllGoodHealthServer, HealthServer},
         quorum::wait_for_start_time_set,
-        LeaderInfo, Service,
+        Group, LeaderInfo, Service, WorkerInfo,
     },
+    spill::Spill,
 };
 use spectrum_primitives::Bytes;
+use spectrum_protocol::Accumulatable;
 
-use futures::Future;
-use log::{debug, error, info, trace};
-use std::convert::{TryFrom, TryInto};
-use std::fmt::Debug;
-use std::sync::Arc;
-use tokio::{
+use crate::rt::{
     spawn,
     sync::{watch, Mutex},
 };
-use tonic::{transport::Channel, Request, Response, Status};
+use chrono::Utc;
+use futures::prelude::*;
+use log::{debug, error, info, trace, warn};
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::{Code, Request, Response, Status};
+
+pub use crate::spill::SpillConfig;
+
+type SharedPublisherClient = Arc<Mutex<PublisherClient<ClientChannel>>>;
+
+// Experiments currently run a single round.
+const ROUND: u64 = 0;
+
+// How many pieces to split the group's share into for combining.
+const ACCUMULATOR_STRIPES: usize = 8;
+
+struct PublisherPeer {
+    client: SharedPublisherClient,
+    // What this publisher already has from us (in delta mode).
+    deltas: Option<delta::Encoder>,
+}
+
+impl PublisherPeer {
+    async fn send(&self, request: AggregateGroupRequest, timeout: Duration) -> Result<(), Status> {
+        let request = deadline::request(request, timeout, None);
+        self.client.lock().await.aggregate_group(request).await?;
+        Ok(())
+    }
 
-type SharedPublisherClient = Arc<Mutex<PublisherClient<Channel>>>;
+    /// Send `share` with `request`, as a delta if the publisher can take one.
+    async fn send_share(
+        &self,
+        mut request: AggregateGroupRequest,
+        share: Share,
+        timeout: Duration,
+    ) -> Result<(), Status> {
+        let deltas = match &self.deltas {
+            Some(deltas) => deltas,
+            None => {
+                request.share = Some(share);
+                return self.send(request, timeout).await;
+            }
+        };
+        if let Payload::Delta(delta) = deltas.encode(&share).await {
+            let mut delta_request = request.clone();
+            delta_request.share_delta = Some(delta);
+            match self.send(delta_request, timeout).await {
+                Ok(()) => {
+                    deltas.acked(request.round, share).await;
+                    return Ok(());
+                }
+                Err(err) if err.code() == Code::FailedPrecondition => {
+                    debug!("Publisher rejected delta ({}); resending in full.", err);
+                    deltas.reset().await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        request.share = Some(share.clone());
+        let round = request.round;
+        self.send(request, timeout).await?;
+        deltas.acked(round, share).await;
+        Ok(())
+    }
+}
+
+/// Who a leader talks to during a round (known once the round starts).
+#[derive(Clone)]
+struct Peers {
+    publishers: Vec<Arc<PublisherPeer>>,
+    // This group's workers that registered in time for the round.
+    workers: HashSet<WorkerInfo>,
+}
+
+// What a leader accumulates during a round; reset when the round ends.
+struct RoundState<A> {
+    // Worker shares get folded in as they arrive.
+    accumulator: StripedAccumulator<A>,
+    // Summed over this group's workers.
+    audit_failures: Mutex<AuditFailures>,
+    // Also summed over this group's workers (noisy, with participation
+    // privacy on).
+    participants: Mutex<u64>,
+    // Combined over this group's workers.
+    stage_tallies: Mutex<StageTallies>,
+    // (round, worker) pairs we've already accumulated.
+    received: Mutex<HashSet<(u64, WorkerInfo)>>,
+    // The last round we finished; later shares for it are stale.
+    finalized: Mutex<Option<u64>>,
+}
+
+impl<A: Accumulatable + Clone + Send + 'static> RoundState<A> {
+    fn new(accumulator: StripedAccumulator<A>) -> Self {
+        RoundState {
+            accumulator,
+            audit_failures: Default::default(),
+            participants: Default::default(),
+            stage_tallies: Default::default(),
+            received: Default::default(),
+            finalized: Default::default(),
+        }
+    }
+
+    async fn is_finalized(&self, round: u64) -> bool {
+        matches!(*self.finalized.lock().await, Some(finalized) if round <= finalized)
+    }
+
+    async fn finalize(&self, round: u64) {
+        self.accumulator.reset().await;
+        *self.audit_failures.lock().await = Default::default();
+        *self.participants.lock().await = 0;
+        *self.stage_tallies.lock().await = Default::default();
+        self.received.lock().await.retain(|(r, _)| *r > round);
+        *self.finalized.lock().await = Some(round);
+    }
+}
 
 pub struct MyLeader<P: Protocol> {
-    accumulator: Arc<Accumulator<Vec<P::Accumulator>>>,
-    total_workers: usize,
-    publisher_client: watch::Receiver<Option<SharedPublisherClient>>,
+    round: Arc<RoundState<P::Accumulator>>,
+    // Worker shares waiting to be folded in (possibly on disk).
+    spill: Arc<Spill>,
+    group: Group,
+    peers: watch::Receiver<Option<Peers>>,
+    deadlines: Deadlines,
+    cancel: CancellationToken,
 }
 
 impl<P> MyLeader<P>
 where
     P: Protocol,
-    P::Accumulator: Clone,
+    P::Accumulator: Clone + Send + 'static,
 {
     fn from_protocol(
         protocol: P,
-        workers_per_group: u16,
-        publisher_client: watch::Receiver<Option<SharedPublisherClient>>,
+        group: Group,
+        peers: watch::Receiver<Option<Peers>>,
+        deadlines: Deadlines,
+        cancel: CancellationToken,
+        spill: SpillConfig,
     ) -> Self {
         MyLeader {
-            accumulator: Arc::new(Accumulator::new(protocol.new_accumulator())),
-            total_workers: workers_per_group as usize,
-            publisher_client,
+            round: Arc::new(RoundState::new(StripedAccumulator::new(
+                protocol.new_accumulator(),
+                ACCUMULATOR_STRIPES,
+            ))),
+            spill: Arc::new(Spill::new(spill)),
+            group,
+            peers,
+            deadlines,
+            cancel,
         }
     }
+
+    /// Round finalization hook: reset this leader for the next round.
+    ///
+    /// Called once the group's share for `round` goes out to the publishers.
+    pub async fn finalize_round(&self, round: u64) {
+        self.round.finalize(round).await;
+    }
 }
 
 #[tonic::async_trait]
@@ -68,22 +208,94 @@ where
         &self,
         request: Request<AggregateWorkerRequest>,
     ) -> Result<Response<AggregateWorkerResponse>, Status> {
+        if self.cancel.is_cancelled() {
+            return Err(Status::aborted("Round aborted."));
+        }
         let request = request.into_inner();
 
         let data = expect_field(request.share, "Share")?;
-        let accumulator = self.accumulator.clone();
-        let total_workers = self.total_workers;
-        let publisher = self
-            .publisher_client
+        let worker = WorkerInfo::from(expect_field(request.worker_id, "Worker ID")?);
+        let peers = self
+            .peers
             .borrow()
             .as_ref()
-            .expect("Should have a publisher by now.")
+            .expect("Should have peers by now.")
             .clone();
+        if !peers.workers.contains(&worker) {
+            return Err(Status::failed_precondition(format!(
+                "Worker {:?} didn't register in time for this round.",
+                worker
+            )));
+        }
+        if self.round.is_finalized(request.round).await {
+            warn!(
+                "Share from worker {:?} for finished round {}; ignoring.",
+                worker, request.round
+            );
+            return Ok(Response::new(AggregateWorkerResponse {}));
+        }
+        if !self
+            .round
+            .received
+            .lock()
+            .await
+            .insert((request.round, worker))
+        {
+            warn!(
+                "Duplicate share from worker {:?} (round {}); ignoring.",
+                worker, request.round
+            );
+            return Ok(Response::new(AggregateWorkerResponse {}));
+        }
+        let held = match self.spill.hold(data).await {
+            Ok(held) => held,
+            Err(err) => {
+                self.round
+                    .received
+                    .lock()
+                    .await
+                    .remove(&(request.round, worker));
+                return Err(Status::internal(format!("Couldn't spill share: {}", err)));
+            }
+        };
+        let spill = self.spill.clone();
+        let worker_audit_failures = request.audit_failures.unwrap_or_default();
+        let worker_participants = request.participants;
+        let worker_stage_tallies: StageTallies = request.stage_tallies.unwrap_or_default().into();
+        let round = self.round.clone();
+        let total_workers = peers.workers.len();
+        let group = self.group;
+        let aggregate_timeout = self.deadlines.aggregate;
+        let publishers = peers.publishers;
+        let cancel = self.cancel.clone();
 
         spawn(async move {
-            // TODO: spawn_blocking for heavy computation?
+            let (data, guard) = match spill.load(held).await {
+                Ok(loaded) => loaded,
+                Err(err) => {
+                    error!(
+                        "Couldn't load spilled share from worker {:?}: {}",
+                        worker, err
+                    );
+                    return;
+                }
+            };
             let data: Vec<P::Accumulator> = data.try_into().unwrap();
-            let worker_count = accumulator.accumulate(data).await;
+            *round.audit_failures.lock().await += &worker_audit_failures;
+            *round.participants.lock().await += worker_participants;
+            round
+                .stage_tallies
+                .lock()
+                .await
+                .merge(&worker_stage_tallies);
+            let worker_count = match round.accumulator.try_accumulate(data).await {
+                Ok(count) => count,
+                Err(err) => {
+                    error!("Malformed share from worker {:?}: {}", worker, err);
+                    return;
+                }
+            };
+            drop(guard);
             if worker_count < total_workers {
                 trace!("Leader receieved {}/{} shares", worker_count, total_workers);
                 return;
@@ -96,13 +308,37 @@ where
                 return;
             }
 
-            let share = accumulator.get().await;
+            if cancel.is_cancelled() {
+                warn!("Round aborted; not sending share to publishers.");
+                return;
+            }
+            let share = round.accumulator.get().await;
             let share: Vec<Vec<u8>> = share.into_iter().map(Into::<Vec<u8>>::into).collect();
             // trace!("Leader final shares: {:?}", share);
-            let req = Request::new(AggregateGroupRequest {
-                share: Some(Share { data: share }),
-            });
-            publisher.lock().await.aggregate_group(req).await.unwrap();
+            let share = Share { data: share };
+            let req = AggregateGroupRequest {
+                group: group.idx.into(),
+                round: ROUND,
+                audit_failures: Some(round.audit_failures.lock().await.clone()),
+                participants: *round.participants.lock().await,
+                stage_tallies: Some((*round.stage_tallies.lock().await).into()),
+                ..Default::default()
+            };
+            // Send to every replica; it's fine if some are down.
+            let mut sent = 0;
+            for publisher in publishers {
+                let result = publisher
+                    .send_share(req.clone(), share.clone(), aggregate_timeout)
+                    .await;
+                match result {
+                    Ok(()) => sent += 1,
+                    Err(err) => warn!("Failed to send share to publisher: {}", err),
+                }
+            }
+            if sent == 0 {
+                error!("Couldn't send share to any publisher!");
+            }
+            round.finalize(ROUND).await;
         });
 
         Ok(Response::new(AggregateWorkerResponse {}))
@@ -115,10 +351,11 @@ async fn inner_run<C, F, P>(
     protocol: P,
     info: LeaderInfo,
     net: NetConfig,
+    spill: SpillConfig,
     shutdown: F,
 ) -> Result<(), Box<dyn std::error::Error + Sync + Send>>
 where
-    C: Store,
+    C: 'static + Store + Discovery + Clone,
     F: Future<Output = ()> + Send + 'static,
     P: Protocol + 'static,
     P::Accumulator: Sync + Send + Clone + TryFrom<Bytes> + Into<Vec<u8>>,
@@ -126,40 +363,98 @@ where
     <Share as TryInto<Vec<P::Accumulator>>>::Error: Debug,
 {
     let (tx, rx) = watch::channel(None);
-    let state = MyLeader::from_protocol(protocol, experiment.group_size(), rx);
+    let cancel = CancellationToken::default();
+    let abort_watcher = spawn(watch_for_abort(config.clone(), cancel.clone()));
+    let state = MyLeader::from_protocol(
+        protocol,
+        info.group,
+        rx,
+        net.deadlines(),
+        cancel.clone(),
+        spill,
+    );
     info!("Leader starting up.");
-    let server_task = tokio::spawn(
-        tonic::transport::server::Server::builder()
+    let shutdown = future::select(
+        Box::pin(shutdown),
+        Box::pin(async move {
+            cancel.cancelled().await;
+        }),
+    )
+    .map(|_| systemd::stopping());
+    let incoming = net.bind().await?;
+    let mut builder = net.server_builder();
+    if let Some(identity) = net.tls_ident() {
+        info!("Adding TLS config.");
+        builder = tls::server(builder, identity)?;
+    }
+    let server_task = spawn(
+        builder
             .add_service(HealthServer::new(AllGoodHealthServer::default()))
+            .add_service(AdminServer::new(LogAdmin::default()))
             .add_service(LeaderServer::new(state))
-            .serve_with_shutdown(net.local_socket_addr(), shutdown),
+            .serve_with_incoming_shutdown(incoming, shutdown),
     );
 
-    wait_for_health(format!("http://{}", net.public_addr()), None).await?;
+    wait_for_health(net.public_uri(), net.tls_cert()).await?;
     trace!("Leader {:?} healthy and serving.", info);
 
-    let node = Node::new(info.into(), net.public_addr());
+    let node = Node::new(info.into(), net.public_addr())
+        .with_scheme(net.public_scheme())
+        .with_pinned_cert(net.pinned_cert());
     register(&config, node).await?;
     debug!("Registered with config server.");
+    systemd::ready();
 
     wait_for_start_time_set(&config).await.unwrap();
     debug!("Got start time.");
-    let publisher_addr = resolve_all(&config)
-        .await?
-        .into_iter()
-        .find_map(|node| match node.service {
-            Service::Publisher(_) => Some(node.addr),
+    let nodes = resolve_all(&config).await?;
+    // The publisher may have started the round without some workers.
+    let workers: HashSet<WorkerInfo> = nodes
+        .iter()
+        .filter_map(|node| match node.service {
+            Service::Worker(worker) if worker.group == info.group => Some(worker),
             _ => None,
         })
-        .expect("Should have a publisher registered");
+        .collect();
+    if workers.len() < usize::from(experiment.group_size()) {
+        warn!(
+            "Only {}/{} workers in group registered.",
+            workers.len(),
+            experiment.group_size()
+        );
+    }
+    let mut publisher_nodes: Vec<Node> = nodes
+        .into_iter()
+        .filter(|node| matches!(node.service, Service::Publisher(_)))
+        .collect();
+    if publisher_nodes.is_empty() {
+        panic!("Should have a publisher registered");
+    }
+    // If the publishers held an election, the winner gets our share first.
+    if let Some(elected) = election::elected(&config, ROUND, Utc::now()).await? {
+        debug!("Elected publisher: {}", elected);
+        publisher_nodes.sort_by_key(|node| node.addr != elected);
+    }
 
-    let publisher = Arc::new(Mutex::new(
-        PublisherClient::connect(format!("http://{}", publisher_addr)).await?,
-    ));
-    tx.send(Some(publisher))
-        .map_err(|_| "Error sending service registry.")?;
+    let mut publishers = vec![];
+    for node in publisher_nodes {
+        let builder = Builder::new(node.uri()).tls(node.tls_cert(net.tls_cert()));
+        match builder.publisher_client(info.into(), node.service).await {
+            Ok(client) => publishers.push(Arc::new(PublisherPeer {
+                client: Arc::new(Mutex::new(client)),
+                deltas: experiment.delta_shares().then(delta::Encoder::default),
+            })),
+            Err(err) => warn!("Failed to connect to publisher {}: {}", builder.uri(), err),
+        }
+    }
+    tx.send(Some(Peers {
+        publishers,
+        workers,
+    }))
+    .map_err(|_| "Error sending service registry.")?;
 
     server_task.await??;
+    abort_watcher.abort();
     info!("Leader shutting down.");
     Ok(())
 }
@@ -170,21 +465,25 @@ pub async fn run<C, F>(
     protocol: ProtocolWrapper,
     info: LeaderInfo,
     net: NetConfig,
+    spill: SpillConfig,
     shutdown: F,
 ) -> Result<(), Box<dyn std::error::Error + Sync + Send>>
 where
-    C: Store,
+    C: 'static + Store + Discovery + Clone,
     F: Future<Output = ()> + Send + 'static,
 {
     match protocol {
+        ProtocolWrapper::Insecure(protocol) => {
+            inner_run(config, experiment, protocol, info, net, spill, shutdown).await?;
+        }
         ProtocolWrapper::Secure(protocol) => {
-            inner_run(config, experiment, protocol, info, net, shutdown).await?;
+            inner_run(config, experiment, protocol, info, net, spill, shutdown).await?;
         }
         ProtocolWrapper::SecurePub(protocol) => {
-            inner_run(config, experiment, protocol, info, net, shutdown).await?;
+            inner_run(config, experiment, protocol, info, net, spill, shutdown).await?;
         }
         ProtocolWrapper::SecureMultiKey(protocol) => {
-            inner_run(config, experiment, protocol, info, net, shutdown).await?;
+            inner_run(config, experiment, protocol, info, net, spill, shutdown).await?;
         }
     }
     Ok(())
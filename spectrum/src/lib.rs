@@ -1,38 +1,62 @@
 //! Spectrum implementation.
+//!
+//! Cargo features (all on by default):
+//!
+//! - `bin`: the command-line tools, their argument parsing ([`cli`]), and the
+//!   `spectrum-top` dashboard ([`top`]).
+//! - `etcd`: etcd-backed config stores (`etcd://`).
+//! - `tls`: TLS between services. Without it, services only speak plaintext.
+//! - `harness`: CPU profiling ([`profile`]) and [`run_new_processes`].
+//!
+//! With none of them, the crate is just the services and [`run_in_process`].
+// gRPC handlers (and their helpers) return `tonic::Status` as-is.
+#![allow(clippy::result_large_err)]
 use futures::{
     future::{AbortHandle, Abortable},
     prelude::*,
     stream::FuturesUnordered,
 };
-use log::error;
-use std::env;
+use log::{error, warn};
+#[cfg(feature = "harness")]
+use rt::Command;
+use rt::{
+    sleep, spawn,
+    sync::{Barrier, Mutex, Notify},
+};
+use spectrum_primitives::Bytes;
+use std::convert::TryInto;
 use std::fmt;
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::{
-    process::Command,
-    sync::{Barrier, Notify},
-    time::sleep,
-};
-use tonic::transport::{Certificate, Identity};
+#[cfg(feature = "harness")]
+use std::{env, fs::File, io::Write, path::Path};
 
 pub use spectrum_protocol as protocols;
 pub use spectrum_protocol::proto as protocol_protos;
 
 mod accumulator;
 pub mod client;
+mod delta;
 pub mod leader;
 pub mod publisher;
+mod spill;
 pub mod worker;
 
+#[cfg(feature = "bin")]
 pub mod cli;
 pub mod config;
 pub mod experiment;
+pub mod keys;
+pub mod logs;
+pub mod metadata;
 pub mod net;
+#[cfg(feature = "harness")]
+pub mod profile;
+pub mod rt;
 pub mod services;
+#[cfg(feature = "bin")]
+pub mod top;
 
 pub mod proto {
     use tonic::Status;
@@ -43,6 +67,20 @@ pub mod proto {
     pub fn expect_field<T>(opt: Option<T>, name: &str) -> Result<T, Status> {
         opt.ok_or_else(|| Status::invalid_argument(format!("{} must be set.", name)))
     }
+
+    impl AuditFailures {
+        pub fn total(&self) -> u64 {
+            self.rejected + self.quarantined + self.flagged
+        }
+    }
+
+    impl std::ops::AddAssign<&AuditFailures> for AuditFailures {
+        fn add_assign(&mut self, rhs: &AuditFailures) {
+            self.rejected += rhs.rejected;
+            self.quarantined += rhs.quarantined;
+            self.flagged += rhs.flagged;
+        }
+    }
 }
 
 #[derive(fmt::Debug)]
@@ -74,19 +112,43 @@ impl std::error::Error for Error {}
 
 use config::store::Store;
 use experiment::Experiment;
-use services::Service::{Client, Leader, Publisher, Worker};
+use net::tls::{Certificate, Identity};
+use net::{PartitionSchedule, PortLayout};
+use services::abort::AbortNotice;
+use services::discovery::Discovered;
+use services::failures::{ErrorBudget, TaskFailure};
+use services::manifest::ManifestSigner;
+use services::quorum::QuorumPolicy;
+use services::registration::RegistrationSchedule;
+use services::Service::{self, Client, Leader, Publisher, Worker};
+
+/// How long `run_in_process` waits for everyone to finish, by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
 
-const TIMEOUT: Duration = Duration::from_secs(10);
+// The watchdog lists at most this many stragglers.
+const MAX_STRAGGLERS_LISTED: usize = 20;
 
 #[derive(Clone)]
 struct PublisherRemote {
     start: Arc<Notify>,
     done: Arc<Barrier>,
+    // How many publishers have gotten to `done`.
+    finished: Arc<AtomicUsize>,
+    recovered: Arc<Mutex<Option<Vec<Bytes>>>>,
+    aborted: Arc<Notify>,
+    abort_notice: Arc<Mutex<Option<AbortNotice>>>,
 }
 
 impl PublisherRemote {
     fn new(done: Arc<Barrier>, start: Arc<Notify>) -> Self {
-        Self { done, start }
+        Self {
+            done,
+            start,
+            finished: Default::default(),
+            recovered: Default::default(),
+            aborted: Default::default(),
+            abort_notice: Default::default(),
+        }
     }
 }
 
@@ -96,38 +158,93 @@ impl publisher::Remote for PublisherRemote {
         self.start.notify_one()
     }
 
-    async fn done(&self) {
+    async fn done(&self, recovered: Vec<Bytes>) {
+        self.recovered.lock().await.replace(recovered);
+        self.finished.fetch_add(1, Ordering::SeqCst);
         self.done.wait().await;
     }
+
+    // Aborted services never reach the barrier, so don't wait on it.
+    async fn aborted(&self, notice: &AbortNotice) {
+        self.abort_notice.lock().await.replace(notice.clone());
+        self.aborted.notify_one();
+    }
+}
+
+// Heals simulated partitions when a run ends, however it ends.
+struct HealPartitions;
+
+impl Drop for HealPartitions {
+    fn drop(&mut self) {
+        net::heal_partitions();
+    }
 }
 
+/// How an in-process run went.
+#[derive(Debug)]
+pub struct RunSummary {
+    /// Time from the start of the round until every service finished.
+    pub elapsed: Duration,
+    /// Tasks that failed without failing the run (always clients).
+    pub failures: Vec<TaskFailure>,
+}
+
+/// Run `experiment` with every service in this process.
+///
+/// Requests between services are cut off according to `partitions` (with
+/// times relative to the start of the run). The run fails as soon as a
+/// publisher, leader, or worker does, or once more clients fail than `budget`
+/// allows; the channels of failed broadcasters aren't checked.
+///
+/// Services listen on localhost, on ports chosen according to `ports`.
+///
+/// The run fails if it isn't done within `timeout` (see [`DEFAULT_TIMEOUT`]).
+/// Shortly before then, a watchdog logs which services haven't finished yet.
 pub async fn run_in_process<C>(
     experiment: Experiment,
     config: C,
     tls: Option<(Identity, Certificate)>,
-) -> Result<Duration, Box<dyn std::error::Error + Sync + Send>>
+    partitions: PartitionSchedule,
+    budget: ErrorBudget,
+    ports: PortLayout,
+    timeout: Duration,
+) -> Result<RunSummary, Box<dyn std::error::Error + Sync + Send>>
 where
     C: 'static + Store + Clone + Sync + Send,
 {
+    net::simulate_partitions(partitions);
+    let _heal = HealPartitions;
     experiment::write_to_store(&config, &experiment).await?;
+    let config = Discovered::new(config);
     let started = Arc::new(Notify::new());
-    // +2: +1 for the "done" notification from the publisher, +1 for the timer task
+    // +1 for the "done" notification from each publisher, +1 for the timer task
     let barrier = Arc::new(Barrier::new(
-        experiment.iter_clients().count() + experiment.iter_services().count() + 2,
+        experiment.iter_clients().count()
+            + experiment.iter_services().count()
+            + experiment.publishers() as usize
+            + 1,
     ));
     let remote = PublisherRemote::new(barrier.clone(), started.clone());
     let handles = FuturesUnordered::new();
-    for service in experiment.iter_services().chain(experiment.iter_clients()) {
+    let mut arrivals = vec![];
+    let services = experiment.iter_services().chain(experiment.iter_clients());
+    for (idx, service) in services.enumerate() {
+        // Whether the task got as far as the barrier (before any failure).
+        let arrived = Arc::new(AtomicBool::new(false));
         let shutdown = {
             let barrier = barrier.clone();
+            let arrived = arrived.clone();
             async move {
+                arrived.store(true, Ordering::SeqCst);
                 barrier.wait().await;
             }
         };
 
+        arrivals.push((service.clone(), arrived.clone()));
+        let task = service.clone();
         let protocol = experiment.get_protocol().clone();
-        let net = net::Config::with_free_port_localhost(tls.clone());
-        handles.push(match service {
+        let net = ports.config(idx, tls.clone())?;
+        let run = match service {
             Publisher(info) => publisher::run(
                 config.clone(),
                 protocol,
@@ -136,6 +253,10 @@ where
                 remote.clone(),
                 shutdown,
                 5000,
+                QuorumPolicy::default(),
+                RegistrationSchedule::default(),
+                ManifestSigner::generate(),
+                None,
             )
             .boxed(),
             Leader(info) => leader::run(
@@ -144,6 +265,7 @@ where
                 protocol,
                 info,
                 net,
+                Default::default(),
                 shutdown,
             )
             .boxed(),
@@ -153,6 +275,12 @@ where
                 protocol,
                 info,
                 net,
+                Default::default(),
+                None,
+                Default::default(),
+                false,
+                false,
+                Default::default(),
                 shutdown,
             )
             .boxed(),
@@ -160,43 +288,172 @@ where
                 config.clone(),
                 protocol,
                 info,
-                experiment.hammer,
+                experiment.hammer_client(),
                 net.tls_cert().clone(),
                 100,
+                None,
+                None,
                 shutdown,
             )
             .boxed(),
-        });
+        };
+        handles.push(run.map(move |result| (task, arrived, result)));
     }
 
-    let timer_task = tokio::spawn(async move {
-        started.notified().await;
-        let start_time = Instant::now();
-        barrier.wait().await;
-        start_time.elapsed()
+    let timer_task = spawn({
+        let barrier = barrier.clone();
+        async move {
+            started.notified().await;
+            let start_time = Instant::now();
+            barrier.wait().await;
+            start_time.elapsed()
+        }
     });
-    let delay_task = tokio::spawn(sleep(TIMEOUT));
+    let watchdog = watchdog(
+        timeout,
+        arrivals,
+        remote.finished.clone(),
+        experiment.publishers() as usize,
+    );
+    let aborted = remote.aborted.clone();
+    let failed = Arc::new(Notify::new());
+    let failure_error: Arc<Mutex<Option<Error>>> = Default::default();
+    let failures: Arc<Mutex<Vec<TaskFailure>>> = Default::default();
     let (work, abort_rx) = AbortHandle::new_pair();
-    tokio::spawn(Abortable::new(
-        async move {
-            handles
-                .for_each(|result| async {
-                    result.unwrap_or_else(|err| error!("Task resulted in error: {:?}", err));
-                })
-                .await
+    spawn(Abortable::new(
+        {
+            let failed = failed.clone();
+            let failure_error = failure_error.clone();
+            let failures = failures.clone();
+            handles.for_each(move |(service, arrived, result)| {
+                let barrier = barrier.clone();
+                let failed = failed.clone();
+                let failure_error = failure_error.clone();
+                let failures = failures.clone();
+                async move {
+                    let err = match result {
+                        Ok(()) => return,
+                        Err(err) => err,
+                    };
+                    error!("Task resulted in error: {:?}", err);
+                    let mut failures = failures.lock().await;
+                    failures.push(TaskFailure::new(service, err.to_string()));
+                    if let Err(err) = budget.check(&failures) {
+                        failure_error.lock().await.replace(err);
+                        failed.notify_one();
+                    } else if !arrived.swap(true, Ordering::SeqCst) {
+                        // Stand in for the failed task so the others can finish.
+                        spawn(async move {
+                            barrier.wait().await;
+                        });
+                    }
+                }
+            })
         },
         abort_rx,
     ));
 
-    futures::select! {
-        elapsed = timer_task.fuse() => Ok(elapsed?),
-        _ = delay_task.fuse() => {
+    let elapsed = futures::select! {
+        elapsed = timer_task.fuse() => elapsed?,
+        _ = watchdog.boxed().fuse() => {
+            work.abort();
+            let msg = format!("Task timed out after {:?}.", timeout);
+            return Err(Box::new(Error::new(&msg)));
+        }
+        _ = aborted.notified().fuse() => {
+            work.abort();
+            let notice = remote.abort_notice.lock().await.take();
+            let reason = notice.map_or_else(String::new, |notice| notice.reason);
+            let msg = format!("Round aborted: {}", reason);
+            return Err(Box::new(Error::new(&msg)));
+        }
+        _ = failed.notified().fuse() => {
             work.abort();
-            Err(Box::new(Error::new(format!("Task timed out after {:?}.", TIMEOUT).as_str())))
+            let err = failure_error.lock().await.take();
+            return Err(Box::new(err.unwrap_or_else(|| Error::new("Task failed."))));
+        }
+    };
+
+    let failures = failures.lock().await.clone();
+    let recovered = remote.recovered.lock().await.take();
+    check_recovered(&experiment, recovered, &failures)?;
+    Ok(RunSummary { elapsed, failures })
+}
+
+/// Wait out `timeout`, warning a little before it's up about any `tasks` that
+/// haven't reached the end-of-run barrier (and publishers that haven't
+/// finished).
+async fn watchdog(
+    timeout: Duration,
+    tasks: Vec<(Service, Arc<AtomicBool>)>,
+    finished: Arc<AtomicUsize>,
+    publishers: usize,
+) {
+    let lead = timeout / 5;
+    sleep(timeout - lead).await;
+
+    let stragglers: Vec<String> = tasks
+        .iter()
+        .filter(|(_, arrived)| !arrived.load(Ordering::SeqCst))
+        .map(|(service, _)| service.to_string())
+        .collect();
+    let finished = finished.load(Ordering::SeqCst);
+    if !stragglers.is_empty() || finished < publishers {
+        let mut listed = stragglers
+            .iter()
+            .take(MAX_STRAGGLERS_LISTED)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ");
+        if stragglers.len() > MAX_STRAGGLERS_LISTED {
+            listed += &format!(", and {} more", stragglers.len() - MAX_STRAGGLERS_LISTED);
         }
+        warn!(
+            "Timing out in {:?}. Not finished: [{}]; {}/{} publishers done.",
+            lead, listed, finished, publishers
+        );
     }
+
+    sleep(lead).await;
+}
+
+/// Check that every broadcaster's message came out of the publisher intact.
+///
+/// Broadcasters in `failures` may not have sent their message, so their
+/// channels are skipped.
+fn check_recovered(
+    experiment: &Experiment,
+    recovered: Option<Vec<Bytes>>,
+    failures: &[TaskFailure],
+) -> Result<(), Error> {
+    let recovered = recovered.ok_or_else(|| Error::new("Publisher never recovered a value."))?;
+    for service in experiment.iter_clients() {
+        if failures.iter().any(|failure| failure.service == service) {
+            continue;
+        }
+        if let Client(info) = service {
+            if let Some((msg, _)) = info.broadcast {
+                let idx: usize = info.idx.try_into().unwrap();
+                let actual = recovered
+                    .get(idx)
+                    .ok_or_else(|| Error::new(&format!("Channel {} not recovered.", idx)))?;
+                // Broadcasters pad messages out to a whole slot.
+                let capacity = experiment.get_protocol().payload_capacity();
+                if actual.len() != capacity || !actual.as_ref().starts_with(msg.as_ref()) {
+                    return Err(Error::new(&format!(
+                        "Channel {} recovered incorrectly: expected {:?}, got {:?}",
+                        idx, msg, actual
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
 }
 
+/// Run `experiment` with each service in its own process, from the binaries in
+/// `$SPECTRUM_BIN_DIR`.
+#[cfg(feature = "harness")]
 pub async fn run_new_processes<C>(
     experiment: Experiment,
     config: C,
@@ -210,15 +467,16 @@ where
     let data_dir = tempfile::tempdir()?;
     let bin_dir = env::var_os("SPECTRUM_BIN_DIR").ok_or("Must set SPECTRUM_BIN_DIR")?;
     let bin_dir = Path::new(&bin_dir);
-    let mut publisher_handle = None;
+    let mut publisher_handles = vec![];
     let mut handles = vec![];
     for service in experiment.iter_services().chain(experiment.iter_clients()) {
         match service {
-            Publisher(_) => {
+            Publisher(info) => {
                 // TODO: publisher stdout should be the time we care about
-                publisher_handle.replace(
+                publisher_handles.push(
                     Command::new(bin_dir.join("publisher"))
-                        .args(&["--log-level", "info"])
+                        .args(["--log-level", "info"])
+                        .args(["--index", &(info.idx + 1).to_string()])
                         .env(&etcd_env.0, &etcd_env.1)
                         .spawn()?,
                 );
@@ -226,8 +484,8 @@ where
             Leader(info) => {
                 handles.push(
                     Command::new(bin_dir.join("leader"))
-                        .args(&["--log-level", "info"])
-                        .args(&["--group", &(info.group.idx + 1).to_string()])
+                        .args(["--log-level", "info"])
+                        .args(["--group", &(info.group.idx + 1).to_string()])
                         .env(&etcd_env.0, &etcd_env.1)
                         .spawn()?,
                 );
@@ -235,9 +493,9 @@ where
             Worker(info) => {
                 handles.push(
                     Command::new(bin_dir.join("worker"))
-                        .args(&["--log-level", "info"])
-                        .args(&["--group", &(info.group.idx + 1).to_string()])
-                        .args(&["--index", &(info.idx + 1).to_string()])
+                        .args(["--log-level", "info"])
+                        .args(["--group", &(info.group.idx + 1).to_string()])
+                        .args(["--index", &(info.idx + 1).to_string()])
                         .env(&etcd_env.0, &etcd_env.1)
                         .spawn()?,
                 );
@@ -251,9 +509,9 @@ where
                     File::create(&msg_file)?.write_all(msg.as_ref())?;
                     handles.push(
                         Command::new(bin_dir.join("broadcaster"))
-                            .args(&["--log-level", "info"])
-                            .args(&["--key-file", &key_file.to_string_lossy()])
-                            .args(&["--message-file", &msg_file.to_string_lossy()])
+                            .args(["--log-level", "info"])
+                            .args(["--key-file", &key_file.to_string_lossy()])
+                            .args(["--message-file", &msg_file.to_string_lossy()])
                             .env(&etcd_env.0, &etcd_env.1)
                             .spawn()?,
                     );
@@ -261,7 +519,7 @@ where
                 None => {
                     handles.push(
                         Command::new(bin_dir.join("viewer"))
-                            .args(&["--log-level", "warn"])
+                            .args(["--log-level", "warn"])
                             .env(&etcd_env.0, &etcd_env.1)
                             .spawn()?,
                     );
@@ -271,10 +529,13 @@ where
     }
 
     // TODO: kill everybody on ^C
-    publisher_handle
-        .expect("Must have at least one publisher in the experiment.")
-        .wait()
-        .await?;
+    assert!(
+        !publisher_handles.is_empty(),
+        "Must have at least one publisher in the experiment."
+    );
+    for mut handle in publisher_handles {
+        handle.wait().await?;
+    }
     // TODO: should:
     // - kill (probably just killing at first is okay too)
     // TOOD:
@@ -0,0 +1,216 @@
+//! Logging, with a filter that can change while the process runs.
+//!
+//! A [`LogFilter`] has a level for Spectrum's own crates (other crates log at
+//! `info`) and per-module overrides, e.g. `debug,spectrum::worker=trace`. The
+//! filter is process-wide; services expose it over their admin endpoint (see
+//! [`crate::services::admin`]), so a live run can turn up logging for one
+//! module without a restart.
+use lazy_static::lazy_static;
+use log::{LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger, SimpleLogger, TermLogger, TerminalMode};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+// Prefix of the targets that the base level applies to.
+const SPECTRUM: &str = "spectrum";
+// Level for everything else (unless overridden).
+const OTHER_LEVEL: LevelFilter = LevelFilter::Info;
+
+lazy_static! {
+    static ref FILTER: RwLock<LogFilter> = Default::default();
+}
+
+/// Which log records to keep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    level: LevelFilter,
+    /// Overrides by module path (the most specific match wins).
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        LogFilter::new(LevelFilter::Debug)
+    }
+}
+
+impl From<LevelFilter> for LogFilter {
+    fn from(level: LevelFilter) -> Self {
+        LogFilter::new(level)
+    }
+}
+
+impl LogFilter {
+    /// Log Spectrum's crates at `level`, with no overrides.
+    pub fn new(level: LevelFilter) -> Self {
+        LogFilter {
+            level,
+            modules: vec![],
+        }
+    }
+
+    /// Log `module` (and its submodules) at `level`.
+    pub fn with_module(mut self, module: &str, level: LevelFilter) -> Self {
+        self.modules.retain(|(m, _)| m != module);
+        self.modules.push((module.to_string(), level));
+        self
+    }
+
+    /// The level for records from `target` (a module path).
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let in_module = |module: &str| {
+            target == module
+                || (target.starts_with(module) && target[module.len()..].starts_with("::"))
+        };
+        let most_specific = self
+            .modules
+            .iter()
+            .filter(|(module, _)| in_module(module))
+            .max_by_key(|(module, _)| module.len());
+        match most_specific {
+            Some((_, level)) => *level,
+            None if target.starts_with(SPECTRUM) => self.level,
+            None => OTHER_LEVEL,
+        }
+    }
+
+    /// The most verbose level that any target gets.
+    pub fn max_level(&self) -> LevelFilter {
+        let levels = self.modules.iter().map(|(_, level)| *level);
+        levels.chain(vec![self.level, OTHER_LEVEL]).max().unwrap()
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.level.to_string().to_lowercase())?;
+        for (module, level) in &self.modules {
+            write!(f, ",{}={}", module, level.to_string().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_level(s: &str) -> Result<LevelFilter, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("Bad log level [{}].", s))
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    /// Parse a level, then any `<module>=<level>` overrides, separated by
+    /// commas (e.g. `debug,spectrum::worker::audit_registry=trace`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut directives = s.split(',').map(str::trim);
+        let mut filter = LogFilter::new(parse_level(directives.next().unwrap())?);
+        for directive in directives.filter(|d| !d.is_empty()) {
+            let (module, level) = directive
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <module>=<level>, got [{}].", directive))?;
+            if module.trim().is_empty() {
+                return Err(format!("Missing module in [{}].", directive));
+            }
+            filter = filter.with_module(module.trim(), parse_level(level)?);
+        }
+        Ok(filter)
+    }
+}
+
+/// The filter currently in effect.
+pub fn filter() -> LogFilter {
+    FILTER.read().unwrap().clone()
+}
+
+/// Replace the filter for this process.
+pub fn set_filter(filter: LogFilter) {
+    log::set_max_level(filter.max_level());
+    *FILTER.write().unwrap() = filter;
+}
+
+struct FilteredLogger {
+    inner: Box<dyn Log>,
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= FILTER.read().unwrap().level_for(metadata.target())
+            && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Start logging to stderr, keeping records that pass `filter`.
+pub fn init(filter: LogFilter) {
+    // The inner logger takes everything; `FILTER` decides.
+    let inner: Box<dyn SharedLogger> =
+        match TermLogger::new(LevelFilter::Trace, Config::default(), TerminalMode::Stderr) {
+            Some(logger) => logger,
+            None => SimpleLogger::new(LevelFilter::Trace, Config::default()),
+        };
+    let logger = FilteredLogger {
+        inner: inner.as_log(),
+    };
+    log::set_boxed_logger(Box::new(logger)).expect("Failed initializing logger.");
+    set_filter(filter);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!("trace".parse(), Ok(LogFilter::new(LevelFilter::Trace)));
+        let filter: LogFilter = "info, spectrum::worker=trace,tonic=debug".parse().unwrap();
+        assert_eq!(
+            filter,
+            LogFilter::new(LevelFilter::Info)
+                .with_module("spectrum::worker", LevelFilter::Trace)
+                .with_module("tonic", LevelFilter::Debug)
+        );
+        assert_eq!(filter.to_string().parse(), Ok(filter));
+        "loud".parse::<LogFilter>().expect_err("not a level");
+        "info,spectrum::worker"
+            .parse::<LogFilter>()
+            .expect_err("missing level");
+        "info,=trace"
+            .parse::<LogFilter>()
+            .expect_err("missing module");
+    }
+
+    #[test]
+    fn test_level_for() {
+        let filter = LogFilter::new(LevelFilter::Warn)
+            .with_module("spectrum::worker", LevelFilter::Debug)
+            .with_module("spectrum::worker::audit_registry", LevelFilter::Trace)
+            .with_module("h2", LevelFilter::Off);
+        assert_eq!(filter.level_for("spectrum::leader"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("spectrum_protocol"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("spectrum::worker"), LevelFilter::Debug);
+        assert_eq!(
+            filter.level_for("spectrum::worker::leader_sender"),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            filter.level_for("spectrum::worker::audit_registry"),
+            LevelFilter::Trace
+        );
+        // Only whole path segments match.
+        assert_eq!(filter.level_for("spectrum::workers"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("h2::codec"), LevelFilter::Off);
+        assert_eq!(filter.level_for("tonic::transport"), LevelFilter::Info);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+}
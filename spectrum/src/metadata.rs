@@ -0,0 +1,88 @@
+//! What produced a set of results: the code, the build, and the experiment.
+//!
+//! The build script stamps the git commit and build profile into every
+//! binary. Each service logs them (with the experiment's ID and parameters)
+//! at startup, and the publisher includes them in its round manifests and
+//! hammer results, so results can be traced back to what produced them.
+use crate::experiment::Experiment;
+use crate::protocols::wrapper::ProtocolWrapper;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The commit this binary was built from (`-dirty` if there were uncommitted
+/// changes), or `unknown` if it wasn't built from a git checkout.
+pub const GIT_COMMIT: &str = env!("SPECTRUM_GIT_COMMIT");
+
+/// The Cargo profile this binary was built with (`debug` or `release`).
+pub const BUILD_PROFILE: &str = env!("SPECTRUM_BUILD_PROFILE");
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunMetadata {
+    pub git_commit: String,
+    pub build_profile: String,
+    /// Empty for experiments stored without an ID.
+    pub experiment_id: String,
+    pub protocol: String,
+    pub groups: u16,
+    pub group_size: u16,
+    pub clients: u128,
+    pub channels: usize,
+    pub msg_size: usize,
+    pub hammer: bool,
+}
+
+impl RunMetadata {
+    pub fn new(experiment: &Experiment) -> Self {
+        let protocol = match experiment.get_protocol() {
+            ProtocolWrapper::Insecure(_) => "insecure",
+            ProtocolWrapper::Secure(_) => "two-key",
+            ProtocolWrapper::SecurePub(_) => "two-key-pub",
+            ProtocolWrapper::SecureMultiKey(_) => "multi-key",
+        };
+        RunMetadata {
+            git_commit: GIT_COMMIT.to_string(),
+            build_profile: BUILD_PROFILE.to_string(),
+            experiment_id: experiment.id().to_string(),
+            protocol: protocol.to_string(),
+            groups: experiment.groups(),
+            group_size: experiment.group_size(),
+            clients: experiment.clients(),
+            channels: experiment.channels(),
+            msg_size: experiment.msg_size(),
+            hammer: experiment.hammer().is_some(),
+        }
+    }
+
+    /// Log (as a `Run metadata: <JSON>` line) for the experiment harness to
+    /// pick up.
+    pub fn log(&self) {
+        info!("Run metadata: {}", self);
+    }
+}
+
+/// As JSON.
+impl fmt::Display for RunMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        f.write_str(&json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_run_metadata(experiment: Experiment) {
+            let metadata = RunMetadata::new(&experiment);
+            prop_assert_eq!(&metadata.experiment_id, experiment.id());
+            prop_assert_eq!(metadata.channels, experiment.channels());
+            let parsed: RunMetadata = serde_json::from_str(&metadata.to_string()).unwrap();
+            prop_assert_eq!(parsed, metadata);
+        }
+    }
+}
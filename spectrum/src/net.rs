@@ -1,8 +1,78 @@
 // TODO(zjn): use IPv6 if available
 // TODO(zjn): use portpicker when https://github.com/Dentosal/portpicker-rs/pull/1 merged
+use crate::rt::{TcpListener, TcpListenerStream};
+use crate::services::deadline::Deadlines;
 use port_check::free_local_port;
-use std::net::SocketAddr;
-use tonic::transport::{Certificate, Identity};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tls::{Certificate, Identity};
+use tonic::transport::Server;
+
+pub mod client;
+mod latency;
+mod limit;
+mod partition;
+pub mod shm;
+pub mod systemd;
+pub mod tls;
+
+pub use client::ClientChannel;
+pub(crate) use latency::parse_duration;
+pub use latency::{simulate_latency, Hop, Latency, SimulatedLatency};
+pub use limit::{Connection, Incoming};
+pub use partition::{heal_partitions, simulate_partitions, Cut, PartitionSchedule, Side};
+
+/// URI scheme that peers should use to reach a service.
+///
+/// This is `https` when a service sits behind a reverse proxy that terminates
+/// TLS, even if the service itself speaks plaintext.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum Scheme {
+    #[default]
+    Http,
+    Https,
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scheme::Http => write!(f, "http"),
+            Scheme::Https => write!(f, "https"),
+        }
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "http" => Ok(Scheme::Http),
+            "https" => Ok(Scheme::Https),
+            _ => Err(format!("Bad scheme [{}]; expected http or https.", s)),
+        }
+    }
+}
+
+/// Caps on the load a server accepts (all unlimited by default).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Concurrent HTTP/2 streams per connection.
+    pub max_concurrent_streams: Option<u32>,
+    /// Requests in flight per connection.
+    pub concurrency_per_connection: Option<usize>,
+    /// Open connections.
+    pub max_connections: Option<usize>,
+    /// Open connections from any one IP address.
+    pub max_connections_per_ip: Option<usize>,
+}
 
 /// Common configuration for a network service.
 #[derive(Debug, Clone)]
@@ -13,7 +83,27 @@ pub struct Config {
     /// Host (and optional port) to publish as the address of this service.
     public_addr: String,
 
+    /// Scheme to publish along with `public_addr`.
+    public_scheme: Scheme,
+
     pub tls: Option<(Identity, Certificate)>,
+
+    /// Certificate to publish (pin) in the config store, if any.
+    ///
+    /// Peers verify this service against the pinned certificate instead of a
+    /// shared CA.
+    pinned_cert: Option<Certificate>,
+
+    /// Deadlines for calls this service makes.
+    deadlines: Deadlines,
+
+    /// Limits for calls this service serves.
+    limits: Limits,
+
+    /// Listener already bound to `local_port`, held until `bind()`.
+    ///
+    /// Shared between clones; only the first `bind()` gets it.
+    reserved: Option<Arc<Mutex<Option<StdTcpListener>>>>,
 }
 
 impl Config {
@@ -21,7 +111,12 @@ impl Config {
         Self {
             local_port,
             public_addr,
+            public_scheme: Scheme::default(),
             tls,
+            pinned_cert: None,
+            deadlines: Deadlines::default(),
+            limits: Limits::default(),
+            reserved: None,
         }
     }
 
@@ -29,7 +124,12 @@ impl Config {
         Self {
             local_port,
             public_addr: format!("localhost:{}", local_port),
+            public_scheme: Scheme::default(),
             tls,
+            pinned_cert: None,
+            deadlines: Deadlines::default(),
+            limits: Limits::default(),
+            reserved: None,
         }
     }
 
@@ -40,12 +140,44 @@ impl Config {
         self.tls.as_ref().map(|(_, c)| c.clone())
     }
 
+    pub fn pinned_cert(&self) -> Option<Certificate> {
+        self.pinned_cert.clone()
+    }
+
+    pub fn set_pinned_cert(&mut self, pinned_cert: Option<Certificate>) {
+        self.pinned_cert = pinned_cert;
+    }
+
     /// A network configuration useful for running locally.
     pub fn with_free_port_localhost(tls: Option<(Identity, Certificate)>) -> Self {
         let local_port = free_local_port().expect("No ports free");
         Self::new_localhost(local_port, tls)
     }
 
+    /// Like `with_free_port_localhost()`, but holds on to the port until
+    /// `bind()` so that nothing else can take it in the meantime.
+    pub fn with_reserved_port_localhost(tls: Option<(Identity, Certificate)>) -> io::Result<Self> {
+        let listener = StdTcpListener::bind(SocketAddr::new("0.0.0.0".parse().unwrap(), 0))?;
+        Self::with_listener(listener, None, tls)
+    }
+
+    /// Serve on `listener` (e.g., one passed by systemd) instead of binding a
+    /// port.
+    ///
+    /// Publishes `public_addr` if given, or `localhost` and the listener's port.
+    pub fn with_listener(
+        listener: StdTcpListener,
+        public_addr: Option<String>,
+        tls: Option<(Identity, Certificate)>,
+    ) -> io::Result<Self> {
+        let mut config = Self::new_localhost(listener.local_addr()?.port(), tls);
+        if let Some(public_addr) = public_addr {
+            config.public_addr = public_addr;
+        }
+        config.reserved = Some(Arc::new(Mutex::new(Some(listener))));
+        Ok(config)
+    }
+
     pub fn with_free_port(public_addr: String, tls: Option<(Identity, Certificate)>) -> Self {
         let mut config = Self::with_free_port_localhost(tls);
         config.public_addr = public_addr;
@@ -63,10 +195,132 @@ impl Config {
     pub fn public_addr(&self) -> String {
         self.public_addr.clone()
     }
+
+    pub fn set_public_scheme(&mut self, public_scheme: Scheme) {
+        self.public_scheme = public_scheme;
+    }
+
+    pub fn public_scheme(&self) -> Scheme {
+        self.public_scheme
+    }
+
+    pub fn deadlines(&self) -> Deadlines {
+        self.deadlines
+    }
+
+    pub fn set_deadlines(&mut self, deadlines: Deadlines) {
+        self.deadlines = deadlines;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// A server builder with this service's per-connection limits applied.
+    pub fn server_builder(&self) -> Server {
+        let builder = Server::builder().max_concurrent_streams(self.limits.max_concurrent_streams);
+        match self.limits.concurrency_per_connection {
+            Some(limit) => builder.concurrency_limit_per_connection(limit),
+            None => builder,
+        }
+    }
+
+    /// The URI at which peers reach this service.
+    pub fn public_uri(&self) -> String {
+        format!("{}://{}", self.public_scheme, self.public_addr)
+    }
+
+    /// Bind the local socket (or take the reserved one), for use with
+    /// `serve_with_incoming_shutdown()`.
+    ///
+    /// Once this returns, connections to the service queue up even if the
+    /// server task hasn't started polling yet, so it's safe to health-check.
+    /// Incoming connections are subject to this service's connection limits.
+    pub async fn bind(&self) -> io::Result<Incoming> {
+        let reserved = self
+            .reserved
+            .as_ref()
+            .and_then(|reserved| reserved.lock().unwrap().take());
+        let listener = match reserved {
+            Some(listener) => {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)?
+            }
+            None => TcpListener::bind(self.local_socket_addr()).await?,
+        };
+        Ok(Incoming::new(
+            TcpListenerStream::new(listener),
+            self.limits.max_connections,
+            self.limits.max_connections_per_ip,
+        ))
+    }
+}
+
+/// How `run_in_process` picks ports for its services.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PortLayout {
+    /// Bind a free port for each service up front, and hold it until the
+    /// service starts serving.
+    #[default]
+    Reserved,
+    /// Pick free ports without holding them.
+    ///
+    /// Racy: another process can take a port before the service binds it.
+    Free,
+    /// Service `i` (in `Experiment::iter_services()` order, then clients)
+    /// gets port `base + i`, for predictable addresses when debugging.
+    Fixed { base: u16 },
+}
+
+impl PortLayout {
+    /// Network config for the `idx`th service.
+    pub fn config(&self, idx: usize, tls: Option<(Identity, Certificate)>) -> io::Result<Config> {
+        match self {
+            PortLayout::Reserved => Config::with_reserved_port_localhost(tls),
+            PortLayout::Free => Ok(Config::with_free_port_localhost(tls)),
+            PortLayout::Fixed { base } => {
+                let port = u16::try_from(idx)
+                    .ok()
+                    .and_then(|idx| base.checked_add(idx))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("No port for service {} after base port {}.", idx, base),
+                        )
+                    })?;
+                Ok(Config::new_localhost(port, tls))
+            }
+        }
+    }
+}
+
+impl FromStr for PortLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reserved" => Ok(PortLayout::Reserved),
+            "free" => Ok(PortLayout::Free),
+            _ => s
+                .parse()
+                .map(|base| PortLayout::Fixed { base })
+                .map_err(|_| {
+                    format!(
+                        "Bad port layout [{}]; expected reserved, free, or a base port.",
+                        s
+                    )
+                }),
+        }
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
+    use super::*;
     use proptest::prelude::*;
 
     pub fn addrs() -> impl Strategy<Value = String> {
@@ -75,4 +329,59 @@ pub mod tests {
             Just("localhost:8080".to_string()),
         ]
     }
+
+    pub fn schemes() -> impl Strategy<Value = Scheme> {
+        prop_oneof![Just(Scheme::Http), Just(Scheme::Https)]
+    }
+
+    proptest! {
+        #[test]
+        fn test_scheme_roundtrip(scheme in schemes()) {
+            prop_assert_eq!(scheme.to_string().parse::<Scheme>().unwrap(), scheme);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reserved_port() {
+        let config = Config::with_reserved_port_localhost(None).unwrap();
+        assert!(
+            StdTcpListener::bind(config.local_socket_addr()).is_err(),
+            "port should be held"
+        );
+        // The clone shares the reservation.
+        config
+            .clone()
+            .bind()
+            .await
+            .expect("should use reserved listener");
+    }
+
+    #[tokio::test]
+    async fn test_with_listener() {
+        let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config =
+            Config::with_listener(listener, Some("example.com:443".to_string()), None).unwrap();
+        assert_eq!(config.local_socket_addr().port(), port);
+        assert_eq!(config.public_addr(), "example.com:443");
+        config.bind().await.expect("should use the listener");
+    }
+
+    #[test]
+    fn test_fixed_port_layout() {
+        let layout = PortLayout::Fixed { base: 9000 };
+        assert_eq!(
+            layout.config(3, None).unwrap().local_socket_addr().port(),
+            9003
+        );
+        assert!(layout.config(usize::from(u16::MAX), None).is_err());
+    }
+
+    #[test]
+    fn test_port_layout_from_str() {
+        assert_eq!("reserved".parse(), Ok(PortLayout::Reserved));
+        assert_eq!("free".parse(), Ok(PortLayout::Free));
+        assert_eq!("9000".parse(), Ok(PortLayout::Fixed { base: 9000 }));
+        assert!("70000".parse::<PortLayout>().is_err());
+    }
 }
@@ -0,0 +1,366 @@
+//! Building gRPC clients.
+//!
+//! Every service connects to its peers through a [`Builder`], so they all get
+//! the same stack: TLS, connection retries, and an optional per-request
+//! timeout and concurrency limit on the connection itself; then, per peer,
+//! extra request metadata, metrics, tracing, and the simulated latency and
+//! partitions.
+//!
+//! Retrying *requests* is up to callers, since only they know which calls are
+//! safe to repeat (see e.g. `worker::leader_sender`).
+use super::latency::{self, Hop};
+use super::partition;
+use super::tls::{self, Certificate};
+use crate::proto::{
+    leader_client::LeaderClient, pir_client::PirClient, publisher_client::PublisherClient,
+    worker_client::WorkerClient,
+};
+use crate::rt::sleep;
+use crate::services::Service;
+
+use futures::future::BoxFuture;
+use lazy_static::lazy_static;
+use log::{debug, trace};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tonic::codegen::http::{header::HeaderName, HeaderValue, Request};
+use tonic::transport::{Channel, Endpoint, Error};
+use tower::BoxError;
+
+/// A channel for gRPC clients between services.
+pub type ClientChannel = Instrumented<partition::Gated<latency::Delayed<Channel>>>;
+
+lazy_static! {
+    static ref METRICS: [Arc<Metrics>; 3] = Default::default();
+}
+
+fn hop_metrics(hop: Hop) -> Arc<Metrics> {
+    let idx = match hop {
+        Hop::Worker => 0,
+        Hop::Leader => 1,
+        Hop::Publisher => 2,
+    };
+    METRICS[idx].clone()
+}
+
+/// Requests from this process to services of kind `hop` so far.
+pub fn metrics(hop: Hop) -> MetricsSnapshot {
+    hop_metrics(hop).snapshot()
+}
+
+/// Request counters, updated as requests complete.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    failures: AtomicU64,
+    latency_us: AtomicU64,
+}
+
+impl Metrics {
+    fn record(&self, latency: Duration, ok: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_us
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            total_latency: Duration::from_micros(self.latency_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub requests: u64,
+    /// Requests that failed in transport (including simulated partitions).
+    ///
+    /// Requests that the server answered with an error status don't count.
+    pub failures: u64,
+    pub total_latency: Duration,
+}
+
+/// Builds clients of the service at one URI.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    uri: String,
+    tls: Option<Certificate>,
+    timeout: Option<Duration>,
+    concurrency_limit: Option<usize>,
+    attempts: usize,
+    retry_delay: Duration,
+    metadata: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Builder {
+    /// Plaintext, with no timeout or concurrency limit, connecting only once.
+    pub fn new(uri: impl Into<String>) -> Self {
+        Builder {
+            uri: uri.into(),
+            tls: None,
+            timeout: None,
+            concurrency_limit: None,
+            attempts: 1,
+            retry_delay: Duration::default(),
+            metadata: vec![],
+        }
+    }
+
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// Verify the service against `cert`, if any.
+    pub fn tls(mut self, cert: Option<Certificate>) -> Self {
+        self.tls = cert;
+        self
+    }
+
+    /// Fail requests that take longer than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send at most `limit` requests at a time over the connection (the rest
+    /// wait).
+    pub fn concurrency_limit(mut self, limit: Option<usize>) -> Self {
+        self.concurrency_limit = limit;
+        self
+    }
+
+    /// Try to connect up to `attempts` times, waiting `delay` in between.
+    pub fn connect_attempts(mut self, attempts: usize, delay: Duration) -> Self {
+        assert!(attempts > 0, "Zero attempts makes no sense.");
+        self.attempts = attempts;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Send `key: value` with every request (e.g., credentials).
+    ///
+    /// Panics if `key` isn't a valid (lowercase) header name.
+    pub fn metadata(mut self, key: &'static str, value: HeaderValue) -> Self {
+        self.metadata.push((HeaderName::from_static(key), value));
+        self
+    }
+
+    fn endpoint(&self) -> Result<Endpoint, Error> {
+        let mut endpoint = Endpoint::new(self.uri.clone())?;
+        if let Some(cert) = &self.tls {
+            debug!("TLS for client of {}.", self.uri);
+            endpoint = tls::client(endpoint, cert)?;
+        }
+        if let Some(timeout) = self.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(limit) = self.concurrency_limit {
+            endpoint = endpoint.concurrency_limit(limit);
+        }
+        Ok(endpoint)
+    }
+
+    /// Connect, without any of the per-peer layers.
+    ///
+    /// Use this to share one connection between many clients (each with
+    /// [`wrap`](Self::wrap)), or for callers that aren't services themselves
+    /// (health checks, the admin tool).
+    pub async fn channel(&self) -> Result<Channel, Error> {
+        let endpoint = self.endpoint()?;
+        let mut attempt = 1;
+        loop {
+            match endpoint.connect().await {
+                Ok(channel) => return Ok(channel),
+                Err(err) if attempt < self.attempts => {
+                    debug!(
+                        "Failed to connect to {} (attempt {}/{}): {}",
+                        self.uri, attempt, self.attempts, err
+                    );
+                    attempt += 1;
+                    sleep(self.retry_delay).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Put the per-peer layers in front of `channel`, a connection to `to` on
+    /// behalf of `from`.
+    pub fn wrap(&self, channel: Channel, from: Service, to: Service) -> ClientChannel {
+        let hop = Hop::from(&to);
+        let inner = partition::gated(latency::delayed(channel, hop), from, to.clone());
+        instrumented(inner, to, hop_metrics(hop), self.metadata.clone())
+    }
+
+    /// Connect to `to` on behalf of `from`.
+    pub async fn connect(&self, from: Service, to: Service) -> Result<ClientChannel, Error> {
+        Ok(self.wrap(self.channel().await?, from, to))
+    }
+
+    pub async fn worker_client(
+        &self,
+        from: Service,
+        to: Service,
+    ) -> Result<WorkerClient<ClientChannel>, Error> {
+        Ok(WorkerClient::new(self.connect(from, to).await?))
+    }
+
+    pub async fn leader_client(
+        &self,
+        from: Service,
+        to: Service,
+    ) -> Result<LeaderClient<ClientChannel>, Error> {
+        Ok(LeaderClient::new(self.connect(from, to).await?))
+    }
+
+    pub async fn publisher_client(
+        &self,
+        from: Service,
+        to: Service,
+    ) -> Result<PublisherClient<ClientChannel>, Error> {
+        Ok(PublisherClient::new(self.connect(from, to).await?))
+    }
+
+    pub async fn pir_client(
+        &self,
+        from: Service,
+        to: Service,
+    ) -> Result<PirClient<ClientChannel>, Error> {
+        Ok(PirClient::new(self.connect(from, to).await?))
+    }
+}
+
+/// A service that adds metadata to its requests, and counts and traces them.
+#[derive(Clone)]
+pub struct Instrumented<S> {
+    inner: S,
+    to: Service,
+    metrics: Arc<Metrics>,
+    metadata: Arc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for Instrumented<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Instrumented")
+            .field("inner", &self.inner)
+            .field("to", &self.to)
+            .finish()
+    }
+}
+
+fn instrumented<S>(
+    inner: S,
+    to: Service,
+    metrics: Arc<Metrics>,
+    metadata: Vec<(HeaderName, HeaderValue)>,
+) -> Instrumented<S> {
+    Instrumented {
+        inner,
+        to,
+        metrics,
+        metadata: Arc::new(metadata),
+    }
+}
+
+impl<S, B> tower::Service<Request<B>> for Instrumented<S>
+where
+    S: tower::Service<Request<B>, Error = BoxError>,
+    S::Response: Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<S::Response, BoxError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        for (key, value) in self.metadata.iter() {
+            request.headers_mut().insert(key.clone(), value.clone());
+        }
+        let path = request.uri().path().to_string();
+        trace!("Calling {} on {:?}.", path, self.to);
+        let to = self.to.clone();
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let result = response.await;
+            metrics.record(started.elapsed(), result.is_ok());
+            match &result {
+                Ok(_) => trace!("{} on {:?} done in {:?}.", path, to, started.elapsed()),
+                Err(err) => debug!("{} on {:?} failed: {}", path, to, err),
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{Group, WorkerInfo};
+    use port_check::free_local_port;
+    use tonic::Status;
+    use tower::{service_fn, ServiceExt};
+
+    fn worker() -> Service {
+        WorkerInfo::new(Group::new(0), 0).into()
+    }
+
+    #[tokio::test]
+    async fn test_instrumented() {
+        let metrics = Arc::new(Metrics::default());
+        let inner = service_fn(|request: Request<()>| async move {
+            match request.headers().get("x-token") {
+                Some(value) if value == "secret" => Ok(()),
+                _ => Err(BoxError::from(Status::unauthenticated("no token"))),
+            }
+        });
+        let metadata = vec![(
+            HeaderName::from_static("x-token"),
+            HeaderValue::from_static("secret"),
+        )];
+
+        let good = instrumented(inner, worker(), metrics.clone(), metadata);
+        for _ in 0..2 {
+            good.clone().oneshot(Request::new(())).await.unwrap();
+        }
+        let bad = instrumented(inner, worker(), metrics.clone(), vec![]);
+        bad.oneshot(Request::new(()))
+            .await
+            .expect_err("missing metadata");
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.requests, 3);
+        assert_eq!(snapshot.failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_connect_attempts() {
+        // Nothing listening.
+        let uri = format!("http://localhost:{}", free_local_port().unwrap());
+        let builder = Builder::new(uri).connect_attempts(3, Duration::from_millis(10));
+        let started = Instant::now();
+        builder.channel().await.expect_err("nothing to connect to");
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_bad_uri() {
+        Builder::new("not a uri")
+            .channel()
+            .await
+            .expect_err("bad uri");
+    }
+}
@@ -0,0 +1,293 @@
+//! Synthetic network latency, for in-process runs.
+//!
+//! With everything on localhost, `run_in_process` finishes unrealistically
+//! fast. `simulate_latency()` makes every gRPC client in the process wait a
+//! configurable delay (plus jitter) on each request, depending on which kind
+//! of service it's talking to, so local runs approximate a WAN deployment.
+//!
+//! The setting is process-wide: it's meant for development runs with every
+//! service in one process, not for real deployments.
+use crate::rt::sleep;
+use crate::services::Service;
+
+use futures::future::BoxFuture;
+use lazy_static::lazy_static;
+use rand::{thread_rng, Rng};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tonic::transport::Channel;
+use tower::Layer;
+
+lazy_static! {
+    static ref SIMULATED: RwLock<SimulatedLatency> = Default::default();
+}
+
+/// Add `latency` to all clients created from now on in this process.
+pub fn simulate_latency(latency: SimulatedLatency) {
+    *SIMULATED.write().unwrap() = latency;
+}
+
+/// The kind of service on the receiving end of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hop {
+    Worker,
+    Leader,
+    Publisher,
+}
+
+impl From<&Service> for Hop {
+    /// The kind of `service`. Panics for clients, which don't serve requests.
+    fn from(service: &Service) -> Self {
+        match service {
+            Service::Worker(_) => Hop::Worker,
+            Service::Leader(_) => Hop::Leader,
+            Service::Publisher(_) => Hop::Publisher,
+            Service::Client(_) => panic!("Clients don't serve requests."),
+        }
+    }
+}
+
+/// A delay, give or take up to `jitter` (uniformly).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    pub delay: Duration,
+    pub jitter: Duration,
+}
+
+impl Latency {
+    fn sample(&self) -> Duration {
+        if self.jitter == Duration::default() {
+            return self.delay;
+        }
+        let low = self.delay.checked_sub(self.jitter).unwrap_or_default();
+        let high = self.delay + self.jitter;
+        thread_rng().gen_range(low..=high)
+    }
+}
+
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit): (&str, fn(u64) -> Duration) = if let Some(n) = value.strip_suffix("ms") {
+        (n, Duration::from_millis)
+    } else if let Some(n) = value.strip_suffix("us") {
+        (n, Duration::from_micros)
+    } else if let Some(n) = value.strip_suffix('s') {
+        (n, Duration::from_secs)
+    } else {
+        return Err(format!("Missing unit (ms, us, or s) in [{}].", value));
+    };
+    number
+        .parse()
+        .map(unit)
+        .map_err(|err| format!("Bad duration [{}]: {}", value, err))
+}
+
+impl FromStr for Latency {
+    type Err = String;
+
+    /// Parse `40ms` or `40ms~5ms` (40ms, give or take 5ms).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (delay, jitter) = match s.split_once('~') {
+            Some((delay, jitter)) => (parse_duration(delay)?, parse_duration(jitter)?),
+            None => (parse_duration(s)?, Duration::default()),
+        };
+        Ok(Latency { delay, jitter })
+    }
+}
+
+/// Latency for requests to each kind of service (none if unset).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SimulatedLatency {
+    pub worker: Option<Latency>,
+    pub leader: Option<Latency>,
+    pub publisher: Option<Latency>,
+}
+
+impl SimulatedLatency {
+    pub fn get(&self, hop: Hop) -> Option<Latency> {
+        match hop {
+            Hop::Worker => self.worker,
+            Hop::Leader => self.leader,
+            Hop::Publisher => self.publisher,
+        }
+    }
+
+    pub fn layer(&self, hop: Hop) -> LatencyLayer {
+        LatencyLayer {
+            latency: self.get(hop),
+        }
+    }
+}
+
+impl FromStr for SimulatedLatency {
+    type Err = String;
+
+    /// Parse e.g. `leader=40ms,worker=10ms~2ms`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut latency = SimulatedLatency::default();
+        for entry in s.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (hop, value) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <service>=<latency>, got [{}].", entry))?;
+            let value = Some(value.parse()?);
+            match hop.trim() {
+                "worker" => latency.worker = value,
+                "leader" => latency.leader = value,
+                "publisher" => latency.publisher = value,
+                other => {
+                    return Err(format!(
+                        "Bad service [{}]; expected worker, leader, or publisher.",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(latency)
+    }
+}
+
+/// Tower layer adding latency to each request through a service.
+#[derive(Debug, Clone, Copy)]
+pub struct LatencyLayer {
+    latency: Option<Latency>,
+}
+
+impl<S> Layer<S> for LatencyLayer {
+    type Service = Delayed<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Delayed {
+            inner,
+            latency: self.latency,
+        }
+    }
+}
+
+/// A service whose requests take (at least) an extra round-trip `latency`:
+/// half on the way there, half on the way back.
+#[derive(Clone)]
+pub struct Delayed<S> {
+    inner: S,
+    latency: Option<Latency>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for Delayed<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Delayed")
+            .field("inner", &self.inner)
+            .field("latency", &self.latency)
+            .finish()
+    }
+}
+
+impl<S, Request> tower::Service<Request> for Delayed<S>
+where
+    S: tower::Service<Request> + Clone + Send + 'static,
+    S::Response: Send,
+    S::Error: Send,
+    S::Future: Send + 'static,
+    Request: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let delay = match self.latency {
+            Some(latency) => latency.sample() / 2,
+            None => return Box::pin(self.inner.call(request)),
+        };
+        // Take the service we polled ready, leaving a fresh clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            sleep(delay).await;
+            let response = inner.call(request).await;
+            sleep(delay).await;
+            response
+        })
+    }
+}
+
+/// Wrap `channel` (to a service of kind `hop`) in the simulated latency.
+pub(super) fn delayed(channel: Channel, hop: Hop) -> Delayed<Channel> {
+    SIMULATED.read().unwrap().layer(hop).layer(channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::time::Instant;
+    use tower::{service_fn, ServiceExt};
+
+    #[test]
+    fn test_parse_latency() {
+        let ms = Duration::from_millis;
+        assert_eq!(
+            "40ms".parse::<Latency>().unwrap(),
+            Latency {
+                delay: ms(40),
+                jitter: ms(0)
+            }
+        );
+        assert_eq!(
+            "1s~500us".parse::<Latency>().unwrap(),
+            Latency {
+                delay: ms(1000),
+                jitter: Duration::from_micros(500)
+            }
+        );
+        "40".parse::<Latency>().expect_err("missing unit");
+        "fast".parse::<Latency>().expect_err("not a number");
+    }
+
+    #[test]
+    fn test_parse_simulated_latency() {
+        let latency: SimulatedLatency = "leader=40ms,worker=10ms~2ms".parse().unwrap();
+        assert_eq!(latency.get(Hop::Leader), Some("40ms".parse().unwrap()));
+        assert_eq!(latency.get(Hop::Worker), Some("10ms~2ms".parse().unwrap()));
+        assert_eq!(latency.get(Hop::Publisher), None);
+
+        assert_eq!("".parse(), Ok(SimulatedLatency::default()));
+        "client=10ms"
+            .parse::<SimulatedLatency>()
+            .expect_err("not a service that gets requests");
+        "leader:10ms"
+            .parse::<SimulatedLatency>()
+            .expect_err("missing =");
+    }
+
+    #[test]
+    fn test_sample_within_jitter() {
+        let latency: Latency = "10ms~5ms".parse().unwrap();
+        for _ in 0..100 {
+            let delay = latency.sample();
+            assert!(delay >= Duration::from_millis(5) && delay <= Duration::from_millis(15));
+        }
+        // Jitter past the delay doesn't go negative.
+        let latency: Latency = "1ms~5ms".parse().unwrap();
+        assert!(latency.sample() <= Duration::from_millis(6));
+    }
+
+    #[tokio::test]
+    async fn test_delayed() {
+        let echo = service_fn(|request: u32| async move { Ok::<_, Infallible>(request) });
+        let layer = LatencyLayer {
+            latency: Some("20ms".parse().unwrap()),
+        };
+        let start = Instant::now();
+        assert_eq!(layer.layer(echo).oneshot(7).await, Ok(7));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let layer = LatencyLayer { latency: None };
+        assert_eq!(layer.layer(echo).oneshot(7).await, Ok(7));
+    }
+}
@@ -0,0 +1,224 @@
+//! Caps on the connections a server accepts.
+//!
+//! Past `max_connections`, the server stops accepting (new connections queue
+//! in the listen backlog) until one closes. Past `max_connections_per_ip`,
+//! new connections from that address are closed right away, so that one busy
+//! client machine can't starve everybody else.
+use crate::rt::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    TcpListenerStream, TcpStream,
+};
+
+use futures::{ready, Stream};
+use log::warn;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tonic::transport::server::Connected;
+
+#[derive(Debug, Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    // Waiting for a connection to close.
+    waker: Option<Waker>,
+}
+
+/// Keeps a connection counted until dropped.
+#[derive(Debug)]
+struct Slot {
+    counts: Arc<Mutex<Counts>>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.total -= 1;
+        if let Some(ip) = self.ip {
+            let remaining = counts.per_ip.get_mut(&ip).expect("counted on accept");
+            *remaining -= 1;
+            if *remaining == 0 {
+                counts.per_ip.remove(&ip);
+            }
+        }
+        if let Some(waker) = counts.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Incoming connections, subject to limits.
+pub struct Incoming {
+    inner: TcpListenerStream,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl Incoming {
+    pub fn new(
+        inner: TcpListenerStream,
+        max_connections: Option<usize>,
+        max_connections_per_ip: Option<usize>,
+    ) -> Self {
+        Incoming {
+            inner,
+            max_connections,
+            max_connections_per_ip,
+            counts: Default::default(),
+        }
+    }
+
+    /// Count a connection from `ip`, unless that address is over its limit.
+    fn admit(&self, ip: Option<IpAddr>) -> Option<Slot> {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(ip) = ip {
+            let count = counts.per_ip.entry(ip).or_default();
+            if matches!(self.max_connections_per_ip, Some(max) if *count >= max) {
+                return None;
+            }
+            *count += 1;
+        }
+        counts.total += 1;
+        Some(Slot {
+            counts: self.counts.clone(),
+            ip,
+        })
+    }
+
+    /// Whether we're at `max_connections` (if so, wake `cx` once we aren't).
+    fn at_capacity(&self, cx: &Context<'_>) -> bool {
+        let max = match self.max_connections {
+            Some(max) => max,
+            None => return false,
+        };
+        let mut counts = self.counts.lock().unwrap();
+        if counts.total < max {
+            return false;
+        }
+        counts.waker = Some(cx.waker().clone());
+        true
+    }
+}
+
+impl Stream for Incoming {
+    type Item = io::Result<Connection>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.at_capacity(cx) {
+                return Poll::Pending;
+            }
+            let stream = match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(stream)) => stream,
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            };
+            let ip = stream.peer_addr().ok().map(|addr| addr.ip());
+            match self.admit(ip) {
+                Some(slot) => {
+                    return Poll::Ready(Some(Ok(Connection {
+                        stream,
+                        _slot: slot,
+                    })))
+                }
+                None => warn!("Too many connections from {:?}; closing.", ip),
+            }
+        }
+    }
+}
+
+/// An accepted connection, which counts against the limits while open.
+#[derive(Debug)]
+pub struct Connection {
+    stream: TcpStream,
+    _slot: Slot,
+}
+
+impl Connected for Connection {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr().ok()
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::TcpListener;
+    use futures::{FutureExt, StreamExt};
+
+    async fn incoming(
+        max_connections: Option<usize>,
+        max_connections_per_ip: Option<usize>,
+    ) -> (Incoming, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = Incoming::new(
+            TcpListenerStream::new(listener),
+            max_connections,
+            max_connections_per_ip,
+        );
+        (incoming, addr)
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let (mut incoming, addr) = incoming(Some(1), None).await;
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let _second = TcpStream::connect(addr).await.unwrap();
+
+        let accepted = incoming.next().await.unwrap().unwrap();
+        // At capacity: the second connection waits in the backlog...
+        assert!(incoming.next().now_or_never().is_none());
+        // ...until the first one closes.
+        drop(accepted);
+        incoming.next().await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_connections_per_ip() {
+        let (mut incoming, addr) = incoming(None, Some(1)).await;
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let accepted = incoming.next().await.unwrap().unwrap();
+
+        // Same address: closed on accept.
+        let _second = TcpStream::connect(addr).await.unwrap();
+        assert!(incoming.next().now_or_never().is_none());
+
+        drop(accepted);
+        let _third = TcpStream::connect(addr).await.unwrap();
+        incoming.next().await.unwrap().unwrap();
+    }
+}
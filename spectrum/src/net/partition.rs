@@ -0,0 +1,344 @@
+//! Simulated network partitions, for in-process runs.
+//!
+//! To exercise retries and failover without real infrastructure, a
+//! [`PartitionSchedule`] cuts the links between sets of services for windows
+//! of time (measured from when the schedule starts). While a link is cut,
+//! requests across it (either way) fail right away with `UNAVAILABLE`, as if
+//! the connection had been refused. Requests already in flight when a cut
+//! starts still complete.
+//!
+//! Like simulated latency, the schedule is process-wide.
+use crate::services::{Group, Service, WorkerInfo};
+
+use futures::future::{self, BoxFuture, TryFutureExt};
+use lazy_static::lazy_static;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tonic::Status;
+use tower::BoxError;
+
+use super::latency::parse_duration;
+
+lazy_static! {
+    static ref ACTIVE: RwLock<Option<(Instant, PartitionSchedule)>> = Default::default();
+}
+
+/// Follow `schedule` (with times relative to now) for all clients in this
+/// process, replacing any previous schedule.
+pub fn simulate_partitions(schedule: PartitionSchedule) {
+    *ACTIVE.write().unwrap() = Some((Instant::now(), schedule));
+}
+
+/// Stop simulating partitions.
+pub fn heal_partitions() {
+    ACTIVE.write().unwrap().take();
+}
+
+fn severed(from: &Service, to: &Service) -> bool {
+    match ACTIVE.read().unwrap().as_ref() {
+        Some((start, schedule)) => schedule.severs(start.elapsed(), from, to),
+        None => false,
+    }
+}
+
+/// A set of services on one side of a cut.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// Every publisher.
+    Publishers,
+    /// Every client.
+    Clients,
+    /// A group's leader.
+    Leader(Group),
+    /// A group's leader and all of its workers.
+    Group(Group),
+    Worker(WorkerInfo),
+}
+
+impl Side {
+    pub fn contains(&self, service: &Service) -> bool {
+        match (self, service) {
+            (Side::Publishers, Service::Publisher(_)) => true,
+            (Side::Clients, Service::Client(_)) => true,
+            (Side::Leader(group), Service::Leader(info)) => info.group == *group,
+            (Side::Group(group), Service::Leader(info)) => info.group == *group,
+            (Side::Group(group), Service::Worker(info)) => info.group == *group,
+            (Side::Worker(worker), Service::Worker(info)) => info == worker,
+            _ => false,
+        }
+    }
+}
+
+// 1-based, like the services' `--group` and `--index` flags.
+fn parse_index(value: &str) -> Result<u16, String> {
+    match value.parse::<u16>() {
+        Ok(idx) if idx > 0 => Ok(idx - 1),
+        _ => Err(format!(
+            "Bad index [{}]; expected a positive integer.",
+            value
+        )),
+    }
+}
+
+impl FromStr for Side {
+    type Err = String;
+
+    /// Parse `publishers`, `clients`, `leader<g>`, `group<g>`, or
+    /// `worker<g>.<i>` (all 1-based).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "publishers" {
+            Ok(Side::Publishers)
+        } else if s == "clients" {
+            Ok(Side::Clients)
+        } else if let Some(group) = s.strip_prefix("leader") {
+            Ok(Side::Leader(Group::new(parse_index(group)?)))
+        } else if let Some(group) = s.strip_prefix("group") {
+            Ok(Side::Group(Group::new(parse_index(group)?)))
+        } else if let Some(worker) = s.strip_prefix("worker") {
+            let (group, idx) = worker
+                .split_once('.')
+                .ok_or_else(|| format!("Expected worker<group>.<index>, got [{}].", s))?;
+            let group = Group::new(parse_index(group)?);
+            Ok(Side::Worker(WorkerInfo::new(group, parse_index(idx)?)))
+        } else {
+            Err(format!(
+                "Bad side [{}]; expected publishers, clients, leader<g>, group<g>, or worker<g>.<i>.",
+                s
+            ))
+        }
+    }
+}
+
+/// No requests between `a` and `b` (either way) from `start` until `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cut {
+    pub a: Side,
+    pub b: Side,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl Cut {
+    pub fn severs(&self, elapsed: Duration, from: &Service, to: &Service) -> bool {
+        (self.start..self.end).contains(&elapsed)
+            && ((self.a.contains(from) && self.b.contains(to))
+                || (self.a.contains(to) && self.b.contains(from)))
+    }
+}
+
+impl FromStr for Cut {
+    type Err = String;
+
+    /// Parse `<a>/<b>@<start>..<end>`, e.g. `group2/leader1@2s..4s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sides, window) = s
+            .split_once('@')
+            .ok_or_else(|| format!("Expected <side>/<side>@<start>..<end>, got [{}].", s))?;
+        let (a, b) = sides
+            .split_once('/')
+            .ok_or_else(|| format!("Expected <side>/<side>, got [{}].", sides))?;
+        let (start, end) = window
+            .split_once("..")
+            .ok_or_else(|| format!("Expected <start>..<end>, got [{}].", window))?;
+        let (start, end) = (parse_duration(start)?, parse_duration(end)?);
+        if end <= start {
+            return Err(format!("Cut [{}] ends before it starts.", s));
+        }
+        Ok(Cut {
+            a: a.parse()?,
+            b: b.parse()?,
+            start,
+            end,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PartitionSchedule {
+    pub cuts: Vec<Cut>,
+}
+
+impl PartitionSchedule {
+    /// Whether requests from `from` to `to` fail at `elapsed`.
+    pub fn severs(&self, elapsed: Duration, from: &Service, to: &Service) -> bool {
+        self.cuts.iter().any(|cut| cut.severs(elapsed, from, to))
+    }
+}
+
+impl FromStr for PartitionSchedule {
+    type Err = String;
+
+    /// Parse comma-separated cuts, e.g. `group2/leader1@2s..4s,clients/group1@0s..1s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cuts = s
+            .split(',')
+            .filter(|cut| !cut.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(PartitionSchedule { cuts })
+    }
+}
+
+/// A service whose requests from `from` to `to` fail while the link between
+/// them is cut.
+#[derive(Clone)]
+pub struct Gated<S> {
+    inner: S,
+    from: Service,
+    to: Service,
+}
+
+impl<S: fmt::Debug> fmt::Debug for Gated<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Gated")
+            .field("inner", &self.inner)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .finish()
+    }
+}
+
+impl<S, Request> tower::Service<Request> for Gated<S>
+where
+    S: tower::Service<Request>,
+    S::Response: Send + 'static,
+    S::Error: Into<BoxError> + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<S::Response, BoxError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        if severed(&self.from, &self.to) {
+            let status = Status::unavailable("Simulated network partition.");
+            return Box::pin(future::err::<S::Response, BoxError>(status.into()));
+        }
+        Box::pin(self.inner.call(request).map_err(Into::<BoxError>::into))
+    }
+}
+
+/// Gate `inner` (a client of `to`, running as `from`) on the simulated
+/// partitions.
+pub fn gated<S>(inner: S, from: Service, to: Service) -> Gated<S> {
+    Gated { inner, from, to }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{ClientInfo, LeaderInfo, PublisherInfo};
+    use std::convert::Infallible;
+    use tower::{service_fn, ServiceExt};
+
+    fn worker(group: u16, idx: u16) -> Service {
+        WorkerInfo::new(Group::new(group), idx).into()
+    }
+
+    fn leader(group: u16) -> Service {
+        LeaderInfo::new(Group::new(group)).into()
+    }
+
+    #[test]
+    fn test_parse_side() {
+        assert_eq!("publishers".parse(), Ok(Side::Publishers));
+        assert_eq!("leader1".parse(), Ok(Side::Leader(Group::new(0))));
+        assert_eq!("group2".parse(), Ok(Side::Group(Group::new(1))));
+        assert_eq!(
+            "worker2.3".parse(),
+            Ok(Side::Worker(WorkerInfo::new(Group::new(1), 2)))
+        );
+        "leader0".parse::<Side>().expect_err("1-based");
+        "worker2".parse::<Side>().expect_err("missing index");
+        "router1"
+            .parse::<Side>()
+            .expect_err("not a kind of service");
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        let schedule: PartitionSchedule = "group2/leader1@2s..4s, clients/publishers@0s..500ms"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            schedule.cuts,
+            vec![
+                Cut {
+                    a: Side::Group(Group::new(1)),
+                    b: Side::Leader(Group::new(0)),
+                    start: Duration::from_secs(2),
+                    end: Duration::from_secs(4),
+                },
+                Cut {
+                    a: Side::Clients,
+                    b: Side::Publishers,
+                    start: Duration::from_secs(0),
+                    end: Duration::from_millis(500),
+                },
+            ]
+        );
+        assert_eq!("".parse(), Ok(PartitionSchedule::default()));
+        "group1/leader1"
+            .parse::<PartitionSchedule>()
+            .expect_err("missing window");
+        "group1@1s..2s"
+            .parse::<PartitionSchedule>()
+            .expect_err("missing second side");
+        "group1/leader1@2s..1s"
+            .parse::<PartitionSchedule>()
+            .expect_err("ends before it starts");
+    }
+
+    #[test]
+    fn test_cut_severs() {
+        let cut: Cut = "group1/leader2@1s..2s".parse().unwrap();
+        let during = Duration::from_millis(1500);
+        assert!(cut.severs(during, &worker(0, 3), &leader(1)));
+        assert!(cut.severs(during, &leader(1), &leader(0)));
+        // Cuts go both ways.
+        assert!(cut.severs(during, &leader(1), &worker(0, 0)));
+        // But only between the two sides...
+        assert!(!cut.severs(during, &worker(0, 0), &leader(0)));
+        assert!(!cut.severs(during, &worker(1, 0), &leader(1)));
+        let publisher = PublisherInfo::new(0).into();
+        assert!(!cut.severs(during, &worker(0, 0), &publisher));
+        let client = ClientInfo::new(0).into();
+        assert!(!cut.severs(during, &client, &worker(0, 0)));
+        // ...and only during the window.
+        assert!(!cut.severs(Duration::from_millis(500), &worker(0, 0), &leader(1)));
+        assert!(!cut.severs(Duration::from_secs(2), &worker(0, 0), &leader(1)));
+    }
+
+    #[tokio::test]
+    async fn test_gated() {
+        // The schedule is process-wide, so stay clear of links that other
+        // tests' services use.
+        let (from, to) = (worker(8, 8), leader(8));
+        let echo = service_fn(|request: u32| async move { Ok::<_, Infallible>(request) });
+        let gated_echo = |from: &Service, to: &Service| gated(echo, from.clone(), to.clone());
+        assert_eq!(gated_echo(&from, &to).oneshot(7).await.unwrap(), 7);
+
+        simulate_partitions("worker9.9/leader9@0s..3600s".parse().unwrap());
+        let err = gated_echo(&from, &to).oneshot(7).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Status>().unwrap().code(),
+            tonic::Code::Unavailable
+        );
+        // Other links are unaffected.
+        assert_eq!(
+            gated_echo(&from, &worker(8, 7)).oneshot(7).await.unwrap(),
+            7
+        );
+
+        heal_partitions();
+        assert_eq!(gated_echo(&from, &to).oneshot(7).await.unwrap(), 7);
+    }
+}
@@ -0,0 +1,391 @@
+//! Shared-memory rings, for messages between processes on the same host.
+//!
+//! A receiver creates an inbox (a file in `/dev/shm`, mapped into memory) and
+//! advertises it, along with its host's machine ID, in discovery. Senders on
+//! the same host map the same file and write messages straight into it, which
+//! skips the HTTP/2 (and TLS) round trip through loopback.
+//!
+//! The inbox is a multi-producer, single-consumer ring buffer. Producers
+//! reserve space by bumping a shared counter, copy their message in, and then
+//! mark it ready; the receiver polls for ready messages in order. If a sender
+//! dies between reserving and marking, the messages behind it are stuck, so
+//! this is only meant for processes that live and die together (e.g. the
+//! workers of one experiment).
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+const MAGIC: u64 = 0x7370_6563_7472_756d; // "spectrum"
+const HEADER_LEN: usize = 64;
+// State of a slot: not written yet (or already read)...
+const EMPTY: u32 = 0;
+// ...or padding up to the end of the buffer (messages don't wrap around).
+const PAD: u32 = u32::MAX;
+// Otherwise, it's a message of length (state - 1).
+
+/// Default inbox size.
+pub const DEFAULT_CAPACITY: usize = 16 << 20;
+
+/// Where a receiver's inbox is, and which host it's on.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ShmInbox {
+    pub machine_id: String,
+    pub path: String,
+}
+
+/// An ID for this host, shared by all of its processes.
+///
+/// From `/etc/machine-id` (systemd) or `/var/lib/dbus/machine-id`.
+pub fn machine_id() -> Option<String> {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|id| id.trim().to_string())
+        .find(|id| !id.is_empty())
+}
+
+fn inbox_dir() -> PathBuf {
+    let shm = Path::new("/dev/shm");
+    if shm.is_dir() {
+        shm.to_path_buf()
+    } else {
+        std::env::temp_dir()
+    }
+}
+
+// Messages start (and so, are padded to) 8-byte boundaries, after a 4-byte
+// state and 4 bytes of padding.
+fn slot_len(msg_len: usize) -> usize {
+    8 + ((msg_len + 7) & !7)
+}
+
+/// A memory-mapped ring buffer.
+#[derive(Debug)]
+struct Ring {
+    base: *mut u8,
+    len: usize,
+    capacity: usize,
+}
+
+// All shared state is accessed through atomics (or behind them, for message
+// bodies).
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn map(file: &File, capacity: usize) -> io::Result<Ring> {
+        let len = HEADER_LEN + capacity;
+        // SAFETY: we map a file we have open for reading and writing, at a
+        // fresh address.
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Ring {
+            base: base as *mut u8,
+            len,
+            capacity,
+        })
+    }
+
+    // SAFETY (for the accessors below): offsets are in bounds and 8-byte
+    // aligned, and the mapping is page-aligned.
+    fn header_u64(&self, idx: usize) -> &AtomicU64 {
+        assert!(idx < HEADER_LEN / 8);
+        unsafe { &*(self.base.add(8 * idx) as *const AtomicU64) }
+    }
+
+    fn magic(&self) -> &AtomicU64 {
+        self.header_u64(0)
+    }
+
+    fn stored_capacity(&self) -> &AtomicU64 {
+        self.header_u64(1)
+    }
+
+    /// Where the next producer writes (total bytes reserved so far).
+    fn reserved(&self) -> &AtomicU64 {
+        self.header_u64(2)
+    }
+
+    /// Where the consumer reads next (total bytes consumed so far).
+    fn consumed(&self) -> &AtomicU64 {
+        self.header_u64(3)
+    }
+
+    fn state(&self, offset: usize) -> &AtomicU32 {
+        assert!(offset & 7 == 0 && offset + 8 <= self.capacity);
+        unsafe { &*(self.base.add(HEADER_LEN + offset) as *const AtomicU32) }
+    }
+
+    fn body(&self, offset: usize, len: usize) -> *mut u8 {
+        assert!(offset & 7 == 0 && offset + slot_len(len) <= self.capacity);
+        unsafe { self.base.add(HEADER_LEN + offset + 8) }
+    }
+
+    fn offset(&self, pos: u64) -> usize {
+        (pos % self.capacity as u64) as usize
+    }
+
+    /// Write `msg`, unless the ring is too full for it.
+    fn push(&self, msg: &[u8]) -> bool {
+        let len = slot_len(msg.len());
+        // Leave room for padding the end, too.
+        if len > self.capacity / 2 {
+            return false;
+        }
+        let offset = loop {
+            let pos = self.reserved().load(Ordering::Acquire);
+            let offset = self.offset(pos);
+            let contiguous = self.capacity - offset;
+            let needed = if len <= contiguous {
+                len
+            } else {
+                contiguous + len
+            };
+            let consumed = self.consumed().load(Ordering::Acquire);
+            if pos + needed as u64 - consumed > self.capacity as u64 {
+                return false;
+            }
+            let next = pos + needed as u64;
+            if self
+                .reserved()
+                .compare_exchange(pos, next, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                break offset;
+            }
+        };
+        let offset = if len <= self.capacity - offset {
+            offset
+        } else {
+            self.state(offset).store(PAD, Ordering::Release);
+            0
+        };
+        // SAFETY: we reserved this slot, and the consumer won't read it until
+        // its state says it's ready.
+        unsafe {
+            ptr::copy_nonoverlapping(msg.as_ptr(), self.body(offset, msg.len()), msg.len());
+        }
+        let state: u32 = (msg.len() + 1).try_into().expect("message too long");
+        self.state(offset).store(state, Ordering::Release);
+        true
+    }
+
+    /// Read the next message, if it's ready. Only one consumer may call this.
+    fn pop(&self) -> Option<Vec<u8>> {
+        loop {
+            let pos = self.consumed().load(Ordering::Acquire);
+            if pos == self.reserved().load(Ordering::Acquire) {
+                return None;
+            }
+            let offset = self.offset(pos);
+            let state = self.state(offset).load(Ordering::Acquire);
+            match state {
+                EMPTY => return None, // reserved, but not written yet
+                PAD => {
+                    self.state(offset).store(EMPTY, Ordering::Relaxed);
+                    let skipped = (self.capacity - offset) as u64;
+                    self.consumed().store(pos + skipped, Ordering::Release);
+                }
+                _ => {
+                    let len = (state - 1) as usize;
+                    let mut msg = vec![0u8; len];
+                    // SAFETY: the producer marked this slot ready, and won't
+                    // touch it again.
+                    unsafe {
+                        ptr::copy_nonoverlapping(self.body(offset, len), msg.as_mut_ptr(), len);
+                    }
+                    self.state(offset).store(EMPTY, Ordering::Relaxed);
+                    let read = slot_len(len) as u64;
+                    self.consumed().store(pos + read, Ordering::Release);
+                    return Some(msg);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // SAFETY: we mapped exactly this range in map().
+        unsafe {
+            libc::munmap(self.base as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// The receiving end of an inbox; removes its file when dropped.
+#[derive(Debug)]
+pub struct Receiver {
+    ring: Ring,
+    path: PathBuf,
+}
+
+impl Receiver {
+    /// Create a fresh inbox named after `name` (which should be unique on this
+    /// host).
+    pub fn create(name: &str, capacity: usize) -> io::Result<Receiver> {
+        assert!(capacity & 7 == 0, "capacity must be a multiple of 8");
+        let path = inbox_dir().join(format!("spectrum-{}-{}.ring", name, std::process::id()));
+        // Start from zeros, even if a stale file's in the way.
+        let _ = fs::remove_file(&path);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.set_len((HEADER_LEN + capacity) as u64)?;
+        let ring = Ring::map(&file, capacity)?;
+        ring.stored_capacity()
+            .store(capacity as u64, Ordering::Relaxed);
+        ring.magic().store(MAGIC, Ordering::Release);
+        Ok(Receiver { ring, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The next message, if any is ready.
+    pub fn try_recv(&self) -> Option<Vec<u8>> {
+        self.ring.pop()
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The sending end of somebody's inbox.
+#[derive(Debug)]
+pub struct Sender {
+    ring: Ring,
+}
+
+impl Sender {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Sender> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len: usize = file
+            .metadata()?
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "inbox too big"))?;
+        if len <= HEADER_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an inbox"));
+        }
+        let ring = Ring::map(&file, len - HEADER_LEN)?;
+        if ring.magic().load(Ordering::Acquire) != MAGIC
+            || ring.stored_capacity().load(Ordering::Relaxed) != ring.capacity as u64
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an inbox"));
+        }
+        Ok(Sender { ring })
+    }
+
+    /// Send `msg`, returning false if the inbox is too full for it (in which
+    /// case, fall back to some other transport).
+    pub fn try_send(&self, msg: &[u8]) -> bool {
+        self.ring.push(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_send_recv() {
+        let receiver = Receiver::create("test-send-recv", 1024).unwrap();
+        let sender = Sender::open(receiver.path()).unwrap();
+        assert_eq!(receiver.try_recv(), None);
+        assert!(sender.try_send(b"hello"));
+        assert!(sender.try_send(b""));
+        assert_eq!(receiver.try_recv(), Some(b"hello".to_vec()));
+        assert_eq!(receiver.try_recv(), Some(vec![]));
+        assert_eq!(receiver.try_recv(), None);
+    }
+
+    #[test]
+    fn test_full_and_wraparound() {
+        let receiver = Receiver::create("test-wraparound", 256).unwrap();
+        let sender = Sender::open(receiver.path()).unwrap();
+        let msg = [7u8; 60]; // 72 bytes with its state
+        assert!(!sender.try_send(&[0u8; 200]), "too big for the ring");
+        for _ in 0..10 {
+            assert!(sender.try_send(&msg));
+            assert!(sender.try_send(&msg));
+            assert!(sender.try_send(&msg));
+            assert!(!sender.try_send(&msg), "ring should be full");
+            for _ in 0..3 {
+                assert_eq!(receiver.try_recv(), Some(msg.to_vec()));
+            }
+            assert_eq!(receiver.try_recv(), None);
+        }
+    }
+
+    #[test]
+    fn test_open_bad_inbox() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        file.as_file().set_len(1024).unwrap();
+        Sender::open(file.path()).expect_err("not an inbox");
+    }
+
+    #[test]
+    fn test_many_senders() {
+        let receiver = Receiver::create("test-many-senders", 4096).unwrap();
+        let senders = 4u32;
+        let per_sender = 1000u32;
+        let handles: Vec<_> = (0..senders)
+            .map(|idx| {
+                let sender = Arc::new(Sender::open(receiver.path()).unwrap());
+                thread::spawn(move || {
+                    for count in 0..per_sender {
+                        let msg = [idx.to_le_bytes(), count.to_le_bytes()].concat();
+                        while !sender.try_send(&msg) {
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        // Each sender's messages arrive in order.
+        let mut next = vec![0u32; senders as usize];
+        let mut received = 0;
+        while received < senders * per_sender {
+            match receiver.try_recv() {
+                Some(msg) => {
+                    let idx = u32::from_le_bytes(msg[..4].try_into().unwrap()) as usize;
+                    let count = u32::from_le_bytes(msg[4..].try_into().unwrap());
+                    assert_eq!(count, next[idx]);
+                    next[idx] += 1;
+                    received += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
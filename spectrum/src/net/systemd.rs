@@ -0,0 +1,166 @@
+//! Running under systemd: readiness notifications (`sd_notify`) and socket
+//! activation (`sd_listen_fds`).
+//!
+//! Both are no-ops outside of systemd (when `$NOTIFY_SOCKET` and `$LISTEN_FDS`
+//! aren't set), so services use them unconditionally. With `Type=notify`,
+//! `systemctl start` returns once the service is registered, so units can be
+//! ordered on each other instead of on sleeps.
+use log::{debug, warn};
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::mem;
+use std::net::TcpListener;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+
+/// The first file descriptor systemd passes (after stdin/stdout/stderr).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Send `state` (newline-separated assignments, e.g. `READY=1`) to systemd.
+///
+/// Returns whether there was a `$NOTIFY_SOCKET` to send it to.
+pub fn notify(state: &str) -> io::Result<bool> {
+    match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => send(&path, state.as_bytes()).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+fn notify_or_warn(state: &str) {
+    match notify(state) {
+        Ok(true) => debug!("Notified systemd: {}", state),
+        Ok(false) => {}
+        Err(err) => warn!("Couldn't notify systemd ({}): {}", state, err),
+    }
+}
+
+/// Tell systemd that the service is up (serving and registered).
+pub fn ready() {
+    notify_or_warn("READY=1");
+}
+
+/// Tell systemd that the service is shutting down.
+pub fn stopping() {
+    notify_or_warn("STOPPING=1");
+}
+
+// Send one datagram to the socket at `path` (in the abstract namespace if it
+// starts with `@`), which std can't address.
+fn send(path: &OsStr, msg: &[u8]) -> io::Result<()> {
+    let mut path = path.as_bytes().to_vec();
+    if path.first() == Some(&b'@') {
+        path[0] = 0;
+    }
+    // Safe: sockaddr_un is plain old data.
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    if path.is_empty() || path.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "bad $NOTIFY_SOCKET",
+        ));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(path.iter()) {
+        *dst = *src as libc::c_char;
+    }
+    let len = mem::size_of::<libc::sa_family_t>() + path.len();
+
+    let socket = UnixDatagram::unbound()?;
+    // Safe: `msg` and `addr` outlive the call, and `len` is within `addr`.
+    let sent = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            msg.as_ptr() as *const libc::c_void,
+            msg.len(),
+            libc::MSG_NOSIGNAL,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            len as libc::socklen_t,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// How many sockets systemd passed this process, given `$LISTEN_PID` and
+/// `$LISTEN_FDS`.
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, my_pid: u32) -> io::Result<usize> {
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(0),
+    };
+    // Inherited from a parent that was socket-activated itself.
+    if pid.parse::<u32>().ok() != Some(my_pid) {
+        return Ok(0);
+    }
+    fds.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Bad $LISTEN_FDS [{}].", fds),
+        )
+    })
+}
+
+/// The listening socket systemd passed this process (socket activation), if
+/// any.
+///
+/// Only the first socket is used. Clears `$LISTEN_FDS` (and friends) so it
+/// can't be taken twice.
+pub fn take_listener() -> io::Result<Option<TcpListener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    let count = listen_fds(pid.as_deref(), fds.as_deref(), std::process::id())?;
+    for var in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    if count == 0 {
+        return Ok(None);
+    }
+    if count > 1 {
+        warn!("Got {} sockets from systemd; using only the first.", count);
+    }
+    // Safe: systemd hands us ownership of the descriptors it passes, and the
+    // environment (now cleared) is the only record of them.
+    let listener = unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    // Also checks that it's really a socket.
+    let addr = listener.local_addr()?;
+    debug!("Listening on {} (from systemd).", addr);
+    Ok(Some(listener))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_fds() {
+        assert_eq!(listen_fds(None, None, 10).unwrap(), 0);
+        assert_eq!(listen_fds(Some("10"), None, 10).unwrap(), 0);
+        assert_eq!(listen_fds(Some("10"), Some("2"), 10).unwrap(), 2);
+        // For some other process.
+        assert_eq!(listen_fds(Some("11"), Some("2"), 10).unwrap(), 0);
+        listen_fds(Some("10"), Some("lots"), 10).expect_err("bad count");
+    }
+
+    #[test]
+    fn test_send() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        send(path.as_os_str(), b"READY=1").unwrap();
+
+        let mut buf = [0; 16];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+    }
+
+    #[test]
+    fn test_send_bad_path() {
+        send(OsStr::new(""), b"READY=1").expect_err("empty path");
+        let dir = tempfile::tempdir().unwrap();
+        send(dir.path().join("nobody").as_os_str(), b"READY=1").expect_err("nothing listening");
+    }
+}
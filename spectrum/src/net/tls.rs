@@ -0,0 +1,74 @@
+//! TLS between services (with the `tls` feature).
+//!
+//! Without the feature, [`Certificate`] and [`Identity`] are stand-ins that
+//! can't be constructed: every `Option<Certificate>` is `None`, and services
+//! only speak plaintext.
+use tonic::transport::{Endpoint, Error, Server};
+
+#[cfg(feature = "tls")]
+pub use tonic::transport::{Certificate, Identity};
+#[cfg(feature = "tls")]
+use tonic::transport::{ClientTlsConfig, ServerTlsConfig};
+
+#[cfg(not(feature = "tls"))]
+use log::warn;
+
+/// Domain name on our certificates.
+#[cfg(feature = "tls")]
+const TLS_DOMAIN: &str = "spectrum.example.com";
+
+#[cfg(not(feature = "tls"))]
+#[derive(Debug, Clone)]
+pub enum Certificate {}
+
+#[cfg(not(feature = "tls"))]
+impl Certificate {
+    pub fn get_ref(&self) -> &[u8] {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+#[derive(Debug, Clone)]
+pub enum Identity {}
+
+/// The certificate pinned (as PEM) in a node's config entry.
+#[cfg(feature = "tls")]
+pub fn pinned(pem: &str) -> Option<Certificate> {
+    Some(Certificate::from_pem(pem))
+}
+
+/// The certificate pinned (as PEM) in a node's config entry.
+///
+/// Built without TLS, so there's nothing to verify it with.
+#[cfg(not(feature = "tls"))]
+pub fn pinned(_pem: &str) -> Option<Certificate> {
+    warn!("Ignoring pinned certificate: built without TLS (the `tls` feature).");
+    None
+}
+
+/// Verify the server at `endpoint` against `cert`.
+#[cfg(feature = "tls")]
+pub(crate) fn client(endpoint: Endpoint, cert: &Certificate) -> Result<Endpoint, Error> {
+    endpoint.tls_config(
+        ClientTlsConfig::new()
+            .domain_name(TLS_DOMAIN)
+            .ca_certificate(cert.clone()),
+    )
+}
+
+#[cfg(not(feature = "tls"))]
+pub(crate) fn client(_endpoint: Endpoint, cert: &Certificate) -> Result<Endpoint, Error> {
+    match *cert {}
+}
+
+/// Serve with `identity`.
+#[cfg(feature = "tls")]
+pub(crate) fn server(builder: Server, identity: Identity) -> Result<Server, Error> {
+    builder.tls_config(ServerTlsConfig::new().identity(identity))
+}
+
+#[cfg(not(feature = "tls"))]
+pub(crate) fn server(_builder: Server, identity: Identity) -> Result<Server, Error> {
+    match identity {}
+}
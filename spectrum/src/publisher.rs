@@ -1,34 +1,194 @@
 use crate::proto::{
-    expect_field,
+    expect_field, get_stats_response,
+    pir_server::PirServer,
     publisher_server::{Publisher, PublisherServer},
-    AggregateGroupRequest, AggregateGroupResponse, Share,
+    AbortRoundRequest, AbortRoundResponse, AggregateGroupRequest, AggregateGroupResponse,
+    AuditFailures, GetStatsRequest, GetStatsResponse, ReportStatsRequest, ReportStatsResponse,
+    Share, SlotAssignmentsRequest, SlotAssignmentsResponse, WorkerId,
 };
 use crate::{
     accumulator::Accumulator,
     config::store::Store,
-    experiment,
-    net::Config as NetConfig,
+    delta::{self, Payload},
+    experiment::{self, HammerConfig},
+    metadata::RunMetadata,
+    net::{systemd, tls, Config as NetConfig},
     protocols::{wrapper::ProtocolWrapper, Protocol},
     services::{
-        discovery::{register, Node},
+        abort::{abort_round, watch_for_abort, AbortNotice, CancellationToken},
+        admin::{AdminServer, LogAdmin},
+        budget::{StageBudgets, StageReport, StageTallies},
+        checksum,
+        compaction::compact_round,
+        decoding::{ChannelDecoders, DecodedChannel, Decoders},
+        discovery::{register, Discovery, Node},
+        election::{Campaign, ElectionPolicy},
         health::{wait_for_health, AllGoodHealthServer, HealthServer},
-        quorum::{delay_until, set_start_time, wait_for_quorum},
-        PublisherInfo,
+        manifest::{
+            publish_manifest, ExpectedTraffic, Manifest, ManifestSigner, SignedManifest, TrafficMix,
+        },
+        pir::PirService,
+        privacy::{Accountant, PrivacyBudget},
+        quorum::{
+            claim_start_time, delay_until, wait_for_quorum, wait_for_start_time_set, QuorumPolicy,
+        },
+        registration::RegistrationSchedule,
+        reservation::{ReservationRound, SlotAssignments},
+        PublisherInfo, WorkerInfo,
     },
 };
 
+use crate::rt::{
+    sleep, spawn,
+    sync::{mpsc, Mutex},
+};
 use chrono::prelude::*;
 use futures::prelude::*;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
 use spectrum_primitives::Bytes;
-use std::{convert::TryInto, fmt::Debug, sync::Arc};
-use tokio::spawn;
+use spectrum_protocol::Accumulatable;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    convert::TryInto,
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tonic::{Request, Response, Status};
 
 #[tonic::async_trait]
 pub trait Remote: Sync + Send + Clone {
     async fn start(&self);
-    async fn done(&self);
+    /// Called once all groups have reported, with the recovered channel contents.
+    async fn done(&self, recovered: Vec<Bytes>);
+    /// Decoders that channel metadata can name (by default, the built-in
+    /// ones; see `services::decoding`).
+    fn decoders(&self) -> Decoders {
+        Decoders::default()
+    }
+    /// Called with the decoded non-empty channels of each recovered round
+    /// (before `done()`).
+    async fn decoded(&self, _channels: &[DecodedChannel]) {}
+    /// Called with the signed manifest of each recovered round (before `done()`).
+    async fn manifest(&self, _manifest: &SignedManifest) {}
+    /// Called on shutdown with the final worker statistics table (hammer mode).
+    async fn stats(&self, _table: String) {}
+    /// Called once a hammer run with a fixed duration is over.
+    async fn hammer_done(&self, _result: &HammerResult) {}
+    /// Called on shutdown if the round was aborted (in which case there's no
+    /// `done()`).
+    async fn aborted(&self, _notice: &AbortNotice) {}
+}
+
+// How often to log worker stats (outside of hammer mode).
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+// The round publisher elections are for (the only one, for now).
+const ROUND: u64 = 0;
+
+/// The latest progress report from a worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WorkerStats {
+    clients_verified: u64,
+    elapsed_ms: u64,
+    pending_audits: u64,
+    audit_failures: u64,
+    viewers_registered: u64,
+    broadcasters_registered: u64,
+}
+
+impl WorkerStats {
+    fn qps(&self) -> u64 {
+        if self.elapsed_ms == 0 {
+            return 0;
+        }
+        self.clients_verified * 1000 / self.elapsed_ms
+    }
+}
+
+// Keyed by (group, index) so the table comes out sorted.
+type StatsMap = BTreeMap<(u16, u16), WorkerStats>;
+
+fn format_stats(stats: &StatsMap) -> String {
+    let mut table = format!(
+        "{:>6} {:>6} {:>12} {:>8} {:>8} {:>8} {:>10} {:>10}\n",
+        "group", "worker", "verified", "qps", "pending", "failed", "viewers", "bcasters"
+    );
+    for ((group, idx), worker) in stats {
+        table.push_str(&format!(
+            "{:>6} {:>6} {:>12} {:>8} {:>8} {:>8} {:>10} {:>10}\n",
+            group + 1,
+            idx + 1,
+            worker.clients_verified,
+            worker.qps(),
+            worker.pending_audits,
+            worker.audit_failures,
+            worker.viewers_registered,
+            worker.broadcasters_registered
+        ));
+    }
+    // Each client is verified by one worker in every group, so total over one group.
+    let verified: u64 = stats
+        .iter()
+        .filter(|((group, _), _)| *group == 0)
+        .map(|(_, worker)| worker.clients_verified)
+        .sum();
+    let qps: u64 = stats
+        .iter()
+        .filter(|((group, _), _)| *group == 0)
+        .map(|(_, worker)| worker.qps())
+        .sum();
+    let pending: u64 = stats.values().map(|worker| worker.pending_audits).sum();
+    let failed: u64 = stats
+        .iter()
+        .filter(|((group, _), _)| *group == 0)
+        .map(|(_, worker)| worker.audit_failures)
+        .sum();
+    let viewers: u64 = stats
+        .iter()
+        .filter(|((group, _), _)| *group == 0)
+        .map(|(_, worker)| worker.viewers_registered)
+        .sum();
+    let broadcasters: u64 = stats
+        .iter()
+        .filter(|((group, _), _)| *group == 0)
+        .map(|(_, worker)| worker.broadcasters_registered)
+        .sum();
+    table.push_str(&format!(
+        "{:>6} {:>6} {:>12} {:>8} {:>8} {:>8} {:>10} {:>10}",
+        "total", "", verified, qps, pending, failed, viewers, broadcasters
+    ));
+    table
+}
+
+/// The outcome of a hammer run, from the workers' final reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HammerResult {
+    pub duration_ms: u64,
+    pub target_qps: Option<u64>,
+    /// Clients verified (by any one group).
+    pub clients_verified: u64,
+    pub qps: u64,
+    pub audit_failures: u64,
+    /// How many workers reported.
+    pub workers: usize,
+    pub run: RunMetadata,
+}
+
+impl HammerResult {
+    fn new(stats: &StatsMap, config: &HammerConfig, duration: Duration, run: RunMetadata) -> Self {
+        // As in format_stats(), count each client once (in the first group).
+        let first_group = || stats.iter().filter(|((group, _), _)| *group == 0);
+        HammerResult {
+            duration_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+            target_qps: config.target_qps,
+            clients_verified: first_group().map(|(_, w)| w.clients_verified).sum(),
+            qps: first_group().map(|(_, w)| w.qps()).sum(),
+            audit_failures: first_group().map(|(_, w)| w.audit_failures).sum(),
+            workers: stats.len(),
+            run,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -37,7 +197,59 @@ pub struct NoopRemote;
 #[tonic::async_trait]
 impl Remote for NoopRemote {
     async fn start(&self) {}
-    async fn done(&self) {}
+    async fn done(&self, _recovered: Vec<Bytes>) {}
+}
+
+// What a publisher accumulates during a round; reset when the round ends.
+struct RoundState<A> {
+    accumulator: Accumulator<Vec<A>>,
+    // (round, group) pairs we've already accumulated.
+    received: Mutex<HashSet<(u64, u32)>>,
+    // Each client is audited in every group, so these are kept per group
+    // rather than summed.
+    audit_failures: Mutex<BTreeMap<u32, AuditFailures>>,
+    // Likewise for (noisy) participation counts.
+    participants: Mutex<BTreeMap<u32, u64>>,
+    // Stage latencies reported by the groups' leaders, plus our own.
+    stage_tallies: Mutex<StageTallies>,
+    // When the round's first share arrived (for the aggregate stage).
+    first_share: Mutex<Option<Instant>>,
+    // The last round we finished; later shares for it are stale.
+    finalized: Mutex<Option<u64>>,
+}
+
+impl<A> RoundState<A>
+where
+    Vec<A>: Accumulatable + Clone,
+{
+    fn new(accumulator: Accumulator<Vec<A>>) -> Self {
+        RoundState {
+            accumulator,
+            received: Default::default(),
+            audit_failures: Default::default(),
+            participants: Default::default(),
+            stage_tallies: Default::default(),
+            first_share: Default::default(),
+            finalized: Default::default(),
+        }
+    }
+
+    async fn is_finalized(&self, round: u64) -> bool {
+        matches!(*self.finalized.lock().await, Some(finalized) if round <= finalized)
+    }
+
+    async fn finalize(&self, round: u64) {
+        self.accumulator.reset().await;
+        self.audit_failures.lock().await.clear();
+        self.participants.lock().await.clear();
+        *self.stage_tallies.lock().await = StageTallies::default();
+        *self.first_share.lock().await = None;
+        self.received
+            .lock()
+            .await
+            .retain(|(received_round, _)| *received_round > round);
+        *self.finalized.lock().await = Some(round);
+    }
 }
 
 pub struct MyPublisher<R, P>
@@ -45,9 +257,39 @@ where
     R: Remote,
     P: Protocol,
 {
-    accumulator: Arc<Accumulator<Vec<P::Accumulator>>>,
+    round: Arc<RoundState<P::Accumulator>>,
     total_groups: usize,
     remote: R,
+    stats: Arc<Mutex<StatsMap>>,
+    // Tracks the privacy budget spent on published counts (with participation
+    // privacy on).
+    privacy: Option<Arc<Mutex<Accountant>>>,
+    // What the recovered channels and client counts should add up to.
+    expected_traffic: ExpectedTraffic,
+    stage_budgets: StageBudgets,
+    // Set if this is a reservation round.
+    reservation_round: Option<ReservationRound>,
+    // Each recovered reservation round's slot assignments, by round.
+    slot_assignments: Arc<Mutex<BTreeMap<u64, SlotAssignments>>>,
+    info: PublisherInfo,
+    signer: Arc<ManifestSigner>,
+    // Signed manifests go out here, to be published.
+    manifests: mpsc::UnboundedSender<SignedManifest>,
+    // Stamped into each manifest.
+    run: RunMetadata,
+    // The latest share from each group (in delta mode).
+    deltas: Option<delta::Decoder<u32>>,
+    // Whether to check recovered channels for collisions.
+    channel_checksums: bool,
+    // How many bytes of each recovered channel are payload (see
+    // `Protocol::payload_capacity()`).
+    payload_capacity: usize,
+    // Serves the latest recovered round to viewers, privately.
+    pir: PirService,
+    decoders: Arc<ChannelDecoders>,
+    // Abort requests go out here, to be published.
+    aborts: mpsc::UnboundedSender<AbortNotice>,
+    cancel: CancellationToken,
 }
 
 impl<R, P> MyPublisher<R, P>
@@ -56,13 +298,61 @@ where
     P: Protocol,
     P::Accumulator: Clone,
 {
-    fn from_protocol(protocol: P, remote: R) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    fn from_protocol(
+        protocol: P,
+        remote: R,
+        stats: Arc<Mutex<StatsMap>>,
+        info: PublisherInfo,
+        signer: ManifestSigner,
+        manifests: mpsc::UnboundedSender<SignedManifest>,
+        run: RunMetadata,
+        delta_shares: bool,
+        channel_checksums: bool,
+        participation_privacy: Option<PrivacyBudget>,
+        expected_traffic: ExpectedTraffic,
+        stage_budgets: StageBudgets,
+        reservation_round: Option<ReservationRound>,
+        pir: PirService,
+        decoders: ChannelDecoders,
+        aborts: mpsc::UnboundedSender<AbortNotice>,
+        cancel: CancellationToken,
+    ) -> Self {
         MyPublisher {
-            accumulator: Arc::new(Accumulator::new(protocol.new_accumulator())),
+            round: Arc::new(RoundState::new(Accumulator::new(
+                protocol.new_accumulator(),
+            ))),
             total_groups: protocol.num_parties(),
+            payload_capacity: protocol.payload_capacity(),
             remote,
+            stats,
+            privacy: participation_privacy
+                .map(|budget| Arc::new(Mutex::new(Accountant::new(budget)))),
+            expected_traffic,
+            stage_budgets,
+            reservation_round,
+            slot_assignments: Default::default(),
+            info,
+            signer: Arc::new(signer),
+            manifests,
+            run,
+            deltas: delta_shares.then(delta::Decoder::default),
+            channel_checksums,
+            pir,
+            decoders: Arc::new(decoders),
+            aborts,
+            cancel,
         }
     }
+
+    /// Round finalization hook: reset this publisher once `round` is done.
+    ///
+    /// Duplicate detection is kept for later rounds (in case shares for them
+    /// arrived early), as are the shares later deltas are relative to and any
+    /// slot assignments. Called once `round`'s channels are recovered.
+    pub async fn finalize_round(&self, round: u64) {
+        self.round.finalize(round).await;
+    }
 }
 
 #[tonic::async_trait]
@@ -78,11 +368,89 @@ where
         &self,
         request: Request<AggregateGroupRequest>,
     ) -> Result<Response<AggregateGroupResponse>, Status> {
+        if self.cancel.is_cancelled() {
+            return Err(Status::aborted("Round aborted."));
+        }
         let request = request.into_inner();
+        if request.group as usize >= self.total_groups {
+            return Err(Status::invalid_argument(format!(
+                "No group {} (expected fewer than {}).",
+                request.group, self.total_groups
+            )));
+        }
 
-        let share: Share = expect_field(request.share, "Share")?;
+        let share: Share = match (&self.deltas, request.share_delta) {
+            (Some(deltas), Some(delta)) => {
+                let payload = Payload::Delta(delta);
+                deltas.decode(request.group, request.round, payload).await?
+            }
+            (None, Some(_)) => {
+                return Err(Status::failed_precondition("Not accepting share deltas."));
+            }
+            (Some(deltas), None) => {
+                let payload = Payload::Full(expect_field(request.share, "Share")?);
+                deltas.decode(request.group, request.round, payload).await?
+            }
+            (None, None) => expect_field(request.share, "Share")?,
+        };
+        if self.round.is_finalized(request.round).await {
+            warn!(
+                "Share for group {} (round {}) after the round finished; ignoring.",
+                request.group, request.round
+            );
+            return Ok(Response::new(AggregateGroupResponse {}));
+        }
+        if !self
+            .round
+            .received
+            .lock()
+            .await
+            .insert((request.round, request.group))
+        {
+            warn!(
+                "Duplicate share for group {} (round {}); ignoring.",
+                request.group, request.round
+            );
+            return Ok(Response::new(AggregateGroupResponse {}));
+        }
         let total_groups = self.total_groups;
-        let accumulator = self.accumulator.clone();
+        let group = request.group;
+        let round = request.round;
+        let publisher = self.info.idx;
+        let signer = self.signer.clone();
+        let manifests = self.manifests.clone();
+        let run = self.run.clone();
+        let channel_checksums = self.channel_checksums;
+        let payload_capacity = self.payload_capacity;
+        let state = self.round.clone();
+        let privacy = self.privacy.clone();
+        let expected_traffic = self.expected_traffic;
+        let stage_budgets = self.stage_budgets;
+        let reservation_round = self.reservation_round;
+        let slot_assignments = self.slot_assignments.clone();
+        let pir = self.pir.clone();
+        let decoders = self.decoders.clone();
+        let first_share = *state
+            .first_share
+            .lock()
+            .await
+            .get_or_insert_with(Instant::now);
+        let cancel = self.cancel.clone();
+        state
+            .audit_failures
+            .lock()
+            .await
+            .insert(request.group, request.audit_failures.unwrap_or_default());
+        state
+            .participants
+            .lock()
+            .await
+            .insert(request.group, request.participants);
+        state
+            .stage_tallies
+            .lock()
+            .await
+            .merge(&request.stage_tallies.unwrap_or_default().into());
 
         let remote = self.remote.clone();
         // TODO: factor out?
@@ -90,7 +458,13 @@ where
             // TODO: spawn_blocking for heavy computation?
             let data: Vec<P::Accumulator> =
                 TryInto::<Vec<P::Accumulator>>::try_into(share).unwrap();
-            let group_count = accumulator.accumulate(data).await;
+            let group_count = match state.accumulator.try_accumulate(data).await {
+                Ok(count) => count,
+                Err(err) => {
+                    error!("Malformed share from group {}: {}", group, err);
+                    return;
+                }
+            };
             if group_count < total_groups {
                 trace!(
                     "Publisher receieved {}/{} shares",
@@ -107,20 +481,243 @@ where
                 return;
             }
 
-            let result = accumulator.get().await;
+            if cancel.is_cancelled() {
+                warn!("Round aborted; not recovering channels.");
+                return;
+            }
+            let result = state.accumulator.get().await;
             // in seed-homomorphic case this is expensive, so it needs to happen
-            // before we call remote.done(). we log the length so the into()
-            // call won't get optimized away!
-            let result: Vec<Bytes> = result.into_iter().map(Into::into).collect();
+            // before we call remote.done().
+            let result: Vec<Bytes> = result
+                .into_iter()
+                .map(|channel| {
+                    // Drop any encoding padding past the payload.
+                    let channel: Bytes = channel.into();
+                    let mut channel: Vec<u8> = channel.into();
+                    channel.truncate(payload_capacity);
+                    channel.into()
+                })
+                .collect();
             info!("Publisher finished!");
+            for (group, failures) in state.audit_failures.lock().await.iter() {
+                if failures.total() > 0 {
+                    warn!("Group {} audit failures: {:?}", group + 1, failures);
+                }
+            }
             trace!("Recovered value len: {:?}", result.len());
-            remote.done().await;
+            let recovered_at = DateTime::<FixedOffset>::from(Utc::now());
+            let mut manifest = Manifest::new(round, publisher, group_count, recovered_at, &result);
+            manifest.run = Some(run);
+            if channel_checksums {
+                manifest.suspected_collisions = checksum::suspected_collisions(&result);
+                if !manifest.suspected_collisions.is_empty() {
+                    warn!(
+                        "Suspected collisions on channels: {:?}",
+                        manifest.suspected_collisions
+                    );
+                }
+            }
+            // Every group's count covers every client (see `privacy`).
+            let clients = state
+                .participants
+                .lock()
+                .await
+                .values()
+                .next()
+                .copied()
+                .expect("Have shares from every group.");
+            if let Some(privacy) = privacy {
+                let participation = privacy.lock().await.publish(clients);
+                info!(
+                    "Participation: {} (privacy spent so far: ε = {}, δ = {})",
+                    participation.clients, participation.epsilon_spent, participation.delta_spent
+                );
+                manifest.participation = Some(participation);
+            }
+            // Noisy counts don't say how much cover traffic there was.
+            let exact_clients = manifest.participation.is_none().then_some(clients);
+            let traffic = TrafficMix::observe(expected_traffic, &result, exact_clients);
+            if traffic.as_expected() {
+                info!("Traffic mix: {:?}", traffic);
+            } else {
+                warn!("Traffic mix doesn't match the experiment: {:?}", traffic);
+            }
+            manifest.traffic = Some(traffic);
+            if !stage_budgets.is_empty() {
+                let mut stages = state.stage_tallies.lock().await;
+                stages
+                    .aggregate
+                    .observe(first_share.elapsed(), stage_budgets.aggregate);
+                let report = StageReport::new(stage_budgets, *stages);
+                if report.within_budget {
+                    info!("Stage latencies: {:?}", report.stages);
+                } else {
+                    warn!("Stages over budget: {:?}", report);
+                }
+                manifest.stages = Some(report);
+            }
+            if let Some(reservation_round) = reservation_round {
+                match reservation_round.tally(&result) {
+                    Ok(slots) => {
+                        info!(
+                            "Reserved {} slots ({} collisions).",
+                            slots.assignments.len(),
+                            slots.collisions
+                        );
+                        slot_assignments.lock().await.insert(round, slots.clone());
+                        manifest.slot_assignments = Some(slots);
+                    }
+                    Err(err) => error!("Couldn't tally slot reservations: {}", err),
+                }
+            }
+            pir.publish(round, &result).await;
+            if manifests.send(signer.sign(manifest)).is_err() {
+                warn!("Publisher shut down; not publishing manifest.");
+            }
+            remote.decoded(&decoders.decode(round, &result)).await;
+            remote.done(result).await;
+            state.finalize(round).await;
         });
 
         Ok(Response::new(AggregateGroupResponse {}))
     }
+
+    async fn report_stats(
+        &self,
+        request: Request<ReportStatsRequest>,
+    ) -> Result<Response<ReportStatsResponse>, Status> {
+        let request = request.into_inner();
+        let worker = WorkerInfo::from(expect_field(request.worker_id, "Worker ID")?);
+        let stats = WorkerStats {
+            clients_verified: request.clients_verified,
+            elapsed_ms: request.elapsed_ms,
+            pending_audits: request.pending_audits,
+            audit_failures: request
+                .audit_failures
+                .map(|failures| failures.total())
+                .unwrap_or_default(),
+            viewers_registered: request.viewers_registered,
+            broadcasters_registered: request.broadcasters_registered,
+        };
+        trace!("Stats from {:?}: {:?}", worker, stats);
+        self.stats
+            .lock()
+            .await
+            .insert((worker.group.idx, worker.idx), stats);
+        Ok(Response::new(ReportStatsResponse {}))
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<GetStatsRequest>,
+    ) -> Result<Response<GetStatsResponse>, Status> {
+        let workers = self
+            .stats
+            .lock()
+            .await
+            .iter()
+            .map(|(&(group, idx), stats)| get_stats_response::Worker {
+                worker_id: Some(WorkerId {
+                    group: group.into(),
+                    idx: idx.into(),
+                }),
+                clients_verified: stats.clients_verified,
+                elapsed_ms: stats.elapsed_ms,
+                pending_audits: stats.pending_audits,
+                audit_failures: stats.audit_failures,
+                viewers_registered: stats.viewers_registered,
+                broadcasters_registered: stats.broadcasters_registered,
+            })
+            .collect();
+        let received = self.round.received.lock().await;
+        let mut latest_round = received.iter().map(|&(round, _)| round).max();
+        let mut groups_received = received
+            .iter()
+            .filter(|&&(round, _)| Some(round) == latest_round)
+            .count();
+        // Until a later round's shares come in, the finished one is the latest.
+        if let Some(finalized) = *self.round.finalized.lock().await {
+            if latest_round.is_none_or(|round| round < finalized) {
+                latest_round = Some(finalized);
+                groups_received = self.total_groups;
+            }
+        }
+        Ok(Response::new(GetStatsResponse {
+            workers,
+            total_groups: self.total_groups.try_into().unwrap_or(u32::MAX),
+            latest_round: latest_round.unwrap_or_default(),
+            groups_received: groups_received.try_into().unwrap_or(u32::MAX),
+        }))
+    }
+
+    async fn abort_round(
+        &self,
+        request: Request<AbortRoundRequest>,
+    ) -> Result<Response<AbortRoundResponse>, Status> {
+        let request = request.into_inner();
+        let reason = if request.reason.is_empty() {
+            "No reason given.".to_string()
+        } else {
+            request.reason
+        };
+        let notice = AbortNotice {
+            round: request.round,
+            reason,
+        };
+        info!("Abort requested: {:?}", notice);
+        self.aborts
+            .send(notice)
+            .map_err(|_| Status::unavailable("Publisher shutting down."))?;
+        Ok(Response::new(AbortRoundResponse {}))
+    }
+
+    async fn get_slot_assignments(
+        &self,
+        request: Request<SlotAssignmentsRequest>,
+    ) -> Result<Response<SlotAssignmentsResponse>, Status> {
+        if self.reservation_round.is_none() {
+            return Err(Status::failed_precondition("Not a reservation round."));
+        }
+        let round = request.into_inner().round;
+        let slots = self
+            .slot_assignments
+            .lock()
+            .await
+            .get(&round)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("Round {} not recovered yet.", round)))?;
+        Ok(Response::new(slots.into()))
+    }
 }
 
+// Pick (and publish) the registration window and start time, `delay_ms` from
+// now. If a start time is already set, keep that one.
+async fn pick_start_time<C: Store + Sync>(
+    config: &C,
+    delay_ms: i64,
+    registration: RegistrationSchedule,
+) -> Result<DateTime<FixedOffset>, crate::Error> {
+    let now = DateTime::<FixedOffset>::from(Utc::now());
+    // TODO(zjn): should be more in the future
+    let start = now + chrono::Duration::milliseconds(delay_ms);
+    info!("Registering experiment start time: {}", start);
+    let claimed = claim_start_time(config, start).await?;
+    if claimed != start {
+        info!("Start time already set; using {}.", claimed);
+        return Ok(claimed);
+    }
+    // Only whoever set the start time sets the window (workers poll for it).
+    let window = registration.apply(config, now).await?;
+    if window != Default::default() {
+        info!("Registration window: {:?}", window);
+    }
+    if matches!(window.closes, Some(closes) if closes > start) {
+        warn!("Registration closes after the experiment start time.");
+    }
+    Ok(claimed)
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn inner_run<C, F, R, P>(
     config: C,
     protocol: P,
@@ -129,9 +726,13 @@ async fn inner_run<C, F, R, P>(
     remote: R,
     shutdown: F,
     delay_ms: i64,
+    quorum: QuorumPolicy,
+    registration: RegistrationSchedule,
+    signer: ManifestSigner,
+    election: Option<ElectionPolicy>,
 ) -> Result<(), Box<dyn std::error::Error + Sync + Send>>
 where
-    C: Store + Sync + Send,
+    C: 'static + Store + Discovery + Clone,
     R: Remote + 'static,
     F: Future<Output = ()> + Send + 'static,
     P: Protocol + 'static,
@@ -139,41 +740,199 @@ where
     Share: TryInto<Vec<P::Accumulator>>,
     <Share as TryInto<Vec<P::Accumulator>>>::Error: Debug,
 {
-    let state = MyPublisher::from_protocol(protocol, remote.clone());
+    let experiment = experiment::read_from_store(&config).await?;
+    let run = RunMetadata::new(&experiment);
+    run.log();
+    let stats: Arc<Mutex<StatsMap>> = Default::default();
+    info!(
+        "Manifest signing key: {}",
+        hex::encode(signer.public_key().as_bytes())
+    );
+    let (manifests_tx, mut manifests) = mpsc::unbounded_channel();
+    let (aborts_tx, mut aborts) = mpsc::unbounded_channel();
+    let cancel = CancellationToken::default();
+    let pir = PirService::default();
+    let decoders = ChannelDecoders::load(&config, &remote.decoders()).await?;
+    // Publish abort requests right away: the round may be stuck anywhere,
+    // including waiting for quorum.
+    let abort_task = {
+        let config = config.clone();
+        let cancel = cancel.clone();
+        spawn(async move {
+            while let Some(notice) = aborts.recv().await {
+                if let Err(err) = abort_round(&config, &notice).await {
+                    error!("Failed to publish round abort: {}", err);
+                }
+                cancel.cancel(notice);
+            }
+        })
+    };
+    let abort_watcher = spawn(watch_for_abort(config.clone(), cancel.clone()));
+    let state = MyPublisher::from_protocol(
+        protocol,
+        remote.clone(),
+        stats.clone(),
+        info,
+        signer,
+        manifests_tx,
+        run.clone(),
+        experiment.delta_shares(),
+        experiment.channel_checksums(),
+        experiment.participation_privacy(),
+        experiment.expected_traffic(),
+        experiment.stage_budgets(),
+        experiment.reservation_round(),
+        pir.clone(),
+        decoders,
+        aborts_tx,
+        cancel.clone(),
+    );
     info!("Publisher starting up.");
-    let local_socket_addr = net.local_socket_addr();
-    let server_task = tokio::spawn(async move {
-        tonic::transport::server::Server::builder()
+    let shutdown = {
+        let cancel = cancel.clone();
+        future::select(
+            Box::pin(shutdown),
+            Box::pin(async move {
+                cancel.cancelled().await;
+            }),
+        )
+        .map(|_| systemd::stopping())
+    };
+    let incoming = net.bind().await?;
+    let mut server = net.server_builder();
+    if let Some(identity) = net.tls_ident() {
+        info!("Adding TLS config.");
+        server = tls::server(server, identity)?;
+    }
+    let server_task = spawn(async move {
+        server
             .add_service(HealthServer::new(AllGoodHealthServer::default()))
+            .add_service(AdminServer::new(LogAdmin::default()))
             .add_service(PublisherServer::new(state))
-            .serve_with_shutdown(local_socket_addr, shutdown)
+            .add_service(PirServer::new(pir))
+            .serve_with_incoming_shutdown(incoming, shutdown)
             .await
     });
 
-    wait_for_health(format!("http://{}", net.public_addr()), None).await?;
+    wait_for_health(net.public_uri(), net.tls_cert()).await?;
     trace!("Publisher {:?} healthy and serving.", info);
 
-    let node = Node::new(info.into(), net.public_addr());
+    let node = Node::new(info.into(), net.public_addr())
+        .with_scheme(net.public_scheme())
+        .with_pinned_cert(net.pinned_cert());
     register(&config, node).await?;
     debug!("Registered with config server.");
+    systemd::ready();
 
-    let experiment = experiment::read_from_store(&config).await?;
-    wait_for_quorum(&config, &experiment).await?;
+    let quorum = wait_for_quorum(&config, &experiment, quorum).await?;
+    if !quorum.missing.is_empty() {
+        warn!(
+            "Starting with {} services missing: {:?}",
+            quorum.missing.len(),
+            quorum.missing
+        );
+    }
 
-    // TODO(zjn): should be more in the future
-    let start =
-        DateTime::<FixedOffset>::from(Utc::now()) + chrono::Duration::milliseconds(delay_ms);
-    info!("Registering experiment start time: {}", start);
-    set_start_time(&config, start).await?;
+    // Only one publisher's start time (and registration window) sticks; the
+    // rest follow it. Without election, every publisher tries, so the round
+    // starts as long as any of them is up; with election, only whoever wins
+    // the lease does. The campaign runs (renewing the lease) until we're done.
+    let campaign =
+        election.map(|policy| Campaign::start(config.clone(), ROUND, net.public_addr(), policy));
+    let start = match &campaign {
+        None => pick_start_time(&config, delay_ms, registration).await?,
+        Some(campaign) => {
+            // If the winner goes down before picking one, the next winner does.
+            let followed = Box::pin(wait_for_start_time_set(&config));
+            let elected = Box::pin(campaign.elected());
+            match future::select(followed, elected).await {
+                future::Either::Left((start, _)) => start?,
+                future::Either::Right(((), _)) => {
+                    pick_start_time(&config, delay_ms, registration).await?
+                }
+            }
+        }
+    };
     delay_until(start).await;
     remote.start().await;
 
-    server_task.await??;
+    let hammer = experiment.hammer();
+    let stats_task = {
+        let stats = stats.clone();
+        let interval = hammer.map_or(STATS_INTERVAL, |hammer| hammer.report_interval);
+        spawn(async move {
+            loop {
+                sleep(interval).await;
+                let stats = stats.lock().await;
+                if !stats.is_empty() {
+                    info!("Worker stats:\n{}", format_stats(&stats));
+                }
+            }
+        })
+    };
+
+    // Workers send a final report when the run ends; give it one interval to
+    // arrive before wrapping up.
+    let hammer_task = hammer.and_then(|hammer| {
+        let duration = hammer.duration?;
+        let stats = stats.clone();
+        let remote = remote.clone();
+        Some(spawn(async move {
+            sleep(duration + hammer.report_interval).await;
+            let result = HammerResult::new(&*stats.lock().await, &hammer, duration, run);
+            info!("Hammer run finished: {:?}", result);
+            remote.hammer_done(&result).await;
+        }))
+    });
+
+    // Runs until the server (and so every sender) shuts down, so manifests
+    // sent just before shutdown still get published. Returns the rounds
+    // recovered.
+    let publish_task = async {
+        let mut recovered = BTreeSet::new();
+        while let Some(signed) = manifests.recv().await {
+            match publish_manifest(&config, &signed).await {
+                Ok(()) => debug!("Published manifest for round {}.", signed.manifest.round),
+                Err(err) => error!("Failed to publish manifest: {}", err),
+            }
+            recovered.insert(signed.manifest.round);
+            remote.manifest(&signed).await;
+        }
+        recovered
+    };
+    let (served, recovered) = future::join(server_task, publish_task).await;
+    served??;
+    stats_task.abort();
+    if let Some(hammer_task) = hammer_task {
+        hammer_task.abort();
+    }
+    // The server's gone, so no more abort requests; finish publishing any.
+    abort_task.await?;
+    abort_watcher.abort();
     info!("Publisher shutting down.");
+    // Stop renewing the lease before deleting it with the rest of the round.
+    if let Some(campaign) = campaign {
+        campaign.stop().await;
+    }
+    for round in recovered {
+        match compact_round(&config, round).await {
+            Ok(compaction) => debug!("Compacted round {}: {:?}", round, compaction),
+            Err(err) => warn!("Failed to compact round {}: {}", round, err),
+        }
+    }
+    if let Some(notice) = cancel.notice() {
+        remote.aborted(&notice).await;
+    }
+
+    let stats = stats.lock().await;
+    if !stats.is_empty() {
+        remote.stats(format_stats(&stats)).await;
+    }
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run<C, R, F>(
     config: C,
     protocol: ProtocolWrapper,
@@ -182,21 +941,80 @@ pub async fn run<C, R, F>(
     remote: R,
     shutdown: F,
     delay_ms: i64,
+    quorum: QuorumPolicy,
+    registration: RegistrationSchedule,
+    signer: ManifestSigner,
+    election: Option<ElectionPolicy>,
 ) -> Result<(), Box<dyn std::error::Error + Sync + Send>>
 where
-    C: Store + Sync + Send,
+    C: 'static + Store + Discovery + Clone,
     R: Remote + 'static,
     F: Future<Output = ()> + Send + 'static,
 {
     match protocol {
+        ProtocolWrapper::Insecure(protocol) => {
+            inner_run(
+                config,
+                protocol,
+                info,
+                net,
+                remote,
+                shutdown,
+                delay_ms,
+                quorum,
+                registration,
+                signer,
+                election,
+            )
+            .await?;
+        }
         ProtocolWrapper::Secure(protocol) => {
-            inner_run(config, protocol, info, net, remote, shutdown, delay_ms).await?;
+            inner_run(
+                config,
+                protocol,
+                info,
+                net,
+                remote,
+                shutdown,
+                delay_ms,
+                quorum,
+                registration,
+                signer,
+                election,
+            )
+            .await?;
         }
         ProtocolWrapper::SecurePub(protocol) => {
-            inner_run(config, protocol, info, net, remote, shutdown, delay_ms).await?;
+            inner_run(
+                config,
+                protocol,
+                info,
+                net,
+                remote,
+                shutdown,
+                delay_ms,
+                quorum,
+                registration,
+                signer,
+                election,
+            )
+            .await?;
         }
         ProtocolWrapper::SecureMultiKey(protocol) => {
-            inner_run(config, protocol, info, net, remote, shutdown, delay_ms).await?;
+            inner_run(
+                config,
+                protocol,
+                info,
+                net,
+                remote,
+                shutdown,
+                delay_ms,
+                quorum,
+                registration,
+                signer,
+                election,
+            )
+            .await?;
         }
     }
     Ok(())
@@ -0,0 +1,48 @@
+//! The async runtime (tokio 1.x).
+//!
+//! Runtime-specific APIs go through this module rather than being imported
+//! directly, so that a future runtime migration only has to touch this file.
+//! (`#[tokio::main]` and `#[tokio::test]` are the exception.)
+use std::future::Future;
+use std::time::Instant;
+
+pub use tokio::net::{TcpListener, TcpStream};
+pub use tokio::process::{Child, Command};
+pub use tokio::signal::ctrl_c;
+pub use tokio::task::{spawn, spawn_blocking, JoinError, JoinHandle};
+pub use tokio::time::{sleep, timeout};
+pub use tokio_stream::wrappers::TcpListenerStream;
+
+pub mod io {
+    pub use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+}
+
+pub mod sync {
+    pub use tokio::sync::{mpsc, oneshot, watch, Barrier, Mutex, Notify, OwnedMutexGuard, RwLock};
+}
+
+/// Blocking (non-async) synchronization, for short critical sections.
+///
+/// With the `loom` feature, these are loom's instead, so that its tests can
+/// explore every interleaving of their users. Only the `loom_*` tests work
+/// with the feature on: `cargo test --release --features loom loom_`.
+pub mod blocking {
+    #[cfg(feature = "loom")]
+    pub use loom::sync::{Mutex, RwLock};
+    #[cfg(not(feature = "loom"))]
+    pub use std::sync::{Mutex, RwLock};
+}
+
+/// Sleep until the given (wall-clock independent) instant.
+pub async fn sleep_until(deadline: Instant) {
+    tokio::time::sleep_until(deadline.into()).await
+}
+
+/// Run `future` to completion on a new multi-threaded runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start runtime.")
+        .block_on(future)
+}
@@ -0,0 +1,192 @@
+//! Aborting a round that's gone wrong.
+//!
+//! A broken round (say, a worker that crashed mid-audit) otherwise hangs until
+//! somebody kills every process. The publisher's `AbortRound` RPC writes an
+//! [`AbortNotice`] to the config store; every service watches for it and
+//! cancels its [`CancellationToken`], which stops new work, skips pending
+//! crypto, and shuts the service down.
+//!
+//! Experiments currently run a single round, so a notice aborts the whole
+//! experiment.
+use crate::config::store::{Error, Key, Store};
+use crate::rt::{sleep, sync::watch};
+
+use futures::future::{self, Either};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Same cadence as workers polling the registration window.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn abort_key() -> Key {
+    vec!["experiment".to_string(), "abort".to_string()]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbortNotice {
+    pub round: u64,
+    pub reason: String,
+}
+
+/// Tell every service to abandon the round.
+pub async fn abort_round<C: Store>(config: &C, notice: &AbortNotice) -> Result<(), Error> {
+    let value = serde_json::to_string(notice).map_err(|err| Error::new(&err.to_string()))?;
+    config.put(abort_key(), value).await
+}
+
+/// The abort notice, if the round was aborted.
+pub async fn get_abort<C: Store>(config: &C) -> Result<Option<AbortNotice>, Error> {
+    match config.get(abort_key()).await? {
+        Some(value) => serde_json::from_str(&value)
+            .map(Some)
+            .map_err(|err| Error::new(&err.to_string())),
+        None => Ok(None),
+    }
+}
+
+async fn watch_for_abort_helper<C: Store>(config: C, token: CancellationToken, interval: Duration) {
+    let poll = async {
+        loop {
+            match get_abort(&config).await {
+                Ok(Some(notice)) => return notice,
+                Ok(None) => {}
+                Err(err) => warn!("Couldn't check for round abort: {}", err),
+            }
+            sleep(interval).await;
+        }
+    };
+    let cancelled = token.cancelled();
+    if let Either::Left((notice, _)) = future::select(Box::pin(poll), Box::pin(cancelled)).await {
+        token.cancel(notice);
+    }
+}
+
+/// Cancel `token` once the round is aborted (or return once it's cancelled
+/// some other way).
+pub async fn watch_for_abort<C: Store>(config: C, token: CancellationToken) {
+    watch_for_abort_helper(config, token, POLL_INTERVAL).await
+}
+
+/// Shared by everything working on a round; cancelled when the round aborts.
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    tx: Arc<watch::Sender<Option<AbortNotice>>>,
+    rx: watch::Receiver<Option<AbortNotice>>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        let (tx, rx) = watch::channel(None);
+        CancellationToken {
+            tx: Arc::new(tx),
+            rx,
+        }
+    }
+}
+
+impl CancellationToken {
+    /// Cancel, unless already cancelled (the first notice sticks).
+    pub fn cancel(&self, notice: AbortNotice) {
+        if self.is_cancelled() {
+            return;
+        }
+        warn!("Aborting round {}: {}", notice.round, notice.reason);
+        // Can't fail: we hold a receiver.
+        let _ = self.tx.send(Some(notice));
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.rx.borrow().is_some()
+    }
+
+    pub fn notice(&self) -> Option<AbortNotice> {
+        self.rx.borrow().clone()
+    }
+
+    /// Wait until cancelled.
+    pub async fn cancelled(&self) -> AbortNotice {
+        let mut rx = self.rx.clone();
+        loop {
+            if let Some(notice) = rx.borrow().clone() {
+                return notice;
+            }
+            if rx.changed().await.is_err() {
+                // The sender lives as long as any token, so this won't happen.
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::factory::from_string;
+    use crate::rt::spawn;
+
+    fn notice() -> AbortNotice {
+        AbortNotice {
+            round: 0,
+            reason: "worker crashed".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_abort_and_get() {
+        let config = from_string("").await.unwrap();
+        assert_eq!(get_abort(&config).await.unwrap(), None);
+        abort_round(&config, &notice()).await.unwrap();
+        assert_eq!(get_abort(&config).await.unwrap(), Some(notice()));
+    }
+
+    #[tokio::test]
+    async fn test_token() {
+        let token = CancellationToken::default();
+        assert!(!token.is_cancelled());
+        let waiter = spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+
+        token.cancel(notice());
+        assert!(token.is_cancelled());
+        assert_eq!(waiter.await.unwrap(), notice());
+
+        // The first notice sticks.
+        let other = AbortNotice {
+            round: 1,
+            reason: "again".to_string(),
+        };
+        token.cancel(other);
+        assert_eq!(token.notice(), Some(notice()));
+        assert_eq!(token.cancelled().await, notice());
+    }
+
+    #[tokio::test]
+    async fn test_watch_for_abort() {
+        let config = from_string("").await.unwrap();
+        let token = CancellationToken::default();
+        let watcher = spawn(watch_for_abort_helper(
+            config.clone(),
+            token.clone(),
+            Duration::from_millis(10),
+        ));
+        sleep(Duration::from_millis(50)).await;
+        assert!(!token.is_cancelled());
+
+        abort_round(&config, &notice()).await.unwrap();
+        assert_eq!(token.cancelled().await, notice());
+        watcher.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_watch_stops_when_cancelled() {
+        let config = from_string("").await.unwrap();
+        let token = CancellationToken::default();
+        let watcher = spawn(watch_for_abort(config, token.clone()));
+        token.cancel(notice());
+        watcher.await.unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+//! Operator controls for running services (currently, the log filter).
+//!
+//! Workers, leaders, and publishers all serve this next to their main
+//! service. Use the `admin` binary to call it.
+use crate::logs::{self, LogFilter};
+use crate::net::{client::Builder, tls::Certificate};
+use log::info;
+use tonic::{transport::Channel, Request, Response, Status};
+
+type TokioError = Box<dyn std::error::Error + Sync + Send>;
+
+pub mod spectrum {
+    tonic::include_proto!("spectrum.admin");
+}
+
+pub use spectrum::{
+    admin_client::AdminClient,
+    admin_server::{Admin, AdminServer},
+    GetLogFilterRequest, LogFilterResponse, SetLogFilterRequest,
+};
+
+/// Controls this process's log filter.
+#[derive(Default)]
+pub struct LogAdmin {}
+
+fn response(filter: LogFilter) -> Response<LogFilterResponse> {
+    Response::new(LogFilterResponse {
+        filter: filter.to_string(),
+    })
+}
+
+#[tonic::async_trait]
+impl Admin for LogAdmin {
+    async fn get_log_filter(
+        &self,
+        _request: Request<GetLogFilterRequest>,
+    ) -> Result<Response<LogFilterResponse>, Status> {
+        Ok(response(logs::filter()))
+    }
+
+    async fn set_log_filter(
+        &self,
+        request: Request<SetLogFilterRequest>,
+    ) -> Result<Response<LogFilterResponse>, Status> {
+        let filter: LogFilter = request
+            .into_inner()
+            .filter
+            .parse()
+            .map_err(Status::invalid_argument)?;
+        info!("Changing log filter to [{}].", filter);
+        logs::set_filter(filter.clone());
+        Ok(response(filter))
+    }
+}
+
+/// Connect to the admin endpoint of the service at `addr`.
+pub async fn connect(
+    addr: &str,
+    tls: Option<Certificate>,
+) -> Result<AdminClient<Channel>, TokioError> {
+    let channel = Builder::new(addr).tls(tls).channel().await?;
+    Ok(AdminClient::new(channel))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_set_log_filter() {
+        let admin = LogAdmin::default();
+        let request = SetLogFilterRequest {
+            filter: "info, spectrum::worker::audit_registry=trace".to_string(),
+        };
+        let set = admin.set_log_filter(Request::new(request)).await.unwrap();
+        assert_eq!(
+            set.into_inner().filter,
+            "info,spectrum::worker::audit_registry=trace"
+        );
+        let got = admin
+            .get_log_filter(Request::new(GetLogFilterRequest {}))
+            .await
+            .unwrap();
+        assert_eq!(
+            got.into_inner().filter,
+            "info,spectrum::worker::audit_registry=trace"
+        );
+
+        let request = SetLogFilterRequest {
+            filter: "info,spectrum::worker".to_string(),
+        };
+        let status = admin
+            .set_log_filter(Request::new(request))
+            .await
+            .expect_err("Bad filter should fail.");
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        // ...and leave the filter alone.
+        assert_eq!(
+            logs::filter().to_string(),
+            "info,spectrum::worker::audit_registry=trace"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Handing out worker indices through the config store.
+//!
+//! Rather than computing each worker's index (and group) up front from how many
+//! machines run how many workers, a worker can claim the first free slot in
+//! its group at startup. Each slot is a key in the config store, claimed with
+//! an atomic put-if-absent, so no two workers end up with the same index.
+//!
+//! The claim holds the worker's address, so a worker that claims again (say,
+//! after a restart) gets its old slot back. Claims are never released: they
+//! live as long as the experiment's config.
+use crate::config::store::{Error, Key, Store};
+use crate::services::{Group, WorkerInfo};
+
+use log::debug;
+
+fn claims_key(group: Group) -> Key {
+    vec![
+        "experiment".to_string(),
+        "worker-claims".to_string(),
+        group.idx.to_string(),
+    ]
+}
+
+fn claim_key(group: Group, idx: u16) -> Key {
+    let mut key = claims_key(group);
+    key.push(idx.to_string());
+    key
+}
+
+// A slot in `group` already claimed by `owner`, if any.
+async fn existing_claim<C: Store>(
+    config: &C,
+    group: Group,
+    owner: &str,
+) -> Result<Option<WorkerInfo>, Error> {
+    for (key, value) in config.list(claims_key(group)).await? {
+        if value != owner {
+            continue;
+        }
+        if let Some(idx) = key.last().and_then(|idx| idx.parse().ok()) {
+            return Ok(Some(WorkerInfo::new(group, idx)));
+        }
+    }
+    Ok(None)
+}
+
+/// Claim the lowest free index in `group` (of `group_size`) for `owner`.
+pub async fn claim_in_group<C: Store>(
+    config: &C,
+    group: Group,
+    group_size: u16,
+    owner: &str,
+) -> Result<WorkerInfo, Error> {
+    if let Some(info) = existing_claim(config, group, owner).await? {
+        debug!("Reusing worker claim {:?} for {}.", info, owner);
+        return Ok(info);
+    }
+    for idx in 0..group_size {
+        if config
+            .put_if_absent(claim_key(group, idx), owner.to_string())
+            .await?
+        {
+            let info = WorkerInfo::new(group, idx);
+            debug!("Claimed {:?} for {}.", info, owner);
+            return Ok(info);
+        }
+    }
+    Err(Error::new(&format!(
+        "All {} worker indices in group {} are claimed.",
+        group_size, group.idx
+    )))
+}
+
+/// Claim the lowest free index in the lowest group with one free.
+///
+/// Groups fill up one at a time, so this only spreads workers across groups
+/// evenly if exactly enough of them start.
+pub async fn claim<C: Store>(
+    config: &C,
+    groups: u16,
+    group_size: u16,
+    owner: &str,
+) -> Result<WorkerInfo, Error> {
+    for group in (0..groups).map(Group::new) {
+        if let Some(info) = existing_claim(config, group, owner).await? {
+            return Ok(info);
+        }
+    }
+    for group in (0..groups).map(Group::new) {
+        match claim_in_group(config, group, group_size, owner).await {
+            Ok(info) => return Ok(info),
+            Err(err) => debug!("{}", err),
+        }
+    }
+    Err(Error::new(&format!(
+        "All worker indices in all {} groups are claimed.",
+        groups
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::factory::from_string;
+    use futures::future;
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn test_claim_in_group() {
+        let config = from_string("").await.unwrap();
+        let group = Group::new(1);
+        for idx in 0..3 {
+            let info = claim_in_group(&config, group, 3, &format!("worker{}", idx))
+                .await
+                .unwrap();
+            assert_eq!(info, WorkerInfo::new(group, idx));
+        }
+        claim_in_group(&config, group, 3, "worker3")
+            .await
+            .expect_err("Group should be full.");
+        // Other groups are separate.
+        let info = claim_in_group(&config, Group::new(0), 3, "worker3")
+            .await
+            .unwrap();
+        assert_eq!(info, WorkerInfo::new(Group::new(0), 0));
+    }
+
+    #[tokio::test]
+    async fn test_claim_again() {
+        let config = from_string("").await.unwrap();
+        let group = Group::new(0);
+        claim_in_group(&config, group, 3, "worker0").await.unwrap();
+        let info = claim_in_group(&config, group, 3, "worker1").await.unwrap();
+        assert_eq!(
+            claim_in_group(&config, group, 3, "worker1").await.unwrap(),
+            info
+        );
+        assert_eq!(claim(&config, 2, 3, "worker1").await.unwrap(), info);
+    }
+
+    #[tokio::test]
+    async fn test_claim_concurrent() {
+        let config = from_string("").await.unwrap();
+        let owners: Vec<String> = (0..6).map(|idx| format!("worker{}", idx)).collect();
+        let claims = future::try_join_all(
+            owners
+                .iter()
+                .map(|owner| claim(&config, 2, 3, owner.as_str())),
+        )
+        .await
+        .unwrap();
+        let claimed: HashSet<WorkerInfo> = claims.into_iter().collect();
+        let expected: HashSet<WorkerInfo> = (0..2)
+            .flat_map(|group| (0..3).map(move |idx| WorkerInfo::new(Group::new(group), idx)))
+            .collect();
+        assert_eq!(claimed, expected);
+
+        claim(&config, 2, 3, "worker6")
+            .await
+            .expect_err("All groups should be full.");
+    }
+}
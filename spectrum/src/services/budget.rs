@@ -0,0 +1,253 @@
+//! Per-stage latency budgets.
+//!
+//! An experiment can give each stage of a round a latency budget:
+//!
+//! - *upload*: a worker handling one upload (up to sending out its audit
+//!   shares);
+//! - *audit*: a worker auditing one upload, from the first it hears of it
+//!   (the upload itself or a peer's audit share) until it's accumulated;
+//! - *aggregate*: the publisher collecting the round, from the first group's
+//!   share until the channels are recovered.
+//!
+//! Workers time their uploads and audits and send the tallies along with their
+//! shares; leaders add up their workers' tallies, and the publisher adds its
+//! own aggregate timing. The round's manifest records the result, so a
+//! performance test can pass or fail on it.
+use crate::net::parse_duration;
+use crate::proto;
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long each stage may take (no limit if `None`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageBudgets {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<Duration>,
+}
+
+impl StageBudgets {
+    pub fn is_empty(&self) -> bool {
+        self.upload.is_none() && self.audit.is_none() && self.aggregate.is_none()
+    }
+}
+
+impl FromStr for StageBudgets {
+    type Err = String;
+
+    /// Parse comma-separated `<stage>=<duration>` pairs, e.g.
+    /// `upload=50ms,audit=200ms,aggregate=2s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut budgets = StageBudgets::default();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (stage, budget) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <stage>=<duration>, got [{}].", pair))?;
+            let budget = Some(parse_duration(budget)?);
+            match stage.trim() {
+                "upload" => budgets.upload = budget,
+                "audit" => budgets.audit = budget,
+                "aggregate" => budgets.aggregate = budget,
+                _ => {
+                    return Err(format!(
+                        "Unknown stage [{}]; expected upload, audit, or aggregate.",
+                        stage
+                    ))
+                }
+            }
+        }
+        Ok(budgets)
+    }
+}
+
+/// Latencies observed for one stage.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageTally {
+    pub count: u64,
+    /// Observations that went over the stage's budget.
+    pub over_budget: u64,
+    /// The slowest observation.
+    pub max_micros: u64,
+}
+
+impl StageTally {
+    pub fn observe(&mut self, elapsed: Duration, budget: Option<Duration>) {
+        self.count += 1;
+        if budget.is_some_and(|budget| elapsed > budget) {
+            self.over_budget += 1;
+        }
+        let micros = elapsed.as_micros().try_into().unwrap_or(u64::MAX);
+        self.max_micros = self.max_micros.max(micros);
+    }
+
+    pub fn merge(&mut self, other: &StageTally) {
+        self.count += other.count;
+        self.over_budget += other.over_budget;
+        self.max_micros = self.max_micros.max(other.max_micros);
+    }
+}
+
+/// Latencies observed for every stage.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageTallies {
+    pub upload: StageTally,
+    pub audit: StageTally,
+    pub aggregate: StageTally,
+}
+
+impl StageTallies {
+    pub fn merge(&mut self, other: &StageTallies) {
+        self.upload.merge(&other.upload);
+        self.audit.merge(&other.audit);
+        self.aggregate.merge(&other.aggregate);
+    }
+
+    pub fn over_budget(&self) -> u64 {
+        self.upload.over_budget + self.audit.over_budget + self.aggregate.over_budget
+    }
+}
+
+impl From<proto::StageTally> for StageTally {
+    fn from(tally: proto::StageTally) -> Self {
+        StageTally {
+            count: tally.count,
+            over_budget: tally.over_budget,
+            max_micros: tally.max_micros,
+        }
+    }
+}
+
+impl From<StageTally> for proto::StageTally {
+    fn from(tally: StageTally) -> Self {
+        proto::StageTally {
+            count: tally.count,
+            over_budget: tally.over_budget,
+            max_micros: tally.max_micros,
+        }
+    }
+}
+
+impl From<proto::StageTallies> for StageTallies {
+    fn from(tallies: proto::StageTallies) -> Self {
+        StageTallies {
+            upload: tallies.upload.unwrap_or_default().into(),
+            audit: tallies.audit.unwrap_or_default().into(),
+            aggregate: tallies.aggregate.unwrap_or_default().into(),
+        }
+    }
+}
+
+impl From<StageTallies> for proto::StageTallies {
+    fn from(tallies: StageTallies) -> Self {
+        proto::StageTallies {
+            upload: Some(tallies.upload.into()),
+            audit: Some(tallies.audit.into()),
+            aggregate: Some(tallies.aggregate.into()),
+        }
+    }
+}
+
+/// A round's stage latencies against their budgets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageReport {
+    pub budgets: StageBudgets,
+    pub stages: StageTallies,
+    /// Whether every observation was within its stage's budget.
+    pub within_budget: bool,
+}
+
+impl StageReport {
+    pub fn new(budgets: StageBudgets, stages: StageTallies) -> Self {
+        StageReport {
+            budgets,
+            stages,
+            within_budget: stages.over_budget() == 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_budgets() {
+        assert_eq!(
+            "upload=50ms, aggregate=2s".parse(),
+            Ok(StageBudgets {
+                upload: Some(Duration::from_millis(50)),
+                audit: None,
+                aggregate: Some(Duration::from_secs(2)),
+            })
+        );
+        assert_eq!("".parse(), Ok(StageBudgets::default()));
+        "upload"
+            .parse::<StageBudgets>()
+            .expect_err("missing budget");
+        "upload=50"
+            .parse::<StageBudgets>()
+            .expect_err("missing unit");
+        "download=50ms"
+            .parse::<StageBudgets>()
+            .expect_err("not a stage");
+    }
+
+    #[test]
+    fn test_tally() {
+        let budget = Some(Duration::from_millis(10));
+        let mut tally = StageTally::default();
+        tally.observe(Duration::from_millis(5), budget);
+        tally.observe(Duration::from_millis(10), budget);
+        tally.observe(Duration::from_millis(12), budget);
+        assert_eq!(
+            tally,
+            StageTally {
+                count: 3,
+                over_budget: 1,
+                max_micros: 12_000,
+            }
+        );
+
+        let mut other = StageTally::default();
+        other.observe(Duration::from_secs(1), None);
+        tally.merge(&other);
+        assert_eq!(
+            tally,
+            StageTally {
+                count: 4,
+                over_budget: 1,
+                max_micros: 1_000_000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let budgets: StageBudgets = "audit=1ms".parse().unwrap();
+        let mut stages = StageTallies::default();
+        stages
+            .upload
+            .observe(Duration::from_secs(1), budgets.upload);
+        stages
+            .audit
+            .observe(Duration::from_micros(500), budgets.audit);
+        assert!(StageReport::new(budgets, stages).within_budget);
+
+        let mut worker = StageTallies::default();
+        worker
+            .audit
+            .observe(Duration::from_millis(2), budgets.audit);
+        let worker: StageTallies = proto::StageTallies::from(worker).into();
+        stages.merge(&worker);
+        let report = StageReport::new(budgets, stages);
+        assert!(!report.within_budget);
+        assert_eq!(report.stages.audit.count, 2);
+        assert_eq!(report.stages.audit.over_budget, 1);
+    }
+}
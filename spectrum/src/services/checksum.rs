@@ -0,0 +1,119 @@
+//! Per-channel checksums, to spot colliding broadcasters.
+//!
+//! Writes to a channel combine, so if two broadcasters write to the same
+//! channel (by mistake, or because they share a key) the recovered contents
+//! are garbage, with nothing to say so. With checksums on, broadcasters prefix
+//! each message with a hash of it, and the publisher flags any non-empty
+//! channel where the prefix doesn't match the rest.
+//!
+//! Only for the byte-oriented protocols: multi-key messages have to be valid
+//! group elements, which a hash isn't.
+use spectrum_primitives::Bytes;
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// Bytes of checksum at the front of each message.
+pub const CHECKSUM_LEN: usize = 8;
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LEN] {
+    // Channels may come back padded out with zeros, so trailing zeros don't
+    // count.
+    let end = payload
+        .iter()
+        .rposition(|b| *b != 0)
+        .map_or(0, |idx| idx + 1);
+    blake3::hash(&payload[..end]).as_bytes()[..CHECKSUM_LEN]
+        .try_into()
+        .unwrap()
+}
+
+/// `msg`, prefixed with its checksum.
+pub fn seal(msg: &[u8]) -> Bytes {
+    let mut sealed = checksum(msg).to_vec();
+    sealed.extend_from_slice(msg);
+    sealed.into()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelStatus {
+    /// Nobody wrote to the channel.
+    Empty,
+    /// Exactly one sealed message (as far as we can tell).
+    Intact,
+    /// Not a sealed message: most likely several writes on top of each other.
+    SuspectedCollision,
+}
+
+pub fn check(contents: &[u8]) -> ChannelStatus {
+    if contents.iter().all(|b| *b == 0) {
+        return ChannelStatus::Empty;
+    }
+    if contents.len() < CHECKSUM_LEN {
+        return ChannelStatus::SuspectedCollision;
+    }
+    let (prefix, payload) = contents.split_at(CHECKSUM_LEN);
+    if prefix == checksum(payload) {
+        ChannelStatus::Intact
+    } else {
+        ChannelStatus::SuspectedCollision
+    }
+}
+
+/// Indices of the `recovered` channels that look like collisions.
+pub fn suspected_collisions(recovered: &[Bytes]) -> Vec<usize> {
+    recovered
+        .iter()
+        .enumerate()
+        .filter(|(_, contents)| check(contents.as_ref()) == ChannelStatus::SuspectedCollision)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+        a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn test_sealed_is_intact(msg in vec(any::<u8>(), 0..100), padding in 0..64usize) {
+            let mut sealed: Vec<u8> = seal(&msg).into();
+            prop_assert_eq!(check(&sealed), ChannelStatus::Intact);
+            sealed.extend(std::iter::repeat_n(0, padding));
+            prop_assert_eq!(check(&sealed), ChannelStatus::Intact);
+        }
+
+        #[test]
+        fn test_collision_detected(
+            (msg1, msg2) in (1..100usize).prop_flat_map(|len| (vec(any::<u8>(), len), vec(any::<u8>(), len)))
+        ) {
+            prop_assume!(msg1 != msg2);
+            let combined = xor(seal(&msg1).as_ref(), seal(&msg2).as_ref());
+            prop_assert_eq!(check(&combined), ChannelStatus::SuspectedCollision);
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(check(&[]), ChannelStatus::Empty);
+        assert_eq!(check(&[0; 100]), ChannelStatus::Empty);
+        // An empty message still gets a (nonzero) checksum.
+        assert_eq!(check(seal(&[]).as_ref()), ChannelStatus::Intact);
+    }
+
+    #[test]
+    fn test_suspected_collisions() {
+        let recovered = vec![
+            seal(b"hello"),
+            Bytes::empty(13),
+            xor(seal(b"hello").as_ref(), seal(b"world").as_ref()).into(),
+        ];
+        assert_eq!(suspected_collisions(&recovered), vec![2]);
+    }
+}
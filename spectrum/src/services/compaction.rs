@@ -0,0 +1,81 @@
+//! Cleaning up the config store after a round.
+//!
+//! Some keys only matter while a round runs: the round's publisher lease, and
+//! revocations of channel keys that have since been replaced. Once a round is
+//! recovered, the publisher deletes them, so the store doesn't grow with every
+//! round. Manifests stay; they're the round's record.
+use crate::config::store::{Error, Store};
+use crate::services::{election, revocation};
+
+/// What [`compact_round`] deleted.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Compaction {
+    /// How many of the round's keys were deleted.
+    pub keys: usize,
+    /// Channels whose revocations no longer applied.
+    pub retired_channels: Vec<usize>,
+}
+
+/// Delete what's left of `round` in the config store, and retire stale channel
+/// revocations.
+///
+/// Only call this once nobody's campaigning for `round` any more (see
+/// [`election::Campaign::stop`]); otherwise they'll just renew the lease.
+pub async fn compact_round<C: Store>(config: &C, round: u64) -> Result<Compaction, Error> {
+    let keys = config.delete_prefix(election::lease_key(round)).await?;
+    let retired_channels = revocation::retire_stale(config).await?;
+    Ok(Compaction {
+        keys,
+        retired_channels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::factory::from_string;
+    use crate::experiment::{write_to_store, Experiment};
+    use crate::protocols::wrapper::ProtocolWrapper;
+    use crate::services::manifest::{get_manifest, publish_manifest, Manifest, ManifestSigner};
+    use chrono::prelude::*;
+
+    #[tokio::test]
+    async fn test_compact_round() {
+        let config = from_string("").await.unwrap();
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 16, false);
+        let experiment = Experiment::new_sample_keys(protocol, 1, 10, false);
+        write_to_store(&config, &experiment).await.unwrap();
+        let policy = election::ElectionPolicy::default();
+        for round in 0..2 {
+            election::try_claim(&config, round, "a", policy, Utc::now())
+                .await
+                .unwrap();
+        }
+        let now = DateTime::<FixedOffset>::from(Utc::now());
+        let signed = ManifestSigner::generate().sign(Manifest::new(0, 0, 2, now, &[]));
+        publish_manifest(&config, &signed).await.unwrap();
+
+        let compaction = compact_round(&config, 0).await.unwrap();
+        assert_eq!(
+            compaction,
+            Compaction {
+                keys: 1,
+                retired_channels: vec![],
+            }
+        );
+        assert_eq!(
+            election::elected(&config, 0, Utc::now()).await.unwrap(),
+            None
+        );
+        // Later rounds and the manifest are untouched.
+        assert_eq!(
+            election::elected(&config, 1, Utc::now()).await.unwrap(),
+            Some("a".to_string())
+        );
+        assert!(get_manifest(&config, 0, 0).await.unwrap().is_some());
+        assert_eq!(
+            compact_round(&config, 0).await.unwrap(),
+            Compaction::default()
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Per-call deadlines for outgoing RPCs.
+//!
+//! Deadlines travel in the standard `grpc-timeout` header (which tonic servers
+//! enforce), so a worker can pass what's left of a client's upload deadline on
+//! to the verify calls that upload triggers.
+use std::time::{Duration, Instant};
+use tonic::Request;
+
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// How long each kind of call may take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadlines {
+    /// Client to worker: upload a write token.
+    pub upload: Duration,
+    /// Worker to worker: send an audit share.
+    pub verify: Duration,
+    /// Worker to leader, or leader to publisher: send an aggregate.
+    pub aggregate: Duration,
+    /// Client to worker: register.
+    pub register: Duration,
+}
+
+impl Default for Deadlines {
+    fn default() -> Self {
+        Deadlines {
+            upload: Duration::from_secs(60),
+            verify: Duration::from_secs(60),
+            aggregate: Duration::from_secs(120),
+            register: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Parse a `grpc-timeout` header value (e.g. `100m` for 100 milliseconds).
+fn parse_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// The deadline that the caller of `request` set, if any.
+///
+/// Call this as soon as the request comes in: the deadline is measured from
+/// now.
+pub fn from_request<T>(request: &Request<T>) -> Option<Instant> {
+    let value = request.metadata().get(GRPC_TIMEOUT_HEADER)?.to_str().ok()?;
+    parse_timeout(value).map(|timeout| Instant::now() + timeout)
+}
+
+/// A request that must finish within `timeout`, or by `deadline` if sooner.
+pub fn request<T>(message: T, timeout: Duration, deadline: Option<Instant>) -> Request<T> {
+    let timeout = match deadline {
+        Some(deadline) => timeout.min(deadline.saturating_duration_since(Instant::now())),
+        None => timeout,
+    };
+    let mut request = Request::new(message);
+    request.set_timeout(timeout);
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_request_roundtrip(millis in 1..1_000_000u64) {
+            let timeout = Duration::from_millis(millis);
+            let before = Instant::now();
+            let request = request((), timeout, None);
+            let deadline = from_request(&request).unwrap();
+            prop_assert!(deadline >= before + timeout);
+            prop_assert!(deadline <= Instant::now() + timeout);
+        }
+    }
+
+    #[test]
+    fn test_request_propagates_sooner_deadline() {
+        let before = Instant::now();
+        let deadline = before + Duration::from_secs(1);
+        let request = request((), Duration::from_secs(60), Some(deadline));
+        // The receiver measures from when it reads the header, a bit later.
+        assert!(from_request(&request).unwrap() <= deadline + before.elapsed());
+    }
+
+    #[test]
+    fn test_request_no_deadline() {
+        assert_eq!(from_request(&Request::new(())), None);
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_timeout("3M"), Some(Duration::from_secs(180)));
+        assert_eq!(parse_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_timeout("7n"), Some(Duration::from_nanos(7)));
+        for bad in &["", "m", "10", "10x", "-1m", "123456789m"] {
+            assert_eq!(parse_timeout(bad), None, "{}", bad);
+        }
+    }
+}
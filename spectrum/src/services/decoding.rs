@@ -0,0 +1,418 @@
+//! Decoding recovered channels into usable payloads.
+//!
+//! Recovered channels are raw bytes, padded out to the message size. Each
+//! channel's metadata (in the config store) can name a pipeline of decoders to
+//! run on them, e.g. `length-prefixed,utf8`; the publisher hands the results
+//! to its [`Remote`](crate::publisher::Remote). Channels without metadata come
+//! out as raw bytes, and empty channels (all zeros) are left out.
+//!
+//! Built-in decoders:
+//!
+//! - `length-prefixed`: a big-endian `u32` length, then that many bytes (the
+//!   rest is padding);
+//! - `utf8`: UTF-8 text;
+//! - `protobuf-any`: a serialized `google.protobuf.Any` (so it usually goes
+//!   after `length-prefixed`, which strips the padding).
+//!
+//! Add more with [`Decoders::register`].
+use crate::config::store::{Error, Key, Store};
+
+use serde::{Deserialize, Serialize, Serializer};
+use spectrum_primitives::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fmt;
+use std::sync::Arc;
+
+fn metadata_prefix() -> Key {
+    vec!["experiment".to_string(), "channel-metadata".to_string()]
+}
+
+fn metadata_key(channel: usize) -> Key {
+    let mut key = metadata_prefix();
+    key.push(channel.to_string());
+    key
+}
+
+/// What we know about a channel's contents.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelMetadata {
+    /// Names of the decoders to run on the channel, in order.
+    #[serde(default)]
+    pub decoders: Vec<String>,
+}
+
+pub async fn set_channel_metadata<C: Store>(
+    config: &C,
+    channel: usize,
+    metadata: &ChannelMetadata,
+) -> Result<(), Error> {
+    let value = serde_json::to_string(metadata).map_err(|err| Error::new(&err.to_string()))?;
+    config.put(metadata_key(channel), value).await
+}
+
+/// The metadata of every channel that has any.
+pub async fn get_channel_metadata<C: Store>(
+    config: &C,
+) -> Result<BTreeMap<usize, ChannelMetadata>, Error> {
+    let mut metadata = BTreeMap::new();
+    for (key, value) in config.list(metadata_prefix()).await? {
+        let channel = key
+            .last()
+            .and_then(|channel| channel.parse().ok())
+            .ok_or_else(|| Error::new(&format!("Bad channel metadata key: {:?}", key)))?;
+        let value = serde_json::from_str(&value).map_err(|err| Error::new(&err.to_string()))?;
+        metadata.insert(channel, value);
+    }
+    Ok(metadata)
+}
+
+fn as_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+/// A decoded channel (serialized with bytes in hex).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "kebab-case")]
+pub enum Payload {
+    Bytes(#[serde(serialize_with = "as_hex")] Vec<u8>),
+    Text(String),
+    Any {
+        type_url: String,
+        #[serde(serialize_with = "as_hex")]
+        value: Vec<u8>,
+    },
+}
+
+/// Turns (partly decoded) channel contents into a payload.
+pub trait Decoder: Send + Sync {
+    fn decode(&self, contents: Vec<u8>) -> Result<Payload, String>;
+}
+
+struct LengthPrefixed;
+
+impl Decoder for LengthPrefixed {
+    fn decode(&self, mut contents: Vec<u8>) -> Result<Payload, String> {
+        if contents.len() < 4 {
+            return Err("too short for a length prefix".to_string());
+        }
+        let len = u32::from_be_bytes(contents[..4].try_into().unwrap()) as usize;
+        if contents.len() - 4 < len {
+            return Err(format!(
+                "length prefix {} is past the end ({} bytes)",
+                len,
+                contents.len() - 4
+            ));
+        }
+        contents.truncate(4 + len);
+        contents.drain(..4);
+        Ok(Payload::Bytes(contents))
+    }
+}
+
+struct Utf8;
+
+impl Decoder for Utf8 {
+    fn decode(&self, contents: Vec<u8>) -> Result<Payload, String> {
+        String::from_utf8(contents)
+            .map(Payload::Text)
+            .map_err(|err| err.to_string())
+    }
+}
+
+// `google.protobuf.Any`, without pulling in all of the well-known types.
+#[derive(Clone, PartialEq, prost::Message)]
+struct Any {
+    #[prost(string, tag = "1")]
+    type_url: String,
+    #[prost(bytes, tag = "2")]
+    value: Vec<u8>,
+}
+
+struct ProtobufAny;
+
+impl Decoder for ProtobufAny {
+    fn decode(&self, contents: Vec<u8>) -> Result<Payload, String> {
+        let any: Any = prost::Message::decode(&contents[..]).map_err(|err| err.to_string())?;
+        Ok(Payload::Any {
+            type_url: any.type_url,
+            value: any.value,
+        })
+    }
+}
+
+/// Decoders by name.
+#[derive(Clone)]
+pub struct Decoders {
+    by_name: HashMap<String, Arc<dyn Decoder>>,
+}
+
+impl Default for Decoders {
+    /// The built-in decoders.
+    fn default() -> Self {
+        let mut decoders = Decoders {
+            by_name: HashMap::new(),
+        };
+        decoders.register("length-prefixed", LengthPrefixed);
+        decoders.register("utf8", Utf8);
+        decoders.register("protobuf-any", ProtobufAny);
+        decoders
+    }
+}
+
+impl fmt::Debug for Decoders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<_> = self.by_name.keys().collect();
+        names.sort();
+        f.debug_struct("Decoders").field("names", &names).finish()
+    }
+}
+
+impl Decoders {
+    /// Add `decoder` under `name` (replacing any decoder already there).
+    pub fn register<D: Decoder + 'static>(&mut self, name: &str, decoder: D) {
+        self.by_name.insert(name.to_string(), Arc::new(decoder));
+    }
+
+    fn pipeline(&self, names: &[String]) -> Result<Pipeline, Error> {
+        names
+            .iter()
+            .map(|name| match self.by_name.get(name) {
+                Some(decoder) => Ok((name.clone(), decoder.clone())),
+                None => Err(Error::new(&format!("Unknown decoder [{}].", name))),
+            })
+            .collect::<Result<_, _>>()
+            .map(Pipeline)
+    }
+}
+
+struct Pipeline(Vec<(String, Arc<dyn Decoder>)>);
+
+impl Pipeline {
+    fn decode(&self, contents: Vec<u8>) -> Result<Payload, String> {
+        let mut payload = Payload::Bytes(contents);
+        for (name, decoder) in &self.0 {
+            let contents = match payload {
+                Payload::Bytes(contents) => contents,
+                _ => return Err(format!("nothing left for [{}] to decode", name)),
+            };
+            payload = decoder
+                .decode(contents)
+                .map_err(|err| format!("{}: {}", name, err))?;
+        }
+        Ok(payload)
+    }
+}
+
+/// One channel of a round, decoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DecodedChannel {
+    pub round: u64,
+    pub channel: usize,
+    /// The decoded payload, unless decoding failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Payload>,
+    /// Why decoding failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The decoder pipeline for each channel.
+#[derive(Default)]
+pub struct ChannelDecoders {
+    pipelines: BTreeMap<usize, Pipeline>,
+}
+
+impl ChannelDecoders {
+    /// Pipelines for `metadata`, out of `decoders`.
+    pub fn new(
+        metadata: &BTreeMap<usize, ChannelMetadata>,
+        decoders: &Decoders,
+    ) -> Result<Self, Error> {
+        let pipelines = metadata
+            .iter()
+            .map(|(channel, metadata)| Ok((*channel, decoders.pipeline(&metadata.decoders)?)))
+            .collect::<Result<_, Error>>()?;
+        Ok(ChannelDecoders { pipelines })
+    }
+
+    /// Pipelines for the channel metadata in the config store.
+    pub async fn load<C: Store>(config: &C, decoders: &Decoders) -> Result<Self, Error> {
+        Self::new(&get_channel_metadata(config).await?, decoders)
+    }
+
+    /// Decode each non-empty channel of `recovered` (from `round`).
+    pub fn decode(&self, round: u64, recovered: &[Bytes]) -> Vec<DecodedChannel> {
+        recovered
+            .iter()
+            .enumerate()
+            .filter(|(_, contents)| contents.as_ref().iter().any(|b| *b != 0))
+            .map(|(channel, contents)| {
+                let contents = contents.as_ref().to_vec();
+                let decoded = match self.pipelines.get(&channel) {
+                    Some(pipeline) => pipeline.decode(contents),
+                    None => Ok(Payload::Bytes(contents)),
+                };
+                let (payload, error) = match decoded {
+                    Ok(payload) => (Some(payload), None),
+                    Err(err) => (None, Some(err)),
+                };
+                DecodedChannel {
+                    round,
+                    channel,
+                    payload,
+                    error,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::factory::from_string;
+    use proptest::prelude::*;
+
+    fn length_prefixed(payload: &[u8], padded_len: usize) -> Bytes {
+        let mut contents = (payload.len() as u32).to_be_bytes().to_vec();
+        contents.extend_from_slice(payload);
+        contents.resize(padded_len, 0);
+        contents.into()
+    }
+
+    fn decoders(pipelines: &[(usize, &[&str])]) -> ChannelDecoders {
+        let metadata = pipelines
+            .iter()
+            .map(|(channel, names)| {
+                let decoders = names.iter().map(|name| name.to_string()).collect();
+                (*channel, ChannelMetadata { decoders })
+            })
+            .collect();
+        ChannelDecoders::new(&metadata, &Decoders::default()).unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn test_length_prefixed(payload: Vec<u8>, padding in 0..100usize) {
+            let contents = length_prefixed(&payload, payload.len() + 4 + padding);
+            prop_assert_eq!(
+                LengthPrefixed.decode(contents.into()),
+                Ok(Payload::Bytes(payload))
+            );
+        }
+
+        #[test]
+        fn test_text(text: String, padding in 0..100usize) {
+            prop_assume!(!text.is_empty());
+            let contents = length_prefixed(text.as_bytes(), text.len() + 4 + padding);
+            let decoded = decoders(&[(0, &["length-prefixed", "utf8"])]).decode(3, &[contents]);
+            prop_assert_eq!(decoded.len(), 1);
+            prop_assert_eq!(decoded[0].payload.clone(), Some(Payload::Text(text)));
+        }
+    }
+
+    #[test]
+    fn test_length_prefixed_bad() {
+        LengthPrefixed.decode(vec![0, 0]).expect_err("too short");
+        LengthPrefixed
+            .decode(vec![0, 0, 0, 5, 1, 2])
+            .expect_err("past the end");
+    }
+
+    #[test]
+    fn test_protobuf_any() {
+        let any = Any {
+            type_url: "type.googleapis.com/spectrum.Example".to_string(),
+            value: vec![1, 2, 3],
+        };
+        let mut encoded = vec![];
+        prost::Message::encode(&any, &mut encoded).unwrap();
+        let contents = length_prefixed(&encoded, 64);
+        let decoded = decoders(&[(1, &["length-prefixed", "protobuf-any"])])
+            .decode(0, &[Bytes::empty(64), contents]);
+        assert_eq!(
+            decoded,
+            vec![DecodedChannel {
+                round: 0,
+                channel: 1,
+                payload: Some(Payload::Any {
+                    type_url: any.type_url,
+                    value: any.value,
+                }),
+                error: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let decoded = decoders(&[(0, &["utf8", "utf8"]), (1, &["utf8"])])
+            .decode(0, &[vec![b'a'].into(), vec![0xff].into()]);
+        assert!(decoded[0].error.as_ref().unwrap().contains("nothing left"));
+        assert!(decoded[1].error.as_ref().unwrap().starts_with("utf8:"));
+
+        let metadata = vec![(
+            0,
+            ChannelMetadata {
+                decoders: vec!["rot13".to_string()],
+            },
+        )]
+        .into_iter()
+        .collect();
+        assert!(
+            ChannelDecoders::new(&metadata, &Decoders::default()).is_err(),
+            "unknown decoder"
+        );
+    }
+
+    #[test]
+    fn test_register() {
+        struct Upper;
+        impl Decoder for Upper {
+            fn decode(&self, contents: Vec<u8>) -> Result<Payload, String> {
+                Ok(Payload::Text(
+                    String::from_utf8_lossy(&contents).to_uppercase(),
+                ))
+            }
+        }
+        let mut registry = Decoders::default();
+        registry.register("upper", Upper);
+        let metadata = vec![(
+            0,
+            ChannelMetadata {
+                decoders: vec!["upper".to_string()],
+            },
+        )]
+        .into_iter()
+        .collect();
+        let decoded = ChannelDecoders::new(&metadata, &registry)
+            .unwrap()
+            .decode(0, &[b"hi".to_vec().into()]);
+        assert_eq!(decoded[0].payload, Some(Payload::Text("HI".to_string())));
+    }
+
+    #[test]
+    fn test_payload_json() {
+        let json = serde_json::to_string(&Payload::Bytes(vec![0xab, 0x01])).unwrap();
+        assert_eq!(json, r#"{"kind":"bytes","value":"ab01"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_store_metadata() {
+        let config = from_string("").await.unwrap();
+        assert_eq!(
+            get_channel_metadata(&config).await.unwrap(),
+            BTreeMap::new()
+        );
+        let metadata = ChannelMetadata {
+            decoders: vec!["length-prefixed".to_string(), "utf8".to_string()],
+        };
+        set_channel_metadata(&config, 2, &metadata).await.unwrap();
+        let stored = get_channel_metadata(&config).await.unwrap();
+        assert_eq!(stored, vec![(2, metadata)].into_iter().collect());
+        ChannelDecoders::load(&config, &Decoders::default())
+            .await
+            .unwrap();
+    }
+}
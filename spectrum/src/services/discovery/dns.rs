@@ -0,0 +1,77 @@
+use crate::config::store::Error;
+
+use super::{parse_service, Discovery, Node};
+use log::debug;
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// Nodes listed as DNS SRV records, using the system resolver.
+///
+/// Each SRV record under the given name (e.g. `_spectrum._tcp.example.com`)
+/// is one node. The first label of the record's target names the service
+/// (e.g. `worker-1-2.example.com`, 0-indexed) and the node's address is the
+/// target plus the record's port.
+///
+/// DNS can't carry pinned certificates or schemes, so use a CA with this
+/// backend; all nodes are reached over `http` (with TLS if configured).
+/// Registration is a no-op: the records are the source of truth.
+#[derive(Debug, Clone)]
+pub struct DnsSrvDiscovery {
+    name: String,
+}
+
+impl DnsSrvDiscovery {
+    pub fn new(name: String) -> Self {
+        DnsSrvDiscovery { name }
+    }
+}
+
+fn to_node(target: &str, port: u16) -> Result<Node, Error> {
+    let target = target.trim_end_matches('.');
+    let label = target.split('.').next().unwrap_or_default();
+    let service = parse_service(label)?;
+    Ok(Node::new(service, format!("{}:{}", target, port)))
+}
+
+#[tonic::async_trait]
+impl Discovery for DnsSrvDiscovery {
+    async fn register(&self, node: Node) -> Result<(), Error> {
+        debug!("DNS discovery; not registering {:?}.", node.service);
+        Ok(())
+    }
+
+    async fn resolve_all(&self) -> Result<Vec<Node>, Error> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(|err| Error::new(&err.to_string()))?;
+        let records = resolver
+            .srv_lookup(self.name.as_str())
+            .await
+            .map_err(|err| Error::new(&err.to_string()))?;
+        records
+            .iter()
+            .map(|srv| to_node(&srv.target().to_utf8(), srv.port()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{Group, WorkerInfo};
+
+    #[test]
+    fn test_to_node() {
+        let node = to_node("worker-1-2.spectrum.example.com.", 6000).unwrap();
+        assert_eq!(
+            node,
+            Node::new(
+                WorkerInfo::new(Group::new(1), 2).into(),
+                "worker-1-2.spectrum.example.com:6000".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_to_node_bad_target() {
+        to_node("www.example.com.", 6000).expect_err("Not a service name.");
+    }
+}
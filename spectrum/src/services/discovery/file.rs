@@ -0,0 +1,168 @@
+use crate::config::store::Error;
+
+use super::{parse_service, Discovery, Node};
+use crate::net::Scheme;
+use log::debug;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct NodesFile {
+    #[serde(default)]
+    nodes: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    /// e.g. `publisher-0`, `leader-1`, or `worker-1-2` (0-indexed)
+    service: String,
+    addr: String,
+    #[serde(default)]
+    scheme: Scheme,
+    /// Path to a PEM-encoded certificate to pin for this node.
+    cert: Option<String>,
+}
+
+/// Nodes listed up front in a static TOML file.
+///
+/// ```toml
+/// [[nodes]]
+/// service = "leader-0"
+/// addr = "10.0.0.1:6000"
+/// scheme = "https"  # optional; default "http"
+/// cert = "certs/leader-0.pem"  # optional
+/// ```
+///
+/// Registration is a no-op: the file is the source of truth.
+#[derive(Debug, Clone)]
+pub struct FileDiscovery {
+    nodes: Vec<Node>,
+}
+
+impl FileDiscovery {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|err| Error::new(&format!("Couldn't read [{}]: {}", path.display(), err)))?;
+        // Relative cert paths are relative to the nodes file.
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::from_toml(&data, base)
+    }
+
+    fn from_toml(data: &str, base: &Path) -> Result<Self, Error> {
+        let file: NodesFile = toml::from_str(data).map_err(|err| Error::new(&err.to_string()))?;
+        let nodes = file
+            .nodes
+            .into_iter()
+            .map(|entry| {
+                let cert = entry
+                    .cert
+                    .map(|cert| {
+                        let cert = base.join(cert);
+                        fs::read_to_string(&cert).map_err(|err| {
+                            Error::new(&format!("Couldn't read [{}]: {}", cert.display(), err))
+                        })
+                    })
+                    .transpose()?;
+                Ok(Node {
+                    service: parse_service(&entry.service)?,
+                    addr: entry.addr,
+                    scheme: entry.scheme,
+                    cert,
+                    shm_inbox: None,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(FileDiscovery { nodes })
+    }
+}
+
+#[tonic::async_trait]
+impl Discovery for FileDiscovery {
+    async fn register(&self, node: Node) -> Result<(), Error> {
+        debug!("Static discovery; not registering {:?}.", node.service);
+        Ok(())
+    }
+
+    async fn resolve_all(&self) -> Result<Vec<Node>, Error> {
+        Ok(self.nodes.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{Group, LeaderInfo, PublisherInfo, WorkerInfo};
+    use std::io::Write;
+
+    #[test]
+    fn test_from_toml() {
+        let data = r#"
+            [[nodes]]
+            service = "publisher-0"
+            addr = "10.0.0.1:6000"
+
+            [[nodes]]
+            service = "leader-1"
+            addr = "leader.example.com:6000"
+
+            [[nodes]]
+            service = "worker-1-2"
+            addr = "10.0.0.3:6000"
+            scheme = "https"
+        "#;
+        let discovery = FileDiscovery::from_toml(data, Path::new("")).unwrap();
+        assert_eq!(
+            discovery.nodes,
+            vec![
+                Node::new(PublisherInfo::new(0).into(), "10.0.0.1:6000".to_string()),
+                Node::new(
+                    LeaderInfo::new(Group::new(1)).into(),
+                    "leader.example.com:6000".to_string()
+                ),
+                Node::new(
+                    WorkerInfo::new(Group::new(1), 2).into(),
+                    "10.0.0.3:6000".to_string()
+                )
+                .with_scheme(Scheme::Https),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_toml_empty() {
+        let discovery = FileDiscovery::from_toml("", Path::new("")).unwrap();
+        assert!(discovery.nodes.is_empty());
+    }
+
+    #[test]
+    fn test_from_toml_bad_service() {
+        let data = r#"
+            [[nodes]]
+            service = "viewer-0"
+            addr = "10.0.0.1:6000"
+        "#;
+        FileDiscovery::from_toml(data, Path::new("")).expect_err("Unknown service.");
+    }
+
+    #[test]
+    fn test_from_file_cert() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = "-----BEGIN CERTIFICATE-----\n...\n-----END CERTIFICATE-----\n";
+        fs::File::create(dir.path().join("leader.pem"))
+            .unwrap()
+            .write_all(cert.as_bytes())
+            .unwrap();
+        let path = dir.path().join("nodes.toml");
+        fs::write(
+            &path,
+            "[[nodes]]\nservice = \"leader-0\"\naddr = \"a:1\"\ncert = \"leader.pem\"\n",
+        )
+        .unwrap();
+
+        let discovery = FileDiscovery::from_file(&path).unwrap();
+        assert_eq!(discovery.nodes[0].cert.as_deref(), Some(cert));
+    }
+}
@@ -0,0 +1,246 @@
+//! Finding the other nodes in an experiment.
+//!
+//! By default, nodes register themselves in the config store (see
+//! [`StoreDiscovery`]). Deployments with their own service discovery can
+//! instead list nodes in a static file ([`FileDiscovery`]) or in DNS SRV
+//! records ([`DnsSrvDiscovery`]).
+use crate::{
+    config,
+    net::{
+        shm::ShmInbox,
+        tls::{self, Certificate},
+        Scheme,
+    },
+    services::{Group, LeaderInfo, PublisherInfo, Service, WorkerInfo},
+};
+
+use config::store::{Error, Key, Store, Value};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+mod dns;
+mod file;
+mod store;
+
+pub use dns::DnsSrvDiscovery;
+pub use file::FileDiscovery;
+pub use store::StoreDiscovery;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone)]
+pub struct Node {
+    pub service: Service,
+    pub addr: String,
+    /// Scheme to reach `addr` with (`https` if behind a TLS-terminating proxy).
+    pub scheme: Scheme,
+    /// PEM-encoded certificate pinned by this node, if any.
+    pub cert: Option<String>,
+    /// Shared-memory inbox for senders on the same host, if any.
+    pub shm_inbox: Option<ShmInbox>,
+}
+
+impl Node {
+    pub fn new(service: Service, addr: String) -> Node {
+        Node {
+            service,
+            addr,
+            scheme: Scheme::default(),
+            cert: None,
+            shm_inbox: None,
+        }
+    }
+
+    pub fn with_scheme(mut self, scheme: Scheme) -> Node {
+        self.scheme = scheme;
+        self
+    }
+
+    /// The URI at which to reach this node.
+    pub fn uri(&self) -> String {
+        format!("{}://{}", self.scheme, self.addr)
+    }
+
+    pub fn with_pinned_cert(mut self, cert: Option<Certificate>) -> Node {
+        self.cert = cert.map(|cert| String::from_utf8_lossy(cert.get_ref()).into_owned());
+        self
+    }
+
+    pub fn with_shm_inbox(mut self, inbox: Option<ShmInbox>) -> Node {
+        self.shm_inbox = inbox;
+        self
+    }
+
+    /// The certificate to verify this node against.
+    ///
+    /// Prefers the node's pinned certificate, falling back to `ca` (if any).
+    pub fn tls_cert(&self, ca: Option<Certificate>) -> Option<Certificate> {
+        self.cert.as_deref().and_then(tls::pinned).or(ca)
+    }
+}
+
+/// Parse a service from its (0-indexed) name, e.g. `worker-1-2`.
+///
+/// Names are `publisher-<idx>`, `leader-<group>`, or `worker-<group>-<idx>`.
+fn parse_service(name: &str) -> Result<Service, Error> {
+    let parse = |x: &str| {
+        x.parse::<u16>()
+            .map_err(|err| Error::new(&format!("Bad service name [{}]: {}", name, err)))
+    };
+    let parts: Vec<&str> = name.split('-').collect();
+    match parts[..] {
+        ["publisher", idx] => Ok(PublisherInfo::new(parse(idx)?).into()),
+        ["leader", group] => Ok(LeaderInfo::new(Group::new(parse(group)?)).into()),
+        ["worker", group, idx] => {
+            Ok(WorkerInfo::new(Group::new(parse(group)?), parse(idx)?).into())
+        }
+        _ => Err(Error::new(&format!("Bad service name [{}].", name))),
+    }
+}
+
+/// A source of truth for which nodes are in the experiment.
+#[tonic::async_trait]
+pub trait Discovery: Debug + Send + Sync {
+    /// Announce that `node` is up.
+    ///
+    /// Backends where nodes are managed externally may ignore this.
+    async fn register(&self, node: Node) -> Result<(), Error>;
+
+    /// Announce several nodes at once (e.g. everything in one process).
+    async fn register_all(&self, nodes: Vec<Node>) -> Result<(), Error> {
+        for node in nodes {
+            self.register(node).await?;
+        }
+        Ok(())
+    }
+
+    async fn resolve_all(&self) -> Result<Vec<Node>, Error>;
+}
+
+/// A config store, paired with a [`Discovery`] backend for finding nodes.
+#[derive(Debug, Clone)]
+pub struct Discovered<C> {
+    store: C,
+    discovery: Arc<dyn Discovery>,
+}
+
+impl<C> Discovered<C>
+where
+    C: 'static + Store + Clone + Send + Sync,
+{
+    /// Discover nodes through the config store itself.
+    pub fn new(store: C) -> Self {
+        let discovery = Arc::new(StoreDiscovery::new(store.clone()));
+        Discovered { store, discovery }
+    }
+}
+
+impl<C> Discovered<C> {
+    pub fn with_discovery<D: 'static + Discovery>(mut self, discovery: D) -> Self {
+        self.discovery = Arc::new(discovery);
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl<C: Store + Send + Sync> Store for Discovered<C> {
+    async fn get(&self, key: Key) -> Result<Option<Value>, Error> {
+        self.store.get(key).await
+    }
+
+    async fn put(&self, key: Key, value: Value) -> Result<(), Error> {
+        self.store.put(key, value).await
+    }
+
+    async fn put_batch(&self, entries: Vec<(Key, Value)>) -> Result<(), Error> {
+        self.store.put_batch(entries).await
+    }
+
+    async fn put_if_absent(&self, key: Key, value: Value) -> Result<bool, Error> {
+        self.store.put_if_absent(key, value).await
+    }
+
+    async fn compare_and_swap(&self, key: Key, current: Value, new: Value) -> Result<bool, Error> {
+        self.store.compare_and_swap(key, current, new).await
+    }
+
+    async fn list(&self, prefix: Key) -> Result<Vec<(Key, Value)>, Error> {
+        self.store.list(prefix).await
+    }
+
+    async fn delete_prefix(&self, prefix: Key) -> Result<usize, Error> {
+        self.store.delete_prefix(prefix).await
+    }
+}
+
+#[tonic::async_trait]
+impl<C: Debug + Send + Sync> Discovery for Discovered<C> {
+    async fn register(&self, node: Node) -> Result<(), Error> {
+        self.discovery.register(node).await
+    }
+
+    async fn register_all(&self, nodes: Vec<Node>) -> Result<(), Error> {
+        self.discovery.register_all(nodes).await
+    }
+
+    async fn resolve_all(&self) -> Result<Vec<Node>, Error> {
+        self.discovery.resolve_all().await
+    }
+}
+
+/// Register a server of the given type at the given address.
+pub async fn register<D: Discovery>(discovery: &D, node: Node) -> Result<(), Error> {
+    discovery.register(node).await
+}
+
+/// Register several servers in one go.
+pub async fn register_all<D: Discovery>(discovery: &D, nodes: Vec<Node>) -> Result<(), Error> {
+    discovery.register_all(nodes).await
+}
+
+pub async fn resolve_all<D: Discovery>(discovery: &D) -> Result<Vec<Node>, Error> {
+    discovery.resolve_all().await
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    pub fn services() -> impl Strategy<Value = Service> {
+        prop_oneof![
+            any::<u16>()
+                .prop_map(PublisherInfo::new)
+                .prop_map(Service::from),
+            any::<u16>()
+                .prop_map(Group::new)
+                .prop_map(LeaderInfo::new)
+                .prop_map(Service::from),
+            (any::<u16>(), any::<u16>())
+                .prop_map(|(group, idx)| WorkerInfo::new(Group::new(group), idx))
+                .prop_map(Service::from),
+        ]
+    }
+
+    fn service_name(service: &Service) -> String {
+        match service {
+            Service::Publisher(info) => format!("publisher-{}", info.idx),
+            Service::Leader(info) => format!("leader-{}", info.group.idx),
+            Service::Worker(info) => format!("worker-{}-{}", info.group.idx, info.idx),
+            Service::Client(_) => unreachable!(),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_service_roundtrip(service in services()) {
+            prop_assert_eq!(parse_service(&service_name(&service)).unwrap(), service);
+        }
+
+        #[test]
+        fn test_parse_service_bad(name in "\\PC*") {
+            prop_assume!(!name.starts_with("publisher-")
+                && !name.starts_with("leader-")
+                && !name.starts_with("worker-"));
+            prop_assert!(parse_service(&name).is_err());
+        }
+    }
+}
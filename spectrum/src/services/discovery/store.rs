@@ -0,0 +1,195 @@
+use crate::{
+    config,
+    net::{shm::ShmInbox, Scheme},
+    services::{Group, LeaderInfo, PublisherInfo, Service, WorkerInfo},
+};
+
+use super::{Discovery, Node};
+use config::store::{Error, Key, Store, Value};
+use serde::{Deserialize, Serialize};
+
+fn to_config_key(service: Service) -> Key {
+    match service {
+        Service::Leader(info) => vec![
+            "nodes".to_string(),
+            "groups".to_string(),
+            info.group.idx.to_string(),
+            "leader".to_string(),
+        ],
+        Service::Publisher(info) => vec![
+            "nodes".to_string(),
+            "publishers".to_string(),
+            info.idx.to_string(),
+        ],
+        Service::Worker(info) => vec![
+            "nodes".to_string(),
+            "groups".to_string(),
+            info.group.idx.to_string(),
+            info.idx.to_string(),
+        ],
+        Service::Client(_) => {
+            panic!("Clients are not stored in the config registry.");
+        }
+    }
+}
+
+// What actually gets stored in the config store for a node.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    addr: String,
+    // Absent from records written before we published schemes.
+    #[serde(default)]
+    scheme: Scheme,
+    cert: Option<String>,
+    #[serde(default)]
+    shm_inbox: Option<ShmInbox>,
+}
+
+fn to_entry(node: Node) -> Result<(Key, Value), Error> {
+    let record = Record {
+        addr: node.addr,
+        scheme: node.scheme,
+        cert: node.cert,
+        shm_inbox: node.shm_inbox,
+    };
+    let value = serde_json::to_string(&record).map_err(|err| Error::new(&err.to_string()))?;
+    Ok((to_config_key(node.service), value))
+}
+
+/// Nodes register themselves in the config store under `nodes/`.
+#[derive(Debug, Clone)]
+pub struct StoreDiscovery<C> {
+    config: C,
+}
+
+impl<C> StoreDiscovery<C> {
+    pub fn new(config: C) -> Self {
+        StoreDiscovery { config }
+    }
+}
+
+#[tonic::async_trait]
+impl<C: Store + Send + Sync> Discovery for StoreDiscovery<C> {
+    async fn register(&self, node: Node) -> Result<(), Error> {
+        let (key, value) = to_entry(node)?;
+        self.config.put(key, value).await
+    }
+
+    async fn register_all(&self, nodes: Vec<Node>) -> Result<(), Error> {
+        let entries = nodes.into_iter().map(to_entry).collect::<Result<_, _>>()?;
+        self.config.put_batch(entries).await
+    }
+
+    async fn resolve_all(&self) -> Result<Vec<Node>, Error> {
+        self.config
+            .list(vec!["nodes".to_string()])
+            .await?
+            .into_iter()
+            .map(|(key, value)| {
+                let key: Vec<&str> = key.iter().map(|x| x.as_str()).collect();
+                // TODO(zjn): don't unwrap
+                let service = match key[..] {
+                    ["nodes", "groups", group, "leader"] => {
+                        LeaderInfo::new(Group::new(group.parse().unwrap())).into()
+                    }
+                    ["nodes", "groups", group, idx] => {
+                        WorkerInfo::new(Group::new(group.parse().unwrap()), idx.parse().unwrap())
+                            .into()
+                    }
+                    ["nodes", "publishers", idx] => PublisherInfo::new(idx.parse().unwrap()).into(),
+                    _ => {
+                        panic!(); // TODO(zjn): better error
+                    }
+                };
+                let record: Record =
+                    serde_json::from_str(&value).map_err(|err| Error::new(&err.to_string()))?;
+                Ok(Node {
+                    service,
+                    addr: record.addr,
+                    scheme: record.scheme,
+                    cert: record.cert,
+                    shm_inbox: record.shm_inbox,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config,
+        net::tests::{addrs, schemes},
+        services::discovery::tests::services,
+    };
+    use config::tests::inmem_stores;
+    use futures::executor::block_on;
+    use prop::collection::hash_map;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    fn certs() -> impl Strategy<Value = Option<String>> {
+        prop::option::of(".*")
+    }
+
+    fn shm_inboxes() -> impl Strategy<Value = Option<ShmInbox>> {
+        prop::option::of(
+            ("[0-9a-f]{32}", "/dev/shm/[a-z0-9-]+")
+                .prop_map(|(machine_id, path)| ShmInbox { machine_id, path }),
+        )
+    }
+
+    fn node_sets() -> impl Strategy<Value = HashSet<Node>> {
+        let records = (addrs(), schemes(), certs(), shm_inboxes());
+        hash_map(services(), records, ..100).prop_map(|services_to_records| {
+            services_to_records
+                .into_iter()
+                .map(|(service, (addr, scheme, cert, shm_inbox))| Node {
+                    service,
+                    addr,
+                    scheme,
+                    cert,
+                    shm_inbox,
+                })
+                .collect::<HashSet<_>>()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_register_and_resolve(store in inmem_stores(), nodes in node_sets()) {
+            let discovery = StoreDiscovery::new(store);
+            let work = async {
+                for node in &nodes {
+                    discovery.register(node.clone()).await.unwrap();
+                }
+
+                let actual: HashSet<_> = discovery.resolve_all().await.unwrap().into_iter().collect();
+
+                assert_eq!(actual, nodes);
+            };
+            block_on(work);
+        }
+
+        #[test]
+        fn test_register_all_and_resolve(store in inmem_stores(), nodes in node_sets()) {
+            let discovery = StoreDiscovery::new(store);
+            let work = async {
+                discovery.register_all(nodes.iter().cloned().collect()).await.unwrap();
+
+                let actual: HashSet<_> = discovery.resolve_all().await.unwrap().into_iter().collect();
+
+                assert_eq!(actual, nodes);
+            };
+            block_on(work);
+        }
+    }
+
+    #[test]
+    fn test_record_default_scheme() {
+        let record: Record = serde_json::from_str(r#"{"addr": "a:1", "cert": null}"#).unwrap();
+        assert_eq!(record.scheme, Scheme::Http);
+        assert_eq!(record.shm_inbox, None);
+    }
+}
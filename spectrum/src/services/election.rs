@@ -0,0 +1,269 @@
+//! Electing the publisher that drives a round, through the config store.
+//!
+//! By default the first publisher picks the start time and the rest follow
+//! it. With election, any publisher can claim that role instead: the winner
+//! holds a lease in the config store (its address and when the lease runs
+//! out), taken with an atomic put-if-absent or compare-and-swap, so only one
+//! candidate wins at a time. The winner renews its lease well before it runs
+//! out; if the winner goes down, another candidate takes over once it has.
+//!
+//! Leaders look up the winner's address from the lease (see [`elected`]).
+use crate::config::store::{Error, Key, Store, Value};
+use crate::rt::{sleep, spawn, sync::watch, JoinHandle};
+
+use chrono::prelude::*;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How publishers elect the one that drives the round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElectionPolicy {
+    /// How long a win lasts without renewal.
+    pub lease: Duration,
+}
+
+impl Default for ElectionPolicy {
+    fn default() -> Self {
+        ElectionPolicy {
+            lease: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ElectionPolicy {
+    // Renew a few times per lease, so a slow renewal or two doesn't lose it.
+    fn renew_interval(&self) -> Duration {
+        self.lease / 3
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Lease {
+    /// The address of the publisher holding the lease.
+    holder: String,
+    /// When the lease runs out (milliseconds since the Unix epoch).
+    expires_ms: i64,
+}
+
+impl Lease {
+    fn new(holder: &str, now: DateTime<Utc>, lease: Duration) -> Self {
+        let lease = chrono::Duration::from_std(lease).expect("Lease duration out of range.");
+        Lease {
+            holder: holder.to_string(),
+            expires_ms: (now + lease).timestamp_millis(),
+        }
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now.timestamp_millis() >= self.expires_ms
+    }
+
+    fn to_value(&self) -> Value {
+        serde_json::to_string(self).expect("Leases should serialize.")
+    }
+
+    fn from_value(value: &str) -> Result<Self, Error> {
+        serde_json::from_str(value)
+            .map_err(|err| Error::new(&format!("Bad publisher lease [{}]: {}", value, err)))
+    }
+}
+
+pub(crate) fn lease_key(round: u64) -> Key {
+    vec![
+        "experiment".to_string(),
+        "publisher-lease".to_string(),
+        round.to_string(),
+    ]
+}
+
+/// Claim (or renew) the publisher lease for `round` for `candidate`.
+///
+/// Succeeds if nobody holds the lease, if it ran out, or if `candidate`
+/// already holds it. Returns whether `candidate` holds the lease now.
+pub async fn try_claim<C: Store>(
+    config: &C,
+    round: u64,
+    candidate: &str,
+    policy: ElectionPolicy,
+    now: DateTime<Utc>,
+) -> Result<bool, Error> {
+    let key = lease_key(round);
+    let lease = Lease::new(candidate, now, policy.lease).to_value();
+    match config.get(key.clone()).await? {
+        None => config.put_if_absent(key, lease).await,
+        Some(current) => {
+            let held = Lease::from_value(&current)?;
+            if held.holder != candidate && !held.is_expired(now) {
+                return Ok(false);
+            }
+            // Fails if someone else got there first.
+            config.compare_and_swap(key, current, lease).await
+        }
+    }
+}
+
+/// The address of the publisher holding the lease for `round`, if anyone
+/// does.
+pub async fn elected<C: Store>(
+    config: &C,
+    round: u64,
+    now: DateTime<Utc>,
+) -> Result<Option<String>, Error> {
+    match config.get(lease_key(round)).await? {
+        Some(value) => {
+            let lease = Lease::from_value(&value)?;
+            if lease.is_expired(now) {
+                return Ok(None);
+            }
+            Ok(Some(lease.holder))
+        }
+        None => Ok(None),
+    }
+}
+
+/// A candidate's ongoing attempt to win (and keep) the lease for a round.
+///
+/// Stops campaigning when dropped; the lease then runs out on its own.
+pub struct Campaign {
+    won: watch::Receiver<bool>,
+    task: JoinHandle<()>,
+}
+
+impl Campaign {
+    /// Campaign for `round` as `candidate` (the publisher's address).
+    pub fn start<C>(config: C, round: u64, candidate: String, policy: ElectionPolicy) -> Self
+    where
+        C: 'static + Store + Send + Sync,
+    {
+        let (tx, won) = watch::channel(false);
+        let task = spawn(async move {
+            loop {
+                let won = try_claim(&config, round, &candidate, policy, Utc::now())
+                    .await
+                    .unwrap_or_else(|err| {
+                        // Without a renewal, we can't be sure we still hold it.
+                        warn!("Publisher election failed: {}", err);
+                        false
+                    });
+                if won != *tx.borrow() {
+                    if won {
+                        info!("Elected publisher for round {}.", round);
+                    } else {
+                        warn!("Lost publisher election for round {}.", round);
+                    }
+                }
+                if tx.send(won).is_err() {
+                    return;
+                }
+                sleep(policy.renew_interval()).await;
+            }
+        });
+        Campaign { won, task }
+    }
+
+    /// Whether this candidate holds the lease (as of the last attempt).
+    pub fn is_elected(&self) -> bool {
+        *self.won.borrow()
+    }
+
+    /// Stop campaigning, and wait until the last renewal (if any) is done.
+    pub async fn stop(mut self) {
+        self.task.abort();
+        let _ = (&mut self.task).await;
+    }
+
+    /// Wait until this candidate wins the lease.
+    pub async fn elected(&self) {
+        let mut won = self.won.clone();
+        while !*won.borrow() {
+            if won.changed().await.is_err() {
+                // The campaign's over, and we never won.
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Drop for Campaign {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::factory::from_string;
+    use futures::future;
+
+    fn policy() -> ElectionPolicy {
+        ElectionPolicy {
+            lease: Duration::from_millis(100),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_claim_once() {
+        let config = from_string("").await.unwrap();
+        let now = Utc::now();
+        assert!(try_claim(&config, 0, "a", policy(), now).await.unwrap());
+        assert!(!try_claim(&config, 0, "b", policy(), now).await.unwrap());
+        // Renewing is fine.
+        assert!(try_claim(&config, 0, "a", policy(), now).await.unwrap());
+        assert_eq!(
+            elected(&config, 0, now).await.unwrap(),
+            Some("a".to_string())
+        );
+        // Each round has its own election.
+        assert!(try_claim(&config, 1, "b", policy(), now).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_claim_expired() {
+        let config = from_string("").await.unwrap();
+        let now = Utc::now();
+        assert!(try_claim(&config, 0, "a", policy(), now).await.unwrap());
+        let later = now + chrono::Duration::milliseconds(200);
+        assert_eq!(elected(&config, 0, later).await.unwrap(), None);
+        assert!(try_claim(&config, 0, "b", policy(), later).await.unwrap());
+        assert!(!try_claim(&config, 0, "a", policy(), later).await.unwrap());
+        assert_eq!(
+            elected(&config, 0, later).await.unwrap(),
+            Some("b".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_claim_concurrent() {
+        let config = from_string("").await.unwrap();
+        let now = Utc::now();
+        let candidates: Vec<String> = (0..5).map(|idx| format!("publisher{}", idx)).collect();
+        let wins = future::try_join_all(
+            candidates
+                .iter()
+                .map(|candidate| try_claim(&config, 0, candidate, policy(), now)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(wins.iter().filter(|won| **won).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_campaign_reelection() {
+        let config = from_string("").await.unwrap();
+        let first = Campaign::start(config.clone(), 0, "a".to_string(), policy());
+        first.elected().await;
+        let second = Campaign::start(config.clone(), 0, "b".to_string(), policy());
+        // The first candidate keeps renewing, so the second can't win...
+        sleep(Duration::from_millis(300)).await;
+        assert!(first.is_elected());
+        assert!(!second.is_elected());
+
+        // ...until the first one stops.
+        drop(first);
+        second.elected().await;
+        let elected = elected(&config, 0, Utc::now()).await.unwrap();
+        assert_eq!(elected, Some("b".to_string()));
+    }
+}
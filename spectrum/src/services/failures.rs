@@ -0,0 +1,115 @@
+//! Which failed tasks an in-process run can get by without.
+//!
+//! Publishers, leaders, and workers are critical: a round can't finish
+//! without every one of them, so the run should fail as soon as any of them
+//! does (rather than hang until it times out). A client failing only costs
+//! its own upload, so a run can put up with a few of those.
+use crate::services::Service;
+use crate::Error;
+
+use std::fmt;
+
+/// How many failed tasks a run puts up with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorBudget {
+    /// Up to this many clients may fail.
+    pub client_failures: usize,
+}
+
+impl ErrorBudget {
+    pub fn new(client_failures: usize) -> Self {
+        ErrorBudget { client_failures }
+    }
+
+    /// Check that the run can go on despite `failures` (so far).
+    pub fn check(&self, failures: &[TaskFailure]) -> Result<(), Error> {
+        if let Some(failure) = failures.iter().find(|f| f.is_critical()) {
+            return Err(Error::new(&format!("Critical task failed: {}", failure)));
+        }
+        if failures.len() > self.client_failures {
+            return Err(Error::new(&format!(
+                "Too many client failures ({}, budget {}); last: {}",
+                failures.len(),
+                self.client_failures,
+                failures.last().expect("more failures than budget")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A task (one service) that exited with an error.
+#[derive(Debug, Clone)]
+pub struct TaskFailure {
+    pub service: Service,
+    pub error: String,
+}
+
+impl TaskFailure {
+    pub fn new(service: Service, error: String) -> Self {
+        TaskFailure { service, error }
+    }
+
+    pub fn is_critical(&self) -> bool {
+        !matches!(self.service, Service::Client(_))
+    }
+}
+
+impl fmt::Display for TaskFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.service, self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{ClientInfo, Group, LeaderInfo, PublisherInfo, WorkerInfo};
+
+    fn failure(service: impl Into<Service>) -> TaskFailure {
+        TaskFailure::new(service.into(), "oops".to_string())
+    }
+
+    #[test]
+    fn test_no_failures() {
+        ErrorBudget::default().check(&[]).unwrap();
+        ErrorBudget::new(2).check(&[]).unwrap();
+    }
+
+    #[test]
+    fn test_critical_failures() {
+        let group = Group::new(0);
+        let critical = vec![
+            failure(PublisherInfo::new(0)),
+            failure(LeaderInfo::new(group)),
+            failure(WorkerInfo::new(group, 1)),
+        ];
+        for failure in critical {
+            assert!(failure.is_critical());
+            ErrorBudget::new(10)
+                .check(&[failure])
+                .expect_err("critical tasks can't fail");
+        }
+    }
+
+    #[test]
+    fn test_client_failures() {
+        let failures: Vec<_> = (0..3).map(|idx| failure(ClientInfo::new(idx))).collect();
+        assert!(!failures[0].is_critical());
+
+        let budget = ErrorBudget::new(2);
+        budget.check(&failures[..2]).unwrap();
+        budget.check(&failures).expect_err("over budget");
+        ErrorBudget::default()
+            .check(&failures[..1])
+            .expect_err("over budget");
+    }
+
+    #[test]
+    fn test_display() {
+        let worker = failure(WorkerInfo::new(Group::new(1), 0));
+        assert_eq!(worker.to_string(), "worker 2.1 (oops)");
+        let client = failure(ClientInfo::new(7));
+        assert_eq!(client.to_string(), "client 7 (oops)");
+    }
+}
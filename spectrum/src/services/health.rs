@@ -1,11 +1,10 @@
 use crate::config::store::Error;
+use crate::net::{client::Builder, tls::Certificate};
+use crate::rt::sleep;
 use log::debug;
+use std::cmp::min;
 use std::time::Duration;
-use tokio::time::sleep;
-use tonic::{
-    transport::Certificate, transport::Channel, transport::ClientTlsConfig, transport::Uri,
-    Request, Response, Status,
-};
+use tonic::{transport::Uri, Request, Response, Status};
 
 pub mod spectrum {
     tonic::include_proto!("grpc.health.v1");
@@ -18,8 +17,10 @@ pub use spectrum::{
     HealthCheckRequest, HealthCheckResponse,
 };
 
-const RETRY_DELAY: Duration = Duration::from_millis(50);
-const RETRY_ATTEMPTS: usize = 10;
+// Delay before the first retry; doubles after each failure (up to MAX_RETRY_DELAY).
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(1);
+const RETRY_ATTEMPTS: usize = 12;
 
 #[derive(Default)]
 pub struct AllGoodHealthServer {}
@@ -37,19 +38,8 @@ impl Health for AllGoodHealthServer {
     }
 }
 
-async fn is_healthy(addr: Uri, tls: Option<Certificate>) -> Result<bool, Error> {
-    let mut builder = Channel::builder(addr.clone());
-    if let Some(ref cert) = tls {
-        debug!("TLS for client.");
-        builder = builder
-            .tls_config(
-                ClientTlsConfig::new()
-                    .domain_name("spectrum.example.com")
-                    .ca_certificate(cert.clone()),
-            )
-            .map_err(|e| format!("{:?}", e))?;
-    }
-    let channel = builder.connect().await.map_err(|err| err.to_string())?;
+async fn is_healthy(builder: &Builder) -> Result<bool, Error> {
+    let channel = builder.channel().await.map_err(|err| err.to_string())?;
     let mut client = HealthClient::new(channel);
     let req = Request::new(HealthCheckRequest {
         service: "".to_string(),
@@ -58,15 +48,22 @@ async fn is_healthy(addr: Uri, tls: Option<Certificate>) -> Result<bool, Error>
     Ok(response.into_inner().status == ServingStatus::Serving as i32)
 }
 
+/// Whether the service at `addr` says it's serving (checking just once).
+pub async fn check_health(addr: &str, tls: Option<Certificate>) -> Result<bool, Error> {
+    is_healthy(&Builder::new(addr).tls(tls)).await
+}
+
 pub async fn wait_for_health_helper(
     addr: String,
     delay: Duration,
     attempts: usize,
     tls: Option<Certificate>,
 ) -> Result<(), Error> {
-    let uri = addr.parse::<Uri>().expect("invalid addr");
+    addr.parse::<Uri>().expect("invalid addr");
+    let builder = Builder::new(addr).tls(tls);
+    let mut delay = delay;
     for _ in 0..attempts {
-        match is_healthy(uri.clone(), tls.clone()).await {
+        match is_healthy(&builder).await {
             Ok(response) => {
                 if response {
                     return Ok(());
@@ -77,6 +74,7 @@ pub async fn wait_for_health_helper(
             }
         }
         sleep(delay).await;
+        delay = min(delay * 2, MAX_RETRY_DELAY);
     }
     Err(Error::new(&format!(
         "Service not healthy after {} attempts",
@@ -0,0 +1,457 @@
+//! Signed manifests of a round's recovered channels.
+//!
+//! Once a round is recovered, the publisher signs (ed25519) a manifest with a
+//! hash of each channel plus some round metadata, and publishes it to the
+//! config store and its `Remote`. Anybody holding the publisher's public key
+//! can then check that channel contents they got (say, from an untrusted
+//! mirror) are the authentic round result.
+use crate::config::store::{Error, Key, Store};
+use crate::metadata::RunMetadata;
+use crate::services::budget::StageReport;
+use crate::services::checksum::{self, ChannelStatus};
+use crate::services::privacy::Participation;
+use crate::services::reservation::SlotAssignments;
+
+use chrono::prelude::*;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use spectrum_primitives::Bytes;
+use std::convert::TryFrom;
+use std::fmt;
+
+fn manifest_key(round: u64, publisher: u16) -> Key {
+    vec![
+        "experiment".to_string(),
+        "manifests".to_string(),
+        round.to_string(),
+        publisher.to_string(),
+    ]
+}
+
+fn channel_hash(contents: &Bytes) -> String {
+    blake3::hash(contents.as_ref()).to_hex().to_string()
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, Error> {
+    hex::decode(value).map_err(|err| Error::new(&err.to_string()))
+}
+
+/// The broadcaster/viewer mix an experiment is set up for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpectedTraffic {
+    pub broadcasters: u64,
+    /// Broadcasters and viewers.
+    pub clients: u64,
+}
+
+/// Real broadcasts vs. cover traffic, as the publisher saw it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficMix {
+    pub expected: ExpectedTraffic,
+    /// Non-empty recovered channels. A message of all zeros looks the same as
+    /// no message, so this can undercount.
+    pub broadcasts: u64,
+    /// Clients whose uploads went into the round, unless participation
+    /// counts are private (see [`Manifest::participation`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clients: Option<u64>,
+}
+
+impl TrafficMix {
+    pub fn observe(expected: ExpectedTraffic, recovered: &[Bytes], clients: Option<u64>) -> Self {
+        let broadcasts = recovered
+            .iter()
+            .filter(|contents| checksum::check(contents.as_ref()) != ChannelStatus::Empty)
+            .count();
+        TrafficMix {
+            expected,
+            broadcasts: broadcasts as u64,
+            clients,
+        }
+    }
+
+    /// Uploads that were cover traffic (if we know how many clients there
+    /// were).
+    pub fn cover(&self) -> Option<u64> {
+        self.clients
+            .map(|clients| clients.saturating_sub(self.broadcasts))
+    }
+
+    /// Whether the round got the mix it was set up for.
+    pub fn as_expected(&self) -> bool {
+        self.broadcasts == self.expected.broadcasters
+            && self
+                .clients
+                .is_none_or(|clients| clients == self.expected.clients)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub round: u64,
+    /// Index of the publisher that recovered the round.
+    pub publisher: u16,
+    /// How many groups' shares went into the result.
+    pub groups: usize,
+    /// RFC 3339.
+    pub recovered_at: String,
+    /// Hex-encoded BLAKE3 hash of each channel's contents, in channel order.
+    pub channel_hashes: Vec<String>,
+    /// Channels that look like several broadcasters wrote to them (only
+    /// checked with channel checksums on).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suspected_collisions: Vec<usize>,
+    /// Noisy count of the clients in the round (only with participation
+    /// privacy on).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub participation: Option<Participation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traffic: Option<TrafficMix>,
+    /// Stage latencies against their budgets (only with stage budgets set).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stages: Option<StageReport>,
+    /// Where each reserved slot goes in the payload round (only for
+    /// reservation rounds).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot_assignments: Option<SlotAssignments>,
+    /// What produced the round: code version, build, and experiment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<RunMetadata>,
+}
+
+impl Manifest {
+    pub fn new(
+        round: u64,
+        publisher: u16,
+        groups: usize,
+        recovered_at: DateTime<FixedOffset>,
+        recovered: &[Bytes],
+    ) -> Self {
+        Manifest {
+            round,
+            publisher,
+            groups,
+            recovered_at: recovered_at.to_rfc3339(),
+            channel_hashes: recovered.iter().map(channel_hash).collect(),
+            suspected_collisions: vec![],
+            participation: None,
+            traffic: None,
+            stages: None,
+            slot_assignments: None,
+            run: None,
+        }
+    }
+
+    /// Whether `recovered` is exactly the channel contents described here.
+    pub fn matches(&self, recovered: &[Bytes]) -> bool {
+        self.channel_hashes.len() == recovered.len()
+            && self
+                .channel_hashes
+                .iter()
+                .zip(recovered)
+                .all(|(hash, contents)| *hash == channel_hash(contents))
+    }
+
+    // What gets signed. Verifiers re-serialize the parsed manifest, so
+    // formatting changes by a mirror don't matter.
+    fn signed_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Manifest should serialize.")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedManifest {
+    pub manifest: Manifest,
+    /// Hex-encoded ed25519 public key of the signer.
+    pub public_key: String,
+    /// Hex-encoded ed25519 signature over the JSON-serialized manifest.
+    pub signature: String,
+}
+
+impl SignedManifest {
+    /// The manifest, if it was signed by `key`.
+    pub fn verify(&self, key: &PublicKey) -> Result<&Manifest, Error> {
+        if self.public_key != hex::encode(key.as_bytes()) {
+            return Err(Error::new("Manifest signed by unexpected key."));
+        }
+        let signature = Signature::try_from(&decode_hex(&self.signature)?[..])
+            .map_err(|err| Error::new(&err.to_string()))?;
+        key.verify(&self.manifest.signed_bytes(), &signature)
+            .map_err(|_| Error::new("Bad manifest signature."))?;
+        Ok(&self.manifest)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Manifest should serialize.")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json).map_err(|err| Error::new(&err.to_string()))
+    }
+}
+
+/// Parse a hex-encoded public key (as printed by the publisher on startup).
+pub fn parse_public_key(value: &str) -> Result<PublicKey, Error> {
+    PublicKey::from_bytes(&decode_hex(value)?).map_err(|err| Error::new(&err.to_string()))
+}
+
+/// The publisher's manifest signing key.
+pub struct ManifestSigner {
+    keypair: Keypair,
+}
+
+impl ManifestSigner {
+    /// A fresh key; its public half has to be distributed out of band.
+    pub fn generate() -> Self {
+        Self::from_secret(&rand::random::<[u8; 32]>()).expect("32 bytes is a valid secret key.")
+    }
+
+    pub fn from_secret(secret: &[u8]) -> Result<Self, Error> {
+        let secret = SecretKey::from_bytes(secret).map_err(|err| Error::new(&err.to_string()))?;
+        let public = PublicKey::from(&secret);
+        Ok(ManifestSigner {
+            keypair: Keypair { secret, public },
+        })
+    }
+
+    /// Parse a hex-encoded 32-byte secret key.
+    pub fn from_hex(secret: &str) -> Result<Self, Error> {
+        Self::from_secret(&decode_hex(secret.trim())?)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.keypair.public
+    }
+
+    pub fn sign(&self, manifest: Manifest) -> SignedManifest {
+        let signature = self.keypair.sign(&manifest.signed_bytes());
+        SignedManifest {
+            manifest,
+            public_key: hex::encode(self.keypair.public.as_bytes()),
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+}
+
+// Don't print the secret key.
+impl fmt::Debug for ManifestSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManifestSigner")
+            .field("public_key", &hex::encode(self.keypair.public.as_bytes()))
+            .finish()
+    }
+}
+
+pub async fn publish_manifest<C: Store>(config: &C, signed: &SignedManifest) -> Result<(), Error> {
+    let key = manifest_key(signed.manifest.round, signed.manifest.publisher);
+    config.put(key, signed.to_json()).await
+}
+
+/// The manifest `publisher` published for `round`, if any (unverified).
+pub async fn get_manifest<C: Store>(
+    config: &C,
+    round: u64,
+    publisher: u16,
+) -> Result<Option<SignedManifest>, Error> {
+    match config.get(manifest_key(round, publisher)).await? {
+        Some(json) => SignedManifest::from_json(&json).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::factory::from_string;
+
+    fn recovered() -> Vec<Bytes> {
+        vec![Bytes::from(b"hello".to_vec()), Bytes::empty(5)]
+    }
+
+    fn manifest() -> Manifest {
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 0, 0, 0)
+            .unwrap();
+        Manifest::new(3, 0, 2, now, &recovered())
+    }
+
+    #[test]
+    fn test_manifest_matches() {
+        let manifest = manifest();
+        assert!(manifest.matches(&recovered()));
+
+        let mut tampered = recovered();
+        tampered[1] = Bytes::from(b"oops!".to_vec());
+        assert!(!manifest.matches(&tampered));
+        assert!(!manifest.matches(&recovered()[..1]));
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let signer = ManifestSigner::generate();
+        let signed = signer.sign(manifest());
+        assert_eq!(signed.verify(&signer.public_key()).unwrap(), &manifest());
+
+        // Round-trips through (re-formatted) JSON.
+        let json = serde_json::to_string_pretty(&signed).unwrap();
+        let parsed = SignedManifest::from_json(&json).unwrap();
+        parsed.verify(&signer.public_key()).unwrap();
+    }
+
+    #[test]
+    fn test_verify_rejects_tampering() {
+        let signer = ManifestSigner::generate();
+        let signed = signer.sign(manifest());
+
+        let mut tampered = signed.clone();
+        tampered.manifest.channel_hashes.swap(0, 1);
+        tampered
+            .verify(&signer.public_key())
+            .expect_err("Tampered manifest should fail to verify.");
+
+        let other = ManifestSigner::generate();
+        signed
+            .verify(&other.public_key())
+            .expect_err("Manifest should only verify under the signer's key.");
+        let mut resigned = other.sign(manifest());
+        resigned.public_key = signed.public_key.clone();
+        resigned
+            .verify(&signer.public_key())
+            .expect_err("Signature by another key should fail to verify.");
+    }
+
+    #[test]
+    fn test_suspected_collisions_signed() {
+        let signer = ManifestSigner::generate();
+        let mut manifest = manifest();
+        // Left out of the JSON when there aren't any.
+        assert!(!signer
+            .sign(manifest.clone())
+            .to_json()
+            .contains("suspected"));
+
+        manifest.suspected_collisions = vec![1];
+        let signed = signer.sign(manifest);
+        let parsed = SignedManifest::from_json(&signed.to_json()).unwrap();
+        assert_eq!(
+            parsed
+                .verify(&signer.public_key())
+                .unwrap()
+                .suspected_collisions,
+            vec![1]
+        );
+        let mut tampered = parsed;
+        tampered.manifest.suspected_collisions.clear();
+        tampered
+            .verify(&signer.public_key())
+            .expect_err("Dropping a suspected collision should fail to verify.");
+    }
+
+    #[test]
+    fn test_participation_signed() {
+        use crate::services::privacy::{Accountant, PrivacyBudget};
+
+        let signer = ManifestSigner::generate();
+        let mut manifest = manifest();
+        assert!(!signer
+            .sign(manifest.clone())
+            .to_json()
+            .contains("participation"));
+
+        let budget = PrivacyBudget::new(1.0, 1e-6).unwrap();
+        manifest.participation = Some(Accountant::new(budget).publish(17));
+        let signed = signer.sign(manifest.clone());
+        let parsed = SignedManifest::from_json(&signed.to_json()).unwrap();
+        assert_eq!(parsed.verify(&signer.public_key()).unwrap(), &manifest);
+        let mut tampered = parsed;
+        tampered.manifest.participation.as_mut().unwrap().clients = 16;
+        tampered
+            .verify(&signer.public_key())
+            .expect_err("Changing the count should fail to verify.");
+    }
+
+    #[test]
+    fn test_traffic_mix() {
+        let expected = ExpectedTraffic {
+            broadcasters: 1,
+            clients: 4,
+        };
+        let mix = TrafficMix::observe(expected, &recovered(), Some(4));
+        assert_eq!(mix.broadcasts, 1);
+        assert_eq!(mix.cover(), Some(3));
+        assert!(mix.as_expected());
+
+        let missing = TrafficMix::observe(expected, &[Bytes::empty(5)], Some(3));
+        assert_eq!(missing.broadcasts, 0);
+        assert!(!missing.as_expected());
+        // Without a client count, only the broadcasts are checked.
+        let private = TrafficMix::observe(expected, &recovered(), None);
+        assert_eq!(private.cover(), None);
+        assert!(private.as_expected());
+    }
+
+    #[test]
+    fn test_stages_signed() {
+        use crate::services::budget::{StageBudgets, StageTallies};
+        use std::time::Duration;
+
+        let signer = ManifestSigner::generate();
+        let mut manifest = manifest();
+        assert!(!signer.sign(manifest.clone()).to_json().contains("stages"));
+
+        let budgets: StageBudgets = "aggregate=1s".parse().unwrap();
+        let mut stages = StageTallies::default();
+        stages
+            .aggregate
+            .observe(Duration::from_secs(2), budgets.aggregate);
+        manifest.stages = Some(StageReport::new(budgets, stages));
+        let signed = signer.sign(manifest.clone());
+        let parsed = SignedManifest::from_json(&signed.to_json()).unwrap();
+        assert_eq!(parsed.verify(&signer.public_key()).unwrap(), &manifest);
+        let mut tampered = parsed;
+        tampered.manifest.stages.as_mut().unwrap().within_budget = true;
+        tampered
+            .verify(&signer.public_key())
+            .expect_err("Hiding a budget violation should fail to verify.");
+    }
+
+    #[test]
+    fn test_slot_assignments_signed() {
+        use crate::services::reservation::ReservationRound;
+
+        let signer = ManifestSigner::generate();
+        let round = ReservationRound::new(4, 64);
+        let recovered = vec![round.sample_message(&mut rand::thread_rng()).0];
+        let mut manifest = manifest();
+        manifest.slot_assignments = Some(round.tally(&recovered).unwrap());
+        let parsed = SignedManifest::from_json(&signer.sign(manifest.clone()).to_json()).unwrap();
+        assert_eq!(parsed.verify(&signer.public_key()).unwrap(), &manifest);
+        let mut tampered = parsed;
+        let slots = tampered.manifest.slot_assignments.as_mut().unwrap();
+        slots.assignments[0].offset += 1;
+        tampered
+            .verify(&signer.public_key())
+            .expect_err("Moving a slot should fail to verify.");
+    }
+
+    #[test]
+    fn test_signer_from_hex() {
+        let secret = [7u8; 32];
+        let signer = ManifestSigner::from_hex(&hex::encode(secret)).unwrap();
+        let key = hex::encode(signer.public_key().as_bytes());
+        assert_eq!(parse_public_key(&key).unwrap(), signer.public_key());
+        ManifestSigner::from_hex("not hex").expect_err("Should fail to parse.");
+    }
+
+    #[tokio::test]
+    async fn test_publish_and_get() {
+        let config = from_string("").await.unwrap();
+        assert_eq!(get_manifest(&config, 3, 0).await.unwrap(), None);
+
+        let signed = ManifestSigner::generate().sign(manifest());
+        publish_manifest(&config, &signed).await.unwrap();
+        assert_eq!(get_manifest(&config, 3, 0).await.unwrap(), Some(signed));
+        assert_eq!(get_manifest(&config, 3, 1).await.unwrap(), None);
+    }
+}
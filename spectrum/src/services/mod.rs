@@ -1,14 +1,33 @@
+pub mod abort;
+pub mod admin;
+pub mod assignment;
+pub mod budget;
+pub mod checksum;
+pub mod compaction;
+pub mod deadline;
+pub mod decoding;
 pub mod discovery;
+pub mod election;
+pub mod failures;
 pub mod health;
+pub mod manifest;
+pub mod pir;
+pub mod privacy;
 pub mod quorum;
+pub mod registration;
+pub mod reservation;
 mod retry;
+pub mod revocation;
 
 use spectrum_primitives::Bytes;
 
-use crate::proto::{ClientId, WorkerId};
+use crate::proto::{self, ClientId, WorkerId};
 use crate::protocols::wrapper::ChannelKeyWrapper;
 
+use std::convert::TryFrom;
+use std::fmt;
 use std::hash::{Hash, Hasher};
+use tonic::Status;
 
 #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 #[non_exhaustive]
@@ -47,13 +66,46 @@ impl WorkerInfo {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Default)]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 #[non_exhaustive]
-pub struct PublisherInfo {}
+pub struct PublisherInfo {
+    /// Index of this publisher among the (replicated) publishers.
+    pub idx: u16,
+}
 
 impl PublisherInfo {
-    pub fn new() -> Self {
-        PublisherInfo::default()
+    pub fn new(idx: u16) -> Self {
+        PublisherInfo { idx }
+    }
+}
+
+/// What a client says it's doing (for metrics; clients can lie).
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Default)]
+pub enum ClientRole {
+    /// Didn't say.
+    #[default]
+    Unknown,
+    Viewer,
+    Broadcaster,
+}
+
+impl From<ClientRole> for proto::ClientRole {
+    fn from(role: ClientRole) -> Self {
+        match role {
+            ClientRole::Unknown => proto::ClientRole::Unspecified,
+            ClientRole::Viewer => proto::ClientRole::Viewer,
+            ClientRole::Broadcaster => proto::ClientRole::Broadcaster,
+        }
+    }
+}
+
+impl From<proto::ClientRole> for ClientRole {
+    fn from(role: proto::ClientRole) -> Self {
+        match role {
+            proto::ClientRole::Unspecified => ClientRole::Unknown,
+            proto::ClientRole::Viewer => ClientRole::Viewer,
+            proto::ClientRole::Broadcaster => ClientRole::Broadcaster,
+        }
     }
 }
 
@@ -61,6 +113,7 @@ impl PublisherInfo {
 #[non_exhaustive]
 pub struct ClientInfo {
     pub idx: u128,
+    pub role: ClientRole,
     pub broadcast: Option<(Bytes, ChannelKeyWrapper)>,
 }
 
@@ -82,6 +135,7 @@ impl ClientInfo {
     pub fn new(idx: u128) -> Self {
         ClientInfo {
             idx,
+            role: ClientRole::Viewer,
             broadcast: None,
         }
     }
@@ -89,11 +143,15 @@ impl ClientInfo {
     pub fn new_broadcaster(idx: u128, message: Bytes, key: ChannelKeyWrapper) -> Self {
         ClientInfo {
             idx,
+            role: ClientRole::Broadcaster,
             broadcast: Some((message, key)),
         }
     }
 }
 
+// Clients carry their broadcast (if any); there are few enough `Service`s
+// around that the size doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Service {
     Leader(LeaderInfo),
@@ -102,6 +160,18 @@ pub enum Service {
     Client(ClientInfo),
 }
 
+/// Short and human-readable (e.g. `worker 2.1`), with 1-based indices.
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Service::Publisher(info) => write!(f, "publisher {}", info.idx + 1),
+            Service::Leader(info) => write!(f, "leader of group {}", info.group.idx + 1),
+            Service::Worker(info) => write!(f, "worker {}.{}", info.group.idx + 1, info.idx + 1),
+            Service::Client(info) => write!(f, "client {}", info.idx),
+        }
+    }
+}
+
 impl From<LeaderInfo> for Service {
     fn from(info: LeaderInfo) -> Self {
         Service::Leader(info)
@@ -130,7 +200,9 @@ impl ClientInfo {
     // TODO(zjn): merge these more closely?
     pub fn to_proto(&self) -> ClientId {
         ClientId {
-            client_id: self.idx.to_string(),
+            idx_high: (self.idx >> 64) as u64,
+            idx_low: self.idx as u64,
+            role: proto::ClientRole::from(self.role) as i32,
         }
     }
 }
@@ -151,9 +223,50 @@ impl From<WorkerId> for WorkerInfo {
     }
 }
 
-impl From<&ClientId> for ClientInfo {
-    fn from(client: &ClientId) -> ClientInfo {
-        // TODO(zjn): change proto type of client_id from string to uint32
-        ClientInfo::new(client.client_id.parse().expect("Should parse as number"))
+impl TryFrom<&ClientId> for ClientInfo {
+    type Error = Status;
+
+    fn try_from(client: &ClientId) -> Result<ClientInfo, Status> {
+        let role = proto::ClientRole::from_i32(client.role).ok_or_else(|| {
+            Status::invalid_argument(format!("Unknown client role {}.", client.role))
+        })?;
+        Ok(ClientInfo {
+            idx: (u128::from(client.idx_high) << 64) | u128::from(client.idx_low),
+            role: role.into(),
+            broadcast: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn roles() -> impl Strategy<Value = ClientRole> {
+        prop_oneof![
+            Just(ClientRole::Unknown),
+            Just(ClientRole::Viewer),
+            Just(ClientRole::Broadcaster),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_client_id_roundtrip(idx: u128, role in roles()) {
+            let mut info = ClientInfo::new(idx);
+            info.role = role;
+            let parsed = ClientInfo::try_from(&info.to_proto()).unwrap();
+            prop_assert_eq!(parsed.idx, idx);
+            prop_assert_eq!(parsed.role, role);
+        }
+    }
+
+    #[test]
+    fn test_client_id_bad_role() {
+        let mut client = ClientInfo::new(1).to_proto();
+        client.role = 42;
+        let status = ClientInfo::try_from(&client).expect_err("Unknown role should fail.");
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
     }
 }
@@ -0,0 +1,204 @@
+//! Private information retrieval (PIR) of recovered channels.
+//!
+//! Every publisher recovers the whole round, so any two publishers hold the
+//! same database: one row per channel. To fetch a single channel without
+//! downloading all of them, a viewer sends each of two publishers one of a
+//! pair of DPF keys (see [`DpfDatabase`]) and XORs their answers. Neither
+//! publisher alone learns which channel it was; the two of them together do.
+//!
+//! Publishers only keep the latest round they recovered.
+use crate::net::{client::Builder, tls::Certificate, ClientChannel};
+use crate::proto::{
+    expect_field, pir_client::PirClient, pir_server::Pir, PirQueryRequest, PirQueryResponse,
+};
+use crate::rt::{spawn_blocking, sync::RwLock};
+use crate::services::{
+    discovery::{resolve_all, Discovery},
+    PublisherInfo, Service,
+};
+
+use futures::future;
+use log::debug;
+use spectrum_primitives::pir::{Database, DpfDatabase, DpfQuery};
+use spectrum_primitives::Bytes;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+type TokioError = Box<dyn std::error::Error + Sync + Send>;
+// The latest round we've published, with its database.
+type Published = Option<(u64, Arc<DpfDatabase>)>;
+
+/// Serves PIR queries against the latest recovered round.
+#[derive(Default, Clone)]
+pub struct PirService {
+    database: Arc<RwLock<Published>>,
+}
+
+impl PirService {
+    /// Serve `channels` (as recovered in `round`) from now on.
+    pub async fn publish(&self, round: u64, channels: &[Bytes]) {
+        if channels.is_empty() {
+            return;
+        }
+        let rows = channels.iter().map(|c| c.as_ref().to_vec()).collect();
+        let database = Arc::new(DpfDatabase::from_vec(rows));
+        *self.database.write().await = Some((round, database));
+        debug!("Serving PIR queries for round {}.", round);
+    }
+}
+
+#[tonic::async_trait]
+impl Pir for PirService {
+    async fn query(
+        &self,
+        request: Request<PirQueryRequest>,
+    ) -> Result<Response<PirQueryResponse>, Status> {
+        let request = request.into_inner();
+        let (round, database) = self
+            .database
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| Status::unavailable("No round recovered yet."))?;
+        if request.round != round {
+            return Err(Status::not_found(format!(
+                "Round {} isn't available (latest is {}).",
+                request.round, round
+            )));
+        }
+        let query = DpfQuery::try_from(expect_field(request.query, "Query")?)
+            .map_err(Status::invalid_argument)?;
+        let answer = spawn_blocking(move || database.answer(query))
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?
+            .map_err(|()| Status::invalid_argument("Query doesn't match the database."))?;
+        Ok(Response::new(PirQueryResponse { answer }))
+    }
+}
+
+async fn query(
+    client: &mut PirClient<ClientChannel>,
+    round: u64,
+    query: DpfQuery,
+) -> Result<Vec<u8>, Status> {
+    let request = PirQueryRequest {
+        round,
+        query: Some(query.into()),
+    };
+    Ok(client.query(request).await?.into_inner().answer)
+}
+
+/// Privately fetch `channel` (out of `channels`) of `round` from the two PIR
+/// servers at `clients`.
+pub async fn fetch(
+    clients: (&mut PirClient<ClientChannel>, &mut PirClient<ClientChannel>),
+    round: u64,
+    channel: usize,
+    channels: usize,
+) -> Result<Bytes, Status> {
+    let [first, second] = <DpfDatabase as Database<2>>::queries(channel, channels)
+        .map_err(|()| Status::invalid_argument(format!("No channel {}.", channel)))?;
+    let answers = future::try_join(
+        query(clients.0, round, first),
+        query(clients.1, round, second),
+    )
+    .await?;
+    if answers.0.len() != answers.1.len() {
+        return Err(Status::internal("PIR servers gave different-size answers."));
+    }
+    Ok(<DpfDatabase as Database<2>>::combine([answers.0, answers.1]).into())
+}
+
+/// Privately fetch `channel` (out of `channels`) of `round` from the first
+/// two publishers, on behalf of `me`.
+pub async fn fetch_from_publishers<D: Discovery>(
+    discovery: &D,
+    me: Service,
+    cert: Option<Certificate>,
+    round: u64,
+    channel: usize,
+    channels: usize,
+) -> Result<Bytes, TokioError> {
+    let nodes = resolve_all(discovery).await?;
+    let mut clients = vec![];
+    for idx in 0..2 {
+        let publisher = Service::from(PublisherInfo::new(idx));
+        let node = nodes
+            .iter()
+            .find(|node| node.service == publisher)
+            .ok_or("PIR needs two publishers.")?;
+        let builder = Builder::new(node.uri()).tls(node.tls_cert(cert.clone()));
+        clients.push(builder.pir_client(me.clone(), publisher).await?);
+    }
+    let (first, second) = clients.split_at_mut(1);
+    let clients = (&mut first[0], &mut second[0]);
+    Ok(fetch(clients, round, channel, channels).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn channels() -> impl Strategy<Value = (Vec<Bytes>, usize)> {
+        (1..10usize, 1..100usize).prop_flat_map(|(count, len)| {
+            (
+                prop::collection::vec(any_with::<Bytes>(len.into()), count),
+                0..count,
+            )
+        })
+    }
+
+    async fn answer(service: &PirService, round: u64, query: DpfQuery) -> Result<Vec<u8>, Status> {
+        let request = PirQueryRequest {
+            round,
+            query: Some(query.into()),
+        };
+        let response = service.query(Request::new(request)).await?;
+        Ok(response.into_inner().answer)
+    }
+
+    proptest! {
+        #[test]
+        fn test_query((channels, channel) in channels()) {
+            let service = PirService::default();
+            crate::rt::block_on(async {
+                service.publish(7, &channels).await;
+                let [first, second] =
+                    <DpfDatabase as Database<2>>::queries(channel, channels.len()).unwrap();
+                let first = answer(&service, 7, first).await.unwrap();
+                let second = answer(&service, 7, second).await.unwrap();
+                let fetched = <DpfDatabase as Database<2>>::combine([first, second]);
+                assert_eq!(Bytes::from(fetched), channels[channel]);
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_wrong_round() {
+        let service = PirService::default();
+        let [query, _] = <DpfDatabase as Database<2>>::queries(0, 2).unwrap();
+        let err = answer(&service, 1, query.clone()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unavailable);
+
+        service
+            .publish(1, &[Bytes::empty(16), Bytes::empty(16)])
+            .await;
+        service
+            .publish(2, &[Bytes::empty(16), Bytes::empty(16)])
+            .await;
+        let err = answer(&service, 1, query.clone()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        answer(&service, 2, query).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_query_wrong_size() {
+        let service = PirService::default();
+        service.publish(1, &[Bytes::empty(16)]).await;
+        let [query, _] = <DpfDatabase as Database<2>>::queries(0, 2).unwrap();
+        let err = answer(&service, 1, query).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+}
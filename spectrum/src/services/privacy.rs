@@ -0,0 +1,272 @@
+//! Differentially private client participation counts.
+//!
+//! With participation privacy on, each worker adds random noise to the number
+//! of clients it processed in a round. Only the count changes: nobody makes
+//! extra writes. (Null writes would leave the share unchanged anyway, so the
+//! count is as if that many more clients had made them.) The publisher puts
+//! the resulting count in the round's manifest.
+//!
+//! A client changes exactly one worker's count (per group) by one, so each
+//! worker's noise on its own makes the count (ε, δ)-differentially private;
+//! everything downstream (leaders summing counts, other workers' noise) is
+//! post-processing. The noise is discrete Laplace, shifted up so that it's
+//! negative (and has to be cut off at zero, since records can't be removed)
+//! with probability at most δ.
+//!
+//! Each group's count is private on its own, so the publisher only publishes
+//! the first group's. Rounds compose: the manifest tracks the total budget
+//! spent so far (by basic composition).
+use crate::Error;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// An (ε, δ) differential privacy guarantee.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedBudget")]
+pub struct PrivacyBudget {
+    epsilon: f64,
+    delta: f64,
+}
+
+// Both parameters are checked to be finite, so never NaN.
+impl Eq for PrivacyBudget {}
+
+#[derive(Deserialize)]
+struct UncheckedBudget {
+    epsilon: f64,
+    delta: f64,
+}
+
+impl TryFrom<UncheckedBudget> for PrivacyBudget {
+    type Error = Error;
+
+    fn try_from(budget: UncheckedBudget) -> Result<Self, Self::Error> {
+        PrivacyBudget::new(budget.epsilon, budget.delta)
+    }
+}
+
+impl PrivacyBudget {
+    /// Requires `epsilon > 0` and `0 < delta < 1`.
+    pub fn new(epsilon: f64, delta: f64) -> Result<Self, Error> {
+        if !(epsilon.is_finite() && epsilon > 0.0) {
+            return Err(Error::new(&format!(
+                "Epsilon must be positive (got {}).",
+                epsilon
+            )));
+        }
+        if !(delta > 0.0 && delta < 1.0) {
+            return Err(Error::new(&format!(
+                "Delta must be strictly between 0 and 1 (got {}).",
+                delta
+            )));
+        }
+        Ok(PrivacyBudget { epsilon, delta })
+    }
+
+    pub fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    pub fn delta(&self) -> f64 {
+        self.delta
+    }
+
+    // exp(-ε): the ratio between the probabilities of adjacent noise values.
+    fn alpha(&self) -> f64 {
+        (-self.epsilon).exp()
+    }
+
+    /// How far the noise is shifted up, so that it's negative with
+    /// probability at most δ.
+    ///
+    /// For discrete Laplace noise `Z`, `P[Z <= -k] = α^k / (1 + α)`.
+    pub fn noise_shift(&self) -> u64 {
+        let alpha = self.alpha();
+        let k = ((self.delta * (1.0 + alpha)).ln() / alpha.ln()).ceil();
+        // k is the smallest magnitude with small enough tail; the shift is one
+        // less (the noise is cut off at -shift - 1).
+        (k - 1.0).max(0.0) as u64
+    }
+}
+
+impl fmt::Display for PrivacyBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.epsilon, self.delta)
+    }
+}
+
+impl FromStr for PrivacyBudget {
+    type Err = String;
+
+    /// Parse `<epsilon>,<delta>`, e.g. `1.0,1e-6`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epsilon, delta) = s
+            .split_once(',')
+            .ok_or_else(|| format!("Expected <epsilon>,<delta>, got [{}].", s))?;
+        let epsilon = epsilon
+            .trim()
+            .parse()
+            .map_err(|err| format!("Bad epsilon [{}]: {}", epsilon, err))?;
+        let delta = delta
+            .trim()
+            .parse()
+            .map_err(|err| format!("Bad delta [{}]: {}", delta, err))?;
+        PrivacyBudget::new(epsilon, delta).map_err(|err| err.to_string())
+    }
+}
+
+// Failures before the first success, with success probability 1 - α.
+fn sample_geometric<R: Rng>(rng: &mut R, alpha: f64) -> u64 {
+    // 1 - gen() is in (0, 1], so the log is finite.
+    let uniform: f64 = 1.0 - rng.gen::<f64>();
+    (uniform.ln() / alpha.ln()).floor() as u64
+}
+
+/// How much noise a worker adds to its client count for one round.
+pub fn sample_noise<R: Rng>(rng: &mut R, budget: &PrivacyBudget) -> u64 {
+    let alpha = budget.alpha();
+    // The difference of two geometric variables is discrete Laplace.
+    let laplace = sample_geometric(rng, alpha) as i128 - sample_geometric(rng, alpha) as i128;
+    let noise = budget.noise_shift() as i128 + laplace;
+    noise.max(0) as u64
+}
+
+/// A round's published participation count, with the privacy accounting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Participation {
+    /// Clients that took part, plus noise.
+    pub clients: u64,
+    /// The guarantee for this round's count alone.
+    pub round_budget: PrivacyBudget,
+    /// Counts published so far (including this one).
+    pub rounds: u64,
+    /// Total ε spent on the counts published so far.
+    pub epsilon_spent: f64,
+    /// Total δ spent on the counts published so far.
+    pub delta_spent: f64,
+}
+
+// Computed from a (non-NaN) budget, so never NaN.
+impl Eq for Participation {}
+
+/// Tracks the budget spent on published counts.
+#[derive(Debug)]
+pub struct Accountant {
+    budget: PrivacyBudget,
+    rounds: u64,
+}
+
+impl Accountant {
+    pub fn new(budget: PrivacyBudget) -> Self {
+        Accountant { budget, rounds: 0 }
+    }
+
+    /// Account for publishing one more (noisy) count of `clients`.
+    pub fn publish(&mut self, clients: u64) -> Participation {
+        self.rounds += 1;
+        Participation {
+            clients,
+            round_budget: self.budget,
+            rounds: self.rounds,
+            epsilon_spent: self.budget.epsilon * self.rounds as f64,
+            delta_spent: self.budget.delta * self.rounds as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    proptest! {
+        #[test]
+        fn test_noise_shift_bounds_tail(epsilon in 0.01..10.0f64, delta in 1e-12..0.5f64) {
+            let budget = PrivacyBudget::new(epsilon, delta).unwrap();
+            let alpha = budget.alpha();
+            // P[shift + Z < 0] = P[Z <= -(shift + 1)]
+            let tail = |k: u64| alpha.powi(k as i32) / (1.0 + alpha);
+            let shift = budget.noise_shift();
+            prop_assert!(tail(shift + 1) <= delta * (1.0 + 1e-9));
+            // ...and the shift is no bigger than it needs to be.
+            if shift > 0 {
+                prop_assert!(tail(shift) > delta * (1.0 - 1e-9));
+            }
+        }
+
+        #[test]
+        fn test_budget_string_roundtrip(epsilon in 0.01..10.0f64, delta in 1e-12..0.5f64) {
+            let budget = PrivacyBudget::new(epsilon, delta).unwrap();
+            prop_assert_eq!(budget.to_string().parse::<PrivacyBudget>(), Ok(budget));
+        }
+    }
+
+    #[test]
+    fn test_budget_validation() {
+        PrivacyBudget::new(0.0, 1e-6).expect_err("epsilon must be positive");
+        PrivacyBudget::new(f64::INFINITY, 1e-6).expect_err("epsilon must be finite");
+        PrivacyBudget::new(1.0, 0.0).expect_err("delta must be positive");
+        PrivacyBudget::new(1.0, 1.0).expect_err("delta must be less than 1");
+        PrivacyBudget::new(1.0, f64::NAN).expect_err("delta can't be NaN");
+        "1.0".parse::<PrivacyBudget>().expect_err("missing delta");
+        "-1,1e-6"
+            .parse::<PrivacyBudget>()
+            .expect_err("negative epsilon");
+
+        let json = r#"{"epsilon": 1.0, "delta": 2.0}"#;
+        serde_json::from_str::<PrivacyBudget>(json).expect_err("should validate");
+        let budget = PrivacyBudget::new(0.5, 1e-6).unwrap();
+        let json = serde_json::to_string(&budget).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PrivacyBudget>(&json).unwrap(),
+            budget
+        );
+    }
+
+    #[test]
+    fn test_noise_distribution() {
+        let budget = PrivacyBudget::new(1.0, 1e-6).unwrap();
+        let shift = budget.noise_shift() as f64;
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples: Vec<u64> = (0..10_000)
+            .map(|_| sample_noise(&mut rng, &budget))
+            .collect();
+        // Centered on the shift (the truncation at zero is too rare to
+        // matter), with variance 2α / (1 - α)^2.
+        let mean = samples.iter().sum::<u64>() as f64 / samples.len() as f64;
+        assert!((mean - shift).abs() < 0.1, "mean {} vs. {}", mean, shift);
+        let alpha = budget.alpha();
+        let variance = samples
+            .iter()
+            .map(|x| (*x as f64 - mean).powi(2))
+            .sum::<f64>()
+            / samples.len() as f64;
+        let expected = 2.0 * alpha / (1.0 - alpha).powi(2);
+        assert!(
+            (variance - expected).abs() < 0.2 * expected,
+            "variance {} vs. {}",
+            variance,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_accountant() {
+        let budget = PrivacyBudget::new(0.5, 1e-6).unwrap();
+        let mut accountant = Accountant::new(budget);
+        let first = accountant.publish(10);
+        assert_eq!(first.clients, 10);
+        assert_eq!(first.rounds, 1);
+        assert_eq!(first.epsilon_spent, 0.5);
+        let second = accountant.publish(12);
+        assert_eq!(second.rounds, 2);
+        assert_eq!(second.round_budget, budget);
+        assert_eq!(second.epsilon_spent, 1.0);
+        assert!((second.delta_spent - 2e-6).abs() < 1e-18);
+    }
+}
@@ -1,29 +1,39 @@
 use crate::{
     config::store::{Error, Store},
     experiment::Experiment,
-    services::{discovery::resolve_all, retry::error_policy},
+    rt::sleep_until,
+    services::{
+        discovery::{resolve_all, Discovery},
+        retry::error_policy,
+        Service,
+    },
 };
 
 use chrono::prelude::*;
 use futures_retry::FutureRetry;
 use log::{debug, warn};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::time::{Duration, Instant};
-use tokio::time::sleep_until as tokio_sleep_until;
 
 // TODO(zjn): make configurable. Short for local testing; long for real deployments
 const RETRY_DELAY: Duration = Duration::from_millis(100);
 const RETRY_ATTEMPTS: usize = 1000;
 
-async fn get_start_time<C: Store>(config: &C) -> Result<DateTime<FixedOffset>, Error> {
+/// The experiment start time, if one is set yet.
+pub async fn start_time<C: Store>(config: &C) -> Result<Option<DateTime<FixedOffset>>, Error> {
     let key = vec!["experiment".to_string(), "start-time".to_string()];
-    let start_time_str: String = config
-        .get(key)
+    match config.get(key).await? {
+        Some(start_time_str) => DateTime::parse_from_rfc3339(&start_time_str)
+            .map(Some)
+            .map_err(|err| Error::new(&err.to_string())),
+        None => Ok(None),
+    }
+}
+
+async fn get_start_time<C: Store>(config: &C) -> Result<DateTime<FixedOffset>, Error> {
+    start_time(config)
         .await?
-        .ok_or_else(|| Error::new("Empty start time."))?;
-    let start_time = DateTime::parse_from_rfc3339(&start_time_str)
-        .map_err(|err| Error::new(&err.to_string()))?;
-    Ok(start_time)
+        .ok_or_else(|| Error::new("Empty start time."))
 }
 
 pub async fn set_start_time<C: Store>(config: &C, dt: DateTime<FixedOffset>) -> Result<(), Error> {
@@ -32,6 +42,20 @@ pub async fn set_start_time<C: Store>(config: &C, dt: DateTime<FixedOffset>) ->
     Ok(())
 }
 
+/// Set the start time to `dt` unless one's already set.
+///
+/// Returns the start time that stuck.
+pub async fn claim_start_time<C: Store>(
+    config: &C,
+    dt: DateTime<FixedOffset>,
+) -> Result<DateTime<FixedOffset>, Error> {
+    let key = vec!["experiment".to_string(), "start-time".to_string()];
+    if config.put_if_absent(key, dt.to_rfc3339()).await? {
+        return Ok(dt);
+    }
+    get_start_time(config).await
+}
+
 async fn wait_for_start_time_set_helper<C: Store>(
     config: &C,
     delay: Duration,
@@ -59,48 +83,143 @@ pub async fn delay_until(dt: DateTime<FixedOffset>) {
     debug!("Delaying for {}", diff);
     let diff = diff.to_std().expect("Already checked >0.");
     let start_time_local = Instant::now() + diff;
-    tokio_sleep_until(start_time_local.into()).await;
+    sleep_until(start_time_local).await;
 }
 
-async fn has_quorum<C: Store>(config: &C, experiment: &Experiment) -> Result<(), Error> {
-    let nodes = resolve_all(config).await?;
-    let actual: HashSet<_> = nodes.iter().map(|node| node.service.clone()).collect();
-    let expected: HashSet<_> = experiment.iter_services().collect();
+/// When the publisher may start a round.
+///
+/// Every publisher and leader must register (the publisher needs a share from
+/// every group), but groups can optionally go ahead without some workers:
+/// clients only pick workers that registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuorumPolicy {
+    /// Each group needs at least this many workers (all of them if `None`).
+    pub min_workers_per_group: Option<u16>,
+    /// How long to wait for every service before settling for the minimum.
+    pub grace: Duration,
+}
 
-    if actual == expected {
-        Ok(())
-    } else {
+impl Default for QuorumPolicy {
+    fn default() -> Self {
+        QuorumPolicy {
+            min_workers_per_group: None,
+            grace: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A quorum that was reached, possibly without some expected services.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Quorum {
+    pub missing: Vec<Service>,
+}
+
+fn check_quorum(
+    expected: &HashSet<Service>,
+    actual: &HashSet<Service>,
+    min_workers_per_group: Option<u16>,
+) -> Result<Quorum, Error> {
+    let unexpected: Vec<_> = actual.difference(expected).collect();
+    let missing: Vec<_> = expected.difference(actual).cloned().collect();
+    if unexpected.is_empty() && missing.is_empty() {
+        return Ok(Quorum::default());
+    }
+    let bad_quorum = || {
         let msg = format!(
             "Bad quorum. \n\
-             Expected {:?} but did not see.\n\"
+             Expected {:?} but did not see.\n\
              Got {:?} but did not expect to.",
-            expected.difference(&actual),
-            actual.difference(&expected)
+            missing, unexpected
         );
         Err(Error::new(&msg))
+    };
+    let min_workers = match min_workers_per_group {
+        Some(min_workers) if unexpected.is_empty() => min_workers,
+        _ => return bad_quorum(),
+    };
+    if missing
+        .iter()
+        .any(|service| !matches!(service, Service::Worker(_)))
+    {
+        return bad_quorum();
+    }
+    let mut workers_per_group = HashMap::new();
+    for service in expected {
+        if let Service::Worker(info) = service {
+            workers_per_group.entry(info.group).or_insert(0u16);
+        }
     }
+    for service in actual {
+        if let Service::Worker(info) = service {
+            *workers_per_group.entry(info.group).or_default() += 1;
+        }
+    }
+    if workers_per_group.values().any(|count| *count < min_workers) {
+        return bad_quorum();
+    }
+    Ok(Quorum { missing })
 }
 
-async fn wait_for_quorum_helper<C: Store + Sync + Send>(
+async fn has_quorum<C: Discovery>(
     config: &C,
     experiment: &Experiment,
+    min_workers_per_group: Option<u16>,
+) -> Result<Quorum, Error> {
+    let nodes = resolve_all(config).await?;
+    let actual: HashSet<_> = nodes.iter().map(|node| node.service.clone()).collect();
+    let expected: HashSet<_> = experiment.iter_services().collect();
+    check_quorum(&expected, &actual, min_workers_per_group)
+}
+
+async fn wait_for_quorum_helper<C: Discovery>(
+    config: &C,
+    experiment: &Experiment,
+    min_workers_per_group: Option<u16>,
     delay: Duration,
     attempts: usize,
-) -> Result<(), Error> {
+) -> Result<Quorum, Error> {
     FutureRetry::new(
-        move || has_quorum(config, experiment),
+        move || has_quorum(config, experiment, min_workers_per_group),
         error_policy(delay, attempts),
     )
     .await
-    .map_err(|(err, _)| err)?;
-    Ok(())
+    .map(|(quorum, _)| quorum)
+    .map_err(|(err, _)| err)
 }
 
-pub async fn wait_for_quorum<C: Store + Sync + Send>(
+/// Wait until the services registered in discovery make a quorum.
+///
+/// Waits up to `policy.grace` for every service in the experiment, then
+/// settles for `policy.min_workers_per_group` (if set). The returned quorum
+/// lists who didn't make it.
+pub async fn wait_for_quorum<C: Discovery>(
     config: &C,
     experiment: &Experiment,
-) -> Result<(), Error> {
-    wait_for_quorum_helper(config, experiment, RETRY_DELAY, RETRY_ATTEMPTS).await
+    policy: QuorumPolicy,
+) -> Result<Quorum, Error> {
+    let min_workers = match policy.min_workers_per_group {
+        Some(min_workers) => min_workers,
+        None => {
+            return wait_for_quorum_helper(config, experiment, None, RETRY_DELAY, RETRY_ATTEMPTS)
+                .await;
+        }
+    };
+    let grace_attempts = (policy.grace.as_millis() / RETRY_DELAY.as_millis()).max(1) as usize;
+    if let Ok(quorum) =
+        wait_for_quorum_helper(config, experiment, None, RETRY_DELAY, grace_attempts).await
+    {
+        return Ok(quorum);
+    }
+    let quorum = wait_for_quorum_helper(
+        config,
+        experiment,
+        Some(min_workers),
+        RETRY_DELAY,
+        RETRY_ATTEMPTS,
+    )
+    .await?;
+    warn!("Proceeding without {:?}", quorum.missing);
+    Ok(quorum)
 }
 
 #[cfg(test)]
@@ -110,9 +229,8 @@ mod test {
         config::{factory::from_string, tests::inmem_stores},
         experiment::Experiment,
         net::tests::addrs,
-        protocols::secure,
-        services::discovery::{register, tests::services, Node},
-        services::Service,
+        services::discovery::{register, tests::services, Node, StoreDiscovery},
+        services::{Group, LeaderInfo, PublisherInfo, Service, WorkerInfo},
     };
     use futures::executor::block_on;
     use proptest::prelude::*;
@@ -132,9 +250,10 @@ mod test {
              hours in 0u32..24u32,
              minutes in 0u32..60u32,
              seconds in 0u32..60u32) -> DateTime<FixedOffset> {
-                FixedOffset::east(0)
-                    .ymd(year, month, day)
-                    .and_hms(hours, minutes, seconds)
+                FixedOffset::east_opt(0)
+                    .unwrap()
+                    .with_ymd_and_hms(year, month, day, hours, minutes, seconds)
+                    .unwrap()
             }
     }
 
@@ -147,6 +266,17 @@ mod test {
                 Ok::<(), Error>(())
             }).unwrap();
         }
+
+        #[test]
+        fn test_claim_start_time(config in inmem_stores(), dt1 in datetimes(), dt2 in datetimes()) {
+            futures::executor::block_on(async {
+                assert_eq!(claim_start_time(&config, dt1).await?, dt1);
+                // The first one sticks.
+                assert_eq!(claim_start_time(&config, dt2).await?, dt1);
+                assert_eq!(get_start_time(&config).await?, dt1);
+                Ok::<(), Error>(())
+            }).unwrap();
+        }
     }
 
     #[tokio::test]
@@ -230,15 +360,16 @@ mod test {
     //         .expect("Should succeed if quorum is ready.");
     // }
 
-    async fn run_quorum_test<C: Store + Sync + Send, I: Iterator<Item = Node>>(
-        config: &C,
+    async fn run_quorum_test<C: Store + Send + Sync, I: Iterator<Item = Node>>(
+        config: C,
         experiment: Experiment,
         nodes: I,
-    ) -> Result<(), Error> {
+    ) -> Result<Quorum, Error> {
+        let discovery = StoreDiscovery::new(config);
         for node in nodes {
-            register(config, node).await?;
+            register(&discovery, node).await?;
         }
-        has_quorum(config, &experiment).await
+        has_quorum(&discovery, &experiment, None).await
     }
 
     fn experiments_and_nodes() -> impl Strategy<Value = (Experiment, Vec<Node>)> {
@@ -262,7 +393,7 @@ mod test {
             let services: Vec<Service> =experiment.iter_services().collect();
             prop_assume!(!services.contains(&extra_service));
             let nodes = nodes.into_iter().chain(once(Node::new(extra_service, addr)));
-            block_on(run_quorum_test(&config, experiment, nodes))
+            block_on(run_quorum_test(config, experiment, nodes))
                 .expect_err("Unexpected nodes--should error.");
         }
 
@@ -274,7 +405,7 @@ mod test {
                 (Just(experiment), prop::sample::subsequence(nodes, 0..num_nodes))
             }),
         ) {
-            block_on(run_quorum_test(&config, experiment, nodes.into_iter()))
+            block_on(run_quorum_test(config, experiment, nodes.into_iter()))
                 .expect_err("Expected nodes missing--should error.");
         }
 
@@ -283,8 +414,53 @@ mod test {
             config in inmem_stores(),
             (experiment, nodes) in experiments_and_nodes()
         ) {
-            block_on(run_quorum_test(&config, experiment, nodes.into_iter()))
+            let quorum = block_on(run_quorum_test(config, experiment, nodes.into_iter()))
                 .expect("Should have quorum.");
+            prop_assert_eq!(quorum, Quorum::default());
+        }
+    }
+
+    // Two groups of three workers, with a leader each, and one publisher.
+    fn expected_services() -> HashSet<Service> {
+        let mut services: HashSet<Service> = once(PublisherInfo::new(0).into()).collect();
+        for group in (0..2).map(Group::new) {
+            services.insert(LeaderInfo::new(group).into());
+            for idx in 0..3 {
+                services.insert(WorkerInfo::new(group, idx).into());
+            }
         }
+        services
+    }
+
+    #[test]
+    fn test_check_quorum_missing_workers() {
+        let expected = expected_services();
+        let missing: Service = WorkerInfo::new(Group::new(1), 2).into();
+        let mut actual = expected.clone();
+        actual.remove(&missing);
+
+        check_quorum(&expected, &actual, None).expect_err("No minimum--need every worker.");
+        check_quorum(&expected, &actual, Some(3)).expect_err("Group 1 has too few workers.");
+        let quorum = check_quorum(&expected, &actual, Some(2)).expect("Enough workers.");
+        assert_eq!(quorum.missing, vec![missing]);
+    }
+
+    #[test]
+    fn test_check_quorum_empty_group() {
+        let expected = expected_services();
+        let actual = expected
+            .iter()
+            .filter(|service| !matches!(service, Service::Worker(info) if info.group.idx == 1))
+            .cloned()
+            .collect();
+        check_quorum(&expected, &actual, Some(1)).expect_err("Group 1 has no workers.");
+    }
+
+    #[test]
+    fn test_check_quorum_missing_leader() {
+        let expected = expected_services();
+        let mut actual = expected.clone();
+        actual.remove(&LeaderInfo::new(Group::new(0)).into());
+        check_quorum(&expected, &actual, Some(0)).expect_err("Every leader is required.");
     }
 }
@@ -0,0 +1,243 @@
+//! The window during which workers accept client registrations.
+//!
+//! By default, registration is open until the experiment start time. The
+//! publisher can instead set explicit open and close times in the config
+//! store (e.g. to measure registration throughput separately from the
+//! broadcast); workers enforce them, and clients can ask a worker for the
+//! window before registering.
+use crate::config::store::{Error, Key, Store};
+use crate::proto::RegistrationWindowResponse;
+
+use chrono::prelude::*;
+use std::time::Duration;
+
+fn opens_key() -> Key {
+    vec![
+        "experiment".to_string(),
+        "registration".to_string(),
+        "opens-at".to_string(),
+    ]
+}
+
+fn closes_key() -> Key {
+    vec![
+        "experiment".to_string(),
+        "registration".to_string(),
+        "closes-at".to_string(),
+    ]
+}
+
+/// Where a registration window is, relative to some point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowState {
+    NotYetOpen,
+    Open,
+    Closed,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    /// Open from the beginning if `None`.
+    pub opens: Option<DateTime<FixedOffset>>,
+    /// Closes at the experiment start time if `None`.
+    pub closes: Option<DateTime<FixedOffset>>,
+}
+
+impl Window {
+    /// The state of the window at `now`, given whether the experiment has
+    /// `started`.
+    pub fn state(&self, now: DateTime<FixedOffset>, started: bool) -> WindowState {
+        if matches!(self.opens, Some(opens) if now < opens) {
+            return WindowState::NotYetOpen;
+        }
+        let closed = match self.closes {
+            Some(closes) => now >= closes,
+            None => started,
+        };
+        if closed {
+            WindowState::Closed
+        } else {
+            WindowState::Open
+        }
+    }
+
+    pub fn to_proto(&self, state: WindowState) -> RegistrationWindowResponse {
+        let format = |dt: Option<DateTime<FixedOffset>>| dt.map(|dt| dt.to_rfc3339());
+        RegistrationWindowResponse {
+            opens_at: format(self.opens).unwrap_or_default(),
+            closes_at: format(self.closes).unwrap_or_default(),
+            open: state == WindowState::Open,
+        }
+    }
+
+    pub fn from_proto(proto: &RegistrationWindowResponse) -> Result<Self, Error> {
+        Ok(Window {
+            opens: parse_time(&proto.opens_at)?,
+            closes: parse_time(&proto.closes_at)?,
+        })
+    }
+}
+
+/// Parse an RFC 3339 timestamp, where empty means unset.
+fn parse_time(value: &str) -> Result<Option<DateTime<FixedOffset>>, Error> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(Some)
+        .map_err(|err| Error::new(&err.to_string()))
+}
+
+async fn get_time<C: Store>(config: &C, key: Key) -> Result<Option<DateTime<FixedOffset>>, Error> {
+    match config.get(key).await? {
+        Some(value) => parse_time(&value),
+        None => Ok(None),
+    }
+}
+
+/// The registration window the publisher set (the default if it hasn't).
+pub async fn get_window<C: Store>(config: &C) -> Result<Window, Error> {
+    Ok(Window {
+        opens: get_time(config, opens_key()).await?,
+        closes: get_time(config, closes_key()).await?,
+    })
+}
+
+/// Open registration at `dt`.
+pub async fn open_registration<C: Store>(
+    config: &C,
+    dt: DateTime<FixedOffset>,
+) -> Result<(), Error> {
+    config.put(opens_key(), dt.to_rfc3339()).await
+}
+
+/// Close registration at `dt`, rather than at the experiment start time.
+pub async fn close_registration<C: Store>(
+    config: &C,
+    dt: DateTime<FixedOffset>,
+) -> Result<(), Error> {
+    config.put(closes_key(), dt.to_rfc3339()).await
+}
+
+/// When the publisher opens and closes registration, relative to reaching
+/// quorum. Unset times keep the default behavior.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RegistrationSchedule {
+    pub opens_after: Option<Duration>,
+    pub closes_after: Option<Duration>,
+}
+
+impl RegistrationSchedule {
+    pub fn window(&self, now: DateTime<FixedOffset>) -> Window {
+        let after = |delay: Duration| {
+            now + chrono::Duration::from_std(delay).expect("Registration delay out of range.")
+        };
+        Window {
+            opens: self.opens_after.map(after),
+            closes: self.closes_after.map(after),
+        }
+    }
+
+    /// Write the window (in one batch) for a round that reached quorum at `now`.
+    pub async fn apply<C: Store + Sync>(
+        &self,
+        config: &C,
+        now: DateTime<FixedOffset>,
+    ) -> Result<Window, Error> {
+        let window = self.window(now);
+        let entries = vec![(opens_key(), window.opens), (closes_key(), window.closes)]
+            .into_iter()
+            .filter_map(|(key, dt)| dt.map(|dt| (key, dt.to_rfc3339())))
+            .collect();
+        config.put_batch(entries).await?;
+        Ok(window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::factory::from_string;
+
+    fn at(seconds: u32) -> DateTime<FixedOffset> {
+        FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2020, 1, 1, 0, 0, seconds)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_default_window_closes_at_start() {
+        let window = Window::default();
+        assert_eq!(window.state(at(0), false), WindowState::Open);
+        assert_eq!(window.state(at(0), true), WindowState::Closed);
+    }
+
+    #[test]
+    fn test_explicit_window() {
+        let window = Window {
+            opens: Some(at(10)),
+            closes: Some(at(20)),
+        };
+        assert_eq!(window.state(at(5), false), WindowState::NotYetOpen);
+        assert_eq!(window.state(at(10), false), WindowState::Open);
+        // An explicit close time overrides the start time.
+        assert_eq!(window.state(at(15), true), WindowState::Open);
+        assert_eq!(window.state(at(20), false), WindowState::Closed);
+    }
+
+    #[test]
+    fn test_proto_roundtrip() {
+        for window in [
+            Window::default(),
+            Window {
+                opens: Some(at(10)),
+                closes: None,
+            },
+            Window {
+                opens: Some(at(10)),
+                closes: Some(at(20)),
+            },
+        ] {
+            let proto = window.to_proto(WindowState::Open);
+            assert!(proto.open);
+            assert_eq!(Window::from_proto(&proto).unwrap(), window);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_window_unset() {
+        let config = from_string("").await.unwrap();
+        assert_eq!(get_window(&config).await.unwrap(), Window::default());
+    }
+
+    #[tokio::test]
+    async fn test_apply_schedule() {
+        let config = from_string("").await.unwrap();
+        let schedule = RegistrationSchedule {
+            opens_after: Some(Duration::from_secs(1)),
+            closes_after: Some(Duration::from_secs(10)),
+        };
+        let window = schedule.apply(&config, at(0)).await.unwrap();
+        assert_eq!(
+            window,
+            Window {
+                opens: Some(at(1)),
+                closes: Some(at(10)),
+            }
+        );
+        assert_eq!(get_window(&config).await.unwrap(), window);
+    }
+
+    #[tokio::test]
+    async fn test_get_window_malformed() {
+        let config = from_string("").await.unwrap();
+        config
+            .put(opens_key(), "not a valid RFC 3339 date".to_string())
+            .await
+            .unwrap();
+        get_window(&config)
+            .await
+            .expect_err("Malformed entry should result in error.");
+    }
+}
@@ -0,0 +1,174 @@
+//! Slot reservation rounds.
+//!
+//! An experiment can be the reservation round of the two-phase variant (see
+//! [`reservation`](crate::protocols::reservation)): each broadcaster reserves
+//! a random slot of its channel, and the publisher works out where each
+//! reserved slot goes in the payload round that follows. The result goes in
+//! the round's (signed) manifest, and the publisher serves it to broadcasters
+//! (`GetSlotAssignments`).
+use crate::proto;
+use crate::protocols::reservation::{
+    Error, Nonce, ReservationAccumulator, SlotAssignment, SlotReservation,
+};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use spectrum_primitives::Bytes;
+use std::convert::{TryFrom, TryInto};
+
+/// Parameters for a reservation round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReservationRound {
+    pub reservation: SlotReservation,
+    /// Message size (in bytes) of the payload round that follows.
+    pub payload_msg_size: usize,
+}
+
+impl ReservationRound {
+    pub fn new(slots: usize, payload_msg_size: usize) -> Self {
+        ReservationRound {
+            reservation: SlotReservation::new(slots),
+            payload_msg_size,
+        }
+    }
+
+    /// A message reserving a random slot, and the nonce it reserves it with.
+    pub fn sample_message<R: Rng>(&self, rng: &mut R) -> (Bytes, Nonce) {
+        let slot = rng.gen_range(0..self.reservation.slots());
+        let nonce: Nonce = rng.gen();
+        let msg = self
+            .reservation
+            .reserve(slot, nonce)
+            .expect("slot should be in range");
+        (msg, nonce)
+    }
+
+    /// Work out the payload slots from the round's recovered channels.
+    pub fn tally(&self, recovered: &[Bytes]) -> Result<SlotAssignments, Error> {
+        let reservations =
+            ReservationAccumulator::from_recovered(self.reservation, recovered.to_vec())?;
+        Ok(SlotAssignments {
+            assignments: reservations.assign(self.payload_msg_size),
+            collisions: reservations.collisions() as u64,
+        })
+    }
+}
+
+/// Where each reserved slot goes in the payload round.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotAssignments {
+    pub assignments: Vec<SlotAssignment>,
+    /// Slots that several broadcasters picked; they all have to try again.
+    pub collisions: u64,
+}
+
+impl From<SlotAssignment> for proto::SlotAssignment {
+    fn from(assignment: SlotAssignment) -> Self {
+        proto::SlotAssignment {
+            channel: assignment.channel as u32,
+            slot: assignment.slot as u32,
+            nonce: assignment.nonce.to_vec(),
+            offset: assignment.offset as u64,
+            len: assignment.len as u64,
+        }
+    }
+}
+
+impl TryFrom<proto::SlotAssignment> for SlotAssignment {
+    type Error = String;
+
+    fn try_from(assignment: proto::SlotAssignment) -> Result<Self, Self::Error> {
+        let nonce = assignment.nonce[..]
+            .try_into()
+            .map_err(|_| format!("Bad nonce length: {}", assignment.nonce.len()))?;
+        Ok(SlotAssignment {
+            channel: assignment.channel as usize,
+            slot: assignment.slot as usize,
+            nonce,
+            offset: assignment.offset as usize,
+            len: assignment.len as usize,
+        })
+    }
+}
+
+impl From<SlotAssignments> for proto::SlotAssignmentsResponse {
+    fn from(assignments: SlotAssignments) -> Self {
+        proto::SlotAssignmentsResponse {
+            assignments: assignments
+                .assignments
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            collisions: assignments.collisions,
+        }
+    }
+}
+
+impl TryFrom<proto::SlotAssignmentsResponse> for SlotAssignments {
+    type Error = String;
+
+    fn try_from(response: proto::SlotAssignmentsResponse) -> Result<Self, Self::Error> {
+        Ok(SlotAssignments {
+            assignments: response
+                .assignments
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            collisions: response.collisions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::reservation::ENTRY_LEN;
+    use rand::thread_rng;
+
+    #[test]
+    fn test_tally() {
+        let round = ReservationRound::new(4, 100);
+        let mut rng = thread_rng();
+        let (msg, nonce) = round.sample_message(&mut rng);
+        let (other, _) = round.sample_message(&mut rng);
+        // Channel 0 has one reservation; channel 1 has two (maybe in the same
+        // slot); channel 2 has none.
+        let recovered = vec![
+            msg,
+            round.sample_message(&mut rng).0 ^ &other,
+            Bytes::empty(round.reservation.message_len()),
+        ];
+
+        let slots = round.tally(&recovered).unwrap();
+        let mine: Vec<_> = slots
+            .assignments
+            .iter()
+            .filter(|assignment| assignment.channel == 0)
+            .collect();
+        assert_eq!(mine.len(), 1);
+        assert_eq!(mine[0].nonce, nonce);
+        assert_eq!(mine[0].len, 25);
+        assert_eq!(mine[0].offset, mine[0].slot * 25);
+        let others = slots.assignments.len() - 1;
+        assert_eq!(others + 2 * slots.collisions as usize, 2);
+
+        round
+            .tally(&[Bytes::empty(ENTRY_LEN)])
+            .expect_err("wrong message size");
+    }
+
+    #[test]
+    fn test_proto_roundtrip() {
+        let round = ReservationRound::new(2, 64);
+        let (msg, _) = round.sample_message(&mut thread_rng());
+        let slots = round.tally(&[msg]).unwrap();
+        let response = proto::SlotAssignmentsResponse::from(slots.clone());
+        assert_eq!(SlotAssignments::try_from(response), Ok(slots));
+
+        let bad = proto::SlotAssignment {
+            nonce: vec![1, 2, 3],
+            ..Default::default()
+        };
+        SlotAssignment::try_from(bad).expect_err("short nonce");
+    }
+}
@@ -37,7 +37,7 @@ mod tests {
     };
     use futures_retry::{FutureFactory, FutureRetry};
     use proptest::prelude::*;
-    use std::iter::{once, repeat};
+    use std::iter::once;
     use tokio::runtime::Runtime;
 
     const NO_DELAY: Duration = Duration::from_millis(0);
@@ -68,8 +68,7 @@ mod tests {
         fn test_error_policy_many_attempts_success(attempts in 1usize..10usize) {
             let runtime = Runtime::new().unwrap();
             runtime.block_on(async {
-                let results = repeat(err(()))
-                    .take(attempts - 1)
+                let results = std::iter::repeat_n(err(()), attempts - 1)
                     .chain(once(ok(())));
                 FutureRetry::new(FutureIterator(results), error_policy(NO_DELAY, attempts))
                     .await
@@ -81,8 +80,7 @@ mod tests {
         fn test_error_policy_many_attempts_failure(attempts in 1usize..10usize) {
             let runtime = Runtime::new().unwrap();
             runtime.block_on(async {
-                let results = repeat(err(()))
-                    .take(attempts)
+                let results = std::iter::repeat_n(err(()), attempts)
                     .chain(once(ok(())));
                 FutureRetry::new(FutureIterator(results), error_policy(NO_DELAY, attempts))
                     .await
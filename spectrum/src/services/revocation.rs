@@ -0,0 +1,246 @@
+//! Revoking compromised channel keys mid-deployment.
+//!
+//! Revoking a channel swaps its key for a fresh one that nobody holds: the
+//! [`Revocation`] (with both keys) goes in the config store, and workers, which
+//! poll for revocations, audit every write against the replacement instead. So
+//! writes under the revoked key fail their audits like any other bad write.
+//!
+//! Every worker must audit against the same keys, and they each pick up a
+//! revocation within a poll interval of each other; writes audited in between
+//! can fail spuriously (on any channel). Where possible, revoke between rounds.
+use crate::config::store::{Error, Key, Store};
+use crate::experiment::{read_from_store, ProtocolConfig};
+use crate::protocols::wrapper::ChannelKeyWrapper;
+use crate::rt::{sleep, sync::watch};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Same cadence as workers polling the registration window.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn revocations_key() -> Key {
+    vec!["experiment".to_string(), "revoked".to_string()]
+}
+
+fn revocation_key(channel: usize) -> Key {
+    let mut key = revocations_key();
+    key.push(channel.to_string());
+    key
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Revocation {
+    pub channel: usize,
+    pub reason: String,
+    /// The key that's no longer good for the channel.
+    pub key: ChannelKeyWrapper,
+    /// The key to audit the channel's writes against instead.
+    pub replacement: ChannelKeyWrapper,
+}
+
+/// Revoke the current key for `channel` of the experiment in `config`.
+pub async fn revoke_channel<C: Store>(
+    config: &C,
+    channel: usize,
+    reason: &str,
+) -> Result<Revocation, Error> {
+    let experiment = read_from_store(config).await?;
+    let key = experiment.get_keys().get(channel).cloned().ok_or_else(|| {
+        Error::new(&format!(
+            "No channel {} (experiment has {}).",
+            channel,
+            experiment.channels()
+        ))
+    })?;
+    let revoked = get_revocations(config).await?;
+    if revoked.iter().any(|r| r.channel == channel && r.key == key) {
+        return Err(Error::new(&format!(
+            "Channel {} is already revoked.",
+            channel
+        )));
+    }
+
+    let revocation = Revocation {
+        channel,
+        reason: reason.to_string(),
+        key,
+        replacement: ProtocolConfig::sample_key(experiment.get_protocol()),
+    };
+    let value = serde_json::to_string(&revocation).map_err(|err| Error::new(&err.to_string()))?;
+    config.put(revocation_key(channel), value).await?;
+    Ok(revocation)
+}
+
+/// Every revocation so far, by channel.
+pub async fn get_revocations<C: Store>(config: &C) -> Result<Vec<Revocation>, Error> {
+    let mut revocations = config
+        .list(revocations_key())
+        .await?
+        .into_iter()
+        .map(|(_, value)| serde_json::from_str(&value))
+        .collect::<Result<Vec<Revocation>, _>>()
+        .map_err(|err| Error::new(&err.to_string()))?;
+    revocations.sort_by_key(|r| r.channel);
+    Ok(revocations)
+}
+
+/// The revocations in `revocations` of keys in `keys` (rather than of keys
+/// that have since been replaced some other way, say by importing new ones).
+pub fn applicable<'a>(
+    revocations: &'a [Revocation],
+    keys: &[ChannelKeyWrapper],
+) -> Vec<&'a Revocation> {
+    revocations
+        .iter()
+        .filter(|r| keys.get(r.channel) == Some(&r.key))
+        .collect()
+}
+
+/// Drop revocations that no longer apply to the experiment's keys (see
+/// [`applicable`]), returning their channels.
+pub async fn retire_stale<C: Store>(config: &C) -> Result<Vec<usize>, Error> {
+    let keys = read_from_store(config).await?.get_keys();
+    let revocations = get_revocations(config).await?;
+    let applicable = applicable(&revocations, &keys);
+    let mut retired = vec![];
+    for revocation in revocations.iter().filter(|r| !applicable.contains(r)) {
+        config
+            .delete_prefix(revocation_key(revocation.channel))
+            .await?;
+        retired.push(revocation.channel);
+    }
+    Ok(retired)
+}
+
+/// `keys`, with the replacement for each revoked one.
+pub fn apply(revocations: &[Revocation], keys: Vec<ChannelKeyWrapper>) -> Vec<ChannelKeyWrapper> {
+    let mut replaced = keys.clone();
+    for revocation in applicable(revocations, &keys) {
+        replaced[revocation.channel] = revocation.replacement.clone();
+    }
+    replaced
+}
+
+async fn watch_revocations_helper<C: Store>(
+    config: C,
+    revocations: watch::Sender<Vec<Revocation>>,
+    interval: Duration,
+) {
+    loop {
+        match get_revocations(&config).await {
+            Ok(latest) => {
+                let known = revocations.borrow().clone();
+                if known != latest {
+                    for revocation in latest.iter().filter(|r| !known.contains(r)) {
+                        info!(
+                            "Channel {} revoked: {}",
+                            revocation.channel, revocation.reason
+                        );
+                    }
+                    if revocations.send(latest).is_err() {
+                        break;
+                    }
+                }
+            }
+            Err(err) => warn!("Couldn't check for revoked channels: {}", err),
+        }
+        sleep(interval).await;
+    }
+}
+
+/// Keep `revocations` in sync with the revocations in the config store (until
+/// nobody's listening).
+pub async fn watch_revocations<C: Store>(config: C, revocations: watch::Sender<Vec<Revocation>>) {
+    watch_revocations_helper(config, revocations, POLL_INTERVAL).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::factory::from_string;
+    use crate::experiment::{write_to_store, Experiment};
+    use crate::protocols::wrapper::ProtocolWrapper;
+    use crate::rt::spawn;
+
+    fn experiment() -> Experiment {
+        let protocol = ProtocolWrapper::new(true, false, 2, 3, 16, false);
+        Experiment::new_sample_keys(protocol, 1, 10, false)
+    }
+
+    #[tokio::test]
+    async fn test_revoke_and_get() {
+        let config = from_string("").await.unwrap();
+        let experiment = experiment();
+        write_to_store(&config, &experiment).await.unwrap();
+        assert_eq!(get_revocations(&config).await.unwrap(), vec![]);
+
+        let revocation = revoke_channel(&config, 1, "leaked").await.unwrap();
+        assert_eq!(revocation.key, experiment.get_keys()[1]);
+        assert_ne!(revocation.replacement, revocation.key);
+        assert_eq!(
+            get_revocations(&config).await.unwrap(),
+            vec![revocation.clone()]
+        );
+
+        revoke_channel(&config, 1, "leaked again")
+            .await
+            .expect_err("Already revoked.");
+        revoke_channel(&config, 3, "no such channel")
+            .await
+            .expect_err("Experiment has 3 channels.");
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let config = from_string("").await.unwrap();
+        let experiment = experiment();
+        write_to_store(&config, &experiment).await.unwrap();
+        let revocation = revoke_channel(&config, 0, "leaked").await.unwrap();
+        let revocations = get_revocations(&config).await.unwrap();
+
+        let keys = apply(&revocations, experiment.get_keys());
+        assert_eq!(keys[0], revocation.replacement);
+        assert_eq!(keys[1..], experiment.get_keys()[1..]);
+
+        // New keys for the channel: the revocation no longer applies.
+        let fresh = Experiment::new_sample_keys(experiment.get_protocol().clone(), 1, 10, false);
+        assert_eq!(apply(&revocations, fresh.get_keys()), fresh.get_keys());
+    }
+
+    #[tokio::test]
+    async fn test_retire_stale() {
+        let config = from_string("").await.unwrap();
+        let experiment = experiment();
+        write_to_store(&config, &experiment).await.unwrap();
+        revoke_channel(&config, 0, "leaked").await.unwrap();
+        let kept = revoke_channel(&config, 1, "leaked").await.unwrap();
+        assert_eq!(retire_stale(&config).await.unwrap(), Vec::<usize>::new());
+
+        // A new key for channel 0: its revocation no longer applies.
+        let mut keys = experiment.get_keys();
+        keys[0] = ProtocolConfig::sample_key(experiment.get_protocol());
+        let experiment = experiment.with_keys(keys).unwrap();
+        write_to_store(&config, &experiment).await.unwrap();
+        assert_eq!(retire_stale(&config).await.unwrap(), vec![0]);
+        assert_eq!(get_revocations(&config).await.unwrap(), vec![kept]);
+    }
+
+    #[tokio::test]
+    async fn test_watch_revocations() {
+        let config = from_string("").await.unwrap();
+        write_to_store(&config, &experiment()).await.unwrap();
+        let (tx, mut rx) = watch::channel(vec![]);
+        let watcher = spawn(watch_revocations_helper(
+            config.clone(),
+            tx,
+            Duration::from_millis(10),
+        ));
+
+        let revocation = revoke_channel(&config, 2, "leaked").await.unwrap();
+        rx.changed().await.unwrap();
+        assert_eq!(*rx.borrow(), vec![revocation]);
+        watcher.abort();
+    }
+}
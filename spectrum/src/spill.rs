@@ -0,0 +1,229 @@
+//! Spilling worker shares to disk when too many are waiting in memory.
+//!
+//! A leader holds each worker's share until it's folded into the group's
+//! accumulator, and with many workers and big messages several can be waiting
+//! at once. Past a watermark (in bytes), new shares go to temporary files
+//! instead, and get read back one at a time to be combined.
+use crate::proto::Share;
+use crate::rt::{
+    spawn_blocking,
+    sync::{Mutex, OwnedMutexGuard},
+};
+
+use log::debug;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// When (and where) to spill shares.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SpillConfig {
+    /// Spill once more than this many bytes of shares are in memory (never,
+    /// if `None`).
+    pub watermark: Option<usize>,
+    /// Directory for spilled shares (default: the system temp directory).
+    pub dir: Option<PathBuf>,
+}
+
+/// A share waiting to be combined.
+#[derive(Debug)]
+pub enum Held {
+    Memory(Share, MemoryPermit),
+    Disk(File),
+}
+
+/// Counts a share against the watermark until dropped.
+#[derive(Debug)]
+pub struct MemoryPermit {
+    bytes: usize,
+    in_memory: Arc<AtomicUsize>,
+}
+
+impl Drop for MemoryPermit {
+    fn drop(&mut self) {
+        self.in_memory.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+/// Hold on to this until a loaded share has been combined.
+///
+/// For a spilled share, this keeps any other spilled share from being loaded
+/// in the meantime.
+#[allow(dead_code)] // held for its drop, never read
+#[derive(Debug)]
+pub enum Guard {
+    Memory(MemoryPermit),
+    Disk(OwnedMutexGuard<()>),
+}
+
+fn share_size(share: &Share) -> usize {
+    share.data.iter().map(Vec::len).sum()
+}
+
+fn join_error(err: crate::rt::JoinError) -> io::Error {
+    io::Error::other(err)
+}
+
+// Format: the number of chunks, then each chunk's length and contents (all
+// lengths are little-endian u64s).
+fn write_share(dir: Option<PathBuf>, share: &Share) -> io::Result<File> {
+    let mut file = match dir {
+        Some(dir) => tempfile::tempfile_in(dir)?,
+        None => tempfile::tempfile()?,
+    };
+    {
+        let mut writer = BufWriter::new(&mut file);
+        writer.write_all(&(share.data.len() as u64).to_le_bytes())?;
+        for chunk in &share.data {
+            writer.write_all(&(chunk.len() as u64).to_le_bytes())?;
+            writer.write_all(chunk)?;
+        }
+        writer.flush()?;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+fn read_len(reader: &mut impl Read) -> io::Result<usize> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf) as usize)
+}
+
+fn read_share(file: File) -> io::Result<Share> {
+    let mut reader = BufReader::new(file);
+    let chunks = read_len(&mut reader)?;
+    let mut data = Vec::with_capacity(chunks);
+    for _ in 0..chunks {
+        let mut chunk = vec![0; read_len(&mut reader)?];
+        reader.read_exact(&mut chunk)?;
+        data.push(chunk);
+    }
+    Ok(Share { data })
+}
+
+pub struct Spill {
+    config: SpillConfig,
+    // Bytes of shares held in memory.
+    in_memory: Arc<AtomicUsize>,
+    // Held while a spilled share is loaded, so they get combined one at a time.
+    from_disk: Arc<Mutex<()>>,
+}
+
+impl Spill {
+    pub fn new(config: SpillConfig) -> Self {
+        Spill {
+            config,
+            in_memory: Default::default(),
+            from_disk: Default::default(),
+        }
+    }
+
+    /// Bytes of shares held in memory right now.
+    #[cfg(test)]
+    pub fn in_memory(&self) -> usize {
+        self.in_memory.load(Ordering::SeqCst)
+    }
+
+    /// Hold `share` until it can be combined: in memory if that keeps us
+    /// under the watermark, and on disk otherwise.
+    pub async fn hold(&self, share: Share) -> io::Result<Held> {
+        let bytes = share_size(&share);
+        let in_memory = self.in_memory.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        let permit = MemoryPermit {
+            bytes,
+            in_memory: self.in_memory.clone(),
+        };
+        match self.config.watermark {
+            Some(watermark) if in_memory > watermark => {
+                let dir = self.config.dir.clone();
+                let file = spawn_blocking(move || write_share(dir, &share))
+                    .await
+                    .map_err(join_error)??;
+                debug!("Spilled a {}-byte share to disk.", bytes);
+                drop(permit);
+                Ok(Held::Disk(file))
+            }
+            _ => Ok(Held::Memory(share, permit)),
+        }
+    }
+
+    /// Get back a held share, along with a guard to keep until it's combined.
+    pub async fn load(&self, held: Held) -> io::Result<(Share, Guard)> {
+        match held {
+            Held::Memory(share, permit) => Ok((share, Guard::Memory(permit))),
+            Held::Disk(file) => {
+                let guard = self.from_disk.clone().lock_owned().await;
+                let share = spawn_blocking(move || read_share(file))
+                    .await
+                    .map_err(join_error)??;
+                Ok((share, Guard::Disk(guard)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    fn share(chunks: usize, len: usize) -> Share {
+        Share {
+            data: (0..chunks).map(|idx| vec![idx as u8; len]).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_watermark() {
+        let spill = Spill::new(SpillConfig::default());
+        let held = spill.hold(share(4, 1000)).await.unwrap();
+        assert!(matches!(held, Held::Memory(..)));
+        assert_eq!(spill.in_memory(), 4000);
+
+        let (loaded, guard) = spill.load(held).await.unwrap();
+        assert_eq!(loaded, share(4, 1000));
+        drop(guard);
+        assert_eq!(spill.in_memory(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_spill_above_watermark() {
+        let dir = tempfile::tempdir().unwrap();
+        let spill = Spill::new(SpillConfig {
+            watermark: Some(1500),
+            dir: Some(dir.path().to_path_buf()),
+        });
+        let first = spill.hold(share(2, 500)).await.unwrap();
+        let second = spill.hold(share(3, 500)).await.unwrap();
+        let third = spill.hold(share(1, 600)).await.unwrap();
+        assert!(matches!(first, Held::Memory(..)));
+        assert!(matches!(second, Held::Disk(_)));
+        assert!(matches!(third, Held::Disk(_)));
+        assert_eq!(spill.in_memory(), 1000);
+
+        let (loaded, guard) = spill.load(second).await.unwrap();
+        assert_eq!(loaded, share(3, 500));
+        // Spilled shares come back one at a time.
+        assert!(spill.load(third).now_or_never().is_none());
+        drop(guard);
+
+        let (loaded, _) = spill.load(first).await.unwrap();
+        assert_eq!(loaded, share(2, 500));
+    }
+
+    #[tokio::test]
+    async fn test_bad_dir() {
+        let spill = Spill::new(SpillConfig {
+            watermark: Some(0),
+            dir: Some("/nonexistent/spill".into()),
+        });
+        spill
+            .hold(share(1, 10))
+            .await
+            .expect_err("nowhere to spill");
+        assert_eq!(spill.in_memory(), 0);
+    }
+}
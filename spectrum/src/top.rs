@@ -0,0 +1,528 @@
+//! A live view of a running experiment (see the `spectrum-top` binary).
+//!
+//! Each refresh resolves the nodes through discovery, checks their health,
+//! asks a publisher for the workers' latest stats (which workers only report
+//! in hammer mode), and reads the round's state from the config store.
+use crate::{
+    config::store::Store,
+    net::{client::Builder, tls::Certificate},
+    proto::{
+        get_stats_response, publisher_client::PublisherClient, GetStatsRequest, GetStatsResponse,
+    },
+    rt::timeout,
+    services::{
+        abort::{get_abort, AbortNotice},
+        discovery::{resolve_all, Discovery, Node},
+        election,
+        health::check_health,
+        quorum::start_time,
+        Service,
+    },
+};
+
+use chrono::prelude::*;
+use futures::future::join_all;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::time::Duration;
+
+type TokioError = Box<dyn std::error::Error + Sync + Send>;
+
+// The round publisher elections are for (as in `publisher`).
+const ROUND: u64 = 0;
+
+/// Where the round is at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Phase {
+    /// No start time yet (publishers wait for a quorum before picking one).
+    Waiting,
+    /// The round starts in this long.
+    Starting(chrono::Duration),
+    /// The round has been running for this long.
+    Running(chrono::Duration),
+    /// Some groups' shares have reached the publisher.
+    Aggregating {
+        round: u64,
+        received: u32,
+        total: u32,
+    },
+    /// Every group's shares reached the publisher.
+    Recovered {
+        round: u64,
+    },
+    Aborted(AbortNotice),
+}
+
+impl Phase {
+    /// Work out the phase from the config store and a publisher's progress.
+    pub fn new(
+        abort: Option<AbortNotice>,
+        start: Option<DateTime<FixedOffset>>,
+        stats: Option<&GetStatsResponse>,
+        now: DateTime<Utc>,
+    ) -> Phase {
+        if let Some(notice) = abort {
+            return Phase::Aborted(notice);
+        }
+        let start = match start {
+            Some(start) => start.with_timezone(&Utc),
+            None => return Phase::Waiting,
+        };
+        if start > now {
+            return Phase::Starting(start - now);
+        }
+        match stats {
+            Some(stats)
+                if stats.total_groups > 0 && stats.groups_received >= stats.total_groups =>
+            {
+                Phase::Recovered {
+                    round: stats.latest_round,
+                }
+            }
+            Some(stats) if stats.groups_received > 0 => Phase::Aggregating {
+                round: stats.latest_round,
+                received: stats.groups_received,
+                total: stats.total_groups,
+            },
+            _ => Phase::Running(now - start),
+        }
+    }
+}
+
+/// Like `1h02m03s`, dropping leading zero units.
+fn format_duration(duration: chrono::Duration) -> String {
+    let secs = duration.num_seconds().max(0);
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Waiting => write!(f, "waiting for quorum"),
+            Phase::Starting(left) => write!(f, "starting in {}", format_duration(*left)),
+            Phase::Running(elapsed) => write!(f, "running for {}", format_duration(*elapsed)),
+            Phase::Aggregating {
+                round,
+                received,
+                total,
+            } => write!(
+                f,
+                "aggregating round {} ({}/{} groups in)",
+                round, received, total
+            ),
+            Phase::Recovered { round } => write!(f, "recovered round {}", round),
+            Phase::Aborted(notice) => {
+                write!(f, "aborted round {}: {}", notice.round, notice.reason)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Serving,
+    NotServing,
+    /// Didn't answer (in time).
+    Unreachable,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Health::Serving => "serving",
+            Health::NotServing => "not serving",
+            Health::Unreachable => "unreachable",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeStatus {
+    pub node: Node,
+    pub health: Health,
+}
+
+/// Everything one refresh of the dashboard shows.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub taken_at: DateTime<Utc>,
+    pub phase: Phase,
+    pub nodes: Vec<NodeStatus>,
+    /// The address of the publisher holding the lease (with elections on).
+    pub elected: Option<String>,
+    pub stats: Option<GetStatsResponse>,
+    /// Whatever went wrong gathering the rest (which is then left out).
+    pub errors: Vec<String>,
+}
+
+// Publishers, then leaders, then workers, each by index.
+fn sort_key(service: &Service) -> (u8, u16, u16) {
+    match service {
+        Service::Publisher(info) => (0, info.idx, 0),
+        Service::Leader(info) => (1, info.group.idx, 0),
+        Service::Worker(info) => (2, info.group.idx, info.idx),
+        Service::Client(_) => (3, 0, 0),
+    }
+}
+
+async fn probe(node: Node, tls: Option<Certificate>, deadline: Duration) -> NodeStatus {
+    let health = match timeout(deadline, check_health(&node.uri(), node.tls_cert(tls))).await {
+        Ok(Ok(true)) => Health::Serving,
+        Ok(Ok(false)) => Health::NotServing,
+        Ok(Err(_)) | Err(_) => Health::Unreachable,
+    };
+    NodeStatus { node, health }
+}
+
+async fn get_stats(
+    node: &Node,
+    tls: Option<Certificate>,
+    deadline: Duration,
+) -> Result<GetStatsResponse, TokioError> {
+    let builder = Builder::new(node.uri())
+        .tls(node.tls_cert(tls))
+        .timeout(deadline);
+    let request = async {
+        let mut client = PublisherClient::new(builder.channel().await?);
+        let response = client.get_stats(GetStatsRequest {}).await?;
+        Ok::<_, TokioError>(response.into_inner())
+    };
+    timeout(deadline, request)
+        .await
+        .map_err(|_| format!("no stats from {} in time", node.service))?
+}
+
+/// Take a snapshot of the experiment, giving each node `deadline` to answer.
+///
+/// Failures end up in [`Snapshot::errors`] rather than failing the whole
+/// snapshot, so the dashboard keeps going through blips.
+pub async fn snapshot<C>(config: &C, tls: Option<Certificate>, deadline: Duration) -> Snapshot
+where
+    C: Store + Discovery,
+{
+    let mut errors = vec![];
+    let mut nodes = resolve_all(config).await.unwrap_or_else(|err| {
+        errors.push(format!("discovery: {}", err));
+        vec![]
+    });
+    nodes.retain(|node| !matches!(node.service, Service::Client(_)));
+    nodes.sort_by_key(|node| sort_key(&node.service));
+    let nodes = join_all(
+        nodes
+            .into_iter()
+            .map(|node| probe(node, tls.clone(), deadline)),
+    )
+    .await;
+
+    let taken_at = Utc::now();
+    let elected = election::elected(config, ROUND, taken_at)
+        .await
+        .unwrap_or_else(|err| {
+            errors.push(format!("publisher lease: {}", err));
+            None
+        });
+
+    // Any publisher that received the workers' reports will do, but the
+    // elected one is the likeliest to have them.
+    let mut publishers: Vec<&Node> = nodes
+        .iter()
+        .map(|status| &status.node)
+        .filter(|node| matches!(node.service, Service::Publisher(_)))
+        .collect();
+    publishers.sort_by_key(|node| Some(&node.addr) != elected.as_ref());
+    let mut stats = None;
+    let mut stats_errors = vec![];
+    for node in publishers {
+        match get_stats(node, tls.clone(), deadline).await {
+            Ok(response) => {
+                stats = Some(response);
+                break;
+            }
+            Err(err) => stats_errors.push(format!("stats from {}: {}", node.service, err)),
+        }
+    }
+    // One publisher answering is enough.
+    if stats.is_none() {
+        errors.extend(stats_errors);
+    }
+
+    let abort = get_abort(config).await.unwrap_or_else(|err| {
+        errors.push(format!("abort notice: {}", err));
+        None
+    });
+    let start = start_time(config).await.unwrap_or_else(|err| {
+        errors.push(format!("start time: {}", err));
+        None
+    });
+    let phase = Phase::new(abort, start, stats.as_ref(), taken_at);
+
+    Snapshot {
+        taken_at,
+        phase,
+        nodes,
+        elected,
+        stats,
+        errors,
+    }
+}
+
+// A worker's latest report, as (clients verified, elapsed ms).
+type Progress = (u64, u64);
+
+/// Renders successive snapshots, tracking each worker's recent throughput.
+///
+/// Workers report cumulative counts, so "qps" is over the last refresh (the
+/// publisher's whole-run average is "avg qps").
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    // Keyed by (group, index).
+    last: BTreeMap<(u32, u32), (Progress, u64)>,
+}
+
+impl Dashboard {
+    // QPS since the worker's previous report (or the average, for the first).
+    fn recent_qps(&mut self, worker: &get_stats_response::Worker) -> u64 {
+        let id = worker.worker_id.clone().unwrap_or_default();
+        let progress = (worker.clients_verified, worker.elapsed_ms);
+        let average = average_qps(worker);
+        let qps = match self.last.get(&(id.group, id.idx)) {
+            // No new report since the last refresh.
+            Some((last, qps)) if *last == progress => *qps,
+            Some(((verified, elapsed), _)) if progress.1 > *elapsed => {
+                progress.0.saturating_sub(*verified) * 1000 / (progress.1 - elapsed)
+            }
+            _ => average,
+        };
+        self.last.insert((id.group, id.idx), (progress, qps));
+        qps
+    }
+
+    pub fn render(&mut self, snapshot: &Snapshot) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        let _ = self.write(&mut out, snapshot);
+        out
+    }
+
+    fn write(&mut self, out: &mut String, snapshot: &Snapshot) -> fmt::Result {
+        writeln!(
+            out,
+            "spectrum-top  {}",
+            snapshot.taken_at.format("%Y-%m-%d %H:%M:%S UTC")
+        )?;
+        writeln!(out, "phase: {}", snapshot.phase)?;
+        writeln!(out)?;
+
+        writeln!(out, "{:<20} {:<32} health", "service", "address")?;
+        for status in &snapshot.nodes {
+            let mut service = status.node.service.to_string();
+            if Some(&status.node.addr) == snapshot.elected.as_ref() {
+                service.push_str(" (elected)");
+            }
+            writeln!(
+                out,
+                "{:<20} {:<32} {}",
+                service,
+                status.node.uri(),
+                status.health
+            )?;
+        }
+        if snapshot.nodes.is_empty() {
+            writeln!(out, "(no nodes registered yet)")?;
+        }
+        writeln!(out)?;
+
+        match &snapshot.stats {
+            Some(stats) if !stats.workers.is_empty() => self.write_workers(out, stats)?,
+            Some(_) => writeln!(out, "(no worker stats yet; workers report in hammer mode)")?,
+            None => writeln!(out, "(no publisher stats)")?,
+        }
+
+        for err in &snapshot.errors {
+            writeln!(out, "error: {}", err)?;
+        }
+        Ok(())
+    }
+
+    fn write_workers(&mut self, out: &mut String, stats: &GetStatsResponse) -> fmt::Result {
+        writeln!(
+            out,
+            "{:>6} {:>6} {:>12} {:>8} {:>8} {:>8} {:>8} {:>10} {:>10}",
+            "group",
+            "worker",
+            "verified",
+            "qps",
+            "avg qps",
+            "pending",
+            "failed",
+            "viewers",
+            "bcasters"
+        )?;
+        // As in the publisher's stats table, each client is verified by one
+        // worker in every group, so totals are over the first group (except
+        // for pending audits).
+        let mut total = [0u64; 7];
+        for worker in &stats.workers {
+            let id = worker.worker_id.clone().unwrap_or_default();
+            let row = [
+                worker.clients_verified,
+                self.recent_qps(worker),
+                average_qps(worker),
+                worker.pending_audits,
+                worker.audit_failures,
+                worker.viewers_registered,
+                worker.broadcasters_registered,
+            ];
+            for (i, (sum, value)) in total.iter_mut().zip(row.iter()).enumerate() {
+                if id.group == 0 || i == 3 {
+                    *sum += value;
+                }
+            }
+            writeln!(
+                out,
+                "{:>6} {:>6} {:>12} {:>8} {:>8} {:>8} {:>8} {:>10} {:>10}",
+                id.group + 1,
+                id.idx + 1,
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                row[4],
+                row[5],
+                row[6]
+            )?;
+        }
+        writeln!(
+            out,
+            "{:>6} {:>6} {:>12} {:>8} {:>8} {:>8} {:>8} {:>10} {:>10}",
+            "total", "", total[0], total[1], total[2], total[3], total[4], total[5], total[6]
+        )
+    }
+}
+
+fn average_qps(worker: &get_stats_response::Worker) -> u64 {
+    if worker.elapsed_ms == 0 {
+        return 0;
+    }
+    worker.clients_verified * 1000 / worker.elapsed_ms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::WorkerId;
+    use crate::services::PublisherInfo;
+
+    fn worker(
+        group: u32,
+        idx: u32,
+        clients_verified: u64,
+        elapsed_ms: u64,
+    ) -> get_stats_response::Worker {
+        get_stats_response::Worker {
+            worker_id: Some(WorkerId { group, idx }),
+            clients_verified,
+            elapsed_ms,
+            ..Default::default()
+        }
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_600_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn test_phase() {
+        let start = Some(at(10).into());
+        let stats = |received| GetStatsResponse {
+            total_groups: 2,
+            latest_round: 3,
+            groups_received: received,
+            ..Default::default()
+        };
+        assert_eq!(Phase::new(None, None, None, at(0)), Phase::Waiting);
+        assert_eq!(
+            Phase::new(None, start, None, at(0)),
+            Phase::Starting(chrono::Duration::seconds(10))
+        );
+        assert_eq!(
+            Phase::new(None, start, Some(&stats(0)), at(75)),
+            Phase::Running(chrono::Duration::seconds(65))
+        );
+        assert_eq!(
+            Phase::new(None, start, Some(&stats(1)), at(75)),
+            Phase::Aggregating {
+                round: 3,
+                received: 1,
+                total: 2
+            }
+        );
+        assert_eq!(
+            Phase::new(None, start, Some(&stats(2)), at(75)),
+            Phase::Recovered { round: 3 }
+        );
+        let notice = AbortNotice {
+            round: 0,
+            reason: "test".to_string(),
+        };
+        assert_eq!(
+            Phase::new(Some(notice.clone()), start, Some(&stats(2)), at(75)),
+            Phase::Aborted(notice)
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(chrono::Duration::seconds(-3)), "0s");
+        assert_eq!(format_duration(chrono::Duration::seconds(42)), "42s");
+        assert_eq!(format_duration(chrono::Duration::seconds(83)), "1m23s");
+        assert_eq!(format_duration(chrono::Duration::seconds(3723)), "1h02m03s");
+    }
+
+    #[test]
+    fn test_recent_qps() {
+        let mut dashboard = Dashboard::default();
+        // First report: the whole-run average.
+        assert_eq!(dashboard.recent_qps(&worker(0, 0, 100, 1000)), 100);
+        // 300 more clients in the next half second.
+        assert_eq!(dashboard.recent_qps(&worker(0, 0, 400, 1500)), 600);
+        // No new report: same as before.
+        assert_eq!(dashboard.recent_qps(&worker(0, 0, 400, 1500)), 600);
+        // Other workers are tracked separately.
+        assert_eq!(dashboard.recent_qps(&worker(1, 0, 10, 1000)), 10);
+    }
+
+    #[test]
+    fn test_render() {
+        let publisher = Node::new(PublisherInfo::new(0).into(), "127.0.0.1:6000".to_string());
+        let snapshot = Snapshot {
+            taken_at: at(75),
+            phase: Phase::Running(chrono::Duration::seconds(65)),
+            nodes: vec![NodeStatus {
+                node: publisher,
+                health: Health::Serving,
+            }],
+            elected: Some("127.0.0.1:6000".to_string()),
+            stats: Some(GetStatsResponse {
+                workers: vec![worker(0, 0, 100, 1000), worker(1, 0, 100, 1000)],
+                ..Default::default()
+            }),
+            errors: vec!["start time: oops".to_string()],
+        };
+        let frame = Dashboard::default().render(&snapshot);
+        assert!(frame.contains("phase: running for 1m05s"), "{}", frame);
+        assert!(frame.contains("publisher 1 (elected)"), "{}", frame);
+        assert!(frame.contains("serving"), "{}", frame);
+        assert!(frame.contains("error: start time: oops"), "{}", frame);
+        // Clients count once, though both groups verified them.
+        let total = frame.lines().find(|line| line.contains("total")).unwrap();
+        assert_eq!(total.split_whitespace().nth(1), Some("100"), "{}", frame);
+    }
+}
@@ -0,0 +1,181 @@
+//! Batched audit checks.
+//!
+//! Each client's audit is checked once all of its shares are in, and at high
+//! client counts those checks pile up. Rather than checking them one at a
+//! time, checks go to the crypto pool in batches (see
+//! [`Protocol::check_audit_batch`]): up to `max_batches` run at once, and a
+//! check arriving while they're all running queues up for the next ones.
+//! Under light load, that's a batch of one per check, all running
+//! concurrently.
+//!
+//! Checking is comparing (or summing) tokens: the hashing happened once per
+//! client when the tokens were made, so there's no hashing left to share
+//! across a batch.
+use super::crypto_pool::{CryptoPool, JobKind};
+use crate::protocols::Protocol;
+use crate::rt::{blocking::Mutex, spawn, sync::oneshot};
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+struct Queue<S> {
+    pending: VecDeque<(Vec<S>, oneshot::Sender<bool>)>,
+    // How many tasks are draining `pending`.
+    draining: usize,
+}
+
+pub struct AuditBatcher<P: Protocol> {
+    protocol: P,
+    crypto_pool: CryptoPool,
+    max_batches: usize,
+    queue: Arc<Mutex<Queue<P::AuditShare>>>,
+}
+
+impl<P: Protocol> AuditBatcher<P> {
+    /// Check batches on `crypto_pool`, up to `max_batches` (at least one) at
+    /// once.
+    pub fn new(protocol: P, crypto_pool: CryptoPool, max_batches: usize) -> Self {
+        AuditBatcher {
+            protocol,
+            crypto_pool,
+            max_batches: max_batches.max(1),
+            queue: Arc::new(Mutex::new(Queue {
+                pending: VecDeque::new(),
+                draining: 0,
+            })),
+        }
+    }
+}
+
+impl<P> AuditBatcher<P>
+where
+    P: Protocol + Clone + Send + 'static,
+    P::AuditShare: Send,
+{
+    /// Check one client's audit shares, along with any others waiting.
+    pub async fn check(&self, shares: Vec<P::AuditShare>) -> bool {
+        let (tx, rx) = oneshot::channel();
+        let start = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.pending.push_back((shares, tx));
+            let start = queue.draining < self.max_batches;
+            if start {
+                queue.draining += 1;
+            }
+            start
+        };
+        if start {
+            spawn(drain(
+                self.protocol.clone(),
+                self.crypto_pool.clone(),
+                self.queue.clone(),
+            ));
+        }
+        rx.await.expect("Checking audits should not panic.")
+    }
+}
+
+async fn drain<P>(protocol: P, crypto_pool: CryptoPool, queue: Arc<Mutex<Queue<P::AuditShare>>>)
+where
+    P: Protocol + Clone + Send + 'static,
+    P::AuditShare: Send,
+{
+    loop {
+        let batch = {
+            let mut queue = queue.lock().unwrap();
+            if queue.pending.is_empty() {
+                queue.draining -= 1;
+                return;
+            }
+            // Oldest first, leaving the rest for the other tasks.
+            let len = queue.pending.len().div_ceil(queue.draining);
+            queue.pending.drain(..len).collect::<Vec<_>>()
+        };
+        let (token_sets, results): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+        let protocol = protocol.clone();
+        let verified = crypto_pool
+            .run(JobKind::Audit, move || {
+                protocol.check_audit_batch(token_sets)
+            })
+            .await
+            .expect("Checking audits should not panic.");
+        for (result, verified) in results.into_iter().zip(verified) {
+            // The verify call may have gone away in the meantime.
+            let _ = result.send(verified);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::secure;
+    use futures::future::join_all;
+    use spectrum_primitives::{MultiKeyVdpf, TwoKeyVdpf, Vdpf};
+
+    // Each server's shares for writes (of an empty message to channel 1) with
+    // each of `write_keys`.
+    fn share_sets<P>(
+        protocol: &P,
+        keys: &[P::ChannelKey],
+        write_keys: Vec<P::ChannelKey>,
+    ) -> Vec<Vec<P::AuditShare>>
+    where
+        P: Protocol,
+        P::AuditShare: Clone,
+    {
+        let mut share_sets = vec![];
+        for key in write_keys {
+            let mut server_shares = vec![vec![]; protocol.num_parties()];
+            let msg = protocol.new_accumulator().remove(0);
+            for token in protocol.broadcast(msg, 1, key) {
+                let shares = protocol.gen_audit(keys, token);
+                for (server, share) in shares.into_iter().enumerate() {
+                    server_shares[server].push(share);
+                }
+            }
+            share_sets.extend(server_shares);
+        }
+        share_sets
+    }
+
+    async fn check_all<P>(protocol: P, share_sets: Vec<Vec<P::AuditShare>>) -> Vec<bool>
+    where
+        P: Protocol + Clone + Send + 'static,
+        P::AuditShare: Send,
+    {
+        let batcher = AuditBatcher::new(protocol, CryptoPool::new(Default::default()), 2);
+        join_all(share_sets.into_iter().map(|shares| batcher.check(shares))).await
+    }
+
+    #[tokio::test]
+    async fn test_check_matches_check_audit() {
+        let vdpf = TwoKeyVdpf::with_channels_msg_size(3, 16);
+        let keys = vdpf.new_access_keys();
+        let bad_key = vdpf.new_access_keys().remove(0);
+        let protocol: secure::Wrapper<TwoKeyVdpf> = vdpf.into();
+
+        let share_sets = share_sets(&protocol, &keys, vec![keys[1], bad_key]);
+        let expected: Vec<bool> = share_sets
+            .iter()
+            .map(|shares| protocol.check_audit(shares.clone()))
+            .collect();
+        assert_eq!(expected, vec![true, true, false, false]);
+        assert_eq!(check_all(protocol, share_sets).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_check_multi_key() {
+        let vdpf = MultiKeyVdpf::with_channels_parties_msg_size(3, 3, 16);
+        let keys = vdpf.new_access_keys();
+        let bad_key = vdpf.new_access_keys().remove(0);
+        let protocol: secure::Wrapper<MultiKeyVdpf> = vdpf.into();
+
+        let good = share_sets(&protocol, &keys, vec![keys[1]; 2]);
+        assert_eq!(check_all(protocol.clone(), good).await, vec![true; 6]);
+
+        let mixed = share_sets(&protocol, &keys, vec![keys[1], bad_key]);
+        let expected = [vec![true; 3], vec![false; 3]].concat();
+        assert_eq!(check_all(protocol, mixed).await, expected);
+    }
+}
@@ -0,0 +1,289 @@
+//! Streaming audit failures to an external sink (a log file or a webhook).
+//!
+//! Recording an event never waits: events go into a bounded queue (and are
+//! dropped, with a count, if it's full). A background task writes them out in
+//! batches of newline-delimited JSON, backing off while the sink is failing.
+use crate::rt::{sleep, spawn, spawn_blocking, sync::mpsc};
+use crate::services::{ClientInfo, WorkerInfo};
+use crate::Error;
+
+use chrono::prelude::*;
+use futures::FutureExt;
+use hyper::{client::HttpConnector, header::CONTENT_TYPE, Body, Client, Request};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const QUEUE_CAPACITY: usize = 4096;
+const MAX_BATCH: usize = 256;
+// Events held while the sink is down; past this, the oldest get dropped.
+const MAX_PENDING: usize = 16 * 1024;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Where a worker sends its audit failures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditSink {
+    /// Append to a file.
+    File(PathBuf),
+    /// `POST` each batch to a (plain HTTP) URL.
+    Webhook(String),
+}
+
+/// Parses `file:<path>` or `http://<url>`.
+impl FromStr for AuditSink {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("file:") {
+            if !path.is_empty() {
+                return Ok(AuditSink::File(path.into()));
+            }
+        } else if s.starts_with("http://") {
+            return Ok(AuditSink::Webhook(s.to_string()));
+        } else if s.starts_with("https://") {
+            return Err(Error::new(
+                "HTTPS webhooks aren't supported; forward through a local proxy.",
+            ));
+        }
+        Err(Error::new(&format!(
+            "Bad audit log [{}]; expected file:<path> or http://<url>.",
+            s
+        )))
+    }
+}
+
+impl fmt::Display for AuditSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditSink::File(path) => write!(f, "file:{}", path.display()),
+            AuditSink::Webhook(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// What the worker did with a write that failed its audit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Rejected,
+    Quarantined,
+    Flagged,
+}
+
+/// One audit failure, as written to the sink (a line of JSON).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub client: u128,
+    pub group: u16,
+    pub worker: u16,
+    /// When the worker saw the failure (RFC 3339).
+    pub timestamp: String,
+    pub outcome: AuditOutcome,
+}
+
+/// Handle for recording audit failures; cheap to clone.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    worker: WorkerInfo,
+    tx: mpsc::Sender<AuditEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AuditLog {
+    fn new(worker: WorkerInfo, tx: mpsc::Sender<AuditEvent>) -> Self {
+        AuditLog {
+            worker,
+            tx,
+            dropped: Default::default(),
+        }
+    }
+
+    /// Start sending the audit failures of `worker` to `sink`.
+    pub fn spawn(sink: AuditSink, worker: WorkerInfo) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let log = AuditLog::new(worker, tx);
+        spawn(forward(sink, rx, log.dropped.clone()));
+        log
+    }
+
+    /// Queue an event, dropping it if the queue is full.
+    pub fn record(&self, client: &ClientInfo, outcome: AuditOutcome) {
+        let event = AuditEvent {
+            client: client.idx,
+            group: self.worker.group.idx,
+            worker: self.worker.idx,
+            timestamp: Utc::now().to_rfc3339(),
+            outcome,
+        };
+        if self.tx.try_send(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Send events from `rx` to `sink` until every `AuditLog` is gone (and the
+// queue is drained).
+async fn forward(sink: AuditSink, mut rx: mpsc::Receiver<AuditEvent>, dropped: Arc<AtomicU64>) {
+    let client = Client::new();
+    let mut pending: VecDeque<AuditEvent> = VecDeque::new();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut open = true;
+    loop {
+        if pending.is_empty() {
+            if !open {
+                return;
+            }
+            match rx.recv().await {
+                Some(event) => pending.push_back(event),
+                None => return,
+            }
+        }
+        // Take whatever else is already waiting.
+        while open && pending.len() < MAX_PENDING {
+            match rx.recv().now_or_never() {
+                Some(Some(event)) => pending.push_back(event),
+                Some(None) => open = false,
+                None => break,
+            }
+        }
+
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        if lost > 0 {
+            warn!("Audit log queue full; dropped {} events.", lost);
+        }
+
+        let batch: Vec<_> = pending.iter().take(MAX_BATCH).cloned().collect();
+        match write(&sink, &client, &batch).await {
+            Ok(()) => {
+                debug!("Wrote {} audit events to {}.", batch.len(), sink);
+                pending.drain(..batch.len());
+                backoff = INITIAL_BACKOFF;
+            }
+            Err(err) => {
+                warn!(
+                    "Couldn't write audit events to {} (retrying in {:?}): {}",
+                    sink, backoff, err
+                );
+                if pending.len() > MAX_PENDING {
+                    let excess = pending.len() - MAX_PENDING;
+                    pending.drain(..excess);
+                    dropped.fetch_add(excess as u64, Ordering::Relaxed);
+                }
+                sleep(backoff).await;
+                backoff = min(backoff * 2, MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+fn ndjson(events: &[AuditEvent]) -> Vec<u8> {
+    let mut data = vec![];
+    for event in events {
+        serde_json::to_writer(&mut data, event).expect("serializing to a Vec can't fail");
+        data.push(b'\n');
+    }
+    data
+}
+
+async fn write(
+    sink: &AuditSink,
+    client: &Client<HttpConnector>,
+    events: &[AuditEvent],
+) -> Result<(), Error> {
+    let data = ndjson(events);
+    match sink {
+        AuditSink::File(path) => {
+            let path = path.clone();
+            spawn_blocking(move || {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(&data))
+                    .map_err(|err| Error::new(&format!("[{}]: {}", path.display(), err)))
+            })
+            .await
+            .map_err(|err| Error::new(&err.to_string()))?
+        }
+        AuditSink::Webhook(url) => {
+            let request = Request::post(url.as_str())
+                .header(CONTENT_TYPE, "application/x-ndjson")
+                .body(Body::from(data))
+                .map_err(|err| Error::new(&err.to_string()))?;
+            let response = client
+                .request(request)
+                .await
+                .map_err(|err| Error::new(&err.to_string()))?;
+            if !response.status().is_success() {
+                return Err(Error::new(&format!("HTTP {}", response.status())));
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::Group;
+
+    fn worker() -> WorkerInfo {
+        WorkerInfo::new(Group::new(1), 2)
+    }
+
+    #[test]
+    fn test_parse_sink() {
+        for s in &["file:audit.ndjson", "http://localhost:8080/audit"] {
+            assert_eq!(s.parse::<AuditSink>().unwrap().to_string(), *s);
+        }
+        for s in &["", "file:", "audit.ndjson", "https://example.com/audit"] {
+            s.parse::<AuditSink>().expect_err("Should fail to parse.");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.ndjson");
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let log = AuditLog::new(worker(), tx);
+        for idx in 0..3 {
+            log.record(&ClientInfo::new(idx), AuditOutcome::Rejected);
+        }
+        let dropped = log.dropped.clone();
+        drop(log);
+        forward(AuditSink::File(path.clone()), rx, dropped).await;
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let events: Vec<AuditEvent> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 3);
+        for (idx, event) in events.iter().enumerate() {
+            assert_eq!(event.client, idx as u128);
+            assert_eq!((event.group, event.worker), (1, 2));
+            assert_eq!(event.outcome, AuditOutcome::Rejected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_drops_when_full() {
+        let (tx, _rx) = mpsc::channel(1);
+        let log = AuditLog::new(worker(), tx);
+        for idx in 0..3 {
+            log.record(&ClientInfo::new(idx), AuditOutcome::Flagged);
+        }
+        assert_eq!(log.dropped.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{proto, services::ClientInfo, Error};
+
+use crate::rt::spawn_blocking;
+use prost::Message;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const DEFAULT_QUARANTINE_DIR: &str = "quarantine";
+
+/// What a worker does with a write whose audit fails.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AuditFailurePolicy {
+    /// Drop the write.
+    #[default]
+    Reject,
+    /// Drop the write, but save the write token to the given directory for
+    /// offline analysis.
+    Quarantine(PathBuf),
+    /// Apply the write anyway, counting it as flagged.
+    AcceptFlagged,
+}
+
+/// Parses `reject`, `accept-flagged`, `quarantine`, or `quarantine:<dir>`.
+impl FromStr for AuditFailurePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(AuditFailurePolicy::Reject),
+            "accept-flagged" => Ok(AuditFailurePolicy::AcceptFlagged),
+            "quarantine" => Ok(AuditFailurePolicy::Quarantine(DEFAULT_QUARANTINE_DIR.into())),
+            _ => match s.strip_prefix("quarantine:") {
+                Some(dir) if !dir.is_empty() => Ok(AuditFailurePolicy::Quarantine(dir.into())),
+                _ => Err(Error::new(&format!(
+                    "Bad audit failure policy [{}]; expected reject, quarantine[:<dir>], or accept-flagged.",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+impl fmt::Display for AuditFailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditFailurePolicy::Reject => write!(f, "reject"),
+            AuditFailurePolicy::Quarantine(dir) => write!(f, "quarantine:{}", dir.display()),
+            AuditFailurePolicy::AcceptFlagged => write!(f, "accept-flagged"),
+        }
+    }
+}
+
+/// Save `token` (protobuf-encoded) as `<dir>/client-<idx>.pb`.
+pub async fn quarantine(
+    dir: PathBuf,
+    client: &ClientInfo,
+    token: proto::WriteToken,
+) -> Result<PathBuf, Error> {
+    let path = dir.join(format!("client-{}.pb", client.idx));
+    spawn_blocking(move || {
+        let mut data = Vec::with_capacity(token.encoded_len());
+        token
+            .encode(&mut data)
+            .map_err(|err| Error::new(&err.to_string()))?;
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&path, data))
+            .map_err(|err| Error::new(&format!("Couldn't write [{}]: {}", path.display(), err)))?;
+        Ok(path)
+    })
+    .await
+    .map_err(|err| Error::new(&err.to_string()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn policies() -> impl Strategy<Value = AuditFailurePolicy> {
+        prop_oneof![
+            Just(AuditFailurePolicy::Reject),
+            Just(AuditFailurePolicy::AcceptFlagged),
+            "[a-z/]{1,10}".prop_map(|dir| AuditFailurePolicy::Quarantine(dir.into())),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_roundtrip(policy in policies()) {
+            prop_assert_eq!(policy.to_string().parse::<AuditFailurePolicy>().unwrap(), policy);
+        }
+    }
+
+    #[test]
+    fn test_parse_quarantine_default_dir() {
+        assert_eq!(
+            "quarantine".parse::<AuditFailurePolicy>().unwrap(),
+            AuditFailurePolicy::Quarantine(DEFAULT_QUARANTINE_DIR.into())
+        );
+    }
+
+    #[test]
+    fn test_parse_bad() {
+        for s in &["", "accept", "quarantine:", "Reject"] {
+            s.parse::<AuditFailurePolicy>()
+                .expect_err("Should fail to parse.");
+        }
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().join("nested");
+        let token = proto::WriteToken::default();
+        let path = crate::rt::block_on(quarantine(dir.clone(), &ClientInfo::new(7), token.clone()))
+            .unwrap();
+        assert_eq!(path, dir.join("client-7.pb"));
+        let data = fs::read(path).unwrap();
+        assert_eq!(proto::WriteToken::decode(&data[..]).unwrap(), token);
+    }
+}
@@ -1,98 +1,135 @@
 // https://github.com/rust-lang/rust-clippy/issues/5902
 #![allow(clippy::same_item_push)]
+use super::audit_slot::AuditSlot;
+use super::token_seal::{Sealed, TokenSealer};
 use crate::services::ClientInfo;
-use log::warn;
+use spectrum_primitives::Zeroizing;
 use std::collections::HashMap;
 
-use tokio::sync::Mutex;
+pub use super::audit_slot::{ClientAudit, Progress};
 
-// The first member of the inner-tuple corresponds to the write token; it's initialized by init().
-// The second member corresponds to each audit share.
-// drain() takes the whole thing.
-#[derive(Debug)]
-struct ClientAuditState<S, T> {
-    pub write_token: Option<T>,
-    audit_shares: Vec<S>,
-}
+// An upload attempt: the client, and the sequence number the first worker
+// assigned (so shares from a retried upload don't mix with the original's).
+type UploadKey = (ClientInfo, u64);
 
-/// A complete client audit, ready to be checked.
-pub struct ClientAudit<S, T> {
-    pub write_token: T,
-    pub audit_shares: Vec<S>,
+/// How to turn write tokens into bytes and back, for sealing them.
+pub struct TokenCodec<T> {
+    pub encode: fn(&T) -> Vec<u8>,
+    pub decode: fn(&[u8]) -> Option<T>,
 }
 
-impl<S, T> From<ClientAuditState<S, T>> for ClientAudit<S, T> {
-    fn from(rhs: ClientAuditState<S, T>) -> Self {
-        ClientAudit::<S, T> {
-            write_token: rhs.write_token.unwrap(),
-            audit_shares: rhs.audit_shares,
-        }
-    }
+// A write token as stored: as-is, or sealed (see `token_seal`).
+enum Stored<T> {
+    Plain(T),
+    Sealed(Sealed),
 }
 
-impl<S, T> ClientAuditState<S, T> {
-    fn new(write_token: Option<T>, audit_shares: Vec<S>) -> Self {
-        ClientAuditState {
-            write_token,
-            audit_shares,
-        }
-    }
-}
-
-// For each client, an Option of lists of audit shares (with appropriate locking).
+// For each upload, an audit slot (see `AuditSlot`).
 //
-// The idea is that you add shares for each client as received, then drain all
-// of them (one-time only) to check the audit.
+// The idea is that you add shares for each upload as received, and the one
+// that completes the audit takes it (one-time only) to check.
 //
-// Each entry also stores the write token for the client.
+// Each entry also stores the write token for the upload: sealed, if sealing
+// is on (see `with_sealing`).
 pub struct AuditRegistry<S, T> {
-    registry: HashMap<ClientInfo, Mutex<ClientAuditState<S, T>>>,
-    num_parties: u16,
+    registry: HashMap<UploadKey, AuditSlot<S, Stored<T>>>,
+    num_parties: usize,
+    sealing: Option<(TokenCodec<T>, TokenSealer)>,
 }
 
 impl<S, T> AuditRegistry<S, T> {
-    pub fn new(num_clients: u128, num_parties: u16) -> AuditRegistry<S, T> {
+    pub fn new(num_clients: u128, num_parties: usize) -> AuditRegistry<S, T> {
         AuditRegistry {
             registry: HashMap::with_capacity(num_clients as usize),
             num_parties,
+            sealing: None,
+        }
+    }
+
+    /// Seal write tokens while they're stored, under a key that's replaced
+    /// each round (see `clear`).
+    pub fn with_sealing(mut self, codec: TokenCodec<T>) -> Self {
+        self.sealing = Some((codec, TokenSealer::default()));
+        self
+    }
+
+    fn slot(&mut self, key: UploadKey) -> &AuditSlot<S, Stored<T>> {
+        let num_parties = self.num_parties;
+        self.registry
+            .entry(key)
+            .or_insert_with(|| AuditSlot::new(num_parties))
+    }
+
+    fn store(&self, token: T) -> Stored<T> {
+        match &self.sealing {
+            Some((codec, sealer)) => {
+                let encoded = Zeroizing::new((codec.encode)(&token));
+                Stored::Sealed(sealer.seal(&encoded))
+            }
+            None => Stored::Plain(token),
         }
     }
 
-    pub async fn init(&mut self, info: &ClientInfo, token: T) {
-        if let Some(mutex) = self.registry.get(info) {
-            let mut state = mutex.lock().await;
-            if !state.audit_shares.is_empty() {
-                warn!("Re-init before drain.")
+    fn load(&self, audit: ClientAudit<S, Stored<T>>) -> ClientAudit<S, T> {
+        let write_token = match (audit.write_token, &self.sealing) {
+            (Stored::Plain(token), _) => token,
+            (Stored::Sealed(sealed), Some((codec, sealer))) => {
+                let encoded = sealer
+                    .open(&sealed)
+                    .expect("Write tokens are sealed with this round's key.");
+                (codec.decode)(&encoded).expect("Sealed write tokens should decode.")
             }
-            state.write_token.replace(token);
-        } else {
-            let vec = Vec::with_capacity(self.num_parties as usize);
-            self.registry.insert(
-                info.clone(),
-                Mutex::new(ClientAuditState::new(Some(token), vec)),
-            );
+            (Stored::Sealed(_), None) => unreachable!("Only sealed if sealing is on."),
+        };
+        ClientAudit {
+            write_token,
+            audit_shares: audit.audit_shares,
+            started: audit.started,
         }
     }
 
-    pub async fn drain(&mut self, info: &ClientInfo) -> ClientAudit<S, T> {
-        if let Some(mutex) = self.registry.remove(info) {
-            return mutex.into_inner().into();
-        } else {
-            panic!("May only drain once, and must be after init'd.");
+    pub async fn init(&mut self, info: &ClientInfo, seq: u64, token: T) {
+        let token = self.store(token);
+        self.slot((info.clone(), seq)).set_token(token);
+    }
+
+    /// The number of uploads with audits in progress (not yet drained).
+    pub fn len(&self) -> usize {
+        self.registry.len()
+    }
+
+    /// Abandon all audits in progress, returning how many there were.
+    ///
+    /// Sealed tokens get a new key from here on.
+    pub fn clear(&mut self) -> usize {
+        let abandoned = self.registry.len();
+        self.registry.clear();
+        if let Some((_, sealer)) = &mut self.sealing {
+            *sealer = TokenSealer::default();
         }
+        abandoned
+    }
+
+    #[cfg(test)]
+    pub async fn drain(&mut self, info: &ClientInfo, seq: u64) -> ClientAudit<S, T> {
+        let audit = self
+            .registry
+            .remove(&(info.clone(), seq))
+            .and_then(|slot| slot.drain())
+            .expect("May only drain once, and must be after init'd.");
+        self.load(audit)
     }
 
-    pub async fn add(&mut self, info: &ClientInfo, value: S) -> usize {
-        if let Some(lock) = self.registry.get(info) {
-            let mut guard = lock.lock().await;
-            guard.audit_shares.push(value);
-            guard.audit_shares.len()
-        } else {
-            let mut vec = Vec::with_capacity(self.num_parties as usize);
-            vec.push(value);
-            let client_state = ClientAuditState::new(None, vec);
-            self.registry.insert(info.clone(), Mutex::new(client_state));
-            1
+    /// Add a share; if that completes the audit, it's taken (and drained).
+    pub async fn add(&mut self, info: &ClientInfo, seq: u64, value: S) -> Progress<S, T> {
+        let key = (info.clone(), seq);
+        match self.slot(key.clone()).add_share(value) {
+            Progress::Ready(audit) => {
+                self.registry.remove(&key);
+                Progress::Ready(self.load(audit))
+            }
+            Progress::Waiting(count) => Progress::Waiting(count),
+            Progress::Taken => Progress::Taken,
         }
     }
 }
@@ -101,16 +138,17 @@ impl<S, T> AuditRegistry<S, T> {
 mod tests {
     #![allow(clippy::unit_arg)]
     use super::*;
+    use std::convert::TryInto;
 
     const NUM_CLIENTS: u128 = 10;
-    const NUM_SHARES: u16 = 100;
+    const NUM_SHARES: usize = 100;
 
     #[should_panic]
     #[tokio::test]
     async fn test_audit_registry_bad_client_idx() {
         let client = ClientInfo::new(0);
         let mut reg = AuditRegistry::<(), ()>::new(0, NUM_SHARES);
-        reg.drain(&client).await;
+        reg.drain(&client, 0).await;
     }
 
     #[should_panic]
@@ -119,7 +157,7 @@ mod tests {
         let clients: Vec<ClientInfo> = (0..NUM_CLIENTS).map(ClientInfo::new).collect();
         let mut reg = AuditRegistry::<(), ()>::new(NUM_CLIENTS, NUM_SHARES);
 
-        reg.drain(&clients[0]).await;
+        reg.drain(&clients[0], 0).await;
     }
 
     #[tokio::test]
@@ -129,8 +167,8 @@ mod tests {
 
         for client in &clients {
             let expected_value = client.idx;
-            reg.init(client, expected_value).await;
-            let state = reg.drain(client).await;
+            reg.init(client, 0, expected_value).await;
+            let state = reg.drain(client, 0).await;
             assert_eq!(state.write_token, expected_value);
             assert!(state.audit_shares.is_empty());
         }
@@ -140,23 +178,57 @@ mod tests {
     async fn test_audit_registry_put_shares() {
         let clients: Vec<ClientInfo> = (0..NUM_CLIENTS).map(ClientInfo::new).collect();
         let mut reg = AuditRegistry::<(), u128>::new(NUM_CLIENTS, NUM_SHARES);
-        let expected_shares = vec![(); NUM_SHARES as usize];
+        let expected_shares = vec![(); NUM_SHARES];
 
         for client in &clients {
             for (idx, share) in expected_shares.iter().enumerate() {
-                assert_eq!(reg.add(client, *share).await, idx + 1);
+                let progress = reg.add(client, 0, *share).await;
+                assert!(matches!(progress, Progress::Waiting(n) if n == idx + 1));
             }
         }
 
         for client in &clients {
             let expected_value = client.idx;
-            reg.init(client, expected_value).await;
-            let state = reg.drain(client).await;
+            reg.init(client, 0, expected_value).await;
+            let state = reg.drain(client, 0).await;
             assert_eq!(state.write_token, expected_value);
             assert_eq!(state.audit_shares, expected_shares);
         }
     }
 
+    #[tokio::test]
+    async fn test_audit_registry_len() {
+        let clients: Vec<ClientInfo> = (0..NUM_CLIENTS).map(ClientInfo::new).collect();
+        let mut reg = AuditRegistry::<(), u128>::new(NUM_CLIENTS, NUM_SHARES);
+
+        for (idx, client) in clients.iter().enumerate() {
+            reg.add(client, 0, ()).await;
+            assert_eq!(reg.len(), idx + 1);
+        }
+
+        for (idx, client) in clients.iter().enumerate() {
+            reg.init(client, 0, client.idx).await;
+            reg.drain(client, 0).await;
+            assert_eq!(reg.len(), clients.len() - idx - 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit_registry_clear() {
+        let clients: Vec<ClientInfo> = (0..NUM_CLIENTS).map(ClientInfo::new).collect();
+        let mut reg = AuditRegistry::<(), u128>::new(NUM_CLIENTS, NUM_SHARES);
+
+        for client in &clients {
+            reg.add(client, 0, ()).await;
+        }
+        assert_eq!(reg.clear(), clients.len());
+        assert_eq!(reg.len(), 0);
+
+        // Clients start over in the next round.
+        reg.init(&clients[0], 0, 1).await;
+        assert!(reg.drain(&clients[0], 0).await.audit_shares.is_empty());
+    }
+
     #[should_panic]
     #[tokio::test]
     async fn test_audit_registry_drain_twice_panics() {
@@ -165,11 +237,11 @@ mod tests {
 
         for client in &clients {
             let expected_value = client.idx;
-            reg.init(client, expected_value).await;
-            reg.drain(client).await;
+            reg.init(client, 0, expected_value).await;
+            reg.drain(client, 0).await;
         }
 
-        reg.drain(&clients.pop().unwrap()).await;
+        reg.drain(&clients.pop().unwrap(), 0).await;
     }
 
     #[tokio::test]
@@ -179,10 +251,10 @@ mod tests {
 
         for client in &clients {
             let expected_value = client.idx;
-            reg.init(client, expected_value).await;
-            assert_eq!(reg.drain(client).await.write_token, expected_value);
-            reg.init(client, expected_value + 1).await;
-            assert_eq!(reg.drain(client).await.write_token, expected_value + 1);
+            reg.init(client, 0, expected_value).await;
+            assert_eq!(reg.drain(client, 0).await.write_token, expected_value);
+            reg.init(client, 0, expected_value + 1).await;
+            assert_eq!(reg.drain(client, 0).await.write_token, expected_value + 1);
         }
     }
 
@@ -193,9 +265,89 @@ mod tests {
 
         for client in &clients {
             let expected_value = client.idx;
-            reg.init(client, expected_value).await;
-            reg.init(client, expected_value + 1).await;
-            assert_eq!(reg.drain(client).await.write_token, expected_value + 1);
+            reg.init(client, 0, expected_value).await;
+            reg.init(client, 0, expected_value + 1).await;
+            assert_eq!(reg.drain(client, 0).await.write_token, expected_value + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit_registry_separates_uploads() {
+        let client = ClientInfo::new(0);
+        let mut reg = AuditRegistry::<u64, u64>::new(1, NUM_SHARES);
+
+        // A retried upload (new sequence number) starts from scratch.
+        reg.init(&client, 1, 1).await;
+        reg.add(&client, 1, 1).await;
+        reg.init(&client, 2, 2).await;
+        assert!(matches!(reg.add(&client, 2, 2).await, Progress::Waiting(1)));
+        assert_eq!(reg.len(), 2);
+
+        let state = reg.drain(&client, 2).await;
+        assert_eq!(state.write_token, 2);
+        assert_eq!(state.audit_shares, vec![2]);
+        assert_eq!(reg.drain(&client, 1).await.audit_shares, vec![1]);
+    }
+
+    fn sealed_registry(num_parties: usize) -> AuditRegistry<u64, u64> {
+        AuditRegistry::new(1, num_parties).with_sealing(TokenCodec {
+            encode: |token| token.to_le_bytes().to_vec(),
+            decode: |data| Some(u64::from_le_bytes(data.try_into().ok()?)),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_audit_registry_sealed() {
+        let client = ClientInfo::new(0);
+        let mut reg = sealed_registry(2);
+
+        reg.init(&client, 0, 7).await;
+        match reg.slot((client.clone(), 0)).drain() {
+            Some(ClientAudit {
+                write_token: Stored::Sealed(_),
+                ..
+            }) => {}
+            _ => panic!("Write token should be sealed."),
+        }
+
+        reg.init(&client, 1, 8).await;
+        reg.add(&client, 1, 1).await;
+        match reg.add(&client, 1, 2).await {
+            Progress::Ready(audit) => assert_eq!(audit.write_token, 8),
+            _ => panic!("Audit should be complete."),
+        }
+        reg.init(&client, 2, 9).await;
+        assert_eq!(reg.drain(&client, 2).await.write_token, 9);
+    }
+
+    #[should_panic]
+    #[tokio::test]
+    async fn test_audit_registry_sealed_new_key_each_round() {
+        let client = ClientInfo::new(0);
+        let mut reg = sealed_registry(2);
+
+        reg.init(&client, 0, 7).await;
+        let audit = reg.slot((client.clone(), 0)).drain().unwrap();
+        reg.clear();
+        // Last round's token doesn't open under this round's key.
+        reg.load(audit);
+    }
+
+    #[tokio::test]
+    async fn test_audit_registry_ready() {
+        let client = ClientInfo::new(0);
+        let mut reg = AuditRegistry::<u64, u64>::new(1, 2);
+
+        reg.init(&client, 0, 7).await;
+        assert!(matches!(reg.add(&client, 0, 1).await, Progress::Waiting(1)));
+        match reg.add(&client, 0, 2).await {
+            Progress::Ready(audit) => {
+                assert_eq!(audit.write_token, 7);
+                assert_eq!(audit.audit_shares, vec![1, 2]);
+            }
+            _ => panic!("Audit should be complete."),
         }
+        // Taken with the last share.
+        assert_eq!(reg.len(), 0);
     }
 }
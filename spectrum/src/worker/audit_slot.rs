@@ -0,0 +1,239 @@
+//! The audit of a single upload: its write token, and audit shares as they
+//! arrive from each party.
+//!
+//! Shares can arrive concurrently, and the audit must be handed off to be
+//! checked exactly once, by whoever adds the last share. Checking the share
+//! count and taking the audit happen under the same lock, so two shares
+//! racing in can't both (or neither) think they finished it.
+use crate::rt::blocking::Mutex;
+
+use log::warn;
+use std::mem;
+use std::time::Instant;
+
+/// A complete client audit, ready to be checked.
+pub struct ClientAudit<S, T> {
+    pub write_token: T,
+    pub audit_shares: Vec<S>,
+    /// When the audit started (the first we heard of the upload).
+    pub started: Instant,
+}
+
+enum State<S, T> {
+    Collecting {
+        write_token: Option<T>,
+        audit_shares: Vec<S>,
+        // When we first heard of the upload (its write token or a share).
+        started: Instant,
+    },
+    Taken,
+}
+
+impl<S, T> State<S, T> {
+    fn new(write_token: Option<T>, capacity: usize) -> Self {
+        State::Collecting {
+            write_token,
+            audit_shares: Vec::with_capacity(capacity),
+            started: Instant::now(),
+        }
+    }
+}
+
+/// What adding a share did.
+pub enum Progress<S, T> {
+    /// Still waiting for the write token or more shares (this many so far).
+    Waiting(usize),
+    /// That share completed the audit.
+    Ready(ClientAudit<S, T>),
+    /// The audit was already taken; the share was dropped.
+    Taken,
+}
+
+pub struct AuditSlot<S, T> {
+    state: Mutex<State<S, T>>,
+    num_parties: usize,
+}
+
+impl<S, T> AuditSlot<S, T> {
+    /// An empty slot for an audit needing a share from each of `num_parties`.
+    pub fn new(num_parties: usize) -> Self {
+        AuditSlot {
+            state: Mutex::new(State::new(None, num_parties)),
+            num_parties,
+        }
+    }
+
+    /// Set the write token (starting over if the audit was already taken).
+    pub fn set_token(&self, token: T) {
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            State::Collecting {
+                write_token,
+                audit_shares,
+                ..
+            } => {
+                if !audit_shares.is_empty() {
+                    warn!("Re-init before drain.")
+                }
+                write_token.replace(token);
+            }
+            State::Taken => {
+                *state = State::new(Some(token), self.num_parties);
+            }
+        }
+    }
+
+    /// Add a share, taking the audit if that completes it.
+    pub fn add_share(&self, share: S) -> Progress<S, T> {
+        let mut state = self.state.lock().unwrap();
+        let count = match &mut *state {
+            State::Collecting {
+                write_token,
+                audit_shares,
+                ..
+            } => {
+                audit_shares.push(share);
+                if write_token.is_none() || audit_shares.len() < self.num_parties {
+                    return Progress::Waiting(audit_shares.len());
+                }
+                audit_shares.len()
+            }
+            State::Taken => return Progress::Taken,
+        };
+        match Self::take(&mut state) {
+            Some(audit) => Progress::Ready(audit),
+            None => Progress::Waiting(count),
+        }
+    }
+
+    /// Take the audit as it stands, if it has a write token.
+    #[cfg(test)]
+    pub fn drain(&self) -> Option<ClientAudit<S, T>> {
+        Self::take(&mut self.state.lock().unwrap())
+    }
+
+    fn take(state: &mut State<S, T>) -> Option<ClientAudit<S, T>> {
+        match mem::replace(state, State::Taken) {
+            State::Collecting {
+                write_token: Some(write_token),
+                audit_shares,
+                started,
+            } => Some(ClientAudit {
+                write_token,
+                audit_shares,
+                started,
+            }),
+            other => {
+                *state = other;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+
+    fn shares(progress: Progress<u8, ()>) -> Option<Vec<u8>> {
+        match progress {
+            Progress::Ready(audit) => Some(audit.audit_shares),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_ready_once() {
+        let slot = AuditSlot::new(2);
+        slot.set_token(());
+        assert!(matches!(slot.add_share(1), Progress::Waiting(1)));
+        assert_eq!(shares(slot.add_share(2)), Some(vec![1, 2]));
+        assert!(matches!(slot.add_share(3), Progress::Taken));
+        assert!(slot.drain().is_none());
+    }
+
+    #[test]
+    fn test_waits_for_token() {
+        let slot = AuditSlot::new(1);
+        assert!(matches!(slot.add_share(1), Progress::Waiting(1)));
+        assert!(slot.drain().is_none());
+        slot.set_token(());
+        assert_eq!(slot.drain().unwrap().audit_shares, vec![1]);
+
+        // A new write token starts a new audit.
+        slot.set_token(());
+        assert_eq!(shares(slot.add_share(2)), Some(vec![2]));
+    }
+}
+
+#[cfg(all(test, feature = "loom"))]
+mod loom_tests {
+    use super::*;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    // Each of `shares` goes in on its own thread (as does the write token, if
+    // `token_last`); returns the audits that came out.
+    fn race(num_parties: usize, shares: Vec<u8>, token_last: bool) -> Vec<Vec<u8>> {
+        let slot = Arc::new(AuditSlot::new(num_parties));
+        if !token_last {
+            slot.set_token(());
+        }
+        let mut threads: Vec<_> = shares
+            .into_iter()
+            .map(|share| {
+                let slot = slot.clone();
+                thread::spawn(move || match slot.add_share(share) {
+                    Progress::Ready(audit) => Some(audit.audit_shares),
+                    _ => None,
+                })
+            })
+            .collect();
+        if token_last {
+            let slot = slot.clone();
+            threads.push(thread::spawn(move || {
+                slot.set_token(());
+                None
+            }));
+        }
+        let mut audits: Vec<_> = threads
+            .into_iter()
+            .filter_map(|t| t.join().unwrap())
+            .collect();
+        // Whatever's left over (if the token came last).
+        audits.extend(slot.drain().map(|audit| audit.audit_shares));
+        audits
+    }
+
+    #[test]
+    fn loom_ready_exactly_once() {
+        loom::model(|| {
+            let audits = race(2, vec![1, 2], false);
+            assert_eq!(audits.len(), 1);
+            let mut shares = audits[0].clone();
+            shares.sort_unstable();
+            assert_eq!(shares, vec![1, 2]);
+        });
+    }
+
+    #[test]
+    fn loom_extra_share() {
+        loom::model(|| {
+            // The third share either completes the audit or is dropped.
+            let audits = race(2, vec![1, 2, 3], false);
+            assert_eq!(audits.len(), 1);
+            assert_eq!(audits[0].len(), 2);
+        });
+    }
+
+    #[test]
+    fn loom_token_races_shares() {
+        loom::model(|| {
+            // Without the token, the shares can't complete the audit; it has
+            // to be drained afterwards.
+            let audits = race(2, vec![1, 2], true);
+            assert_eq!(audits.len(), 1);
+            assert_eq!(audits[0].len(), 2);
+        });
+    }
+}
@@ -1,15 +1,33 @@
-use crate::services::{ClientInfo, WorkerInfo};
+use crate::services::{ClientInfo, ClientRole, WorkerInfo};
 
-use log::{trace, warn};
+use crate::rt::sync::RwLock;
+use log::trace;
+use spectrum_primitives::ConstantTimeEq;
 use std::collections::HashMap;
-use tokio::sync::RwLock;
 use tonic::Status;
 
-type PeersMap = HashMap<ClientInfo, Vec<WorkerInfo>>;
+/// Opaque token issued to a client on registration; it must accompany uploads.
+pub type SessionToken = Vec<u8>;
+
+const SESSION_TOKEN_BYTES: usize = 16;
+
+struct Registration {
+    shards: Vec<WorkerInfo>,
+    token: SessionToken,
+}
+
+/// Registered clients, by self-reported role.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RoleCounts {
+    pub viewers: usize,
+    pub broadcasters: usize,
+    pub unknown: usize,
+}
 
 #[derive(Default)]
 pub struct State {
-    peers: PeersMap,
+    clients: HashMap<ClientInfo, Registration>,
+    roles: RoleCounts,
 }
 
 #[derive(Default)]
@@ -17,6 +35,10 @@ pub struct Registry {
     state: RwLock<State>,
 }
 
+fn not_registered(client: &ClientInfo) -> Status {
+    Status::failed_precondition(format!("Client info {:?} not registered.", client))
+}
+
 impl Registry {
     pub fn new() -> Self {
         Registry {
@@ -26,25 +48,159 @@ impl Registry {
 
     pub async fn get_peers(&self, client: &ClientInfo) -> Result<Vec<WorkerInfo>, Status> {
         let lock = self.state.read().await;
-        lock.peers
+        lock.clients
             .get(client)
-            .ok_or_else(|| {
-                Status::failed_precondition(format!("Client info {:?} not registered.", client))
-            })
-            .map(|x| x.clone())
+            .ok_or_else(|| not_registered(client))
+            .map(|x| x.shards.clone())
     }
 
-    pub async fn register_client(&self, client: &ClientInfo, shards: Vec<WorkerInfo>) {
+    /// Register `client`, returning the session token it must present on upload.
+    ///
+    /// A client may only register once; otherwise, anybody could take over
+    /// another client's session by re-registering under its ID.
+    pub async fn register_client(
+        &self,
+        client: &ClientInfo,
+        shards: Vec<WorkerInfo>,
+    ) -> Result<SessionToken, Status> {
         trace!("Registering client {:?}; shards: {:?}", &client, shards);
         let mut lock = self.state.write().await;
-        if lock.peers.insert(client.clone(), shards).is_some() {
-            warn!("Client registered twice: {:?}", &client);
+        if lock.clients.contains_key(client) {
+            return Err(Status::already_exists(format!(
+                "Client {:?} registered twice.",
+                client
+            )));
+        }
+        let token: SessionToken = (0..SESSION_TOKEN_BYTES).map(|_| rand::random()).collect();
+        let registration = Registration {
+            shards,
+            token: token.clone(),
+        };
+        lock.clients.insert(client.clone(), registration);
+        match client.role {
+            ClientRole::Viewer => lock.roles.viewers += 1,
+            ClientRole::Broadcaster => lock.roles.broadcasters += 1,
+            ClientRole::Unknown => lock.roles.unknown += 1,
         }
+        Ok(token)
+    }
+
+    /// Check that `token` is the session token issued to `client`.
+    ///
+    /// In constant time (in the token contents), so that timing doesn't give
+    /// away how much of a guess was right.
+    pub async fn check_token(&self, client: &ClientInfo, token: &[u8]) -> Result<(), Status> {
+        let lock = self.state.read().await;
+        let registration = lock
+            .clients
+            .get(client)
+            .ok_or_else(|| not_registered(client))?;
+        if !bool::from(registration.token.as_slice().ct_eq(token)) {
+            return Err(Status::permission_denied(format!(
+                "Bad session token for client {:?}.",
+                client
+            )));
+        }
+        Ok(())
     }
 
     pub async fn num_clients(&self) -> usize {
-        // TODO(zjn): do something less heavy-weight then getting all the peers
         let lock = self.state.read().await;
-        lock.peers.len()
+        lock.clients.len()
+    }
+
+    pub async fn role_counts(&self) -> RoleCounts {
+        self.state.read().await.roles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::Group;
+    use futures::executor::block_on;
+    use tonic::Code;
+
+    fn shards() -> Vec<WorkerInfo> {
+        vec![WorkerInfo::new(Group::new(0), 0)]
+    }
+
+    #[test]
+    fn test_check_token() {
+        let registry = Registry::new();
+        let client = ClientInfo::new(1);
+        block_on(async {
+            let token = registry.register_client(&client, shards()).await.unwrap();
+            registry.check_token(&client, &token).await.unwrap();
+            assert_eq!(registry.get_peers(&client).await.unwrap(), shards());
+        });
+    }
+
+    #[test]
+    fn test_check_token_mismatch() {
+        let registry = Registry::new();
+        let alice = ClientInfo::new(1);
+        let mallory = ClientInfo::new(2);
+        block_on(async {
+            let alice_token = registry.register_client(&alice, shards()).await.unwrap();
+            let mallory_token = registry.register_client(&mallory, shards()).await.unwrap();
+            assert_ne!(alice_token, mallory_token);
+
+            let err = registry
+                .check_token(&alice, &mallory_token)
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), Code::PermissionDenied);
+            let err = registry.check_token(&alice, &[]).await.unwrap_err();
+            assert_eq!(err.code(), Code::PermissionDenied);
+        });
+    }
+
+    #[test]
+    fn test_check_token_unregistered() {
+        let registry = Registry::new();
+        let err = block_on(registry.check_token(&ClientInfo::new(1), &[])).unwrap_err();
+        assert_eq!(err.code(), Code::FailedPrecondition);
+    }
+
+    #[test]
+    fn test_register_twice() {
+        let registry = Registry::new();
+        let client = ClientInfo::new(1);
+        block_on(async {
+            let token = registry.register_client(&client, shards()).await.unwrap();
+            let err = registry.register_client(&client, vec![]).await.unwrap_err();
+            assert_eq!(err.code(), Code::AlreadyExists);
+            // The original session is untouched.
+            registry.check_token(&client, &token).await.unwrap();
+            assert_eq!(registry.get_peers(&client).await.unwrap(), shards());
+        });
+    }
+
+    #[test]
+    fn test_role_counts() {
+        let registry = Registry::new();
+        let mut broadcaster = ClientInfo::new(2);
+        broadcaster.role = ClientRole::Broadcaster;
+        let mut unknown = ClientInfo::new(3);
+        unknown.role = ClientRole::Unknown;
+        block_on(async {
+            for client in &[ClientInfo::new(1), broadcaster.clone(), unknown] {
+                registry.register_client(client, shards()).await.unwrap();
+            }
+            // Rejected registrations don't count.
+            registry
+                .register_client(&broadcaster, shards())
+                .await
+                .unwrap_err();
+            assert_eq!(
+                registry.role_counts().await,
+                RoleCounts {
+                    viewers: 1,
+                    broadcasters: 1,
+                    unknown: 1,
+                }
+            );
+        });
     }
 }
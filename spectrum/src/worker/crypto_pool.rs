@@ -0,0 +1,475 @@
+//! Fair scheduling of the worker's CPU-heavy jobs.
+//!
+//! Workers do two kinds of expensive work on the blocking thread pool:
+//! generating and checking audits, and evaluating write tokens into the
+//! accumulator. Accumulation jobs can run long, and under load they crowd out
+//! audits; peers wait on our audit shares, so that shows up as *their* verify
+//! latency.
+//!
+//! The pool runs at most `slots` jobs at a time, with a queue for each kind of
+//! job. When a slot frees up, it goes to the waiting kind that has used the
+//! least run time relative to its weight, so each kind gets its weighted share
+//! of the slots over time. Jobs aren't preempted: a long job runs to the end,
+//! but its kind pays for the time (once it finishes).
+use crate::rt::{blocking::Mutex, spawn_blocking, sync::oneshot, JoinError};
+use crate::Error;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const KINDS: usize = 2;
+
+/// The kinds of job the pool schedules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    /// Generating or checking an audit.
+    Audit,
+    /// Evaluating a write token into the accumulator.
+    Accumulate,
+}
+
+impl JobKind {
+    fn idx(self) -> usize {
+        match self {
+            JobKind::Audit => 0,
+            JobKind::Accumulate => 1,
+        }
+    }
+
+    fn from_idx(idx: usize) -> Self {
+        match idx {
+            0 => JobKind::Audit,
+            1 => JobKind::Accumulate,
+            _ => panic!("No job kind {}.", idx),
+        }
+    }
+}
+
+impl fmt::Display for JobKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobKind::Audit => write!(f, "audit"),
+            JobKind::Accumulate => write!(f, "accumulate"),
+        }
+    }
+}
+
+/// How many jobs the pool runs at once, and how it splits them between kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub slots: usize,
+    pub audit_weight: u32,
+    pub accumulate_weight: u32,
+}
+
+/// One slot per CPU (as far as we can tell).
+fn default_slots() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            slots: default_slots(),
+            audit_weight: 1,
+            accumulate_weight: 1,
+        }
+    }
+}
+
+/// Parses comma-separated `<key>=<n>` pairs (`slots`, `audit`, and
+/// `accumulate`; all positive), e.g. `slots=8,audit=3,accumulate=1`. Missing
+/// keys keep their defaults (one slot per CPU, and weights of 1).
+impl FromStr for PoolConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = PoolConfig::default();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let bad = || {
+                Error::new(&format!(
+                    "Bad crypto pool setting [{}]; expected slots, audit, or accumulate=<n> (n > 0).",
+                    pair
+                ))
+            };
+            let (key, value) = pair.split_once('=').ok_or_else(bad)?;
+            let value: u32 = value.trim().parse().map_err(|_| bad())?;
+            if value == 0 {
+                return Err(bad());
+            }
+            match key.trim() {
+                "slots" => config.slots = value as usize,
+                "audit" => config.audit_weight = value,
+                "accumulate" => config.accumulate_weight = value,
+                _ => return Err(bad()),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for PoolConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "slots={},audit={},accumulate={}",
+            self.slots, self.audit_weight, self.accumulate_weight
+        )
+    }
+}
+
+/// How long one kind's jobs waited for a slot.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueWaits {
+    pub jobs: u64,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+impl QueueWaits {
+    fn observe(&mut self, wait: Duration) {
+        self.jobs += 1;
+        self.total_wait += wait;
+        self.max_wait = self.max_wait.max(wait);
+    }
+
+    pub fn mean_wait(&self) -> Duration {
+        if self.jobs == 0 {
+            return Duration::default();
+        }
+        self.total_wait / self.jobs as u32
+    }
+}
+
+impl fmt::Display for QueueWaits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} jobs, mean wait {:?}, max wait {:?}",
+            self.jobs,
+            self.mean_wait(),
+            self.max_wait
+        )
+    }
+}
+
+struct Waiter {
+    queued: Instant,
+    tx: oneshot::Sender<Slot>,
+}
+
+struct Scheduler {
+    slots: usize,
+    weights: [u32; KINDS],
+    // Run time charged to each kind, divided by its weight (in nanoseconds).
+    virtual_time: [u128; KINDS],
+    running: [usize; KINDS],
+    queues: [VecDeque<Waiter>; KINDS],
+    waits: [QueueWaits; KINDS],
+}
+
+impl Scheduler {
+    fn new(config: PoolConfig) -> Self {
+        Scheduler {
+            slots: config.slots,
+            weights: [config.audit_weight, config.accumulate_weight],
+            virtual_time: Default::default(),
+            running: Default::default(),
+            queues: Default::default(),
+            waits: Default::default(),
+        }
+    }
+
+    fn is_idle(&self, kind: JobKind) -> bool {
+        self.running[kind.idx()] == 0 && self.queues[kind.idx()].is_empty()
+    }
+
+    fn has_free_slot(&self) -> bool {
+        self.running.iter().sum::<usize>() < self.slots
+    }
+
+    /// A kind coming back from idle doesn't get to spend the time it saved up
+    /// while idle: it starts level with the busy kinds.
+    fn wake(&mut self, kind: JobKind) {
+        if !self.is_idle(kind) {
+            return;
+        }
+        let busy = (0..KINDS)
+            .filter(|&idx| idx != kind.idx() && !self.is_idle(JobKind::from_idx(idx)))
+            .map(|idx| self.virtual_time[idx])
+            .min();
+        if let Some(busy) = busy {
+            let time = &mut self.virtual_time[kind.idx()];
+            *time = (*time).max(busy);
+        }
+    }
+
+    /// The waiting kind with the least (weighted) run time so far; ties go to
+    /// audits.
+    fn next_kind(&self) -> Option<JobKind> {
+        (0..KINDS)
+            .filter(|&idx| !self.queues[idx].is_empty())
+            .min_by_key(|&idx| self.virtual_time[idx])
+            .map(JobKind::from_idx)
+    }
+
+    fn charge(&mut self, kind: JobKind, elapsed: Duration) {
+        let idx = kind.idx();
+        self.virtual_time[idx] += elapsed.as_nanos() / self.weights[idx] as u128;
+    }
+}
+
+/// Hand free slots to waiters.
+fn dispatch(pool: &Arc<Mutex<Scheduler>>, scheduler: &mut Scheduler) {
+    while scheduler.has_free_slot() {
+        let kind = match scheduler.next_kind() {
+            Some(kind) => kind,
+            None => return,
+        };
+        let waiter = scheduler.queues[kind.idx()]
+            .pop_front()
+            .expect("Next kind should have a waiter.");
+        scheduler.running[kind.idx()] += 1;
+        match waiter.tx.send(Slot::new(pool.clone(), kind)) {
+            Ok(()) => scheduler.waits[kind.idx()].observe(waiter.queued.elapsed()),
+            Err(mut slot) => {
+                // The waiter gave up; we're already holding the lock.
+                slot.armed = false;
+                scheduler.running[kind.idx()] -= 1;
+            }
+        }
+    }
+}
+
+/// Permission to run one job; frees the slot (and charges for the time) when
+/// dropped.
+struct Slot {
+    pool: Arc<Mutex<Scheduler>>,
+    kind: JobKind,
+    started: Instant,
+    armed: bool,
+}
+
+impl Slot {
+    fn new(pool: Arc<Mutex<Scheduler>>, kind: JobKind) -> Self {
+        Slot {
+            pool,
+            kind,
+            started: Instant::now(),
+            armed: true,
+        }
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let mut scheduler = self.pool.lock().unwrap();
+        scheduler.charge(self.kind, self.started.elapsed());
+        scheduler.running[self.kind.idx()] -= 1;
+        dispatch(&self.pool, &mut scheduler);
+    }
+}
+
+/// Runs blocking jobs, sharing a fixed number of slots fairly between kinds.
+#[derive(Clone)]
+pub struct CryptoPool {
+    scheduler: Arc<Mutex<Scheduler>>,
+}
+
+impl CryptoPool {
+    pub fn new(config: PoolConfig) -> Self {
+        CryptoPool {
+            scheduler: Arc::new(Mutex::new(Scheduler::new(config))),
+        }
+    }
+
+    async fn slot(&self, kind: JobKind) -> Slot {
+        let rx = {
+            let mut scheduler = self.scheduler.lock().unwrap();
+            scheduler.wake(kind);
+            // Don't jump the queue.
+            if scheduler.has_free_slot() && scheduler.next_kind().is_none() {
+                scheduler.running[kind.idx()] += 1;
+                scheduler.waits[kind.idx()].observe(Duration::default());
+                return Slot::new(self.scheduler.clone(), kind);
+            }
+            let (tx, rx) = oneshot::channel();
+            scheduler.queues[kind.idx()].push_back(Waiter {
+                queued: Instant::now(),
+                tx,
+            });
+            rx
+        };
+        rx.await
+            .expect("Waiters are only dropped after their receivers.")
+    }
+
+    /// Run `f` on the blocking thread pool, once it gets a slot.
+    ///
+    /// The slot stays taken until `f` returns, even if the returned future is
+    /// dropped first.
+    pub async fn run<F, T>(&self, kind: JobKind, f: F) -> Result<T, JoinError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let slot = self.slot(kind).await;
+        spawn_blocking(move || {
+            let _slot = slot;
+            f()
+        })
+        .await
+    }
+
+    /// How long `kind`'s jobs have waited for slots (since the last reset).
+    pub fn waits(&self, kind: JobKind) -> QueueWaits {
+        self.scheduler.lock().unwrap().waits[kind.idx()]
+    }
+
+    pub fn reset_waits(&self) {
+        self.scheduler.lock().unwrap().waits = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::{sleep, spawn};
+    use futures::FutureExt;
+
+    #[test]
+    fn test_config_from_str() {
+        assert_eq!("".parse::<PoolConfig>().unwrap(), PoolConfig::default());
+        let config: PoolConfig = "slots=8,audit=3,accumulate=1".parse().unwrap();
+        assert_eq!(
+            config,
+            PoolConfig {
+                slots: 8,
+                audit_weight: 3,
+                accumulate_weight: 1,
+            }
+        );
+        assert_eq!(config.to_string().parse::<PoolConfig>().unwrap(), config);
+        assert_eq!(
+            "audit=2".parse::<PoolConfig>().unwrap().slots,
+            default_slots()
+        );
+
+        for bad in &["slots", "slots=0", "audit=-1", "accumulate=x", "other=1"] {
+            assert!(bad.parse::<PoolConfig>().is_err(), "{}", bad);
+        }
+    }
+
+    // Runs jobs that each take `job_time`, in the scheduler's order.
+    fn schedule(config: PoolConfig, jobs: usize, job_time: Duration) -> Vec<JobKind> {
+        let mut scheduler = Scheduler::new(config);
+        let mut receivers = vec![];
+        for idx in 0..KINDS {
+            for _ in 0..jobs {
+                let (tx, rx) = oneshot::channel();
+                receivers.push(rx);
+                let queued = Instant::now();
+                scheduler.queues[idx].push_back(Waiter { queued, tx });
+            }
+        }
+        let mut order = vec![];
+        while let Some(kind) = scheduler.next_kind() {
+            scheduler.queues[kind.idx()].pop_front();
+            scheduler.charge(kind, job_time);
+            order.push(kind);
+        }
+        order
+    }
+
+    #[test]
+    fn test_weighted_order() {
+        let config = PoolConfig {
+            slots: 1,
+            audit_weight: 3,
+            accumulate_weight: 1,
+        };
+        let order = schedule(config, 8, Duration::from_millis(10));
+        let audits = order[..8]
+            .iter()
+            .filter(|&&kind| kind == JobKind::Audit)
+            .count();
+        assert_eq!(audits, 6);
+
+        let order = schedule(PoolConfig::default(), 4, Duration::from_millis(10));
+        use JobKind::*;
+        assert_eq!(
+            order,
+            vec![Audit, Accumulate, Audit, Accumulate, Audit, Accumulate, Audit, Accumulate]
+        );
+    }
+
+    #[test]
+    fn test_wake_after_idle() {
+        let mut scheduler = Scheduler::new(PoolConfig::default());
+        scheduler.running[JobKind::Audit.idx()] = 1;
+        scheduler.charge(JobKind::Audit, Duration::from_secs(1));
+        // Accumulation was idle the whole time: it doesn't get a second of
+        // credit.
+        scheduler.wake(JobKind::Accumulate);
+        assert_eq!(scheduler.virtual_time[0], scheduler.virtual_time[1]);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let pool = CryptoPool::new(PoolConfig::default());
+        assert_eq!(pool.run(JobKind::Audit, || 1 + 1).await.unwrap(), 2);
+        assert!(pool
+            .run(JobKind::Accumulate, || panic!("bad job"))
+            .await
+            .is_err());
+        // The panicking job gave its slot back.
+        assert_eq!(pool.run(JobKind::Accumulate, || 3).await.unwrap(), 3);
+        assert_eq!(pool.waits(JobKind::Audit).jobs, 1);
+        assert_eq!(pool.waits(JobKind::Accumulate).jobs, 2);
+    }
+
+    #[tokio::test]
+    async fn test_waits() {
+        let config = PoolConfig {
+            slots: 1,
+            ..Default::default()
+        };
+        let pool = CryptoPool::new(config);
+        let slot = pool.slot(JobKind::Audit).await;
+        let job = {
+            let pool = pool.clone();
+            spawn(async move { pool.run(JobKind::Accumulate, || ()).await })
+        };
+        sleep(Duration::from_millis(20)).await;
+        drop(slot);
+        job.await.unwrap().unwrap();
+
+        let waits = pool.waits(JobKind::Accumulate);
+        assert_eq!(waits.jobs, 1);
+        assert!(waits.max_wait >= Duration::from_millis(20));
+        pool.reset_waits();
+        assert_eq!(pool.waits(JobKind::Accumulate), QueueWaits::default());
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_waiter() {
+        let config = PoolConfig {
+            slots: 1,
+            ..Default::default()
+        };
+        let pool = CryptoPool::new(config);
+        let slot = pool.slot(JobKind::Audit).await;
+        // Queues up, then gives up.
+        assert!(pool.slot(JobKind::Audit).now_or_never().is_none());
+        drop(slot);
+        // The abandoned waiter didn't keep the slot.
+        assert_eq!(pool.run(JobKind::Audit, || 1).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::rt::sync::watch;
+use crate::services::abort::CancellationToken;
+use crate::Error;
+
+use futures::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tonic::Status;
+
+const DEFAULT_HOLD_CAP: usize = 1024;
+
+/// What a worker does with an upload that arrives before the round starts.
+///
+/// Until then, the worker can't reach its peers to audit the upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EarlyUploadPolicy {
+    /// Turn the upload away (the client can retry).
+    #[default]
+    Reject,
+    /// Accept the upload, and process it once the round starts. At most this
+    /// many are held at a time.
+    Hold(usize),
+}
+
+/// Parses `reject`, `hold`, or `hold:<max>`.
+impl FromStr for EarlyUploadPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(EarlyUploadPolicy::Reject),
+            "hold" => Ok(EarlyUploadPolicy::Hold(DEFAULT_HOLD_CAP)),
+            _ => match s.strip_prefix("hold:").map(str::parse) {
+                Some(Ok(max)) if max > 0 => Ok(EarlyUploadPolicy::Hold(max)),
+                _ => Err(Error::new(&format!(
+                    "Bad early upload policy [{}]; expected reject or hold[:<max>].",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+impl fmt::Display for EarlyUploadPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EarlyUploadPolicy::Reject => write!(f, "reject"),
+            EarlyUploadPolicy::Hold(max) => write!(f, "hold:{}", max),
+        }
+    }
+}
+
+/// Counts the uploads waiting for the round to start.
+#[derive(Debug, Default)]
+pub struct HeldUploads {
+    held: AtomicUsize,
+    // Most held at once, to size the cap by.
+    peak: AtomicUsize,
+}
+
+impl HeldUploads {
+    /// Take a slot for an early upload, if `policy` allows it.
+    ///
+    /// Call `release()` once the upload stops waiting.
+    pub fn hold(&self, policy: EarlyUploadPolicy) -> Result<(), Status> {
+        let max = match policy {
+            EarlyUploadPolicy::Reject => {
+                return Err(Status::unavailable("Round hasn't started yet."));
+            }
+            EarlyUploadPolicy::Hold(max) => max,
+        };
+        let held = self
+            .held
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |held| {
+                (held < max).then(|| held + 1)
+            })
+            .map_err(|_| {
+                Status::resource_exhausted(format!(
+                    "Already holding {} uploads for the start of the round.",
+                    max
+                ))
+            })?;
+        self.peak.fetch_max(held + 1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn release(&self) {
+        self.held.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// How many uploads are waiting right now.
+    pub fn held(&self) -> usize {
+        self.held.load(Ordering::SeqCst)
+    }
+
+    /// The most uploads that were waiting at once.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+}
+
+/// Wait until the round starts (returning false if it's aborted first, or the
+/// worker shuts down).
+pub async fn wait_for_start(
+    mut start_rx: watch::Receiver<Option<Instant>>,
+    cancel: CancellationToken,
+) -> bool {
+    let started = async move {
+        while start_rx.borrow().is_none() {
+            if start_rx.changed().await.is_err() {
+                return false;
+            }
+        }
+        true
+    };
+    let cancelled = async move {
+        cancel.cancelled().await;
+        false
+    };
+    future::select(Box::pin(started), Box::pin(cancelled))
+        .await
+        .factor_first()
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::spawn;
+    use crate::services::abort::AbortNotice;
+
+    #[test]
+    fn test_parse_policy() {
+        for policy in &["reject", "hold:7"] {
+            let parsed: EarlyUploadPolicy = policy.parse().unwrap();
+            assert_eq!(&parsed.to_string(), policy);
+        }
+        assert_eq!(
+            "hold".parse::<EarlyUploadPolicy>().unwrap(),
+            EarlyUploadPolicy::Hold(DEFAULT_HOLD_CAP)
+        );
+        "hold:0"
+            .parse::<EarlyUploadPolicy>()
+            .expect_err("cap must be positive");
+        "hold:lots"
+            .parse::<EarlyUploadPolicy>()
+            .expect_err("not a number");
+        "queue"
+            .parse::<EarlyUploadPolicy>()
+            .expect_err("not a policy");
+    }
+
+    #[test]
+    fn test_hold_cap() {
+        let held = HeldUploads::default();
+        held.hold(EarlyUploadPolicy::Reject)
+            .expect_err("reject holds nothing");
+        let policy = EarlyUploadPolicy::Hold(2);
+        held.hold(policy).unwrap();
+        held.hold(policy).unwrap();
+        let status = held.hold(policy).expect_err("over the cap");
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(held.held(), 2);
+
+        held.release();
+        held.hold(policy).unwrap();
+        held.release();
+        held.release();
+        assert_eq!(held.held(), 0);
+        assert_eq!(held.peak(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_start() {
+        let (start_tx, start_rx) = watch::channel(None);
+        let waiting = spawn(wait_for_start(start_rx.clone(), Default::default()));
+        start_tx.send(Some(Instant::now())).unwrap();
+        assert!(waiting.await.unwrap());
+
+        // Already started.
+        assert!(wait_for_start(start_rx, Default::default()).await);
+
+        let (_start_tx, start_rx) = watch::channel(None);
+        let cancel = CancellationToken::default();
+        let waiting = spawn(wait_for_start(start_rx, cancel.clone()));
+        cancel.cancel(AbortNotice {
+            round: 0,
+            reason: "test".to_string(),
+        });
+        assert!(!waiting.await.unwrap());
+    }
+}
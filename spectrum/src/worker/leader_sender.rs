@@ -0,0 +1,252 @@
+//! Delivery of this worker's aggregate share to its leader.
+//!
+//! A worker only talks to its leader once per round, at the very end, so a
+//! leader restart in the middle of the round shouldn't cost the round. The
+//! sender holds on to the share, reconnecting (with backoff) and resending
+//! until the leader acknowledges it; leaders drop duplicates by (round,
+//! worker), so resending after a lost acknowledgement is harmless.
+use crate::net::{client::Builder, tls::Certificate, ClientChannel};
+use crate::proto::{
+    leader_client::LeaderClient, AggregateWorkerRequest, AuditFailures, Share, StageTallies,
+};
+use crate::rt::{sleep, sync::Mutex};
+use crate::services::{deadline, LeaderInfo, WorkerInfo};
+
+use log::{debug, warn};
+use std::cmp::min;
+use std::time::{Duration, Instant};
+use tonic::Status;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+pub struct LeaderSender {
+    builder: Builder,
+    worker: WorkerInfo,
+    // Connected lazily, and dropped on any error so that the next attempt
+    // reconnects.
+    client: Mutex<Option<LeaderClient<ClientChannel>>>,
+}
+
+impl LeaderSender {
+    pub fn new(worker: WorkerInfo, uri: String, tls: Option<Certificate>) -> Self {
+        LeaderSender {
+            builder: Builder::new(uri).tls(tls),
+            worker,
+            client: Default::default(),
+        }
+    }
+
+    async fn try_send(
+        &self,
+        request: &AggregateWorkerRequest,
+        timeout: Duration,
+        deadline: Instant,
+    ) -> Result<(), Status> {
+        let mut client = self.client.lock().await;
+        if client.is_none() {
+            debug!("Connecting to leader at {}.", self.builder.uri());
+            let leader = LeaderInfo::new(self.worker.group);
+            let connected = self
+                .builder
+                .leader_client(self.worker.into(), leader.into())
+                .await
+                .map_err(|err| Status::unavailable(err.to_string()))?;
+            *client = Some(connected);
+        }
+        let request = deadline::request(request.clone(), timeout, Some(deadline));
+        let result = client
+            .as_mut()
+            .expect("just connected")
+            .aggregate_worker(request)
+            .await;
+        if result.is_err() {
+            *client = None;
+        }
+        result.map(|_| ())
+    }
+
+    /// Send this worker's share for `round`, retrying until the leader
+    /// acknowledges it or `timeout` elapses.
+    pub async fn send(
+        &self,
+        round: u64,
+        share: Share,
+        audit_failures: AuditFailures,
+        participants: u64,
+        stage_tallies: StageTallies,
+        timeout: Duration,
+    ) -> Result<(), Status> {
+        let request = AggregateWorkerRequest {
+            share: Some(share),
+            audit_failures: Some(audit_failures),
+            worker_id: Some(self.worker.into()),
+            round,
+            participants,
+            stage_tallies: Some(stage_tallies),
+        };
+        let deadline = Instant::now() + timeout;
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let err = match self.try_send(&request, timeout, deadline).await {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Err(Status::deadline_exceeded(format!(
+                    "Couldn't send share to leader: {}",
+                    err
+                )));
+            }
+            warn!(
+                "Failed to send share to leader (retrying in {:?}): {}",
+                backoff, err
+            );
+            sleep(min(backoff, remaining)).await;
+            backoff = min(backoff * 2, MAX_BACKOFF);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{
+        leader_server::{Leader, LeaderServer},
+        AggregateWorkerResponse,
+    };
+    use crate::rt::{spawn, sync::Notify, JoinHandle, TcpListener, TcpListenerStream};
+    use crate::services::Group;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tonic::{Request, Response};
+
+    #[derive(Clone, Default)]
+    struct FakeLeader(Arc<Mutex<Vec<AggregateWorkerRequest>>>);
+
+    #[tonic::async_trait]
+    impl Leader for FakeLeader {
+        async fn aggregate_worker(
+            &self,
+            request: Request<AggregateWorkerRequest>,
+        ) -> Result<Response<AggregateWorkerResponse>, Status> {
+            self.0.lock().await.push(request.into_inner());
+            Ok(Response::new(AggregateWorkerResponse {}))
+        }
+    }
+
+    struct RunningLeader {
+        received: FakeLeader,
+        shutdown: Arc<Notify>,
+        task: JoinHandle<Result<(), tonic::transport::Error>>,
+    }
+
+    impl RunningLeader {
+        async fn start(addr: SocketAddr) -> Self {
+            let listener = TcpListener::bind(addr).await.unwrap();
+            let received = FakeLeader::default();
+            let shutdown = Arc::new(Notify::new());
+            let notified = shutdown.clone();
+            let task = spawn(
+                tonic::transport::server::Server::builder()
+                    .add_service(LeaderServer::new(received.clone()))
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+                        notified.notified().await
+                    }),
+            );
+            RunningLeader {
+                received,
+                shutdown,
+                task,
+            }
+        }
+
+        async fn kill(self) -> Vec<AggregateWorkerRequest> {
+            self.shutdown.notify_one();
+            self.task.await.unwrap().unwrap();
+            self.received.0.lock().await.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_survives_leader_restart() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let worker = WorkerInfo::new(Group::new(0), 1);
+        let sender = Arc::new(LeaderSender::new(worker, format!("http://{}", addr), None));
+        let share = Share {
+            data: vec![vec![1, 2, 3]],
+        };
+        let timeout = Duration::from_secs(10);
+
+        // Connect once so the sender holds a (soon-to-be stale) client.
+        let leader = RunningLeader::start(addr).await;
+        sender
+            .send(
+                0,
+                share.clone(),
+                AuditFailures::default(),
+                1,
+                StageTallies::default(),
+                timeout,
+            )
+            .await
+            .unwrap();
+        assert_eq!(leader.kill().await.len(), 1);
+
+        // The leader is down when the worker finishes verifying...
+        let send = spawn({
+            let sender = sender.clone();
+            let share = share.clone();
+            async move {
+                let tallies = StageTallies {
+                    upload: Some(Default::default()),
+                    ..Default::default()
+                };
+                sender
+                    .send(1, share, AuditFailures::default(), 3, tallies, timeout)
+                    .await
+            }
+        });
+        sleep(Duration::from_millis(500)).await;
+
+        // ...and comes back before the worker gives up.
+        let leader = RunningLeader::start(addr).await;
+        send.await.unwrap().unwrap();
+        let received = leader.kill().await;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].share, Some(share));
+        assert_eq!(received[0].worker_id, Some(worker.into()));
+        assert_eq!(received[0].round, 1);
+        assert_eq!(received[0].participants, 3);
+        assert!(received[0].stage_tallies.as_ref().unwrap().upload.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_send_gives_up() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let worker = WorkerInfo::new(Group::new(0), 0);
+        let sender = LeaderSender::new(worker, format!("http://{}", addr), None);
+        let share = Share { data: vec![] };
+        let status = sender
+            .send(
+                0,
+                share,
+                AuditFailures::default(),
+                0,
+                StageTallies::default(),
+                Duration::from_millis(300),
+            )
+            .await
+            .expect_err("No leader to send to.");
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+    }
+}
@@ -1,16 +1,23 @@
 use crate::{
     accumulator::Accumulator,
     config::store::Store,
-    experiment::Experiment,
-    net::Config as NetConfig,
+    experiment::{Experiment, HammerConfig},
+    net::{shm, systemd, tls, Config as NetConfig},
     protocols::{
         wrapper::{ChannelKeyWrapper, ProtocolWrapper},
         Protocol,
     },
     services::{
-        discovery::{register, Node},
+        abort::{watch_for_abort, CancellationToken},
+        admin::{AdminServer, LogAdmin},
+        budget::StageTallies,
+        deadline::{self, Deadlines},
+        discovery::{register, Discovery, Node},
         health::{wait_for_health, AllGoodHealthServer, HealthServer},
+        privacy,
         quorum::wait_for_start_time_set,
+        registration::{get_window, Window, WindowState},
+        revocation::{self, watch_revocations, Revocation},
         ClientInfo, WorkerInfo,
     },
 };
@@ -18,46 +25,89 @@ use crate::{
     proto::{
         self, expect_field,
         worker_server::{Worker, WorkerServer},
-        AggregateWorkerRequest, RegisterClientRequest, RegisterClientResponse, Share,
-        UploadRequest, UploadResponse, VerifyRequest, VerifyResponse,
+        AuditFailures, RegisterClientRequest, RegisterClientResponse, RegistrationWindowRequest,
+        RegistrationWindowResponse, ReportStatsRequest, Share, UploadRequest, UploadResponse,
+        VerifyRequest, VerifyResponse,
     },
     services::quorum::delay_until,
 };
-use std::time::Instant;
+use chrono::prelude::*;
+use prost::Message;
+use std::time::{Duration, Instant};
 
+use crate::rt::{
+    sleep, sleep_until, spawn,
+    sync::{watch, Mutex, Notify},
+};
 use futures::prelude::*;
 use log::{debug, error, info, trace, warn};
+use rand::thread_rng;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::{
-    spawn,
-    sync::{watch, Mutex, RwLock},
-    task::spawn_blocking,
-    time::sleep,
-};
-use tonic::{transport::ServerTlsConfig, Request, Response, Status};
+use tonic::{Request, Response, Status};
 
+mod audit_batch;
+mod audit_log;
+mod audit_policy;
 mod audit_registry;
+mod audit_slot;
 mod client_registry;
+mod crypto_pool;
+mod early_uploads;
+mod leader_sender;
 mod service_registry;
+mod start_gate;
+mod token_seal;
+
+pub use audit_log::{AuditEvent, AuditOutcome, AuditSink};
+pub use audit_policy::AuditFailurePolicy;
+pub use crypto_pool::PoolConfig as CryptoPoolConfig;
+pub use early_uploads::EarlyUploadPolicy;
 
-use audit_registry::AuditRegistry;
-use client_registry::Registry as ClientRegistry;
+use audit_batch::AuditBatcher;
+use audit_log::AuditLog;
+use audit_registry::{AuditRegistry, Progress, TokenCodec};
+use client_registry::{Registry as ClientRegistry, SessionToken};
+use crypto_pool::{CryptoPool, JobKind};
+use early_uploads::{wait_for_start, HeldUploads};
 use service_registry::{Registry as ServiceRegistry, SharedClient};
+use start_gate::StartGate;
 
 type Error = crate::config::store::Error;
 type BoxedError = Box<dyn std::error::Error + Sync + Send>;
 
+const REGISTRATION_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const SHM_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// Experiments currently run a single round.
+const ROUND: u64 = 0;
+
 struct WorkerState<P: Protocol> {
     // TODO: less heavyweight than a full mutex...
     // Maybe follow the actor model?
     // https://book.async.rs/tutorial/connecting_readers_and_writers.html
     audit_registry: Mutex<AuditRegistry<P::AuditShare, P::WriteToken>>,
     accumulator: Accumulator<Vec<P::Accumulator>>,
+    // Shared fairly between audits and accumulation.
+    crypto_pool: CryptoPool,
+    audit_batcher: AuditBatcher<P>,
     experiment: Experiment,
     client_registry: ClientRegistry,
     protocol: P,
+    on_audit_failure: AuditFailurePolicy,
+    audit_failures: Mutex<AuditFailures>,
+    audit_log: Option<AuditLog>,
+    // Channel keys revoked so far; audits use the replacements.
+    revocations: watch::Receiver<Vec<Revocation>>,
+    // How long our uploads and audits took.
+    stage_tallies: Mutex<StageTallies>,
+    // Clients verified and audit failures in the last finished round.
+    last_round: Mutex<Option<(usize, AuditFailures)>>,
+    // For uploads where we're the first worker; zero means unassigned.
+    next_upload_seq: AtomicU64,
+    cancel: CancellationToken,
 }
 
 impl<P> WorkerState<P>
@@ -65,108 +115,237 @@ where
     P: Protocol,
     P::Accumulator: Clone,
 {
-    fn from_experiment(experiment: Experiment, protocol: P) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    fn from_experiment(
+        experiment: Experiment,
+        protocol: P,
+        on_audit_failure: AuditFailurePolicy,
+        audit_log: Option<AuditLog>,
+        revocations: watch::Receiver<Vec<Revocation>>,
+        token_codec: Option<TokenCodec<P::WriteToken>>,
+        crypto_pool: CryptoPoolConfig,
+        cancel: CancellationToken,
+    ) -> Self
+    where
+        P: Clone,
+    {
+        let mut audit_registry = AuditRegistry::new(experiment.clients(), protocol.num_parties());
+        if let Some(codec) = token_codec {
+            audit_registry = audit_registry.with_sealing(codec);
+        }
+        // One partial accumulator per slot, so evaluations never wait on each
+        // other (just on the pool).
+        let slots = crypto_pool.slots;
+        let accumulator = Accumulator::with_partials(protocol.new_accumulator(), slots);
+        let crypto_pool = CryptoPool::new(crypto_pool);
         WorkerState {
-            audit_registry: Mutex::new(AuditRegistry::new(
-                experiment.clients(),
-                experiment.groups(),
-            )),
-            accumulator: Accumulator::new(protocol.new_accumulator()),
+            audit_registry: Mutex::new(audit_registry),
+            accumulator,
+            // A batch per slot, so audit checks can use all of them.
+            audit_batcher: AuditBatcher::new(protocol.clone(), crypto_pool.clone(), slots),
+            crypto_pool,
             experiment,
             client_registry: ClientRegistry::new(),
             protocol,
+            on_audit_failure,
+            audit_failures: Default::default(),
+            audit_log,
+            revocations,
+            stage_tallies: Default::default(),
+            last_round: Default::default(),
+            next_upload_seq: AtomicU64::new(1),
+            cancel,
         }
     }
 
-    fn hammer(&self) -> bool {
-        self.experiment.hammer
+    fn hammer(&self) -> Option<HammerConfig> {
+        self.experiment.hammer()
+    }
+
+    fn check_not_aborted(&self) -> Result<(), Error> {
+        if self.cancel.is_cancelled() {
+            return Err(Error::new("Round aborted."));
+        }
+        Ok(())
+    }
+
+    /// The sequence number for an upload: `seq` if the client has one
+    /// (from the first worker it uploaded to), else a fresh one.
+    fn upload_seq(&self, seq: u64) -> u64 {
+        if seq != 0 {
+            return seq;
+        }
+        self.next_upload_seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Reclaim per-round state so that another round can reuse this worker.
+    ///
+    /// Audits still in progress are abandoned; client registrations carry over.
+    async fn finalize_round(&self) {
+        let abandoned = self.audit_registry.lock().await.clear();
+        if abandoned > 0 {
+            warn!("Abandoned {} audits in progress.", abandoned);
+        }
+        let clients = self.accumulator.count().await;
+        self.accumulator.reset().await;
+        let audit_failures = std::mem::take(&mut *self.audit_failures.lock().await);
+        *self.last_round.lock().await = Some((clients, audit_failures));
+        *self.stage_tallies.lock().await = Default::default();
+        info!(
+            "Crypto pool queues this round: audit {}; accumulate {}.",
+            self.crypto_pool.waits(JobKind::Audit),
+            self.crypto_pool.waits(JobKind::Accumulate)
+        );
+        self.crypto_pool.reset_waits();
+    }
+
+    /// Clients verified and audit failures this round; for the last round
+    /// until this one verifies a client.
+    async fn round_stats(&self) -> (usize, AuditFailures) {
+        let clients = self.accumulator.count().await;
+        if clients == 0 {
+            if let Some(last_round) = self.last_round.lock().await.clone() {
+                return last_round;
+            }
+        }
+        (clients, self.audit_failures.lock().await.clone())
     }
 }
 
 enum VerifyStatus<P: Protocol> {
     AwaitingShares,
-    ShareVerified { clients: usize },
-    AllClientsVerified { accumulator: Vec<P::Accumulator> },
+    ShareVerified {
+        clients: usize,
+    },
+    AllClientsVerified {
+        accumulator: Vec<P::Accumulator>,
+        audit_failures: AuditFailures,
+        /// Clients processed, plus noise if participation privacy is on.
+        participants: u64,
+        stage_tallies: StageTallies,
+    },
 }
 
 impl<P> WorkerState<P>
 where
     P: Protocol + 'static + Sync + Send + Clone,
-    P::WriteToken: Clone + Send + fmt::Debug,
+    P::WriteToken: Clone + Send + fmt::Debug + Into<proto::WriteToken>,
     P::AuditShare: Send + fmt::Debug,
-    P::Accumulator: Send + Clone,
+    P::Accumulator: Sync + Send + Clone,
     P::ChannelKey: TryFrom<ChannelKeyWrapper> + Send,
     <P::ChannelKey as TryFrom<ChannelKeyWrapper>>::Error: fmt::Debug,
 {
-    async fn upload(&self, client: &ClientInfo, write_token: P::WriteToken) -> Vec<P::AuditShare> {
+    async fn upload(
+        &self,
+        client: &ClientInfo,
+        seq: u64,
+        write_token: P::WriteToken,
+    ) -> Result<Vec<P::AuditShare>, Error> {
         trace!("upload() task for client_info: {:?}", client);
+        let started = Instant::now();
         {
             self.audit_registry
                 .lock()
                 .await
-                .init(&client, write_token.clone())
+                .init(client, seq, write_token.clone())
                 .await;
         }
         trace!("init'd for client_info: {:?}", client);
 
         let protocol = self.protocol.clone();
         let keys = self.experiment.get_keys(); // TODO(zjn): move into WorkerState
+        let keys = revocation::apply(&self.revocations.borrow(), keys);
         let keys = keys
             .into_iter()
             .map(TryInto::try_into)
             .collect::<Result<Vec<P::ChannelKey>, _>>()
             .unwrap();
 
-        spawn_blocking(move || protocol.gen_audit(&keys, write_token))
+        self.check_not_aborted()?;
+        let audit_shares = self
+            .crypto_pool
+            .run(JobKind::Audit, move || {
+                protocol.gen_audit(&keys, write_token)
+            })
+            .await
+            .expect("Generating audit should not panic.");
+        let budget = self.experiment.stage_budgets().upload;
+        self.stage_tallies
+            .lock()
             .await
-            .expect("Generating audit should not panic.")
+            .upload
+            .observe(started.elapsed(), budget);
+        Ok(audit_shares)
     }
 
     async fn verify(
         &self,
         client: &ClientInfo,
+        seq: u64,
         share: P::AuditShare,
     ) -> Result<VerifyStatus<P>, Error> {
         trace!("verify() task for client_info: {:?}", client);
-        let check_count = self.audit_registry.lock().await.add(client, share).await;
-        trace!(
-            "{}/{} shares received for {:?}",
-            check_count,
-            self.protocol.num_parties(),
-            client.clone()
-        );
-        if check_count < self.protocol.num_parties() {
-            return Ok(VerifyStatus::AwaitingShares);
-        }
+        let progress = self
+            .audit_registry
+            .lock()
+            .await
+            .add(client, seq, share)
+            .await;
+        let state = match progress {
+            Progress::Ready(state) => state,
+            Progress::Waiting(check_count) => {
+                trace!(
+                    "{}/{} shares received for {:?}",
+                    check_count,
+                    self.protocol.num_parties(),
+                    client.clone()
+                );
+                return Ok(VerifyStatus::AwaitingShares);
+            }
+            Progress::Taken => {
+                warn!("Dropping extra audit share for {:?}.", client);
+                return Ok(VerifyStatus::AwaitingShares);
+            }
+        };
         trace!("Running verification.");
+        self.check_not_aborted()?;
 
-        let state = self.audit_registry.lock().await.drain(client).await;
-        let protocol = self.protocol.clone();
-        let shares = state.audit_shares;
-        let verify = spawn_blocking(move || protocol.check_audit(shares))
-            .await
-            .unwrap();
-        if !verify {
-            warn!("Didn't verify");
-            // TODO: fix serialization bugs
-            // return None;
-        }
+        let started = state.started;
+        let verify = self.audit_batcher.check(state.audit_shares).await;
+        self.check_not_aborted()?;
+        let token = if verify {
+            Some(state.write_token)
+        } else {
+            self.on_audit_failure(client, state.write_token).await
+        };
 
+        // Evaluate the write token directly into a partial accumulator, rather
+        // than expanding it and combining afterwards. Dropped (and malformed)
+        // writes still count towards the number of clients processed.
         let protocol = self.protocol.clone();
-        let token = state.write_token;
-        let accumulator = spawn_blocking(move || protocol.to_accumulator(token))
+        let client = client.clone();
+        let pool = &self.crypto_pool;
+        let accumulated_clients = self
+            .accumulator
+            .accumulate_on(
+                |job| pool.run(JobKind::Accumulate, job),
+                move |accumulator| {
+                    if let Some(token) = token {
+                        if let Err(err) = protocol.try_accumulate_into(accumulator, token) {
+                            warn!("Dropping malformed write token from {:?}: {}", client, err);
+                        }
+                    }
+                },
+            )
             .await
-            .expect("Accepting write token should never fail.");
-
-        if accumulator.len() != self.protocol.num_channels() {
-            return Err(Error::new(&format!(
-                "Invalid number of accumulator channels! {} != {}",
-                accumulator.len(),
-                self.protocol.num_channels()
-            )));
-        }
-        let accumulated_clients = self.accumulator.accumulate(accumulator).await;
-        if self.hammer() {
+            .map_err(|err| Error::new(&format!("Invalid write token: {}", err)))?;
+        let budget = self.experiment.stage_budgets().audit;
+        self.stage_tallies
+            .lock()
+            .await
+            .audit
+            .observe(started.elapsed(), budget);
+        if self.hammer().is_some() {
             return Ok(VerifyStatus::ShareVerified {
                 clients: accumulated_clients,
             });
@@ -178,6 +357,9 @@ where
         if accumulated_clients == total_clients {
             Ok(VerifyStatus::AllClientsVerified {
                 accumulator: self.accumulator.get().await,
+                audit_failures: self.audit_failures.lock().await.clone(),
+                participants: accumulated_clients as u64 + self.count_noise(),
+                stage_tallies: *self.stage_tallies.lock().await,
             })
         } else {
             Ok(VerifyStatus::ShareVerified {
@@ -186,17 +368,99 @@ where
         }
     }
 
-    async fn register_client(&self, client: &ClientInfo, shards: Vec<WorkerInfo>) {
-        self.client_registry.register_client(client, shards).await;
+    // Noise to add to our client count. Only the count changes; there are no
+    // actual noise writes.
+    fn count_noise(&self) -> u64 {
+        self.experiment.participation_privacy().map_or(0, |budget| {
+            privacy::sample_noise(&mut thread_rng(), &budget)
+        })
+    }
+
+    /// Apply the audit failure policy, returning the write token if it should
+    /// be accumulated anyway.
+    ///
+    /// If quarantining the token fails, it's rejected instead: the client
+    /// still has to count towards the round.
+    async fn on_audit_failure(
+        &self,
+        client: &ClientInfo,
+        token: P::WriteToken,
+    ) -> Option<P::WriteToken> {
+        warn!(
+            "Audit failed for client {:?} (policy: {}).",
+            client, self.on_audit_failure
+        );
+        let (outcome, token) = match &self.on_audit_failure {
+            AuditFailurePolicy::Reject => {
+                self.audit_failures.lock().await.rejected += 1;
+                (AuditOutcome::Rejected, None)
+            }
+            AuditFailurePolicy::Quarantine(dir) => {
+                match audit_policy::quarantine(dir.clone(), client, token.into()).await {
+                    Ok(path) => {
+                        info!("Quarantined write token: {}", path.display());
+                        self.audit_failures.lock().await.quarantined += 1;
+                        (AuditOutcome::Quarantined, None)
+                    }
+                    Err(err) => {
+                        error!("Rejecting write token from {:?}: {}", client, err);
+                        self.audit_failures.lock().await.rejected += 1;
+                        (AuditOutcome::Rejected, None)
+                    }
+                }
+            }
+            AuditFailurePolicy::AcceptFlagged => {
+                self.audit_failures.lock().await.flagged += 1;
+                (AuditOutcome::Flagged, Some(token))
+            }
+        };
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(client, outcome);
+        }
+        token
+    }
+
+    async fn register_client(
+        &self,
+        client: &ClientInfo,
+        shards: Vec<WorkerInfo>,
+    ) -> Result<SessionToken, Status> {
+        self.client_registry.register_client(client, shards).await
+    }
+
+    async fn check_session_token(&self, client: &ClientInfo, token: &[u8]) -> Result<(), Status> {
+        self.client_registry.check_token(client, token).await
     }
 }
 
 pub struct MyWorker<P: Protocol> {
     start_rx: watch::Receiver<Option<Instant>>,
-    start_time: Arc<RwLock<Option<Instant>>>,
+    registration_rx: watch::Receiver<Window>,
+    start_time: Arc<StartGate>,
     services: Arc<ServiceRegistry>,
     state: Arc<WorkerState<P>>,
-    notify: Arc<tokio::sync::Notify>,
+    notify: Arc<Notify>,
+    deadlines: Deadlines,
+    early_uploads: EarlyUploadPolicy,
+    // Uploads that arrived before the start time, waiting for it.
+    held_uploads: Arc<HeldUploads>,
+}
+
+// Not derived: that would require `P: Clone`.
+impl<P: Protocol> Clone for MyWorker<P> {
+    fn clone(&self) -> Self {
+        MyWorker {
+            start_rx: self.start_rx.clone(),
+            registration_rx: self.registration_rx.clone(),
+            start_time: self.start_time.clone(),
+            services: self.services.clone(),
+            state: self.state.clone(),
+            notify: self.notify.clone(),
+            deadlines: self.deadlines,
+            early_uploads: self.early_uploads,
+            held_uploads: self.held_uploads.clone(),
+        }
+    }
 }
 
 impl<P> MyWorker<P>
@@ -204,59 +468,92 @@ where
     P: Protocol,
     P::Accumulator: Clone,
 {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         start_rx: watch::Receiver<Option<Instant>>,
+        registration_rx: watch::Receiver<Window>,
         services: Arc<ServiceRegistry>,
         experiment: Experiment,
         protocol: P,
-    ) -> Self {
-        let state = WorkerState::from_experiment(experiment, protocol);
+        on_audit_failure: AuditFailurePolicy,
+        audit_log: Option<AuditLog>,
+        revocations: watch::Receiver<Vec<Revocation>>,
+        early_uploads: EarlyUploadPolicy,
+        deadlines: Deadlines,
+        token_codec: Option<TokenCodec<P::WriteToken>>,
+        crypto_pool: CryptoPoolConfig,
+        cancel: CancellationToken,
+    ) -> Self
+    where
+        P: Clone,
+    {
+        let state = WorkerState::from_experiment(
+            experiment,
+            protocol,
+            on_audit_failure,
+            audit_log,
+            revocations,
+            token_codec,
+            crypto_pool,
+            cancel,
+        );
         MyWorker {
             start_rx,
+            registration_rx,
             start_time: Default::default(),
             services,
             state: Arc::new(state),
             notify: Default::default(),
+            deadlines,
+            early_uploads,
+            held_uploads: Default::default(),
         }
     }
 
-    async fn get_start_time(&self) -> Option<Instant> {
-        {
-            let start_time_guard = self.start_time.read().await;
-            if start_time_guard.is_some() {
-                return *start_time_guard;
-            }
-        }
+    fn get_start_time(&self) -> Option<Instant> {
+        let latest = *self.start_rx.borrow();
+        self.start_time.observe(latest)
+    }
 
-        let start_lock = *self.start_rx.borrow();
-        start_lock?;
+    async fn get_peers(&self, client: &ClientInfo) -> Result<Vec<SharedClient>, Status> {
+        get_peers(&self.state.client_registry, &self.services, client).await
+    }
 
-        // Need to populate self.start_time if we can
-        let mut start_time_lock = self.start_time.write().await;
-        *start_time_lock = start_lock;
+    /// Round finalization hook: reset this worker for the next round.
+    pub async fn finalize_round(&self) {
+        self.state.finalize_round().await;
+    }
 
-        start_lock
+    fn registration_window(&self) -> (Window, WindowState) {
+        let window = *self.registration_rx.borrow();
+        let started = self.start_rx.borrow().is_some();
+        let now = DateTime::<FixedOffset>::from(Utc::now());
+        (window, window.state(now, started))
     }
 
-    async fn get_peers(&self, client: &ClientInfo) -> Result<Vec<SharedClient>, Status> {
-        self.state
-            .client_registry
-            .get_peers(client)
-            .await?
-            .into_iter()
-            .map(|info| self.services.get_worker(info))
-            .collect()
+    fn check_not_aborted(&self) -> Result<(), Status> {
+        if self.state.cancel.is_cancelled() {
+            return Err(Status::aborted("Round aborted."));
+        }
+        Ok(())
     }
 
-    fn check_not_started(&self) -> Result<(), Status> {
-        let started = *self.start_rx.borrow();
-        if started.is_some() {
-            return Err(Status::failed_precondition(
+    fn check_registration_open(&self) -> Result<(), Status> {
+        let (window, state) = self.registration_window();
+        match (state, window.opens, window.closes) {
+            (WindowState::Open, _, _) => Ok(()),
+            (WindowState::NotYetOpen, Some(opens), _) => Err(Status::unavailable(format!(
+                "Client registration opens at {}.",
+                opens
+            ))),
+            (WindowState::Closed, _, Some(closes)) => Err(Status::failed_precondition(format!(
+                "Client registration closed at {}.",
+                closes
+            ))),
+            _ => Err(Status::failed_precondition(
                 "Client registration after start time.",
-            ));
+            )),
         }
-
-        Ok(())
     }
 }
 
@@ -264,7 +561,8 @@ where
 impl<P> Worker for MyWorker<P>
 where
     P: Protocol + 'static + Sync + Send + Clone,
-    P::WriteToken: Clone + TryFrom<proto::WriteToken> + Sync + Send + fmt::Debug,
+    P::WriteToken:
+        Clone + TryFrom<proto::WriteToken> + Into<proto::WriteToken> + Sync + Send + fmt::Debug,
     <P::WriteToken as TryFrom<proto::WriteToken>>::Error: fmt::Debug + Send,
     P::AuditShare: TryFrom<proto::AuditShare> + Into<proto::AuditShare> + Sync + Send + fmt::Debug,
     <P::AuditShare as TryFrom<proto::AuditShare>>::Error: fmt::Debug,
@@ -276,56 +574,125 @@ where
         &self,
         request: Request<UploadRequest>,
     ) -> Result<Response<UploadResponse>, Status> {
+        self.check_not_aborted()?;
+        // The verify calls for this upload shouldn't outlive it.
+        let upload_deadline = deadline::from_request(&request);
+        let verify_timeout = self.deadlines.verify;
         let request = request.into_inner();
 
         let client_id = expect_field(request.client_id, "Client ID")?;
-        let client_info = ClientInfo::from(&client_id);
+        let client_info = ClientInfo::try_from(&client_id)?;
         trace!("upload() client_info: {:?}", &client_info);
-        let write_token = expect_field(request.write_token, "Write Token")?;
+        self.state
+            .check_session_token(&client_info, &request.session_token)
+            .await?;
+        let write_token: P::WriteToken = expect_field(request.write_token, "Write Token")?
+            .try_into()
+            .map_err(|err| Status::invalid_argument(format!("Bad write token: {:?}.", err)))?;
+        // Auditing assumes the token fits the protocol (and panics otherwise).
+        self.state
+            .protocol
+            .check_write_token(&write_token)
+            .map_err(|err| Status::invalid_argument(format!("Bad write token: {}.", err)))?;
         debug!("upload() write token: {:?}", &client_info);
+        let first = request.upload_seq == 0;
+        // Before the start time, we can't reach our peers yet.
+        let started = self.start_rx.borrow().is_some();
+        let peers = if started {
+            Some(self.get_peers(&client_info).await?)
+        } else {
+            self.held_uploads.hold(self.early_uploads)?;
+            debug!("Holding upload from {:?} for the start time.", client_info);
+            None
+        };
+        let upload_seq = self.state.upload_seq(request.upload_seq);
         let state = self.state.clone();
-        let peers: Vec<SharedClient> = self.get_peers(&client_info).await?;
+        let services = self.services.clone();
+        let held_uploads = self.held_uploads.clone();
+        let start_rx = self.start_rx.clone();
 
         spawn(async move {
-            let audit_shares = state
-                .upload(&client_info, write_token.try_into().unwrap())
-                .await;
+            let peers: Vec<SharedClient> = match peers {
+                Some(peers) => peers,
+                None => {
+                    let started = wait_for_start(start_rx, state.cancel.clone()).await;
+                    held_uploads.release();
+                    if !started {
+                        warn!(
+                            "Dropping upload from {:?} held for the start time.",
+                            client_info
+                        );
+                        return;
+                    }
+                    match get_peers(&state.client_registry, &services, &client_info).await {
+                        Ok(peers) => peers,
+                        Err(err) => {
+                            warn!("Not auditing held upload from {:?}: {}", client_info, err);
+                            return;
+                        }
+                    }
+                }
+            };
+            let audit_shares = match state.upload(&client_info, upload_seq, write_token).await {
+                Ok(audit_shares) => audit_shares,
+                Err(err) => {
+                    warn!("Not auditing upload from {:?}: {}", client_info, err);
+                    return;
+                }
+            };
 
-            for (peer, audit_share) in peers.into_iter().zip(audit_shares.into_iter()) {
-                let req = Request::new(VerifyRequest {
-                    client_id: Some(client_id.clone()),
-                    audit_share: Some(audit_share.into()),
-                });
+            for (peer, audit_share) in peers.into_iter().zip(audit_shares) {
+                let req = deadline::request(
+                    VerifyRequest {
+                        client_id: Some(client_id.clone()),
+                        audit_share: Some(audit_share.into()),
+                        upload_seq,
+                    },
+                    verify_timeout,
+                    upload_deadline,
+                );
                 spawn(async move {
-                    peer.lock().await.verify(req).await.unwrap();
+                    if let Err(err) = peer.verify(req).await {
+                        error!("Failed to send audit share to peer: {}", err);
+                    }
                 });
             }
-            Ok::<_, Status>(())
         });
 
-        if self.state.hammer() {
-            // block to apply backpressure to clients
-            self.notify.notified().await;
+        // Don't hold up the first upload: until the client has its sequence
+        // number, it can't upload to the other workers to finish the audit.
+        if self.state.hammer().is_some() && !first {
+            // block to apply backpressure to clients (until the round aborts)
+            future::select(
+                Box::pin(self.notify.notified()),
+                Box::pin(self.state.cancel.cancelled()),
+            )
+            .await;
         }
 
-        Ok(Response::new(UploadResponse {}))
+        Ok(Response::new(UploadResponse { upload_seq }))
     }
 
     async fn verify(
         &self,
         request: Request<VerifyRequest>,
     ) -> Result<Response<VerifyResponse>, Status> {
+        self.check_not_aborted()?;
         let request = request.into_inner();
 
         // TODO(zjn): check which worker this comes from, don't double-insert
-        let client_info = ClientInfo::from(&expect_field(request.client_id, "Client ID")?);
+        let client_info = ClientInfo::try_from(&expect_field(request.client_id, "Client ID")?)?;
         let share = expect_field(request.audit_share, "Audit Share")?;
-        let share = share.try_into().unwrap();
+        let share: P::AuditShare = share
+            .try_into()
+            .map_err(|err| Status::invalid_argument(format!("Bad audit share: {:?}.", err)))?;
+        let upload_seq = request.upload_seq;
         let state = self.state.clone();
-        let start_time = self.get_start_time().await;
+        let start_time = self.get_start_time();
+        let aggregate_timeout = self.deadlines.aggregate;
         let leader;
         let notify;
-        if self.state.hammer() {
+        if self.state.hammer().is_some() {
             leader = None;
             notify = Some(self.notify.clone());
         } else {
@@ -334,24 +701,37 @@ where
         }
 
         spawn(async move {
-            match state.verify(&client_info, share).await {
-                Ok(VerifyStatus::AllClientsVerified { accumulator }) => {
+            match state.verify(&client_info, upload_seq, share).await {
+                Ok(VerifyStatus::AllClientsVerified {
+                    accumulator,
+                    audit_failures,
+                    participants,
+                    stage_tallies,
+                }) => {
                     if let Some(n) = notify {
                         n.notify_one()
                     };
                     let accumulator: Vec<Vec<u8>> =
                         accumulator.into_iter().map(Into::<Vec<u8>>::into).collect();
                     info!("Forwarding to leader.");
-                    let req = Request::new(AggregateWorkerRequest {
-                        share: Some(Share { data: accumulator }),
-                    });
-                    leader
+                    if audit_failures.total() > 0 {
+                        warn!("Audit failures: {:?}", audit_failures);
+                    }
+                    let result = leader
                         .expect("leader should be Some() when not in hammer mode")
-                        .lock()
-                        .await
-                        .aggregate_worker(req)
-                        .await
-                        .unwrap();
+                        .send(
+                            ROUND,
+                            Share { data: accumulator },
+                            audit_failures,
+                            participants,
+                            stage_tallies.into(),
+                            aggregate_timeout,
+                        )
+                        .await;
+                    if let Err(err) = result {
+                        error!("Failed to send share to leader: {}", err);
+                    }
+                    state.finalize_round().await;
                 }
                 Ok(VerifyStatus::AwaitingShares) => {
                     // nothing to do
@@ -385,31 +765,164 @@ where
         &self,
         request: Request<RegisterClientRequest>,
     ) -> Result<Response<RegisterClientResponse>, Status> {
-        self.check_not_started()?;
+        self.check_not_aborted()?;
+        self.check_registration_open()?;
 
         let request = request.into_inner();
-        let client_info = ClientInfo::from(&expect_field(request.client_id, "Client ID")?);
+        let client_info = ClientInfo::try_from(&expect_field(request.client_id, "Client ID")?)?;
         let shards = request.shards.into_iter().map(WorkerInfo::from).collect();
-        self.state.register_client(&client_info, shards).await;
+        let session_token = self.state.register_client(&client_info, shards).await?;
 
-        let reply = RegisterClientResponse {};
+        let reply = RegisterClientResponse { session_token };
         Ok(Response::new(reply))
     }
+
+    async fn get_registration_window(
+        &self,
+        _request: Request<RegistrationWindowRequest>,
+    ) -> Result<Response<RegistrationWindowResponse>, Status> {
+        let (window, state) = self.registration_window();
+        Ok(Response::new(window.to_proto(state)))
+    }
+}
+
+// The workers (one per other group) that audit `client`'s uploads with us.
+async fn get_peers(
+    clients: &ClientRegistry,
+    services: &ServiceRegistry,
+    client: &ClientInfo,
+) -> Result<Vec<SharedClient>, Status> {
+    clients
+        .get_peers(client)
+        .await?
+        .into_iter()
+        .map(|info| services.get_worker(info))
+        .collect()
+}
+
+// Keep `window` in sync with the registration window in the config store,
+// until the worker stops listening.
+async fn watch_registration_window<C: Store>(config: C, window: watch::Sender<Window>) {
+    loop {
+        match get_window(&config).await {
+            Ok(latest) => {
+                if *window.borrow() != latest && window.send(latest).is_err() {
+                    break;
+                }
+            }
+            Err(err) => warn!("Couldn't read registration window: {}", err),
+        }
+        sleep(REGISTRATION_POLL_INTERVAL).await;
+    }
 }
 
+// Handle the audit shares that colocated peers put in `inbox`, until the round
+// is aborted.
+async fn serve_shm_inbox<P>(worker: MyWorker<P>, inbox: shm::Receiver)
+where
+    P: Protocol + 'static + Sync + Send + Clone,
+    P::WriteToken:
+        Clone + TryFrom<proto::WriteToken> + Into<proto::WriteToken> + Sync + Send + fmt::Debug,
+    <P::WriteToken as TryFrom<proto::WriteToken>>::Error: fmt::Debug + Send,
+    P::AuditShare: TryFrom<proto::AuditShare> + Into<proto::AuditShare> + Sync + Send + fmt::Debug,
+    <P::AuditShare as TryFrom<proto::AuditShare>>::Error: fmt::Debug,
+    P::ChannelKey: TryFrom<ChannelKeyWrapper> + Send,
+    <P::ChannelKey as TryFrom<ChannelKeyWrapper>>::Error: fmt::Debug,
+    P::Accumulator: Sync + Send + Clone + Into<Vec<u8>>,
+{
+    while !worker.state.cancel.is_cancelled() {
+        let msg = match inbox.try_recv() {
+            Some(msg) => msg,
+            None => {
+                sleep(SHM_POLL_INTERVAL).await;
+                continue;
+            }
+        };
+        let req = match VerifyRequest::decode(&msg[..]) {
+            Ok(req) => req,
+            Err(err) => {
+                warn!("Bad message in shared-memory inbox: {}", err);
+                continue;
+            }
+        };
+        if let Err(err) = Worker::verify(&worker, Request::new(req)).await {
+            warn!("Error verifying audit share from shared memory: {}", err);
+        }
+    }
+}
+
+// Periodically report progress to the publisher (hammer mode only), until
+// `end` (after one last report).
+async fn report_stats<P>(
+    state: Arc<WorkerState<P>>,
+    registry: Arc<ServiceRegistry>,
+    info: WorkerInfo,
+    start_time: Instant,
+    interval: Duration,
+    end: Option<Instant>,
+) where
+    P: Protocol,
+    P::Accumulator: Clone,
+{
+    let publisher = match registry.get_publisher() {
+        Some(publisher) => publisher,
+        None => {
+            warn!("No publisher registered; not reporting stats.");
+            return;
+        }
+    };
+    loop {
+        let mut next = Instant::now() + interval;
+        let last = match end {
+            Some(end) if end <= next => {
+                next = end;
+                true
+            }
+            _ => false,
+        };
+        sleep_until(next).await;
+        let roles = state.client_registry.role_counts().await;
+        let (clients_verified, audit_failures) = state.round_stats().await;
+        let req = Request::new(ReportStatsRequest {
+            worker_id: Some(info.into()),
+            clients_verified: clients_verified as u64,
+            elapsed_ms: start_time.elapsed().as_millis() as u64,
+            pending_audits: state.audit_registry.lock().await.len() as u64,
+            audit_failures: Some(audit_failures),
+            viewers_registered: roles.viewers as u64,
+            broadcasters_registered: roles.broadcasters as u64,
+        });
+        if let Err(err) = publisher.lock().await.report_stats(req).await {
+            debug!("Stopped reporting stats: {}", err);
+            break;
+        }
+        if last {
+            break;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn inner_run<C, F, P>(
     config: C,
     experiment: Experiment,
     protocol: P,
     info: WorkerInfo,
     net: NetConfig,
+    on_audit_failure: AuditFailurePolicy,
+    audit_sink: Option<AuditSink>,
+    early_uploads: EarlyUploadPolicy,
+    shm_transport: bool,
+    seal_write_tokens: bool,
+    crypto_pool: CryptoPoolConfig,
     shutdown: F,
 ) -> Result<(), BoxedError>
 where
-    C: Store,
+    C: 'static + Store + Discovery + Clone + Send + Sync,
     F: Future<Output = ()> + Send + 'static,
     P: Protocol + 'static + Sync + Send + Clone,
-    P::WriteToken: Clone + TryFrom<proto::WriteToken> + Sync + Send + fmt::Debug,
+    P::WriteToken:
+        Clone + TryFrom<proto::WriteToken> + Into<proto::WriteToken> + Sync + Send + fmt::Debug,
     <P::WriteToken as TryFrom<proto::WriteToken>>::Error: fmt::Debug + Send,
     P::AuditShare: TryFrom<proto::AuditShare> + Into<proto::AuditShare> + Sync + Send + fmt::Debug,
     <P::AuditShare as TryFrom<proto::AuditShare>>::Error: fmt::Debug,
@@ -419,78 +932,271 @@ where
 {
     info!("Worker starting up.");
 
+    // Hammer runs with a duration stop on their own; any run stops if the
+    // round is aborted.
+    let stop = Arc::new(Notify::new());
+    let cancel = CancellationToken::default();
+    let abort_watcher = spawn(watch_for_abort(config.clone(), cancel.clone()));
+    let shutdown = {
+        let stop = stop.clone();
+        let cancel = cancel.clone();
+        future::select(
+            Box::pin(shutdown),
+            Box::pin(future::select(
+                Box::pin(async move { stop.notified().await }),
+                Box::pin(async move {
+                    cancel.cancelled().await;
+                }),
+            )),
+        )
+        .map(|_| systemd::stopping())
+    };
+
     let (start_tx, start_rx) = watch::channel(None);
+    let (registration_tx, registration_rx) = watch::channel(Window::default());
+    let (revocations_tx, revocations_rx) = watch::channel(vec![]);
+    spawn(watch_revocations(config.clone(), revocations_tx));
     let (registry, registry_remote) = ServiceRegistry::new_with_remote();
     let registry = Arc::new(registry);
 
-    let worker = MyWorker::new(start_rx, registry.clone(), experiment, protocol);
+    let audit_log = audit_sink.map(|sink| {
+        info!("Sending audit failures to {}.", sink);
+        AuditLog::spawn(sink, info)
+    });
+    let worker = MyWorker::new(
+        start_rx,
+        registration_rx,
+        registry.clone(),
+        experiment,
+        protocol,
+        on_audit_failure,
+        audit_log,
+        revocations_rx,
+        early_uploads,
+        net.deadlines(),
+        seal_write_tokens.then_some(TokenCodec {
+            encode: encode_write_token::<P::WriteToken>,
+            decode: decode_write_token::<P::WriteToken>,
+        }),
+        crypto_pool,
+        cancel,
+    );
     let state = worker.state.clone();
-    let mut builder = tonic::transport::server::Server::builder();
+    let held_uploads = worker.held_uploads.clone();
+    // Colocated peers can skip gRPC for audit shares (see `net::shm`).
+    let mut shm_inbox = None;
+    let mut shm_task = None;
+    if shm_transport {
+        match shm::machine_id() {
+            Some(machine_id) => {
+                let name = format!("worker-{}-{}", info.group.idx, info.idx);
+                let inbox = shm::Receiver::create(&name, shm::DEFAULT_CAPACITY)?;
+                shm_inbox = Some(shm::ShmInbox {
+                    machine_id,
+                    path: inbox.path().display().to_string(),
+                });
+                shm_task = Some(spawn(serve_shm_inbox(worker.clone(), inbox)));
+            }
+            None => warn!("No machine ID for this host; not using shared memory."),
+        }
+    }
+    let mut builder = net.server_builder();
     if let Some(identity) = net.tls_ident() {
         info!("Adding TLS config.");
-        builder = builder.tls_config(ServerTlsConfig::new().identity(identity))?;
+        builder = tls::server(builder, identity)?;
     }
     let server = builder
         .add_service(HealthServer::new(AllGoodHealthServer::default()))
+        .add_service(AdminServer::new(LogAdmin::default()))
         .add_service(WorkerServer::new(worker))
-        .serve_with_shutdown(net.local_socket_addr(), shutdown);
+        .serve_with_incoming_shutdown(net.bind().await?, shutdown);
 
     let server_task = spawn(server);
 
-    sleep(std::time::Duration::from_millis(500)).await;
-
-    wait_for_health(format!("http://{}", net.public_addr()), net.tls_cert()).await?;
+    wait_for_health(net.public_uri(), net.tls_cert()).await?;
     trace!("Worker {:?} healthy and serving.", info);
-    register(&config, Node::new(info.into(), net.public_addr())).await?;
+    let node = Node::new(info.into(), net.public_addr())
+        .with_scheme(net.public_scheme())
+        .with_pinned_cert(net.pinned_cert())
+        .with_shm_inbox(shm_inbox.clone());
+    register(&config, node).await?;
+    systemd::ready();
+    spawn(watch_registration_window(config.clone(), registration_tx));
 
     let start_time = wait_for_start_time_set(&config).await.unwrap();
-    registry_remote.init(info, &config, net.tls_cert()).await?;
+    registry_remote
+        .init(info, &config, net.tls_cert(), shm_inbox.as_ref())
+        .await?;
     delay_until(start_time).await;
-    start_tx.send(Some(Instant::now()))?;
+    let start_instant = Instant::now();
+    start_tx.send(Some(start_instant))?;
+    if held_uploads.peak() > 0 {
+        info!(
+            "Processing {} uploads held for the start time (at most {} at once; policy: {}).",
+            held_uploads.held(),
+            held_uploads.peak(),
+            early_uploads
+        );
+    }
+
+    if let Some(hammer) = state.hammer() {
+        let end = hammer.duration.map(|duration| start_instant + duration);
+        let reporter = spawn(report_stats(
+            state.clone(),
+            registry.clone(),
+            info,
+            start_instant,
+            hammer.report_interval,
+            end,
+        ));
+        if let Some(end) = end {
+            spawn(async move {
+                sleep_until(end).await;
+                // Let the final report go out.
+                reporter.await.ok();
+                info!("Hammer run finished.");
+                stop.notify_one();
+            });
+        }
+    }
 
-    if !state.hammer() && state.client_registry.num_clients().await == 0 {
+    if state.hammer().is_none() && state.client_registry.num_clients().await == 0 {
+        let aggregate_timeout = net.deadlines().aggregate;
         spawn(async move {
             warn!("No clients registered; forwarding empty accumulator to leader.");
             let leader = registry.get_my_leader();
             let accumulator = state.accumulator.get().await;
             let accumulator: Vec<Vec<u8>> =
                 accumulator.into_iter().map(Into::<Vec<u8>>::into).collect();
-            let req = Request::new(AggregateWorkerRequest {
-                share: Some(Share { data: accumulator }),
-            });
-            leader.lock().await.aggregate_worker(req).await.unwrap();
+            let result = leader
+                .send(
+                    ROUND,
+                    Share { data: accumulator },
+                    AuditFailures::default(),
+                    state.count_noise(),
+                    StageTallies::default().into(),
+                    aggregate_timeout,
+                )
+                .await;
+            if let Err(err) = result {
+                error!("Failed to send share to leader: {}", err);
+            }
         })
         .await
         .expect("tokio spawn should succeed");
     }
 
     server_task.await??;
+    abort_watcher.abort();
+    if let Some(shm_task) = shm_task {
+        // Drops (and removes) the inbox.
+        shm_task.abort();
+    }
     info!("Worker shutting down.");
     Ok(())
 }
 
+fn encode_write_token<T: Clone + Into<proto::WriteToken>>(token: &T) -> Vec<u8> {
+    let mut data = Vec::new();
+    let token: proto::WriteToken = token.clone().into();
+    token
+        .encode(&mut data)
+        .expect("Vec<u8> should have enough capacity.");
+    data
+}
+
+fn decode_write_token<T: TryFrom<proto::WriteToken>>(data: &[u8]) -> Option<T> {
+    proto::WriteToken::decode(data).ok()?.try_into().ok()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run<C, F>(
     config: C,
     experiment: Experiment,
     protocol: ProtocolWrapper,
     info: WorkerInfo,
     net: NetConfig,
+    on_audit_failure: AuditFailurePolicy,
+    audit_sink: Option<AuditSink>,
+    early_uploads: EarlyUploadPolicy,
+    shm_transport: bool,
+    seal_write_tokens: bool,
+    crypto_pool: CryptoPoolConfig,
     shutdown: F,
 ) -> Result<(), BoxedError>
 where
-    C: Store,
+    C: 'static + Store + Discovery + Clone + Send + Sync,
     F: Future<Output = ()> + Send + 'static,
 {
     debug!("auth keys: {:?}", experiment.get_keys());
     match protocol {
+        ProtocolWrapper::Insecure(protocol) => {
+            inner_run(
+                config,
+                experiment,
+                protocol,
+                info,
+                net,
+                on_audit_failure,
+                audit_sink,
+                early_uploads,
+                shm_transport,
+                seal_write_tokens,
+                crypto_pool,
+                shutdown,
+            )
+            .await?;
+        }
         ProtocolWrapper::Secure(protocol) => {
-            inner_run(config, experiment, protocol, info, net, shutdown).await?;
+            inner_run(
+                config,
+                experiment,
+                protocol,
+                info,
+                net,
+                on_audit_failure,
+                audit_sink,
+                early_uploads,
+                shm_transport,
+                seal_write_tokens,
+                crypto_pool,
+                shutdown,
+            )
+            .await?;
         }
         ProtocolWrapper::SecurePub(protocol) => {
-            inner_run(config, experiment, protocol, info, net, shutdown).await?;
+            inner_run(
+                config,
+                experiment,
+                protocol,
+                info,
+                net,
+                on_audit_failure,
+                audit_sink,
+                early_uploads,
+                shm_transport,
+                seal_write_tokens,
+                crypto_pool,
+                shutdown,
+            )
+            .await?;
         }
         ProtocolWrapper::SecureMultiKey(protocol) => {
-            inner_run(config, experiment, protocol, info, net, shutdown).await?;
+            inner_run(
+                config,
+                experiment,
+                protocol,
+                info,
+                net,
+                on_audit_failure,
+                audit_sink,
+                early_uploads,
+                shm_transport,
+                seal_write_tokens,
+                crypto_pool,
+                shutdown,
+            )
+            .await?;
         }
     }
     Ok(())
@@ -1,36 +1,73 @@
 // https://github.com/rust-lang/rust-clippy/issues/6819
 #![allow(clippy::manual_map)]
-use crate::proto::{leader_client::LeaderClient, worker_client::WorkerClient};
-use crate::{
-    config::store::Store,
-    services::{discovery::resolve_all, Service, WorkerInfo},
+use super::leader_sender::LeaderSender;
+use crate::net::{
+    client::Builder,
+    shm::{self, ShmInbox},
+    tls::Certificate,
+    ClientChannel,
+};
+use crate::proto::{publisher_client::PublisherClient, worker_client::WorkerClient, VerifyRequest};
+use crate::services::{
+    discovery::{resolve_all, Discovery},
+    Service, WorkerInfo,
 };
 
-use log::debug;
+use crate::rt::sync::{watch, Mutex};
+use log::{debug, info, warn};
+use prost::Message;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{watch, Mutex};
-use tonic::{
-    transport::Certificate, transport::Channel, transport::ClientTlsConfig, transport::Uri, Status,
-};
+use tonic::{Request, Status};
 
 type Error = Box<dyn std::error::Error + Sync + Send>;
 
-pub type SharedClient = Arc<Mutex<WorkerClient<Channel>>>;
+/// A peer worker, reached over gRPC or (if it's on our host) its shared-memory
+/// inbox.
+pub struct Peer {
+    client: Mutex<WorkerClient<ClientChannel>>,
+    inbox: Option<shm::Sender>,
+}
+
+impl Peer {
+    /// Send the peer an audit share.
+    ///
+    /// Goes through the inbox if there's room, skipping the request's deadline
+    /// (and any simulated latency); otherwise, falls back to gRPC.
+    pub async fn verify(&self, req: Request<VerifyRequest>) -> Result<(), Status> {
+        if let Some(inbox) = &self.inbox {
+            let mut msg = Vec::with_capacity(req.get_ref().encoded_len());
+            req.get_ref()
+                .encode(&mut msg)
+                .expect("Vec should have capacity");
+            if inbox.try_send(&msg) {
+                return Ok(());
+            }
+            debug!("Peer's inbox is full; verifying over gRPC.");
+        }
+        self.client.lock().await.verify(req).await?;
+        Ok(())
+    }
+}
+
+pub type SharedClient = Arc<Peer>;
 type WorkersMap = HashMap<WorkerInfo, SharedClient>;
-type SharedLeaderClient = Arc<Mutex<LeaderClient<Channel>>>;
+type SharedLeaderSender = Arc<LeaderSender>;
+type SharedPublisherClient = Arc<Mutex<PublisherClient<ClientChannel>>>;
 
 #[derive(Clone)]
 struct Map {
     workers: WorkersMap,
-    leader: Option<SharedLeaderClient>,
+    leader: Option<SharedLeaderSender>,
+    publisher: Option<SharedPublisherClient>,
 }
 
 impl Map {
-    async fn from_config<C: Store>(
+    async fn from_config<C: Discovery>(
         worker: WorkerInfo,
         config: &C,
         tls: Option<Certificate>,
+        shm_inbox: Option<&ShmInbox>,
     ) -> Result<Self, Error> {
         let all_services = resolve_all(config).await?;
 
@@ -38,61 +75,95 @@ impl Map {
         let peer_workers: Vec<_> = all_services
             .iter()
             .filter_map(|node| match node.service {
-                Service::Worker(info) => Some((info, node.addr.clone())),
+                Service::Worker(info) => Some((
+                    info,
+                    node.uri(),
+                    node.tls_cert(tls.clone()),
+                    node.shm_inbox.clone(),
+                )),
                 _ => None,
             })
             .collect();
-        for (worker_info, addr) in peer_workers {
-            let uri = format!("https://{}", addr)
-                .parse::<Uri>()
-                .expect("bad addr");
-            let mut builder = Channel::builder(uri);
-            if let Some(ref cert) = tls {
-                debug!("TLS for WorkerClient.");
-                builder = builder
-                    .tls_config(
-                        ClientTlsConfig::new()
-                            .domain_name("spectrum.example.com")
-                            .ca_certificate(cert.clone()),
-                    )
-                    .map_err(|e| format!("{:?}", e))?;
-            }
-            let channel = builder.connect().await.map_err(|err| err.to_string())?;
-            let worker = WorkerClient::new(channel);
-            workers.insert(worker_info, Arc::new(Mutex::new(worker)));
+        for (worker_info, uri, tls, peer_inbox) in peer_workers {
+            let client = Builder::new(uri)
+                .tls(tls)
+                .worker_client(worker.into(), worker_info.into())
+                .await?;
+            let inbox = match (shm_inbox, peer_inbox) {
+                (Some(ours), Some(theirs)) if ours.machine_id == theirs.machine_id => {
+                    match shm::Sender::open(&theirs.path) {
+                        Ok(inbox) => {
+                            info!(
+                                "Reaching colocated {:?} through shared memory.",
+                                worker_info
+                            );
+                            Some(inbox)
+                        }
+                        Err(err) => {
+                            warn!("Couldn't open inbox for {:?}: {}", worker_info, err);
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let peer = Peer {
+                client: Mutex::new(client),
+                inbox,
+            };
+            workers.insert(worker_info, Arc::new(peer));
         }
 
-        let addr = all_services
-            .into_iter()
-            .find_map(|node| match node.service {
-                Service::Leader(leader) if leader.group == worker.group => Some(node.addr),
-                _ => None,
-            });
-        let leader = if let Some(addr) = addr {
-            Some(Arc::new(Mutex::new(
-                LeaderClient::connect(format!("http://{}", addr)).await?,
-            )))
+        let leader = all_services.iter().find_map(|node| match node.service {
+            Service::Leader(leader) if leader.group == worker.group => {
+                Some((node.uri(), node.tls_cert(tls.clone())))
+            }
+            _ => None,
+        });
+        // Connects lazily (and reconnects as needed) when sending.
+        let leader = leader.map(|(uri, tls)| Arc::new(LeaderSender::new(worker, uri, tls)));
+
+        let publisher = all_services.into_iter().find(|node| match node.service {
+            // Stats only go to the first publisher (if replicated).
+            Service::Publisher(info) => info.idx == 0,
+            _ => false,
+        });
+        let publisher = if let Some(node) = publisher {
+            let client = Builder::new(node.uri())
+                .tls(node.tls_cert(tls))
+                .publisher_client(worker.into(), node.service)
+                .await?;
+            Some(Arc::new(Mutex::new(client)))
         } else {
             None
         };
 
-        Ok(Map { workers, leader })
+        Ok(Map {
+            workers,
+            leader,
+            publisher,
+        })
     }
 }
 
 pub struct Remote(watch::Sender<Option<Map>>);
 
 impl Remote {
+    /// Connect to everything in `config`.
+    ///
+    /// With our own `shm_inbox`, peers that have one on the same host get
+    /// their audit shares through it.
     pub async fn init<C>(
         &self,
         worker: WorkerInfo,
         config: &C,
         tls: Option<Certificate>,
+        shm_inbox: Option<&ShmInbox>,
     ) -> Result<(), Error>
     where
-        C: Store,
+        C: Discovery,
     {
-        let map = Map::from_config(worker, config, tls).await?;
+        let map = Map::from_config(worker, config, tls, shm_inbox).await?;
         self.0
             .send(Some(map))
             .map_err(|_| "Error sending service registry.")?;
@@ -125,7 +196,7 @@ impl Registry {
         Ok(client.clone())
     }
 
-    pub fn get_my_leader(&self) -> SharedLeaderClient {
+    pub fn get_my_leader(&self) -> SharedLeaderSender {
         let lock = self.0.borrow();
         lock.as_ref()
             .expect("Should only get_leader() after initialization.")
@@ -134,4 +205,12 @@ impl Registry {
             .expect("Don't call get_my_leader() in hammer mode.")
             .clone()
     }
+
+    pub fn get_publisher(&self) -> Option<SharedPublisherClient> {
+        let lock = self.0.borrow();
+        lock.as_ref()
+            .expect("Should only get_publisher() after initialization.")
+            .publisher
+            .clone()
+    }
 }
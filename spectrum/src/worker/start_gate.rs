@@ -0,0 +1,91 @@
+//! The worker's view of the round's start time.
+//!
+//! The start time arrives on a `watch` channel, and gets cached the first time
+//! anyone sees it. After that, every caller gets that same start time, even if
+//! the channel later changes: two callers racing to fill the cache used to be
+//! able to overwrite each other.
+use crate::rt::blocking::RwLock;
+
+use std::time::Instant;
+
+pub struct StartGate {
+    start: RwLock<Option<Instant>>,
+}
+
+// Not derived: loom's `RwLock` isn't `Default`.
+impl Default for StartGate {
+    fn default() -> Self {
+        StartGate {
+            start: RwLock::new(None),
+        }
+    }
+}
+
+impl StartGate {
+    /// The start time, given the `latest` one from the channel.
+    ///
+    /// Returns the cached start time if there is one; otherwise, caches (and
+    /// returns) `latest`.
+    pub fn observe(&self, latest: Option<Instant>) -> Option<Instant> {
+        if let Some(start) = *self.start.read().unwrap() {
+            return Some(start);
+        }
+        latest?;
+        let mut start = self.start.write().unwrap();
+        // Someone else may have gotten here first.
+        if start.is_none() {
+            *start = latest;
+        }
+        *start
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_observe() {
+        let gate = StartGate::default();
+        assert_eq!(gate.observe(None), None);
+
+        let start = Instant::now();
+        assert_eq!(gate.observe(Some(start)), Some(start));
+        assert_eq!(gate.observe(None), Some(start));
+        let later = start + Duration::from_secs(1);
+        assert_eq!(gate.observe(Some(later)), Some(start));
+    }
+}
+
+#[cfg(all(test, feature = "loom"))]
+mod loom_tests {
+    use super::*;
+    use loom::sync::Arc;
+    use loom::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn loom_first_start_wins() {
+        let first = Instant::now();
+        let second = first + Duration::from_secs(1);
+        loom::model(move || {
+            let gate = Arc::new(StartGate::default());
+            let threads: Vec<_> = vec![Some(first), Some(second), None]
+                .into_iter()
+                .map(|latest| {
+                    let gate = gate.clone();
+                    thread::spawn(move || gate.observe(latest))
+                })
+                .collect();
+            let seen: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+            // Whoever cached first, everyone agrees with them from then on.
+            let start = gate.observe(None).expect("someone cached a start");
+            assert!(start == first || start == second);
+            for observed in seen.into_iter().flatten() {
+                assert_eq!(observed, start);
+            }
+        });
+    }
+}
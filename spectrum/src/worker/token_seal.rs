@@ -0,0 +1,131 @@
+//! Sealing write tokens while they wait for their audits.
+//!
+//! With sealing on, the audit registry keeps write tokens encrypted under a
+//! key that only ever lives in memory, and that's replaced every round. So a
+//! captured image of the registry's state doesn't give up the tokens in it.
+//!
+//! The cipher is BLAKE3 in keyed mode, twice over: its extendable output
+//! (keyed, over a random nonce) is the keystream, and a keyed hash over the
+//! nonce and ciphertext is the tag (encrypt-then-MAC). The two keys are
+//! derived from one random key.
+use rand::{thread_rng, RngCore};
+use spectrum_primitives::Zeroizing;
+
+const NONCE_LEN: usize = 16;
+const ENCRYPTION_CONTEXT: &str = "spectrum 2021 worker write token sealing: encryption";
+const MAC_CONTEXT: &str = "spectrum 2021 worker write token sealing: mac";
+
+/// An encrypted and authenticated write token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sealed {
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+    tag: blake3::Hash,
+}
+
+/// Seals and opens write tokens under a fresh, in-memory key.
+pub struct TokenSealer {
+    encryption_key: Zeroizing<[u8; 32]>,
+    mac_key: Zeroizing<[u8; 32]>,
+}
+
+impl Default for TokenSealer {
+    fn default() -> Self {
+        let mut key = Zeroizing::new([0u8; 32]);
+        thread_rng().fill_bytes(&mut key[..]);
+        TokenSealer {
+            encryption_key: derive_key(ENCRYPTION_CONTEXT, &key[..]),
+            mac_key: derive_key(MAC_CONTEXT, &key[..]),
+        }
+    }
+}
+
+fn derive_key(context: &str, key_material: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    blake3::derive_key(context, key_material, &mut key[..]);
+    key
+}
+
+impl TokenSealer {
+    // XOR `data` with the keystream for `nonce`.
+    fn apply_keystream(&self, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+        let mut keystream = Zeroizing::new(vec![0u8; data.len()]);
+        blake3::Hasher::new_keyed(&self.encryption_key)
+            .update(nonce)
+            .finalize_xof()
+            .fill(&mut keystream);
+        for (byte, key) in data.iter_mut().zip(keystream.iter()) {
+            *byte ^= key;
+        }
+    }
+
+    fn tag(&self, nonce: &[u8; NONCE_LEN], ciphertext: &[u8]) -> blake3::Hash {
+        blake3::Hasher::new_keyed(&self.mac_key)
+            .update(nonce)
+            .update(ciphertext)
+            .finalize()
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> Sealed {
+        let mut nonce = [0u8; NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+        let mut ciphertext = plaintext.to_vec();
+        self.apply_keystream(&nonce, &mut ciphertext);
+        let tag = self.tag(&nonce, &ciphertext);
+        Sealed {
+            nonce,
+            ciphertext,
+            tag,
+        }
+    }
+
+    /// The plaintext, if `sealed` was sealed by this sealer (and untouched).
+    pub fn open(&self, sealed: &Sealed) -> Option<Zeroizing<Vec<u8>>> {
+        // `blake3::Hash` compares in constant time.
+        if self.tag(&sealed.nonce, &sealed.ciphertext) != sealed.tag {
+            return None;
+        }
+        let mut plaintext = Zeroizing::new(sealed.ciphertext.clone());
+        self.apply_keystream(&sealed.nonce, &mut plaintext);
+        Some(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_seal_open(data: Vec<u8>) {
+            let sealer = TokenSealer::default();
+            let sealed = sealer.seal(&data);
+            prop_assert_eq!(sealer.open(&sealed).unwrap().to_vec(), data);
+        }
+
+        #[test]
+        fn test_tampered(data in prop::collection::vec(any::<u8>(), 1..100), idx: prop::sample::Index) {
+            let sealer = TokenSealer::default();
+            let mut sealed = sealer.seal(&data);
+            sealed.ciphertext[idx.index(data.len())] ^= 1;
+            prop_assert!(sealer.open(&sealed).is_none());
+        }
+    }
+
+    #[test]
+    fn test_other_key() {
+        let sealed = TokenSealer::default().seal(b"write token");
+        assert!(TokenSealer::default().open(&sealed).is_none());
+    }
+
+    #[test]
+    fn test_hides_plaintext() {
+        let sealer = TokenSealer::default();
+        let data = vec![0u8; 64];
+        let first = sealer.seal(&data);
+        assert_ne!(first.ciphertext, data);
+        // Fresh nonce each time.
+        assert_ne!(sealer.seal(&data), first);
+    }
+}
@@ -2,7 +2,12 @@ extern crate spectrum;
 
 use simplelog::{LevelFilter, TermLogger, TerminalMode};
 use spectrum::{
-    config, experiment::Experiment, protocols::wrapper::ProtocolWrapper, run_in_process,
+    config,
+    experiment::{Experiment, TopologyBounds},
+    protocols::wrapper::ProtocolWrapper,
+    run_in_process,
+    services::failures::ErrorBudget,
+    DEFAULT_TIMEOUT,
 };
 
 #[tokio::test]
@@ -16,9 +21,80 @@ async fn test_pass() {
     )
     .unwrap();
 
+    let budget = ErrorBudget::default();
     let protocol = ProtocolWrapper::new(true, false, 2, 1, 100, false);
     let experiment = Experiment::new_sample_keys(protocol, 2, 3, false);
 
     let config = config::from_string("").await.unwrap();
-    run_in_process(experiment, config, None).await.unwrap();
+    run_in_process(
+        experiment,
+        config,
+        None,
+        Default::default(),
+        budget,
+        Default::default(),
+        DEFAULT_TIMEOUT,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_replicated_publishers() {
+    let protocol = ProtocolWrapper::new(true, false, 2, 1, 100, false);
+    let experiment = Experiment::new_sample_keys(protocol, 2, 3, false).with_publishers(2);
+    let config = config::from_string("").await.unwrap();
+    run_in_process(
+        experiment,
+        config,
+        None,
+        Default::default(),
+        ErrorBudget::default(),
+        Default::default(),
+        DEFAULT_TIMEOUT,
+    )
+    .await
+    .unwrap();
+}
+
+// Group 1's workers can't reach their leader for the first second, so they
+// may have to retry sending their shares.
+#[tokio::test]
+async fn test_partitioned_leader() {
+    let protocol = ProtocolWrapper::new(true, false, 2, 1, 100, false);
+    let experiment = Experiment::new_sample_keys(protocol, 2, 3, false);
+    let config = config::from_string("").await.unwrap();
+    let partitions = "group1/leader1@0s..1s".parse().unwrap();
+    run_in_process(
+        experiment,
+        config,
+        None,
+        partitions,
+        ErrorBudget::default(),
+        Default::default(),
+        DEFAULT_TIMEOUT,
+    )
+    .await
+    .unwrap();
+}
+
+// A few random topologies, one at a time.
+#[tokio::test]
+async fn test_fuzz_topology() {
+    let bounds = TopologyBounds::default();
+    for seed in 0..3 {
+        let experiment = Experiment::random(seed, &bounds);
+        let config = config::from_string("").await.unwrap();
+        run_in_process(
+            experiment,
+            config,
+            None,
+            Default::default(),
+            ErrorBudget::default(),
+            Default::default(),
+            DEFAULT_TIMEOUT,
+        )
+        .await
+        .unwrap_or_else(|err| panic!("seed {}: {}", seed, err));
+    }
 }
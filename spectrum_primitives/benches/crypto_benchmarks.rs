@@ -2,7 +2,10 @@
 use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
 use rand::thread_rng;
 use spectrum_primitives::pir;
-use spectrum_primitives::{Bytes, Dpf, MultiKeyVdpf, TwoKeyVdpf, Vdpf};
+use spectrum_primitives::{
+    Bytes, ChaChaPrg, Dpf, MultiKeyBls12Vdpf, MultiKeyRistrettoVdpf, MultiKeyVdpf, TwoKeyFp61Vdpf,
+    TwoKeyGf128Vdpf, TwoKeyVdpf, Vdpf,
+};
 use std::fmt::{self, Display};
 use std::iter::repeat_with;
 
@@ -25,15 +28,21 @@ fn criterion_benchmark(c: &mut Criterion) {
     static SIZES: [usize; 6] = [KB, 10 * KB, 100 * KB, 250 * KB, 500 * KB, 1 * MB];
     static CHANNELS: [usize; 6] = [1, 10, 100, 1000, 10000, 100000];
 
-    let mut group = c.benchmark_group("DPF (AES) Evaluation");
+    let mut group = c.benchmark_group("DPF (PRG) Evaluation");
     for size in SIZES.iter() {
         group.throughput(Throughput::Bytes(*size as u64));
-        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
+        group.bench_with_input(BenchmarkId::new("aes", size), size, |b, &size| {
             let dpf = TwoKeyVdpf::with_channels_msg_size(1, size);
             let keys = dpf.gen_empty();
             let key = &keys[0];
             b.iter_batched(|| key.clone(), |key| dpf.eval(key), BatchSize::LargeInput)
         });
+        group.bench_with_input(BenchmarkId::new("chacha", size), size, |b, &size| {
+            let dpf = TwoKeyVdpf::with_channels_msg_size_prg(1, ChaChaPrg::new(size));
+            let keys = dpf.gen_empty();
+            let key = &keys[0];
+            b.iter_batched(|| key.clone(), |key| dpf.eval(key), BatchSize::LargeInput)
+        });
     }
     group.finish();
 
@@ -41,12 +50,24 @@ fn criterion_benchmark(c: &mut Criterion) {
     for size in SIZES.iter() {
         let size = size / 10;
         group.throughput(Throughput::Bytes(size as u64));
-        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+        group.bench_with_input(BenchmarkId::new("jubjub", size), &size, |b, &size| {
             let dpf = MultiKeyVdpf::with_channels_parties_msg_size(1, 3, size);
             let keys = dpf.gen_empty();
             let key = &keys[0];
             b.iter_batched(|| key.clone(), |key| dpf.eval(key), BatchSize::LargeInput)
         });
+        group.bench_with_input(BenchmarkId::new("ristretto", size), &size, |b, &size| {
+            let dpf = MultiKeyRistrettoVdpf::with_channels_parties_msg_size(1, 3, size);
+            let keys = dpf.gen_empty();
+            let key = &keys[0];
+            b.iter_batched(|| key.clone(), |key| dpf.eval(key), BatchSize::LargeInput)
+        });
+        group.bench_with_input(BenchmarkId::new("bls12_381", size), &size, |b, &size| {
+            let dpf = MultiKeyBls12Vdpf::with_channels_parties_msg_size(1, 3, size);
+            let keys = dpf.gen_empty();
+            let key = &keys[0];
+            b.iter_batched(|| key.clone(), |key| dpf.eval(key), BatchSize::LargeInput)
+        });
     }
     group.finish();
 
@@ -99,10 +120,61 @@ fn criterion_benchmark(c: &mut Criterion) {
     }
     group.finish();
 
+    // Audit cost is dominated by auth key arithmetic, which scales with channels.
+    let mut group = c.benchmark_group("Vdpf.gen_audit() (AES) by auth key field");
+    for channels in CHANNELS.iter().take(4) {
+        group.bench_with_input(
+            BenchmarkId::new("jubjub", channels),
+            channels,
+            |b, &channels| {
+                let vdpf = TwoKeyVdpf::with_channels_msg_size(channels, KB);
+                let auth_keys = vdpf.new_access_keys();
+                let dpf_keys = vdpf.gen_empty();
+                let proof_share = &vdpf.gen_proofs_noop()[0];
+                b.iter_batched(
+                    || proof_share.clone(),
+                    |proof| vdpf.gen_audit(&auth_keys, &dpf_keys[0], proof),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("Fp61", channels),
+            channels,
+            |b, &channels| {
+                let vdpf = TwoKeyFp61Vdpf::with_channels_msg_size(channels, KB);
+                let auth_keys = vdpf.new_access_keys();
+                let dpf_keys = vdpf.gen_empty();
+                let proof_share = &vdpf.gen_proofs_noop()[0];
+                b.iter_batched(
+                    || proof_share.clone(),
+                    |proof| vdpf.gen_audit(&auth_keys, &dpf_keys[0], proof),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("GF(2^128)", channels),
+            channels,
+            |b, &channels| {
+                let vdpf = TwoKeyGf128Vdpf::with_channels_msg_size(channels, KB);
+                let auth_keys = vdpf.new_access_keys();
+                let dpf_keys = vdpf.gen_empty();
+                let proof_share = &vdpf.gen_proofs_noop()[0];
+                b.iter_batched(
+                    || proof_share.clone(),
+                    |proof| vdpf.gen_audit(&auth_keys, &dpf_keys[0], proof),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+
     let mut group = c.benchmark_group("Vdpf.gen_audit() (SH)");
     for size in SIZES.iter() {
         group.throughput(Throughput::Bytes(*size as u64));
-        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
+        group.bench_with_input(BenchmarkId::new("jubjub", size), size, |b, &size| {
             let vdpf = MultiKeyVdpf::with_channels_parties_msg_size(1, 3, size);
             let auth_keys = vdpf.new_access_keys();
             let dpf_keys = vdpf.gen_empty();
@@ -115,6 +187,71 @@ fn criterion_benchmark(c: &mut Criterion) {
                 BatchSize::LargeInput,
             )
         });
+        group.bench_with_input(BenchmarkId::new("ristretto", size), size, |b, &size| {
+            let vdpf = MultiKeyRistrettoVdpf::with_channels_parties_msg_size(1, 3, size);
+            let auth_keys = vdpf.new_access_keys();
+            let dpf_keys = vdpf.gen_empty();
+            let proof_shares = vdpf.gen_proofs_noop();
+            let dpf_key = &dpf_keys[0];
+            let proof_share = &proof_shares[0];
+            b.iter_batched(
+                || proof_share.clone(),
+                |proof| vdpf.gen_audit(&auth_keys, dpf_key, proof),
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("bls12_381", size), size, |b, &size| {
+            let vdpf = MultiKeyBls12Vdpf::with_channels_parties_msg_size(1, 3, size);
+            let auth_keys = vdpf.new_access_keys();
+            let dpf_keys = vdpf.gen_empty();
+            let proof_shares = vdpf.gen_proofs_noop();
+            let dpf_key = &dpf_keys[0];
+            let proof_share = &proof_shares[0];
+            b.iter_batched(
+                || proof_share.clone(),
+                |proof| vdpf.gen_audit(&auth_keys, dpf_key, proof),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+
+    // Proofs only read the keys' seeds and bits, so they shouldn't cost more
+    // for bigger messages; "cloned" is what copying the keys first would add.
+    let mut group = c.benchmark_group("Vdpf.gen_proofs() (AES)");
+    for size in SIZES.iter() {
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::new("borrowed", size), size, |b, &size| {
+            let vdpf = TwoKeyVdpf::with_channels_msg_size(1, size);
+            let auth_keys = vdpf.new_access_keys();
+            let dpf_keys = vdpf.gen(Bytes::random(size, &mut thread_rng()), 0);
+            b.iter(|| vdpf.gen_proofs(&auth_keys[0], 0, &dpf_keys))
+        });
+        group.bench_with_input(BenchmarkId::new("cloned", size), size, |b, &size| {
+            let vdpf = TwoKeyVdpf::with_channels_msg_size(1, size);
+            let auth_keys = vdpf.new_access_keys();
+            let dpf_keys = vdpf.gen(Bytes::random(size, &mut thread_rng()), 0);
+            b.iter(|| vdpf.gen_proofs(&auth_keys[0], 0, &dpf_keys.clone()))
+        });
+    }
+    group.finish();
+
+    // Client-side keygen; compare with `--features spectrum_primitives/parallel`.
+    let mut group = c.benchmark_group("Vdpf.gen_proofs() (SH)");
+    for channels in CHANNELS.iter().take(5) {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(channels),
+            channels,
+            |b, &channels| {
+                let vdpf = MultiKeyVdpf::with_channels_parties_msg_size(channels, 3, KB);
+                let auth_keys = vdpf.new_access_keys();
+                b.iter_batched(
+                    || vdpf.gen_empty(),
+                    |dpf_keys| vdpf.gen_proofs(&auth_keys[0], 0, &dpf_keys),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
     }
     group.finish();
 
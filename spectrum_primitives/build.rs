@@ -0,0 +1,13 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // The GPU kernel, compiled for the CPU too, so tests can check it without
+    // a GPU (see `constructions/jubjub/gpu.rs`).
+    #[cfg(feature = "gpu")]
+    {
+        println!("cargo:rerun-if-changed=src/constructions/jubjub/fixed_base.cl");
+        println!("cargo:rerun-if-changed=src/constructions/jubjub/fixed_base_host.c");
+        cc::Build::new()
+            .file("src/constructions/jubjub/fixed_base_host.c")
+            .compile("fixed_base_host");
+    }
+}
@@ -44,8 +44,66 @@ macro_rules! check_monoid_laws {
 pub trait SpecialExponentMonoid: Monoid {
     type Exponent: Monoid;
 
+    /// Whatever an implementation precomputes about a base to raise it to
+    /// many exponents faster (see `pow_fixed`).
+    type FixedBase;
+
     /// Raise `self` to the `exp`th power.
     fn pow(&self, exp: Self::Exponent) -> Self;
+
+    /// Precompute `self` as a fixed base.
+    fn fixed_base(&self) -> Self::FixedBase;
+
+    /// Same as `base.pow(exp)`, where `table` is `base.fixed_base()`.
+    fn pow_fixed(table: &Self::FixedBase, exp: &Self::Exponent) -> Self;
+
+    /// Same as `pow_many`, with each base precomputed (see `fixed_base`).
+    fn pow_many_fixed(tables: &[Self::FixedBase], exp: &Self::Exponent) -> Vec<Self> {
+        tables
+            .iter()
+            .map(|table| Self::pow_fixed(table, exp))
+            .collect()
+    }
+
+    /// Add each of `tables` raised to the `exp`th power into the matching one
+    /// of `out` (see `pow_many_fixed`).
+    ///
+    /// Same as adding in the results of `pow_many_fixed`, without collecting
+    /// them first. Panics if the lengths differ.
+    fn pow_many_fixed_into(tables: &[Self::FixedBase], exp: &Self::Exponent, out: &mut [Self]) {
+        assert_eq!(tables.len(), out.len(), "need one output per base");
+        for (acc, table) in out.iter_mut().zip(tables) {
+            *acc = std::mem::replace(acc, Self::zero()) + Self::pow_fixed(table, exp);
+        }
+    }
+
+    /// Raise each of `bases` to the `exp`th power.
+    ///
+    /// Same as calling `pow` on each, but lets implementations batch the work
+    /// (the group PRG raises a fixed set of generators to each seed).
+    fn pow_many(bases: &[Self], exp: &Self::Exponent) -> Vec<Self>
+    where
+        Self::Exponent: Clone,
+    {
+        bases.iter().map(|base| base.pow(exp.clone())).collect()
+    }
+
+    /// The sum of each of `bases` raised to the matching one of `exps`
+    /// (multi-scalar multiplication, in additive notation).
+    ///
+    /// Same as summing the `pow`s, but lets implementations share work across
+    /// terms. Panics if the lengths differ.
+    fn msm(bases: &[Self], exps: &[Self::Exponent]) -> Self
+    where
+        Self::Exponent: Clone,
+    {
+        assert_eq!(bases.len(), exps.len(), "need one exponent per base");
+        bases
+            .iter()
+            .zip(exps)
+            .map(|(base, exp)| base.pow(exp.clone()))
+            .fold(Self::zero(), ops::Add::add)
+    }
 }
 
 #[cfg(test)]
@@ -87,6 +145,82 @@ macro_rules! check_monoid_custom_exponent {
                     );
                 }
 
+                /// Check batched exponentiation matches one-at-a-time.
+                #[test]
+                fn test_pow_many(
+                    bases in prop::collection::vec(any::<$type>(), 0..10),
+                    exp: <$type as SpecialExponentMonoid>::Exponent,
+                ) {
+                    let expected: Vec<$type> = bases
+                        .iter()
+                        .map(|base| base.pow(exp.clone()))
+                        .collect();
+                    prop_assert_eq!(
+                        <$type as SpecialExponentMonoid>::pow_many(&bases, &exp),
+                        expected
+                    );
+                }
+
+                /// Check fixed-base exponentiation matches `pow`.
+                #[test]
+                fn test_pow_fixed(
+                    bases in prop::collection::vec(any::<$type>(), 0..10),
+                    exp: <$type as SpecialExponentMonoid>::Exponent,
+                ) {
+                    let tables: Vec<_> = bases.iter().map(|base| base.fixed_base()).collect();
+                    for (base, table) in bases.iter().zip(tables.iter()) {
+                        prop_assert_eq!(
+                            <$type as SpecialExponentMonoid>::pow_fixed(table, &exp),
+                            base.pow(exp.clone())
+                        );
+                    }
+                    prop_assert_eq!(
+                        <$type as SpecialExponentMonoid>::pow_many_fixed(&tables, &exp),
+                        <$type as SpecialExponentMonoid>::pow_many(&bases, &exp)
+                    );
+                }
+
+                /// Check accumulating fixed-base powers matches adding them.
+                #[test]
+                fn test_pow_many_fixed_into(
+                    terms in prop::collection::vec((any::<$type>(), any::<$type>()), 0..10),
+                    exp: <$type as SpecialExponentMonoid>::Exponent,
+                ) {
+                    let (bases, mut out): (Vec<$type>, Vec<$type>) = terms.into_iter().unzip();
+                    let tables: Vec<_> = bases.iter().map(|base| base.fixed_base()).collect();
+                    let expected: Vec<$type> = out
+                        .iter()
+                        .cloned()
+                        .zip(bases.iter())
+                        .map(|(acc, base)| acc + base.pow(exp.clone()))
+                        .collect();
+                    <$type as SpecialExponentMonoid>::pow_many_fixed_into(&tables, &exp, &mut out);
+                    prop_assert_eq!(out, expected);
+                }
+
+                /// Check multi-exponentiation matches a product of powers.
+                #[test]
+                fn test_msm(
+                    terms in prop::collection::vec(
+                        (
+                            any::<$type>(),
+                            any::<<$type as SpecialExponentMonoid>::Exponent>(),
+                        ),
+                        0..40,
+                    ),
+                ) {
+                    let (bases, exps): (Vec<$type>, Vec<_>) = terms.into_iter().unzip();
+                    let expected = bases
+                        .iter()
+                        .zip(exps.iter())
+                        .map(|(base, exp)| base.pow(exp.clone()))
+                        .fold(<$type as Monoid>::zero(), |acc, x| acc + x);
+                    prop_assert_eq!(
+                        <$type as SpecialExponentMonoid>::msm(&bases, &exps),
+                        expected
+                    );
+                }
+
                 /// Check (x*y)^a == x^a * y^a
                 #[test]
                 fn test_exponent_distributive(
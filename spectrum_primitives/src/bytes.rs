@@ -1,10 +1,15 @@
 //! Spectrum implementation.
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::convert::AsRef;
+use std::fmt;
 use std::iter::FromIterator;
 use std::ops;
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::Zeroize;
 
-#[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Bytes(Vec<u8>);
 
 impl Bytes {
@@ -34,14 +39,85 @@ impl Bytes {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    /// Consecutive `chunk_size`-byte slices, if `chunk_size` divides the length.
+    pub fn chunks_exact(&self, chunk_size: usize) -> Option<std::slice::ChunksExact<'_, u8>> {
+        if chunk_size == 0 || !self.len().is_multiple_of(chunk_size) {
+            return None;
+        }
+        Some(self.0.chunks_exact(chunk_size))
+    }
+
+    /// Consecutive `chunk_size`-byte chunks, the last one padded out with
+    /// zeros (so only it gets copied).
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub fn chunks_padded(&self, chunk_size: usize) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        self.0.chunks(chunk_size).map(move |chunk| {
+            if chunk.len() == chunk_size {
+                Cow::Borrowed(chunk)
+            } else {
+                let mut padded = chunk.to_vec();
+                padded.resize(chunk_size, 0);
+                Cow::Owned(padded)
+            }
+        })
+    }
+
+    /// XOR with `rhs`, or an error if the lengths differ.
+    ///
+    /// Use this (rather than `^`, which panics) when either side came from
+    /// the network.
+    pub fn checked_xor(mut self, rhs: &Bytes) -> Result<Bytes, LengthMismatch> {
+        self.checked_xor_assign(rhs)?;
+        Ok(self)
+    }
+
+    /// XOR `rhs` into `self`, or an error (leaving `self` unchanged) if the
+    /// lengths differ.
+    pub fn checked_xor_assign(&mut self, rhs: &Bytes) -> Result<(), LengthMismatch> {
+        if self.len() != rhs.len() {
+            return Err(LengthMismatch {
+                expected: self.len(),
+                actual: rhs.len(),
+            });
+        }
+        *self ^= rhs;
+        Ok(())
+    }
 }
 
+/// Tried to combine `Bytes` of different lengths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthMismatch {
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "length mismatch: expected {} bytes, got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for LengthMismatch {}
+
 impl AsRef<[u8]> for Bytes {
     fn as_ref(&self) -> &[u8] {
         &self.0
     }
 }
 
+impl AsMut<[u8]> for Bytes {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
 impl From<Vec<u8>> for Bytes {
     fn from(other: Vec<u8>) -> Self {
         Bytes(other)
@@ -145,6 +221,19 @@ impl ops::BitXorAssign<Bytes> for Bytes {
     }
 }
 
+/// Constant-time in the contents (but not the lengths).
+impl ConstantTimeEq for Bytes {
+    fn ct_eq(&self, other: &Bytes) -> Choice {
+        self.0.as_slice().ct_eq(other.0.as_slice())
+    }
+}
+
+impl Zeroize for Bytes {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 #[cfg(any(test, feature = "testing"))]
 use proptest::prelude::*;
 
@@ -219,5 +308,58 @@ pub mod tests {
             prop_assert!(is_all_zero(value),
                     "XORing with self should give 0.");
         }
+
+        #[test]
+        fn test_bytes_checked_xor_matches_xor(
+            (a, b) in SIZE_RANGE.prop_flat_map(|size| (bytes(size), bytes(size)))
+        ) {
+            prop_assert_eq!(a.clone().checked_xor(&b), Ok(a ^ b));
+        }
+
+        #[test]
+        fn test_bytes_checked_xor_length_mismatch(a: Bytes, b: Bytes) {
+            prop_assume!(a.len() != b.len());
+            let expected = LengthMismatch { expected: a.len(), actual: b.len() };
+            let mut c = a.clone();
+            prop_assert_eq!(c.checked_xor_assign(&b), Err(expected));
+            prop_assert_eq!(&c, &a, "failed XOR should leave value unchanged");
+            prop_assert_eq!(a.checked_xor(&b), Err(expected));
+        }
+
+        #[test]
+        fn test_bytes_chunks_exact(value: Bytes, chunk_size in 1..64usize) {
+            match value.chunks_exact(chunk_size) {
+                Some(chunks) => {
+                    prop_assert_eq!(value.len() % chunk_size, 0);
+                    let chunks: Vec<&[u8]> = chunks.collect();
+                    prop_assert!(chunks.iter().all(|chunk| chunk.len() == chunk_size));
+                    prop_assert_eq!(chunks.concat(), value.0);
+                }
+                None => prop_assert_ne!(value.len() % chunk_size, 0),
+            }
+        }
+
+        #[test]
+        fn test_bytes_chunks_padded(value: Bytes, chunk_size in 1..64usize) {
+            let chunks: Vec<Cow<[u8]>> = value.chunks_padded(chunk_size).collect();
+            prop_assert_eq!(chunks.len(), value.len().div_ceil(chunk_size));
+            prop_assert!(chunks.iter().all(|chunk| chunk.len() == chunk_size));
+            let joined = chunks.concat();
+            prop_assert_eq!(&joined[..value.len()], value.as_ref());
+            prop_assert!(joined[value.len()..].iter().all(|b| *b == 0));
+        }
+
+        #[test]
+        fn test_bytes_ct_eq_matches_eq(a: Bytes, b: Bytes) {
+            prop_assert_eq!(bool::from(a.ct_eq(&b)), a == b);
+            prop_assert!(bool::from(a.ct_eq(&a.clone())));
+        }
+    }
+
+    #[test]
+    fn test_bytes_chunks_zero_size() {
+        assert!(Bytes::empty(4).chunks_exact(0).is_none());
+        assert_eq!(Bytes::empty(0).chunks_exact(3).unwrap().count(), 0);
+        assert_eq!(Bytes::empty(0).chunks_padded(3).count(), 0);
     }
 }
@@ -1,13 +1,28 @@
 use std::convert::TryFrom;
 
 use derivative::Derivative;
-use openssl::symm::{encrypt, Cipher};
-use serde::{Deserialize, Serialize};
+use openssl::symm::{encrypt, Cipher, Crypter, Mode};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
 
 use crate::bytes::Bytes;
-use crate::prg::Prg;
+#[cfg(any(test, feature = "testing"))]
+use crate::dpf::TreeDpf;
+use crate::prg::{ChunkedPrg, Prg};
 
 pub const SEED_SIZE: usize = 16; // in bytes
+const BLOCK_SIZE: usize = 16; // in bytes
+/// Keystream is generated (on the stack) this much at a time when XORing it
+/// into an existing buffer.
+const KEYSTREAM_CHUNK_SIZE: usize = 4096; // in bytes
+
+/// Outputs at least this long are expanded in segments, in parallel (with the
+/// `parallel` feature).
+#[cfg(any(test, feature = "parallel"))]
+const PARALLEL_THRESHOLD: usize = 1 << 20; // in bytes
+/// A whole number of blocks, so that every segment starts a fresh block.
+#[cfg(any(test, feature = "parallel"))]
+const PARALLEL_SEGMENT_SIZE: usize = 1 << 16; // in bytes
 
 /// PRG uses AES to expand a seed to desired length
 #[derive(Clone, PartialEq, Copy, Serialize, Deserialize, Derivative)]
@@ -20,6 +35,8 @@ pub struct AesPrg {
 }
 
 /// seed for AES-based PRG
+///
+/// Zeroized on drop.
 #[derive(Default, Clone, PartialEq, Eq, Debug, Hash)]
 pub struct AesSeed {
     bytes: Bytes,
@@ -35,15 +52,27 @@ impl AesSeed {
     }
 }
 
+impl Zeroize for AesSeed {
+    fn zeroize(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl Drop for AesSeed {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl From<AesSeed> for Bytes {
-    fn from(value: AesSeed) -> Bytes {
-        value.bytes
+    fn from(mut value: AesSeed) -> Bytes {
+        std::mem::take(&mut value.bytes)
     }
 }
 
 impl From<AesSeed> for Vec<u8> {
     fn from(value: AesSeed) -> Vec<u8> {
-        value.bytes.into()
+        Bytes::from(value).into()
     }
 }
 
@@ -60,6 +89,20 @@ impl TryFrom<Vec<u8>> for AesSeed {
     }
 }
 
+impl Serialize for AesSeed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.bytes.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AesSeed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let len = bytes.len();
+        AesSeed::try_from(bytes).map_err(|()| de::Error::invalid_length(len, &"a 16-byte seed"))
+    }
+}
+
 impl AesPrg {
     pub fn new(eval_size: usize) -> Self {
         assert!(
@@ -72,6 +115,49 @@ impl AesPrg {
             cipher: Cipher::aes_128_ctr(),
         }
     }
+
+    /// XORs the keystream for `seed`, starting `offset` bytes in, into `out`.
+    ///
+    /// The counter is the whole big-endian IV, starting at zero, so this
+    /// starts it at the block containing `offset`.
+    fn apply_keystream(&self, seed: &AesSeed, offset: usize, out: &mut [u8]) {
+        let iv = ((offset / BLOCK_SIZE) as u128).to_be_bytes();
+        let mut crypter =
+            Crypter::new(self.cipher, Mode::Encrypt, seed.bytes.as_ref(), Some(&iv)).unwrap();
+        let zeros = [0; KEYSTREAM_CHUNK_SIZE];
+        // CTR mode doesn't buffer, but `update()` wants room for a block extra.
+        let mut keystream = [0; KEYSTREAM_CHUNK_SIZE + BLOCK_SIZE];
+        crypter
+            .update(&zeros[..offset % BLOCK_SIZE], &mut keystream)
+            .unwrap();
+        for chunk in out.chunks_mut(KEYSTREAM_CHUNK_SIZE) {
+            let len = crypter
+                .update(&zeros[..chunk.len()], &mut keystream)
+                .unwrap();
+            debug_assert_eq!(len, chunk.len());
+            for (x, y) in chunk.iter_mut().zip(&keystream) {
+                *x ^= y;
+            }
+        }
+    }
+
+    /// XORs the keystream for `seed` into `data`, one segment per task on the
+    /// rayon thread pool.
+    #[cfg(feature = "parallel")]
+    fn apply_keystream_parallel(&self, seed: &AesSeed, data: &mut [u8]) {
+        use rayon::prelude::*;
+        data.par_chunks_mut(PARALLEL_SEGMENT_SIZE)
+            .enumerate()
+            .for_each(|(idx, segment)| {
+                self.eval_chunk_into(seed, idx * PARALLEL_SEGMENT_SIZE, segment)
+            });
+    }
+}
+
+/// PRG for expanding the nodes of a [`TreeDpf`](crate::dpf::TreeDpf): two
+/// child seeds, plus a byte for their bits.
+pub fn tree_node_prg() -> AesPrg {
+    AesPrg::new(2 * SEED_SIZE + 1)
 }
 
 // Implementation of an AES-based PRG
@@ -90,6 +176,13 @@ impl Prg for AesPrg {
 
     /// evaluates the PRG on the given seed
     fn eval(&self, seed: &AesSeed) -> Self::Output {
+        #[cfg(feature = "parallel")]
+        if self.eval_size >= PARALLEL_THRESHOLD {
+            let mut data = vec![0; self.eval_size];
+            self.apply_keystream_parallel(seed, &mut data);
+            return data.into();
+        }
+
         // nonce set to zero: PRG eval should be deterministic
         let iv: [u8; 16] = [0; 16];
 
@@ -111,11 +204,32 @@ impl Prg for AesPrg {
         ciphertext.into()
     }
 
+    /// XORs the PRG output into `out`.
+    ///
+    /// CTR mode XORs its keystream into the plaintext, so we XOR it into
+    /// `out` directly (a chunk at a time) rather than expanding the seed and
+    /// XORing afterwards.
+    fn eval_into(&self, seed: &AesSeed, out: &mut Bytes) {
+        assert_eq!(out.len(), self.eval_size);
+        #[cfg(feature = "parallel")]
+        if self.eval_size >= PARALLEL_THRESHOLD {
+            self.apply_keystream_parallel(seed, out.as_mut());
+            return;
+        }
+        self.apply_keystream(seed, 0, out.as_mut());
+    }
+
     fn null_output(&self) -> Bytes {
         Bytes::empty(self.eval_size)
     }
 }
 
+impl ChunkedPrg for AesPrg {
+    fn eval_chunk_into(&self, seed: &AesSeed, offset: usize, out: &mut [u8]) {
+        self.apply_keystream(seed, offset, out);
+    }
+}
+
 #[cfg(any(test, feature = "testing"))]
 use proptest::prelude::*;
 
@@ -144,9 +258,42 @@ impl Arbitrary for AesSeed {
     }
 }
 
+#[cfg(any(test, feature = "testing"))]
+impl Arbitrary for TreeDpf<AesPrg> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        const MAX_POINTS: usize = 20;
+        (any::<AesPrg>(), 1..=MAX_POINTS)
+            .prop_map(|(prg, points)| TreeDpf::new(prg, tree_node_prg(), points))
+            .boxed()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     check_prg!(AesPrg);
+    check_chunked_prg!(AesPrg);
     check_dpf!(crate::dpf::TwoKeyDpf<AesPrg>);
+    check_dpf!(crate::dpf::TreeDpf<AesPrg>, tree_dpf);
+
+    /// Big enough outputs take the parallel path (with the `parallel`
+    /// feature); they should match the single-call keystream.
+    #[test]
+    fn test_large_output() {
+        let size = PARALLEL_THRESHOLD + PARALLEL_SEGMENT_SIZE + 7;
+        let prg = AesPrg::new(size);
+        let seed = AesSeed::random();
+
+        let mut expected = vec![0; size];
+        prg.eval_chunk_into(&seed, 0, &mut expected);
+        assert_eq!(prg.eval(&seed), Bytes::from(expected.clone()));
+
+        let data = Bytes::from(vec![0xab; size]);
+        let mut out = data.clone();
+        prg.eval_into(&seed, &mut out);
+        assert_eq!(out, data ^ &Bytes::from(expected));
+    }
 }
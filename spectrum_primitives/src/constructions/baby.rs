@@ -49,6 +49,8 @@ impl<const N: u8> Group for IntMod<N> {
 
 impl<const N: u8> SpecialExponentMonoid for IntMod<N> {
     type Exponent = IntMod<N>;
+    // Nothing to precompute.
+    type FixedBase = Self;
 
     fn pow(&self, rhs: IntMod<N>) -> Self {
         let inner = (Integer::from(self.inner) * Integer::from(rhs.inner)) % Self::order();
@@ -56,6 +58,14 @@ impl<const N: u8> SpecialExponentMonoid for IntMod<N> {
             inner: inner.try_into().unwrap(),
         }
     }
+
+    fn fixed_base(&self) -> Self {
+        self.clone()
+    }
+
+    fn pow_fixed(table: &Self, exp: &Self) -> Self {
+        table.pow(exp.clone())
+    }
 }
 
 impl<const N: u8> ops::Add for IntMod<N> {
@@ -130,7 +140,7 @@ impl<const N: u8> TryFrom<u8> for IntMod<N> {
 //     }
 // }
 
-use rand::prelude::*;
+use rand::{prelude::*, Rng};
 
 impl<const N: u8> Sampleable for IntMod<N> {
     type Seed = <StdRng as SeedableRng>::Seed;
@@ -0,0 +1,545 @@
+//! The G1 group of BLS12-381, as a pairing-friendly alternative to Jubjub for
+//! seed-homomorphic PRGs.
+//!
+//! Points are 48 bytes (compressed), so unlike Jubjub and Ristretto points they
+//! don't fit the 32-byte chunks of `ElementVector`'s byte encodings: this is
+//! for trying out the multi-key VDPF, not for the wire.
+use std::convert::{TryFrom, TryInto};
+use std::hash::{Hash, Hasher};
+use std::iter::{repeat_with, Sum};
+use std::ops;
+
+use ::bls12_381::{G1Affine, G1Projective, Scalar as BlsScalar};
+use rand::RngCore;
+use rug::{integer::Order, Integer};
+use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::DefaultIsZeroes;
+
+use crate::algebra::{Field, Group, Monoid, SpecialExponentMonoid};
+use crate::bytes::Bytes;
+use crate::constructions::aes_prg::{AesPrg, AesSeed};
+use crate::util::Sampleable;
+
+#[cfg(any(test, feature = "testing"))]
+use proptest::prelude::*;
+
+// 0x73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001 (the
+// order of G1), least-significant limb first.
+const ORDER: [u64; 4] = [
+    0xffff_ffff_0000_0001_u64,
+    0x53bd_a402_fffe_5bfe_u64,
+    0x3339_d808_09a1_d805_u64,
+    0x73ed_a753_299d_7d48_u64,
+];
+
+// size of (compressed) group elements
+pub const ELEMENT_BYTES: usize = 48;
+// size of scalars
+pub const SCALAR_BYTES: usize = 32;
+// bytes to sample for a uniformly random scalar
+const WIDE_BYTES: usize = 64;
+const BYTE_ORDER: Order = Order::LsfLe;
+
+// Flags in the first byte of a compressed point.
+const COMPRESSION_FLAG: u8 = 0x80;
+const INFINITY_FLAG: u8 = 0x40;
+
+fn wide(bytes: &[u8]) -> [u8; WIDE_BYTES] {
+    bytes.try_into().expect("need 64 bytes")
+}
+
+fn random_wide() -> [u8; WIDE_BYTES] {
+    let mut bytes = [0u8; WIDE_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+// `n` chunks of 64 pseudorandom bytes from `seed`.
+fn wide_from_seed(seed: &AesSeed, n: usize) -> Vec<[u8; WIDE_BYTES]> {
+    use crate::prg::Prg;
+    if n == 0 {
+        return vec![];
+    }
+    let prg = AesPrg::new(WIDE_BYTES * n);
+    let rand_bytes: Vec<u8> = prg.eval(seed).into();
+    rand_bytes.chunks_exact(WIDE_BYTES).map(wide).collect()
+}
+
+// `n` points from `seed`, with unknown discrete logs: try successive 48-byte
+// chunks of the PRG output as x-coordinates until one is on the curve, then
+// clear the cofactor.
+fn points_from_seed(seed: &AesSeed, n: usize) -> Vec<G1Projective> {
+    use crate::prg::ChunkedPrg;
+    let prg = AesPrg::new(ELEMENT_BYTES);
+    let mut offset = 0;
+    repeat_with(|| loop {
+        let mut bytes = [0u8; ELEMENT_BYTES];
+        prg.eval_chunk_into(seed, offset, &mut bytes);
+        offset += ELEMENT_BYTES;
+        bytes[0] = (bytes[0] | COMPRESSION_FLAG) & !INFINITY_FLAG;
+        let point: Option<G1Affine> = G1Affine::from_compressed_unchecked(&bytes).into();
+        if let Some(point) = point {
+            let point = G1Projective::from(point).clear_cofactor();
+            if !bool::from(point.is_identity()) {
+                return point;
+            }
+        }
+    })
+    .take(n)
+    .collect()
+}
+
+/// A point in G1.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "Vec<u8>", into = "Vec<u8>")]
+pub struct CurvePoint {
+    inner: G1Projective,
+}
+
+impl CurvePoint {
+    pub fn generator() -> Self {
+        G1Projective::generator().into()
+    }
+
+    fn to_compressed(self) -> [u8; ELEMENT_BYTES] {
+        G1Affine::from(self.inner).to_compressed()
+    }
+}
+
+impl From<Scalar> for CurvePoint {
+    fn from(scalar: Scalar) -> Self {
+        (G1Projective::generator() * scalar.inner).into() // exponentiation!
+    }
+}
+
+// Like Jubjub, messages get encoded as points by decoding their bytes, which
+// only works for some messages.
+impl TryFrom<Bytes> for CurvePoint {
+    type Error = &'static str;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        let mut bytes: Vec<u8> = value.into();
+        if bytes.len() < ELEMENT_BYTES {
+            bytes.extend(vec![0u8; ELEMENT_BYTES - bytes.len()]);
+        }
+        CurvePoint::try_from(bytes)
+    }
+}
+
+impl From<CurvePoint> for Bytes {
+    fn from(value: CurvePoint) -> Bytes {
+        Vec::<u8>::from(value).into()
+    }
+}
+
+impl From<CurvePoint> for Vec<u8> {
+    fn from(value: CurvePoint) -> Self {
+        value.to_compressed().to_vec()
+    }
+}
+
+impl TryFrom<Vec<u8>> for CurvePoint {
+    type Error = &'static str;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        let bytes: [u8; ELEMENT_BYTES] = value.try_into().map_err(|_| "bad bytes size")?;
+        let point: Option<G1Affine> = G1Affine::from_compressed(&bytes).into();
+        point
+            .map(|point| G1Projective::from(point).into())
+            .ok_or("bad conversion from bytes")
+    }
+}
+
+impl Sampleable for CurvePoint {
+    type Seed = AesSeed;
+
+    fn sample() -> Self {
+        <G1Projective as ::group::Group>::random(&mut rand::thread_rng()).into()
+    }
+
+    fn sample_many_from_seed(seed: &Self::Seed, n: usize) -> Vec<Self> {
+        points_from_seed(seed, n)
+            .into_iter()
+            .map(CurvePoint::from)
+            .collect()
+    }
+}
+
+impl Monoid for CurvePoint {
+    fn zero() -> Self {
+        G1Projective::identity().into()
+    }
+}
+
+impl Group for CurvePoint {
+    fn order() -> Integer {
+        Integer::from_digits(&ORDER, BYTE_ORDER)
+    }
+}
+
+impl ops::Add for CurvePoint {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        (self.inner + rhs.inner).into()
+    }
+}
+
+impl ops::AddAssign for CurvePoint {
+    fn add_assign(&mut self, rhs: Self) {
+        self.inner += rhs.inner;
+    }
+}
+
+impl ops::Neg for CurvePoint {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        (-self.inner).into()
+    }
+}
+
+impl ops::Sub for CurvePoint {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        (self.inner - rhs.inner).into()
+    }
+}
+
+impl Sum for CurvePoint {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> CurvePoint {
+        let mut total = <Self as Monoid>::zero();
+        iter.for_each(|value| total += value);
+        total
+    }
+}
+
+// Boilerplate: conversions etc.
+impl From<G1Projective> for CurvePoint {
+    fn from(inner: G1Projective) -> Self {
+        CurvePoint { inner }
+    }
+}
+
+impl Hash for CurvePoint {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_compressed().hash(state);
+    }
+}
+
+impl ConstantTimeEq for CurvePoint {
+    fn ct_eq(&self, rhs: &CurvePoint) -> Choice {
+        self.inner.ct_eq(&rhs.inner)
+    }
+}
+
+impl Default for CurvePoint {
+    fn default() -> Self {
+        CurvePoint::zero()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Arbitrary for CurvePoint {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        any::<Scalar>().prop_map(CurvePoint::from).boxed()
+    }
+}
+
+/// A scalar (exponent) for G1.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "Vec<u8>", into = "Vec<u8>")]
+pub struct Scalar {
+    inner: BlsScalar,
+}
+
+impl Monoid for Scalar {
+    fn zero() -> Self {
+        BlsScalar::zero().into()
+    }
+}
+
+impl Group for Scalar {
+    fn order() -> Integer {
+        Integer::from_digits(&ORDER, BYTE_ORDER)
+    }
+}
+
+impl Field for Scalar {
+    fn mul_invert(&self) -> Self {
+        Option::<BlsScalar>::from(self.inner.invert())
+            .expect("zero has no inverse")
+            .into()
+    }
+
+    fn one() -> Self {
+        BlsScalar::one().into()
+    }
+}
+
+impl ops::Add for Scalar {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        (self.inner + rhs.inner).into()
+    }
+}
+
+impl ops::AddAssign for Scalar {
+    fn add_assign(&mut self, rhs: Self) {
+        self.inner += rhs.inner;
+    }
+}
+
+impl ops::Neg for Scalar {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        (-self.inner).into()
+    }
+}
+
+impl ops::Sub for Scalar {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        (self.inner - rhs.inner).into()
+    }
+}
+
+impl ops::Mul for Scalar {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        (self.inner * rhs.inner).into()
+    }
+}
+
+impl Sum for Scalar {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Scalar {
+        let mut total = <Self as Monoid>::zero();
+        iter.for_each(|value| total += value);
+        total
+    }
+}
+
+// Boilerplate: conversions etc.
+impl From<BlsScalar> for Scalar {
+    fn from(inner: BlsScalar) -> Self {
+        Scalar { inner }
+    }
+}
+
+impl From<Scalar> for Bytes {
+    fn from(value: Scalar) -> Bytes {
+        Bytes::from(value.inner.to_bytes().to_vec())
+    }
+}
+
+impl TryFrom<Bytes> for Scalar {
+    type Error = String;
+
+    fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
+        let len = bytes.len();
+        if len <= SCALAR_BYTES {
+            let mut bytes_arr: [u8; SCALAR_BYTES] = [0; SCALAR_BYTES];
+            bytes_arr[..len].copy_from_slice(bytes.as_ref());
+            Option::<BlsScalar>::from(BlsScalar::from_bytes(&bytes_arr))
+                .map(Scalar::from)
+                .ok_or_else(|| "Converting from bytes failed.".to_string())
+        } else if len == WIDE_BYTES {
+            Ok(BlsScalar::from_bytes_wide(&wide(bytes.as_ref())).into())
+        } else {
+            Err(format!("invalid byte length {}", bytes.len()))
+        }
+    }
+}
+
+impl TryFrom<Vec<u8>> for Scalar {
+    type Error = &'static str;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Option::<BlsScalar>::from(BlsScalar::from_bytes(
+            &value.try_into().map_err(|_| "vec was wrong size")?,
+        ))
+        .map(Scalar::from)
+        .ok_or("converting from bytes failed")
+    }
+}
+
+impl From<Scalar> for Vec<u8> {
+    fn from(value: Scalar) -> Vec<u8> {
+        value.inner.to_bytes().into()
+    }
+}
+
+impl Hash for Scalar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.to_bytes().hash(state);
+    }
+}
+
+impl ConstantTimeEq for Scalar {
+    fn ct_eq(&self, rhs: &Scalar) -> Choice {
+        self.inner.ct_eq(&rhs.inner)
+    }
+}
+
+impl Default for Scalar {
+    fn default() -> Self {
+        Scalar::zero()
+    }
+}
+
+impl DefaultIsZeroes for Scalar {}
+
+#[cfg(any(test, feature = "testing"))]
+impl Arbitrary for Scalar {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        proptest::collection::vec(any::<u8>(), WIDE_BYTES)
+            .prop_map(|v| BlsScalar::from_bytes_wide(&wide(&v)).into())
+            .boxed()
+    }
+}
+
+impl Sampleable for Scalar {
+    type Seed = AesSeed;
+
+    fn sample() -> Self {
+        BlsScalar::from_bytes_wide(&random_wide()).into()
+    }
+
+    fn sample_many_from_seed(seed: &Self::Seed, n: usize) -> Vec<Self> {
+        wide_from_seed(seed, n)
+            .iter()
+            .map(|bytes| BlsScalar::from_bytes_wide(bytes).into())
+            .collect()
+    }
+}
+
+impl SpecialExponentMonoid for CurvePoint {
+    type Exponent = Scalar;
+    // The library has no precomputed multiples for G1: just the base.
+    type FixedBase = G1Projective;
+
+    fn pow(&self, exp: Self::Exponent) -> Self {
+        (self.inner * exp.inner).into()
+    }
+
+    fn fixed_base(&self) -> G1Projective {
+        self.inner
+    }
+
+    fn pow_fixed(base: &G1Projective, exp: &Scalar) -> Self {
+        (base * exp.inner).into()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn pow_many(bases: &[Self], exp: &Self::Exponent) -> Vec<Self> {
+        use rayon::prelude::*;
+        bases
+            .par_iter()
+            .map(|base| (base.inner * exp.inner).into())
+            .collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn pow_many_fixed(bases: &[G1Projective], exp: &Scalar) -> Vec<Self> {
+        use rayon::prelude::*;
+        bases
+            .par_iter()
+            .map(|base| (base * exp.inner).into())
+            .collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn pow_many_fixed_into(bases: &[G1Projective], exp: &Scalar, out: &mut [Self]) {
+        use rayon::prelude::*;
+        assert_eq!(bases.len(), out.len(), "need one output per base");
+        out.par_iter_mut()
+            .zip(bases)
+            .for_each(|(acc, base)| *acc += (base * exp.inner).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dpf::MultiKeyDpf;
+    use crate::prg::GroupPrg;
+
+    #[test]
+    fn test_order() {
+        assert_eq!(
+            CurvePoint::order(),
+            Integer::from_str_radix(
+                "73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001",
+                16
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            CurvePoint::generator().pow(-Scalar::one()),
+            -CurvePoint::generator()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_points_from_seed_in_subgroup(seed: AesSeed) {
+            for point in points_from_seed(&seed, 3) {
+                prop_assert!(bool::from(G1Affine::from(point).is_torsion_free()));
+            }
+        }
+    }
+
+    check_group_laws!(CurvePoint);
+    check_monoid_custom_exponent!(CurvePoint);
+    check_field_laws!(Scalar);
+    check_sampleable!(Scalar);
+    check_shareable!(Scalar);
+    check_linearly_shareable!(Scalar);
+
+    mod point {
+        use super::*;
+        check_sampleable!(CurvePoint);
+    }
+
+    check_roundtrip!(
+        CurvePoint,
+        Into::<Vec<u8>>::into,
+        |x| CurvePoint::try_from(x).unwrap(),
+        point_to_vec_u8_rt
+    );
+    check_roundtrip!(
+        CurvePoint,
+        |p: CurvePoint| serde_json::to_string(&p).unwrap(),
+        |s: String| serde_json::from_str(&s).unwrap(),
+        point_to_json_rt
+    );
+    check_prg!(GroupPrg<CurvePoint>);
+    check_seed_homomorphic_prg!(GroupPrg<CurvePoint>);
+
+    check_dpf!(MultiKeyDpf<GroupPrg<CurvePoint>>);
+
+    check_roundtrip!(
+        Scalar,
+        Into::<Vec<u8>>::into,
+        |x| Scalar::try_from(x).unwrap(),
+        scalar_to_vec_u8
+    );
+    check_roundtrip!(
+        Scalar,
+        |p: Scalar| serde_json::to_string(&p).unwrap(),
+        |s: String| serde_json::from_str(&s).unwrap(),
+        scalar_to_json_rt
+    );
+}
@@ -0,0 +1,192 @@
+use std::convert::TryFrom;
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
+
+use crate::bytes::Bytes;
+use crate::prg::{ChunkedPrg, Prg};
+
+pub const SEED_SIZE: usize = 16; // in bytes
+const WORD_SIZE: usize = 4; // in bytes
+
+/// PRG uses the ChaCha20 stream cipher to expand a seed to desired length.
+///
+/// A software alternative to [`AesPrg`](super::AesPrg), for machines without
+/// AES instructions (where it's much faster).
+#[derive(Clone, PartialEq, Eq, Copy, Debug, Serialize, Deserialize)]
+pub struct ChaChaPrg {
+    eval_size: usize,
+}
+
+/// seed for ChaCha-based PRG
+///
+/// Zeroized on drop.
+#[derive(Default, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct ChaChaSeed {
+    bytes: Bytes,
+}
+
+impl ChaChaSeed {
+    pub fn random() -> Self {
+        use rand::prelude::*;
+        let mut rand_seed_bytes = vec![0; SEED_SIZE];
+        thread_rng().fill_bytes(&mut rand_seed_bytes);
+        ChaChaSeed::try_from(rand_seed_bytes).expect("Correct seed size")
+    }
+}
+
+impl Zeroize for ChaChaSeed {
+    fn zeroize(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl Drop for ChaChaSeed {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl From<ChaChaSeed> for Bytes {
+    fn from(mut value: ChaChaSeed) -> Bytes {
+        std::mem::take(&mut value.bytes)
+    }
+}
+
+impl From<ChaChaSeed> for Vec<u8> {
+    fn from(value: ChaChaSeed) -> Vec<u8> {
+        Bytes::from(value).into()
+    }
+}
+
+impl TryFrom<Vec<u8>> for ChaChaSeed {
+    type Error = ();
+
+    fn try_from(other: Vec<u8>) -> Result<Self, ()> {
+        if other.len() != SEED_SIZE {
+            return Err(());
+        }
+        Ok(Self {
+            bytes: other.into(),
+        })
+    }
+}
+
+impl Serialize for ChaChaSeed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.bytes.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChaChaSeed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let len = bytes.len();
+        ChaChaSeed::try_from(bytes).map_err(|()| de::Error::invalid_length(len, &"a 16-byte seed"))
+    }
+}
+
+impl ChaChaPrg {
+    pub fn new(eval_size: usize) -> Self {
+        assert!(
+            SEED_SIZE <= eval_size,
+            "eval size must be at least the seed size"
+        );
+
+        ChaChaPrg { eval_size }
+    }
+
+    fn keystream(&self, seed: &ChaChaSeed) -> ChaCha20Rng {
+        // Seeds are the same size as AES seeds (so they convert to the same
+        // field elements); pad to a full ChaCha20 key. Stream/nonce are zero:
+        // PRG eval should be deterministic.
+        let mut key = [0; 32];
+        key[..SEED_SIZE].copy_from_slice(seed.bytes.as_ref());
+        let rng = ChaCha20Rng::from_seed(key);
+        key.zeroize();
+        rng
+    }
+}
+
+impl Prg for ChaChaPrg {
+    type Seed = ChaChaSeed;
+    type Output = Bytes;
+
+    /// generates a new (random) seed for the given PRG
+    fn new_seed() -> ChaChaSeed {
+        ChaChaSeed::random()
+    }
+
+    fn output_size(&self) -> usize {
+        self.eval_size
+    }
+
+    /// evaluates the PRG on the given seed
+    fn eval(&self, seed: &ChaChaSeed) -> Self::Output {
+        let mut data = vec![0; self.eval_size];
+        self.keystream(seed).fill_bytes(&mut data);
+        data.into()
+    }
+
+    /// XORs the PRG output into `out`.
+    fn eval_into(&self, seed: &ChaChaSeed, out: &mut Bytes) {
+        assert_eq!(out.len(), self.eval_size);
+        *out ^= self.eval(seed);
+    }
+
+    fn null_output(&self) -> Bytes {
+        Bytes::empty(self.eval_size)
+    }
+}
+
+impl ChunkedPrg for ChaChaPrg {
+    /// Seeks the keystream to the (32-bit) word containing `offset`.
+    fn eval_chunk_into(&self, seed: &ChaChaSeed, offset: usize, out: &mut [u8]) {
+        let mut keystream = self.keystream(seed);
+        keystream.set_word_pos((offset / WORD_SIZE) as u128);
+        let skip = offset % WORD_SIZE;
+        let mut data = vec![0; skip + out.len()];
+        keystream.fill_bytes(&mut data);
+        for (x, y) in out.iter_mut().zip(&data[skip..]) {
+            *x ^= y;
+        }
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+use proptest::prelude::*;
+
+#[cfg(any(test, feature = "testing"))]
+impl Arbitrary for ChaChaPrg {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        use std::ops::Range;
+        const SIZES: Range<usize> = 16..1000; // in bytes
+        SIZES.prop_map(ChaChaPrg::new).boxed()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Arbitrary for ChaChaSeed {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        prop::collection::vec(any::<u8>(), SEED_SIZE)
+            .prop_map(ChaChaSeed::try_from)
+            .prop_map(Result::unwrap)
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    check_prg!(ChaChaPrg);
+    check_chunked_prg!(ChaChaPrg);
+    check_dpf!(crate::dpf::TwoKeyDpf<ChaChaPrg>);
+}
@@ -1,22 +1,31 @@
 use std::convert::{TryFrom, TryInto};
 use std::hash::{Hash, Hasher};
-use std::iter::Sum;
+use std::iter::{repeat_with, Sum};
 use std::ops;
 
+use ::group::cofactor::CofactorGroup;
 use ::group::Group as _;
 use ::group::GroupEncoding;
-use jubjub::{Fr, SubgroupPoint};
+use jubjub::{ExtendedPoint, Fr, SubgroupPoint};
 use rug::{integer::Order, Integer};
 use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+use zeroize::DefaultIsZeroes;
 
 use crate::algebra::{Field, Group, Monoid, SpecialExponentMonoid};
 use crate::bytes::Bytes;
 use crate::constructions::aes_prg::{AesPrg, AesSeed};
+use crate::prg::Embed;
 use crate::util::Sampleable;
 
 #[cfg(any(test, feature = "testing"))]
 use proptest::prelude::*;
 
+#[cfg(feature = "gpu")]
+mod gpu;
+#[cfg(feature = "gpu")]
+mod opencl;
+
 // see jubjub::Fr for details
 // PR to expose this as public within the library:
 // https://github.com/zkcrypto/jubjub/pull/34
@@ -31,6 +40,29 @@ const MODULUS: [u64; 4] = [
 pub const MODULUS_BYTES: usize = 32;
 const BYTE_ORDER: Order = Order::LsfLe;
 
+// `n` points from `seed`, with unknown discrete logs: try successive 32-byte
+// chunks of the PRG output as encodings until one is on the curve, then clear
+// the cofactor.
+fn points_from_seed(seed: &AesSeed, n: usize) -> Vec<SubgroupPoint> {
+    use crate::prg::ChunkedPrg;
+    let prg = AesPrg::new(MODULUS_BYTES);
+    let mut offset = 0;
+    repeat_with(|| loop {
+        let mut bytes = [0u8; MODULUS_BYTES];
+        prg.eval_chunk_into(seed, offset, &mut bytes);
+        offset += MODULUS_BYTES;
+        let point: Option<ExtendedPoint> = ExtendedPoint::from_bytes(&bytes).into();
+        if let Some(point) = point {
+            let point = point.clear_cofactor();
+            if !bool::from(point.is_identity()) {
+                return point;
+            }
+        }
+    })
+    .take(n)
+    .collect()
+}
+
 /// A CurvePoint representing a point in the elliptic curve group.
 #[derive(Eq, Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(try_from = "Vec<u8>", into = "Vec<u8>")]
@@ -79,7 +111,7 @@ impl From<CurvePoint> for Bytes {
 
 impl From<CurvePoint> for Vec<u8> {
     fn from(value: CurvePoint) -> Self {
-        let bytes = (&value.inner).to_bytes();
+        let bytes = value.inner.to_bytes();
         Vec::from(bytes)
     }
 }
@@ -103,9 +135,47 @@ impl Sampleable for CurvePoint {
     }
 
     fn sample_many_from_seed(seed: &Self::Seed, n: usize) -> Vec<Self> {
-        let _ = seed;
-        let _ = n;
-        todo!()
+        points_from_seed(seed, n)
+            .into_iter()
+            .map(CurvePoint::from)
+            .collect()
+    }
+}
+
+// Data goes in the low bytes of the point's y-coordinate, then a counter
+// (never zero, so the identity, y = 1, is never mistaken for data); the top
+// byte stays zero, keeping y in range. We count up until the encoding is a
+// point in the prime-order subgroup (about 1 in 16 are).
+const EMBED_DATA_BYTES: usize = 29;
+const EMBED_COUNTER_BYTES: usize = 2;
+
+impl Embed for CurvePoint {
+    const DATA_BYTES: usize = EMBED_DATA_BYTES;
+
+    fn embed(data: &[u8]) -> Option<Self> {
+        if data.len() > EMBED_DATA_BYTES {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        bytes[..data.len()].copy_from_slice(data);
+        (1..=u16::MAX).find_map(|counter| {
+            bytes[EMBED_DATA_BYTES..EMBED_DATA_BYTES + EMBED_COUNTER_BYTES]
+                .copy_from_slice(&counter.to_le_bytes());
+            Option::<SubgroupPoint>::from(SubgroupPoint::from_bytes(&bytes)).map(CurvePoint::from)
+        })
+    }
+
+    fn extract(&self) -> Option<Vec<u8>> {
+        if bool::from(self.inner.is_identity()) {
+            return Some(vec![0; EMBED_DATA_BYTES]);
+        }
+        let bytes = self.inner.to_bytes();
+        let (data, rest) = bytes.split_at(EMBED_DATA_BYTES);
+        let (counter, top) = rest.split_at(EMBED_COUNTER_BYTES);
+        if counter.iter().all(|&b| b == 0) || top.iter().any(|&b| b != 0) {
+            return None;
+        }
+        Some(data.to_vec())
     }
 }
 
@@ -179,26 +249,31 @@ impl PartialEq for CurvePoint {
     }
 }
 
+impl ConstantTimeEq for CurvePoint {
+    fn ct_eq(&self, rhs: &CurvePoint) -> Choice {
+        self.inner.to_bytes().ct_eq(&rhs.inner.to_bytes())
+    }
+}
+
+impl Default for CurvePoint {
+    fn default() -> Self {
+        CurvePoint::zero()
+    }
+}
+
+// The identity isn't all zero bytes, but zeroizing only needs a value that
+// doesn't depend on the secret.
+impl DefaultIsZeroes for CurvePoint {}
+
+/// Points of the whole subgroup (as multiples of the generator by a uniform
+/// scalar), plus the identity and generator (which uniform ones would miss).
 #[cfg(any(test, feature = "testing"))]
 pub(crate) fn subgroup_points() -> impl Strategy<Value = SubgroupPoint> {
-    use ::group::Group as _;
-    any::<u8>().prop_map(|mut exp| {
-        let g = SubgroupPoint::generator();
-        let mut p = g;
-        loop {
-            // Exponentiation by squaring
-            // Err, multiplication by doubling, but same idea.
-            if exp % 2 == 1 {
-                p += g;
-            }
-            exp /= 2;
-            if exp <= 1 {
-                break;
-            }
-            p = p.double();
-        }
-        p
-    })
+    prop_oneof![
+        1 => Just(SubgroupPoint::identity()),
+        1 => Just(SubgroupPoint::generator()),
+        8 => jubjubs().prop_map(|exp| SubgroupPoint::generator() * exp),
+    ]
 }
 
 #[cfg(any(test, feature = "testing"))]
@@ -294,13 +369,14 @@ impl From<Fr> for Scalar {
 }
 
 impl From<&Integer> for Scalar {
+    /// Reduces `value` mod the order (negative values included).
     fn from(value: &Integer) -> Self {
-        use std::cmp::Ordering;
-        let reduced = if value.cmp0() == Ordering::Less {
-            Self::order() - (Integer::from(-value) % Self::order())
-        } else {
-            value % Self::order()
-        };
+        let order = Self::order();
+        // Truncating remainder: negative if `value` is.
+        let mut reduced = Integer::from(value % &order);
+        if reduced < 0 {
+            reduced += &order;
+        }
 
         let mut digits: [u8; MODULUS_BYTES] = [0x0u8; MODULUS_BYTES];
         reduced.write_digits(&mut digits, BYTE_ORDER);
@@ -314,6 +390,13 @@ impl From<Integer> for Scalar {
     }
 }
 
+impl From<Scalar> for Integer {
+    /// The canonical representative of `value`, in `[0, order)`.
+    fn from(value: Scalar) -> Integer {
+        Integer::from_digits(&value.inner.to_bytes(), BYTE_ORDER)
+    }
+}
+
 impl From<Scalar> for Bytes {
     fn from(value: Scalar) -> Bytes {
         Bytes::from(value.inner.to_bytes().to_vec())
@@ -371,6 +454,20 @@ impl PartialEq for Scalar {
     }
 }
 
+impl ConstantTimeEq for Scalar {
+    fn ct_eq(&self, rhs: &Scalar) -> Choice {
+        self.inner.ct_eq(&rhs.inner)
+    }
+}
+
+impl Default for Scalar {
+    fn default() -> Self {
+        Scalar::zero()
+    }
+}
+
+impl DefaultIsZeroes for Scalar {}
+
 #[cfg(any(test, feature = "testing"))]
 pub(crate) fn jubjubs() -> impl Strategy<Value = Fr> {
     proptest::collection::vec(any::<u8>(), 64)
@@ -389,7 +486,6 @@ impl Arbitrary for Scalar {
 
 impl Sampleable for Scalar {
     type Seed = AesSeed;
-    /// identity element in the elliptic curve field
 
     /// generates a new random group element
     fn sample() -> Self {
@@ -418,21 +514,246 @@ impl Sampleable for Scalar {
     }
 }
 
+// Below this many terms, plain scalar multiplication beats bucketing.
+const PIPPENGER_THRESHOLD: usize = 8;
+
+// Window size (in bits) for Pippenger's algorithm over `n` terms.
+fn pippenger_window(n: usize) -> usize {
+    if n < 32 {
+        3
+    } else {
+        (n as f64).ln().ceil() as usize + 2
+    }
+}
+
+// The `width`-bit digit of a little-endian scalar starting at bit `start`.
+fn scalar_digit(scalar: &[u8; MODULUS_BYTES], start: usize, width: usize) -> usize {
+    let end = usize::min(start + width, MODULUS_BYTES * 8);
+    (start..end).rev().fold(0, |acc, bit| {
+        (acc << 1) | ((scalar[bit / 8] >> (bit % 8)) & 1) as usize
+    })
+}
+
+/// Pippenger's bucket method: sum of `scalars[i] * bases[i]`.
+///
+/// For each window of scalar bits (most significant first), add each base
+/// into the bucket for its digit, then combine the buckets with a running sum
+/// (so bucket `d` gets counted `d` times).
+fn pippenger(bases: &[SubgroupPoint], scalars: &[[u8; MODULUS_BYTES]]) -> SubgroupPoint {
+    let width = pippenger_window(bases.len());
+    let windows = (MODULUS_BYTES * 8).div_ceil(width);
+    let mut total = SubgroupPoint::identity();
+    for window in (0..windows).rev() {
+        for _ in 0..width {
+            total = total.double();
+        }
+        let mut buckets = vec![SubgroupPoint::identity(); (1 << width) - 1];
+        for (base, scalar) in bases.iter().zip(scalars) {
+            let digit = scalar_digit(scalar, window * width, width);
+            if digit != 0 {
+                buckets[digit - 1] += base;
+            }
+        }
+        let mut running = SubgroupPoint::identity();
+        for bucket in buckets.into_iter().rev() {
+            running += bucket;
+            total += running;
+        }
+    }
+    total
+}
+
+// Digit width (in bits) for fixed-base exponentiation.
+const FIXED_BASE_WIDTH: usize = 4;
+
+/// A point, precomputed for fixed-base exponentiation.
+///
+/// For each `FIXED_BASE_WIDTH`-bit window `i` of the exponent, holds `d *
+/// 2^(i * FIXED_BASE_WIDTH) * base` for every nonzero digit `d`; raising the
+/// base to a power is then one addition per window (64 in all), rather than a
+/// doubling and an addition per bit. That's about 150KiB per point (so the group
+/// PRG only precomputes so many; see [`MAX_FIXED_BASE_TABLES`](crate::MAX_FIXED_BASE_TABLES)).
+pub struct FixedBaseTable {
+    windows: Vec<[SubgroupPoint; (1 << FIXED_BASE_WIDTH) - 1]>,
+    // Tells tables apart, so the GPU keeps them uploaded between calls.
+    #[cfg(feature = "gpu")]
+    id: u64,
+}
+
+impl FixedBaseTable {
+    fn new(mut base: SubgroupPoint) -> Self {
+        let windows = (MODULUS_BYTES * 8).div_ceil(FIXED_BASE_WIDTH);
+        let windows = (0..windows)
+            .map(|_| {
+                let mut multiples = [SubgroupPoint::identity(); (1 << FIXED_BASE_WIDTH) - 1];
+                let mut multiple = SubgroupPoint::identity();
+                for entry in multiples.iter_mut() {
+                    multiple += base;
+                    *entry = multiple;
+                }
+                // On to the next window: 2^FIXED_BASE_WIDTH times this one.
+                base = multiple + base;
+                multiples
+            })
+            .collect();
+        FixedBaseTable {
+            windows,
+            #[cfg(feature = "gpu")]
+            id: {
+                use std::sync::atomic::{AtomicU64, Ordering};
+                static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            },
+        }
+    }
+
+    fn pow(&self, exp: &Fr) -> SubgroupPoint {
+        let scalar = exp.to_bytes();
+        self.windows.iter().enumerate().fold(
+            SubgroupPoint::identity(),
+            |acc, (window, multiples)| {
+                let digit = scalar_digit(&scalar, window * FIXED_BASE_WIDTH, FIXED_BASE_WIDTH);
+                // Look at every entry, so the digit (a secret) doesn't show
+                // in memory accesses.
+                let mut term = SubgroupPoint::identity();
+                for (d, multiple) in multiples.iter().enumerate() {
+                    term.conditional_assign(multiple, ((d + 1) as u8).ct_eq(&(digit as u8)));
+                }
+                acc + term
+            },
+        )
+    }
+}
+
 impl SpecialExponentMonoid for CurvePoint {
     type Exponent = Scalar;
+    type FixedBase = FixedBaseTable;
 
     fn pow(&self, exp: Self::Exponent) -> Self {
         (self.inner * exp.inner).into()
     }
+
+    fn fixed_base(&self) -> FixedBaseTable {
+        FixedBaseTable::new(self.inner)
+    }
+
+    fn pow_fixed(table: &FixedBaseTable, exp: &Scalar) -> Self {
+        table.pow(&exp.inner).into()
+    }
+
+    fn msm(bases: &[Self], exps: &[Self::Exponent]) -> Self {
+        assert_eq!(bases.len(), exps.len(), "need one exponent per base");
+        if bases.len() < PIPPENGER_THRESHOLD {
+            return bases
+                .iter()
+                .zip(exps)
+                .map(|(base, exp)| base.pow(*exp))
+                .sum();
+        }
+        let points: Vec<SubgroupPoint> = bases.iter().map(|base| base.inner).collect();
+        let scalars: Vec<[u8; MODULUS_BYTES]> =
+            exps.iter().map(|exp| exp.inner.to_bytes()).collect();
+        pippenger(&points, &scalars).into()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn pow_many(bases: &[Self], exp: &Self::Exponent) -> Vec<Self> {
+        use rayon::prelude::*;
+        bases
+            .par_iter()
+            .map(|base| (base.inner * exp.inner).into())
+            .collect()
+    }
+
+    fn pow_many_fixed(tables: &[FixedBaseTable], exp: &Scalar) -> Vec<Self> {
+        #[cfg(feature = "gpu")]
+        {
+            if let Some(points) = gpu::pow_many_fixed(tables, &exp.inner) {
+                return points.into_iter().map(Into::into).collect();
+            }
+        }
+        #[cfg(feature = "parallel")]
+        use rayon::prelude::*;
+        #[cfg(feature = "parallel")]
+        let tables = tables.par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let tables = tables.iter();
+        tables.map(|table| table.pow(&exp.inner).into()).collect()
+    }
+
+    fn pow_many_fixed_into(tables: &[FixedBaseTable], exp: &Scalar, out: &mut [Self]) {
+        assert_eq!(tables.len(), out.len(), "need one output per base");
+        #[cfg(feature = "gpu")]
+        {
+            if let Some(points) = gpu::pow_many_fixed(tables, &exp.inner) {
+                for (acc, point) in out.iter_mut().zip(points) {
+                    *acc += point.into();
+                }
+                return;
+            }
+        }
+        #[cfg(feature = "parallel")]
+        use rayon::prelude::*;
+        #[cfg(feature = "parallel")]
+        let out = out.par_iter_mut();
+        #[cfg(not(feature = "parallel"))]
+        let out = out.iter_mut();
+        out.zip(tables)
+            .for_each(|(acc, table)| *acc += table.pow(&exp.inner).into());
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::algebra::Group as _;
     use crate::dpf::MultiKeyDpf;
     use crate::prg::GroupPrg;
 
+    proptest! {
+        #[test]
+        fn test_scalar_digit(scalar: Scalar, start in 0..256usize, width in 1..16usize) {
+            let bytes = scalar.inner.to_bytes();
+            let value = Integer::from_digits(&bytes, BYTE_ORDER);
+            let end = usize::min(start + width, 256);
+            let expected = (value >> start as u32).keep_bits((end - start) as u32);
+            prop_assert_eq!(Integer::from(scalar_digit(&bytes, start, width)), expected);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_points_from_seed(seed: AesSeed, n in 0..5usize) {
+            let points = points_from_seed(&seed, n);
+            prop_assert_eq!(points.len(), n);
+            prop_assert_eq!(&points, &points_from_seed(&seed, n));
+            for point in points {
+                prop_assert!(!bool::from(point.is_identity()));
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_embed_roundtrip(data in prop::collection::vec(any::<u8>(), 0..=EMBED_DATA_BYTES)) {
+            let point = CurvePoint::embed(&data).expect("some counter should work");
+            let mut expected = data.clone();
+            expected.resize(EMBED_DATA_BYTES, 0);
+            prop_assert_eq!(point.extract(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_embed_identity() {
+        assert_eq!(
+            CurvePoint::zero().extract(),
+            Some(vec![0; EMBED_DATA_BYTES])
+        );
+        assert!(CurvePoint::embed(&[0; EMBED_DATA_BYTES + 1]).is_none());
+    }
+
     check_group_laws!(CurvePoint);
+    check_monoid_custom_exponent!(CurvePoint);
     // check_sampleable!(CurvePoint);
     check_field_laws!(Scalar);
     check_sampleable!(Scalar);
@@ -489,4 +810,126 @@ mod tests {
         Bytes::from,
         bytes_element_vector_rt
     );
+
+    /// Integers of either sign and up to a few times the width of the order,
+    /// plus ones near (positive and negative) multiples of it.
+    fn integers() -> impl Strategy<Value = Integer> {
+        let order = Scalar::order();
+        prop_oneof![
+            (any::<bool>(), prop::collection::vec(any::<u8>(), 0..80)).prop_map(
+                |(negative, digits)| {
+                    let value = Integer::from_digits(&digits, BYTE_ORDER);
+                    if negative {
+                        -value
+                    } else {
+                        value
+                    }
+                }
+            ),
+            (-3i32..=3, -2i32..=2).prop_map(move |(k, d)| Integer::from(&order * k) + d),
+        ]
+    }
+
+    check_roundtrip!(
+        Scalar,
+        Into::<Bytes>::into,
+        |b| Scalar::try_from(b).unwrap(),
+        scalar_to_bytes_rt
+    );
+    check_roundtrip!(
+        Scalar,
+        Into::<Integer>::into,
+        |x: Integer| Scalar::from(x),
+        scalar_to_integer_rt
+    );
+    check_roundtrip!(
+        CurvePoint,
+        Into::<Bytes>::into,
+        |b| CurvePoint::try_from(b).unwrap(),
+        point_to_bytes_rt
+    );
+
+    proptest! {
+        #[test]
+        fn test_scalar_from_integer(x in integers()) {
+            let order = Scalar::order();
+            let value = Integer::from(Scalar::from(&x));
+            prop_assert!(value >= 0 && value < order);
+            // Differs from x by a multiple of the order.
+            prop_assert!(Integer::from(&x - &value).is_divisible(&order));
+        }
+
+        #[test]
+        fn test_scalar_arithmetic(a in integers(), b in integers()) {
+            let (x, y) = (Scalar::from(&a), Scalar::from(&b));
+            prop_assert_eq!(x + y, Scalar::from(Integer::from(&a + &b)));
+            prop_assert_eq!(x - y, Scalar::from(Integer::from(&a - &b)));
+            prop_assert_eq!(x * y, Scalar::from(Integer::from(&a * &b)));
+            prop_assert_eq!(-x, Scalar::from(Integer::from(-&a)));
+        }
+
+        #[test]
+        fn test_exponent_arithmetic(base: CurvePoint, a in integers(), b in integers()) {
+            let pow = |x: &Integer| base.pow(Scalar::from(x));
+            prop_assert_eq!(pow(&a) + pow(&b), pow(&Integer::from(&a + &b)));
+            prop_assert_eq!(pow(&a) - pow(&b), pow(&Integer::from(&a - &b)));
+            prop_assert_eq!(pow(&a).pow(Scalar::from(&b)), pow(&Integer::from(&a * &b)));
+        }
+
+        #[test]
+        fn test_scalar_to_point(a: Scalar, b: Scalar) {
+            let g = CurvePoint::generator();
+            prop_assert_eq!(CurvePoint::from(a), g.pow(a));
+            prop_assert_eq!(CurvePoint::from(a) + CurvePoint::from(b), CurvePoint::from(a + b));
+        }
+
+        #[test]
+        fn test_scalar_from_wide_bytes(digits in prop::collection::vec(any::<u8>(), 64)) {
+            let expected = Scalar::from(Integer::from_digits(&digits, BYTE_ORDER));
+            prop_assert_eq!(Scalar::try_from(Bytes::from(digits)).unwrap(), expected);
+        }
+
+        #[test]
+        fn test_scalar_from_short_bytes(digits in prop::collection::vec(any::<u8>(), 0..32)) {
+            // Zero-padded, and always less than the order.
+            let expected = Scalar::from(Integer::from_digits(&digits, BYTE_ORDER));
+            prop_assert_eq!(Scalar::try_from(Bytes::from(digits)).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_scalar_from_negative_integer() {
+        let order = Scalar::order();
+        assert_eq!(Scalar::from(Integer::from(-1)), -Scalar::one());
+        for k in 1..4 {
+            // Used to come out as the order itself (and fail to convert).
+            assert_eq!(Scalar::from(Integer::from(&order * -k)), Scalar::zero());
+            assert_eq!(Scalar::from(Integer::from(&order * -k) - 1), -Scalar::one());
+        }
+    }
+
+    #[test]
+    fn test_scalar_noncanonical_bytes() {
+        let mut order = [0u8; MODULUS_BYTES];
+        Scalar::order().write_digits(&mut order, BYTE_ORDER);
+        Scalar::try_from(Bytes::from(order.to_vec())).expect_err("the order isn't reduced");
+        Scalar::try_from(order.to_vec()).expect_err("the order isn't reduced");
+        for len in &[33, 63, 65] {
+            Scalar::try_from(Bytes::empty(*len)).expect_err("wrong length");
+        }
+        Scalar::try_from(vec![0u8; 31]).expect_err("vectors must be exactly 32 bytes");
+    }
+
+    #[test]
+    fn test_element_vector_partial_chunks() {
+        let mut long = Vec::<u8>::from(SubgroupPoint::generator().to_bytes());
+        long.push(0);
+        ElementVector::<CurvePoint>::try_from(long).expect_err("33 bytes isn't whole elements");
+
+        // Bytes (messages) get padded out instead: [1, 0, ..., 0] encodes the
+        // identity.
+        let identity = Bytes::from(Vec::<u8>::from(SubgroupPoint::identity().to_bytes()));
+        let elements = ElementVector::<CurvePoint>::try_from(Bytes::from(vec![1u8])).unwrap();
+        assert_eq!(Bytes::from(elements), identity);
+    }
 }
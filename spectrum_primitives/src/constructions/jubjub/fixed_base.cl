@@ -0,0 +1,222 @@
+// Fixed-base exponentiation on the Jubjub curve (see `gpu.rs`).
+//
+// Each work item raises one base to the exponent, using the same windowed
+// table as `FixedBaseTable::pow`: one addition per window, picking the
+// multiple for that window's digit by looking at every entry.
+//
+// Field elements (of Fq, the scalar field of BLS12-381) are four 64-bit
+// limbs, little-endian, in Montgomery form; the arithmetic follows the
+// bls12_381 crate. Curve points are in extended twisted Edwards coordinates
+// (X, Y, Z, T), as in the jubjub crate, and table entries are affine points
+// (u, v) stored as (v - u, v + u, 2 * d * u * v).
+
+// Limbs per field element.
+#define FQ_LIMBS 4
+// Limbs per table entry and per output point (three field elements each).
+#define ENTRY_LIMBS (3 * FQ_LIMBS)
+
+typedef struct {
+    ulong l[FQ_LIMBS];
+} fq;
+
+typedef struct {
+    fq x, y, z, t;
+} point;
+
+typedef struct {
+    fq y_minus_x, y_plus_x, t2d;
+} niels;
+
+__constant ulong FQ_MODULUS[FQ_LIMBS] = {
+    0xffffffff00000001UL,
+    0x53bda402fffe5bfeUL,
+    0x3339d80809a1d805UL,
+    0x73eda753299d7d48UL,
+};
+
+// -(q^-1 mod 2^64) mod 2^64
+#define FQ_INV 0xfffffffeffffffffUL
+
+// One (R = 2^256 mod q), in Montgomery form.
+__constant ulong FQ_ONE[FQ_LIMBS] = {
+    0x00000001fffffffeUL,
+    0x5884b7fa00034802UL,
+    0x998c4fefecbc4ff5UL,
+    0x1824b159acc5056fUL,
+};
+
+// a + b + *carry, setting *carry to the carry out.
+ulong fq_adc(ulong a, ulong b, ulong *carry) {
+    ulong sum = a + b;
+    ulong out = (ulong)(sum < a);
+    ulong ret = sum + *carry;
+    out += (ulong)(ret < sum);
+    *carry = out;
+    return ret;
+}
+
+// a - b - *borrow, setting *borrow to the borrow out (0 or 1).
+ulong fq_sbb(ulong a, ulong b, ulong *borrow) {
+    ulong diff = a - b;
+    ulong out = (ulong)(a < b);
+    ulong ret = diff - *borrow;
+    out += (ulong)(diff < *borrow);
+    *borrow = out;
+    return ret;
+}
+
+// a + b * c + *carry, setting *carry to the high limb.
+ulong fq_mac(ulong a, ulong b, ulong c, ulong *carry) {
+    ulong lo = b * c;
+    ulong hi = mul_hi(b, c);
+    lo += a;
+    hi += (ulong)(lo < a);
+    lo += *carry;
+    hi += (ulong)(lo < *carry);
+    *carry = hi;
+    return lo;
+}
+
+fq fq_const(__constant ulong *limbs) {
+    fq ret;
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        ret.l[i] = limbs[i];
+    }
+    return ret;
+}
+
+fq fq_zero(void) {
+    fq ret;
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        ret.l[i] = 0;
+    }
+    return ret;
+}
+
+fq fq_sub(fq a, fq b) {
+    fq ret;
+    ulong borrow = 0;
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        ret.l[i] = fq_sbb(a.l[i], b.l[i], &borrow);
+    }
+    // On underflow, add the modulus back.
+    ulong mask = (ulong)0 - borrow;
+    ulong carry = 0;
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        ret.l[i] = fq_adc(ret.l[i], FQ_MODULUS[i] & mask, &carry);
+    }
+    return ret;
+}
+
+fq fq_add(fq a, fq b) {
+    // Both are below q < 2^255, so this can't overflow.
+    fq ret;
+    ulong carry = 0;
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        ret.l[i] = fq_adc(a.l[i], b.l[i], &carry);
+    }
+    return fq_sub(ret, fq_const(FQ_MODULUS));
+}
+
+fq fq_mul(fq a, fq b) {
+    // Schoolbook multiplication...
+    ulong r[2 * FQ_LIMBS];
+    for (int i = 0; i < 2 * FQ_LIMBS; i++) {
+        r[i] = 0;
+    }
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        ulong carry = 0;
+        for (int j = 0; j < FQ_LIMBS; j++) {
+            r[i + j] = fq_mac(r[i + j], a.l[i], b.l[j], &carry);
+        }
+        r[i + FQ_LIMBS] = carry;
+    }
+
+    // ...then Montgomery reduction (HAC algorithm 14.32).
+    ulong carry2 = 0;
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        ulong k = r[i] * FQ_INV;
+        ulong carry = 0;
+        fq_mac(r[i], k, FQ_MODULUS[0], &carry);
+        for (int j = 1; j < FQ_LIMBS; j++) {
+            r[i + j] = fq_mac(r[i + j], k, FQ_MODULUS[j], &carry);
+        }
+        r[i + FQ_LIMBS] = fq_adc(r[i + FQ_LIMBS], carry2, &carry);
+        carry2 = carry;
+    }
+
+    // The result may be within q of the answer.
+    fq ret;
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        ret.l[i] = r[i + FQ_LIMBS];
+    }
+    return fq_sub(ret, fq_const(FQ_MODULUS));
+}
+
+// Set `*dst` to `src` if `mask` is all ones (leaving it if zero).
+void fq_select(fq *dst, __global const ulong *src, ulong mask) {
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        dst->l[i] = (dst->l[i] & ~mask) | (src[i] & mask);
+    }
+}
+
+// p + q, for the twisted Edwards curve -u^2 + v^2 = 1 + d u^2 v^2.
+//
+// Complete (since d isn't a square), so fine for the identity and doubling
+// too. See "Twisted Edwards Curves Revisited" (Hisil et al.), section 3.1.
+point point_add_niels(point p, niels q) {
+    fq a = fq_mul(fq_sub(p.y, p.x), q.y_minus_x);
+    fq b = fq_mul(fq_add(p.y, p.x), q.y_plus_x);
+    fq c = fq_mul(p.t, q.t2d);
+    fq d = fq_add(p.z, p.z);
+    fq e = fq_sub(b, a);
+    fq f = fq_sub(d, c);
+    fq g = fq_add(d, c);
+    fq h = fq_add(b, a);
+    point ret;
+    ret.x = fq_mul(e, f);
+    ret.y = fq_mul(g, h);
+    ret.z = fq_mul(f, g);
+    ret.t = fq_mul(e, h);
+    return ret;
+}
+
+// For each base: `windows` windows of `entries` table entries each; then
+// the exponent's digit for each window. Writes (X, Y, Z) for each base.
+__kernel void fixed_base_pow(__global const ulong *tables,
+                             __global const uchar *digits,
+                             uint windows,
+                             uint entries,
+                             __global ulong *out) {
+    size_t base = get_global_id(0);
+    __global const ulong *table = tables + base * windows * entries * ENTRY_LIMBS;
+
+    point acc;
+    acc.x = fq_zero();
+    acc.y = fq_const(FQ_ONE);
+    acc.z = fq_const(FQ_ONE);
+    acc.t = fq_zero();
+    for (uint window = 0; window < windows; window++) {
+        uint digit = digits[window];
+        // Entry d is (d + 1) times the window's base; digit 0 is the identity.
+        niels term;
+        term.y_minus_x = fq_const(FQ_ONE);
+        term.y_plus_x = fq_const(FQ_ONE);
+        term.t2d = fq_zero();
+        for (uint d = 0; d < entries; d++) {
+            __global const ulong *entry = table + (window * entries + d) * ENTRY_LIMBS;
+            ulong mask = (ulong)0 - (ulong)(d + 1 == digit);
+            fq_select(&term.y_minus_x, entry, mask);
+            fq_select(&term.y_plus_x, entry + FQ_LIMBS, mask);
+            fq_select(&term.t2d, entry + 2 * FQ_LIMBS, mask);
+        }
+        acc = point_add_niels(acc, term);
+    }
+
+    __global ulong *dst = out + base * ENTRY_LIMBS;
+    for (int i = 0; i < FQ_LIMBS; i++) {
+        dst[i] = acc.x.l[i];
+        dst[FQ_LIMBS + i] = acc.y.l[i];
+        dst[2 * FQ_LIMBS + i] = acc.z.l[i];
+    }
+}
@@ -0,0 +1,36 @@
+// Runs `fixed_base.cl` on the CPU, to test it against the Rust code without a
+// GPU (see `gpu.rs`).
+#include <stddef.h>
+#include <stdint.h>
+
+typedef uint8_t uchar;
+typedef uint32_t uint;
+typedef uint64_t ulong;
+
+#define __kernel
+#define __global
+#define __constant const
+
+static ulong mul_hi(ulong a, ulong b) {
+    return (ulong)(((unsigned __int128)a * b) >> 64);
+}
+
+static size_t global_id;
+
+static size_t get_global_id(uint dim) {
+    (void)dim;
+    return global_id;
+}
+
+#include "fixed_base.cl"
+
+void fixed_base_pow_host(const ulong *tables,
+                         const uchar *digits,
+                         uint windows,
+                         uint entries,
+                         ulong *out,
+                         size_t bases) {
+    for (global_id = 0; global_id < bases; global_id++) {
+        fixed_base_pow(tables, digits, windows, entries, out);
+    }
+}
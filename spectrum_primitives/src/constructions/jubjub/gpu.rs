@@ -0,0 +1,260 @@
+//! Fixed-base exponentiation on a GPU (the `gpu` feature).
+//!
+//! Multi-key rounds raise thousands of group PRG generators to each seed. With
+//! a GPU, [`pow_many_fixed`] does those in parallel, one work item per base
+//! (see `fixed_base.cl`). It returns `None`, and callers use the CPU, when
+//! there's no GPU, the batch is too small to be worth it, or OpenCL fails.
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use jubjub::{ExtendedPoint, Fq, Fr, SubgroupPoint};
+
+use super::opencl::{self, Arg, Buffer, Device};
+use super::{scalar_digit, FixedBaseTable, FIXED_BASE_WIDTH, MODULUS_BYTES};
+
+const SOURCE: &str = include_str!("fixed_base.cl");
+
+// Below this many bases, copying to and from the device costs more than it
+// saves.
+const MIN_BASES: usize = 256;
+
+const WINDOWS: usize = (MODULUS_BYTES * 8).div_ceil(FIXED_BASE_WIDTH);
+const ENTRIES: usize = (1 << FIXED_BASE_WIDTH) - 1;
+// Limbs per table entry, and per output point: three field elements each.
+const ENTRY_LIMBS: usize = 12;
+
+// 2^256 mod q; multiplying by it gives the Montgomery form the kernel uses.
+const R: Fq = Fq::from_raw([
+    0x0000_0001_ffff_fffe,
+    0x5884_b7fa_0003_4802,
+    0x998c_4fef_ecbc_4ff5,
+    0x1824_b159_acc5_056f,
+]);
+
+struct Backend {
+    device: Device,
+    // The last tables uploaded (by ID): the group PRG uses the same ones for
+    // every evaluation.
+    tables: Mutex<Option<(Vec<u64>, Arc<Buffer>)>>,
+}
+
+fn backend() -> Option<&'static Backend> {
+    static BACKEND: OnceLock<Option<Backend>> = OnceLock::new();
+    BACKEND
+        .get_or_init(|| {
+            Device::open(SOURCE, "fixed_base_pow").map(|device| Backend {
+                device,
+                tables: Mutex::new(None),
+            })
+        })
+        .as_ref()
+}
+
+impl Backend {
+    fn tables(&self, tables: &[FixedBaseTable]) -> Result<Arc<Buffer>, opencl::Error> {
+        let ids: Vec<u64> = tables.iter().map(|table| table.id).collect();
+        let mut cached = self.tables.lock().unwrap();
+        if let Some((cached_ids, buffer)) = &*cached {
+            if *cached_ids == ids {
+                return Ok(buffer.clone());
+            }
+        }
+        let buffer = Arc::new(self.device.upload(&pack(tables))?);
+        *cached = Some((ids, buffer.clone()));
+        Ok(buffer)
+    }
+
+    fn pow_many_fixed(
+        &self,
+        tables: &[FixedBaseTable],
+        exp: &Fr,
+    ) -> Result<Vec<SubgroupPoint>, opencl::Error> {
+        let table_buffer = self.tables(tables)?;
+        let digit_buffer = self.device.upload(&digits(exp))?;
+        let mut limbs = vec![0u64; tables.len() * ENTRY_LIMBS];
+        let out = self.device.output(limbs.len() * 8)?;
+        self.device.launch(
+            &[
+                Arg::Buffer(&table_buffer),
+                Arg::Buffer(&digit_buffer),
+                Arg::Uint(WINDOWS as u32),
+                Arg::Uint(ENTRIES as u32),
+                Arg::Buffer(&out),
+            ],
+            tables.len(),
+        )?;
+        self.device.read(&out, &mut limbs)?;
+        Ok(unpack(&limbs))
+    }
+}
+
+/// Raises each of `tables` to the `exp`th power on the GPU, if it's worth it.
+pub(super) fn pow_many_fixed(tables: &[FixedBaseTable], exp: &Fr) -> Option<Vec<SubgroupPoint>> {
+    if tables.len() < MIN_BASES {
+        return None;
+    }
+    backend()?.pow_many_fixed(tables, exp).ok()
+}
+
+fn push_limbs(limbs: &mut Vec<u64>, value: Fq) {
+    let bytes = (value * R).to_bytes();
+    limbs.extend(
+        bytes
+            .chunks(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap())),
+    );
+}
+
+// Each table entry (u, v), as (v - u, v + u, 2 * d * u * v).
+fn pack(tables: &[FixedBaseTable]) -> Vec<u64> {
+    // d = -(10240/10241)
+    let d = -(Fq::from(10240) * Fq::from(10241).invert().unwrap());
+    let d2 = d + d;
+    let mut limbs = Vec::with_capacity(tables.len() * WINDOWS * ENTRIES * ENTRY_LIMBS);
+    for table in tables {
+        let mut points: Vec<ExtendedPoint> = table
+            .windows
+            .iter()
+            .flatten()
+            .map(|&point| point.into())
+            .collect();
+        for point in jubjub::batch_normalize(&mut points) {
+            let (u, v) = (point.get_u(), point.get_v());
+            push_limbs(&mut limbs, v - u);
+            push_limbs(&mut limbs, v + u);
+            push_limbs(&mut limbs, d2 * u * v);
+        }
+    }
+    limbs
+}
+
+fn digits(exp: &Fr) -> Vec<u8> {
+    let bytes = exp.to_bytes();
+    (0..WINDOWS)
+        .map(|window| scalar_digit(&bytes, window * FIXED_BASE_WIDTH, FIXED_BASE_WIDTH) as u8)
+        .collect()
+}
+
+// Points in (X, Y, Z), to affine (u, v) = (X/Z, Y/Z). The kernel's Montgomery
+// factors cancel out in the division.
+fn unpack(limbs: &[u64]) -> Vec<SubgroupPoint> {
+    let coordinate =
+        |point: &[u64], i: usize| Fq::from_raw(point[4 * i..4 * (i + 1)].try_into().unwrap());
+    let mut z_invs: Vec<Fq> = limbs
+        .chunks(ENTRY_LIMBS)
+        .map(|point| coordinate(point, 2))
+        .collect();
+    batch_invert(&mut z_invs);
+    limbs
+        .chunks(ENTRY_LIMBS)
+        .zip(z_invs)
+        .map(|(point, z_inv)| {
+            let u = coordinate(point, 0) * z_inv;
+            let v = coordinate(point, 1) * z_inv;
+            // The kernel only adds multiples of subgroup points.
+            SubgroupPoint::from_raw_unchecked(u, v)
+        })
+        .collect()
+}
+
+// Inverts every one of `values` (all nonzero) with one field inversion.
+fn batch_invert(values: &mut [Fq]) {
+    let mut products = Vec::with_capacity(values.len());
+    let mut product = Fq::one();
+    for value in values.iter() {
+        products.push(product);
+        product *= value;
+    }
+    let mut inv = product.invert().unwrap();
+    for (value, product) in values.iter_mut().zip(products).rev() {
+        let next = inv * *value;
+        *value = inv * product;
+        inv = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructions::aes_prg::AesSeed;
+    use crate::constructions::jubjub::{points_from_seed, CurvePoint, Scalar};
+    use crate::util::Sampleable;
+    use ::group::Group as _;
+    use proptest::prelude::*;
+
+    extern "C" {
+        // `fixed_base.cl`, compiled for the CPU (see `build.rs`).
+        fn fixed_base_pow_host(
+            tables: *const u64,
+            digits: *const u8,
+            windows: u32,
+            entries: u32,
+            out: *mut u64,
+            bases: usize,
+        );
+    }
+
+    fn pow_many_fixed_host(tables: &[FixedBaseTable], exp: &Fr) -> Vec<SubgroupPoint> {
+        let packed = pack(tables);
+        let digits = digits(exp);
+        let mut limbs = vec![0u64; tables.len() * ENTRY_LIMBS];
+        unsafe {
+            fixed_base_pow_host(
+                packed.as_ptr(),
+                digits.as_ptr(),
+                WINDOWS as u32,
+                ENTRIES as u32,
+                limbs.as_mut_ptr(),
+                tables.len(),
+            );
+        }
+        unpack(&limbs)
+    }
+
+    fn check_kernel(bases: &[SubgroupPoint], exp: &Fr) {
+        let tables: Vec<_> = bases.iter().cloned().map(FixedBaseTable::new).collect();
+        let expected: Vec<_> = tables.iter().map(|table| table.pow(exp)).collect();
+        assert_eq!(pow_many_fixed_host(&tables, exp), expected);
+    }
+
+    proptest! {
+        #[test]
+        fn test_kernel_matches_cpu(
+            bases in prop::collection::vec(any::<CurvePoint>(), 1..4),
+            exp: Scalar,
+        ) {
+            let bases: Vec<_> = bases.into_iter().map(|base| base.inner).collect();
+            check_kernel(&bases, &exp.inner);
+        }
+    }
+
+    #[test]
+    fn test_kernel_edge_cases() {
+        let bases = vec![SubgroupPoint::identity(), CurvePoint::generator().inner];
+        for exp in &[Fr::zero(), Fr::one(), -Fr::one()] {
+            check_kernel(&bases, exp);
+        }
+    }
+
+    #[test]
+    fn test_small_batch_uses_cpu() {
+        assert!(pow_many_fixed(&[], &Fr::one()).is_none());
+    }
+
+    // Only does anything on machines with a GPU.
+    #[test]
+    fn test_pow_many_fixed_matches_cpu() {
+        if backend().is_none() {
+            return;
+        }
+        let tables: Vec<_> = points_from_seed(&AesSeed::default(), MIN_BASES)
+            .into_iter()
+            .map(FixedBaseTable::new)
+            .collect();
+        let exp = Scalar::sample().inner;
+        let expected: Vec<_> = tables.iter().map(|table| table.pow(&exp)).collect();
+        assert_eq!(pow_many_fixed(&tables, &exp), Some(expected.clone()));
+        // Again, with the tables already on the device.
+        assert_eq!(pow_many_fixed(&tables, &exp), Some(expected));
+    }
+}
@@ -0,0 +1,289 @@
+//! Just enough of OpenCL to run one kernel on a GPU.
+//!
+//! The library is loaded at runtime (with `dlopen`), so building doesn't need
+//! OpenCL installed, and machines without it just don't get a device.
+use std::ffi::{c_void, CString};
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr;
+use std::sync::Mutex;
+
+type ClInt = i32;
+type ClUint = u32;
+type Handle = *mut c_void;
+
+const CL_SUCCESS: ClInt = 0;
+const CL_DEVICE_NOT_FOUND: ClInt = -1;
+const CL_DEVICE_TYPE_GPU: u64 = 1 << 2;
+const CL_MEM_WRITE_ONLY: u64 = 1 << 1;
+const CL_MEM_READ_ONLY: u64 = 1 << 2;
+const CL_MEM_COPY_HOST_PTR: u64 = 1 << 5;
+const CL_TRUE: ClUint = 1;
+
+/// An OpenCL error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error(pub ClInt);
+
+fn check(code: ClInt) -> Result<(), Error> {
+    if code == CL_SUCCESS {
+        Ok(())
+    } else {
+        Err(Error(code))
+    }
+}
+
+macro_rules! api {
+    ($($name:ident: fn($($arg:ty),*) -> $ret:ty;)*) => {
+        #[allow(non_snake_case)]
+        struct Api {
+            $($name: unsafe extern "C" fn($($arg),*) -> $ret,)*
+        }
+
+        impl Api {
+            /// Looks up every function in `library` (from `dlopen`).
+            unsafe fn load(library: Handle) -> Option<Api> {
+                Some(Api {
+                    $($name: {
+                        let name = concat!(stringify!($name), "\0");
+                        let symbol = libc::dlsym(library, name.as_ptr() as *const c_char);
+                        if symbol.is_null() {
+                            return None;
+                        }
+                        mem::transmute::<Handle, unsafe extern "C" fn($($arg),*) -> $ret>(symbol)
+                    },)*
+                })
+            }
+        }
+    };
+}
+
+api! {
+    clGetPlatformIDs: fn(ClUint, *mut Handle, *mut ClUint) -> ClInt;
+    clGetDeviceIDs: fn(Handle, u64, ClUint, *mut Handle, *mut ClUint) -> ClInt;
+    clCreateContext: fn(*const isize, ClUint, *const Handle, Handle, Handle, *mut ClInt) -> Handle;
+    clCreateCommandQueue: fn(Handle, Handle, u64, *mut ClInt) -> Handle;
+    clCreateProgramWithSource: fn(Handle, ClUint, *const *const c_char, *const usize, *mut ClInt) -> Handle;
+    clBuildProgram: fn(Handle, ClUint, *const Handle, *const c_char, Handle, Handle) -> ClInt;
+    clCreateKernel: fn(Handle, *const c_char, *mut ClInt) -> Handle;
+    clCreateBuffer: fn(Handle, u64, usize, *mut c_void, *mut ClInt) -> Handle;
+    clSetKernelArg: fn(Handle, ClUint, usize, *const c_void) -> ClInt;
+    clEnqueueNDRangeKernel: fn(Handle, Handle, ClUint, *const usize, *const usize, *const usize, ClUint, *const Handle, *mut Handle) -> ClInt;
+    clEnqueueReadBuffer: fn(Handle, Handle, ClUint, usize, usize, *mut c_void, ClUint, *const Handle, *mut Handle) -> ClInt;
+    clReleaseMemObject: fn(Handle) -> ClInt;
+}
+
+/// A kernel, compiled for the first GPU found.
+pub struct Device {
+    api: Api,
+    context: Handle,
+    queue: Handle,
+    // Setting arguments and launching have to happen together.
+    kernel: Mutex<Handle>,
+}
+
+// OpenCL contexts, queues, and memory objects are safe to share between
+// threads. Kernels aren't (arguments are set on the kernel object itself), so
+// the kernel is behind a mutex.
+unsafe impl Send for Device {}
+unsafe impl Sync for Device {}
+
+/// Device memory, released when dropped.
+pub struct Buffer {
+    handle: Handle,
+    len: usize,
+    release: unsafe extern "C" fn(Handle) -> ClInt,
+}
+
+unsafe impl Send for Buffer {}
+unsafe impl Sync for Buffer {}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        // Launched kernels keep their own reference, so this is safe while
+        // they run.
+        unsafe { (self.release)(self.handle) };
+    }
+}
+
+/// An argument to a kernel.
+pub enum Arg<'a> {
+    Buffer(&'a Buffer),
+    Uint(u32),
+}
+
+impl Device {
+    /// Compiles kernel `name` from `source` for the first GPU found.
+    ///
+    /// `None` if there's no OpenCL library, no GPU, or the kernel doesn't
+    /// build. Contexts and such live as long as the process, so this is meant
+    /// to be called once.
+    pub fn open(source: &str, name: &str) -> Option<Device> {
+        unsafe {
+            let library = ["libOpenCL.so.1", "libOpenCL.so"].iter().find_map(|path| {
+                let path = CString::new(*path).unwrap();
+                let library = libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+                if library.is_null() {
+                    None
+                } else {
+                    Some(library)
+                }
+            })?;
+            let api = Api::load(library)?;
+            Device::compile(api, source, name).ok()
+        }
+    }
+
+    unsafe fn compile(api: Api, source: &str, name: &str) -> Result<Device, Error> {
+        let mut num_platforms = 0;
+        check((api.clGetPlatformIDs)(
+            0,
+            ptr::null_mut(),
+            &mut num_platforms,
+        ))?;
+        let mut platforms = vec![ptr::null_mut(); num_platforms as usize];
+        check((api.clGetPlatformIDs)(
+            num_platforms,
+            platforms.as_mut_ptr(),
+            ptr::null_mut(),
+        ))?;
+        let device = platforms
+            .into_iter()
+            .find_map(|platform| {
+                let mut device = ptr::null_mut();
+                let code = (api.clGetDeviceIDs)(
+                    platform,
+                    CL_DEVICE_TYPE_GPU,
+                    1,
+                    &mut device,
+                    ptr::null_mut(),
+                );
+                if code == CL_SUCCESS {
+                    Some(device)
+                } else {
+                    None
+                }
+            })
+            .ok_or(Error(CL_DEVICE_NOT_FOUND))?;
+
+        let mut code = CL_SUCCESS;
+        let context = (api.clCreateContext)(
+            ptr::null(),
+            1,
+            &device,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut code,
+        );
+        check(code)?;
+        let queue = (api.clCreateCommandQueue)(context, device, 0, &mut code);
+        check(code)?;
+
+        let source_ptr = source.as_ptr() as *const c_char;
+        let source_len = source.len();
+        let program =
+            (api.clCreateProgramWithSource)(context, 1, &source_ptr, &source_len, &mut code);
+        check(code)?;
+        check((api.clBuildProgram)(
+            program,
+            1,
+            &device,
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        ))?;
+        let name = CString::new(name).unwrap();
+        let kernel = (api.clCreateKernel)(program, name.as_ptr(), &mut code);
+        check(code)?;
+
+        Ok(Device {
+            api,
+            context,
+            queue,
+            kernel: Mutex::new(kernel),
+        })
+    }
+
+    fn buffer(&self, flags: u64, len: usize, data: *mut c_void) -> Result<Buffer, Error> {
+        let mut code = CL_SUCCESS;
+        let handle =
+            unsafe { (self.api.clCreateBuffer)(self.context, flags, len, data, &mut code) };
+        check(code)?;
+        Ok(Buffer {
+            handle,
+            len,
+            release: self.api.clReleaseMemObject,
+        })
+    }
+
+    /// Copies `data` to a new read-only buffer.
+    pub fn upload<T: Copy>(&self, data: &[T]) -> Result<Buffer, Error> {
+        self.buffer(
+            CL_MEM_READ_ONLY | CL_MEM_COPY_HOST_PTR,
+            mem::size_of_val(data),
+            data.as_ptr() as *mut c_void,
+        )
+    }
+
+    /// Makes a write-only buffer of `len` bytes.
+    pub fn output(&self, len: usize) -> Result<Buffer, Error> {
+        self.buffer(CL_MEM_WRITE_ONLY, len, ptr::null_mut())
+    }
+
+    /// Runs the kernel on `work_items` work items.
+    pub fn launch(&self, args: &[Arg], work_items: usize) -> Result<(), Error> {
+        let kernel = self.kernel.lock().unwrap();
+        for (index, arg) in args.iter().enumerate() {
+            let code = match arg {
+                Arg::Buffer(buffer) => unsafe {
+                    (self.api.clSetKernelArg)(
+                        *kernel,
+                        index as ClUint,
+                        mem::size_of::<Handle>(),
+                        &buffer.handle as *const Handle as *const c_void,
+                    )
+                },
+                Arg::Uint(value) => unsafe {
+                    (self.api.clSetKernelArg)(
+                        *kernel,
+                        index as ClUint,
+                        mem::size_of::<u32>(),
+                        value as *const u32 as *const c_void,
+                    )
+                },
+            };
+            check(code)?;
+        }
+        check(unsafe {
+            (self.api.clEnqueueNDRangeKernel)(
+                self.queue,
+                *kernel,
+                1,
+                ptr::null(),
+                &work_items,
+                ptr::null(),
+                0,
+                ptr::null(),
+                ptr::null_mut(),
+            )
+        })
+    }
+
+    /// Waits for queued work, then copies `buffer` into `out`.
+    pub fn read<T: Copy>(&self, buffer: &Buffer, out: &mut [T]) -> Result<(), Error> {
+        let len = mem::size_of_val(out);
+        assert!(len <= buffer.len, "reading past the end of the buffer");
+        check(unsafe {
+            (self.api.clEnqueueReadBuffer)(
+                self.queue,
+                buffer.handle,
+                CL_TRUE,
+                0,
+                len,
+                out.as_mut_ptr() as *mut c_void,
+                0,
+                ptr::null(),
+                ptr::null_mut(),
+            )
+        })
+    }
+}
@@ -1,15 +1,30 @@
 mod aes_prg;
 mod baby;
+pub mod bls12_381;
+mod chacha_prg;
+mod gf2_128;
 pub mod jubjub;
+mod montgomery;
+pub mod ristretto;
 
 use crate::bytes::Bytes;
-use crate::dpf::{MultiKeyDpf, TwoKeyDpf};
+use crate::dpf::{MultiKeyDpf, TreeDpf, TwoKeyDpf};
 use crate::prg::GroupPrg;
 use crate::vdpf::FieldVdpf;
 
 pub use self::jubjub::Scalar as AuthKey;
+pub use aes_prg::tree_node_prg;
 pub use aes_prg::AesPrg;
 pub use aes_prg::AesSeed;
+pub use aes_prg::SEED_SIZE as AES_SEED_SIZE;
+pub use chacha_prg::ChaChaPrg;
+pub use chacha_prg::ChaChaSeed;
+pub use gf2_128::Gf2_128;
+pub use montgomery::Fp;
+
+/// The prime field of order `2^61 - 1`: a cheaper (but less sound) choice of
+/// `AuthKey` for two-key audits.
+pub type Fp61 = Fp<2305843009213693951>;
 
 impl From<AesSeed> for AuthKey {
     fn from(rhs: AesSeed) -> AuthKey {
@@ -19,8 +34,50 @@ impl From<AesSeed> for AuthKey {
     }
 }
 
-pub type TwoKeyVdpf = FieldVdpf<TwoKeyDpf<AesPrg>, AuthKey>;
+impl From<ChaChaSeed> for AuthKey {
+    fn from(rhs: ChaChaSeed) -> AuthKey {
+        use std::convert::TryInto;
+        let bytes: Bytes = rhs.into();
+        bytes.try_into().unwrap()
+    }
+}
+
+impl From<AesSeed> for Fp61 {
+    fn from(rhs: AesSeed) -> Fp61 {
+        use std::convert::TryInto;
+        let bytes: Vec<u8> = rhs.into();
+        u128::from_le_bytes(bytes.try_into().unwrap()).into()
+    }
+}
+
+impl From<AesSeed> for Gf2_128 {
+    fn from(rhs: AesSeed) -> Gf2_128 {
+        use std::convert::TryInto;
+        let bytes: Vec<u8> = rhs.into();
+        u128::from_le_bytes(bytes.try_into().unwrap()).into()
+    }
+}
+
+/// Two-key VDPF; AES-based unless another PRG (e.g. `ChaChaPrg`) is given.
+pub type TwoKeyVdpf<P = AesPrg> = FieldVdpf<TwoKeyDpf<P>, AuthKey>;
+pub type TwoKeyFp61Vdpf = FieldVdpf<TwoKeyDpf<AesPrg>, Fp61>;
+/// Like `TwoKeyVdpf`, but with audits over GF(2^128) (see `Gf2_128`) rather
+/// than the Jubjub scalar field.
+pub type TwoKeyGf128Vdpf = FieldVdpf<TwoKeyDpf<AesPrg>, Gf2_128>;
+/// Like `TwoKeyVdpf`, but with keys logarithmic (rather than linear) in the
+/// number of channels.
+///
+/// Library-only for now: no protocol in `spectrum_protocol` uses it, so its
+/// keys have no wire format.
+pub type TwoKeyCompactVdpf = FieldVdpf<TreeDpf<AesPrg>, AuthKey>;
 pub type MultiKeyVdpf = FieldVdpf<MultiKeyDpf<GroupPrg<jubjub::CurvePoint>>, AuthKey>;
+/// Like `MultiKeyVdpf`, but over Ristretto rather than Jubjub.
+pub type MultiKeyRistrettoVdpf =
+    FieldVdpf<MultiKeyDpf<GroupPrg<ristretto::CurvePoint>>, ristretto::Scalar>;
+/// Like `MultiKeyVdpf`, but over (pairing-friendly) BLS12-381 G1 rather than
+/// Jubjub.
+pub type MultiKeyBls12Vdpf =
+    FieldVdpf<MultiKeyDpf<GroupPrg<bls12_381::CurvePoint>>, bls12_381::Scalar>;
 #[cfg(feature = "testing")]
 pub type IntsModP = baby::IntMod<11>;
 
@@ -0,0 +1,298 @@
+//! Prime fields with word-sized moduli, using Montgomery multiplication.
+//!
+//! Much cheaper than the Jubjub scalar field (no big-integer arithmetic), at
+//! the cost of a smaller field: a cheating client passes a two-key audit over
+//! `Fp<P>` with probability about `1/P`.
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::{repeat_with, Sum};
+use std::ops;
+
+use rand::{prelude::*, Rng};
+use rug::Integer;
+use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::DefaultIsZeroes;
+
+use crate::algebra::{Field, Group, Monoid};
+use crate::bytes::Bytes;
+use crate::util::Sampleable;
+
+/// `-p^{-1} mod 2^64`, for odd `p`.
+const fn neg_inv(p: u64) -> u64 {
+    // Newton's method: each step doubles the number of correct low bits, and
+    // `p * p == 1 (mod 8)` for odd `p`, so we start with 3 (then 6, ..., 96).
+    let mut inv = p;
+    let mut i = 0;
+    while i < 5 {
+        inv = inv.wrapping_mul(2u64.wrapping_sub(p.wrapping_mul(inv)));
+        i += 1;
+    }
+    inv.wrapping_neg()
+}
+
+/// `2^64 mod p`.
+const fn r_mod(p: u64) -> u64 {
+    ((1u128 << 64) % (p as u128)) as u64
+}
+
+/// `2^128 mod p`.
+const fn r2_mod(p: u64) -> u64 {
+    let r = r_mod(p) as u128;
+    ((r * r) % (p as u128)) as u64
+}
+
+/// An element of the prime field of order `P`.
+///
+/// `P` must be an odd prime less than `2^63`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "Bytes", into = "Bytes")]
+pub struct Fp<const P: u64> {
+    // x * 2^64 mod P; always fully reduced, so derived Eq/Hash are fine.
+    mont: u64,
+}
+
+impl<const P: u64> Fp<P> {
+    pub const MODULUS: u64 = P;
+    const NEG_INV: u64 = neg_inv(P);
+    const R: u64 = r_mod(P);
+    const R2: u64 = r2_mod(P);
+
+    /// Montgomery reduction: `t * 2^-64 mod P`, for `t < P * 2^64`.
+    #[inline]
+    fn redc(t: u128) -> u64 {
+        let m = (t as u64).wrapping_mul(Self::NEG_INV);
+        // Can't overflow: t + m * P < 2 * P * 2^64 <= 2^128.
+        let u = ((t + (m as u128) * (P as u128)) >> 64) as u64;
+        if u >= P {
+            u - P
+        } else {
+            u
+        }
+    }
+
+    /// The canonical representative of this element, in `[0, P)`.
+    pub fn value(&self) -> u64 {
+        Self::redc(self.mont as u128)
+    }
+
+    fn pow(&self, mut exp: u64) -> Self {
+        let mut base = *self;
+        let mut acc = Self::one();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                acc = acc * base;
+            }
+            base = base * base;
+            exp >>= 1;
+        }
+        acc
+    }
+}
+
+impl<const P: u64> From<u64> for Fp<P> {
+    /// Reduces `value` mod `P`.
+    fn from(value: u64) -> Self {
+        assert!(P % 2 == 1 && P < 1 << 63, "modulus must be odd and < 2^63");
+        let mont = Self::redc(((value % P) as u128) * (Self::R2 as u128));
+        Fp { mont }
+    }
+}
+
+impl<const P: u64> From<u128> for Fp<P> {
+    /// Reduces `value` mod `P`.
+    fn from(value: u128) -> Self {
+        Self::from((value % (P as u128)) as u64)
+    }
+}
+
+impl<const P: u64> fmt::Debug for Fp<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Fp").field(&self.value()).finish()
+    }
+}
+
+impl<const P: u64> Monoid for Fp<P> {
+    fn zero() -> Self {
+        Fp { mont: 0 }
+    }
+}
+
+impl<const P: u64> ConstantTimeEq for Fp<P> {
+    fn ct_eq(&self, rhs: &Self) -> Choice {
+        self.mont.ct_eq(&rhs.mont)
+    }
+}
+
+impl<const P: u64> Default for Fp<P> {
+    fn default() -> Self {
+        Self::zero()
+    }
+}
+
+impl<const P: u64> DefaultIsZeroes for Fp<P> {}
+
+impl<const P: u64> Group for Fp<P> {
+    fn order() -> Integer {
+        Integer::from(P)
+    }
+}
+
+impl<const P: u64> Field for Fp<P> {
+    fn one() -> Self {
+        Fp { mont: Self::R }
+    }
+
+    fn mul_invert(&self) -> Self {
+        if self.mont == 0 {
+            panic!("Zero has no multiplicative inverse");
+        }
+        // Fermat's little theorem.
+        self.pow(P - 2)
+    }
+}
+
+impl<const P: u64> ops::Add for Fp<P> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        // Can't overflow: both are < 2^63.
+        let sum = self.mont + rhs.mont;
+        let mont = if sum >= P { sum - P } else { sum };
+        Fp { mont }
+    }
+}
+
+impl<const P: u64> ops::AddAssign for Fp<P> {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl<const P: u64> ops::Neg for Fp<P> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        let mont = if self.mont == 0 { 0 } else { P - self.mont };
+        Fp { mont }
+    }
+}
+
+impl<const P: u64> ops::Sub for Fp<P> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + (-rhs)
+    }
+}
+
+impl<const P: u64> ops::Mul for Fp<P> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let mont = Self::redc((self.mont as u128) * (rhs.mont as u128));
+        Fp { mont }
+    }
+}
+
+impl<const P: u64> Sum for Fp<P> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), ops::Add::add)
+    }
+}
+
+impl<const P: u64> From<Fp<P>> for Bytes {
+    fn from(value: Fp<P>) -> Bytes {
+        Bytes::from(value.value().to_le_bytes().to_vec())
+    }
+}
+
+impl<const P: u64> TryFrom<Bytes> for Fp<P> {
+    type Error = String;
+
+    fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
+        let bytes: [u8; 8] = TryFrom::try_from(bytes.as_ref())
+            .map_err(|_| format!("invalid byte length {}", bytes.len()))?;
+        let value = u64::from_le_bytes(bytes);
+        if value >= P {
+            return Err(format!("{} out of range", value));
+        }
+        Ok(Self::from(value))
+    }
+}
+
+impl<const P: u64> Sampleable for Fp<P> {
+    type Seed = <StdRng as SeedableRng>::Seed;
+
+    fn sample() -> Self {
+        thread_rng().gen_range(0..P).into()
+    }
+
+    fn sample_many_from_seed(seed: &Self::Seed, n: usize) -> Vec<Self> {
+        let mut rng = <StdRng as SeedableRng>::from_seed(*seed);
+        repeat_with(|| rng.gen_range(0..P))
+            .take(n)
+            .map(Self::from)
+            .collect()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+use proptest::prelude::*;
+#[cfg(any(test, feature = "testing"))]
+impl<const P: u64> Arbitrary for Fp<P> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (0..P).prop_map(Self::from).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2^61 - 1
+    type Mersenne61 = Fp<2305843009213693951>;
+
+    check_field_laws!(Mersenne61);
+    check_group_laws!(Mersenne61);
+    check_sampleable!(Mersenne61);
+    check_shareable!(Mersenne61);
+    check_linearly_shareable!(Mersenne61);
+    check_roundtrip!(
+        Mersenne61,
+        Into::<Bytes>::into,
+        |b| Mersenne61::try_from(b).unwrap(),
+        check_bytes_roundtrip
+    );
+
+    mod small {
+        use super::*;
+        // Small enough that proptest hits edge cases (zero, P - 1).
+        check_field_laws!(Fp<11>);
+    }
+
+    proptest! {
+        #[test]
+        fn test_mul_matches_naive(a in 0..Mersenne61::MODULUS, b in 0..Mersenne61::MODULUS) {
+            let expected = ((a as u128) * (b as u128)) % (Mersenne61::MODULUS as u128);
+            prop_assert_eq!(
+                (Mersenne61::from(a) * Mersenne61::from(b)).value(),
+                expected as u64
+            );
+        }
+
+        #[test]
+        fn test_value_roundtrip(a in 0..Mersenne61::MODULUS) {
+            prop_assert_eq!(Mersenne61::from(a).value(), a);
+        }
+    }
+
+    #[test]
+    fn test_neg_inv() {
+        let p = Mersenne61::MODULUS;
+        assert_eq!(p.wrapping_mul(neg_inv(p)), u64::MAX);
+    }
+}
@@ -0,0 +1,500 @@
+//! The Ristretto group (over Curve25519), as an alternative to Jubjub for
+//! seed-homomorphic PRGs.
+use std::convert::{TryFrom, TryInto};
+use std::hash::{Hash, Hasher};
+use std::iter::Sum;
+use std::ops;
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_TABLE;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoBasepointTable, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar as DalekScalar;
+use curve25519_dalek::traits::{Identity, MultiscalarMul};
+use rand::RngCore;
+use rug::{integer::Order, Integer};
+use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::DefaultIsZeroes;
+
+use crate::algebra::{Field, Group, Monoid, SpecialExponentMonoid};
+use crate::bytes::Bytes;
+use crate::constructions::aes_prg::{AesPrg, AesSeed};
+use crate::util::Sampleable;
+
+#[cfg(any(test, feature = "testing"))]
+use proptest::prelude::*;
+
+// 2^252 + 27742317777372353535851937790883648493 (the order of the
+// Ristretto group), least-significant limb first.
+const ORDER: [u64; 4] = [
+    0x5812_631a_5cf5_d3ed_u64,
+    0x14de_f9de_a2f7_9cd6_u64,
+    0x0000_0000_0000_0000_u64,
+    0x1000_0000_0000_0000_u64,
+];
+
+// size of (encoded) group elements and scalars
+pub const ELEMENT_BYTES: usize = 32;
+// bytes to sample for a uniformly random element
+const WIDE_BYTES: usize = 64;
+const BYTE_ORDER: Order = Order::LsfLe;
+
+fn wide(bytes: &[u8]) -> [u8; WIDE_BYTES] {
+    bytes.try_into().expect("need 64 bytes")
+}
+
+fn random_wide() -> [u8; WIDE_BYTES] {
+    let mut bytes = [0u8; WIDE_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+// `n` chunks of 64 pseudorandom bytes from `seed`.
+fn wide_from_seed(seed: &AesSeed, n: usize) -> Vec<[u8; WIDE_BYTES]> {
+    use crate::prg::Prg;
+    if n == 0 {
+        return vec![];
+    }
+    let prg = AesPrg::new(WIDE_BYTES * n);
+    let rand_bytes: Vec<u8> = prg.eval(seed).into();
+    rand_bytes.chunks_exact(WIDE_BYTES).map(wide).collect()
+}
+
+/// A point in the Ristretto group.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "Vec<u8>", into = "Vec<u8>")]
+pub struct CurvePoint {
+    inner: RistrettoPoint,
+}
+
+impl CurvePoint {
+    pub fn generator() -> Self {
+        curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT.into()
+    }
+}
+
+impl From<Scalar> for CurvePoint {
+    fn from(scalar: Scalar) -> Self {
+        (&RISTRETTO_BASEPOINT_TABLE * &scalar.inner).into() // exponentiation!
+    }
+}
+
+// Like Jubjub, messages get encoded as points by decoding their bytes, which
+// only works for some messages.
+impl TryFrom<Bytes> for CurvePoint {
+    type Error = &'static str;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        let mut bytes: Vec<u8> = value.into();
+        if bytes.len() < ELEMENT_BYTES {
+            bytes.extend(vec![0u8; ELEMENT_BYTES - bytes.len()]);
+        }
+        CurvePoint::try_from(bytes)
+    }
+}
+
+impl From<CurvePoint> for Bytes {
+    fn from(value: CurvePoint) -> Bytes {
+        Vec::<u8>::from(value).into()
+    }
+}
+
+impl From<CurvePoint> for Vec<u8> {
+    fn from(value: CurvePoint) -> Self {
+        value.inner.compress().to_bytes().to_vec()
+    }
+}
+
+impl TryFrom<Vec<u8>> for CurvePoint {
+    type Error = &'static str;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        let bytes: [u8; ELEMENT_BYTES] = value.try_into().map_err(|_| "bad bytes size")?;
+        CompressedRistretto(bytes)
+            .decompress()
+            .map(Into::into)
+            .ok_or("bad conversion from bytes")
+    }
+}
+
+impl Sampleable for CurvePoint {
+    type Seed = AesSeed;
+
+    fn sample() -> Self {
+        RistrettoPoint::from_uniform_bytes(&random_wide()).into()
+    }
+
+    fn sample_many_from_seed(seed: &Self::Seed, n: usize) -> Vec<Self> {
+        wide_from_seed(seed, n)
+            .iter()
+            .map(|bytes| RistrettoPoint::from_uniform_bytes(bytes).into())
+            .collect()
+    }
+}
+
+impl Monoid for CurvePoint {
+    fn zero() -> Self {
+        RistrettoPoint::identity().into()
+    }
+}
+
+impl Group for CurvePoint {
+    fn order() -> Integer {
+        Integer::from_digits(&ORDER, BYTE_ORDER)
+    }
+}
+
+impl ops::Add for CurvePoint {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        (self.inner + rhs.inner).into()
+    }
+}
+
+impl ops::AddAssign for CurvePoint {
+    fn add_assign(&mut self, rhs: Self) {
+        self.inner += rhs.inner;
+    }
+}
+
+impl ops::Neg for CurvePoint {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        (-self.inner).into()
+    }
+}
+
+impl ops::Sub for CurvePoint {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        (self.inner - rhs.inner).into()
+    }
+}
+
+impl Sum for CurvePoint {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> CurvePoint {
+        let mut total = <Self as Monoid>::zero();
+        iter.for_each(|value| total += value);
+        total
+    }
+}
+
+// Boilerplate: conversions etc.
+impl From<RistrettoPoint> for CurvePoint {
+    fn from(inner: RistrettoPoint) -> Self {
+        CurvePoint { inner }
+    }
+}
+
+impl Hash for CurvePoint {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.compress().to_bytes().hash(state);
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Arbitrary for CurvePoint {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        any::<Scalar>().prop_map(CurvePoint::from).boxed()
+    }
+}
+
+/// A scalar (exponent) for the Ristretto group.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "Vec<u8>", into = "Vec<u8>")]
+pub struct Scalar {
+    inner: DalekScalar,
+}
+
+impl Monoid for Scalar {
+    fn zero() -> Self {
+        DalekScalar::zero().into()
+    }
+}
+
+impl Group for Scalar {
+    fn order() -> Integer {
+        Integer::from_digits(&ORDER, BYTE_ORDER)
+    }
+}
+
+impl Field for Scalar {
+    fn mul_invert(&self) -> Self {
+        assert!(self.inner != DalekScalar::zero(), "zero has no inverse");
+        self.inner.invert().into()
+    }
+
+    fn one() -> Self {
+        DalekScalar::one().into()
+    }
+}
+
+impl ops::Add for Scalar {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        (self.inner + rhs.inner).into()
+    }
+}
+
+impl ops::AddAssign for Scalar {
+    fn add_assign(&mut self, rhs: Self) {
+        self.inner += rhs.inner;
+    }
+}
+
+impl ops::Neg for Scalar {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        (-self.inner).into()
+    }
+}
+
+impl ops::Sub for Scalar {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        (self.inner - rhs.inner).into()
+    }
+}
+
+impl ops::Mul for Scalar {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        (self.inner * rhs.inner).into()
+    }
+}
+
+impl Sum for Scalar {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Scalar {
+        let mut total = <Self as Monoid>::zero();
+        iter.for_each(|value| total += value);
+        total
+    }
+}
+
+// Boilerplate: conversions etc.
+impl From<DalekScalar> for Scalar {
+    fn from(inner: DalekScalar) -> Self {
+        Scalar { inner }
+    }
+}
+
+impl From<Scalar> for Bytes {
+    fn from(value: Scalar) -> Bytes {
+        Bytes::from(value.inner.to_bytes().to_vec())
+    }
+}
+
+impl TryFrom<Bytes> for Scalar {
+    type Error = String;
+
+    fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
+        let len = bytes.len();
+        if len <= ELEMENT_BYTES {
+            let mut bytes_arr: [u8; ELEMENT_BYTES] = [0; ELEMENT_BYTES];
+            bytes_arr[..len].copy_from_slice(bytes.as_ref());
+            DalekScalar::from_canonical_bytes(bytes_arr)
+                .map(Scalar::from)
+                .ok_or_else(|| "Converting from bytes failed.".to_string())
+        } else if len == WIDE_BYTES {
+            Ok(DalekScalar::from_bytes_mod_order_wide(&wide(bytes.as_ref())).into())
+        } else {
+            Err(format!("invalid byte length {}", bytes.len()))
+        }
+    }
+}
+
+impl TryFrom<Vec<u8>> for Scalar {
+    type Error = &'static str;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        DalekScalar::from_canonical_bytes(value.try_into().map_err(|_| "vec was wrong size")?)
+            .map(Scalar::from)
+            .ok_or("converting from bytes failed")
+    }
+}
+
+impl From<Scalar> for Vec<u8> {
+    fn from(value: Scalar) -> Vec<u8> {
+        value.inner.to_bytes().into()
+    }
+}
+
+impl Hash for Scalar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.as_bytes().hash(state);
+    }
+}
+
+impl ConstantTimeEq for Scalar {
+    fn ct_eq(&self, rhs: &Scalar) -> Choice {
+        self.inner.ct_eq(&rhs.inner)
+    }
+}
+
+impl Default for Scalar {
+    fn default() -> Self {
+        Scalar::zero()
+    }
+}
+
+impl DefaultIsZeroes for Scalar {}
+
+#[cfg(any(test, feature = "testing"))]
+impl Arbitrary for Scalar {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        proptest::collection::vec(any::<u8>(), WIDE_BYTES)
+            .prop_map(|v| DalekScalar::from_bytes_mod_order_wide(&wide(&v)).into())
+            .boxed()
+    }
+}
+
+impl Sampleable for Scalar {
+    type Seed = AesSeed;
+
+    fn sample() -> Self {
+        DalekScalar::from_bytes_mod_order_wide(&random_wide()).into()
+    }
+
+    fn sample_many_from_seed(seed: &Self::Seed, n: usize) -> Vec<Self> {
+        wide_from_seed(seed, n)
+            .iter()
+            .map(|bytes| DalekScalar::from_bytes_mod_order_wide(bytes).into())
+            .collect()
+    }
+}
+
+impl SpecialExponentMonoid for CurvePoint {
+    type Exponent = Scalar;
+    // Dalek's own precomputed multiples (as for the standard basepoint).
+    type FixedBase = RistrettoBasepointTable;
+
+    fn pow(&self, exp: Self::Exponent) -> Self {
+        (self.inner * exp.inner).into()
+    }
+
+    fn fixed_base(&self) -> RistrettoBasepointTable {
+        RistrettoBasepointTable::create(&self.inner)
+    }
+
+    fn pow_fixed(table: &RistrettoBasepointTable, exp: &Scalar) -> Self {
+        (table * &exp.inner).into()
+    }
+
+    fn msm(bases: &[Self], exps: &[Self::Exponent]) -> Self {
+        assert_eq!(bases.len(), exps.len(), "need one exponent per base");
+        RistrettoPoint::multiscalar_mul(
+            exps.iter().map(|exp| exp.inner),
+            bases.iter().map(|base| base.inner),
+        )
+        .into()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn pow_many(bases: &[Self], exp: &Self::Exponent) -> Vec<Self> {
+        use rayon::prelude::*;
+        bases
+            .par_iter()
+            .map(|base| (base.inner * exp.inner).into())
+            .collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn pow_many_fixed(tables: &[RistrettoBasepointTable], exp: &Scalar) -> Vec<Self> {
+        use rayon::prelude::*;
+        tables
+            .par_iter()
+            .map(|table| (table * &exp.inner).into())
+            .collect()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn pow_many_fixed_into(tables: &[RistrettoBasepointTable], exp: &Scalar, out: &mut [Self]) {
+        use rayon::prelude::*;
+        assert_eq!(tables.len(), out.len(), "need one output per base");
+        out.par_iter_mut()
+            .zip(tables)
+            .for_each(|(acc, table)| *acc += (table * &exp.inner).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dpf::MultiKeyDpf;
+    use crate::prg::GroupPrg;
+
+    #[test]
+    fn test_order() {
+        assert_eq!(
+            CurvePoint::order(),
+            (Integer::from(1) << 252)
+                + Integer::from_str_radix("27742317777372353535851937790883648493", 10).unwrap()
+        );
+        assert_eq!(
+            CurvePoint::generator().pow(Scalar::from(-DalekScalar::one())),
+            -CurvePoint::generator()
+        );
+    }
+
+    check_group_laws!(CurvePoint);
+    check_monoid_custom_exponent!(CurvePoint);
+    check_field_laws!(Scalar);
+    check_sampleable!(Scalar);
+    check_shareable!(Scalar);
+    check_linearly_shareable!(Scalar);
+
+    mod point {
+        use super::*;
+        check_sampleable!(CurvePoint);
+    }
+
+    check_roundtrip!(
+        CurvePoint,
+        Into::<Vec<u8>>::into,
+        |x| CurvePoint::try_from(x).unwrap(),
+        point_to_vec_u8_rt
+    );
+    check_roundtrip!(
+        CurvePoint,
+        |p: CurvePoint| serde_json::to_string(&p).unwrap(),
+        |s: String| serde_json::from_str(&s).unwrap(),
+        point_to_json_rt
+    );
+    check_prg!(GroupPrg<CurvePoint>);
+    check_seed_homomorphic_prg!(GroupPrg<CurvePoint>);
+
+    check_dpf!(MultiKeyDpf<GroupPrg<CurvePoint>>);
+
+    check_roundtrip!(
+        Scalar,
+        Into::<Vec<u8>>::into,
+        |x| Scalar::try_from(x).unwrap(),
+        scalar_to_vec_u8
+    );
+    check_roundtrip!(
+        Scalar,
+        |p: Scalar| serde_json::to_string(&p).unwrap(),
+        |s: String| serde_json::from_str(&s).unwrap(),
+        scalar_to_json_rt
+    );
+
+    use crate::ElementVector;
+    check_roundtrip!(
+        ElementVector<CurvePoint>,
+        Into::<Vec<u8>>::into,
+        |d| ElementVector::<CurvePoint>::try_from(d).unwrap(),
+        element_vector_vec_u8_rt
+    );
+}
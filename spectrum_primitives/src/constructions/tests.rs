@@ -1,11 +1,112 @@
-use super::{MultiKeyVdpf, TwoKeyVdpf};
+use super::{
+    ChaChaPrg, MultiKeyBls12Vdpf, MultiKeyRistrettoVdpf, MultiKeyVdpf, TwoKeyCompactVdpf,
+    TwoKeyFp61Vdpf, TwoKeyGf128Vdpf, TwoKeyVdpf,
+};
 
 mod two_key_vdpf_with_jubjub {
     use super::*;
     check_vdpf!(TwoKeyVdpf);
 }
 
+mod two_key_vdpf_with_chacha {
+    use super::*;
+    check_vdpf!(TwoKeyVdpf<ChaChaPrg>);
+}
+
+mod two_key_vdpf_with_fp61 {
+    use super::*;
+    check_vdpf!(TwoKeyFp61Vdpf);
+}
+
+mod two_key_vdpf_with_gf2_128 {
+    use super::*;
+    check_vdpf!(TwoKeyGf128Vdpf);
+}
+
+mod two_key_compact_vdpf_with_jubjub {
+    use super::*;
+    use crate::Bytes;
+
+    check_vdpf!(TwoKeyCompactVdpf);
+
+    #[test]
+    fn test_wrong_depth_fails_audit() {
+        let vdpf = TwoKeyCompactVdpf::with_channels_msg_size(8, 16);
+        let auth_keys = vdpf.new_access_keys();
+        let mut dpf_keys = vdpf.gen(Bytes::from(vec![7; 16]), 3);
+        let proofs = vdpf.gen_proofs(&auth_keys[3], 3, &dpf_keys);
+        dpf_keys[0].corrections.pop();
+        let tokens = dpf_keys
+            .iter()
+            .zip(proofs)
+            .map(|(key, proof)| vdpf.gen_audit(&auth_keys, key, proof))
+            .collect();
+        assert!(!vdpf.check_audit(tokens));
+    }
+}
+
 mod many_key_vdpf_with_jubjub {
     use super::*;
     check_vdpf!(MultiKeyVdpf);
 }
+
+mod many_key_vdpf_with_ristretto {
+    use super::*;
+    check_vdpf!(MultiKeyRistrettoVdpf);
+}
+
+mod many_key_vdpf_with_bls12_381 {
+    use super::*;
+    check_vdpf!(MultiKeyBls12Vdpf);
+}
+
+mod many_key_vdpf_from_seed {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_vdpf() {
+        let vdpf = MultiKeyVdpf::with_channels_parties_msg_size_seed(3, 2, 64, [1; 32]);
+        let same = MultiKeyVdpf::with_channels_parties_msg_size_seed(3, 2, 64, [1; 32]);
+        let other = MultiKeyVdpf::with_channels_parties_msg_size_seed(3, 2, 64, [2; 32]);
+        assert_eq!(vdpf, same);
+        assert_ne!(vdpf, other);
+    }
+}
+
+mod secrets {
+    use super::super::{AesSeed, AuthKey, Fp61};
+    use crate::algebra::Monoid;
+    use crate::vdpf::two_key::{ProofShare, Token};
+    use crate::{Bytes, ConstantTimeEq, Zeroize};
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_ct_eq_matches_eq(a: AuthKey, b: AuthKey, c: Fp61, d: Fp61) {
+            prop_assert_eq!(bool::from(a.ct_eq(&b)), a == b);
+            prop_assert_eq!(bool::from(c.ct_eq(&d)), c == d);
+        }
+
+        #[test]
+        fn test_token_ct_eq_matches_eq(a: Token<AuthKey>, b: Token<AuthKey>) {
+            prop_assert_eq!(bool::from(a.ct_eq(&b)), a == b);
+            prop_assert!(bool::from(a.ct_eq(&a.clone())));
+        }
+
+        #[test]
+        fn test_proof_share_zeroize(mut proof: ProofShare<AuthKey>) {
+            proof.zeroize();
+            prop_assert_eq!(proof, ProofShare::new(AuthKey::zero(), AuthKey::zero()));
+        }
+    }
+
+    #[test]
+    fn test_seed_into_bytes() {
+        // Taking the bytes out of a seed (which zeroizes on drop) keeps them.
+        let seed = AesSeed::random();
+        let expected = seed.clone();
+        let bytes = Bytes::from(seed);
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(bytes, Bytes::from(expected));
+    }
+}
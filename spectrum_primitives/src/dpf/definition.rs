@@ -1,6 +1,79 @@
+use std::fmt;
+
+/// A key (e.g., from the network) that doesn't have the shape of the DPF's own
+/// keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyMismatch {
+    /// What's the wrong size (say, "seeds").
+    pub field: &'static str,
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl KeyMismatch {
+    /// An error unless the key's `field` has the `expected` size.
+    pub fn check(field: &'static str, expected: usize, actual: usize) -> Result<(), KeyMismatch> {
+        if expected != actual {
+            return Err(KeyMismatch {
+                field,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for KeyMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "key mismatch: expected {} {}, got {}",
+            self.expected, self.field, self.actual
+        )
+    }
+}
+
+impl std::error::Error for KeyMismatch {}
+
 /// Distributed Point Function
 /// Must generate a set of keys k_1, k_2, ...
 /// such that combine(eval(k_1), eval(k_2), ...) = e_i * msg
+///
+/// # Examples
+///
+/// Each party evaluates its key separately; only the combination reveals the
+/// message (at index 1 here, with null messages everywhere else).
+///
+/// ```
+/// use spectrum_primitives::{Bytes, Dpf, TwoKeyVdpf};
+///
+/// let dpf = TwoKeyVdpf::with_channels_msg_size(3, 16);
+/// let msg = Bytes::from(vec![7; 16]);
+/// let keys = dpf.gen(msg.clone(), 1);
+/// assert_eq!(keys.len(), dpf.keys());
+///
+/// let parts = keys.into_iter().map(|key| dpf.eval(key)).collect();
+/// let result = dpf.combine(parts);
+/// assert_eq!(result, vec![dpf.null_message(), msg, dpf.null_message()]);
+/// ```
+///
+/// Evaluating many keys into one accumulator per party gives the combination
+/// of all of their messages:
+///
+/// ```
+/// use spectrum_primitives::{Bytes, Dpf, TwoKeyVdpf};
+///
+/// let dpf = TwoKeyVdpf::with_channels_msg_size(2, 16);
+/// let msg = Bytes::from(vec![7; 16]);
+/// let mut accumulators = vec![vec![dpf.null_message(); dpf.points()]; dpf.keys()];
+/// for keys in vec![dpf.gen(msg.clone(), 0), dpf.gen_empty(), dpf.gen_empty()] {
+///     for (key, accumulator) in keys.into_iter().zip(accumulators.iter_mut()) {
+///         dpf.eval_into(key, accumulator);
+///     }
+/// }
+/// assert_eq!(dpf.combine(accumulators), vec![msg, dpf.null_message()]);
+/// ```
 pub trait Dpf {
     type Key;
     type Message;
@@ -14,6 +87,30 @@ pub trait Dpf {
     fn gen(&self, msg: Self::Message, idx: usize) -> Vec<Self::Key>;
     fn gen_empty(&self) -> Vec<Self::Key>;
     fn eval(&self, key: Self::Key) -> Vec<Self::Message>;
+    /// Evaluate `key` and combine the result into `acc` in place.
+    ///
+    /// Same as `acc = combine(vec![acc, eval(key)])`, but avoids allocating
+    /// the intermediate evaluation where possible.
+    ///
+    /// Panics if `acc` doesn't have one message per point of `key`.
+    fn eval_into(&self, key: Self::Key, acc: &mut [Self::Message]);
+    /// Evaluate `key` at just the point `idx`.
+    ///
+    /// Same as `eval(key)[idx]`, but only pays for the one point (e.g., for
+    /// recovering a single channel).
+    ///
+    /// Panics if `idx` is out of range.
+    fn eval_at(&self, key: &Self::Key, idx: usize) -> Self::Message;
+    /// The (encoded) message that `key` carries, if any.
+    ///
+    /// Lets callers check the shape of a key from the network before
+    /// evaluating it.
+    fn key_message<'a>(&self, key: &'a Self::Key) -> Option<&'a Self::Message>;
+    /// Check that `key` has the shape of this DPF's keys (e.g., a seed and bit
+    /// for every point), so that evaluating or auditing it won't panic.
+    ///
+    /// Use this for keys from the network.
+    fn check_key_shape(&self, key: &Self::Key) -> Result<(), KeyMismatch>;
     fn combine(&self, parts: Vec<Vec<Self::Message>>) -> Vec<Self::Message>;
 }
 
@@ -24,6 +121,7 @@ macro_rules! check_dpf {
             #![allow(unused_imports)]
             use super::*;
             use crate::dpf::Dpf;
+            use crate::testing::{assert_dpf_correct, assert_dpf_empty, dpf_with_data};
             use proptest::prelude::*;
             use std::collections::HashSet;
             use std::iter::repeat_with;
@@ -34,41 +132,52 @@ macro_rules! check_dpf {
                 check::<$type>();
             }
 
-            fn dpf_with_data() -> impl Strategy<Value = ($type, <$type as Dpf>::Message)> {
-                any::<$type>().prop_flat_map(|dpf| {
-                    (
-                        Just(dpf.clone()),
-                        <$type as Dpf>::Message::arbitrary_with(dpf.msg_size().into()),
-                    )
-                })
-            }
-
             proptest! {
                 #[test]
-                fn test_correct((dpf, data) in dpf_with_data(), index: prop::sample::Index) {
+                fn test_correct((dpf, data) in dpf_with_data::<$type>(), index: prop::sample::Index) {
                     assert_eq!(data.len(), dpf.msg_size());
                     let index = index.index(dpf.points());
-                    let dpf_keys = dpf.gen(data.clone(), index);
-                    let dpf_shares = dpf_keys.into_iter().map(|k| dpf.eval(k)).collect();
-                    let dpf_output = dpf.combine(dpf_shares);
+                    assert_dpf_correct(&dpf, data, index)?;
+                }
 
-                    for (chunk_idx, chunk) in dpf_output.into_iter().enumerate() {
-                        if chunk_idx == index {
-                            prop_assert_eq!(chunk, data.clone());
-                        } else {
-                            prop_assert_eq!(chunk, dpf.null_message());
-                        }
+                #[test]
+                fn test_eval_into((dpf, data) in dpf_with_data::<$type>(), index: prop::sample::Index) {
+                    let index = index.index(dpf.points());
+                    let dpf_keys = dpf.gen(data, index);
+                    let dpf_shares = dpf_keys.iter().cloned().map(|k| dpf.eval(k)).collect();
+                    let expected = dpf.combine(dpf_shares);
+
+                    let mut acc = vec![dpf.null_message(); dpf.points()];
+                    for key in dpf_keys {
+                        dpf.eval_into(key, &mut acc);
+                    }
+                    prop_assert_eq!(acc, expected);
+                }
+
+                #[test]
+                fn test_eval_at(
+                    (dpf, data) in dpf_with_data::<$type>(),
+                    index: prop::sample::Index,
+                    point: prop::sample::Index,
+                ) {
+                    let index = index.index(dpf.points());
+                    let point = point.index(dpf.points());
+                    for key in dpf.gen(data, index).into_iter().chain(dpf.gen_empty()) {
+                        let expected = dpf.eval(key.clone()).remove(point);
+                        prop_assert_eq!(dpf.eval_at(&key, point), expected);
                     }
                 }
 
                 #[test]
                 fn test_correct_empty(dpf: $type) {
-                    let dpf_keys = dpf.gen_empty();
-                    let dpf_shares = dpf_keys.into_iter().map(|k| dpf.eval(k)).collect();
-                    let dpf_output = dpf.combine(dpf_shares);
+                    assert_dpf_empty(&dpf)?;
+                }
 
-                    for chunk in dpf_output {
-                        prop_assert_eq!(chunk, dpf.null_message());
+                #[test]
+                fn test_check_key_shape((dpf, data) in dpf_with_data::<$type>(), index: prop::sample::Index) {
+                    let index = index.index(dpf.points());
+                    for key in dpf.gen(data, index).iter().chain(&dpf.gen_empty()) {
+                        prop_assert_eq!(dpf.check_key_shape(key), Ok(()));
                     }
                 }
             }
@@ -1,4 +1,4 @@
-use super::Dpf;
+use super::{Dpf, KeyMismatch};
 
 use std::marker::PhantomData;
 
@@ -14,7 +14,7 @@ impl<M> Construction<M> {
         Construction::<M> {
             points,
             keys,
-            phantom: PhantomData::default(),
+            phantom: PhantomData,
         }
     }
 }
@@ -61,12 +61,44 @@ where
         acc
     }
 
+    fn eval_into(&self, key: Self::Key, acc: &mut [Self::Message]) {
+        assert_eq!(acc.len(), self.points(), "wrong number of points");
+        if let Some((msg, idx)) = key {
+            if acc[idx] == M::default() {
+                acc[idx] = msg;
+            }
+        }
+    }
+
+    fn eval_at(&self, key: &Self::Key, idx: usize) -> Self::Message {
+        assert!(idx < self.points(), "point out of range");
+        match key {
+            Some((msg, point)) if *point == idx => msg.clone(),
+            _ => M::default(),
+        }
+    }
+
+    fn key_message<'a>(&self, key: &'a Self::Key) -> Option<&'a Self::Message> {
+        key.as_ref().map(|(msg, _)| msg)
+    }
+
+    fn check_key_shape(&self, key: &Self::Key) -> Result<(), KeyMismatch> {
+        match key {
+            Some((_, idx)) if *idx >= self.points => Err(KeyMismatch {
+                field: "points",
+                expected: self.points,
+                actual: idx.saturating_add(1),
+            }),
+            _ => Ok(()),
+        }
+    }
+
     fn combine(&self, parts: Vec<Vec<Self::Message>>) -> Vec<Self::Message> {
         parts
             .into_iter()
             .reduce(|a, b| {
                 a.into_iter()
-                    .zip(b.into_iter())
+                    .zip(b)
                     .map(|(a, b)| if a != M::default() { a } else { b })
                     .collect()
             })
@@ -3,8 +3,10 @@ mod definition;
 
 pub(in crate) mod insecure;
 pub mod multi_key;
+pub mod tree;
 pub mod two_key;
 
-pub use definition::Dpf;
+pub use definition::{Dpf, KeyMismatch};
 pub use multi_key::Construction as MultiKeyDpf;
+pub use tree::Construction as TreeDpf;
 pub use two_key::Construction as TwoKeyDpf;
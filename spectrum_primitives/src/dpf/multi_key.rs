@@ -4,7 +4,7 @@ use std::iter::repeat_with;
 
 use serde::{Deserialize, Serialize};
 
-use super::Dpf;
+use super::{Dpf, KeyMismatch};
 use crate::algebra::{Field, SpecialExponentMonoid};
 use crate::prg::{Prg, SeedHomomorphicPrg};
 use crate::sharing::Shareable;
@@ -25,13 +25,16 @@ impl<P> Construction<P> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Key<M, S> {
     pub(in crate) encoded_msg: M, //P::Output,
     pub(in crate) bits: Vec<S>,
     pub(in crate) seeds: Vec<S>,
 }
 
+versioned_serde!(Key<M, S>);
+
 impl<M, S> Key<M, S> {
     pub fn new(encoded_msg: M, bits: Vec<S>, seeds: Vec<S>) -> Self {
         assert_eq!(bits.len(), seeds.len());
@@ -143,7 +146,7 @@ where
         // (pseudo)random  message
         let encoded_msg = self.prg.eval(&P::new_seed());
 
-        Iterator::zip(seed_shares.into_iter(), bit_shares.into_iter())
+        Iterator::zip(seed_shares.into_iter(), bit_shares)
             .map(|(s, b)| Key::new(encoded_msg.clone(), b, s))
             .collect()
     }
@@ -158,6 +161,30 @@ where
             .collect()
     }
 
+    fn eval_into(&self, key: Self::Key, acc: &mut [Self::Message]) {
+        assert_eq!(key.seeds.len(), acc.len(), "wrong number of points");
+        for ((seed, bit), out) in key.seeds.iter().zip(key.bits.iter().cloned()).zip(acc) {
+            *out = self.prg.combine_outputs(&[out, &key.encoded_msg.pow(bit)]);
+            self.prg.eval_into(seed, out);
+        }
+    }
+
+    fn eval_at(&self, key: &Self::Key, idx: usize) -> Self::Message {
+        self.prg.combine_outputs(&[
+            &key.encoded_msg.pow(key.bits[idx].clone()),
+            &self.prg.eval(&key.seeds[idx]),
+        ])
+    }
+
+    fn key_message<'a>(&self, key: &'a Self::Key) -> Option<&'a Self::Message> {
+        Some(&key.encoded_msg)
+    }
+
+    fn check_key_shape(&self, key: &Self::Key) -> Result<(), KeyMismatch> {
+        KeyMismatch::check("bits", self.points, key.bits.len())?;
+        KeyMismatch::check("seeds", self.points, key.seeds.len())
+    }
+
     /// combines the results produced by running eval on both keys
     /// combine([[a, b], [c, d], [e, f]]) == [a + c + e, b + d + f]
     fn combine(&self, parts: Vec<Vec<Self::Message>>) -> Vec<Self::Message> {
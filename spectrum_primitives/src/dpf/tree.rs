@@ -0,0 +1,436 @@
+//! 2-DPF (i.e. keys = 2) from a GGM tree, with keys logarithmic in the number
+//! of points.
+//!
+//! This is the construction of [BGI16] (Fig. 1), except that the final
+//! correction word is an encoded message as in [`two_key`](super::two_key).
+//! Each key expands to a seed and a bit for every point, which agree between
+//! the two keys everywhere but the point being written; keys only carry a
+//! seed (and two bits) per *level* of the tree. Since the expanded seeds and
+//! bits look just like a `two_key` key's, the two-key audit works on them
+//! unchanged.
+//!
+//! [BGI16]: https://eprint.iacr.org/2018/707
+use std::convert::TryFrom;
+use std::fmt::{self, Debug};
+use std::iter::repeat_with;
+use std::ops;
+use std::sync::Arc;
+
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+use super::{Dpf, KeyMismatch};
+use crate::prg::Prg;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Construction<P> {
+    /// Expands leaf seeds into messages.
+    prg: P,
+    /// Expands a node's seed into its children's seeds and bits.
+    node_prg: P,
+    points: usize,
+}
+
+impl<P> Construction<P> {
+    /// `node_prg` must output exactly two seeds' worth of bytes, plus one.
+    pub fn new(prg: P, node_prg: P, points: usize) -> Construction<P> {
+        assert!(points >= 1, "Need at least one point.");
+        Construction {
+            prg,
+            node_prg,
+            points,
+        }
+    }
+
+    /// Levels of the tree below the root.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        while (1 << depth) < self.points {
+            depth += 1;
+        }
+        depth
+    }
+}
+
+/// A key for a tree of a different depth (say, one with a different number of
+/// points, or a malformed key from the network).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthMismatch {
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl fmt::Display for DepthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "depth mismatch: expected {} levels, got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for DepthMismatch {}
+
+/// Correction for one level of the tree, applied to the children of nodes
+/// whose bit is set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorrectionWord<S> {
+    pub seed: S,
+    pub left_bit: bool,
+    pub right_bit: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct Key<M, S> {
+    pub encoded_msg: M,
+    /// Seed and bit of the root.
+    pub seed: S,
+    pub bit: bool,
+    /// One per level, from the top.
+    pub corrections: Vec<CorrectionWord<S>>,
+}
+
+versioned_serde!(Key<M, S>);
+
+impl<M, S> Key<M, S> {
+    pub fn new(encoded_msg: M, seed: S, bit: bool, corrections: Vec<CorrectionWord<S>>) -> Self {
+        Key {
+            encoded_msg,
+            seed,
+            bit,
+            corrections,
+        }
+    }
+}
+
+fn xor_seeds<S>(lhs: &S, rhs: &S) -> S
+where
+    S: Clone + Into<Vec<u8>> + TryFrom<Vec<u8>>,
+    <S as TryFrom<Vec<u8>>>::Error: Debug,
+{
+    let lhs: Vec<u8> = lhs.clone().into();
+    let rhs: Vec<u8> = rhs.clone().into();
+    let xor = lhs.iter().zip(rhs.iter()).map(|(l, r)| l ^ r).collect();
+    S::try_from(xor).expect("seeds should be the same size")
+}
+
+/// Apply the correction word to a child of a node with bit `parent_bit`.
+fn correct<S>(child: (S, bool), parent_bit: bool, seed: &S, bit: bool) -> (S, bool)
+where
+    S: Clone + Into<Vec<u8>> + TryFrom<Vec<u8>>,
+    <S as TryFrom<Vec<u8>>>::Error: Debug,
+{
+    if parent_bit {
+        (xor_seeds(&child.0, seed), child.1 ^ bit)
+    } else {
+        child
+    }
+}
+
+impl<P> Construction<P>
+where
+    P: Prg,
+    P::Seed: Clone + Into<Vec<u8>> + TryFrom<Vec<u8>>,
+    <P::Seed as TryFrom<Vec<u8>>>::Error: Debug,
+    P::Output: AsRef<[u8]>,
+{
+    /// The (uncorrected) left and right children of a node.
+    fn children(&self, seed: &P::Seed) -> [(P::Seed, bool); 2] {
+        let output = self.node_prg.eval(seed);
+        let bytes = output.as_ref();
+        let seed_len = (bytes.len() - 1) / 2;
+        let bits = bytes[2 * seed_len];
+        let seed_at = |start: usize| {
+            P::Seed::try_from(bytes[start..start + seed_len].to_vec())
+                .expect("node PRG output should split into seeds")
+        };
+        [
+            (seed_at(0), bits & 1 != 0),
+            (seed_at(seed_len), bits & 2 != 0),
+        ]
+    }
+
+    /// The seed and bit of every point (leaf) of `key`.
+    pub fn leaves<M>(&self, key: &Key<M, P::Seed>) -> Result<Vec<(P::Seed, bool)>, DepthMismatch> {
+        self.check_key(key)?;
+        let depth = self.depth();
+        let mut level = vec![(key.seed.clone(), key.bit)];
+        for (height, cw) in key.corrections.iter().enumerate() {
+            let mut next = Vec::with_capacity(2 * level.len());
+            for (seed, bit) in level {
+                let [left, right] = self.children(&seed);
+                next.push(correct(left, bit, &cw.seed, cw.left_bit));
+                next.push(correct(right, bit, &cw.seed, cw.right_bit));
+            }
+            // Skip subtrees entirely past the last point.
+            let remaining = depth - height - 1;
+            next.truncate(((self.points - 1) >> remaining) + 1);
+            level = next;
+        }
+        Ok(level)
+    }
+
+    /// The seed and bit of point `idx` of `key` (without expanding the rest).
+    pub fn leaf<M>(
+        &self,
+        key: &Key<M, P::Seed>,
+        idx: usize,
+    ) -> Result<(P::Seed, bool), DepthMismatch> {
+        self.check_key(key)?;
+        let depth = self.depth();
+        let mut node = (key.seed.clone(), key.bit);
+        for (height, cw) in key.corrections.iter().enumerate() {
+            let [left, right] = self.children(&node.0);
+            node = if (idx >> (depth - height - 1)) & 1 == 1 {
+                correct(right, node.1, &cw.seed, cw.right_bit)
+            } else {
+                correct(left, node.1, &cw.seed, cw.left_bit)
+            };
+        }
+        Ok(node)
+    }
+}
+
+impl<P> Construction<P> {
+    /// Check that `key` is for a tree of this depth, before expanding it.
+    pub fn check_key<M, S>(&self, key: &Key<M, S>) -> Result<(), DepthMismatch> {
+        let expected = self.depth();
+        let actual = key.corrections.len();
+        if expected != actual {
+            return Err(DepthMismatch { expected, actual });
+        }
+        Ok(())
+    }
+}
+
+impl<P> Dpf for Construction<P>
+where
+    P: Prg + Clone,
+    P::Seed: Clone + PartialEq + Eq + Debug + Into<Vec<u8>> + TryFrom<Vec<u8>>,
+    <P::Seed as TryFrom<Vec<u8>>>::Error: Debug,
+    P::Output: Clone
+        + PartialEq
+        + Eq
+        + Debug
+        + AsRef<[u8]>
+        + ops::BitXor<P::Output, Output = P::Output>
+        + ops::BitXor<Arc<P::Output>, Output = P::Output>
+        + ops::BitXorAssign<P::Output>,
+{
+    type Key = Key<P::Output, P::Seed>;
+    type Message = P::Output;
+
+    fn points(&self) -> usize {
+        self.points
+    }
+
+    fn keys(&self) -> usize {
+        2 // this construction only works for s = 2
+    }
+
+    fn msg_size(&self) -> usize {
+        self.prg.output_size()
+    }
+
+    fn null_message(&self) -> Self::Message {
+        self.prg.null_output()
+    }
+
+    fn gen(&self, msg: Self::Message, idx: usize) -> Vec<Self::Key> {
+        assert!(idx < self.points, "point out of range");
+        let depth = self.depth();
+        let root_a = P::new_seed();
+        let root_b = P::new_seed();
+        let bit_a: bool = thread_rng().gen();
+        let mut node_a = (root_a.clone(), bit_a);
+        let mut node_b = (root_b.clone(), !bit_a);
+
+        // Walk down the path to `idx`. Off the path, the correction makes the
+        // two keys' children agree; on it, their bits keep differing.
+        let mut corrections = Vec::with_capacity(depth);
+        for height in 0..depth {
+            let right = (idx >> (depth - height - 1)) & 1 == 1;
+            let [left_a, right_a] = self.children(&node_a.0);
+            let [left_b, right_b] = self.children(&node_b.0);
+            let lose_seed = if right {
+                xor_seeds(&left_a.0, &left_b.0)
+            } else {
+                xor_seeds(&right_a.0, &right_b.0)
+            };
+            let cw = CorrectionWord {
+                seed: lose_seed,
+                left_bit: left_a.1 ^ left_b.1 ^ !right,
+                right_bit: right_a.1 ^ right_b.1 ^ right,
+            };
+            let (keep_a, keep_b, keep_bit) = if right {
+                (right_a, right_b, cw.right_bit)
+            } else {
+                (left_a, left_b, cw.left_bit)
+            };
+            node_a = correct(keep_a, node_a.1, &cw.seed, keep_bit);
+            node_b = correct(keep_b, node_b.1, &cw.seed, keep_bit);
+            corrections.push(cw);
+        }
+
+        let encoded_msg = self.prg.eval(&node_a.0) ^ self.prg.eval(&node_b.0) ^ msg;
+
+        vec![
+            Self::Key::new(encoded_msg.clone(), root_a, bit_a, corrections.clone()),
+            Self::Key::new(encoded_msg, root_b, !bit_a, corrections),
+        ]
+    }
+
+    fn gen_empty(&self) -> Vec<Self::Key> {
+        let mut rng = thread_rng();
+        let corrections = repeat_with(|| CorrectionWord {
+            seed: P::new_seed(),
+            left_bit: rng.gen(),
+            right_bit: rng.gen(),
+        })
+        .take(self.depth())
+        .collect();
+        let encoded_msg = self.prg.eval(&P::new_seed()); // random message
+        let bit: bool = thread_rng().gen();
+
+        vec![Self::Key::new(encoded_msg, P::new_seed(), bit, corrections); 2]
+    }
+
+    // Keys of the wrong depth (see `check_key()`) evaluate to nothing; the
+    // VDPF fails their audits.
+    fn eval(&self, key: Self::Key) -> Vec<P::Output> {
+        let leaves = match self.leaves(&key) {
+            Ok(leaves) => leaves,
+            Err(_) => return vec![self.null_message(); self.points],
+        };
+        let msg_ref = Arc::new(key.encoded_msg);
+        leaves
+            .into_iter()
+            .map(|(seed, bit)| {
+                if bit {
+                    self.prg.eval(&seed) ^ msg_ref.clone()
+                } else {
+                    self.prg.eval(&seed)
+                }
+            })
+            .collect()
+    }
+
+    fn eval_into(&self, key: Self::Key, acc: &mut [P::Output]) {
+        assert_eq!(self.points, acc.len(), "wrong number of points");
+        let leaves = match self.leaves(&key) {
+            Ok(leaves) => leaves,
+            Err(_) => return,
+        };
+        for ((seed, bit), out) in leaves.into_iter().zip(acc.iter_mut()) {
+            self.prg.eval_into(&seed, out);
+            if bit {
+                *out ^= key.encoded_msg.clone();
+            }
+        }
+    }
+
+    fn eval_at(&self, key: &Self::Key, idx: usize) -> P::Output {
+        assert!(idx < self.points, "point out of range");
+        let (seed, bit) = match self.leaf(key, idx) {
+            Ok(leaf) => leaf,
+            Err(_) => return self.null_message(),
+        };
+        let out = self.prg.eval(&seed);
+        if bit {
+            out ^ key.encoded_msg.clone()
+        } else {
+            out
+        }
+    }
+
+    fn key_message<'a>(&self, key: &'a Self::Key) -> Option<&'a Self::Message> {
+        Some(&key.encoded_msg)
+    }
+
+    fn check_key_shape(&self, key: &Self::Key) -> Result<(), KeyMismatch> {
+        self.check_key(key).map_err(|err| KeyMismatch {
+            field: "levels",
+            expected: err.expected,
+            actual: err.actual,
+        })
+    }
+
+    fn combine(&self, parts: Vec<Vec<P::Output>>) -> Vec<P::Output> {
+        let mut parts = parts.into_iter();
+        let mut res = parts.next().expect("Need at least one part to combine.");
+        for part in parts {
+            for (x, y) in res.iter_mut().zip(part) {
+                *x ^= y;
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes::Bytes;
+    use crate::constructions::{tree_node_prg, AesPrg};
+
+    fn dpf(points: usize) -> Construction<AesPrg> {
+        Construction::new(AesPrg::new(16), tree_node_prg(), points)
+    }
+
+    #[test]
+    fn test_depth() {
+        let depths: Vec<_> = [1, 2, 3, 4, 5, 1024, 1025]
+            .iter()
+            .map(|&points| dpf(points).depth())
+            .collect();
+        assert_eq!(depths, vec![0, 1, 2, 2, 3, 10, 11]);
+    }
+
+    #[test]
+    fn test_key_size_logarithmic() {
+        let dpf = dpf(1000);
+        for key in dpf.gen(dpf.null_message(), 123) {
+            assert_eq!(key.corrections.len(), 10);
+        }
+    }
+
+    #[test]
+    fn test_leaves_differ_only_at_point() {
+        for &points in &[1, 5, 8, 13] {
+            let dpf = dpf(points);
+            let idx = points / 2;
+            let keys = dpf.gen(dpf.null_message(), idx);
+            let leaves_a = dpf.leaves(&keys[0]).unwrap();
+            let leaves_b = dpf.leaves(&keys[1]).unwrap();
+            assert_eq!(leaves_a.len(), points);
+            for (point, (a, b)) in leaves_a.iter().zip(leaves_b.iter()).enumerate() {
+                if point == idx {
+                    assert_ne!(a.0, b.0);
+                    assert_ne!(a.1, b.1);
+                } else {
+                    assert_eq!(a, b);
+                }
+                assert_eq!(dpf.leaf(&keys[0], point).unwrap(), *a);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wrong_depth() {
+        let dpf = dpf(8);
+        let msg = Bytes::from(vec![7; 16]);
+        let mut key = dpf.gen(msg, 3).remove(0);
+        key.corrections.pop();
+        let err = DepthMismatch {
+            expected: 3,
+            actual: 2,
+        };
+        assert_eq!(dpf.check_key(&key), Err(err));
+        assert_eq!(dpf.leaves(&key), Err(err));
+        assert_eq!(dpf.leaf(&key, 3), Err(err));
+        assert_eq!(dpf.eval_at(&key, 3), dpf.null_message());
+        assert_eq!(dpf.eval(key), vec![dpf.null_message(); 8]);
+    }
+}
@@ -1,4 +1,15 @@
 //! 2-DPF (i.e. keys = 2) based on any PRG G(.).
+//!
+//! Keys carry a seed (and a bit) for every point, so they grow linearly with
+//! the number of channels. Deriving all but the written point's seeds from one
+//! shared PRF seed would shrink them, but then each key would have to say which
+//! seed is the odd one out: that's the point being written, which neither
+//! server may learn. See [`tree`](super::tree) for keys logarithmic in the
+//! number of points.
+//!
+//! For very large messages, [`Construction::gen_chunked`] gives keys without
+//! a message: instead, the message is encoded (and evaluated) a chunk at a
+//! time, so neither side ever holds more than a chunk per point of it.
 use std::fmt::Debug;
 use std::iter::repeat_with;
 use std::ops;
@@ -7,8 +18,9 @@ use std::sync::Arc;
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 
-use super::Dpf;
-use crate::prg::Prg;
+use super::{Dpf, KeyMismatch};
+use crate::bytes::Bytes;
+use crate::prg::{ChunkedPrg, Prg};
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Construction<P> {
@@ -22,13 +34,16 @@ impl<P> Construction<P> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Key<M, S> {
     pub encoded_msg: M, // P::Output,
     pub bits: Vec<bool>,
     pub seeds: Vec<S>, // Vec<<P as Prg>::Seed>,
 }
 
+versioned_serde!(Key<M, S>);
+
 impl<M, S> Key<M, S> {
     pub fn new(encoded_msg: M, bits: Vec<bool>, seeds: Vec<S>) -> Self {
         Key {
@@ -60,6 +75,30 @@ where
     }
 }
 
+/// A key whose encoded message is streamed separately, in chunks (see
+/// [`Construction::gen_chunked`]).
+pub type ChunkedKey<S> = Key<(), S>;
+
+/// Encodes a message for a pair of [`ChunkedKey`]s, a chunk at a time.
+pub struct ChunkEncoder<P: Prg> {
+    prg: P,
+    seeds: (P::Seed, P::Seed),
+    offset: usize,
+}
+
+impl<P: ChunkedPrg> ChunkEncoder<P> {
+    /// Encode the next chunk of the message (chunks can be any size).
+    pub fn encode(&mut self, chunk: Bytes) -> Bytes {
+        let mut chunk: Vec<u8> = chunk.into();
+        self.prg
+            .eval_chunk_into(&self.seeds.0, self.offset, &mut chunk);
+        self.prg
+            .eval_chunk_into(&self.seeds.1, self.offset, &mut chunk);
+        self.offset += chunk.len();
+        chunk.into()
+    }
+}
+
 #[cfg(any(test, feature = "testing"))]
 impl<M, S> Arbitrary for Key<M, S>
 where
@@ -165,12 +204,41 @@ where
             .collect()
     }
 
+    /// evaluates the DPF on a given PrgKey, XORing the result into `acc`
+    fn eval_into(&self, key: Self::Key, acc: &mut [P::Output]) {
+        assert_eq!(key.seeds.len(), acc.len(), "wrong number of points");
+        for ((seed, bit), out) in key.seeds.iter().zip(key.bits.iter()).zip(acc.iter_mut()) {
+            self.prg.eval_into(seed, out);
+            if *bit {
+                *out ^= key.encoded_msg.clone();
+            }
+        }
+    }
+
+    fn eval_at(&self, key: &Self::Key, idx: usize) -> P::Output {
+        let out = self.prg.eval(&key.seeds[idx]);
+        if key.bits[idx] {
+            out ^ key.encoded_msg.clone()
+        } else {
+            out
+        }
+    }
+
+    fn key_message<'a>(&self, key: &'a Self::Key) -> Option<&'a Self::Message> {
+        Some(&key.encoded_msg)
+    }
+
+    fn check_key_shape(&self, key: &Self::Key) -> Result<(), KeyMismatch> {
+        KeyMismatch::check("bits", self.points, key.bits.len())?;
+        KeyMismatch::check("seeds", self.points, key.seeds.len())
+    }
+
     /// combines the results produced by running eval on both keys
     fn combine(&self, parts: Vec<Vec<P::Output>>) -> Vec<P::Output> {
         let mut parts = parts.into_iter();
         let mut res = parts.next().expect("Need at least one part to combine.");
         for part in parts {
-            for (x, y) in res.iter_mut().zip(part.into_iter()) {
+            for (x, y) in res.iter_mut().zip(part) {
                 *x ^= y;
             }
         }
@@ -178,6 +246,99 @@ where
     }
 }
 
+impl<P> Construction<P>
+where
+    P: ChunkedPrg + Clone,
+    P::Seed: Clone,
+{
+    /// Generate keys writing a message to `idx`, along with the encoder for
+    /// the message.
+    ///
+    /// The message can be any length (not just [`Dpf::msg_size`]). Send both
+    /// servers the same encoded chunks, and have them evaluate their key on
+    /// each with [`eval_chunk`](Self::eval_chunk).
+    pub fn gen_chunked(&self, idx: usize) -> (Vec<ChunkedKey<P::Seed>>, ChunkEncoder<P>) {
+        assert!(idx < self.points, "index out of range");
+        let seeds_a: Vec<_> = repeat_with(P::new_seed).take(self.points).collect();
+        let mut seeds_b = seeds_a.clone();
+        seeds_b[idx] = P::new_seed();
+
+        let bits_a: Vec<bool> = repeat_with(|| thread_rng().gen())
+            .take(self.points)
+            .collect();
+        let mut bits_b = bits_a.clone();
+        bits_b[idx] = !bits_b[idx];
+
+        let encoder = ChunkEncoder {
+            prg: self.prg.clone(),
+            seeds: (seeds_a[idx].clone(), seeds_b[idx].clone()),
+            offset: 0,
+        };
+        let keys = vec![Key::new((), bits_a, seeds_a), Key::new((), bits_b, seeds_b)];
+        (keys, encoder)
+    }
+
+    /// Like [`gen_chunked`](Self::gen_chunked), but writing nothing.
+    ///
+    /// The encoded chunks are (pseudo)random, whatever goes into the encoder.
+    pub fn gen_chunked_empty(&self) -> (Vec<ChunkedKey<P::Seed>>, ChunkEncoder<P>) {
+        let seeds: Vec<_> = repeat_with(P::new_seed).take(self.points).collect();
+        let bits: Vec<bool> = repeat_with(|| thread_rng().gen())
+            .take(self.points)
+            .collect();
+        let encoder = ChunkEncoder {
+            prg: self.prg.clone(),
+            seeds: (P::new_seed(), P::new_seed()),
+            offset: 0,
+        };
+        (vec![Key::new((), bits, seeds); 2], encoder)
+    }
+
+    /// Evaluate `key` on the chunk of the encoded message starting at
+    /// `offset`, giving that chunk of the result at every point.
+    ///
+    /// Combining the results for both keys gives the chunk of the message at
+    /// its point, and zeros everywhere else.
+    pub fn eval_chunk(
+        &self,
+        key: &ChunkedKey<P::Seed>,
+        offset: usize,
+        chunk: &Bytes,
+    ) -> Vec<Bytes> {
+        key.seeds
+            .iter()
+            .zip(key.bits.iter())
+            .map(|(seed, bit)| {
+                let mut out = if *bit {
+                    chunk.as_ref().to_vec()
+                } else {
+                    vec![0; chunk.len()]
+                };
+                self.prg.eval_chunk_into(seed, offset, &mut out);
+                out.into()
+            })
+            .collect()
+    }
+
+    /// Evaluate `key` on each of the encoded message's `chunks` in turn.
+    pub fn eval_chunks<'a, I>(
+        &'a self,
+        key: &'a ChunkedKey<P::Seed>,
+        chunks: I,
+    ) -> impl Iterator<Item = Vec<Bytes>> + 'a
+    where
+        I: IntoIterator<Item = Bytes>,
+        I::IntoIter: 'a,
+    {
+        let mut offset = 0;
+        chunks.into_iter().map(move |chunk| {
+            let out = self.eval_chunk(key, offset, &chunk);
+            offset += chunk.len();
+            out
+        })
+    }
+}
+
 #[cfg(any(test, feature = "testing"))]
 use proptest::prelude::*;
 
@@ -193,3 +354,119 @@ impl<P: Arbitrary + 'static> Arbitrary for Construction<P> {
             .boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructions::{AesPrg, ChaChaPrg};
+    use crate::testing::dpf_with_data;
+    use proptest::collection::vec;
+
+    // Every point's result for a chunked message, both keys combined.
+    fn eval_chunked<P>(
+        dpf: &Construction<P>,
+        keys: Vec<ChunkedKey<P::Seed>>,
+        encoded: Vec<Bytes>,
+    ) -> Vec<Bytes>
+    where
+        P: ChunkedPrg + Clone,
+        P::Seed: Clone,
+    {
+        let len = encoded.iter().map(Bytes::len).sum();
+        let mut results = vec![vec![0; len]; dpf.points];
+        for key in keys {
+            let mut offset = 0;
+            for outputs in dpf.eval_chunks(&key, encoded.clone()) {
+                let len = outputs[0].len();
+                for (result, output) in results.iter_mut().zip(outputs) {
+                    for (x, y) in result[offset..].iter_mut().zip(output) {
+                        *x ^= y;
+                    }
+                }
+                offset += len;
+            }
+        }
+        results.into_iter().map(Bytes::from).collect()
+    }
+
+    fn dpf_with_chunked_msg<P: Arbitrary + 'static>(
+    ) -> impl Strategy<Value = (Construction<P>, Vec<u8>, usize, usize)> {
+        (
+            any::<Construction<P>>(),
+            vec(any::<u8>(), 0..2000),
+            1..500usize,
+            any::<prop::sample::Index>(),
+        )
+            .prop_map(|(dpf, msg, chunk_size, idx)| {
+                let idx = idx.index(dpf.points);
+                (dpf, msg, chunk_size, idx)
+            })
+    }
+
+    fn encode_chunks<P: ChunkedPrg>(
+        mut encoder: ChunkEncoder<P>,
+        msg: &[u8],
+        chunk_size: usize,
+    ) -> Vec<Bytes> {
+        msg.chunks(chunk_size)
+            .map(|chunk| encoder.encode(chunk.to_vec().into()))
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn test_chunked_correct((dpf, msg, chunk_size, idx) in dpf_with_chunked_msg::<AesPrg>()) {
+            let (keys, encoder) = dpf.gen_chunked(idx);
+            let encoded = encode_chunks(encoder, &msg, chunk_size);
+            let results = eval_chunked(&dpf, keys, encoded);
+            for (point, result) in results.into_iter().enumerate() {
+                if point == idx {
+                    prop_assert_eq!(result, Bytes::from(msg.clone()));
+                } else {
+                    prop_assert_eq!(result, Bytes::empty(msg.len()));
+                }
+            }
+        }
+
+        #[test]
+        fn test_chunked_correct_chacha((dpf, msg, chunk_size, idx) in dpf_with_chunked_msg::<ChaChaPrg>()) {
+            let (keys, encoder) = dpf.gen_chunked(idx);
+            let encoded = encode_chunks(encoder, &msg, chunk_size);
+            let results = eval_chunked(&dpf, keys, encoded);
+            prop_assert_eq!(&results[idx], &Bytes::from(msg));
+        }
+
+        #[test]
+        fn test_chunked_empty((dpf, msg, chunk_size, _) in dpf_with_chunked_msg::<AesPrg>()) {
+            let (keys, encoder) = dpf.gen_chunked_empty();
+            let encoded = encode_chunks(encoder, &msg, chunk_size);
+            for result in eval_chunked(&dpf, keys, encoded) {
+                prop_assert_eq!(result, Bytes::empty(msg.len()));
+            }
+        }
+
+        /// A message of the usual size, in one chunk, evaluates just like a
+        /// regular key carrying it.
+        #[test]
+        fn test_chunked_matches_eval((dpf, data) in dpf_with_data::<Construction<AesPrg>>(), idx: prop::sample::Index) {
+            let (keys, mut encoder) = dpf.gen_chunked(idx.index(dpf.points()));
+            let encoded = encoder.encode(data);
+            for key in keys {
+                let expected = dpf.eval(Key::new(encoded.clone(), key.bits(), key.seeds()));
+                prop_assert_eq!(dpf.eval_chunk(&key, 0, &encoded), expected);
+            }
+        }
+
+        #[test]
+        fn test_check_key_shape_mismatch((dpf, data) in dpf_with_data::<Construction<AesPrg>>(), idx: prop::sample::Index) {
+            let points = dpf.points();
+            let mut key = dpf.gen(data, idx.index(points)).remove(0);
+            key.seeds.pop();
+            let expected = KeyMismatch { field: "seeds", expected: points, actual: points - 1 };
+            prop_assert_eq!(dpf.check_key_shape(&key), Err(expected));
+            key.bits.push(true);
+            let expected = KeyMismatch { field: "bits", expected: points, actual: points + 1 };
+            prop_assert_eq!(dpf.check_key_shape(&key), Err(expected));
+        }
+    }
+}
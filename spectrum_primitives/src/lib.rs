@@ -1,10 +1,12 @@
-#![feature(type_ascription)]
 #![allow(dead_code)] // for now
+#![allow(non_local_definitions)] // proptest-derive's `Arbitrary` impls
 #[macro_use]
 mod algebra;
 #[macro_use]
 mod util;
 #[macro_use]
+mod versioned;
+#[macro_use]
 mod sharing;
 #[macro_use]
 mod prg;
@@ -19,21 +21,44 @@ pub mod pir;
 
 mod constructions;
 
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 pub use algebra::Group;
-pub use bytes::Bytes;
-pub use dpf::Dpf;
+pub use bytes::{Bytes, LengthMismatch};
+pub use dpf::{Dpf, KeyMismatch};
+pub use prg::ChunkedPrg;
 pub use prg::Prg;
+pub use sharing::{recover_threshold, share_threshold, ThresholdShare};
+pub use vdpf::ThresholdVdpf;
 pub use vdpf::Vdpf;
+pub use versioned::FORMAT_VERSION;
 
+pub use constructions::ChaChaPrg;
+pub use constructions::MultiKeyBls12Vdpf;
+pub use constructions::MultiKeyRistrettoVdpf;
 pub use constructions::MultiKeyVdpf;
+pub use constructions::TwoKeyCompactVdpf;
+pub use constructions::TwoKeyFp61Vdpf;
+pub use constructions::TwoKeyGf128Vdpf;
 pub use constructions::TwoKeyVdpf;
 
 // These are kind-of leaking. Better to do away with entirely.
 pub use constructions::AuthKey;
+pub use constructions::Fp61;
+pub use constructions::Gf2_128;
 pub use dpf::multi_key::Key as MultiKeyKey;
+pub use dpf::tree::Key as TreeKey;
+pub use dpf::two_key::ChunkEncoder as TwoKeyChunkEncoder;
+pub use dpf::two_key::ChunkedKey as TwoKeyChunkedKey;
 pub use dpf::two_key::Key as TwoKeyKey;
 pub use dpf::TwoKeyDpf;
+pub use prg::Decode as DecodeElements;
 pub use prg::ElementVector;
+pub use prg::Embed;
+pub use prg::Encode as EncodeElements;
+pub use prg::EncodingError;
+pub use prg::MAX_FIXED_BASE_TABLES;
 pub use util::Sampleable;
 pub use vdpf::multi_key::ProofShare as MultiKeyProof;
 pub use vdpf::multi_key::Token as MultiKeyToken;
@@ -44,12 +69,45 @@ pub use vdpf::two_key_pub::KeyPair as TwoKeyPubAuthKey;
 pub use vdpf::two_key_pub::ProofShare as TwoKeyPubProof;
 pub use vdpf::two_key_pub::Token as TwoKeyPubToken;
 
-use constructions::AesPrg;
+pub use subtle::ConstantTimeEq;
+pub use zeroize::{Zeroize, Zeroizing};
+
+use constructions::{tree_node_prg, AesPrg, AesSeed};
 use prg::GroupPrg;
 
+use std::convert::TryFrom;
+
 impl TwoKeyVdpf {
     pub fn with_channels_msg_size(channels: usize, msg_size: usize) -> Self {
-        TwoKeyVdpf::new(dpf::TwoKeyDpf::new(AesPrg::new(msg_size), channels))
+        Self::with_channels_msg_size_prg(channels, AesPrg::new(msg_size))
+    }
+}
+
+impl<P: Prg> TwoKeyVdpf<P> {
+    /// Like `with_channels_msg_size`, but expanding seeds with `prg` (whose
+    /// output size is the message size): e.g., `ChaChaPrg` on machines
+    /// without AES instructions.
+    pub fn with_channels_msg_size_prg(channels: usize, prg: P) -> Self {
+        Self::new(dpf::TwoKeyDpf::new(prg, channels))
+    }
+}
+
+impl TwoKeyFp61Vdpf {
+    pub fn with_channels_msg_size(channels: usize, msg_size: usize) -> Self {
+        TwoKeyFp61Vdpf::new(dpf::TwoKeyDpf::new(AesPrg::new(msg_size), channels))
+    }
+}
+
+impl TwoKeyGf128Vdpf {
+    pub fn with_channels_msg_size(channels: usize, msg_size: usize) -> Self {
+        TwoKeyGf128Vdpf::new(dpf::TwoKeyDpf::new(AesPrg::new(msg_size), channels))
+    }
+}
+
+impl TwoKeyCompactVdpf {
+    pub fn with_channels_msg_size(channels: usize, msg_size: usize) -> Self {
+        let dpf = dpf::TreeDpf::new(AesPrg::new(msg_size), tree_node_prg(), channels);
+        TwoKeyCompactVdpf::new(dpf)
     }
 }
 
@@ -67,6 +125,38 @@ impl MultiKeyVdpf {
         let dpf = dpf::MultiKeyDpf::new(prg, channels, groups);
         MultiKeyVdpf::new(dpf)
     }
+
+    /// Like `with_channels_parties_msg_size`, but with generators derived from
+    /// `seed`: the same seed (and parameters) always gives the same VDPF.
+    pub fn with_channels_parties_msg_size_seed(
+        channels: usize,
+        groups: usize,
+        msg_size: usize,
+        seed: [u8; 32],
+    ) -> Self {
+        let mut prg_seed = vec![0; constructions::AES_SEED_SIZE];
+        blake3::derive_key("spectrum MultiKeyVdpf generators", &seed, &mut prg_seed);
+        let prg_seed = AesSeed::try_from(prg_seed).expect("Correct seed size");
+        let prg = GroupPrg::from_seed(msg_size / 32 + 1, prg_seed);
+        let dpf = dpf::MultiKeyDpf::new(prg, channels, groups);
+        MultiKeyVdpf::new(dpf)
+    }
+}
+
+impl MultiKeyBls12Vdpf {
+    pub fn with_channels_parties_msg_size(channels: usize, groups: usize, msg_size: usize) -> Self {
+        let prg = GroupPrg::random(msg_size / 32 + 1);
+        let dpf = dpf::MultiKeyDpf::new(prg, channels, groups);
+        MultiKeyBls12Vdpf::new(dpf)
+    }
+}
+
+impl MultiKeyRistrettoVdpf {
+    pub fn with_channels_parties_msg_size(channels: usize, groups: usize, msg_size: usize) -> Self {
+        let prg = GroupPrg::random(msg_size / 32 + 1);
+        let dpf = dpf::MultiKeyDpf::new(prg, channels, groups);
+        MultiKeyRistrettoVdpf::new(dpf)
+    }
 }
 
 #[cfg(feature = "testing")]
@@ -1,10 +1,14 @@
 /// A database for private information retrieval.
+#[allow(clippy::result_unit_err)]
 pub trait Database<const NUM_SERVERS: usize> {
     type Row;
     type Query;
     type Response;
 
     fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
     fn get(&self, idx: usize) -> Self::Row;
     fn queries(idx: usize, db_size: usize) -> Result<[Self::Query; NUM_SERVERS], ()>;
     fn answer(&self, query: Self::Query) -> Result<Self::Response, ()>;
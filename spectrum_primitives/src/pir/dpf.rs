@@ -0,0 +1,139 @@
+use crate::bytes::Bytes;
+use crate::constructions::{AesPrg, AesSeed};
+use crate::dpf::{two_key::Key, Dpf, TwoKeyDpf};
+use crate::pir::Database;
+
+/// A query for a [`DpfDatabase`]: a two-key DPF key.
+pub type Query = Key<Bytes, AesSeed>;
+
+// The smallest PRG output the DPF allows; only the first bit gets used.
+const MSG_SIZE: usize = 16;
+
+fn new_dpf(rows: usize) -> TwoKeyDpf<AesPrg> {
+    TwoKeyDpf::new(AesPrg::new(MSG_SIZE), rows)
+}
+
+#[derive(Debug, Clone)]
+/// Two-server PIR scheme using the two-key DPF.
+///
+/// For an l-row database, queries for the (i)th index are the two keys for a
+/// DPF over l points writing a message with its low bit set at point i. Each
+/// server evaluates its key, and answers with the XOR of the rows at points
+/// where the low bit of the evaluation is set: the evaluations differ (in that
+/// bit) only at point i, so XORing the two answers gives row i.
+///
+/// Queries are about as big as [`LinearDatabase`](super::LinearDatabase)
+/// queries (a seed per row, rather than a bit), but they're ordinary DPF keys,
+/// so they can go over the wire like write tokens.
+pub struct DpfDatabase {
+    row_len: usize,
+    data: Vec<Vec<u8>>,
+}
+
+impl DpfDatabase {
+    pub fn from_vec(data: Vec<Vec<u8>>) -> Self {
+        assert!(!data.is_empty());
+        let row_len = data[0].len();
+        for row in data.iter() {
+            assert_eq!(row_len, row.len());
+        }
+        Self { row_len, data }
+    }
+
+    pub fn row_len(&self) -> usize {
+        self.row_len
+    }
+}
+
+impl Database<2> for DpfDatabase {
+    type Row = Vec<u8>;
+    type Query = Query;
+    type Response = Vec<u8>;
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn get(&self, idx: usize) -> Self::Row {
+        self.data[idx].clone()
+    }
+
+    fn queries(idx: usize, db_size: usize) -> Result<[Self::Query; 2], ()> {
+        if db_size <= idx {
+            return Err(());
+        }
+        let mut msg = vec![0; MSG_SIZE];
+        msg[0] = 1;
+        let mut keys = new_dpf(db_size).gen(msg.into(), idx).into_iter();
+        Ok([keys.next().unwrap(), keys.next().unwrap()])
+    }
+
+    fn answer(&self, query: Self::Query) -> Result<Self::Response, ()> {
+        let valid = |key: &Query| {
+            key.bits.len() == self.data.len()
+                && key.seeds.len() == self.data.len()
+                && key.encoded_msg.len() == MSG_SIZE
+        };
+        if !valid(&query) {
+            return Err(());
+        }
+
+        let mut answer = vec![0u8; self.row_len];
+        let selected = new_dpf(self.data.len()).eval(query);
+        for (row, point) in self.data.iter().zip(selected.iter()) {
+            if point.as_ref()[0] & 1 == 1 {
+                for (x, y) in answer.iter_mut().zip(row.iter()) {
+                    *x ^= y;
+                }
+            }
+        }
+        Ok(answer)
+    }
+
+    fn combine(responses: [Self::Response; 2]) -> Self::Row {
+        let [mut row, other] = responses;
+        assert_eq!(row.len(), other.len());
+        for (x, y) in row.iter_mut().zip(other.iter()) {
+            *x ^= y;
+        }
+        row
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    impl Arbitrary for DpfDatabase {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            use prop::collection::vec;
+            const MAX_ROW_LEN: usize = 100;
+            const MAX_NUM_ROWS: usize = 10;
+            (1..MAX_ROW_LEN)
+                .prop_flat_map(|row_len| {
+                    let row_strat = vec(any::<u8>(), row_len);
+                    vec(row_strat, 1..MAX_NUM_ROWS)
+                })
+                .prop_map(DpfDatabase::from_vec)
+                .boxed()
+        }
+    }
+
+    mod two_server {
+        use super::*;
+        check_pir!(DpfDatabase, 2);
+    }
+
+    proptest! {
+        #[test]
+        fn test_answer_wrong_size(db: DpfDatabase) {
+            let len = <DpfDatabase as Database<2>>::len(&db);
+            let [query, _] = <DpfDatabase as Database<2>>::queries(0, len + 1).unwrap();
+            prop_assert!(db.answer(query).is_err());
+        }
+    }
+}
@@ -12,7 +12,7 @@ struct InsecureDatabase {
 
 impl InsecureDatabase {
     fn new(data: Rc<Vec<Vec<u8>>>) -> Self {
-        assert!(data.len() > 0);
+        assert!(!data.is_empty());
         Self { data }
     }
 
@@ -23,7 +23,7 @@ pub struct LinearDatabase {
 
 impl LinearDatabase {
     fn new(data: Rc<Vec<Vec<u8>>>) -> Self {
-        assert!(data.len() > 0);
+        assert!(!data.is_empty());
         let row_len = data[0].len();
         for row in data.iter() {
             assert_eq!(row_len, row.len());
@@ -68,8 +68,8 @@ impl<const N: usize> Database<N> for LinearDatabase {
         let mut last_row = vec![false; db_size];
         for j in 0..db_size {
             let mut parity = false;
-            for i in 0..(N - 1) {
-                parity ^= queries[i][j];
+            for query in &queries {
+                parity ^= query[j];
             }
             // Figure out the parity of all previous queries.
             // If we match that, XORing gives false.
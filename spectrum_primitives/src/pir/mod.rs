@@ -1,8 +1,10 @@
 #[macro_use]
 mod definition;
 
+mod dpf;
 mod insecure;
 mod linear;
 
 pub use definition::Database;
+pub use dpf::{DpfDatabase, Query as DpfQuery};
 pub use linear::LinearDatabase;
@@ -1,3 +1,44 @@
+/// Pseudorandom generator: expands a short seed into a long output.
+///
+/// # Examples
+///
+/// A (very insecure) PRG that repeats its one-byte seed:
+///
+/// ```
+/// use spectrum_primitives::{Bytes, Prg};
+///
+/// struct Repeat(usize);
+///
+/// impl Prg for Repeat {
+///     type Seed = u8;
+///     type Output = Bytes;
+///
+///     fn new_seed() -> u8 {
+///         rand::random()
+///     }
+///     fn output_size(&self) -> usize {
+///         self.0
+///     }
+///     fn eval(&self, seed: &u8) -> Bytes {
+///         Bytes::from(vec![*seed; self.0])
+///     }
+///     fn eval_into(&self, seed: &u8, out: &mut Bytes) {
+///         *out ^= self.eval(seed);
+///     }
+///     fn null_output(&self) -> Bytes {
+///         Bytes::empty(self.0)
+///     }
+/// }
+///
+/// let prg = Repeat(4);
+/// let seed = Repeat::new_seed();
+/// assert_eq!(prg.eval(&seed).len(), prg.output_size());
+/// assert_eq!(prg.eval(&seed), prg.eval(&seed));
+///
+/// let mut out = prg.null_output();
+/// prg.eval_into(&seed, &mut out);
+/// assert_eq!(out, prg.eval(&seed));
+/// ```
 pub trait Prg {
     type Seed;
     type Output;
@@ -5,9 +46,22 @@ pub trait Prg {
     fn new_seed() -> Self::Seed;
     fn output_size(&self) -> usize;
     fn eval(&self, seed: &Self::Seed) -> Self::Output;
+    /// Combine `eval(seed)` into `out` (XOR, or the group operation).
+    fn eval_into(&self, seed: &Self::Seed, out: &mut Self::Output);
     fn null_output(&self) -> Self::Output;
 }
 
+/// A [`Prg`] whose output can be computed a piece at a time, for outputs too
+/// big to hold in memory at once.
+///
+/// Each seed gives an (unbounded) stream of bytes; [`eval`](Prg::eval) is its
+/// first [`output_size`](Prg::output_size) bytes.
+pub trait ChunkedPrg: Prg {
+    /// XOR bytes `offset..offset + out.len()` of the stream for `seed` into
+    /// `out`.
+    fn eval_chunk_into(&self, seed: &Self::Seed, offset: usize, out: &mut [u8]);
+}
+
 #[cfg(test)]
 macro_rules! check_prg {
     ($type:ty) => {
@@ -43,6 +97,14 @@ macro_rules! check_prg {
                     prop_assert_eq!(prg.eval(&seed), prg.eval(&seed));
                 }
 
+                /// Evaluating into a null output is the same as evaluating.
+                #[test]
+                fn test_eval_into_null(prg: $type, seed: <$type as Prg>::Seed) {
+                    let mut out = prg.null_output();
+                    prg.eval_into(&seed, &mut out);
+                    prop_assert_eq!(out, prg.eval(&seed));
+                }
+
                 /// Evaluation with different seeds should give different results.
                 #[test]
                 fn test_eval_pseudorandom(prg: $type, seeds in proptest::collection::hash_set(any::<<$type as Prg>::Seed>(), 0..5)) {
@@ -55,3 +117,46 @@ macro_rules! check_prg {
         }
     };
 }
+
+#[cfg(test)]
+macro_rules! check_chunked_prg {
+    ($type:ty) => {
+        mod chunked_prg {
+            #![allow(unused_imports)]
+            use super::*;
+            use proptest::prelude::*;
+            use crate::prg::{ChunkedPrg, Prg};
+
+            proptest! {
+                /// Evaluating chunk by chunk gives the same output as evaluating
+                /// all at once.
+                #[test]
+                fn test_eval_chunks(prg: $type, seed: <$type as Prg>::Seed, chunk_size in 1..100usize) {
+                    let expected: Vec<u8> = prg.eval(&seed).into();
+                    let mut actual = vec![0; expected.len()];
+                    for (idx, chunk) in actual.chunks_mut(chunk_size).enumerate() {
+                        prg.eval_chunk_into(&seed, idx * chunk_size, chunk);
+                    }
+                    prop_assert_eq!(actual, expected);
+                }
+
+                /// Chunks XOR into their output.
+                #[test]
+                fn test_eval_chunk_into_xors(
+                    prg: $type,
+                    seed: <$type as Prg>::Seed,
+                    offset in 0..10_000usize,
+                    data in proptest::collection::vec(any::<u8>(), 0..100),
+                ) {
+                    let mut stream = vec![0; data.len()];
+                    prg.eval_chunk_into(&seed, offset, &mut stream);
+                    let mut actual = data.clone();
+                    prg.eval_chunk_into(&seed, offset, &mut actual);
+                    for ((x, y), z) in actual.iter().zip(data).zip(stream) {
+                        prop_assert_eq!(*x, y ^ z);
+                    }
+                }
+            }
+        }
+    };
+}
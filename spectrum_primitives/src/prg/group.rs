@@ -6,19 +6,16 @@ use crate::{
     Bytes,
 };
 
-use itertools::Itertools;
-use serde::{Deserialize, Serialize};
+use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 
-use std::convert::TryFrom;
-use std::fmt::Debug;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{self, Debug};
 use std::hash::Hash;
-use std::iter::repeat;
 use std::ops::{Add, BitXor, BitXorAssign};
+use std::sync::Arc;
 
 #[cfg(any(test, feature = "testing"))]
 use proptest::{collection::SizeRange, prelude::*};
-#[cfg(any(test, feature = "testing"))]
-use proptest_derive::Arbitrary;
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub struct ElementVector<G>(pub Vec<G>);
@@ -44,7 +41,7 @@ where
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        let inner = Iterator::zip(self.0.into_iter(), rhs.0.into_iter())
+        let inner = Iterator::zip(self.0.into_iter(), rhs.0)
             .map(|(x, y)| x + y)
             .collect();
         Self(inner)
@@ -63,10 +60,19 @@ where
     G::Exponent: Clone,
 {
     type Exponent = G::Exponent;
+    type FixedBase = Vec<G::FixedBase>;
 
     fn pow(&self, exp: Self::Exponent) -> Self {
         Self(self.0.iter().map(|x| x.pow(exp.clone())).collect())
     }
+
+    fn fixed_base(&self) -> Self::FixedBase {
+        self.0.iter().map(G::fixed_base).collect()
+    }
+
+    fn pow_fixed(table: &Self::FixedBase, exp: &Self::Exponent) -> Self {
+        Self(G::pow_many_fixed(table, exp))
+    }
 }
 
 #[cfg(any(test, feature = "testing"))]
@@ -105,27 +111,122 @@ where
     }
 }
 
+/// At most this many of a `GroupPrg`'s generators are precomputed for
+/// fixed-base exponentiation; the rest are raised to the seed as usual.
+///
+/// There's a generator per 32 bytes of message, and tables can be big (about
+/// 150KiB each for jubjub), so this caps them at about 150MiB per PRG.
+pub const MAX_FIXED_BASE_TABLES: usize = 1024;
+
 // Implementation of a group-based PRG
-#[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
-#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
-pub struct GroupPrg<G: Group + 'static> {
+//
+// Evaluating it raises every generator to the seed, so the generators are
+// precomputed for fixed-base exponentiation (once, when the PRG is made), up
+// to `MAX_FIXED_BASE_TABLES` of them.
+pub struct GroupPrg<G: Group + SpecialExponentMonoid + 'static> {
     generators: ElementVector<G>,
+    // For the first `tables.len()` generators. Shared between clones: these
+    // can be big (see `fixed_base`).
+    tables: Arc<Vec<G::FixedBase>>,
 }
 
-impl<G: Group> GroupPrg<G> {
+impl<G: Group + SpecialExponentMonoid> GroupPrg<G> {
+    pub fn new(generators: ElementVector<G>) -> Self {
+        Self::with_max_tables(generators, MAX_FIXED_BASE_TABLES)
+    }
+
+    /// Like `new`, but precomputing at most `max_tables` generators (see
+    /// [`MAX_FIXED_BASE_TABLES`]).
+    pub fn with_max_tables(generators: ElementVector<G>, max_tables: usize) -> Self {
+        let tables = generators
+            .0
+            .iter()
+            .take(max_tables)
+            .map(G::fixed_base)
+            .collect();
+        GroupPrg {
+            generators,
+            tables: Arc::new(tables),
+        }
+    }
+
+    // The generators without tables.
+    fn variable_base(&self) -> &[G] {
+        &self.generators.0[self.tables.len()..]
+    }
+
     fn len(&self) -> usize {
         self.generators.0.len()
     }
 }
 
-impl<G> GroupPrg<G>
+// By hand: the derives would want `G::FixedBase` to implement these too.
+impl<G: Group + SpecialExponentMonoid + Clone> Clone for GroupPrg<G> {
+    fn clone(&self) -> Self {
+        GroupPrg {
+            generators: self.generators.clone(),
+            tables: Arc::clone(&self.tables),
+        }
+    }
+}
+
+impl<G: Group + SpecialExponentMonoid + Debug> Debug for GroupPrg<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupPrg")
+            .field("generators", &self.generators)
+            .finish()
+    }
+}
+
+impl<G: Group + SpecialExponentMonoid> PartialEq for GroupPrg<G> {
+    fn eq(&self, other: &Self) -> bool {
+        self.generators == other.generators
+    }
+}
+
+// Only the generators get serialized; deserializing rebuilds the tables.
+impl<G: Group + SpecialExponentMonoid + Serialize> Serialize for GroupPrg<G> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("GroupPrg", 1)?;
+        state.serialize_field("generators", &self.generators)?;
+        state.end()
+    }
+}
+
+impl<'de, G> Deserialize<'de> for GroupPrg<G>
 where
-    G: Group + Sampleable,
+    G: Group + SpecialExponentMonoid + Deserialize<'de>,
 {
-    pub fn new(generators: ElementVector<G>) -> Self {
-        GroupPrg { generators }
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "GroupPrg")]
+        struct Generators<G> {
+            generators: ElementVector<G>,
+        }
+        let Generators { generators } = Generators::deserialize(deserializer)?;
+        Ok(GroupPrg::new(generators))
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl<G> Arbitrary for GroupPrg<G>
+where
+    G: Debug + Arbitrary + Group + SpecialExponentMonoid + 'static,
+{
+    type Parameters = <ElementVector<G> as Arbitrary>::Parameters;
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(size: Self::Parameters) -> Self::Strategy {
+        any_with::<ElementVector<G>>(size)
+            .prop_map(GroupPrg::new)
+            .boxed()
     }
+}
 
+impl<G> GroupPrg<G>
+where
+    G: Group + SpecialExponentMonoid + Sampleable,
+{
     pub fn from_seed(num_elements: usize, seed: <G as Sampleable>::Seed) -> Self {
         let elements = G::sample_many_from_seed(&seed, num_elements);
         GroupPrg::new(ElementVector(elements))
@@ -153,18 +254,23 @@ where
 
     /// evaluates the PRG on the given seed
     fn eval(&self, seed: &Self::Seed) -> Self::Output {
-        ElementVector(
-            self.generators
-                .0
-                .iter()
-                .cloned()
-                .map(|g| g.pow(seed.clone()))
-                .collect(),
-        )
+        let mut elements = G::pow_many_fixed(&self.tables, seed);
+        elements.extend(G::pow_many(self.variable_base(), seed));
+        ElementVector(elements)
+    }
+
+    fn eval_into(&self, seed: &Self::Seed, out: &mut Self::Output) {
+        assert_eq!(out.0.len(), self.len());
+        let (fixed, variable) = out.0.split_at_mut(self.tables.len());
+        G::pow_many_fixed_into(&self.tables, seed, fixed);
+        let elements = G::pow_many(self.variable_base(), seed);
+        for (acc, element) in variable.iter_mut().zip(elements) {
+            *acc = std::mem::replace(acc, G::zero()) + element;
+        }
     }
 
     fn null_output(&self) -> Self::Output {
-        ElementVector(repeat(G::zero()).take(self.len()).collect())
+        ElementVector(std::iter::repeat_n(G::zero(), self.len()).collect())
     }
 
     fn output_size(&self) -> usize {
@@ -204,18 +310,12 @@ where
 {
     type Error = &'static str;
 
+    /// Messages that aren't a whole number of elements get zero-padded.
     fn try_from(value: Bytes) -> Result<Self, Self::Error> {
-        let len = value.len();
         value
-            .into_iter()
-            .chunks(32)
-            .into_iter()
-            .map(|chunk| G::try_from(Into::<Bytes>::into(chunk.collect::<Vec<_>>())))
+            .chunks_padded(32)
+            .map(|chunk| G::try_from(Bytes::from(chunk.into_owned())))
             .collect::<Result<Vec<G>, _>>()
-            .map(|vec| {
-                assert_eq!(vec.len() * 32, len);
-                vec
-            })
             .map(ElementVector::new)
             .map_err(|_| "conversion from bytes failed")
     }
@@ -229,12 +329,11 @@ where
     type Error = &'static str;
 
     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
-        let chunk_size = 32;
+        let value = Bytes::from(value);
         value
-            .into_iter()
-            .chunks(chunk_size)
-            .into_iter()
-            .map(|chunk| G::try_from(chunk.collect::<Vec<u8>>()))
+            .chunks_exact(32)
+            .ok_or("length not a multiple of the element size")?
+            .map(|chunk| G::try_from(chunk.to_vec()))
             .collect::<Result<Vec<G>, _>>()
             .map_err(|_| "conversion failed")
             .map(ElementVector::new)
@@ -252,15 +351,128 @@ where
         let mut all_bytes = Vec::with_capacity(chunk_size * value.0.len());
         for element in value.0.into_iter() {
             let bytes: Bytes = element.into();
-            let bytes: Vec<u8> = bytes.into();
-            // assert_eq!(bytes.clone()[31], 0);
-            let bytes = Bytes::from(bytes[0..32].to_vec());
-            all_bytes.append(&mut bytes.into());
+            all_bytes.extend_from_slice(&bytes.as_ref()[..chunk_size]);
         }
         Bytes::from(all_bytes)
     }
 }
 
+/// Size (in bytes) of the length prefix on messages encoded as elements.
+pub const LEN_PREFIX_BYTES: usize = 4;
+
+/// Group elements that can carry a few bytes of data (and give them back).
+///
+/// Unlike `TryFrom<Bytes>`, which reads bytes as an element's encoding (and
+/// fails for most byte strings), any data fits.
+pub trait Embed: Sized {
+    /// Bytes of data per element.
+    const DATA_BYTES: usize;
+
+    /// An element carrying `data` (zero-padded to `DATA_BYTES`).
+    ///
+    /// `None` if `data` is too long, or if no such element turned up.
+    fn embed(data: &[u8]) -> Option<Self>;
+
+    /// The `DATA_BYTES` bytes this element carries; `None` if it carries
+    /// none. The identity carries all zeros.
+    fn extract(&self) -> Option<Vec<u8>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodingError {
+    /// The message doesn't fit in the elements.
+    TooLong { len: usize, max: usize },
+    /// The elements don't contain a valid encoded message.
+    Malformed(String),
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodingError::TooLong { len, max } => {
+                write!(f, "message too long: {} bytes, at most {} fit", len, max)
+            }
+            EncodingError::Malformed(msg) => write!(f, "malformed elements: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+/// Messages that can be written as a fixed number of group elements.
+pub trait Encode<G> {
+    fn encode(&self, elements: usize) -> Result<ElementVector<G>, EncodingError>;
+}
+
+/// Messages that can be read back from group elements (see [`Encode`]).
+pub trait Decode<G>: Sized {
+    fn decode(elements: &ElementVector<G>) -> Result<Self, EncodingError>;
+}
+
+impl<G: Embed> ElementVector<G> {
+    /// The longest message that encodes into `elements` elements.
+    pub fn encoding_capacity(elements: usize) -> usize {
+        (elements * G::DATA_BYTES).saturating_sub(LEN_PREFIX_BYTES)
+    }
+}
+
+/// The message, prefixed with its length (4 bytes, big-endian), zero-padded,
+/// and split across the elements. All identity elements decode to the empty
+/// message.
+impl<G: Embed> Encode<G> for Bytes {
+    fn encode(&self, elements: usize) -> Result<ElementVector<G>, EncodingError> {
+        let max = ElementVector::<G>::encoding_capacity(elements);
+        let too_long = EncodingError::TooLong {
+            len: self.len(),
+            max,
+        };
+        if self.len() > max {
+            return Err(too_long);
+        }
+        let len = u32::try_from(self.len()).map_err(|_| too_long)?;
+
+        let mut data = Vec::with_capacity(elements * G::DATA_BYTES);
+        data.extend_from_slice(&len.to_be_bytes());
+        data.extend_from_slice(self.as_ref());
+        data.resize(elements * G::DATA_BYTES, 0);
+        data.chunks(G::DATA_BYTES)
+            .map(|chunk| {
+                G::embed(chunk)
+                    .ok_or_else(|| EncodingError::Malformed("no element carries chunk".to_string()))
+            })
+            .collect::<Result<Vec<G>, _>>()
+            .map(ElementVector)
+    }
+}
+
+impl<G: Embed> Decode<G> for Bytes {
+    fn decode(elements: &ElementVector<G>) -> Result<Self, EncodingError> {
+        let mut data = Vec::with_capacity(elements.0.len() * G::DATA_BYTES);
+        for (idx, element) in elements.0.iter().enumerate() {
+            let chunk = element.extract().ok_or_else(|| {
+                EncodingError::Malformed(format!("element {} carries no data", idx))
+            })?;
+            data.extend(chunk);
+        }
+        if data.len() < LEN_PREFIX_BYTES {
+            return Err(EncodingError::Malformed(format!(
+                "{} elements can't hold a length prefix",
+                elements.0.len()
+            )));
+        }
+        let (prefix, rest) = data.split_at(LEN_PREFIX_BYTES);
+        let len = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+        if len > rest.len() {
+            return Err(EncodingError::Malformed(format!(
+                "length prefix {} exceeds capacity {}",
+                len,
+                rest.len()
+            )));
+        }
+        Ok(Bytes::from(rest[..len].to_vec()))
+    }
+}
+
 impl<G> BitXor<ElementVector<G>> for ElementVector<G>
 where
     G: Group,
@@ -273,7 +485,7 @@ where
         ElementVector(
             self.0
                 .into_iter()
-                .zip(rhs.0.into_iter())
+                .zip(rhs.0)
                 .map(|(element1, element2)| element1 + element2)
                 .collect(),
         )
@@ -308,7 +520,90 @@ where
     fn bitxor_assign(&mut self, rhs: ElementVector<G>) {
         self.0
             .iter_mut()
-            .zip(rhs.0.into_iter())
+            .zip(rhs.0)
             .for_each(|(element1, element2)| *element1 = element1.clone() + element2);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructions::jubjub::CurvePoint;
+
+    fn elements_for(len: usize) -> usize {
+        let chunk = <CurvePoint as Embed>::DATA_BYTES;
+        (len + LEN_PREFIX_BYTES).div_ceil(chunk)
+    }
+
+    proptest! {
+        // Embedding searches for a point per chunk, so keep the big cases few.
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn test_encode_roundtrip(data in prop::collection::vec(any::<u8>(), 1..4096)) {
+            let msg = Bytes::from(data);
+            let elements = elements_for(msg.len());
+            let encoded: ElementVector<CurvePoint> = msg.encode(elements).unwrap();
+            prop_assert_eq!(encoded.len(), elements);
+            prop_assert_eq!(Bytes::decode(&encoded).unwrap(), msg);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_encode_roundtrip_small(
+            data in prop::collection::vec(any::<u8>(), 0..100),
+            extra in 0..3usize,
+        ) {
+            let msg = Bytes::from(data);
+            let elements = elements_for(msg.len()) + extra;
+            let encoded: ElementVector<CurvePoint> = msg.encode(elements).unwrap();
+            prop_assert_eq!(Bytes::decode(&encoded).unwrap(), msg);
+        }
+
+        #[test]
+        fn test_encode_too_long(len in 1..200usize) {
+            let msg = Bytes::from(vec![0xff; len]);
+            let elements = elements_for(len) - 1;
+            let result: Result<ElementVector<CurvePoint>, _> = msg.encode(elements);
+            prop_assert_eq!(
+                result.unwrap_err(),
+                EncodingError::TooLong {
+                    len,
+                    max: ElementVector::<CurvePoint>::encoding_capacity(elements),
+                }
+            );
+        }
+
+        #[test]
+        fn test_decode_garbage(elements in prop::collection::vec(any::<CurvePoint>(), 0..5)) {
+            // Random points rarely carry data, and never panic.
+            let _ = Bytes::decode(&ElementVector(elements));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_max_tables(
+            prg: GroupPrg<CurvePoint>,
+            max_tables in 0..6usize,
+            seed: <GroupPrg<CurvePoint> as Prg>::Seed,
+        ) {
+            let capped = GroupPrg::with_max_tables(prg.generators.clone(), max_tables);
+            prop_assert!(capped.tables.len() <= max_tables);
+            prop_assert_eq!(capped.eval(&seed), prg.eval(&seed));
+
+            let mut out = prg.eval(&seed);
+            let mut expected = out.clone();
+            prg.eval_into(&seed, &mut expected);
+            capped.eval_into(&seed, &mut out);
+            prop_assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn test_decode_identity() {
+        let empty = ElementVector(vec![CurvePoint::zero(); 3]);
+        assert_eq!(Bytes::decode(&empty).unwrap(), Bytes::empty(0));
+    }
+}
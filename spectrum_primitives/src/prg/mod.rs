@@ -4,6 +4,8 @@ mod group;
 #[macro_use]
 mod seed_homomorphic;
 
-pub use self::group::{ElementVector, GroupPrg};
-pub use definition::Prg;
+pub use self::group::{
+    Decode, ElementVector, Embed, Encode, EncodingError, GroupPrg, MAX_FIXED_BASE_TABLES,
+};
+pub use definition::{ChunkedPrg, Prg};
 pub use seed_homomorphic::SeedHomomorphicPrg;
@@ -1,4 +1,8 @@
 //! Linear secret sharing.
+//!
+//! [`Shareable`] splits a value into additive shares, all of which are needed
+//! to recover it. [`share_threshold`] makes Shamir shares instead, any `t` of
+//! which recover it.
 use std::iter::{once, repeat_with};
 use std::{fmt::Debug, ops::Add};
 
@@ -77,13 +81,13 @@ mod transpose_tests {
     #[test]
     fn test_transpose_empty() {
         let zero_by_n: Vec<Vec<u8>> = vec![];
-        assert_eq!(transpose(zero_by_n), vec![]: Vec::<Vec<u8>>);
+        assert_eq!(transpose(zero_by_n), Vec::<Vec<u8>>::new());
 
         let one_by_zero: Vec<Vec<u8>> = vec![vec![]];
-        assert_eq!(transpose(one_by_zero), vec![vec![]]: Vec<Vec<u8>>);
+        assert_eq!(transpose(one_by_zero), vec![Vec::<u8>::new()]);
 
         let n_by_zero: Vec<Vec<u8>> = vec![vec![], vec![]];
-        assert_eq!(transpose(n_by_zero), vec![vec![]]: Vec<Vec<u8>>);
+        assert_eq!(transpose(n_by_zero), vec![Vec::<u8>::new()]);
     }
 
     proptest! {
@@ -143,6 +147,95 @@ impl Shareable for bool {
     }
 }
 
+/// One of the shares made by [`share_threshold`]: the sharing polynomial's
+/// value at `x = index + 1`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThresholdShare<F> {
+    pub index: usize,
+    pub value: F,
+}
+
+/// The field element `x + 1` (where `ThresholdShare`s with index `x` are
+/// evaluated).
+fn share_point<F: Field + Clone>(x: usize) -> F {
+    let x = x + 1;
+    // Double-and-add, from the top bit.
+    (0..8 * std::mem::size_of::<usize>())
+        .rev()
+        .fold(F::zero(), |acc, bit| {
+            let acc = acc.clone() + acc;
+            if (x >> bit) & 1 == 1 {
+                acc + F::one()
+            } else {
+                acc
+            }
+        })
+}
+
+/// Split `secret` into `n` shares, any `t` of which recover it (see
+/// [`recover_threshold`]); fewer than `t` reveal nothing about it.
+///
+/// Panics unless `1 <= t <= n` and `n` is less than the field's order.
+pub fn share_threshold<F>(secret: F, t: usize, n: usize) -> Vec<ThresholdShare<F>>
+where
+    F: Field + Sampleable + Clone,
+{
+    assert!(t >= 1, "threshold must be at least one!");
+    assert!(t <= n, "threshold can't exceed the number of shares!");
+    assert!(n < F::order(), "too many shares for this field!");
+    let coefficients: Vec<F> = once(secret)
+        .chain(repeat_with(F::sample).take(t - 1))
+        .collect();
+    (0..n)
+        .map(|index| {
+            let x: F = share_point(index);
+            // Horner's rule.
+            let value = coefficients
+                .iter()
+                .rev()
+                .cloned()
+                .fold(F::zero(), |acc, c| acc * x.clone() + c);
+            ThresholdShare { index, value }
+        })
+        .collect()
+}
+
+/// Recover a secret from (at least `t` of) the shares made by
+/// `share_threshold(secret, t, n)`.
+///
+/// Given fewer than `t` shares, the result is garbage. Panics if no shares are
+/// given or two share the same index.
+///
+/// Shares are linear: adding two sharings index by index gives a sharing of
+/// the sum.
+pub fn recover_threshold<F: Field + Clone>(shares: Vec<ThresholdShare<F>>) -> F {
+    assert!(
+        !shares.is_empty(),
+        "need at least one share to recover a secret!"
+    );
+    assert_eq!(
+        shares.iter().map(|s| s.index).sorted().dedup().count(),
+        shares.len(),
+        "shares must have distinct indices!"
+    );
+    let points: Vec<F> = shares.iter().map(|s| share_point(s.index)).collect();
+    // Lagrange interpolation at x = 0.
+    shares
+        .into_iter()
+        .enumerate()
+        .map(|(j, share)| {
+            let (num, den) = points
+                .iter()
+                .enumerate()
+                .filter(|(m, _)| *m != j)
+                .fold((F::one(), F::one()), |(num, den), (_, x)| {
+                    (num * x.clone(), den * (x.clone() - points[j].clone()))
+                });
+            share.value * num * den.mul_invert()
+        })
+        .fold(F::zero(), Add::add)
+}
+
 #[cfg(test)]
 macro_rules! check_shareable_norandom {
     ($type:ty) => {
@@ -283,4 +376,73 @@ mod tests {
     mod vec {
         check_shareable_norandom!(Vec<bool>);
     }
+
+    mod threshold {
+        use crate::algebra::Field;
+        use crate::constructions::Fp61;
+        use crate::sharing::*;
+        use proptest::prelude::*;
+        use proptest::sample::subsequence;
+
+        const MAX_SHARES: usize = 20;
+
+        /// `(t, n)` and the indices of some `t` of the `n` shares.
+        fn threshold_subset() -> impl Strategy<Value = (usize, usize, Vec<usize>)> {
+            (1..=MAX_SHARES)
+                .prop_flat_map(|n| (1..=n, Just(n)))
+                .prop_flat_map(|(t, n)| {
+                    (Just(t), Just(n), subsequence((0..n).collect::<Vec<_>>(), t))
+                })
+        }
+
+        fn pick<F: Clone>(
+            shares: &[ThresholdShare<F>],
+            indices: &[usize],
+        ) -> Vec<ThresholdShare<F>> {
+            indices.iter().map(|&i| shares[i].clone()).collect()
+        }
+
+        proptest! {
+            #[test]
+            fn test_recover_any_t(value: Fp61, (t, n, indices) in threshold_subset()) {
+                let shares = share_threshold(value, t, n);
+                prop_assert_eq!(shares.len(), n);
+                prop_assert_eq!(recover_threshold(pick(&shares, &indices)), value);
+                prop_assert_eq!(recover_threshold(shares), value);
+            }
+
+            #[test]
+            fn test_share_add(
+                value1: Fp61,
+                value2: Fp61,
+                (t, n, indices) in threshold_subset()
+            ) {
+                let shares: Vec<_> = share_threshold(value1, t, n)
+                    .into_iter()
+                    .zip(share_threshold(value2, t, n))
+                    .map(|(x, y)| ThresholdShare { index: x.index, value: x.value + y.value })
+                    .collect();
+                prop_assert_eq!(recover_threshold(pick(&shares, &indices)), value1 + value2);
+            }
+
+            #[test]
+            fn test_share_randomized(value: Fp61, n in 2..MAX_SHARES) {
+                prop_assert_ne!(share_threshold(value, 2, n), share_threshold(value, 2, n));
+            }
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_duplicate_index() {
+            let mut shares = share_threshold(Fp61::one(), 2, 3);
+            shares[1].index = shares[0].index;
+            recover_threshold(shares);
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_threshold_too_big() {
+            share_threshold(Fp61::one(), 4, 3);
+        }
+    }
 }
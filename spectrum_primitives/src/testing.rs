@@ -0,0 +1,246 @@
+//! Proptest strategies and checks for code built on [`Dpf`]s and [`Vdpf`]s.
+//!
+//! Enabled by the `testing` feature, so that downstream crates' tests can
+//! reuse them. The checks return a [`TestCaseError`] (rather than panicking),
+//! so use them with `?` inside `proptest!`.
+use crate::dpf::Dpf;
+use crate::vdpf::Vdpf;
+
+use proptest::prelude::*;
+use proptest::sample::Index;
+use proptest::test_runner::TestCaseError;
+
+use std::fmt::Debug;
+
+/// A DPF and a message of the right size for it.
+pub fn dpf_with_data<D>() -> impl Strategy<Value = (D, D::Message)>
+where
+    D: Dpf + Arbitrary + Clone,
+    D::Message: Arbitrary,
+    <D::Message as Arbitrary>::Parameters: From<usize>,
+{
+    any::<D>().prop_flat_map(|dpf| {
+        let data = D::Message::arbitrary_with(dpf.msg_size().into());
+        (Just(dpf), data)
+    })
+}
+
+/// A DPF, a message and a point, and the keys writing the message there.
+pub fn dpf_with_keys<D>() -> impl Strategy<Value = (D, D::Message, usize, Vec<D::Key>)>
+where
+    D: Dpf + Arbitrary + Clone,
+    D::Key: Debug,
+    D::Message: Arbitrary + Clone,
+    <D::Message as Arbitrary>::Parameters: From<usize>,
+{
+    (dpf_with_data::<D>(), any::<Index>()).prop_map(|((dpf, data), idx)| {
+        let idx = idx.index(dpf.points());
+        let keys = dpf.gen(data.clone(), idx);
+        (dpf, data, idx, keys)
+    })
+}
+
+/// A VDPF and access keys for each of its points.
+pub fn vdpf_with_keys<V>() -> impl Strategy<Value = (V, Vec<V::AuthKey>)>
+where
+    V: Vdpf + Arbitrary + Clone,
+    V::AuthKey: Clone + Debug,
+{
+    any::<V>().prop_map(|vdpf| {
+        let auth_keys = vdpf.new_access_keys();
+        (vdpf, auth_keys)
+    })
+}
+
+/// A VDPF, access keys for each of its points, and a message to write.
+pub fn vdpf_with_keys_data<V>() -> impl Strategy<Value = (V, Vec<V::AuthKey>, V::Message)>
+where
+    V: Vdpf + Arbitrary + Clone,
+    V::AuthKey: Clone + Debug,
+    V::Message: Arbitrary,
+    <V::Message as Arbitrary>::Parameters: From<usize>,
+{
+    vdpf_with_keys::<V>().prop_flat_map(|(vdpf, auth_keys)| {
+        let data = V::Message::arbitrary_with(vdpf.msg_size().into());
+        (Just(vdpf), Just(auth_keys), data)
+    })
+}
+
+/// Like [`vdpf_with_keys_data`], plus an access key that isn't any point's.
+pub fn vdpf_with_keys_data_bad_key<V>(
+) -> impl Strategy<Value = (V, Vec<V::AuthKey>, V::Message, V::AuthKey)>
+where
+    V: Vdpf + Arbitrary + Clone,
+    V::AuthKey: Arbitrary + Clone + PartialEq,
+    V::Message: Arbitrary + Clone,
+    <V::Message as Arbitrary>::Parameters: From<usize>,
+{
+    vdpf_with_keys_data::<V>().prop_flat_map(|(vdpf, auth_keys, data)| {
+        let good_keys = auth_keys.clone();
+        let bad_key = any::<V::AuthKey>()
+            .prop_filter("must be different", move |key| !good_keys.contains(key));
+        (Just(vdpf), Just(auth_keys), Just(data), bad_key)
+    })
+}
+
+/// A VDPF, access keys for each of its points, and the DPF keys and proof
+/// shares for a valid write to one of them.
+#[allow(clippy::type_complexity)]
+pub fn vdpf_with_proofs<V>(
+) -> impl Strategy<Value = (V, Vec<V::AuthKey>, Vec<V::Key>, Vec<V::ProofShare>)>
+where
+    V: Vdpf + Arbitrary + Clone,
+    V::AuthKey: Clone + Debug,
+    V::Key: Debug,
+    V::ProofShare: Debug,
+    V::Message: Arbitrary,
+    <V::Message as Arbitrary>::Parameters: From<usize>,
+{
+    (vdpf_with_keys_data::<V>(), any::<Index>()).prop_map(|((vdpf, auth_keys, data), idx)| {
+        let idx = idx.index(vdpf.points());
+        let dpf_keys = vdpf.gen(data, idx);
+        let proof_shares = vdpf.gen_proofs(&auth_keys[idx], idx, &dpf_keys);
+        (vdpf, auth_keys, dpf_keys, proof_shares)
+    })
+}
+
+/// A VDPF and the audit tokens (one per party) for a valid write.
+pub fn vdpf_with_tokens<V>() -> impl Strategy<Value = (V, Vec<V::Token>)>
+where
+    V: Vdpf + Arbitrary + Clone,
+    V::AuthKey: Clone + Debug,
+    V::Key: Debug,
+    V::ProofShare: Debug,
+    V::Token: Debug,
+    V::Message: Arbitrary,
+    <V::Message as Arbitrary>::Parameters: From<usize>,
+{
+    vdpf_with_proofs::<V>().prop_map(|(vdpf, auth_keys, dpf_keys, proof_shares)| {
+        let tokens = audit_tokens(&vdpf, &auth_keys, &dpf_keys, proof_shares);
+        (vdpf, tokens)
+    })
+}
+
+/// Each party's audit token for the write with `dpf_keys` and `proof_shares`.
+pub fn audit_tokens<V: Vdpf>(
+    vdpf: &V,
+    auth_keys: &[V::AuthKey],
+    dpf_keys: &[V::Key],
+    proof_shares: Vec<V::ProofShare>,
+) -> Vec<V::Token> {
+    dpf_keys
+        .iter()
+        .zip(proof_shares)
+        .map(|(dpf_key, proof_share)| vdpf.gen_audit(auth_keys, dpf_key, proof_share))
+        .collect()
+}
+
+/// Checks that writing `data` to point `idx` gives `data` there and null
+/// messages everywhere else.
+pub fn assert_dpf_correct<D>(dpf: &D, data: D::Message, idx: usize) -> Result<(), TestCaseError>
+where
+    D: Dpf,
+    D::Message: Clone + Debug + PartialEq,
+{
+    let shares = dpf.gen(data.clone(), idx).into_iter().map(|k| dpf.eval(k));
+    let output = dpf.combine(shares.collect());
+    prop_assert_eq!(output.len(), dpf.points());
+    for (point, chunk) in output.into_iter().enumerate() {
+        if point == idx {
+            prop_assert_eq!(chunk, data.clone());
+        } else {
+            prop_assert_eq!(chunk, dpf.null_message());
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the empty keys give null messages everywhere.
+pub fn assert_dpf_empty<D>(dpf: &D) -> Result<(), TestCaseError>
+where
+    D: Dpf,
+    D::Message: Debug + PartialEq,
+{
+    let shares = dpf.gen_empty().into_iter().map(|k| dpf.eval(k));
+    for chunk in dpf.combine(shares.collect()) {
+        prop_assert_eq!(chunk, dpf.null_message());
+    }
+    Ok(())
+}
+
+/// Checks that a write of `data` to point `idx` with the right access key
+/// passes the audit.
+pub fn assert_audit_complete<V: Vdpf>(
+    vdpf: &V,
+    auth_keys: &[V::AuthKey],
+    data: V::Message,
+    idx: usize,
+) -> Result<(), TestCaseError> {
+    let dpf_keys = vdpf.gen(data, idx);
+    let proof_shares = vdpf.gen_proofs(&auth_keys[idx], idx, &dpf_keys);
+    let tokens = audit_tokens(vdpf, auth_keys, &dpf_keys, proof_shares);
+    prop_assert!(vdpf.check_audit(tokens), "audit should pass");
+    Ok(())
+}
+
+/// Checks that an empty write (cover traffic) passes the audit.
+pub fn assert_audit_noop_complete<V: Vdpf>(
+    vdpf: &V,
+    auth_keys: &[V::AuthKey],
+) -> Result<(), TestCaseError> {
+    let dpf_keys = vdpf.gen_empty();
+    let tokens = audit_tokens(vdpf, auth_keys, &dpf_keys, vdpf.gen_proofs_noop());
+    prop_assert!(vdpf.check_audit(tokens), "audit should pass");
+    Ok(())
+}
+
+/// Checks that a batch audit (of a good write, an empty write, and a write
+/// with `bad_key`) gives the same results as auditing each on its own, and
+/// that a batch of only the first two passes.
+pub fn assert_audit_batch<V: Vdpf>(
+    vdpf: &V,
+    auth_keys: &[V::AuthKey],
+    data: V::Message,
+    idx: usize,
+    bad_key: &V::AuthKey,
+) -> Result<(), TestCaseError>
+where
+    V::Token: Clone,
+{
+    let dpf_keys = vdpf.gen(data, idx);
+    let good_proofs = vdpf.gen_proofs(&auth_keys[idx], idx, &dpf_keys);
+    let bad_proofs = vdpf.gen_proofs(bad_key, idx, &dpf_keys);
+    let empty_keys = vdpf.gen_empty();
+    let token_sets = vec![
+        audit_tokens(vdpf, auth_keys, &dpf_keys, good_proofs),
+        audit_tokens(vdpf, auth_keys, &empty_keys, vdpf.gen_proofs_noop()),
+        audit_tokens(vdpf, auth_keys, &dpf_keys, bad_proofs),
+    ];
+    let expected: Vec<bool> = token_sets
+        .iter()
+        .cloned()
+        .map(|tokens| vdpf.check_audit(tokens))
+        .collect();
+    prop_assert_eq!(expected.clone(), vec![true, true, false]);
+    // Without the bad write, the whole batch passes.
+    let good_sets = token_sets[..2].to_vec();
+    prop_assert_eq!(vdpf.check_audit_batch(good_sets), vec![true, true]);
+    prop_assert_eq!(vdpf.check_audit_batch(token_sets), expected);
+    Ok(())
+}
+
+/// Checks that a write of `data` to point `idx` with `bad_key` (rather than
+/// the point's access key) fails the audit.
+pub fn assert_audit_sound<V: Vdpf>(
+    vdpf: &V,
+    auth_keys: &[V::AuthKey],
+    data: V::Message,
+    idx: usize,
+    bad_key: &V::AuthKey,
+) -> Result<(), TestCaseError> {
+    let dpf_keys = vdpf.gen(data, idx);
+    let proof_shares = vdpf.gen_proofs(bad_key, idx, &dpf_keys);
+    let tokens = audit_tokens(vdpf, auth_keys, &dpf_keys, proof_shares);
+    prop_assert!(!vdpf.check_audit(tokens), "audit should fail");
+    Ok(())
+}
@@ -20,6 +20,37 @@ pub trait Sampleable {
         Self: Sized;
 }
 
+/// Bounds needed to share a value across threads, but only when the `parallel`
+/// feature is enabled (otherwise, implemented for everything).
+#[cfg(feature = "parallel")]
+pub trait MaybeSync: Send + Sync {}
+#[cfg(feature = "parallel")]
+impl<T: Send + Sync> MaybeSync for T {}
+
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "parallel"))]
+impl<T> MaybeSync for T {}
+
+/// `items.into_iter().map(f).collect()`, spread across threads when the
+/// `parallel` feature is enabled.
+pub fn map_maybe_parallel<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+where
+    T: MaybeSync,
+    R: MaybeSync,
+    F: Fn(T) -> R + MaybeSync,
+{
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        items.into_par_iter().map(f).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        items.into_iter().map(f).collect()
+    }
+}
+
 #[cfg(test)]
 macro_rules! check_sampleable {
     ($type:ty) => {
@@ -95,13 +126,14 @@ macro_rules! check_roundtrip {
     };
     ($type:ty,$strat:expr,$to:expr,$from:expr,$name:ident) => {
         mod $name {
-            #![allow(unused_imports,clippy::redundant_closure_call)]
+            #![allow(unused_imports, clippy::redundant_closure_call)]
             use super::*;
             use proptest::prelude::*;
             proptest! {
                 #[test]
                 fn test_roundtrip(x in $strat) {
-                    prop_assert_eq!(($from)(($to)(x.clone())): $type, x: $type, "round-trip failed");
+                    let actual: $type = ($from)(($to)(x.clone()));
+                    prop_assert_eq!(actual, x, "round-trip failed");
                 }
             }
         }
@@ -1,5 +1,56 @@
 use crate::dpf::Dpf;
 
+/// A [`Dpf`] whose keys can be audited for well-formedness.
+///
+/// Each party turns its DPF key and proof share into an audit token; the
+/// tokens check out only if the keys write to at most one point, and only with
+/// the access key for that point.
+///
+/// # Examples
+///
+/// ```
+/// use spectrum_primitives::{Bytes, Dpf, TwoKeyVdpf, Vdpf};
+///
+/// let vdpf = TwoKeyVdpf::with_channels_msg_size(3, 16);
+/// let access_keys = vdpf.new_access_keys();
+///
+/// // A client holding the access key for point 1 writes there.
+/// let dpf_keys = vdpf.gen(Bytes::from(vec![7; 16]), 1);
+/// let proof_shares = vdpf.gen_proofs(&access_keys[1], 1, &dpf_keys);
+///
+/// // Each party computes an audit token; together, they verify.
+/// let tokens = dpf_keys
+///     .iter()
+///     .zip(proof_shares)
+///     .map(|(key, proof_share)| vdpf.gen_audit(&access_keys, key, proof_share))
+///     .collect();
+/// assert!(vdpf.check_audit(tokens));
+///
+/// // Without the access key, the audit fails.
+/// let proof_shares = vdpf.gen_proofs(&vdpf.new_access_key(), 1, &dpf_keys);
+/// let tokens = dpf_keys
+///     .iter()
+///     .zip(proof_shares)
+///     .map(|(key, proof_share)| vdpf.gen_audit(&access_keys, key, proof_share))
+///     .collect();
+/// assert!(!vdpf.check_audit(tokens));
+/// ```
+///
+/// Cover traffic (writing nothing) needs no access key:
+///
+/// ```
+/// use spectrum_primitives::{Dpf, TwoKeyVdpf, Vdpf};
+///
+/// let vdpf = TwoKeyVdpf::with_channels_msg_size(3, 16);
+/// let access_keys = vdpf.new_access_keys();
+/// let tokens = vdpf
+///     .gen_empty()
+///     .iter()
+///     .zip(vdpf.gen_proofs_noop())
+///     .map(|(key, proof_share)| vdpf.gen_audit(&access_keys, key, proof_share))
+///     .collect();
+/// assert!(vdpf.check_audit(tokens));
+/// ```
 pub trait Vdpf: Dpf {
     type AuthKey;
     type ProofShare;
@@ -8,6 +59,12 @@ pub trait Vdpf: Dpf {
     fn new_access_key(&self) -> Self::AuthKey;
     fn new_access_keys(&self) -> Vec<Self::AuthKey>;
 
+    /// Proof shares (one per key) that `dpf_keys` write to `point_idx` only,
+    /// with `auth_key`.
+    ///
+    /// Implementations only read what they need from the keys (their seeds
+    /// and bits at `point_idx`), never their encoded messages, so this costs
+    /// the same however big the message is.
     fn gen_proofs(
         &self,
         auth_key: &Self::AuthKey,
@@ -25,11 +82,27 @@ pub trait Vdpf: Dpf {
     ) -> Self::Token;
 
     fn check_audit(&self, tokens: Vec<Self::Token>) -> bool;
+
+    /// Check many clients' audits at once: `check_audit()` on each of
+    /// `token_sets`, in order.
+    ///
+    /// The default checks them one at a time; implementations may spread
+    /// them across threads (with the `parallel` feature).
+    fn check_audit_batch(&self, token_sets: Vec<Vec<Self::Token>>) -> Vec<bool> {
+        token_sets
+            .into_iter()
+            .map(|tokens| self.check_audit(tokens))
+            .collect()
+    }
 }
 
 #[cfg(test)]
 macro_rules! check_vdpf {
     ($type:ty) => {
+        use crate::testing::{
+            assert_audit_batch, assert_audit_complete, assert_audit_noop_complete,
+            assert_audit_sound, vdpf_with_keys, vdpf_with_keys_data, vdpf_with_keys_data_bad_key,
+        };
         #[allow(unused_imports)]
         use crate::{dpf::Dpf, vdpf::Vdpf};
         #[allow(unused_imports)]
@@ -41,54 +114,40 @@ macro_rules! check_vdpf {
             check::<$type>();
         }
 
-        fn vdpf_with_keys_data() -> impl Strategy<
-            Value = (
-                $type,
-                Vec<<$type as Vdpf>::AuthKey>,
-                <$type as Dpf>::Message,
-            ),
-        > {
-            any::<$type>().prop_flat_map(|vdpf| {
-                (
-                    Just(vdpf.clone()),
-                    Just(vdpf.new_access_keys()),
-                    <$type as Dpf>::Message::arbitrary_with(vdpf.msg_size().into()),
-                )
-            })
-        }
-
-        fn vdpf_with_keys() -> impl Strategy<Value = ($type, Vec<<$type as Vdpf>::AuthKey>)> {
-            any::<$type>().prop_flat_map(|vdpf| (Just(vdpf.clone()), Just(vdpf.new_access_keys())))
-        }
-
         proptest! {
             /// Completeness for gen_proofs.
             #[test]
             fn test_gen_proofs_complete(
-                (vdpf, auth_keys, data) in vdpf_with_keys_data(),
+                (vdpf, auth_keys, data) in vdpf_with_keys_data::<$type>(),
                 idx: prop::sample::Index
             ) {
                 let point_idx = idx.index(vdpf.points());
-                let dpf_keys = vdpf.gen(data, point_idx);
-                let proof_shares = vdpf.gen_proofs(&auth_keys[point_idx], point_idx, &dpf_keys);
-                let audit_tokens = dpf_keys
-                    .iter()
-                    .zip(proof_shares.into_iter())
-                    .map(|(dpf_key, proof_share)| vdpf.gen_audit(&auth_keys, dpf_key, proof_share))
-                    .collect();
-                prop_assert!(vdpf.check_audit(audit_tokens));
+                assert_audit_complete(&vdpf, &auth_keys, data, point_idx)?;
             }
 
             #[test]
-            fn test_gen_proofs_noop_complete((vdpf, auth_keys) in vdpf_with_keys()) {
-                let dpf_keys = vdpf.gen_empty();
-                let proof_shares = vdpf.gen_proofs_noop();
-                let audit_tokens = dpf_keys
-                    .iter()
-                    .zip(proof_shares.into_iter())
-                    .map(|(dpf_key, proof_share)| vdpf.gen_audit(&auth_keys, dpf_key, proof_share))
-                    .collect();
-                prop_assert!(vdpf.check_audit(audit_tokens));
+            fn test_gen_proofs_noop_complete((vdpf, auth_keys) in vdpf_with_keys::<$type>()) {
+                assert_audit_noop_complete(&vdpf, &auth_keys)?;
+            }
+
+            /// Soundness for gen_proofs: the wrong access key fails the audit.
+            #[test]
+            fn test_gen_proofs_sound(
+                (vdpf, auth_keys, data, bad_key) in vdpf_with_keys_data_bad_key::<$type>(),
+                idx: prop::sample::Index
+            ) {
+                let point_idx = idx.index(vdpf.points());
+                assert_audit_sound(&vdpf, &auth_keys, data, point_idx, &bad_key)?;
+            }
+
+            /// Batch audits agree with one-at-a-time audits.
+            #[test]
+            fn test_check_audit_batch(
+                (vdpf, auth_keys, data, bad_key) in vdpf_with_keys_data_bad_key::<$type>(),
+                idx: prop::sample::Index
+            ) {
+                let point_idx = idx.index(vdpf.points());
+                assert_audit_batch(&vdpf, &auth_keys, data, point_idx, &bad_key)?;
             }
         }
     };
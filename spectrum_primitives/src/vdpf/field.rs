@@ -1,6 +1,6 @@
 //! Spectrum implementation.
 #![allow(clippy::unit_arg)] // proptest-derive bug?
-use crate::dpf::Dpf;
+use crate::dpf::{Dpf, KeyMismatch};
 
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
@@ -23,6 +23,10 @@ impl<D, F> FieldVdpf<D, F> {
             phantom: Default::default(),
         }
     }
+
+    pub(super) fn dpf(&self) -> &D {
+        &self.dpf
+    }
 }
 
 // Pass through DPF methods
@@ -58,6 +62,22 @@ impl<D: Dpf, F> Dpf for FieldVdpf<D, F> {
         self.dpf.eval(key)
     }
 
+    fn eval_into(&self, key: Self::Key, acc: &mut [Self::Message]) {
+        self.dpf.eval_into(key, acc)
+    }
+
+    fn eval_at(&self, key: &Self::Key, idx: usize) -> Self::Message {
+        self.dpf.eval_at(key, idx)
+    }
+
+    fn key_message<'a>(&self, key: &'a Self::Key) -> Option<&'a Self::Message> {
+        self.dpf.key_message(key)
+    }
+
+    fn check_key_shape(&self, key: &Self::Key) -> Result<(), KeyMismatch> {
+        self.dpf.check_key_shape(key)
+    }
+
     fn combine(&self, parts: Vec<Vec<Self::Message>>) -> Vec<Self::Message> {
         self.dpf.combine(parts)
     }
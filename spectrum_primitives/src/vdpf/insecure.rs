@@ -1,6 +1,5 @@
 use crate::dpf::{insecure::Construction, Dpf};
 use crate::vdpf::Vdpf;
-use std::iter::repeat;
 
 impl<M> Vdpf for Construction<M>
 where
@@ -18,7 +17,7 @@ where
     }
 
     fn new_access_keys(&self) -> Vec<Self::AuthKey> {
-        repeat(true).take(self.points()).collect()
+        std::iter::repeat_n(true, self.points()).collect()
     }
 
     fn gen_proofs(
@@ -31,7 +30,7 @@ where
     }
 
     fn gen_proofs_noop(&self) -> Vec<Self::ProofShare> {
-        repeat(false).take(self.keys()).collect()
+        std::iter::repeat_n(false, self.keys()).collect()
     }
 
     fn gen_audit(
@@ -6,7 +6,10 @@ pub use definition::Vdpf;
 mod field;
 mod insecure;
 pub mod multi_key;
+pub mod threshold;
+mod tree;
 pub mod two_key;
 pub mod two_key_pub;
 
 pub use field::FieldVdpf;
+pub use threshold::ThresholdVdpf;
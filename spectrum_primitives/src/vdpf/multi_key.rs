@@ -2,13 +2,17 @@ use std::iter::repeat_with;
 use std::ops::Add;
 use std::{fmt::Debug, iter::Sum};
 
+use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::{Zeroize, Zeroizing};
+
 use crate::algebra::{Field, Group, SpecialExponentMonoid};
 use crate::bytes::Bytes;
 use crate::dpf::Dpf;
 use crate::dpf::MultiKeyDpf;
 use crate::prg::GroupPrg;
 use crate::sharing::Shareable;
-use crate::util::Sampleable;
+use crate::util::{map_maybe_parallel, MaybeSync, Sampleable};
 
 use super::*;
 
@@ -16,12 +20,15 @@ use super::*;
 use proptest_derive::Arbitrary;
 
 #[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct ProofShare<S> {
     bit: S,
     seed: S,
 }
 
+versioned_serde!(ProofShare<S>);
+
 impl<S> ProofShare<S> {
     pub fn new(bit: S, seed: S) -> Self {
         ProofShare { bit, seed }
@@ -40,19 +47,28 @@ where
     }
 }
 
+impl<S: Zeroize> Zeroize for ProofShare<S> {
+    fn zeroize(&mut self) {
+        self.bit.zeroize();
+        self.seed.zeroize();
+    }
+}
+
 impl<F> Shareable for ProofShare<F>
 where
-    F: Field + Shareable<Share = F>,
+    F: Field + Shareable<Share = F> + MaybeSync,
 {
     type Share = ProofShare<F>;
 
     fn share(self, n: usize) -> Vec<Self::Share> {
-        Iterator::zip(
-            self.seed.share(n).into_iter(),
-            self.bit.share(n).into_iter(),
-        )
-        .map(|(seed, bit)| ProofShare { bit, seed })
-        .collect()
+        let ProofShare { bit, seed } = self;
+        #[cfg(feature = "parallel")]
+        let (seeds, bits) = rayon::join(|| seed.share(n), || bit.share(n));
+        #[cfg(not(feature = "parallel"))]
+        let (seeds, bits) = (seed.share(n), bit.share(n));
+        Iterator::zip(seeds.into_iter(), bits)
+            .map(|(seed, bit)| ProofShare { bit, seed })
+            .collect()
     }
 
     fn recover(shares: Vec<Self::Share>) -> Self {
@@ -63,14 +79,17 @@ where
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
+#[serde(remote = "Self")]
 pub struct Token<S> {
     seed: S,
     bit: S,
     data: Bytes,
 }
 
+versioned_serde!(Token<S>);
+
 impl<S> Token<S> {
     pub fn new(seed: S, bit: S, data: Bytes) -> Self {
         Token { seed, bit, data }
@@ -94,6 +113,12 @@ where
     }
 }
 
+impl<S: ConstantTimeEq> ConstantTimeEq for Token<S> {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.seed.ct_eq(&other.seed) & self.bit.ct_eq(&other.bit) & self.data.ct_eq(&other.data)
+    }
+}
+
 impl<S> From<Token<S>> for ProofShare<S> {
     fn from(token: Token<S>) -> Self {
         ProofShare {
@@ -103,6 +128,30 @@ impl<S> From<Token<S>> for ProofShare<S> {
     }
 }
 
+// Whether all of a client's tokens have the same message hash.
+fn hashes_match<F>(tokens: &[Token<F>]) -> Choice {
+    tokens.windows(2).fold(Choice::from(1), |acc, pair| {
+        acc & pair[0].data.ct_eq(&pair[1].data)
+    })
+}
+
+/// Check one client's audit tokens (one per server).
+///
+/// In constant time (for a given number of servers).
+fn check_tokens<F>(tokens: Vec<Token<F>>) -> bool
+where
+    F: Field + ConstantTimeEq + Shareable<Share = F> + MaybeSync,
+{
+    // make sure all hashes are equal
+    let hashes_match = hashes_match(&tokens);
+
+    // and bit/seed checks sum to zero
+    let proof = ProofShare::recover(tokens.into_iter().map(ProofShare::from).collect());
+    let proof_zero = proof.bit.ct_eq(&F::zero()) & proof.seed.ct_eq(&F::zero());
+
+    (hashes_match & proof_zero).into()
+}
+
 impl<G, F> Vdpf for FieldVdpf<MultiKeyDpf<GroupPrg<G>>, F>
 where
     G: Shareable
@@ -112,7 +161,15 @@ where
         + Sampleable
         + SpecialExponentMonoid<Exponent = F>
         + Into<Vec<u8>>,
-    F: Sampleable + Field + Sum + Clone + Debug + Shareable<Share = F>,
+    F: Sampleable
+        + Field
+        + Sum
+        + Clone
+        + Debug
+        + Shareable<Share = F>
+        + ConstantTimeEq
+        + Zeroize
+        + MaybeSync,
 {
     type AuthKey = F;
     type ProofShare = ProofShare<F>;
@@ -138,11 +195,20 @@ where
         // We need to share a "correction term" that makes that check out.
         // Our bit vector is 0 everywhere except at idx, where it's 1, so the inner product is just auth_key.
         // Our seed vector is 0 everywhere except at idx, so the inner product is auth_key * seed.
-        let seed = dpf_keys
-            .iter()
-            .map(|k| k.seeds[idx].clone())
-            .fold(F::zero(), Add::add);
-        ProofShare::new(-auth_key.clone(), -(seed * auth_key.clone())).share(self.keys())
+        let seeds = dpf_keys.iter().map(|k| k.seeds[idx].clone());
+        #[cfg(feature = "parallel")]
+        let seed = {
+            use rayon::prelude::*;
+            seeds
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .reduce(F::zero, Add::add)
+        };
+        #[cfg(not(feature = "parallel"))]
+        let seed = seeds.fold(F::zero(), Add::add);
+        // The sum of the seeds at idx is what the DPF keys hide; don't leave it around.
+        let seed = Zeroizing::new(seed);
+        ProofShare::new(-auth_key.clone(), -((*seed).clone() * auth_key.clone())).share(self.keys())
     }
 
     fn gen_proofs_noop(&self) -> Vec<Self::ProofShare> {
@@ -184,21 +250,16 @@ where
     }
 
     fn check_audit(&self, tokens: Vec<Self::Token>) -> bool {
-        use std::collections::HashSet;
-        // make sure all hashes are equal
-        let distinct_hashes: HashSet<_> = tokens.iter().map(|t| t.data.clone()).collect();
-        if distinct_hashes.len() != 1 {
-            eprintln!("bad hashes {:?}", distinct_hashes);
-            return false;
-        }
-
-        // and bit/seed checks sum to zero
-        let proof = ProofShare::recover(tokens.into_iter().map(ProofShare::from).collect());
-        if proof.bit != F::zero() || proof.seed != F::zero() {
-            eprintln!("bad pf bits {:?}", proof);
-            return false;
-        }
+        check_tokens(tokens)
+    }
 
-        true
+    /// Checks each client on its own (across threads, with the `parallel`
+    /// feature).
+    ///
+    /// Recovering a client's proof is just summing its shares, so there's
+    /// nothing to amortize: combining clients (say, with random coefficients)
+    /// would still compute every sum, with the combining on top.
+    fn check_audit_batch(&self, token_sets: Vec<Vec<Self::Token>>) -> Vec<bool> {
+        map_maybe_parallel(token_sets, check_tokens)
     }
 }
@@ -0,0 +1,229 @@
+//! Audits that tolerate slow or offline parties.
+//!
+//! A [`ThresholdVdpf`] hands each of the wrapped VDPF's keys (with its proof
+//! share) to `n` holders: say, every worker in a group. Each holder audits its
+//! copy on its own, and a key's audit token counts once `t` of its holders
+//! have sent it in. So up to `n - t` holders of each key can be missing and
+//! the audit still goes through.
+//!
+//! Holders of the same key compute the same token, so any holder that
+//! disagrees with the others fails the audit.
+use itertools::Itertools;
+use subtle::ConstantTimeEq;
+
+use std::iter::repeat_with;
+
+use super::Vdpf;
+use crate::dpf::Dpf;
+
+/// An audit token from one holder of one of the wrapped VDPF's keys.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdToken<T> {
+    key_idx: usize,
+    holder: usize,
+    token: T,
+}
+
+impl<T> ThresholdToken<T> {
+    /// Which of the wrapped VDPF's keys this token is for.
+    pub fn key_idx(&self) -> usize {
+        self.key_idx
+    }
+
+    /// Which of that key's holders sent this token.
+    pub fn holder(&self) -> usize {
+        self.holder
+    }
+}
+
+/// Wraps a [`Vdpf`] so that each key's audit needs only `t` of its `n`
+/// holders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdVdpf<V> {
+    vdpf: V,
+    threshold: usize,
+    holders: usize,
+}
+
+impl<V: Vdpf> ThresholdVdpf<V> {
+    /// Panics unless `1 <= threshold <= holders`.
+    pub fn new(vdpf: V, threshold: usize, holders: usize) -> Self {
+        assert!(threshold >= 1, "threshold must be at least one!");
+        assert!(
+            threshold <= holders,
+            "threshold can't exceed the number of holders!"
+        );
+        ThresholdVdpf {
+            vdpf,
+            threshold,
+            holders,
+        }
+    }
+
+    /// The wrapped VDPF, for generating keys and proofs.
+    pub fn inner(&self) -> &V {
+        &self.vdpf
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn holders(&self) -> usize {
+        self.holders
+    }
+
+    /// The audit token of `holder`, which holds key number `key_idx`.
+    ///
+    /// Panics if either index is out of range.
+    pub fn gen_audit(
+        &self,
+        key_idx: usize,
+        holder: usize,
+        auth_keys: &[V::AuthKey],
+        dpf_key: &<V as Dpf>::Key,
+        proof_share: V::ProofShare,
+    ) -> ThresholdToken<V::Token> {
+        assert!(key_idx < self.vdpf.keys(), "no such key!");
+        assert!(holder < self.holders, "no such holder!");
+        ThresholdToken {
+            key_idx,
+            holder,
+            token: self.vdpf.gen_audit(auth_keys, dpf_key, proof_share),
+        }
+    }
+
+    /// Check one client's audit, given tokens from (at least `t` holders of)
+    /// each key.
+    ///
+    /// Fails if any key is short of holders, if a holder sent in two tokens,
+    /// or if a key's holders don't agree on its token.
+    pub fn check_audit(&self, tokens: Vec<ThresholdToken<V::Token>>) -> bool
+    where
+        V::Token: ConstantTimeEq,
+    {
+        let keys = self.vdpf.keys();
+        let mut by_key: Vec<Vec<ThresholdToken<V::Token>>> =
+            repeat_with(Vec::new).take(keys).collect();
+        for token in tokens {
+            if token.key_idx >= keys || token.holder >= self.holders {
+                return false;
+            }
+            by_key[token.key_idx].push(token);
+        }
+
+        let mut agreed = Vec::with_capacity(keys);
+        for replicas in by_key {
+            let distinct = replicas.iter().map(|t| t.holder).sorted().dedup().count();
+            if distinct != replicas.len() || distinct < self.threshold {
+                return false;
+            }
+            let mut replicas = replicas.into_iter().map(|t| t.token);
+            let first = replicas.next().expect("threshold is at least one");
+            if !replicas.all(|token| bool::from(token.ct_eq(&first))) {
+                return false;
+            }
+            agreed.push(first);
+        }
+        self.vdpf.check_audit(agreed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bytes, TwoKeyVdpf};
+    use proptest::prelude::*;
+    use proptest::sample::subsequence;
+
+    const CHANNELS: usize = 3;
+    const MSG_SIZE: usize = 16;
+
+    fn vdpf() -> ThresholdVdpf<TwoKeyVdpf> {
+        ThresholdVdpf::new(TwoKeyVdpf::with_channels_msg_size(CHANNELS, MSG_SIZE), 2, 3)
+    }
+
+    /// Tokens for writing to `point`, from `holders` of each key.
+    fn tokens(
+        vdpf: &ThresholdVdpf<TwoKeyVdpf>,
+        auth_keys: &[<TwoKeyVdpf as Vdpf>::AuthKey],
+        auth_key: &<TwoKeyVdpf as Vdpf>::AuthKey,
+        point: usize,
+        holders: &[Vec<usize>],
+    ) -> Vec<ThresholdToken<<TwoKeyVdpf as Vdpf>::Token>> {
+        let inner = vdpf.inner();
+        let dpf_keys = inner.gen(Bytes::from(vec![7; MSG_SIZE]), point);
+        let proof_shares = inner.gen_proofs(auth_key, point, &dpf_keys);
+        let mut tokens = vec![];
+        for (key_idx, (dpf_key, proof_share)) in dpf_keys.iter().zip(proof_shares).enumerate() {
+            for &holder in &holders[key_idx] {
+                let proof_share = proof_share.clone();
+                tokens.push(vdpf.gen_audit(key_idx, holder, auth_keys, dpf_key, proof_share));
+            }
+        }
+        tokens
+    }
+
+    fn enough_holders() -> impl Strategy<Value = Vec<Vec<usize>>> {
+        let holders = vdpf().holders();
+        let threshold = vdpf().threshold();
+        prop::collection::vec(
+            subsequence((0..holders).collect::<Vec<_>>(), threshold..=holders),
+            2,
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn test_enough_holders(holders in enough_holders(), point in 0..CHANNELS) {
+            let vdpf = vdpf();
+            let auth_keys = vdpf.inner().new_access_keys();
+            let tokens = tokens(&vdpf, &auth_keys, &auth_keys[point], point, &holders);
+            prop_assert!(vdpf.check_audit(tokens));
+        }
+
+        #[test]
+        fn test_bad_access_key(holders in enough_holders(), point in 0..CHANNELS) {
+            let vdpf = vdpf();
+            let auth_keys = vdpf.inner().new_access_keys();
+            let bad_key = vdpf.inner().new_access_key();
+            let tokens = tokens(&vdpf, &auth_keys, &bad_key, point, &holders);
+            prop_assert!(!vdpf.check_audit(tokens));
+        }
+    }
+
+    #[test]
+    fn test_too_few_holders() {
+        let vdpf = vdpf();
+        let auth_keys = vdpf.inner().new_access_keys();
+        let holders = vec![vec![0, 2], vec![1]];
+        let tokens = tokens(&vdpf, &auth_keys, &auth_keys[0], 0, &holders);
+        assert!(!vdpf.check_audit(tokens));
+    }
+
+    #[test]
+    fn test_duplicate_holder() {
+        let vdpf = vdpf();
+        let auth_keys = vdpf.inner().new_access_keys();
+        let holders = vec![vec![0, 1], vec![1, 2, 1]];
+        let tokens = tokens(&vdpf, &auth_keys, &auth_keys[0], 0, &holders);
+        assert!(!vdpf.check_audit(tokens));
+    }
+
+    #[test]
+    fn test_holders_disagree() {
+        let vdpf = vdpf();
+        let auth_keys = vdpf.inner().new_access_keys();
+        let holders = vec![vec![0, 1], vec![0, 1]];
+        let mut all = tokens(&vdpf, &auth_keys, &auth_keys[0], 0, &holders);
+        // Holder 2 of the first key saw a different write.
+        all.extend(tokens(
+            &vdpf,
+            &auth_keys,
+            &auth_keys[1],
+            1,
+            &[vec![2], vec![]],
+        ));
+        assert!(!vdpf.check_audit(all));
+    }
+}
@@ -0,0 +1,100 @@
+//! VDPF for the tree-based 2-DPF.
+//!
+//! Tree keys expand to a seed and a bit per point, just like a two-key DPF
+//! key, so this is the same audit as [`two_key`](super::two_key), run on the
+//! expanded seeds and bits.
+use crate::algebra::Field;
+use crate::dpf::Dpf;
+use crate::dpf::TreeDpf;
+use crate::prg::Prg;
+use crate::sharing::Shareable;
+use crate::util::{map_maybe_parallel, MaybeSync, Sampleable};
+use crate::vdpf::Vdpf;
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Debug;
+use std::iter::repeat_with;
+use std::ops::{BitXor, BitXorAssign};
+use std::sync::Arc;
+
+use subtle::ConstantTimeEq;
+
+use super::field::FieldVdpf;
+use super::two_key::{
+    check_tokens, gen_audit_token, gen_proof_shares, gen_proof_shares_noop, reject_token,
+};
+use super::two_key::{ProofShare, Token};
+
+impl<F, P> Vdpf for FieldVdpf<TreeDpf<P>, F>
+where
+    F: Field + Sampleable + Clone + Shareable<Share = F> + ConstantTimeEq + MaybeSync,
+    P: Prg + Clone,
+    P::Seed: Clone + Debug + Eq + TryInto<F> + Into<Vec<u8>> + TryFrom<Vec<u8>>,
+    <P::Seed as TryInto<F>>::Error: Debug,
+    <P::Seed as TryFrom<Vec<u8>>>::Error: Debug,
+    P::Output: Debug
+        + Eq
+        + Clone
+        + AsRef<[u8]>
+        + BitXor<P::Output, Output = P::Output>
+        + BitXor<Arc<P::Output>, Output = P::Output>
+        + BitXorAssign<P::Output>,
+{
+    type AuthKey = F;
+    type ProofShare = ProofShare<F>;
+    type Token = Token<F>;
+
+    fn new_access_key(&self) -> Self::AuthKey {
+        F::sample()
+    }
+
+    fn new_access_keys(&self) -> Vec<Self::AuthKey> {
+        repeat_with(F::sample).take(self.points()).collect()
+    }
+
+    fn gen_proofs(
+        &self,
+        auth_key: &F,
+        idx: usize,
+        dpf_keys: &[<Self as Dpf>::Key],
+    ) -> Vec<Self::ProofShare> {
+        assert_eq!(dpf_keys.len(), 2, "not implemented");
+        // Only need the path down to `idx`, not the whole tree.
+        let (seed_a, bit_a) = self.dpf().leaf(&dpf_keys[0], idx).expect("keys from gen()");
+        let (seed_b, _) = self.dpf().leaf(&dpf_keys[1], idx).expect("keys from gen()");
+        gen_proof_shares(auth_key, bit_a, [seed_a, seed_b])
+    }
+
+    fn gen_proofs_noop(&self) -> Vec<Self::ProofShare> {
+        gen_proof_shares_noop()
+    }
+
+    fn gen_audit(
+        &self,
+        auth_keys: &[F],
+        dpf_key: &<Self as Dpf>::Key,
+        proof_share: Self::ProofShare,
+    ) -> Self::Token {
+        let leaves = match self.dpf().leaves(dpf_key) {
+            Ok(leaves) => leaves,
+            // Malformed key: fail the audit.
+            Err(_) => return reject_token(),
+        };
+        let (seeds, bits): (Vec<_>, Vec<_>) = leaves.into_iter().unzip();
+        gen_audit_token(
+            auth_keys,
+            &bits,
+            &seeds,
+            dpf_key.encoded_msg.as_ref(),
+            proof_share,
+        )
+    }
+
+    fn check_audit(&self, tokens: Vec<Self::Token>) -> bool {
+        check_tokens(tokens)
+    }
+
+    fn check_audit_batch(&self, token_sets: Vec<Vec<Self::Token>>) -> Vec<bool> {
+        map_maybe_parallel(token_sets, check_tokens)
+    }
+}
@@ -4,7 +4,7 @@ use crate::dpf::Dpf;
 use crate::dpf::TwoKeyDpf;
 use crate::prg::Prg;
 use crate::sharing::Shareable;
-use crate::util::Sampleable;
+use crate::util::{map_maybe_parallel, MaybeSync, Sampleable};
 use crate::vdpf::Vdpf;
 
 use std::fmt::Debug;
@@ -13,18 +13,25 @@ use std::ops::{BitXor, BitXorAssign};
 use std::sync::Arc;
 use std::{convert::TryInto, ops::Add};
 
+use serde::{Deserialize, Serialize};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::Zeroize;
+
 use super::field::FieldVdpf;
 
 #[cfg(any(test, feature = "testing"))]
 use proptest_derive::Arbitrary;
 
 #[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct ProofShare<S> {
     seed: S,
     bit: S,
 }
 
+versioned_serde!(ProofShare<S>);
+
 impl<S> ProofShare<S> {
     pub fn new(seed: S, bit: S) -> Self {
         ProofShare { seed, bit }
@@ -44,14 +51,24 @@ where
     }
 }
 
+impl<S: Zeroize> Zeroize for ProofShare<S> {
+    fn zeroize(&mut self) {
+        self.seed.zeroize();
+        self.bit.zeroize();
+    }
+}
+
 #[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Token<S> {
     seed: S,
     bit: S,
     data: Bytes,
 }
 
+versioned_serde!(Token<S>);
+
 impl<S> Token<S> {
     pub fn new(seed: S, bit: S, data: Bytes) -> Self {
         Token { seed, bit, data }
@@ -75,6 +92,12 @@ where
     }
 }
 
+impl<S: ConstantTimeEq> ConstantTimeEq for Token<S> {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.seed.ct_eq(&other.seed) & self.bit.ct_eq(&other.bit) & self.data.ct_eq(&other.data)
+    }
+}
+
 impl<S> From<Token<S>> for ProofShare<S> {
     fn from(token: Token<S>) -> Self {
         ProofShare {
@@ -84,9 +107,127 @@ impl<S> From<Token<S>> for ProofShare<S> {
     }
 }
 
+/// Proof shares for writing to a point where the first key has `bit` and
+/// `seeds` are the two keys' seeds.
+///
+/// Shared with any construction whose keys expand to seeds and bits that agree
+/// between the two keys everywhere but the point being written.
+pub(super) fn gen_proof_shares<F, S>(auth_key: &F, bit: bool, seeds: [S; 2]) -> Vec<ProofShare<F>>
+where
+    F: Field + Sampleable + Clone,
+    S: TryInto<F>,
+    S::Error: Debug,
+{
+    // Server i computes: <auth_keys> . <bits[i]> + bit_proofs[i]
+    // Then servers 1 and 2 check results equal.
+    //
+    // We know bits[i] are the same, except at bits[i][idx].
+    // So we really just need:
+    // bits[1][idx] * auth_key + proofs[1] == bits[0][idx] * auth_key + proofs[0] <=>
+    // proofs[1] == proofs[0] + (bits[0][idx] - bits[1][idx]) * auth_key
+    let bit_a = F::sample();
+    // Because bits[0][idx] is boolean, we just need to know whether to add/subtract auth_key.
+    let mut bit_b = bit_a.clone();
+    if bit {
+        bit_b = bit_b + auth_key.clone();
+    } else {
+        bit_b = bit_b - auth_key.clone();
+    }
+
+    // Similar here for seeds instead of bits, but we don't have seeds in {0, 1}. Want:
+    // proofs[1] == proofs[0] + (seeds[0][idx] seeds[1][idx]) * auth_key
+    let [seed_0, seed_1] = seeds;
+    let mut seed_a = F::sample();
+    let mut seed_b = seed_a.clone();
+    seed_a = seed_a + seed_1.try_into().unwrap() * auth_key.clone();
+    seed_b = seed_b + seed_0.try_into().unwrap() * auth_key.clone();
+
+    vec![
+        ProofShare::new(seed_a, bit_a),
+        ProofShare::new(seed_b, bit_b),
+    ]
+}
+
+pub(super) fn gen_proof_shares_noop<F>() -> Vec<ProofShare<F>>
+where
+    F: Sampleable + Clone,
+{
+    // Same random values
+    std::iter::repeat_n(
+        ProofShare {
+            seed: F::sample(),
+            bit: F::sample(),
+        },
+        2,
+    )
+    .collect()
+}
+
+/// Audit token for a key that expands to `bits` and `seeds` (one per point).
+pub(super) fn gen_audit_token<F, S>(
+    auth_keys: &[F],
+    bits: &[bool],
+    seeds: &[S],
+    encoded_msg: &[u8],
+    proof_share: ProofShare<F>,
+) -> Token<F>
+where
+    F: Field + Clone,
+    S: Clone + TryInto<F>,
+    S::Error: Debug,
+{
+    assert_eq!(auth_keys.len(), bits.len());
+    assert_eq!(auth_keys.len(), seeds.len());
+    // Inner product + proof share
+    let bit_check = bits
+        .iter()
+        .zip(auth_keys)
+        .map(|(bit, key)| if *bit { key.clone() } else { F::zero() })
+        .fold(F::zero(), Add::add)
+        + proof_share.bit;
+
+    // Inner product + proof share
+    let seed_check = seeds
+        .iter()
+        .map(|seed| seed.clone().try_into().unwrap())
+        .zip(auth_keys)
+        .map(|(seed, key)| seed * key.clone())
+        .fold(F::zero(), Add::add)
+        + proof_share.seed;
+
+    // Hash of message
+    let mut hasher = blake3::Hasher::new();
+    if encoded_msg.len() >= 125000 {
+        hasher.update_with_join::<blake3::join::RayonJoin>(encoded_msg);
+    } else {
+        hasher.update(encoded_msg);
+    }
+    let data: [u8; 32] = hasher.finalize().into();
+
+    Token {
+        bit: bit_check,
+        seed: seed_check,
+        data: Bytes::from(data.to_vec()),
+    }
+}
+
+/// A token that fails the audit (with overwhelming probability), for keys too
+/// malformed to audit.
+pub(super) fn reject_token<F: Sampleable>() -> Token<F> {
+    Token::new(F::sample(), F::sample(), Bytes::empty(0))
+}
+
+/// Check one client's audit tokens: the two servers' should match.
+///
+/// In constant time, so that timing doesn't reveal how close a bad write came.
+pub(super) fn check_tokens<F: ConstantTimeEq>(tokens: Vec<Token<F>>) -> bool {
+    assert_eq!(tokens.len(), 2, "not implemented");
+    tokens[0].ct_eq(&tokens[1]).into()
+}
+
 impl<F, P> Vdpf for FieldVdpf<TwoKeyDpf<P>, F>
 where
-    F: Field + Sampleable + Clone + Shareable<Share = F>,
+    F: Field + Sampleable + Clone + Shareable<Share = F> + ConstantTimeEq + MaybeSync,
     P: Prg + Clone,
     P::Seed: Clone + Debug + Eq + TryInto<F>,
     <P::Seed as TryInto<F>>::Error: Debug,
@@ -117,44 +258,15 @@ where
         dpf_keys: &[<Self as Dpf>::Key],
     ) -> Vec<Self::ProofShare> {
         assert_eq!(dpf_keys.len(), 2, "not implemented");
-        // Server i computes: <auth_keys> . <bits[i]> + bit_proofs[i]
-        // Then servers 1 and 2 check results equal.
-        //
-        // We know bits[i] are the same, except at bits[i][idx].
-        // So we really just need:
-        // bits[1][idx] * auth_key + proofs[1] == bits[0][idx] * auth_key + proofs[0] <=>
-        // proofs[1] == proofs[0] + (bits[0][idx] - bits[1][idx]) * auth_key
-        let bit_a = F::sample();
-        // Because bits[0][idx] is boolean, we just need to know whether to add/subtract auth_key.
-        let mut bit_b = bit_a.clone();
-        if dpf_keys[0].bits[idx] {
-            bit_b = bit_b + auth_key.clone();
-        } else {
-            bit_b = bit_b - auth_key.clone();
-        }
-
-        // Similar here for seeds instead of bits, but we don't have seeds in {0, 1}. Want:
-        // proofs[1] == proofs[0] + (seeds[0][idx] seeds[1][idx]) * auth_key
-        let mut seed_a = F::sample();
-        let mut seed_b = seed_a.clone();
-        seed_a = seed_a + dpf_keys[1].seeds[idx].clone().try_into().unwrap() * auth_key.clone();
-        seed_b = seed_b + dpf_keys[0].seeds[idx].clone().try_into().unwrap() * auth_key.clone();
-
-        vec![
-            ProofShare::new(seed_a, bit_a),
-            ProofShare::new(seed_b, bit_b),
-        ]
+        let seeds = [
+            dpf_keys[0].seeds[idx].clone(),
+            dpf_keys[1].seeds[idx].clone(),
+        ];
+        gen_proof_shares(auth_key, dpf_keys[0].bits[idx], seeds)
     }
 
     fn gen_proofs_noop(&self) -> Vec<Self::ProofShare> {
-        use std::iter::repeat;
-        // Same random values
-        repeat(ProofShare {
-            seed: F::sample(),
-            bit: F::sample(),
-        })
-        .take(2)
-        .collect()
+        gen_proof_shares_noop()
     }
 
     fn gen_audit(
@@ -163,46 +275,20 @@ where
         dpf_key: &<Self as Dpf>::Key,
         proof_share: Self::ProofShare,
     ) -> Self::Token {
-        assert_eq!(auth_keys.len(), dpf_key.bits.len());
-        assert_eq!(auth_keys.len(), dpf_key.seeds.len());
-        // Inner product + proof share
-        let bit_check = dpf_key
-            .bits
-            .iter()
-            .zip(auth_keys)
-            .map(|(bit, key)| if *bit { key.clone() } else { F::zero() })
-            .fold(F::zero(), Add::add)
-            + proof_share.bit;
-
-        // Inner product + proof share
-        let seed_check = dpf_key
-            .seeds
-            .iter()
-            .map(|seed| seed.clone().try_into().unwrap())
-            .zip(auth_keys)
-            .map(|(seed, key)| seed * key.clone())
-            .fold(F::zero(), Add::add)
-            + proof_share.seed;
-
-        // Hash of message
-        let mut hasher = blake3::Hasher::new();
-        let input: &[u8] = dpf_key.encoded_msg.as_ref();
-        if input.len() >= 125000 {
-            hasher.update_with_join::<blake3::join::RayonJoin>(input);
-        } else {
-            hasher.update(input);
-        }
-        let data: [u8; 32] = hasher.finalize().into();
-
-        Token {
-            bit: bit_check,
-            seed: seed_check,
-            data: Bytes::from(data.to_vec()),
-        }
+        gen_audit_token(
+            auth_keys,
+            &dpf_key.bits,
+            &dpf_key.seeds,
+            dpf_key.encoded_msg.as_ref(),
+            proof_share,
+        )
     }
 
     fn check_audit(&self, tokens: Vec<Self::Token>) -> bool {
-        assert_eq!(tokens.len(), 2, "not implemented");
-        tokens[0] == tokens[1]
+        check_tokens(tokens)
+    }
+
+    fn check_audit_batch(&self, token_sets: Vec<Vec<Self::Token>>) -> Vec<bool> {
+        map_maybe_parallel(token_sets, check_tokens)
     }
 }
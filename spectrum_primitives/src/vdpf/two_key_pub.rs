@@ -2,8 +2,8 @@ use crate::algebra::{Monoid, SpecialExponentMonoid};
 use crate::bytes::Bytes;
 use crate::constructions::jubjub::{CurvePoint, Scalar};
 use crate::constructions::AesSeed;
-use crate::dpf::Dpf;
 use crate::dpf::TwoKeyDpf;
+use crate::dpf::{Dpf, KeyMismatch};
 use crate::prg::Prg;
 use crate::util::Sampleable;
 use crate::vdpf::Vdpf;
@@ -15,6 +15,8 @@ use std::sync::Arc;
 use std::{convert::TryInto, ops::Add};
 
 use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
 
 #[cfg(any(test, feature = "testing"))]
 use proptest::prelude::*;
@@ -54,20 +56,30 @@ impl Arbitrary for KeyPair {
     }
 }
 
+/// Zeroizes (just) the private key.
+impl Zeroize for KeyPair {
+    fn zeroize(&mut self) {
+        self.private.zeroize();
+    }
+}
+
 impl From<Scalar> for KeyPair {
     fn from(private: Scalar) -> Self {
-        let public: CurvePoint = private.clone().into();
+        let public: CurvePoint = private.into();
         Self { public, private }
     }
 }
 
 #[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct ProofShare {
     seed: CurvePoint,
     bit: CurvePoint,
 }
 
+versioned_serde!(ProofShare);
+
 impl ProofShare {
     pub fn new(seed: CurvePoint, bit: CurvePoint) -> Self {
         ProofShare { seed, bit }
@@ -76,22 +88,32 @@ impl ProofShare {
 
 impl ProofShare {
     pub fn bit(&self) -> CurvePoint {
-        self.bit.clone()
+        self.bit
     }
 
     pub fn seed(&self) -> CurvePoint {
-        self.seed.clone()
+        self.seed
+    }
+}
+
+impl Zeroize for ProofShare {
+    fn zeroize(&mut self) {
+        self.seed.zeroize();
+        self.bit.zeroize();
     }
 }
 
 #[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Token {
     seed: CurvePoint,
     bit: CurvePoint,
     data: Bytes,
 }
 
+versioned_serde!(Token);
+
 impl Token {
     pub fn new(seed: CurvePoint, bit: CurvePoint, data: Bytes) -> Self {
         Token { seed, bit, data }
@@ -104,11 +126,11 @@ impl Token {
 
 impl Token {
     pub fn seed(&self) -> CurvePoint {
-        self.seed.clone()
+        self.seed
     }
 
     pub fn bit(&self) -> CurvePoint {
-        self.bit.clone()
+        self.bit
     }
 }
 
@@ -169,6 +191,22 @@ where
         self.dpf.eval(key)
     }
 
+    fn eval_into(&self, key: Self::Key, acc: &mut [Self::Message]) {
+        self.dpf.eval_into(key, acc)
+    }
+
+    fn eval_at(&self, key: &Self::Key, idx: usize) -> Self::Message {
+        self.dpf.eval_at(key, idx)
+    }
+
+    fn key_message<'a>(&self, key: &'a Self::Key) -> Option<&'a Self::Message> {
+        self.dpf.key_message(key)
+    }
+
+    fn check_key_shape(&self, key: &Self::Key) -> Result<(), KeyMismatch> {
+        self.dpf.check_key_shape(key)
+    }
+
     fn combine(&self, parts: Vec<Vec<Self::Message>>) -> Vec<Self::Message> {
         self.dpf.combine(parts)
     }
@@ -216,21 +254,19 @@ where
         // So we really just need:
         // auth_key ^ bits[1][idx] + proofs[1] == auth_key ^ bits[0][idx] + proofs[0]
         let mut bit_a = CurvePoint::sample();
-        let mut bit_b = bit_a.clone();
+        let mut bit_b = bit_a;
         if dpf_keys[0].bits[idx] {
-            bit_b = bit_b + auth_key.private.into(); // into() -> exponentiation!
+            bit_b += auth_key.private.into(); // into() -> exponentiation!
         } else {
-            bit_a = bit_a + auth_key.private.into(); // into() -> exponentiation!
+            bit_a += auth_key.private.into(); // into() -> exponentiation!
         }
 
         // Similar here for seeds instead of bits, but we don't have seeds in {0, 1}. Want:
         // proofs[1] == proofs[0] + auth_key ^ (seeds[0][idx] - seeds[1][idx])
         let mut seed_a = CurvePoint::sample();
-        let mut seed_b = seed_a.clone();
-        seed_a =
-            seed_a + (dpf_keys[1].seeds[idx].clone().try_into().unwrap() * auth_key.private).into(); // into() -> exp
-        seed_b =
-            seed_b + (dpf_keys[0].seeds[idx].clone().try_into().unwrap() * auth_key.private).into(); // into() -> exp
+        let mut seed_b = seed_a;
+        seed_a += (dpf_keys[1].seeds[idx].clone().try_into().unwrap() * auth_key.private).into(); // into() -> exp
+        seed_b += (dpf_keys[0].seeds[idx].clone().try_into().unwrap() * auth_key.private).into(); // into() -> exp
 
         vec![
             ProofShare::new(seed_a, bit_a),
@@ -239,13 +275,14 @@ where
     }
 
     fn gen_proofs_noop(&self) -> Vec<Self::ProofShare> {
-        use std::iter::repeat;
         // Same random values
-        repeat(ProofShare {
-            seed: CurvePoint::sample(),
-            bit: CurvePoint::sample(),
-        })
-        .take(2)
+        std::iter::repeat_n(
+            ProofShare {
+                seed: CurvePoint::sample(),
+                bit: CurvePoint::sample(),
+            },
+            2,
+        )
         .collect()
     }
 
@@ -276,14 +313,13 @@ where
             + proof_share.bit;
 
         // Inner product + proof share
-        let seed_check = dpf_key
+        let publics: Vec<CurvePoint> = auth_keys.iter().map(|key| key.public).collect();
+        let seeds: Vec<Scalar> = dpf_key
             .seeds
             .iter()
             .map(|seed| seed.clone().try_into().unwrap())
-            .zip(auth_keys)
-            .map(|(seed, key)| key.public.pow(seed))
-            .fold(CurvePoint::zero(), Add::add)
-            + proof_share.seed;
+            .collect();
+        let seed_check = CurvePoint::msm(&publics, &seeds) + proof_share.seed;
 
         // Hash of message
         let mut hasher = blake3::Hasher::new();
@@ -305,7 +341,7 @@ where
     fn check_audit(&self, tokens: Vec<Self::Token>) -> bool {
         assert_eq!(tokens.len(), 2, "not implemented");
         // tokens[0] == tokens[1]
-        tokens[0].seed == tokens[1].seed
+        tokens[0].seed.ct_eq(&tokens[1].seed).into()
     }
 }
 
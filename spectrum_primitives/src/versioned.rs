@@ -0,0 +1,202 @@
+//! Versioned serialization for keys, proof shares, and tokens.
+//!
+//! These get written to disk (e.g., broadcaster key files) and read back,
+//! possibly by a later build. So each one serializes as
+//! `{"version": FORMAT_VERSION, "value": ...}`, and reading any other version
+//! is an error rather than a garbled key.
+//!
+//! The types themselves derive `Serialize` and `Deserialize` with
+//! `#[serde(remote = "Self")]` (which gives inherent `serialize` and
+//! `deserialize` functions instead of trait impls), and `versioned_serde!`
+//! wraps those up.
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Version of the serialized format of keys, proof shares, and tokens.
+///
+/// Bump this whenever any of them changes shape.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The current [`FORMAT_VERSION`], checked as it's read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Version;
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(FORMAT_VERSION)
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = u32::deserialize(deserializer)?;
+        if version != FORMAT_VERSION {
+            return Err(de::Error::custom(format!(
+                "unsupported format version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        Ok(Version)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Tagged<T> {
+    // First, so that a bad version fails before the value gets parsed.
+    pub(crate) version: Version,
+    pub(crate) value: T,
+}
+
+/// Implement `Serialize` and `Deserialize` (tagged with the format version)
+/// for a type deriving them with `#[serde(remote = "Self")]`.
+macro_rules! versioned_serde {
+    ($name:ident $(< $($param:ident),+ >)?) => {
+        impl$(< $($param: serde::Serialize),+ >)? serde::Serialize for $name$(< $($param),+ >)? {
+            fn serialize<Z: serde::Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+                struct Value<'a $($(, $param)+)?>(&'a $name$(< $($param),+ >)?);
+
+                impl<'a $($(, $param: serde::Serialize)+)?> serde::Serialize
+                    for Value<'a $($(, $param)+)?>
+                {
+                    fn serialize<Z: serde::Serializer>(
+                        &self,
+                        serializer: Z,
+                    ) -> Result<Z::Ok, Z::Error> {
+                        $name::serialize(self.0, serializer)
+                    }
+                }
+
+                let tagged = $crate::versioned::Tagged {
+                    version: $crate::versioned::Version,
+                    value: Value(self),
+                };
+                serde::Serialize::serialize(&tagged, serializer)
+            }
+        }
+
+        impl<'de $($(, $param: serde::Deserialize<'de>)+)?> serde::Deserialize<'de>
+            for $name$(< $($param),+ >)?
+        {
+            fn deserialize<Z: serde::Deserializer<'de>>(deserializer: Z) -> Result<Self, Z::Error> {
+                struct Value$(< $($param),+ >)?($name$(< $($param),+ >)?);
+
+                impl<'de $($(, $param: serde::Deserialize<'de>)+)?> serde::Deserialize<'de>
+                    for Value$(< $($param),+ >)?
+                {
+                    fn deserialize<Z: serde::Deserializer<'de>>(
+                        deserializer: Z,
+                    ) -> Result<Self, Z::Error> {
+                        $name::deserialize(deserializer).map(Value)
+                    }
+                }
+
+                let tagged: $crate::versioned::Tagged<Value$(< $($param),+ >)?> =
+                    serde::Deserialize::deserialize(deserializer)?;
+                Ok(tagged.value.0)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constructions::jubjub::CurvePoint;
+    use crate::constructions::{AesPrg, AesSeed, AuthKey, ChaChaSeed, Fp61};
+    use crate::dpf::{multi_key, two_key, Dpf, TreeDpf, TwoKeyDpf};
+    use crate::prg::ElementVector;
+    use crate::testing::dpf_with_keys;
+    use crate::vdpf;
+    use crate::Bytes;
+
+    use proptest::prelude::*;
+    use serde::de::DeserializeOwned;
+    use std::fmt::Debug;
+
+    fn check_roundtrip<T>(value: &T) -> Result<(), TestCaseError>
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        let json = serde_json::to_string(value).unwrap();
+        prop_assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), value);
+        let tagged: serde_json::Value = serde_json::from_str(&json).unwrap();
+        prop_assert_eq!(&tagged["version"], &serde_json::json!(FORMAT_VERSION));
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_two_key_key(key: two_key::Key<Bytes, AesSeed>) {
+            check_roundtrip(&key)?;
+        }
+
+        #[test]
+        fn test_multi_key_key(key: multi_key::Key<ElementVector<CurvePoint>, AuthKey>) {
+            check_roundtrip(&key)?;
+        }
+
+        #[test]
+        fn test_tree_key((_, _, _, keys) in dpf_with_keys::<TreeDpf<AesPrg>>()) {
+            for key in keys {
+                check_roundtrip(&key)?;
+            }
+        }
+
+        #[test]
+        fn test_two_key_proof(
+            proof: vdpf::two_key::ProofShare<AuthKey>,
+            token: vdpf::two_key::Token<AuthKey>,
+        ) {
+            check_roundtrip(&proof)?;
+            check_roundtrip(&token)?;
+        }
+
+        #[test]
+        fn test_two_key_fp61_proof(
+            proof: vdpf::two_key::ProofShare<Fp61>,
+            token: vdpf::two_key::Token<Fp61>,
+        ) {
+            check_roundtrip(&proof)?;
+            check_roundtrip(&token)?;
+        }
+
+        #[test]
+        fn test_two_key_pub_proof(
+            proof: vdpf::two_key_pub::ProofShare,
+            token: vdpf::two_key_pub::Token,
+        ) {
+            check_roundtrip(&proof)?;
+            check_roundtrip(&token)?;
+        }
+
+        #[test]
+        fn test_multi_key_proof(
+            proof: vdpf::multi_key::ProofShare<AuthKey>,
+            token: vdpf::multi_key::Token<AuthKey>,
+        ) {
+            check_roundtrip(&proof)?;
+            check_roundtrip(&token)?;
+        }
+    }
+
+    #[test]
+    fn test_wrong_version() {
+        let key = TwoKeyDpf::new(AesPrg::new(16), 2)
+            .gen(Bytes::empty(16), 0)
+            .pop()
+            .unwrap();
+        let mut json = serde_json::to_value(&key).unwrap();
+        json["version"] = serde_json::json!(FORMAT_VERSION + 1);
+        let err = serde_json::from_value::<two_key::Key<Bytes, AesSeed>>(json)
+            .expect_err("wrong version");
+        assert!(err.to_string().contains("unsupported format version"));
+    }
+
+    #[test]
+    fn test_seed_length() {
+        let seed = AesSeed::random();
+        let json = serde_json::to_string(&seed).unwrap();
+        assert_eq!(serde_json::from_str::<AesSeed>(&json).unwrap(), seed);
+        serde_json::from_str::<AesSeed>("[1, 2, 3]").expect_err("short seed");
+        serde_json::from_str::<ChaChaSeed>("[1, 2, 3]").expect_err("short seed");
+    }
+}
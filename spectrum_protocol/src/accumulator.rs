@@ -1,6 +1,40 @@
+use std::fmt;
 use std::iter::repeat_with;
 
-use spectrum_primitives::{Bytes, ElementVector, Group};
+use spectrum_primitives::{Bytes, ElementVector, Group, KeyMismatch, LengthMismatch};
+
+/// Tried to combine accumulatables with different parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamsMismatch(String);
+
+impl ParamsMismatch {
+    pub fn new<T: fmt::Debug>(expected: T, actual: T) -> Self {
+        ParamsMismatch(format!("expected {:?}, got {:?}", expected, actual))
+    }
+}
+
+impl fmt::Display for ParamsMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parameter mismatch: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParamsMismatch {}
+
+impl From<LengthMismatch> for ParamsMismatch {
+    fn from(err: LengthMismatch) -> Self {
+        ParamsMismatch::new(err.expected, err.actual)
+    }
+}
+
+impl From<KeyMismatch> for ParamsMismatch {
+    fn from(err: KeyMismatch) -> Self {
+        ParamsMismatch(format!(
+            "expected {} {}, got {}",
+            err.expected, err.field, err.actual
+        ))
+    }
+}
 
 /// Something that can be accumulated.
 ///
@@ -10,13 +44,89 @@ pub trait Accumulatable {
     /// Parameters for creating an empty Accumultable.
     ///
     /// There's no one-size-fits-all à la Default.
-    type Parameters: Copy;
+    type Parameters: Copy + PartialEq + fmt::Debug;
     // TODO: other should be a reference?
+    /// Panics (or worse) if `other` has different parameters; see
+    /// `try_combine()` for untrusted input.
     fn combine(&mut self, other: Self);
 
     fn empty(params: Self::Parameters) -> Self;
 
     fn params(&self) -> Self::Parameters;
+
+    /// Check that `other` can be combined into `self`.
+    fn check_combine(&self, other: &Self) -> Result<(), ParamsMismatch> {
+        let (expected, actual) = (self.params(), other.params());
+        if expected != actual {
+            return Err(ParamsMismatch::new(expected, actual));
+        }
+        Ok(())
+    }
+
+    /// Like `combine()`, but returns an error (leaving `self` unchanged)
+    /// rather than panicking if `other` has different parameters.
+    ///
+    /// Use this for anything that came from the network.
+    fn try_combine(&mut self, other: Self) -> Result<(), ParamsMismatch>
+    where
+        Self: Sized,
+    {
+        self.check_combine(&other)?;
+        self.combine(other);
+        Ok(())
+    }
+
+    /// Combine `weight` copies of `other` into `self`.
+    ///
+    /// For protocols where contributions count for more (or less) than one,
+    /// e.g. majority-vote channels or threshold reveal. The default uses
+    /// double-and-add (so `O(log weight)` combines); implementations can
+    /// often do better.
+    fn combine_weighted(&mut self, other: Self, weight: u64)
+    where
+        Self: Sized + Clone,
+    {
+        let mut power = other;
+        let mut weight = weight;
+        while weight > 0 {
+            if weight & 1 == 1 {
+                self.combine(power.clone());
+            }
+            weight >>= 1;
+            if weight > 0 {
+                power.combine(power.clone());
+            }
+        }
+    }
+
+    /// Like `combine_weighted()`, but returns an error (leaving `self`
+    /// unchanged) rather than panicking if `other` has different parameters.
+    fn try_combine_weighted(&mut self, other: Self, weight: u64) -> Result<(), ParamsMismatch>
+    where
+        Self: Sized + Clone,
+    {
+        self.check_combine(&other)?;
+        self.combine_weighted(other, weight);
+        Ok(())
+    }
+}
+
+/// `weight` copies of `value` added together (i.e., scalar multiplication by
+/// an integer), by double-and-add.
+fn times<G: Group + Clone>(value: G, weight: u64) -> G {
+    let mut acc = G::zero();
+    let mut power = value;
+    let mut weight = weight;
+    while weight > 0 {
+        if weight & 1 == 1 {
+            acc = acc + power.clone();
+        }
+        weight >>= 1;
+        if weight > 0 {
+            power = power.clone() + power;
+        }
+    }
+    acc
 }
 
 /// Check the accumulatable properties.
@@ -85,7 +195,43 @@ macro_rules! check_accumulatable {
                     acc.combine(empty.clone());
                     prop_assert_eq!(acc, a.clone());
                 }
+
+                #[test]
+                fn test_try_combine(values in values_with_same_params(2)) {
+                    let (mut a, b) = (values[0].clone(), values[1].clone());
+                    let mut expected = a.clone();
+                    expected.combine(b.clone());
+                    prop_assert_eq!(a.try_combine(b), Ok(()));
+                    prop_assert_eq!(a, expected);
+                }
+
+                #[test]
+                fn test_combine_weighted(values in values_with_same_params(2), weight in 0..10u64) {
+                    let (mut a, b) = (values[0].clone(), values[1].clone());
+                    let mut expected = a.clone();
+                    for _ in 0..weight {
+                        expected.combine(b.clone());
+                    }
+                    a.combine_weighted(b, weight);
+                    prop_assert_eq!(a, expected);
+                }
+
+                #[test]
+                fn test_try_combine_weighted_mismatch(a: $type, b: $type, weight: u64) {
+                    prop_assume!(a.params() != b.params());
+                    let mut c = a.clone();
+                    prop_assert!(c.try_combine_weighted(b, weight).is_err());
+                    prop_assert_eq!(c, a, "failed combine should leave value unchanged");
+                }
+
+                #[test]
+                fn test_try_combine_mismatch(a: $type, b: $type) {
+                    prop_assume!(a.params() != b.params());
+                    let mut c = a.clone();
+                    prop_assert!(c.try_combine(b).is_err());
+                    prop_assert_eq!(c, a, "failed combine should leave value unchanged");
                 }
+            }
         }
     };
 }
@@ -97,6 +243,17 @@ impl Accumulatable for Bytes {
         *self ^= &other;
     }
 
+    fn try_combine(&mut self, other: Bytes) -> Result<(), ParamsMismatch> {
+        Ok(self.checked_xor_assign(&other)?)
+    }
+
+    /// XOR is its own inverse, so only the parity of `weight` matters.
+    fn combine_weighted(&mut self, other: Bytes, weight: u64) {
+        if weight % 2 == 1 {
+            self.combine(other);
+        }
+    }
+
     fn empty(length: usize) -> Self {
         Bytes::empty(length)
     }
@@ -110,6 +267,16 @@ impl Accumulatable for Bytes {
 mod bytes {
     use super::*;
     check_accumulatable!(Bytes);
+
+    #[test]
+    fn test_combine_weighted_parity() {
+        let data = Bytes::from(vec![1, 2, 3]);
+        let mut acc = Bytes::empty(3);
+        acc.combine_weighted(data.clone(), 2);
+        assert_eq!(acc, Bytes::empty(3));
+        acc.combine_weighted(data.clone(), 1001);
+        assert_eq!(acc, data);
+    }
 }
 
 impl<G> Accumulatable for ElementVector<G>
@@ -121,6 +288,12 @@ where
     fn combine(&mut self, other: Self) {
         *self ^= other;
     }
+
+    /// Element-wise scalar multiplication by `weight`.
+    fn combine_weighted(&mut self, other: Self, weight: u64) {
+        *self ^= ElementVector(other.0.into_iter().map(|g| times(g, weight)).collect());
+    }
+
     fn empty(length: Option<usize>) -> Self {
         Self(vec![G::zero(); length.unwrap_or(1)])
     }
@@ -134,7 +307,22 @@ where
 mod element_vector {
     use super::*;
     use spectrum_primitives::IntsModP;
+    use std::convert::TryFrom;
     check_accumulatable!(ElementVector<IntsModP>);
+
+    #[test]
+    fn test_combine_weighted_order() {
+        // Multiplying by the group order gives zero.
+        let value = ElementVector(vec![
+            IntsModP::try_from(3u8).unwrap(),
+            IntsModP::try_from(5u8).unwrap(),
+        ]);
+        let mut acc = ElementVector::<IntsModP>::empty(Some(2));
+        acc.combine_weighted(value.clone(), 11);
+        assert_eq!(acc, ElementVector::empty(Some(2)));
+        acc.combine_weighted(value.clone(), 12);
+        assert_eq!(acc, value);
+    }
 }
 
 impl<T> Accumulatable for Vec<T>
@@ -145,7 +333,7 @@ where
 
     fn combine(&mut self, other: Vec<T>) {
         assert_eq!(self.len(), other.len());
-        for (this, that) in self.iter_mut().zip(other.into_iter()) {
+        for (this, that) in self.iter_mut().zip(other) {
             this.combine(that);
         }
     }
@@ -157,4 +345,15 @@ where
     fn params(&self) -> Self::Parameters {
         (self.len(), self[0].params())
     }
+
+    // Check every element (not just the first, like `params()`).
+    fn check_combine(&self, other: &Vec<T>) -> Result<(), ParamsMismatch> {
+        if self.len() != other.len() {
+            return Err(ParamsMismatch::new(self.len(), other.len()));
+        }
+        for (this, that) in self.iter().zip(other.iter()) {
+            this.check_combine(that)?;
+        }
+        Ok(())
+    }
 }
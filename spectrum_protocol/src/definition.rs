@@ -1,4 +1,4 @@
-use crate::Accumulatable;
+use crate::{Accumulatable, ParamsMismatch};
 
 pub trait Protocol {
     type ChannelKey;
@@ -9,7 +9,12 @@ pub trait Protocol {
     // General protocol properties
     fn num_parties(&self) -> usize;
     fn num_channels(&self) -> usize;
-    fn message_len(&self) -> usize;
+    /// The size of each channel's slot, in the accumulator's units (bytes, or
+    /// group elements for the multi-key protocol).
+    fn slot_size(&self) -> usize;
+    /// How many bytes of payload fit in a slot: the most a broadcaster can
+    /// send, and the length of each channel recovered as `Bytes`.
+    fn payload_capacity(&self) -> usize;
 
     // Client algorithms
     fn broadcast(
@@ -21,6 +26,13 @@ pub trait Protocol {
     fn cover(&self) -> Vec<Self::WriteToken>;
 
     // Server algorithms
+    /// Check that `token` fits this protocol's parameters, so that auditing
+    /// and accumulating it won't panic.
+    ///
+    /// Use this for write tokens from the network, before anything else.
+    fn check_write_token(&self, _token: &Self::WriteToken) -> Result<(), ParamsMismatch> {
+        Ok(())
+    }
     fn gen_audit(
         &self,
         keys: &[Self::ChannelKey],
@@ -28,196 +40,166 @@ pub trait Protocol {
     ) -> Vec<Self::AuditShare>;
     fn check_audit(&self, tokens: Vec<Self::AuditShare>) -> bool;
 
+    /// Check many clients' audit shares at once, giving one result per client
+    /// (in order).
+    ///
+    /// Equivalent to calling `check_audit()` on each; implementations may
+    /// batch the work.
+    fn check_audit_batch(&self, token_sets: Vec<Vec<Self::AuditShare>>) -> Vec<bool> {
+        token_sets
+            .into_iter()
+            .map(|tokens| self.check_audit(tokens))
+            .collect()
+    }
+
     fn new_accumulator(&self) -> Vec<Self::Accumulator>;
 
     fn to_accumulator(&self, token: Self::WriteToken) -> Vec<Self::Accumulator>;
+
+    /// Combine the write token into `accumulator` directly.
+    ///
+    /// Equivalent to `accumulator.combine(self.to_accumulator(token))`;
+    /// implementations may skip materializing the intermediate value.
+    fn accumulate_into(&self, accumulator: &mut [Self::Accumulator], token: Self::WriteToken) {
+        let values = self.to_accumulator(token);
+        assert_eq!(accumulator.len(), values.len());
+        for (acc, value) in accumulator.iter_mut().zip(values) {
+            acc.combine(value);
+        }
+    }
+
+    /// Like `accumulate_into()`, but returns an error (leaving `accumulator`
+    /// unchanged) rather than panicking if `token` doesn't fit.
+    ///
+    /// Use this for write tokens from the network.
+    fn try_accumulate_into(
+        &self,
+        accumulator: &mut [Self::Accumulator],
+        token: Self::WriteToken,
+    ) -> Result<(), ParamsMismatch> {
+        let values = self.to_accumulator(token);
+        if accumulator.len() != values.len() {
+            return Err(ParamsMismatch::new(accumulator.len(), values.len()));
+        }
+        for (acc, value) in accumulator.iter().zip(values.iter()) {
+            acc.check_combine(value)?;
+        }
+        for (acc, value) in accumulator.iter_mut().zip(values) {
+            acc.combine(value);
+        }
+        Ok(())
+    }
 }
 
-/// Checks correctness of protocol implementation.
+/// Checks correctness of protocol implementation (see
+/// [`ProtocolTester`](crate::testing::ProtocolTester)).
 ///
-/// Also, if feature `proto` is enable, tests roundtrips to/from proto format.
-#[cfg(test)]
+/// The protocol type must implement `proptest::Arbitrary` (as must its
+/// channel keys and accumulator), and the calling crate needs `proptest`.
+#[cfg(any(test, feature = "testing"))]
+#[macro_export]
 macro_rules! check_protocol {
     ($type:ty) => {
         mod protocol {
             #![allow(unused_imports)]
             use super::*;
-            use crate::Accumulatable;
-            use crate::Protocol;
             use proptest::prelude::*;
+            use $crate::testing::ProtocolTester;
 
-            /// Returns a vector of (the vector of audit tokens that each server receives) in the protocol.
-            ///
-            /// That is, both the outer and inner vectors have length `protocol.num_parties()`.
-            fn get_server_shares(
-                protocol: &$type,
-                tokens: Vec<<$type as Protocol>::WriteToken>,
-                keys: Vec<<$type as Protocol>::ChannelKey>,
-            ) -> Vec<Vec<<$type as Protocol>::AuditShare>>
-            {
-                let mut server_shares = vec![Vec::new(); protocol.num_parties()];
-                for token in tokens {
-                    for (idx, share) in protocol.gen_audit(&keys, token).into_iter().enumerate() {
-                        server_shares[idx].push(share);
-                    }
-                }
-                server_shares
-            }
-
-            fn protocol_with_keys() -> impl Strategy<Value=($type, Vec<<$type as Protocol>::ChannelKey>)> {
-                use proptest::collection::vec;
-                any::<$type>().prop_flat_map(|protocol| {
-                    (
-                        Just(protocol.clone()),
-                        vec(any::<<$type as Protocol>::ChannelKey>(), protocol.num_channels()),
-                    )
-                })
-            }
-
-            fn protocol_with_keys_msg() -> impl Strategy<Value=($type, Vec<<$type as Protocol>::ChannelKey>, <$type as Protocol>::Accumulator)> {
-                use proptest::collection::vec;
-                protocol_with_keys().prop_flat_map(|(protocol, keys)| {
-                    let length = protocol.message_len();
-                    (Just(protocol), Just(keys), any_with::<<$type as Protocol>::Accumulator>(length.into()))
-                })
-            }
-
-            fn protocol_with_keys_msg_bad_key() -> impl Strategy<Value=($type, Vec<<$type as Protocol>::ChannelKey>, <$type as Protocol>::Accumulator, <$type as Protocol>::ChannelKey)> {
-                use prop::sample::Index;
-                (protocol_with_keys_msg(), any::<Index>()).prop_flat_map(|((protocol, good_keys, msg), idx)| {
-                    let idx = idx.index(protocol.num_channels());
-                    let good_key = good_keys[idx].clone();
-                    let bad_key = any::<<$type as Protocol>::ChannelKey>()
-                        .prop_filter("must be different", move |key| key != &good_key);
-                    (Just(protocol), Just(good_keys), Just(msg), bad_key)
-                })
-            }
-
-            fn protocol_with_accumulator() -> impl Strategy<Value=($type, Vec<<$type as Protocol>::Accumulator>)>{
-                use prop::collection::vec;
-                any::<$type>().prop_flat_map(move |protocol| {
-                    let channels = protocol.num_channels();
-                    let empty = protocol.new_accumulator();
-                    let params = empty[0].params();
-                    (Just(protocol), vec(any_with::<<$type as Protocol>::Accumulator>(params.into()), channels))
-                })
-            }
+            type Tester = ProtocolTester<$type>;
 
             proptest! {
                 #[test]
-                fn test_cover_complete((protocol, keys) in protocol_with_keys()) {
-                    let tokens = protocol.cover();
-                    prop_assert_eq!(tokens.len(), protocol.num_parties(), "cover should give one message per party");
-
-                    for shares in get_server_shares(&protocol, tokens, keys) {
-                        prop_assert!(protocol.check_audit(shares), "audit should pass");
-                    }
+                fn test_cover_complete(tester in Tester::strategy()) {
+                    tester.check_cover_complete()?;
                 }
 
                 #[test]
                 fn test_broadcast_complete(
-                    (protocol, keys, msg) in protocol_with_keys_msg(),
+                    (tester, msg) in Tester::with_message(),
                     idx: prop::sample::Index,
-                )
-                {
-                    let idx = idx.index(keys.len());
-                    let good_key = keys[idx].clone();
-                    let tokens = protocol.broadcast(msg, idx, good_key);
-                    prop_assert_eq!(tokens.len(), protocol.num_parties(), "broadcast should give one message per party");
-
-                    for shares in get_server_shares(&protocol, tokens, keys) {
-                        prop_assert!(protocol.check_audit(shares), "audit should pass");
-                    }
+                ) {
+                    let idx = idx.index(tester.keys().len());
+                    tester.check_broadcast_complete(msg, idx)?;
                 }
 
                 #[test]
                 fn test_broadcast_soundness(
-                    (protocol, good_keys, msg, bad_key) in protocol_with_keys_msg_bad_key(),
+                    (tester, msg, bad_key) in Tester::with_message_bad_key(),
+                    idx: prop::sample::Index,
+                ) {
+                    let idx = idx.index(tester.keys().len());
+                    tester.check_broadcast_sound(msg, idx, bad_key)?;
+                }
+
+                #[test]
+                fn test_audit_batch(
+                    (tester, msg, bad_key) in Tester::with_message_bad_key(),
+                    idx: prop::sample::Index,
+                ) {
+                    let idx = idx.index(tester.keys().len());
+                    tester.check_audit_batch(msg, idx, bad_key)?;
+                }
+
+                /// Tests that accumulating in place matches `to_accumulator()`.
+                #[test]
+                fn test_accumulate_into(
+                    (tester, msg) in Tester::with_message(),
                     idx: prop::sample::Index,
-                )
-                {
-                    let idx = idx.index(good_keys.len());
-                    prop_assume!(!good_keys.contains(&bad_key));
-                    let tokens = protocol.broadcast(msg, idx, bad_key);
-                    prop_assert_eq!(tokens.len(), protocol.num_parties());
-
-                    for shares in get_server_shares(&protocol, tokens, good_keys) {
-                        prop_assert!(!protocol.check_audit(shares), "audit should fail");
-                    }
+                ) {
+                    let idx = idx.index(tester.keys().len());
+                    tester.check_accumulate_into(msg, idx)?;
                 }
 
                 /// Tests that cover messages do not change the accumulator value.
                 #[test]
-                fn test_cover_correct((protocol, mut accumulator) in protocol_with_accumulator()) {
-                    let expected = accumulator.clone();
-                    prop_assert_eq!(accumulator.len(), protocol.num_channels());
-                    for write_token in protocol.cover() {
-                        accumulator.combine(protocol.to_accumulator(write_token));
-                    }
-                    prop_assert_eq!(accumulator, expected);
+                fn test_cover_correct((tester, accumulator) in Tester::with_accumulator()) {
+                    tester.check_cover_correct(accumulator)?;
                 }
 
                 #[test]
                 fn test_broadcast_correct(
-                    (protocol, keys, msg) in protocol_with_keys_msg(),
-                    key_idx: prop::sample::Index,
-                )
-                {
-                    let key_idx = key_idx.index(keys.len());
-                    let good_key = keys[key_idx].clone();
-
-                    let mut accumulator = protocol.new_accumulator();
-                    for write_token in protocol.broadcast(msg.clone(), key_idx, good_key) {
-                        accumulator.combine(protocol.to_accumulator(write_token));
-                    }
-
-                    let recovered_msgs = accumulator;
-                    prop_assert_eq!(recovered_msgs.len(), protocol.num_channels(), "wrong accumulator size");
-                    for (msg_idx, actual_msg) in recovered_msgs.into_iter().enumerate() {
-                        if msg_idx == key_idx {
-                            prop_assert_eq!(
-                                actual_msg.into(): <$type as Protocol>::Accumulator,
-                                msg.clone(),
-                                "Channel was incorrect"
-                            );
-                        } else {
-                            prop_assert_eq!(
-                                actual_msg.into(): <$type as Protocol>::Accumulator,
-                                <$type as Protocol>::Accumulator::empty(protocol.message_len().into()),
-                                "Channel was non-null"
-                            )
-                        }
-                    }
+                    (tester, msg) in Tester::with_message(),
+                    idx: prop::sample::Index,
+                ) {
+                    let idx = idx.index(tester.keys().len());
+                    tester.check_broadcast_recovers_message(msg, idx)?;
                 }
             }
-            #[cfg(feature = "proto")]
-            mod proto {
-                use super::*;
-                use crate::proto::{AuditShare, Share, WriteToken};
-                use std::convert::TryFrom;
-                use crate::Protocol;
-                use spectrum_primitives::check_roundtrip;
-                check_roundtrip!(
-                    <$type as Protocol>::WriteToken,
-                    WriteToken::from,
-                    |p| <$type as Protocol>::WriteToken::try_from(p).unwrap(),
-                    write_token_rt
-                );
-
-                check_roundtrip!(
-                    <$type as Protocol>::AuditShare,
-                    AuditShare::from,
-                    |p| <$type as Protocol>::AuditShare::try_from(p).unwrap(),
-                    audit_share_rt
-                );
-
-                check_roundtrip!(
-                    Vec::<<$type as Protocol>::Accumulator>,
-                    Share::from,
-                    |p| Vec::<<$type as Protocol>::Accumulator>::try_from(p).unwrap(),
-                    share_rt
-                );
-            }
+        }
+    };
+}
+
+/// Tests roundtrips of the protocol types to/from proto format.
+#[cfg(all(test, feature = "proto"))]
+macro_rules! check_protocol_proto {
+    ($type:ty) => {
+        mod proto {
+            use super::*;
+            use crate::proto::{AuditShare, Share, WriteToken};
+            use crate::Protocol;
+            use spectrum_primitives::check_roundtrip;
+            use std::convert::TryFrom;
+            check_roundtrip!(
+                <$type as Protocol>::WriteToken,
+                WriteToken::from,
+                |p| <$type as Protocol>::WriteToken::try_from(p).unwrap(),
+                write_token_rt
+            );
+
+            check_roundtrip!(
+                <$type as Protocol>::AuditShare,
+                AuditShare::from,
+                |p| <$type as Protocol>::AuditShare::try_from(p).unwrap(),
+                audit_share_rt
+            );
+
+            check_roundtrip!(
+                Vec::<<$type as Protocol>::Accumulator>,
+                Share::from,
+                |p| Vec::<<$type as Protocol>::Accumulator>::try_from(p).unwrap(),
+                share_rt
+            );
         }
     };
 }
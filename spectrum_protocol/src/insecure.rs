@@ -0,0 +1,171 @@
+//! A fast, insecure protocol, for measuring everything but the cryptography.
+//!
+//! The first group's write token carries the message itself (and its
+//! channel); every other group's token is empty, so XORing the groups'
+//! accumulators together recovers the channels. Audits don't check anything:
+//! anyone can write to any channel, and the servers see every message. Use it
+//! to isolate network and serialization overhead in benchmarks, and nothing
+//! else.
+use crate::{accumulator::Accumulatable, ParamsMismatch, Protocol};
+
+use serde::{Deserialize, Serialize};
+use spectrum_primitives::Bytes;
+
+#[cfg(any(test, feature = "testing"))]
+use proptest::prelude::*;
+#[cfg(any(test, feature = "testing"))]
+use proptest_derive::Arbitrary;
+
+#[cfg(feature = "proto")]
+mod proto;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Wrapper {
+    parties: usize,
+    channels: usize,
+    msg_size: usize,
+}
+
+impl Wrapper {
+    pub fn new(parties: usize, channels: usize, msg_size: usize) -> Self {
+        assert!(parties >= 1, "Expected at least 1 party.");
+        assert!(channels >= 1, "Expected at least 1 channel.");
+        Wrapper {
+            parties,
+            channels,
+            msg_size,
+        }
+    }
+}
+
+/// A message and its channel, or nothing (cover traffic, and every group but
+/// the first).
+#[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteToken(Option<(Bytes, usize)>);
+
+impl WriteToken {
+    pub fn new(write: Option<(Bytes, usize)>) -> Self {
+        WriteToken(write)
+    }
+
+    fn empty() -> Self {
+        WriteToken(None)
+    }
+}
+
+/// Audits are no-ops, so there's nothing to share.
+#[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditShare;
+
+impl Protocol for Wrapper {
+    /// Unused (audits always pass), but experiments still hand out a key per
+    /// channel.
+    type ChannelKey = String;
+    type WriteToken = WriteToken;
+    type AuditShare = AuditShare;
+    type Accumulator = Bytes;
+
+    fn num_parties(&self) -> usize {
+        self.parties
+    }
+
+    fn num_channels(&self) -> usize {
+        self.channels
+    }
+
+    fn slot_size(&self) -> usize {
+        self.msg_size
+    }
+
+    fn payload_capacity(&self) -> usize {
+        self.msg_size
+    }
+
+    fn broadcast(
+        &self,
+        message: Self::Accumulator,
+        idx: usize,
+        _key: Self::ChannelKey,
+    ) -> Vec<Self::WriteToken> {
+        assert!(idx < self.channels, "no such channel!");
+        let mut tokens = self.cover();
+        tokens[0] = WriteToken(Some((message, idx)));
+        tokens
+    }
+
+    fn cover(&self) -> Vec<Self::WriteToken> {
+        vec![WriteToken::empty(); self.parties]
+    }
+
+    fn check_write_token(&self, token: &Self::WriteToken) -> Result<(), ParamsMismatch> {
+        if let WriteToken(Some((message, idx))) = token {
+            if *idx >= self.channels {
+                return Err(ParamsMismatch::new(
+                    format!("a channel below {}", self.channels),
+                    format!("channel {}", idx),
+                ));
+            }
+            Bytes::empty(self.msg_size).check_combine(message)?;
+        }
+        Ok(())
+    }
+
+    fn gen_audit(
+        &self,
+        _keys: &[Self::ChannelKey],
+        _token: Self::WriteToken,
+    ) -> Vec<Self::AuditShare> {
+        vec![AuditShare; self.parties]
+    }
+
+    fn check_audit(&self, tokens: Vec<Self::AuditShare>) -> bool {
+        assert_eq!(tokens.len(), self.num_parties());
+        true
+    }
+
+    fn new_accumulator(&self) -> Vec<Self::Accumulator> {
+        vec![Bytes::empty(self.slot_size()); self.channels]
+    }
+
+    fn to_accumulator(&self, token: Self::WriteToken) -> Vec<Self::Accumulator> {
+        let mut accumulator = self.new_accumulator();
+        self.accumulate_into(&mut accumulator, token);
+        accumulator
+    }
+
+    fn accumulate_into(&self, accumulator: &mut [Self::Accumulator], token: Self::WriteToken) {
+        if let WriteToken(Some((message, idx))) = token {
+            accumulator[idx].combine(message);
+        }
+    }
+
+    fn try_accumulate_into(
+        &self,
+        accumulator: &mut [Self::Accumulator],
+        token: Self::WriteToken,
+    ) -> Result<(), ParamsMismatch> {
+        if accumulator.len() != self.channels {
+            return Err(ParamsMismatch::new(self.channels, accumulator.len()));
+        }
+        self.check_write_token(&token)?;
+        if let WriteToken(Some((message, idx))) = &token {
+            accumulator[*idx].check_combine(message)?;
+        }
+        self.accumulate_into(accumulator, token);
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Arbitrary for Wrapper {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (1..5usize, 1..10usize, 1..100usize)
+            .prop_map(|(parties, channels, msg_size)| Wrapper::new(parties, channels, msg_size))
+            .boxed()
+    }
+}
@@ -0,0 +1,57 @@
+//! Conversions between the insecure protocol's types and their protobufs.
+use super::{AuditShare, WriteToken};
+use crate::proto;
+
+use spectrum_primitives::Bytes;
+use std::convert::{TryFrom, TryInto};
+
+impl TryFrom<proto::WriteToken> for WriteToken {
+    type Error = &'static str;
+
+    fn try_from(value: proto::WriteToken) -> Result<Self, Self::Error> {
+        match value.inner.ok_or("no inner")? {
+            proto::write_token::Inner::Insecure(token) => match token.write {
+                Some(write) => {
+                    let channel = write.channel.try_into().map_err(|_| "bad channel")?;
+                    Ok(WriteToken::new(Some((Bytes::from(write.data), channel))))
+                }
+                None => Ok(WriteToken::new(None)),
+            },
+            _ => Err("wrong type"),
+        }
+    }
+}
+
+impl From<WriteToken> for proto::WriteToken {
+    fn from(value: WriteToken) -> Self {
+        let write = value
+            .0
+            .map(|(data, channel)| proto::insecure_write_token::Write {
+                data: data.into(),
+                channel: channel as u64,
+            });
+        let token = proto::InsecureWriteToken { write };
+        let inner = Some(proto::write_token::Inner::Insecure(token));
+        proto::WriteToken { inner }
+    }
+}
+
+impl TryFrom<proto::AuditShare> for AuditShare {
+    type Error = &'static str;
+
+    fn try_from(value: proto::AuditShare) -> Result<Self, Self::Error> {
+        match value.inner.ok_or("no enum")? {
+            proto::audit_share::Inner::Insecure(_) => Ok(AuditShare),
+            _ => Err("wrong type"),
+        }
+    }
+}
+
+impl From<AuditShare> for proto::AuditShare {
+    fn from(_: AuditShare) -> Self {
+        let inner = Some(proto::audit_share::Inner::Insecure(
+            proto::InsecureAuditShare {},
+        ));
+        proto::AuditShare { inner }
+    }
+}
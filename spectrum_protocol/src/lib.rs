@@ -1,14 +1,22 @@
-#![feature(type_ascription)]
+#![allow(non_local_definitions)] // proptest-derive's `Arbitrary` impls
+
 mod accumulator;
 
 #[macro_use]
 mod definition;
 
+pub mod insecure;
+pub mod reservation;
 pub mod secure;
+pub mod typed;
 pub mod wrapper;
 
-pub use accumulator::Accumulatable;
+pub use accumulator::{Accumulatable, ParamsMismatch};
 pub use definition::Protocol;
+pub use typed::TypedProtocol;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,440 @@
+//! Slot reservations: a cheap round to claim part of a channel before the
+//! payload round.
+//!
+//! Several broadcasters can share a channel (and its key), but writes to a
+//! channel combine, so if two of them write in the same round both messages
+//! are lost. In the two-phase variant, a reservation round comes first. Its
+//! messages are small: one entry per slot. Each broadcaster picks a slot at
+//! random and writes a random nonce (plus a check value) into that slot's
+//! entry. If nobody else picked the slot, the recovered entry is the
+//! broadcaster's nonce, and the slot is theirs; if somebody else did, the
+//! entries combine into something that fails its check, and everybody who
+//! picked the slot backs off.
+//!
+//! In the payload round, each channel's message is split into equal-size
+//! slots, and broadcasters write only within the slot they reserved (zeros
+//! elsewhere), so their writes don't interfere.
+//!
+//! Only for the byte-oriented protocols, whose writes combine by XOR.
+use crate::{Accumulatable, ParamsMismatch};
+
+use serde::{Deserialize, Serialize};
+use spectrum_primitives::Bytes;
+
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::Range;
+
+/// Size (in bytes) of the nonce in each reservation entry.
+pub const NONCE_LEN: usize = 8;
+/// Size (in bytes) of the check value in each reservation entry.
+pub const CHECK_LEN: usize = 8;
+/// Size (in bytes) of each slot's entry in a reservation-round message.
+pub const ENTRY_LEN: usize = NONCE_LEN + CHECK_LEN;
+
+pub type Nonce = [u8; NONCE_LEN];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// There's no such slot.
+    BadSlot { slot: usize, slots: usize },
+    /// The message doesn't fit in a slot.
+    TooLong { len: usize, max: usize },
+    /// The recovered reservations don't match the reservation parameters.
+    Malformed(String),
+    /// The protocol can't carry arbitrary bytes.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadSlot { slot, slots } => {
+                write!(f, "no slot {} (channels have {} slots)", slot, slots)
+            }
+            Error::TooLong { len, max } => write!(
+                f,
+                "message too long: {} bytes, at most {} fit in a slot",
+                len, max
+            ),
+            Error::Malformed(msg) => write!(f, "malformed reservations: {}", msg),
+            Error::Unsupported(protocol) => {
+                write!(
+                    f,
+                    "slot reservations not supported by {} protocol",
+                    protocol
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn check(nonce: &Nonce) -> [u8; CHECK_LEN] {
+    blake3::hash(nonce).as_bytes()[..CHECK_LEN]
+        .try_into()
+        .unwrap()
+}
+
+/// How many slots each channel is split into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotReservation {
+    slots: usize,
+}
+
+impl SlotReservation {
+    pub fn new(slots: usize) -> Self {
+        assert!(slots >= 1, "Expected at least 1 slot per channel.");
+        SlotReservation { slots }
+    }
+
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+
+    /// The message size (in bytes) for the reservation round.
+    pub fn message_len(&self) -> usize {
+        self.slots * ENTRY_LEN
+    }
+
+    fn check_slot(&self, slot: usize) -> Result<(), Error> {
+        if slot >= self.slots {
+            return Err(Error::BadSlot {
+                slot,
+                slots: self.slots,
+            });
+        }
+        Ok(())
+    }
+
+    /// The reservation-round message claiming `slot` with `nonce`.
+    pub fn reserve(&self, slot: usize, nonce: Nonce) -> Result<Bytes, Error> {
+        self.check_slot(slot)?;
+        let mut msg = vec![0u8; self.message_len()];
+        let entry = &mut msg[slot * ENTRY_LEN..(slot + 1) * ENTRY_LEN];
+        entry[..NONCE_LEN].copy_from_slice(&nonce);
+        entry[NONCE_LEN..].copy_from_slice(&check(&nonce));
+        Ok(msg.into())
+    }
+
+    /// Size (in bytes) of each slot in a payload message of `payload_len`
+    /// bytes. Any remainder at the end goes unused.
+    pub fn slot_len(&self, payload_len: usize) -> usize {
+        payload_len / self.slots
+    }
+
+    /// Where `slot` is in a payload message of `payload_len` bytes.
+    pub fn slot_range(&self, payload_len: usize, slot: usize) -> Result<Range<usize>, Error> {
+        self.check_slot(slot)?;
+        let len = self.slot_len(payload_len);
+        Ok(slot * len..(slot + 1) * len)
+    }
+
+    /// The payload-round message writing `msg` into `slot` (zeros elsewhere).
+    pub fn place(&self, payload_len: usize, slot: usize, msg: &[u8]) -> Result<Bytes, Error> {
+        let range = self.slot_range(payload_len, slot)?;
+        if msg.len() > range.len() {
+            return Err(Error::TooLong {
+                len: msg.len(),
+                max: range.len(),
+            });
+        }
+        let mut payload = vec![0u8; payload_len];
+        payload[range.start..range.start + msg.len()].copy_from_slice(msg);
+        Ok(payload.into())
+    }
+}
+
+/// What the reservation round says about a slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlotStatus {
+    /// Nobody reserved the slot.
+    Free,
+    /// Exactly one broadcaster reserved the slot (as far as we can tell),
+    /// with this nonce.
+    Reserved(Nonce),
+    /// Several broadcasters picked the slot; none of them gets it.
+    Collided,
+}
+
+fn entry_status(entry: &[u8]) -> SlotStatus {
+    if entry.iter().all(|b| *b == 0) {
+        return SlotStatus::Free;
+    }
+    let (nonce, value) = entry.split_at(NONCE_LEN);
+    let nonce: Nonce = nonce.try_into().unwrap();
+    if value == check(&nonce) {
+        SlotStatus::Reserved(nonce)
+    } else {
+        SlotStatus::Collided
+    }
+}
+
+/// A reserved slot, and where it goes in the payload round.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotAssignment {
+    pub channel: usize,
+    pub slot: usize,
+    /// The nonce the slot was reserved with, so its broadcaster can spot it.
+    pub nonce: Nonce,
+    /// Byte offset of the slot in the channel's payload message.
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// The reservation round's contents, channel by channel.
+///
+/// Combines like the round's own accumulator, so it can be built up from
+/// shares or from the recovered channels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReservationAccumulator {
+    reservation: SlotReservation,
+    channels: Vec<Bytes>,
+}
+
+impl ReservationAccumulator {
+    /// From the recovered channels of a reservation round.
+    pub fn from_recovered(
+        reservation: SlotReservation,
+        recovered: Vec<Bytes>,
+    ) -> Result<Self, Error> {
+        for (channel, contents) in recovered.iter().enumerate() {
+            if contents.len() != reservation.message_len() {
+                return Err(Error::Malformed(format!(
+                    "channel {} has {} bytes, expected {}",
+                    channel,
+                    contents.len(),
+                    reservation.message_len()
+                )));
+            }
+        }
+        Ok(ReservationAccumulator {
+            reservation,
+            channels: recovered,
+        })
+    }
+
+    /// Each channel's slots.
+    pub fn statuses(&self) -> Vec<Vec<SlotStatus>> {
+        self.channels
+            .iter()
+            .map(|contents| {
+                contents
+                    .chunks_exact(ENTRY_LEN)
+                    .expect("length checked on construction")
+                    .map(entry_status)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// How many slots had colliding reservations.
+    pub fn collisions(&self) -> usize {
+        self.statuses()
+            .iter()
+            .flatten()
+            .filter(|status| **status == SlotStatus::Collided)
+            .count()
+    }
+
+    /// Map each reserved slot to its place in a payload message of
+    /// `payload_len` bytes.
+    pub fn assign(&self, payload_len: usize) -> Vec<SlotAssignment> {
+        let len = self.reservation.slot_len(payload_len);
+        let mut assignments = vec![];
+        for (channel, statuses) in self.statuses().into_iter().enumerate() {
+            for (slot, status) in statuses.into_iter().enumerate() {
+                if let SlotStatus::Reserved(nonce) = status {
+                    assignments.push(SlotAssignment {
+                        channel,
+                        slot,
+                        nonce,
+                        offset: slot * len,
+                        len,
+                    });
+                }
+            }
+        }
+        assignments
+    }
+}
+
+impl Accumulatable for ReservationAccumulator {
+    /// The reservation parameters, and how many channels.
+    type Parameters = (SlotReservation, usize);
+
+    fn combine(&mut self, other: Self) {
+        assert_eq!(self.reservation, other.reservation);
+        self.channels.combine(other.channels);
+    }
+
+    fn empty((reservation, channels): Self::Parameters) -> Self {
+        ReservationAccumulator {
+            reservation,
+            channels: Vec::empty((channels, reservation.message_len())),
+        }
+    }
+
+    fn params(&self) -> Self::Parameters {
+        (self.reservation, self.channels.len())
+    }
+
+    fn check_combine(&self, other: &Self) -> Result<(), ParamsMismatch> {
+        if self.reservation != other.reservation {
+            return Err(ParamsMismatch::new(self.params(), other.params()));
+        }
+        self.channels.check_combine(&other.channels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{secure::Wrapper, Protocol};
+    use proptest::prelude::*;
+    use spectrum_primitives::{AuthKey, Sampleable, TwoKeyVdpf};
+
+    fn reservations() -> impl Strategy<Value = SlotReservation> {
+        (1..8usize).prop_map(SlotReservation::new)
+    }
+
+    proptest! {
+        #[test]
+        fn test_lone_reservation(
+            reservation in reservations(),
+            nonce: Nonce,
+            slot: prop::sample::Index,
+        ) {
+            let slot = slot.index(reservation.slots());
+            let msg = reservation.reserve(slot, nonce).unwrap();
+            prop_assert_eq!(msg.len(), reservation.message_len());
+
+            let acc = ReservationAccumulator::from_recovered(reservation, vec![msg]).unwrap();
+            let mut expected = vec![SlotStatus::Free; reservation.slots()];
+            expected[slot] = SlotStatus::Reserved(nonce);
+            prop_assert_eq!(acc.statuses(), vec![expected]);
+        }
+
+        #[test]
+        fn test_collision(
+            reservation in reservations(),
+            nonce1: Nonce,
+            nonce2: Nonce,
+            slot: prop::sample::Index,
+        ) {
+            prop_assume!(nonce1 != nonce2);
+            let slot = slot.index(reservation.slots());
+            let mut acc = ReservationAccumulator::empty((reservation, 1));
+            for nonce in &[nonce1, nonce2] {
+                let msg = reservation.reserve(slot, *nonce).unwrap();
+                let other = ReservationAccumulator::from_recovered(reservation, vec![msg]);
+                acc.combine(other.unwrap());
+            }
+            prop_assert_eq!(acc.statuses()[0][slot], SlotStatus::Collided);
+            prop_assert_eq!(acc.collisions(), 1);
+            prop_assert_eq!(acc.assign(1024), vec![]);
+        }
+
+        #[test]
+        fn test_place(
+            reservation in reservations(),
+            payload_len in 0..256usize,
+            slot: prop::sample::Index,
+            msg in prop::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let slot = slot.index(reservation.slots());
+            let range = reservation.slot_range(payload_len, slot).unwrap();
+            match reservation.place(payload_len, slot, &msg) {
+                Ok(payload) => {
+                    prop_assert_eq!(payload.len(), payload_len);
+                    let payload: Vec<u8> = payload.into();
+                    prop_assert_eq!(&payload[range.start..range.start + msg.len()], &msg[..]);
+                    prop_assert!(payload[..range.start].iter().all(|b| *b == 0));
+                    prop_assert!(payload[range.start + msg.len()..].iter().all(|b| *b == 0));
+                }
+                Err(Error::TooLong { .. }) => prop_assert!(msg.len() > range.len()),
+                Err(err) => prop_assert!(false, "unexpected error: {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_bad_slot() {
+        let reservation = SlotReservation::new(4);
+        assert_eq!(
+            reservation.reserve(4, [1; NONCE_LEN]),
+            Err(Error::BadSlot { slot: 4, slots: 4 })
+        );
+        reservation.place(64, 4, &[]).expect_err("no slot 4");
+    }
+
+    #[test]
+    fn test_from_recovered_wrong_len() {
+        let reservation = SlotReservation::new(2);
+        ReservationAccumulator::from_recovered(reservation, vec![Bytes::empty(ENTRY_LEN)])
+            .expect_err("wrong length");
+    }
+
+    #[test]
+    fn test_try_combine_mismatch() {
+        let mut acc = ReservationAccumulator::empty((SlotReservation::new(2), 3));
+        acc.try_combine(ReservationAccumulator::empty((SlotReservation::new(3), 3)))
+            .expect_err("different slots");
+        acc.try_combine(ReservationAccumulator::empty((SlotReservation::new(2), 2)))
+            .expect_err("different channels");
+    }
+
+    // Both rounds, end to end: two broadcasters share channel 1; one
+    // reservation goes through.
+    #[test]
+    fn test_two_rounds() {
+        let channels = 3;
+        let payload_len = 64;
+        let reservation = SlotReservation::new(4);
+        let keys: Vec<AuthKey> = (0..channels).map(|_| AuthKey::sample()).collect();
+        let run = |protocol: &Wrapper<TwoKeyVdpf>, writes: Vec<(usize, Bytes)>| {
+            let mut accumulator = protocol.new_accumulator();
+            for (channel, msg) in writes {
+                for token in protocol.broadcast(msg, channel, keys[channel]) {
+                    accumulator.combine(protocol.to_accumulator(token));
+                }
+            }
+            accumulator
+        };
+
+        let reservation_round: Wrapper<TwoKeyVdpf> =
+            TwoKeyVdpf::with_channels_msg_size(channels, reservation.message_len()).into();
+        let (alice, bob) = ([1; NONCE_LEN], [2; NONCE_LEN]);
+        let recovered = run(
+            &reservation_round,
+            vec![
+                (1, reservation.reserve(0, alice).unwrap()),
+                (1, reservation.reserve(2, bob).unwrap()),
+            ],
+        );
+        let acc = ReservationAccumulator::from_recovered(reservation, recovered).unwrap();
+        let assignments = acc.assign(payload_len);
+        assert_eq!(assignments.len(), 2);
+        assert_eq!(acc.collisions(), 0);
+
+        let payload_round: Wrapper<TwoKeyVdpf> =
+            TwoKeyVdpf::with_channels_msg_size(channels, payload_len).into();
+        let writes = assignments
+            .iter()
+            .map(|assignment| {
+                let msg = if assignment.nonce == alice {
+                    b"alice".as_ref()
+                } else {
+                    b"bob".as_ref()
+                };
+                let payload = reservation.place(payload_len, assignment.slot, msg);
+                (assignment.channel, payload.unwrap())
+            })
+            .collect();
+        let recovered: Vec<u8> = run(&payload_round, writes).remove(1).into();
+        let slot_len = reservation.slot_len(payload_len);
+        assert_eq!(&recovered[..5], b"alice");
+        assert_eq!(&recovered[2 * slot_len..2 * slot_len + 3], b"bob");
+    }
+}
@@ -1,7 +1,7 @@
-use crate::{accumulator::Accumulatable, Protocol};
+use crate::{accumulator::Accumulatable, ParamsMismatch, Protocol};
 
 use serde::{Deserialize, Serialize};
-use spectrum_primitives::{Dpf, Vdpf};
+use spectrum_primitives::{Bytes, Dpf, Vdpf};
 
 use std::fmt;
 use std::iter::repeat;
@@ -9,6 +9,9 @@ use std::iter::repeat;
 #[cfg(any(test, feature = "testing"))]
 use proptest_derive::Arbitrary;
 
+#[cfg(feature = "proto")]
+mod proto;
+
 #[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Wrapper<V> {
@@ -45,12 +48,39 @@ impl<T> AuditShare<T> {
     }
 }
 
+impl<V> Wrapper<V>
+where
+    V: Vdpf,
+{
+    /// The first stage of [`Protocol::broadcast`]: the DPF keys.
+    ///
+    /// Split out (with [`gen_write_tokens`](Self::gen_write_tokens)) so that
+    /// benchmarks can time the stages separately.
+    pub fn gen_dpf_keys(&self, message: <V as Dpf>::Message, idx: usize) -> Vec<<V as Dpf>::Key> {
+        self.vdpf.gen(message, idx)
+    }
+
+    /// The second stage of [`Protocol::broadcast`]: proof shares for
+    /// `dpf_keys`, and the write tokens with both.
+    pub fn gen_write_tokens(
+        &self,
+        key: &<V as Vdpf>::AuthKey,
+        idx: usize,
+        dpf_keys: Vec<<V as Dpf>::Key>,
+    ) -> Vec<WriteToken<<V as Dpf>::Key, <V as Vdpf>::ProofShare>> {
+        let proof_shares = self.vdpf.gen_proofs(key, idx, &dpf_keys);
+        Iterator::zip(dpf_keys.into_iter(), proof_shares)
+            .map(|(k, p)| WriteToken::new(k, p))
+            .collect()
+    }
+}
+
 impl<V> Protocol for Wrapper<V>
 where
     V: Vdpf,
     <V as Vdpf>::Token: Clone,
     <V as Dpf>::Key: fmt::Debug,
-    <V as Dpf>::Message: Accumulatable + Clone,
+    <V as Dpf>::Message: Accumulatable + Clone + Into<Bytes>,
 {
     type ChannelKey = <V as Vdpf>::AuthKey;
     type WriteToken = WriteToken<<V as Dpf>::Key, <V as Vdpf>::ProofShare>;
@@ -67,31 +97,42 @@ where
         self.vdpf.points()
     }
 
-    fn message_len(&self) -> usize {
+    fn slot_size(&self) -> usize {
         self.vdpf.msg_size()
     }
 
+    /// Group elements pad out to a fixed-size encoding each, so this can be
+    /// more than [`slot_size`](Self::slot_size).
+    fn payload_capacity(&self) -> usize {
+        Into::<Bytes>::into(self.vdpf.null_message()).len()
+    }
+
     fn broadcast(
         &self,
         message: Self::Accumulator,
         idx: usize,
         key: Self::ChannelKey,
     ) -> Vec<Self::WriteToken> {
-        let dpf_keys = self.vdpf.gen(message, idx);
-        let proof_shares = self.vdpf.gen_proofs(&key, idx, &dpf_keys);
-        Iterator::zip(dpf_keys.into_iter(), proof_shares.into_iter())
-            .map(|(k, p)| WriteToken::new(k, p))
-            .collect()
+        let dpf_keys = self.gen_dpf_keys(message, idx);
+        self.gen_write_tokens(&key, idx, dpf_keys)
     }
 
     fn cover(&self) -> Vec<Self::WriteToken> {
         let dpf_keys = self.vdpf.gen_empty();
         let proof_shares = self.vdpf.gen_proofs_noop();
-        Iterator::zip(dpf_keys.into_iter(), proof_shares.into_iter())
+        Iterator::zip(dpf_keys.into_iter(), proof_shares)
             .map(|(k, p)| WriteToken::new(k, p))
             .collect()
     }
 
+    fn check_write_token(&self, token: &Self::WriteToken) -> Result<(), ParamsMismatch> {
+        self.vdpf.check_key_shape(&token.key)?;
+        if let Some(msg) = self.vdpf.key_message(&token.key) {
+            self.vdpf.null_message().check_combine(msg)?;
+        }
+        Ok(())
+    }
+
     fn gen_audit(
         &self,
         keys: &[Self::ChannelKey],
@@ -99,7 +140,7 @@ where
     ) -> Vec<Self::AuditShare> {
         let token = self
             .vdpf
-            .gen_audit(&keys, &write_token.key, write_token.proof);
+            .gen_audit(keys, &write_token.key, write_token.proof);
         repeat(token)
             .map(AuditShare::new)
             .take(self.num_parties())
@@ -112,403 +153,41 @@ where
             .check_audit(tokens.into_iter().map(|x| x.token).collect())
     }
 
-    fn new_accumulator(&self) -> Vec<Self::Accumulator> {
-        vec![self.vdpf.null_message(); self.num_channels()]
-    }
-
-    fn to_accumulator(&self, token: Self::WriteToken) -> Vec<Self::Accumulator> {
-        self.vdpf.eval(token.key)
-    }
-}
-
-#[cfg(feature = "proto")]
-use {
-    crate::proto,
-    spectrum_primitives::{
-        ElementVector, MultiKeyKey, MultiKeyProof, MultiKeyToken, TwoKeyKey, TwoKeyProof,
-        TwoKeyPubProof, TwoKeyPubToken, TwoKeyToken,
-    },
-    std::convert::{TryFrom, TryInto},
-};
-
-#[cfg(feature = "proto")]
-impl<M, S> TryFrom<proto::secure_write_token::DpfKey> for TwoKeyKey<M, S>
-where
-    Vec<u8>: Into<M> + TryInto<S>,
-{
-    type Error = &'static str;
-
-    fn try_from(proto: proto::secure_write_token::DpfKey) -> Result<Self, Self::Error> {
-        let msg = proto.encoded_msg.into();
-        let bits = proto
-            .bits
+    fn check_audit_batch(&self, token_sets: Vec<Vec<Self::AuditShare>>) -> Vec<bool> {
+        let token_sets = token_sets
             .into_iter()
-            .map(|bytes| {
-                if bytes.len() != 1 {
-                    return Err(());
-                }
-                match bytes[0] {
-                    0u8 => Ok(false),
-                    1u8 => Ok(true),
-                    _ => Err(()),
-                }
+            .map(|tokens| {
+                assert_eq!(tokens.len(), self.num_parties());
+                tokens.into_iter().map(|x| x.token).collect()
             })
-            .collect::<Result<Vec<bool>, _>>()
-            .map_err(|_| "couldn't convert bits")?;
-        let seeds = proto
-            .seeds
-            .into_iter()
-            .map(TryInto::try_into)
-            .collect::<Result<Vec<S>, _>>()
-            .map_err(|_| "couldn't convert seeds")?;
-        Ok(Self::new(msg, bits, seeds))
+            .collect();
+        self.vdpf.check_audit_batch(token_sets)
     }
-}
 
-#[cfg(feature = "proto")]
-impl<M, S> From<TwoKeyKey<M, S>> for proto::secure_write_token::DpfKey
-where
-    M: Clone + Into<Vec<u8>>,
-    S: Clone + Into<Vec<u8>>,
-{
-    fn from(value: TwoKeyKey<M, S>) -> Self {
-        proto::secure_write_token::DpfKey {
-            encoded_msg: value.msg().into(),
-            bits: value
-                .bits()
-                .into_iter()
-                .map(Into::into)
-                .map(|b| vec![b])
-                .collect(),
-            seeds: value.seeds().into_iter().map(Into::into).collect(),
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<M, S> TryFrom<proto::secure_write_token::DpfKey> for MultiKeyKey<M, S>
-where
-    Vec<u8>: TryInto<M> + TryInto<S>,
-{
-    type Error = &'static str;
-
-    fn try_from(proto: proto::secure_write_token::DpfKey) -> Result<Self, Self::Error> {
-        let msg = proto.encoded_msg.try_into().map_err(|_| "msg failed")?;
-        let bits = proto
-            .bits
-            .into_iter()
-            .map(TryInto::try_into)
-            .collect::<Result<Vec<S>, _>>()
-            .map_err(|_| "bits failed")?;
-        let seeds = proto
-            .seeds
-            .into_iter()
-            .map(TryInto::try_into)
-            .collect::<Result<Vec<S>, _>>()
-            .map_err(|_| "seeds failed")?;
-        Ok(Self::new(msg, bits, seeds))
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<M, S> From<MultiKeyKey<M, S>> for proto::secure_write_token::DpfKey
-where
-    M: Clone + Into<Vec<u8>>,
-    S: Clone + Into<Vec<u8>>,
-{
-    fn from(value: MultiKeyKey<M, S>) -> Self {
-        proto::secure_write_token::DpfKey {
-            encoded_msg: value.msg().into(),
-            bits: value.bits().into_iter().map(Into::into).collect(),
-            seeds: value.seeds().into_iter().map(Into::into).collect(),
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl TryFrom<proto::secure_write_token::ProofShare> for TwoKeyPubProof {
-    type Error = &'static str;
-
-    fn try_from(proto: proto::secure_write_token::ProofShare) -> Result<Self, Self::Error> {
-        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
-        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
-        Ok(Self::new(seed, bit))
-    }
-}
-
-#[cfg(feature = "proto")]
-impl From<TwoKeyPubProof> for proto::secure_write_token::ProofShare {
-    fn from(value: TwoKeyPubProof) -> Self {
-        proto::secure_write_token::ProofShare {
-            bit: value.bit().into(),
-            seed: value.seed().into(),
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<S> TryFrom<proto::secure_write_token::ProofShare> for TwoKeyProof<S>
-where
-    Vec<u8>: TryInto<S>,
-{
-    type Error = &'static str;
-
-    fn try_from(proto: proto::secure_write_token::ProofShare) -> Result<Self, Self::Error> {
-        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
-        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
-        Ok(Self::new(seed, bit))
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<S> From<TwoKeyProof<S>> for proto::secure_write_token::ProofShare
-where
-    S: Clone + Into<Vec<u8>>,
-{
-    fn from(value: TwoKeyProof<S>) -> Self {
-        proto::secure_write_token::ProofShare {
-            bit: value.bit().into(),
-            seed: value.seed().into(),
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<S> TryFrom<proto::secure_write_token::ProofShare> for MultiKeyProof<S>
-where
-    Vec<u8>: TryInto<S>,
-{
-    type Error = &'static str;
-
-    fn try_from(proto: proto::secure_write_token::ProofShare) -> Result<Self, Self::Error> {
-        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
-        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
-        Ok(Self::new(bit, seed))
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<S> From<MultiKeyProof<S>> for proto::secure_write_token::ProofShare
-where
-    S: Clone + Into<Vec<u8>>,
-{
-    fn from(value: MultiKeyProof<S>) -> Self {
-        proto::secure_write_token::ProofShare {
-            bit: value.bit().into(),
-            seed: value.seed().into(),
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<K, P> TryFrom<proto::WriteToken> for WriteToken<K, P>
-where
-    proto::secure_write_token::DpfKey: TryInto<K>,
-    <proto::secure_write_token::DpfKey as TryInto<K>>::Error: std::fmt::Debug,
-    proto::secure_write_token::ProofShare: TryInto<P>,
-{
-    type Error = &'static str;
-
-    #[allow(irrefutable_let_patterns)] // TODO: we removed insecure stuff
-    fn try_from(value: proto::WriteToken) -> Result<Self, Self::Error> {
-        // WriteToken has an optional enum for the token type; this should always be populated.
-        let token_enum = value.inner.ok_or("no inner")?;
-        // We expect the enum value to be a SecureWriteToken.
-        if let proto::write_token::Inner::Secure(token) = token_enum {
-            let key = token
-                .key
-                .ok_or("no key")?
-                .try_into()
-                .map_err(|_| "can't convert key")?;
-            let proof = token
-                .proof
-                .ok_or("no proof")?
-                .try_into()
-                .map_err(|_| "can't convert proof")?;
-            Ok(WriteToken::new(key, proof))
-        } else {
-            Err("bad")
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<K, P> From<WriteToken<K, P>> for proto::WriteToken
-where
-    K: Into<proto::secure_write_token::DpfKey>,
-    P: Into<proto::secure_write_token::ProofShare>,
-{
-    fn from(value: WriteToken<K, P>) -> Self {
-        // The main thing.
-        let token = proto::SecureWriteToken {
-            key: Some(value.key.into()),
-            proof: Some(value.proof.into()),
-        };
-        // Stuff it in a wrapper.
-        let inner = Some(proto::write_token::Inner::Secure(token));
-        proto::WriteToken { inner }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<S> TryFrom<proto::SecureAuditShare> for TwoKeyToken<S>
-where
-    Vec<u8>: TryInto<S>,
-{
-    type Error = &'static str;
-
-    fn try_from(proto: proto::SecureAuditShare) -> Result<Self, Self::Error> {
-        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
-        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
-        let data = proto.data.into();
-        Ok(Self::new(seed, bit, data))
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<S> From<TwoKeyToken<S>> for proto::SecureAuditShare
-where
-    S: Clone + Into<Vec<u8>>,
-{
-    fn from(value: TwoKeyToken<S>) -> Self {
-        proto::SecureAuditShare {
-            bit: value.bit().into(),
-            seed: value.seed().into(),
-            data: value.data().into(),
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl TryFrom<proto::SecureAuditShare> for TwoKeyPubToken {
-    type Error = &'static str;
-
-    fn try_from(proto: proto::SecureAuditShare) -> Result<Self, Self::Error> {
-        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
-        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
-        let data = proto.data.into();
-        Ok(Self::new(seed, bit, data))
-    }
-}
-
-#[cfg(feature = "proto")]
-impl From<TwoKeyPubToken> for proto::SecureAuditShare {
-    fn from(value: TwoKeyPubToken) -> Self {
-        proto::SecureAuditShare {
-            bit: value.bit().into(),
-            seed: value.seed().into(),
-            data: value.data().into(),
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<S> TryFrom<proto::SecureAuditShare> for MultiKeyToken<S>
-where
-    Vec<u8>: TryInto<S>,
-{
-    type Error = &'static str;
-
-    fn try_from(proto: proto::SecureAuditShare) -> Result<Self, Self::Error> {
-        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
-        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
-        let data = proto.data.into();
-        Ok(Self::new(seed, bit, data))
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<S> From<MultiKeyToken<S>> for proto::SecureAuditShare
-where
-    S: Clone + Into<Vec<u8>>,
-{
-    fn from(value: MultiKeyToken<S>) -> Self {
-        proto::SecureAuditShare {
-            bit: value.bit().into(),
-            seed: value.seed().into(),
-            data: value.data().into(),
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<T> TryFrom<proto::AuditShare> for AuditShare<T>
-where
-    proto::SecureAuditShare: TryInto<T>,
-{
-    type Error = &'static str;
-
-    fn try_from(value: proto::AuditShare) -> Result<Self, Self::Error> {
-        // AuditShare has an optional enum for the token type; this should always be populated.
-        let token_enum = value.inner.ok_or("no enum")?;
-        // We expect the enum value to be a SecureAuditShare.
-        if let proto::audit_share::Inner::Secure(token_proto) = token_enum {
-            let token = token_proto.try_into().map_err(|_| "can't convert token")?;
-            Ok(AuditShare::new(token))
-        } else {
-            Err("wrong type")
-        }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<T> From<AuditShare<T>> for proto::AuditShare
-where
-    T: Into<proto::SecureAuditShare>,
-{
-    fn from(value: AuditShare<T>) -> Self {
-        // The main thing.
-        let token = value.token.into();
-        // Stuff it in a wrapper.
-        let inner = Some(proto::audit_share::Inner::Secure(token));
-        proto::AuditShare { inner }
-    }
-}
-
-#[cfg(feature = "proto")]
-impl<G> From<Vec<ElementVector<G>>> for proto::Share
-where
-    ElementVector<G>: Into<Vec<u8>>,
-{
-    fn from(values: Vec<ElementVector<G>>) -> Self {
-        proto::Share {
-            data: values.into_iter().map(Into::into).collect(),
-        }
+    fn new_accumulator(&self) -> Vec<Self::Accumulator> {
+        vec![self.vdpf.null_message(); self.num_channels()]
     }
-}
 
-#[cfg(feature = "proto")]
-impl<G> TryFrom<proto::Share> for Vec<ElementVector<G>>
-where
-    ElementVector<G>: TryFrom<Vec<u8>>,
-{
-    type Error = &'static str;
-
-    fn try_from(proto: proto::Share) -> Result<Self, Self::Error> {
-        proto
-            .data
-            .into_iter()
-            .map(ElementVector::<G>::try_from)
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|_| "conversion failed")
+    fn to_accumulator(&self, token: Self::WriteToken) -> Vec<Self::Accumulator> {
+        self.vdpf.eval(token.key)
     }
-}
 
-use spectrum_primitives::Bytes;
-
-#[cfg(feature = "proto")]
-impl TryFrom<proto::Share> for Vec<Bytes> {
-    type Error = ();
-    fn try_from(share: proto::Share) -> Result<Self, Self::Error> {
-        Ok(share.data.into_iter().map(Bytes::from).collect())
+    fn accumulate_into(&self, accumulator: &mut [Self::Accumulator], token: Self::WriteToken) {
+        self.vdpf.eval_into(token.key, accumulator)
     }
-}
 
-#[cfg(feature = "proto")]
-impl From<Vec<Bytes>> for proto::Share {
-    fn from(value: Vec<Bytes>) -> Self {
-        proto::Share {
-            data: value.into_iter().map(Into::into).collect(),
+    // Check the shape of the key up front, so we can still evaluate it in
+    // place.
+    fn try_accumulate_into(
+        &self,
+        accumulator: &mut [Self::Accumulator],
+        token: Self::WriteToken,
+    ) -> Result<(), ParamsMismatch> {
+        if accumulator.len() != self.num_channels() {
+            return Err(ParamsMismatch::new(self.num_channels(), accumulator.len()));
         }
+        self.check_write_token(&token)?;
+        self.accumulate_into(accumulator, token);
+        Ok(())
     }
 }
@@ -0,0 +1,367 @@
+//! Conversions between the secure protocol's types and their protobufs.
+use super::{AuditShare, WriteToken};
+use crate::proto;
+
+use spectrum_primitives::{
+    Bytes, ElementVector, MultiKeyKey, MultiKeyProof, MultiKeyToken, TwoKeyKey, TwoKeyProof,
+    TwoKeyPubProof, TwoKeyPubToken, TwoKeyToken,
+};
+use std::convert::{TryFrom, TryInto};
+
+impl<M, S> TryFrom<proto::secure_write_token::DpfKey> for TwoKeyKey<M, S>
+where
+    Vec<u8>: Into<M> + TryInto<S>,
+{
+    type Error = &'static str;
+
+    fn try_from(proto: proto::secure_write_token::DpfKey) -> Result<Self, Self::Error> {
+        let msg = proto.encoded_msg.into();
+        let bits = proto
+            .bits
+            .into_iter()
+            .map(|bytes| {
+                if bytes.len() != 1 {
+                    return Err(());
+                }
+                match bytes[0] {
+                    0u8 => Ok(false),
+                    1u8 => Ok(true),
+                    _ => Err(()),
+                }
+            })
+            .collect::<Result<Vec<bool>, _>>()
+            .map_err(|_| "couldn't convert bits")?;
+        let seeds = proto
+            .seeds
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<S>, _>>()
+            .map_err(|_| "couldn't convert seeds")?;
+        Ok(Self::new(msg, bits, seeds))
+    }
+}
+
+impl<M, S> From<TwoKeyKey<M, S>> for proto::secure_write_token::DpfKey
+where
+    M: Clone + Into<Vec<u8>>,
+    S: Clone + Into<Vec<u8>>,
+{
+    fn from(value: TwoKeyKey<M, S>) -> Self {
+        proto::secure_write_token::DpfKey {
+            encoded_msg: value.msg().into(),
+            bits: value
+                .bits()
+                .into_iter()
+                .map(Into::into)
+                .map(|b| vec![b])
+                .collect(),
+            seeds: value.seeds().into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<M, S> TryFrom<proto::secure_write_token::DpfKey> for MultiKeyKey<M, S>
+where
+    Vec<u8>: TryInto<M> + TryInto<S>,
+{
+    type Error = &'static str;
+
+    fn try_from(proto: proto::secure_write_token::DpfKey) -> Result<Self, Self::Error> {
+        let msg = proto.encoded_msg.try_into().map_err(|_| "msg failed")?;
+        let bits = proto
+            .bits
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<S>, _>>()
+            .map_err(|_| "bits failed")?;
+        let seeds = proto
+            .seeds
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<S>, _>>()
+            .map_err(|_| "seeds failed")?;
+        if bits.len() != seeds.len() {
+            return Err("bits and seeds differ in number");
+        }
+        Ok(Self::new(msg, bits, seeds))
+    }
+}
+
+impl<M, S> From<MultiKeyKey<M, S>> for proto::secure_write_token::DpfKey
+where
+    M: Clone + Into<Vec<u8>>,
+    S: Clone + Into<Vec<u8>>,
+{
+    fn from(value: MultiKeyKey<M, S>) -> Self {
+        proto::secure_write_token::DpfKey {
+            encoded_msg: value.msg().into(),
+            bits: value.bits().into_iter().map(Into::into).collect(),
+            seeds: value.seeds().into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::secure_write_token::ProofShare> for TwoKeyPubProof {
+    type Error = &'static str;
+
+    fn try_from(proto: proto::secure_write_token::ProofShare) -> Result<Self, Self::Error> {
+        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
+        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
+        Ok(Self::new(seed, bit))
+    }
+}
+
+impl From<TwoKeyPubProof> for proto::secure_write_token::ProofShare {
+    fn from(value: TwoKeyPubProof) -> Self {
+        proto::secure_write_token::ProofShare {
+            bit: value.bit().into(),
+            seed: value.seed().into(),
+        }
+    }
+}
+
+impl<S> TryFrom<proto::secure_write_token::ProofShare> for TwoKeyProof<S>
+where
+    Vec<u8>: TryInto<S>,
+{
+    type Error = &'static str;
+
+    fn try_from(proto: proto::secure_write_token::ProofShare) -> Result<Self, Self::Error> {
+        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
+        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
+        Ok(Self::new(seed, bit))
+    }
+}
+
+impl<S> From<TwoKeyProof<S>> for proto::secure_write_token::ProofShare
+where
+    S: Clone + Into<Vec<u8>>,
+{
+    fn from(value: TwoKeyProof<S>) -> Self {
+        proto::secure_write_token::ProofShare {
+            bit: value.bit().into(),
+            seed: value.seed().into(),
+        }
+    }
+}
+
+impl<S> TryFrom<proto::secure_write_token::ProofShare> for MultiKeyProof<S>
+where
+    Vec<u8>: TryInto<S>,
+{
+    type Error = &'static str;
+
+    fn try_from(proto: proto::secure_write_token::ProofShare) -> Result<Self, Self::Error> {
+        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
+        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
+        Ok(Self::new(bit, seed))
+    }
+}
+
+impl<S> From<MultiKeyProof<S>> for proto::secure_write_token::ProofShare
+where
+    S: Clone + Into<Vec<u8>>,
+{
+    fn from(value: MultiKeyProof<S>) -> Self {
+        proto::secure_write_token::ProofShare {
+            bit: value.bit().into(),
+            seed: value.seed().into(),
+        }
+    }
+}
+
+impl<K, P> TryFrom<proto::WriteToken> for WriteToken<K, P>
+where
+    proto::secure_write_token::DpfKey: TryInto<K>,
+    <proto::secure_write_token::DpfKey as TryInto<K>>::Error: std::fmt::Debug,
+    proto::secure_write_token::ProofShare: TryInto<P>,
+{
+    type Error = &'static str;
+
+    fn try_from(value: proto::WriteToken) -> Result<Self, Self::Error> {
+        // WriteToken has an optional enum for the token type; this should always be populated.
+        let token_enum = value.inner.ok_or("no inner")?;
+        // We expect the enum value to be a SecureWriteToken.
+        if let proto::write_token::Inner::Secure(token) = token_enum {
+            let key = token
+                .key
+                .ok_or("no key")?
+                .try_into()
+                .map_err(|_| "can't convert key")?;
+            let proof = token
+                .proof
+                .ok_or("no proof")?
+                .try_into()
+                .map_err(|_| "can't convert proof")?;
+            Ok(WriteToken::new(key, proof))
+        } else {
+            Err("bad")
+        }
+    }
+}
+
+impl<K, P> From<WriteToken<K, P>> for proto::WriteToken
+where
+    K: Into<proto::secure_write_token::DpfKey>,
+    P: Into<proto::secure_write_token::ProofShare>,
+{
+    fn from(value: WriteToken<K, P>) -> Self {
+        // The main thing.
+        let token = proto::SecureWriteToken {
+            key: Some(value.key.into()),
+            proof: Some(value.proof.into()),
+        };
+        // Stuff it in a wrapper.
+        let inner = Some(proto::write_token::Inner::Secure(token));
+        proto::WriteToken { inner }
+    }
+}
+
+impl<S> TryFrom<proto::SecureAuditShare> for TwoKeyToken<S>
+where
+    Vec<u8>: TryInto<S>,
+{
+    type Error = &'static str;
+
+    fn try_from(proto: proto::SecureAuditShare) -> Result<Self, Self::Error> {
+        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
+        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
+        let data = proto.data.into();
+        Ok(Self::new(seed, bit, data))
+    }
+}
+
+impl<S> From<TwoKeyToken<S>> for proto::SecureAuditShare
+where
+    S: Clone + Into<Vec<u8>>,
+{
+    fn from(value: TwoKeyToken<S>) -> Self {
+        proto::SecureAuditShare {
+            bit: value.bit().into(),
+            seed: value.seed().into(),
+            data: value.data().into(),
+        }
+    }
+}
+
+impl TryFrom<proto::SecureAuditShare> for TwoKeyPubToken {
+    type Error = &'static str;
+
+    fn try_from(proto: proto::SecureAuditShare) -> Result<Self, Self::Error> {
+        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
+        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
+        let data = proto.data.into();
+        Ok(Self::new(seed, bit, data))
+    }
+}
+
+impl From<TwoKeyPubToken> for proto::SecureAuditShare {
+    fn from(value: TwoKeyPubToken) -> Self {
+        proto::SecureAuditShare {
+            bit: value.bit().into(),
+            seed: value.seed().into(),
+            data: value.data().into(),
+        }
+    }
+}
+
+impl<S> TryFrom<proto::SecureAuditShare> for MultiKeyToken<S>
+where
+    Vec<u8>: TryInto<S>,
+{
+    type Error = &'static str;
+
+    fn try_from(proto: proto::SecureAuditShare) -> Result<Self, Self::Error> {
+        let bit = proto.bit.try_into().map_err(|_| "can't convert bit")?;
+        let seed = proto.seed.try_into().map_err(|_| "can't convert seed")?;
+        let data = proto.data.into();
+        Ok(Self::new(seed, bit, data))
+    }
+}
+
+impl<S> From<MultiKeyToken<S>> for proto::SecureAuditShare
+where
+    S: Clone + Into<Vec<u8>>,
+{
+    fn from(value: MultiKeyToken<S>) -> Self {
+        proto::SecureAuditShare {
+            bit: value.bit().into(),
+            seed: value.seed().into(),
+            data: value.data().into(),
+        }
+    }
+}
+
+impl<T> TryFrom<proto::AuditShare> for AuditShare<T>
+where
+    proto::SecureAuditShare: TryInto<T>,
+{
+    type Error = &'static str;
+
+    fn try_from(value: proto::AuditShare) -> Result<Self, Self::Error> {
+        // AuditShare has an optional enum for the token type; this should always be populated.
+        let token_enum = value.inner.ok_or("no enum")?;
+        // We expect the enum value to be a SecureAuditShare.
+        if let proto::audit_share::Inner::Secure(token_proto) = token_enum {
+            let token = token_proto.try_into().map_err(|_| "can't convert token")?;
+            Ok(AuditShare::new(token))
+        } else {
+            Err("wrong type")
+        }
+    }
+}
+
+impl<T> From<AuditShare<T>> for proto::AuditShare
+where
+    T: Into<proto::SecureAuditShare>,
+{
+    fn from(value: AuditShare<T>) -> Self {
+        // The main thing.
+        let token = value.token.into();
+        // Stuff it in a wrapper.
+        let inner = Some(proto::audit_share::Inner::Secure(token));
+        proto::AuditShare { inner }
+    }
+}
+
+impl<G> From<Vec<ElementVector<G>>> for proto::Share
+where
+    ElementVector<G>: Into<Vec<u8>>,
+{
+    fn from(values: Vec<ElementVector<G>>) -> Self {
+        proto::Share {
+            data: values.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<G> TryFrom<proto::Share> for Vec<ElementVector<G>>
+where
+    ElementVector<G>: TryFrom<Vec<u8>>,
+{
+    type Error = &'static str;
+
+    fn try_from(proto: proto::Share) -> Result<Self, Self::Error> {
+        proto
+            .data
+            .into_iter()
+            .map(ElementVector::<G>::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| "conversion failed")
+    }
+}
+
+impl TryFrom<proto::Share> for Vec<Bytes> {
+    type Error = ();
+    fn try_from(share: proto::Share) -> Result<Self, Self::Error> {
+        Ok(share.data.into_iter().map(Bytes::from).collect())
+    }
+}
+
+impl From<Vec<Bytes>> for proto::Share {
+    fn from(value: Vec<Bytes>) -> Self {
+        proto::Share {
+            data: value.into_iter().map(Into::into).collect(),
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! Proptest strategies and checks for [`Protocol`] implementations.
+//!
+//! Enabled by the `testing` feature, so that downstream (e.g., experimental)
+//! protocols can reuse the same property suite as the ones here: either all
+//! of it, via [`check_protocol!`](crate::check_protocol), or piece by piece.
+//! The checks return a [`TestCaseError`] (rather than panicking), so use them
+//! with `?` inside `proptest!`.
+use crate::{Accumulatable, Protocol};
+
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+
+use std::fmt::Debug;
+
+/// A protocol, along with a key for each of its channels.
+#[derive(Debug, Clone)]
+pub struct ProtocolTester<P: Protocol> {
+    protocol: P,
+    keys: Vec<P::ChannelKey>,
+}
+
+impl<P: Protocol> ProtocolTester<P> {
+    pub fn new(protocol: P, keys: Vec<P::ChannelKey>) -> Self {
+        assert_eq!(
+            keys.len(),
+            protocol.num_channels(),
+            "need a key per channel"
+        );
+        ProtocolTester { protocol, keys }
+    }
+
+    pub fn protocol(&self) -> &P {
+        &self.protocol
+    }
+
+    pub fn keys(&self) -> &[P::ChannelKey] {
+        &self.keys
+    }
+
+    /// The audit shares that each server gets for `tokens`.
+    ///
+    /// That is, both the outer and inner vectors have length
+    /// `protocol.num_parties()`.
+    pub fn server_shares(&self, tokens: Vec<P::WriteToken>) -> Vec<Vec<P::AuditShare>>
+    where
+        P::AuditShare: Clone,
+    {
+        let mut server_shares = vec![Vec::new(); self.protocol.num_parties()];
+        for token in tokens {
+            let shares = self.protocol.gen_audit(&self.keys, token);
+            for (idx, share) in shares.into_iter().enumerate() {
+                server_shares[idx].push(share);
+            }
+        }
+        server_shares
+    }
+
+    /// Checks that every server's audit passes for cover traffic.
+    pub fn check_cover_complete(&self) -> Result<(), TestCaseError>
+    where
+        P::AuditShare: Clone,
+    {
+        let tokens = self.protocol.cover();
+        prop_assert_eq!(
+            tokens.len(),
+            self.protocol.num_parties(),
+            "cover should give one message per party"
+        );
+        for shares in self.server_shares(tokens) {
+            prop_assert!(self.protocol.check_audit(shares), "audit should pass");
+        }
+        Ok(())
+    }
+
+    /// Checks that every server's audit passes for a broadcast of `msg` on
+    /// channel `idx` with the right key.
+    pub fn check_broadcast_complete(
+        &self,
+        msg: P::Accumulator,
+        idx: usize,
+    ) -> Result<(), TestCaseError>
+    where
+        P::ChannelKey: Clone,
+        P::AuditShare: Clone,
+    {
+        let tokens = self.protocol.broadcast(msg, idx, self.keys[idx].clone());
+        prop_assert_eq!(
+            tokens.len(),
+            self.protocol.num_parties(),
+            "broadcast should give one message per party"
+        );
+        for shares in self.server_shares(tokens) {
+            prop_assert!(self.protocol.check_audit(shares), "audit should pass");
+        }
+        Ok(())
+    }
+
+    /// Checks that every server's audit fails for a broadcast of `msg` on
+    /// channel `idx` with `bad_key`.
+    pub fn check_broadcast_sound(
+        &self,
+        msg: P::Accumulator,
+        idx: usize,
+        bad_key: P::ChannelKey,
+    ) -> Result<(), TestCaseError>
+    where
+        P::ChannelKey: PartialEq,
+        P::AuditShare: Clone,
+    {
+        prop_assume!(!self.keys.contains(&bad_key));
+        let tokens = self.protocol.broadcast(msg, idx, bad_key);
+        prop_assert_eq!(tokens.len(), self.protocol.num_parties());
+        for shares in self.server_shares(tokens) {
+            prop_assert!(!self.protocol.check_audit(shares), "audit should fail");
+        }
+        Ok(())
+    }
+
+    /// Checks that `check_audit_batch()` matches `check_audit()` for cover
+    /// traffic and broadcasts of `msg` on channel `idx` with the right key and
+    /// with `bad_key`, all checked together.
+    pub fn check_audit_batch(
+        &self,
+        msg: P::Accumulator,
+        idx: usize,
+        bad_key: P::ChannelKey,
+    ) -> Result<(), TestCaseError>
+    where
+        P::ChannelKey: Clone,
+        P::AuditShare: Clone,
+        P::Accumulator: Clone,
+    {
+        let writes = vec![
+            self.protocol.cover(),
+            self.protocol
+                .broadcast(msg.clone(), idx, self.keys[idx].clone()),
+            self.protocol.broadcast(msg, idx, bad_key),
+        ];
+        let token_sets: Vec<_> = writes
+            .into_iter()
+            .flat_map(|tokens| self.server_shares(tokens))
+            .collect();
+        let expected: Vec<_> = token_sets
+            .iter()
+            .map(|shares| self.protocol.check_audit(shares.clone()))
+            .collect();
+        prop_assert_eq!(self.protocol.check_audit_batch(token_sets), expected);
+        Ok(())
+    }
+
+    /// Checks that accumulating a broadcast of `msg` on channel `idx` gives
+    /// `msg` there and empty messages on every other channel.
+    pub fn check_broadcast_recovers_message(
+        &self,
+        msg: P::Accumulator,
+        idx: usize,
+    ) -> Result<(), TestCaseError>
+    where
+        P::ChannelKey: Clone,
+        P::Accumulator: Clone + Debug + PartialEq,
+        <P::Accumulator as Accumulatable>::Parameters: From<usize>,
+    {
+        let mut accumulator = self.protocol.new_accumulator();
+        for token in self
+            .protocol
+            .broadcast(msg.clone(), idx, self.keys[idx].clone())
+        {
+            accumulator.combine(self.protocol.to_accumulator(token));
+        }
+
+        let empty = P::Accumulator::empty(self.protocol.slot_size().into());
+        prop_assert_eq!(
+            accumulator.len(),
+            self.protocol.num_channels(),
+            "wrong accumulator size"
+        );
+        for (channel, actual) in accumulator.into_iter().enumerate() {
+            if channel == idx {
+                prop_assert_eq!(actual, msg.clone(), "Channel was incorrect");
+            } else {
+                prop_assert_eq!(actual, empty.clone(), "Channel was non-null");
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that cover traffic doesn't change `accumulator`.
+    pub fn check_cover_correct(
+        &self,
+        mut accumulator: Vec<P::Accumulator>,
+    ) -> Result<(), TestCaseError>
+    where
+        P::Accumulator: Clone + Debug + PartialEq,
+    {
+        let expected = accumulator.clone();
+        prop_assert_eq!(accumulator.len(), self.protocol.num_channels());
+        for token in self.protocol.cover() {
+            accumulator.combine(self.protocol.to_accumulator(token));
+        }
+        prop_assert_eq!(accumulator, expected);
+        Ok(())
+    }
+
+    /// Checks that `accumulate_into()` and `try_accumulate_into()` match
+    /// `to_accumulator()` for a broadcast of `msg` on channel `idx` (and that
+    /// its tokens pass `check_write_token()`).
+    pub fn check_accumulate_into(
+        &self,
+        msg: P::Accumulator,
+        idx: usize,
+    ) -> Result<(), TestCaseError>
+    where
+        P::ChannelKey: Clone,
+        P::WriteToken: Clone,
+        P::Accumulator: Debug + PartialEq,
+    {
+        let tokens = self.protocol.broadcast(msg, idx, self.keys[idx].clone());
+
+        let mut expected = self.protocol.new_accumulator();
+        let mut in_place = self.protocol.new_accumulator();
+        let mut checked = self.protocol.new_accumulator();
+        for token in tokens {
+            prop_assert_eq!(self.protocol.check_write_token(&token), Ok(()));
+            expected.combine(self.protocol.to_accumulator(token.clone()));
+            self.protocol.accumulate_into(&mut in_place, token.clone());
+            prop_assert_eq!(
+                self.protocol.try_accumulate_into(&mut checked, token),
+                Ok(())
+            );
+        }
+        prop_assert_eq!(&in_place, &expected);
+        prop_assert_eq!(&checked, &expected);
+        Ok(())
+    }
+}
+
+impl<P> ProtocolTester<P>
+where
+    P: Protocol + Arbitrary + Clone,
+    P::ChannelKey: Arbitrary + Clone + Debug,
+{
+    /// An arbitrary protocol, with arbitrary channel keys.
+    pub fn strategy() -> impl Strategy<Value = Self> {
+        use proptest::collection::vec;
+        any::<P>().prop_flat_map(|protocol| {
+            let keys = vec(any::<P::ChannelKey>(), protocol.num_channels());
+            (Just(protocol), keys).prop_map(|(protocol, keys)| Self::new(protocol, keys))
+        })
+    }
+
+    /// Like [`strategy`](Self::strategy), plus a message of the right size.
+    pub fn with_message() -> impl Strategy<Value = (Self, P::Accumulator)>
+    where
+        P::Accumulator: Arbitrary,
+        <P::Accumulator as Arbitrary>::Parameters: From<usize>,
+    {
+        Self::strategy().prop_flat_map(|tester| {
+            let length = tester.protocol.slot_size();
+            (Just(tester), any_with::<P::Accumulator>(length.into()))
+        })
+    }
+
+    /// Like [`with_message`](Self::with_message), plus a key that isn't the
+    /// key for (at least) one of the channels.
+    pub fn with_message_bad_key() -> impl Strategy<Value = (Self, P::Accumulator, P::ChannelKey)>
+    where
+        P::ChannelKey: PartialEq,
+        P::Accumulator: Arbitrary + Clone,
+        <P::Accumulator as Arbitrary>::Parameters: From<usize>,
+    {
+        use proptest::sample::Index;
+        (Self::with_message(), any::<Index>()).prop_flat_map(|((tester, msg), idx)| {
+            let good_key = tester.keys[idx.index(tester.keys.len())].clone();
+            let bad_key = any::<P::ChannelKey>()
+                .prop_filter("must be different", move |key| key != &good_key);
+            (Just(tester), Just(msg), bad_key)
+        })
+    }
+
+    /// Like [`strategy`](Self::strategy), plus an arbitrary accumulator
+    /// value (one message per channel).
+    pub fn with_accumulator() -> impl Strategy<Value = (Self, Vec<P::Accumulator>)>
+    where
+        P::Accumulator: Arbitrary,
+        <P::Accumulator as Arbitrary>::Parameters:
+            From<<P::Accumulator as Accumulatable>::Parameters>,
+    {
+        use proptest::collection::vec;
+        Self::strategy().prop_flat_map(|tester| {
+            let channels = tester.protocol.num_channels();
+            let params = tester.protocol.new_accumulator()[0].params();
+            let values = vec(any_with::<P::Accumulator>(params.into()), channels);
+            (Just(tester), values)
+        })
+    }
+}
@@ -2,16 +2,154 @@ mod two_key {
     use crate::secure::Wrapper;
     use spectrum_primitives::TwoKeyVdpf;
     check_protocol!(Wrapper<TwoKeyVdpf>);
+    #[cfg(feature = "proto")]
+    check_protocol_proto!(Wrapper<TwoKeyVdpf>);
 }
 
 mod multi_key {
     use crate::secure::Wrapper;
     use spectrum_primitives::MultiKeyVdpf;
     check_protocol!(Wrapper<MultiKeyVdpf>);
+    #[cfg(feature = "proto")]
+    check_protocol_proto!(Wrapper<MultiKeyVdpf>);
 }
 
 mod two_key_pub {
     use crate::secure::Wrapper;
     use spectrum_primitives::TwoKeyPubVdpf;
     check_protocol!(Wrapper<TwoKeyPubVdpf>);
+    #[cfg(feature = "proto")]
+    check_protocol_proto!(Wrapper<TwoKeyPubVdpf>);
+}
+
+mod malformed {
+    use crate::secure::{Wrapper, WriteToken};
+    use crate::Protocol;
+    use spectrum_primitives::{Bytes, Dpf, MultiKeyKey, MultiKeyVdpf, TwoKeyKey, TwoKeyVdpf, Vdpf};
+
+    #[test]
+    fn test_try_accumulate_into_wrong_msg_len() {
+        let vdpf = TwoKeyVdpf::with_channels_msg_size(3, 16);
+        let proof = vdpf.gen_proofs_noop().remove(0);
+        let key = vdpf.gen_empty().remove(0);
+        let key = TwoKeyKey::new(Bytes::empty(17), key.bits(), key.seeds());
+        let protocol: Wrapper<TwoKeyVdpf> = vdpf.into();
+
+        let mut accumulator = protocol.new_accumulator();
+        let expected = accumulator.clone();
+        let token = WriteToken::new(key, proof);
+        assert!(protocol
+            .try_accumulate_into(&mut accumulator, token)
+            .is_err());
+        assert_eq!(accumulator, expected);
+    }
+
+    #[test]
+    fn test_check_write_token_wrong_points() {
+        let vdpf = TwoKeyVdpf::with_channels_msg_size(3, 16);
+        let proof = vdpf.gen_proofs_noop().remove(0);
+        let key = vdpf.gen_empty().remove(0);
+        let mut seeds = key.seeds();
+        seeds.pop();
+        let key = TwoKeyKey::new(key.msg(), key.bits(), seeds);
+        let protocol: Wrapper<TwoKeyVdpf> = vdpf.into();
+
+        let token = WriteToken::new(key, proof);
+        assert!(protocol.check_write_token(&token).is_err());
+    }
+
+    #[test]
+    fn test_check_write_token_wrong_points_multi_key() {
+        let vdpf = MultiKeyVdpf::with_channels_parties_msg_size(3, 2, 16);
+        let proof = vdpf.gen_proofs_noop().remove(0);
+        let key = vdpf.gen_empty().remove(0);
+        let mut bits = key.bits();
+        let mut seeds = key.seeds();
+        bits.push(bits[0]);
+        seeds.push(seeds[0]);
+        let key = MultiKeyKey::new(key.msg(), bits, seeds);
+        let protocol: Wrapper<MultiKeyVdpf> = vdpf.into();
+
+        let token = WriteToken::new(key, proof);
+        assert!(protocol.check_write_token(&token).is_err());
+    }
+}
+
+mod insecure {
+    use crate::insecure::{Wrapper, WriteToken};
+    use crate::testing::ProtocolTester;
+    use crate::Protocol;
+    use proptest::prelude::*;
+    use spectrum_primitives::Bytes;
+
+    type Tester = ProtocolTester<Wrapper>;
+
+    // Everything in `check_protocol!` but soundness: audits always pass.
+    proptest! {
+        #[test]
+        fn test_cover_complete(tester in Tester::strategy()) {
+            tester.check_cover_complete()?;
+        }
+
+        #[test]
+        fn test_broadcast_complete(
+            (tester, msg) in Tester::with_message(),
+            idx: prop::sample::Index,
+        ) {
+            let idx = idx.index(tester.keys().len());
+            tester.check_broadcast_complete(msg, idx)?;
+        }
+
+        #[test]
+        fn test_accumulate_into(
+            (tester, msg) in Tester::with_message(),
+            idx: prop::sample::Index,
+        ) {
+            let idx = idx.index(tester.keys().len());
+            tester.check_accumulate_into(msg, idx)?;
+        }
+
+        #[test]
+        fn test_cover_correct((tester, accumulator) in Tester::with_accumulator()) {
+            tester.check_cover_correct(accumulator)?;
+        }
+
+        #[test]
+        fn test_broadcast_correct(
+            (tester, msg) in Tester::with_message(),
+            idx: prop::sample::Index,
+        ) {
+            let idx = idx.index(tester.keys().len());
+            tester.check_broadcast_recovers_message(msg, idx)?;
+        }
+    }
+
+    #[test]
+    fn test_try_accumulate_into_malformed() {
+        let protocol = Wrapper::new(2, 3, 16);
+        let mut accumulator = protocol.new_accumulator();
+        let expected = accumulator.clone();
+
+        let bad_channel = WriteToken::new(Some((Bytes::empty(16), 3)));
+        assert!(protocol
+            .try_accumulate_into(&mut accumulator, bad_channel)
+            .is_err());
+        let bad_len = WriteToken::new(Some((Bytes::empty(17), 0)));
+        assert!(protocol
+            .try_accumulate_into(&mut accumulator, bad_len)
+            .is_err());
+        assert_eq!(accumulator, expected);
+    }
+
+    #[test]
+    fn test_check_write_token_malformed() {
+        let protocol = Wrapper::new(2, 3, 16);
+        let bad_channel = WriteToken::new(Some((Bytes::empty(16), 3)));
+        assert!(protocol.check_write_token(&bad_channel).is_err());
+        let bad_len = WriteToken::new(Some((Bytes::empty(17), 0)));
+        assert!(protocol.check_write_token(&bad_len).is_err());
+    }
+
+    #[cfg(feature = "proto")]
+    check_protocol_proto!(Wrapper);
 }
@@ -0,0 +1,260 @@
+//! Typed messages on top of a byte-oriented [`Protocol`].
+//!
+//! Each channel carries a fixed-size slot of bytes. A message is serialized
+//! (as JSON), prefixed with its length (4 bytes, big-endian), and padded with
+//! zeros to fill the slot. An all-zero slot (length 0) means "no message".
+//!
+//! Protocols whose slots are group elements (the multi-key one) instead embed
+//! the length-prefixed message in the elements themselves (see
+//! [`encode_elements`]).
+use crate::Protocol;
+
+use serde::{de::DeserializeOwned, Serialize};
+use spectrum_primitives::{
+    Bytes, DecodeElements, ElementVector, Embed, EncodeElements, EncodingError,
+};
+
+use std::convert::TryInto;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Size (in bytes) of the length prefix in each slot.
+pub const LEN_PREFIX_BYTES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The serialized message doesn't fit in a slot.
+    TooLong { len: usize, max: usize },
+    /// The slot doesn't contain a valid encoded message.
+    Malformed(String),
+    /// Serializing/deserializing the message itself failed.
+    Serde(String),
+    /// The protocol can't carry arbitrary bytes.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TooLong { len, max } => write!(
+                f,
+                "message too long: {} bytes serialized, at most {} allowed",
+                len, max
+            ),
+            Error::Malformed(msg) => write!(f, "malformed slot: {}", msg),
+            Error::Serde(msg) => write!(f, "message (de)serialization failed: {}", msg),
+            Error::Unsupported(protocol) => {
+                write!(f, "typed messages not supported by {} protocol", protocol)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<EncodingError> for Error {
+    fn from(err: EncodingError) -> Self {
+        match err {
+            EncodingError::TooLong { len, max } => Error::TooLong { len, max },
+            EncodingError::Malformed(msg) => Error::Malformed(msg),
+        }
+    }
+}
+
+/// Encode `message` into a slot of exactly `slot_len` bytes.
+pub fn encode<M: Serialize>(message: &M, slot_len: usize) -> Result<Bytes, Error> {
+    let max = slot_len.saturating_sub(LEN_PREFIX_BYTES);
+    let data = serde_json::to_vec(message).map_err(|err| Error::Serde(err.to_string()))?;
+    if data.len() > max {
+        return Err(Error::TooLong {
+            len: data.len(),
+            max,
+        });
+    }
+    let len: u32 = data.len().try_into().map_err(|_| Error::TooLong {
+        len: data.len(),
+        max,
+    })?;
+
+    let mut slot = Vec::with_capacity(slot_len);
+    slot.extend_from_slice(&len.to_be_bytes());
+    slot.extend(data);
+    slot.resize(slot_len, 0);
+    Ok(slot.into())
+}
+
+/// Decode a slot produced by [`encode`]; `None` if the slot is empty.
+pub fn decode<M: DeserializeOwned>(slot: &Bytes) -> Result<Option<M>, Error> {
+    let slot = slot.as_ref();
+    if slot.len() < LEN_PREFIX_BYTES {
+        return Err(Error::Malformed(format!(
+            "slot has {} bytes, need at least {}",
+            slot.len(),
+            LEN_PREFIX_BYTES
+        )));
+    }
+    let (prefix, rest) = slot.split_at(LEN_PREFIX_BYTES);
+    let len = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    if len > rest.len() {
+        return Err(Error::Malformed(format!(
+            "length prefix {} exceeds slot size {}",
+            len,
+            rest.len()
+        )));
+    }
+    serde_json::from_slice(&rest[..len])
+        .map(Some)
+        .map_err(|err| Error::Serde(err.to_string()))
+}
+
+/// Encode `message` into exactly `elements` group elements.
+pub fn encode_elements<M, G>(message: &M, elements: usize) -> Result<ElementVector<G>, Error>
+where
+    M: Serialize,
+    G: Embed,
+{
+    let data = serde_json::to_vec(message).map_err(|err| Error::Serde(err.to_string()))?;
+    Ok(Bytes::from(data).encode(elements)?)
+}
+
+/// Decode elements produced by [`encode_elements`]; `None` if they're empty
+/// (all identity).
+pub fn decode_elements<M, G>(elements: &ElementVector<G>) -> Result<Option<M>, Error>
+where
+    M: DeserializeOwned,
+    G: Embed,
+{
+    let data = Bytes::decode(elements)?;
+    if data.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_slice(data.as_ref())
+        .map(Some)
+        .map_err(|err| Error::Serde(err.to_string()))
+}
+
+/// A [`Protocol`] over raw bytes, used to send messages of type `M`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypedProtocol<P, M> {
+    protocol: P,
+    message: PhantomData<M>,
+}
+
+impl<P, M> From<P> for TypedProtocol<P, M> {
+    fn from(protocol: P) -> Self {
+        TypedProtocol {
+            protocol,
+            message: PhantomData,
+        }
+    }
+}
+
+impl<P, M> TypedProtocol<P, M>
+where
+    P: Protocol<Accumulator = Bytes>,
+    M: Serialize + DeserializeOwned,
+{
+    pub fn new(protocol: P) -> Self {
+        protocol.into()
+    }
+
+    pub fn protocol(&self) -> &P {
+        &self.protocol
+    }
+
+    /// The largest serialized message (in bytes) that fits in a channel.
+    pub fn max_message_len(&self) -> usize {
+        self.protocol
+            .payload_capacity()
+            .saturating_sub(LEN_PREFIX_BYTES)
+    }
+
+    pub fn encode(&self, message: &M) -> Result<Bytes, Error> {
+        encode(message, self.protocol.payload_capacity())
+    }
+
+    pub fn decode(&self, slot: &Bytes) -> Result<Option<M>, Error> {
+        decode(slot)
+    }
+
+    pub fn broadcast(
+        &self,
+        message: &M,
+        idx: usize,
+        key: P::ChannelKey,
+    ) -> Result<Vec<P::WriteToken>, Error> {
+        Ok(self.protocol.broadcast(self.encode(message)?, idx, key))
+    }
+
+    pub fn cover(&self) -> Vec<P::WriteToken> {
+        self.protocol.cover()
+    }
+
+    /// Decode each channel of a final accumulator.
+    pub fn recover(&self, accumulator: &[Bytes]) -> Vec<Result<Option<M>, Error>> {
+        accumulator.iter().map(|slot| self.decode(slot)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{secure::Wrapper, Accumulatable};
+    use proptest::prelude::*;
+    use spectrum_primitives::{AuthKey, Sampleable, TwoKeyVdpf};
+
+    const SLOT_LEN: usize = 64;
+
+    proptest! {
+        #[test]
+        fn test_encode_decode_roundtrip(message in "[a-z]{0,40}", extra in 0..32usize) {
+            let slot_len = LEN_PREFIX_BYTES + message.len() + 2 + extra;
+            let slot = encode(&message, slot_len).unwrap();
+            prop_assert_eq!(slot.len(), slot_len);
+            prop_assert_eq!(decode::<String>(&slot).unwrap(), Some(message));
+        }
+
+        #[test]
+        fn test_encode_too_long(message in "[a-z]{60,100}") {
+            let result = encode(&message, SLOT_LEN);
+            let is_too_long = matches!(result, Err(Error::TooLong { .. }));
+            prop_assert!(is_too_long);
+        }
+
+        #[test]
+        fn test_decode_bad_prefix(len in (SLOT_LEN as u32)..) {
+            let mut slot = vec![0u8; SLOT_LEN];
+            slot[..LEN_PREFIX_BYTES].copy_from_slice(&len.to_be_bytes());
+            let result = decode::<String>(&slot.into());
+            let is_malformed = matches!(result, Err(Error::Malformed(_)));
+            prop_assert!(is_malformed);
+        }
+    }
+
+    #[test]
+    fn test_decode_empty() {
+        let slot = Bytes::empty(SLOT_LEN);
+        assert_eq!(decode::<String>(&slot), Ok(None));
+    }
+
+    #[test]
+    fn test_broadcast_recover() {
+        let channels = 3;
+        let protocol: Wrapper<TwoKeyVdpf> =
+            TwoKeyVdpf::with_channels_msg_size(channels, SLOT_LEN).into();
+        let protocol: TypedProtocol<_, (String, u32)> = TypedProtocol::new(protocol);
+        let keys: Vec<AuthKey> = (0..channels).map(|_| AuthKey::sample()).collect();
+        let message = ("hello".to_string(), 42);
+
+        let mut accumulator = protocol.protocol().new_accumulator();
+        for token in protocol.broadcast(&message, 1, keys[1]).unwrap() {
+            accumulator.combine(protocol.protocol().to_accumulator(token));
+        }
+
+        let recovered = protocol.recover(&accumulator);
+        assert_eq!(recovered, vec![Ok(None), Ok(Some(message)), Ok(None)]);
+    }
+}
@@ -1,9 +1,11 @@
 // https://github.com/rust-lang/rust-clippy/issues/6594
 #![allow(clippy::unit_arg)]
-use crate::{secure, Protocol};
+use crate::{insecure, reservation, secure, typed, Protocol};
 
-use serde::{Deserialize, Serialize};
-use spectrum_primitives::{AuthKey, MultiKeyVdpf, TwoKeyPubAuthKey, TwoKeyPubVdpf, TwoKeyVdpf};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use spectrum_primitives::{
+    AuthKey, Bytes, Dpf, MultiKeyVdpf, TwoKeyPubAuthKey, TwoKeyPubVdpf, TwoKeyVdpf,
+};
 
 use std::convert::TryFrom;
 use std::fmt::Debug;
@@ -11,6 +13,7 @@ use std::fmt::Debug;
 type SecureProtocolTwoKey = secure::Wrapper<TwoKeyVdpf>;
 type SecureProtocolTwoKeyPub = secure::Wrapper<TwoKeyPubVdpf>;
 type SecureProtocolMultiKey = secure::Wrapper<MultiKeyVdpf>;
+type MultiKeyMessage = <MultiKeyVdpf as Dpf>::Message;
 
 #[cfg(any(test, feature = "testing"))]
 use proptest_derive::Arbitrary;
@@ -18,6 +21,7 @@ use proptest_derive::Arbitrary;
 #[cfg_attr(any(test, feature = "testing"), derive(Arbitrary))]
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum ChannelKeyWrapper {
+    Insecure(String),
     Secure(AuthKey),
     SecurePub(TwoKeyPubAuthKey),
 }
@@ -62,25 +66,34 @@ impl TryFrom<ChannelKeyWrapper> for String {
     type Error = &'static str;
 
     fn try_from(wrapper: ChannelKeyWrapper) -> Result<Self, Self::Error> {
-        let _ = wrapper;
-        Err("Invalid channel key")
+        if let ChannelKeyWrapper::Insecure(key) = wrapper {
+            Ok(key)
+        } else {
+            Err("Invalid channel key")
+        }
     }
 }
 
 impl From<String> for ChannelKeyWrapper {
     fn from(value: String) -> ChannelKeyWrapper {
-        let _ = value;
-        panic!("Removed insecure protocol")
+        ChannelKeyWrapper::Insecure(value)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ProtocolWrapper {
+    Insecure(insecure::Wrapper),
     Secure(SecureProtocolTwoKey),
     SecurePub(SecureProtocolTwoKeyPub),
     SecureMultiKey(SecureProtocolMultiKey),
 }
 
+impl From<insecure::Wrapper> for ProtocolWrapper {
+    fn from(protocol: insecure::Wrapper) -> Self {
+        Self::Insecure(protocol)
+    }
+}
+
 impl From<SecureProtocolTwoKey> for ProtocolWrapper {
     fn from(protocol: SecureProtocolTwoKey) -> Self {
         Self::Secure(protocol)
@@ -129,12 +142,27 @@ impl ProtocolWrapper {
                     .into()
                 }
             }
-            false => panic!("Insecure protocol removed"),
+            false => insecure::Wrapper::new(groups, channels, msg_size).into(),
         }
     }
 
+    /// A multi-key protocol with generators derived from `seed` (see
+    /// [`MultiKeyVdpf::with_channels_parties_msg_size_seed`]).
+    pub fn multi_key_from_seed(
+        groups: usize,
+        channels: usize,
+        msg_size: usize,
+        seed: [u8; 32],
+    ) -> Self {
+        Into::<secure::Wrapper<_>>::into(MultiKeyVdpf::with_channels_parties_msg_size_seed(
+            channels, groups, msg_size, seed,
+        ))
+        .into()
+    }
+
     pub fn num_parties(&self) -> usize {
         match self {
+            Self::Insecure(protocol) => protocol.num_parties(),
             Self::Secure(protocol) => protocol.num_parties(),
             Self::SecurePub(protocol) => protocol.num_parties(),
             Self::SecureMultiKey(protocol) => protocol.num_parties(),
@@ -143,17 +171,81 @@ impl ProtocolWrapper {
 
     pub fn num_channels(&self) -> usize {
         match self {
+            Self::Insecure(protocol) => protocol.num_channels(),
             Self::Secure(protocol) => protocol.num_channels(),
             Self::SecurePub(protocol) => protocol.num_channels(),
             Self::SecureMultiKey(protocol) => protocol.num_channels(),
         }
     }
 
-    pub fn message_len(&self) -> usize {
+    pub fn slot_size(&self) -> usize {
         match self {
-            Self::Secure(protocol) => protocol.message_len(),
-            Self::SecurePub(protocol) => protocol.message_len(),
-            Self::SecureMultiKey(protocol) => protocol.message_len(),
+            Self::Insecure(protocol) => protocol.slot_size(),
+            Self::Secure(protocol) => protocol.slot_size(),
+            Self::SecurePub(protocol) => protocol.slot_size(),
+            Self::SecureMultiKey(protocol) => protocol.slot_size(),
+        }
+    }
+
+    pub fn payload_capacity(&self) -> usize {
+        match self {
+            Self::Insecure(protocol) => protocol.payload_capacity(),
+            Self::Secure(protocol) => protocol.payload_capacity(),
+            Self::SecurePub(protocol) => protocol.payload_capacity(),
+            Self::SecureMultiKey(protocol) => protocol.payload_capacity(),
+        }
+    }
+
+    /// Encode a typed message into a channel slot (see [`typed`]).
+    pub fn encode_message<M: Serialize>(&self, message: &M) -> Result<Bytes, typed::Error> {
+        match self {
+            Self::Insecure(_) | Self::Secure(_) | Self::SecurePub(_) => {
+                typed::encode(message, self.payload_capacity())
+            }
+            Self::SecureMultiKey(protocol) => {
+                let elements: MultiKeyMessage =
+                    typed::encode_elements(message, protocol.slot_size())?;
+                Ok(elements.into())
+            }
+        }
+    }
+
+    /// The protocol for a reservation round ahead of this one (see
+    /// [`reservation`]): same channels and keys, but each message is just the
+    /// reservation entries.
+    pub fn reservation_round(
+        &self,
+        reservation: reservation::SlotReservation,
+    ) -> Result<Self, reservation::Error> {
+        let (channels, msg_size) = (self.num_channels(), reservation.message_len());
+        match self {
+            Self::Insecure(protocol) => {
+                Ok(insecure::Wrapper::new(protocol.num_parties(), channels, msg_size).into())
+            }
+            Self::Secure(_) => Ok(Into::<secure::Wrapper<_>>::into(
+                TwoKeyVdpf::with_channels_msg_size(channels, msg_size),
+            )
+            .into()),
+            Self::SecurePub(_) => Ok(Into::<secure::Wrapper<_>>::into(
+                TwoKeyPubVdpf::with_channels_msg_size(channels, msg_size),
+            )
+            .into()),
+            Self::SecureMultiKey(_) => Err(reservation::Error::Unsupported("multi-key")),
+        }
+    }
+
+    /// Decode a typed message from a recovered channel slot (see [`typed`]).
+    pub fn decode_message<M: DeserializeOwned>(
+        &self,
+        slot: &Bytes,
+    ) -> Result<Option<M>, typed::Error> {
+        match self {
+            Self::Insecure(_) | Self::Secure(_) | Self::SecurePub(_) => typed::decode(slot),
+            Self::SecureMultiKey(_) => {
+                let elements = MultiKeyMessage::try_from(slot.clone())
+                    .map_err(|err| typed::Error::Malformed(err.to_string()))?;
+                typed::decode_elements(&elements)
+            }
         }
     }
 }
@@ -165,13 +257,12 @@ mod tests {
     use spectrum_primitives::check_roundtrip;
     use std::convert::TryInto;
 
-    // TODO: remove
-    // check_roundtrip!(
-    //     String,
-    //     Into::<ChannelKeyWrapper>::into,
-    //     |w: ChannelKeyWrapper| w.try_into().unwrap(),
-    //     string_channelkeywrapper_rt
-    // );
+    check_roundtrip!(
+        String,
+        Into::<ChannelKeyWrapper>::into,
+        |w: ChannelKeyWrapper| w.try_into().unwrap(),
+        string_channelkeywrapper_rt
+    );
 
     check_roundtrip!(
         AuthKey,
@@ -179,4 +270,50 @@ mod tests {
         |w: ChannelKeyWrapper| w.try_into().unwrap(),
         authkey_channelkeywrapper_rt
     );
+
+    #[test]
+    fn test_payload_capacity_bytes() {
+        for &public in &[false, true] {
+            let protocol = ProtocolWrapper::new(true, false, 2, 3, 100, public);
+            assert_eq!(protocol.slot_size(), 100);
+            assert_eq!(protocol.payload_capacity(), 100);
+        }
+        let protocol = ProtocolWrapper::new(false, false, 2, 3, 100, false);
+        assert_eq!(protocol.slot_size(), 100);
+        assert_eq!(protocol.payload_capacity(), 100);
+    }
+
+    #[test]
+    fn test_typed_message_multi_key() {
+        let protocol = ProtocolWrapper::new(true, true, 2, 3, 100, false);
+        // Lengths that aren't a whole number of elements.
+        let long = "x".repeat(90);
+        for message in &["", "hi", long.as_str()] {
+            let slot = protocol.encode_message(message).unwrap();
+            assert_eq!(slot.len(), protocol.payload_capacity());
+            assert_eq!(
+                protocol.decode_message::<String>(&slot),
+                Ok(Some(message.to_string()))
+            );
+        }
+        if let ProtocolWrapper::SecureMultiKey(inner) = &protocol {
+            let empty = Bytes::from(inner.new_accumulator().remove(0));
+            assert_eq!(protocol.decode_message::<String>(&empty), Ok(None));
+        }
+        let result = protocol.encode_message(&"x".repeat(200));
+        assert!(matches!(result, Err(typed::Error::TooLong { .. })));
+    }
+
+    #[test]
+    fn test_payload_capacity_multi_key() {
+        let protocol = ProtocolWrapper::new(true, true, 2, 3, 100, false);
+        // Whole group elements, 32 bytes each.
+        assert_eq!(protocol.payload_capacity(), 32 * protocol.slot_size());
+        assert!(protocol.payload_capacity() >= 100);
+        if let ProtocolWrapper::SecureMultiKey(protocol) = protocol {
+            for channel in protocol.new_accumulator() {
+                assert_eq!(Bytes::from(channel).len(), protocol.payload_capacity());
+            }
+        }
+    }
 }